@@ -10,6 +10,7 @@ extern crate serde_derive;
 extern crate serde_json;
 extern crate slab;
 extern crate timely;
+extern crate timely_sort;
 extern crate ws;
 
 #[macro_use]
@@ -20,19 +21,25 @@ extern crate env_logger;
 extern crate abomonation_derive;
 extern crate abomonation;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::BufRead;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 use std::{thread, usize};
 
 use getopts::Options;
 
 use timely::dataflow::channels::pact::Exchange;
 use timely::dataflow::operators::generic::OutputHandle;
-use timely::dataflow::operators::{Operator, Probe};
+use timely::dataflow::operators::{Filter, Operator, Probe, ToStream};
+use timely::dataflow::{ProbeHandle, Scope};
 use timely::synchronization::Sequencer;
 
+use differential_dataflow::Hashable;
+
+use timely_sort::Unsigned;
+
 use mio::net::TcpListener;
 use mio::*;
 
@@ -40,14 +47,52 @@ use slab::Slab;
 
 use ws::connection::{ConnEvent, Connection};
 
-use declarative_dataflow::server::{Config, CreateAttribute, Request, Server};
-use declarative_dataflow::{Error, ImplContext, ResultDiff};
+use declarative_dataflow::server::accounting::UsageWebhook;
+use declarative_dataflow::server::catalog::Catalog;
+use declarative_dataflow::server::durable::{journaled_by, Durable, DurableBatch};
+use declarative_dataflow::server::encoding::{Encoding, Incoming, Outgoing, Payload};
+use declarative_dataflow::server::logging::Logging;
+use declarative_dataflow::server::replication::{is_replicated, Replica, ReplicationLog};
+use declarative_dataflow::server::tcp::FramedConnection;
+use declarative_dataflow::server::watchdog::Alarm;
+use declarative_dataflow::server::{
+    parse_requests, recovery_order, CatchUp, Config, CreateAttribute, Diff, Interest,
+    RegisterOutcome, RegisterSource, Request, Server,
+};
+use declarative_dataflow::udf::ExternalFunction;
+use declarative_dataflow::{Error, ImplContext, ResultDiff, Time, Value};
 
 const SERVER: Token = Token(usize::MAX - 1);
 const RESULTS: Token = Token(usize::MAX - 2);
 const ERRORS: Token = Token(usize::MAX - 3);
 const SYSTEM: Token = Token(usize::MAX - 4);
 const CLI: Token = Token(usize::MAX - 5);
+const RESPONSES: Token = Token(usize::MAX - 6);
+const TCP_SERVER: Token = Token(usize::MAX - 7);
+const READ_ONLY_SERVER: Token = Token(usize::MAX - 8);
+
+/// Clients connected via plain TCP are identified by tokens starting
+/// here, s.t. they can share the interest and response plumbing with
+/// WebSocket clients.
+const TCP_CLIENTS: usize = 1 << 24;
+
+/// Reports an invalid configuration and exits.
+fn misconfigured<T, E: std::fmt::Display>(err: E) -> T {
+    eprintln!("Invalid configuration: {}", err);
+    std::process::exit(1)
+}
+
+/// Returns true iff the token identifies a plain TCP client.
+fn is_tcp(token: Token) -> bool {
+    token.0 >= TCP_CLIENTS && token.0 < TCP_CLIENTS + MAX_CONNECTIONS
+}
+
+/// Maximum number of connections per protocol.
+const MAX_CONNECTIONS: usize = 1024;
+
+/// Static assets for the built-in admin UI.
+#[cfg(feature = "admin-ui")]
+const ADMIN_UI: &str = include_str!("../../assets/admin.html");
 
 /// A mutation of server state.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Debug)]
@@ -58,8 +103,364 @@ pub struct Command {
     /// The client token that issued the command. Only relevant to the
     /// owning worker, as no one else has the connection.
     pub client: usize,
+    /// Id assigned to the command by the client, if any. Responses
+    /// to the command are tagged with it.
+    pub id: Option<u64>,
     /// Requests issued by the client.
     pub requests: Vec<Request>,
+    /// Wall-clock instant (in milliseconds since the unix epoch) at
+    /// which the command was issued, as recorded in `df.tx/instant`
+    /// by all workers.
+    pub instant: u64,
+}
+
+/// Milliseconds since the unix epoch, according to the wall clock.
+fn wall_clock_millis() -> u64 {
+    let elapsed = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+}
+
+/// Identifies a request by the sequence number of the command it
+/// was part of and its offset within that command (counting the
+/// items of batches individually).
+#[derive(Clone, Copy, Debug)]
+pub struct RequestId {
+    /// Sequence number of the command.
+    pub tx: u64,
+    /// Offset of the request within the command.
+    pub offset: usize,
+    /// Kind of request.
+    pub kind: &'static str,
+    /// Id assigned to the command by the client, if any.
+    pub command: Option<u64>,
+}
+
+/// Outputs of an interest dataflow, to be forwarded to all clients
+/// interested in the respective query. Outputs carry the generation
+/// of the query's implementation, s.t. those of implementations
+/// discarded since are dropped.
+#[derive(Clone, Debug)]
+enum Output {
+    /// All results of a query at a single time, sent once that time
+    /// is complete.
+    Results(String, usize, Vec<ResultDiff>),
+    /// The output frontier of a query has advanced, i.e. results at
+    /// times not in advance of the frontier are complete.
+    Frontier(String, usize, Vec<Time>),
+    /// All results of a query at a single time, to be journaled for
+    /// the named durable consumer.
+    Journal(String, String, usize, Vec<ResultDiff>),
+}
+
+/// Per-connection settings, as negotiated by clients or implied by
+/// the port they connected to.
+#[derive(Default)]
+struct Session {
+    /// The encoding of messages sent to the client.
+    encoding: Encoding,
+    /// Whether requests writing to the domain (or changing the
+    /// server's configuration) are rejected.
+    read_only: bool,
+}
+
+/// Sends a message to a client, via whichever protocol it is
+/// connected by. Messages to clients that have disconnected (or to
+/// system commands) are dropped. Returns the number of bytes sent.
+fn deliver(
+    poll: &Poll,
+    connections: &mut Slab<Connection>,
+    tcp_connections: &mut Slab<FramedConnection>,
+    sessions: &HashMap<Token, Session>,
+    token: Token,
+    message: &mut Outgoing,
+) -> usize {
+    let encoding = sessions
+        .get(&token)
+        .map(|session| session.encoding)
+        .unwrap_or_default();
+
+    let bytes = message.encoded(encoding).len();
+
+    if is_tcp(token) {
+        if let Some(conn) = tcp_connections.get_mut(token.0 - TCP_CLIENTS) {
+            conn.send(message.encoded(encoding));
+
+            poll.reregister(
+                conn.socket(),
+                token,
+                conn.events(),
+                PollOpt::edge() | PollOpt::oneshot(),
+            )
+            .unwrap();
+
+            return bytes;
+        }
+    } else if let Some(conn) = connections.get_mut(token.into()) {
+        let msg = match encoding {
+            Encoding::Json => ws::Message::text(message.text().to_string()),
+            encoding => ws::Message::binary(message.encoded(encoding).to_vec()),
+        };
+
+        conn.send_message(msg).expect("failed to send message");
+
+        poll.reregister(
+            conn.socket(),
+            conn.token(),
+            conn.events(),
+            PollOpt::edge() | PollOpt::oneshot(),
+        )
+        .unwrap();
+
+        return bytes;
+    }
+
+    0
+}
+
+/// Sums the sizes each worker reports for its part of a number of
+/// relations at the owning worker, and hands it the totals once all
+/// workers have reported theirs.
+fn sum_sizes<S, F>(
+    scope: &mut S,
+    sizes: Vec<(String, usize)>,
+    owner: usize,
+    probe: &mut ProbeHandle<Time>,
+    respond: F,
+) where
+    S: Scope<Timestamp = Time>,
+    F: FnOnce(HashMap<String, usize>) + 'static,
+{
+    let is_owner = scope.index() == owner;
+    let mut respond = Some(respond);
+
+    sizes
+        .to_stream(scope)
+        .unary_frontier(
+            Exchange::new(move |_| owner as u64),
+            "SizesRecv",
+            move |_capability, _info| {
+                let mut totals: HashMap<String, usize> = HashMap::new();
+                let mut buffer = Vec::new();
+
+                move |input, _output: &mut OutputHandle<_, (), _>| {
+                    input.for_each(|_time, data| {
+                        data.swap(&mut buffer);
+
+                        for (name, size) in buffer.drain(..) {
+                            *totals.entry(name).or_insert(0) += size;
+                        }
+                    });
+
+                    if is_owner && input.frontier().is_empty() {
+                        if let Some(respond) = respond.take() {
+                            respond(std::mem::replace(&mut totals, HashMap::new()));
+                        }
+                    }
+                }
+            },
+        )
+        .probe_with(probe);
+}
+
+/// Notifies clients interested in any of the specified relations
+/// that these have been invalidated by a change to the named
+/// attribute or rule, along with the suggested action. Interests are
+/// only tracked by the worker owning the respective client, so each
+/// worker notifies its own clients.
+fn notify_invalidated(
+    server: &Server<Token>,
+    relations: Vec<String>,
+    cause: &str,
+    change: &str,
+    action: &str,
+    send_responses: &mio::channel::Sender<(Vec<Token>, String, Payload)>,
+) {
+    for relation in relations {
+        if let Some(tokens) = server.interests.get(&relation) {
+            let payload = serde_json::json!({
+                "df.invalidated/name": relation,
+                "df.invalidated/cause": cause,
+                "df.invalidated/change": change,
+                "df.invalidated/action": action,
+            });
+
+            send_responses
+                .send((tokens.clone(), "df.invalidated".to_string(), payload.into()))
+                .unwrap();
+        }
+    }
+}
+
+/// Builds the message delivering a batch of results to a durable
+/// consumer, to be acknowledged by its sequence number.
+fn durable_payload(consumer: &str, batch: DurableBatch, lost: u64) -> serde_json::Value {
+    serde_json::json!({
+        "df.durable/consumer": consumer,
+        "df.durable/seq": batch.seq,
+        "df.durable/name": batch.name,
+        "df.durable/results": batch.results,
+        "df.durable/lost": lost,
+    })
+}
+
+/// Handles a message received from a client, via whichever protocol
+/// it is connected by. Commands are sequenced, unless they are not
+/// permitted on the client's connection. Hello messages change the
+/// connection's settings.
+fn receive(
+    worker_index: usize,
+    token: Token,
+    incoming: Incoming,
+    sessions: &mut HashMap<Token, Session>,
+    sequencer: &mut Sequencer<Command>,
+    send_errors: &mio::channel::Sender<(Vec<Token>, Vec<Error>, Option<RequestId>)>,
+    send_responses: &mio::channel::Sender<(Vec<Token>, String, Payload)>,
+) {
+    let session = sessions.entry(token).or_insert_with(Session::default);
+
+    // Read-only connections are enforced before sequencing, as only
+    // the worker owning the connection knows about them.
+    let forbidden = match incoming {
+        Incoming::Command(_, Ok(ref requests)) if session.read_only => requests
+            .iter()
+            .find(|req| !req.is_read_only())
+            .map(|req| req.kind()),
+        _ => None,
+    };
+
+    let incoming = match (forbidden, incoming) {
+        (Some(kind), Incoming::Command(id, _)) => Incoming::Command(
+            id,
+            Err(Error {
+                category: "df.error.category/forbidden",
+                message: format!(
+                    "{} requests are not permitted on read-only connections.",
+                    kind
+                ),
+            }),
+        ),
+        (_, incoming) => incoming,
+    };
+
+    match incoming {
+        Incoming::Hello(Err(error)) => {
+            send_errors.send((vec![token], vec![error], None)).unwrap();
+        }
+        Incoming::Hello(Ok(hello)) => {
+            if let Some(encoding) = hello.encoding {
+                session.encoding = encoding;
+            }
+
+            // read-only connections stay that way
+            session.read_only |= hello.read_only;
+
+            let hello = serde_json::json!({
+                "df.hello/encoding": session.encoding.name(),
+                "df.hello/read-only": session.read_only,
+            });
+
+            send_responses
+                .send((vec![token], "df.hello".to_string(), hello.into()))
+                .unwrap();
+        }
+        Incoming::Command(id, Err(error)) => {
+            if let Some(id) = id {
+                // the command never reaches the sequencer
+                let ack = serde_json::json!({
+                    "df.ack/command": id,
+                    "df.ack/tx": serde_json::Value::Null,
+                    "df.ack/status": "error",
+                    "df.ack/errors": [{
+                        "df.error/category": error.category,
+                        "df.error/message": error.message,
+                    }],
+                });
+
+                send_responses
+                    .send((vec![token], "df.ack".to_string(), ack.into()))
+                    .unwrap();
+            }
+
+            send_errors.send((vec![token], vec![error], None)).unwrap();
+        }
+        Incoming::Command(id, Ok(requests)) => {
+            let command = Command {
+                owner: worker_index,
+                client: token.into(),
+                id,
+                requests,
+                instant: wall_clock_millis(),
+            };
+
+            trace!("[WORKER {}] {:?}", worker_index, command);
+
+            sequencer.push(command);
+        }
+    }
+}
+
+/// Determines the time to which the internal domain is advanced once
+/// a command has been handled, starting from its current frontier.
+#[cfg(not(feature = "real-time"))]
+fn next_time(now: &Time) -> Time {
+    // sources driving the domain might have advanced it beyond the
+    // number of commands handled, so we always move on from there
+    now + 1
+}
+
+/// Determines the time to which the internal domain is advanced once
+/// a command has been handled, starting from its current frontier.
+#[cfg(feature = "real-time")]
+fn next_time(now: &Time) -> Time {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let wall_clock = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch");
+
+    std::cmp::max(*now, wall_clock)
+}
+
+/// Serves the admin UI over plain HTTP on the specified port. The
+/// page itself connects back to the server via WebSocket.
+#[cfg(feature = "admin-ui")]
+fn serve_admin_ui(port: u16) {
+    use std::io::{BufReader, Write};
+
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let listener = std::net::TcpListener::bind(&addr).expect("failed to bind admin port");
+
+    thread::spawn(move || {
+        info!("[ADMIN] serving admin ui at {}", addr);
+
+        for stream in listener.incoming() {
+            match stream {
+                Err(err) => error!("[ADMIN] error while accepting connection {:?}", err),
+                Ok(mut stream) => {
+                    // We serve the same page for every request, so
+                    // we only have to consume the request line.
+                    let mut request_line = String::new();
+                    if let Err(err) = BufReader::new(&stream).read_line(&mut request_line) {
+                        error!("[ADMIN] error while reading request {:?}", err);
+                        continue;
+                    }
+
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        ADMIN_UI.len(),
+                        ADMIN_UI
+                    );
+
+                    if let Err(err) = stream.write_all(response.as_bytes()) {
+                        error!("[ADMIN] error while writing response {:?}", err);
+                    }
+                }
+            }
+        }
+    });
 }
 
 fn main() {
@@ -67,10 +468,136 @@ fn main() {
 
     let mut opts = Options::new();
     opts.optopt("", "port", "server port", "PORT");
+    opts.optopt(
+        "",
+        "tcp-port",
+        "plain tcp port (length-prefixed frames)",
+        "PORT",
+    );
+    opts.optopt(
+        "",
+        "read-only-port",
+        "port accepting read-only connections",
+        "PORT",
+    );
     opts.optflag("", "enable-cli", "enable the CLI interface");
     opts.optflag("", "enable-history", "enable historical queries");
     opts.optflag("", "enable-optimizer", "enable WCO queries");
     opts.optflag("", "enable-meta", "enable queries on the query graph");
+    opts.optflag("", "enable-tx-times", "allow binding transaction times");
+    opts.optopt(
+        "",
+        "watchdog-timeout",
+        "report frontiers stalled for longer than this",
+        "MS",
+    );
+    opts.optflag(
+        "",
+        "enable-watchdog-notifications",
+        "notify clients of stuck relations",
+    );
+    opts.optopt("", "catalog", "persist the schema in this file", "PATH");
+    opts.optflag("", "simulation", "drive the domain from sources only");
+    opts.optflag("", "enable-logging", "record timely and differential logs");
+    opts.optopt(
+        "",
+        "accounting-interval",
+        "record resources consumed per rule and client this often",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "accounting-webhook",
+        "post usage reports to this url",
+        "URL",
+    );
+    opts.optopt(
+        "",
+        "paused-results-limit",
+        "maximum number of tuples held back per paused subscription",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "hector-batch-limit",
+        "maximum number of prefixes worked off at a time per worst-case optimal join operator",
+        "N",
+    );
+    opts.optmulti(
+        "",
+        "udf",
+        "implement an external function by a subprocess",
+        "NAME=COMMAND",
+    );
+    opts.optopt("", "udf-batch-size", "tuples sent to a udf at once", "N");
+    opts.optopt(
+        "",
+        "udf-timeout",
+        "time allowed for a udf to respond to a batch",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "udf-restarts",
+        "restarts of failing udfs (never, always, or a limit)",
+        "POLICY",
+    );
+    opts.optopt(
+        "",
+        "snapshot-dir",
+        "write and restore snapshots within this directory",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "restore",
+        "load attribute contents from this snapshot",
+        "NAME",
+    );
+    opts.optopt(
+        "",
+        "durable-dir",
+        "retain results for durable consumers in this directory",
+        "PATH",
+    );
+    opts.optmulti(
+        "",
+        "priority",
+        "restore interests in this relation first after a restart",
+        "NAME",
+    );
+    opts.optopt(
+        "",
+        "durable-max-batches",
+        "maximum number of batches retained per durable consumer",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "durable-max-age",
+        "maximum age of batches retained per durable consumer",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "replication-log",
+        "append all writes to this file, for replicas to follow",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "replica-of",
+        "serve reads only, following this replication log",
+        "PATH",
+    );
+    opts.optopt(
+        "",
+        "shutdown-grace-period",
+        "time allowed for delivering results on shutdown",
+        "MS",
+    );
+    #[cfg(feature = "admin-ui")]
+    opts.optopt("", "admin-port", "serve the admin ui at this port", "PORT");
 
     let args: Vec<String> = std::env::args().collect();
     let timely_args = std::env::args().take_while(|ref arg| *arg != "--");
@@ -80,7 +607,7 @@ fn main() {
         let server_args = args.iter().rev().take_while(|arg| *arg != "--");
         let default_config: Config = Default::default();
         let config = match opts.parse(server_args) {
-            Err(err) => panic!(err),
+            Err(err) => misconfigured(err),
             Ok(matches) => {
                 let starting_port = matches
                     .opt_str("port")
@@ -93,31 +620,224 @@ fn main() {
                     enable_history: matches.opt_present("enable-history"),
                     enable_optimizer: matches.opt_present("enable-optimizer"),
                     enable_meta: matches.opt_present("enable-meta"),
+                    enable_tx_times: matches.opt_present("enable-tx-times"),
+                    watchdog_timeout: matches
+                        .opt_str("watchdog-timeout")
+                        .and_then(|x| x.parse().ok())
+                        .map(Duration::from_millis),
+                    enable_watchdog_notifications: matches.opt_present("enable-watchdog-notifications"),
+                    enable_simulation: matches.opt_present("simulation"),
+                    enable_logging: matches.opt_present("enable-logging"),
+                    accounting_interval: matches
+                        .opt_str("accounting-interval")
+                        .and_then(|x| x.parse().ok())
+                        .map(Duration::from_millis),
+                    paused_results_limit: matches
+                        .opt_str("paused-results-limit")
+                        .and_then(|x| x.parse().ok())
+                        .unwrap_or_else(|| Config::default().paused_results_limit),
+                    external_functions: matches
+                        .opt_strs("udf")
+                        .iter()
+                        .map(|spec| {
+                            let (name, mut function) = ExternalFunction::parse(spec)
+                                .unwrap_or_else(misconfigured);
+
+                            if let Some(batch_size) = matches
+                                .opt_str("udf-batch-size")
+                                .and_then(|x| x.parse().ok())
+                            {
+                                function.batch_size = batch_size;
+                            }
+
+                            if let Some(timeout) = matches
+                                .opt_str("udf-timeout")
+                                .and_then(|x| x.parse().ok())
+                            {
+                                function.timeout = Duration::from_millis(timeout);
+                            }
+
+                            if let Some(restart) = matches.opt_str("udf-restarts") {
+                                function.restart = restart.parse().unwrap_or_else(misconfigured);
+                            }
+
+                            (name, function)
+                        })
+                        .collect(),
+                    hector_batch_limit: matches
+                        .opt_str("hector-batch-limit")
+                        .and_then(|x| x.parse().ok()),
+                    snapshot_dir: matches.opt_str("snapshot-dir").map(PathBuf::from),
                 }
             }
         };
 
+        #[cfg(feature = "admin-ui")]
+        {
+            if worker.index() == 0 {
+                let server_args = args.iter().rev().take_while(|arg| *arg != "--");
+                if let Some(admin_port) = opts
+                    .parse(server_args)
+                    .ok()
+                    .and_then(|matches| matches.opt_str("admin-port"))
+                    .and_then(|port| port.parse().ok())
+                {
+                    serve_admin_ui(admin_port);
+                }
+            }
+        }
+
         // setup interpretation context
         let mut server = Server::<Token>::new(config.clone());
 
+        // Logging has to be set up before any dataflow is
+        // constructed, in order to observe all of them.
+        if config.enable_logging {
+            server.logging = Some(Logging::install(worker.index(), &mut worker.log_register()));
+        }
+
+        if let Some(ref mut accounting) = server.accounting {
+            let server_args = args.iter().rev().take_while(|arg| *arg != "--");
+            if let Some(url) = opts
+                .parse(server_args)
+                .ok()
+                .and_then(|matches| matches.opt_str("accounting-webhook"))
+            {
+                let webhook = UsageWebhook::new(&url).unwrap_or_else(|err| misconfigured(err.message));
+                accounting.hooks.push(Box::new(webhook));
+            }
+        }
+
         // The server might specify a sequence of requests for
         // setting-up built-in arrangements. We serialize those here
         // and pre-load the sequencer with them, such that they will
         // flow through the regular request handling.
-        let builtins = Server::<Token>::builtins();
+        let mut builtins = Server::<Token>::builtins();
+
+        // Schema persisted by a previous run is restored right after
+        // the built-ins. Only the first worker appends to the
+        // catalog, but all of them replay it.
+        let server_args = args.iter().rev().take_while(|arg| *arg != "--");
+        let server_matches = opts.parse(server_args).ok();
+        let mut catalog = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("catalog"))
+            .map(|path| Catalog::open(path).expect("failed to open catalog"));
+
+        // Interests in priority relations are served as soon as the
+        // schema they depend on has been restored, ahead of all
+        // others (see `recovery_order`).
+        let priority: HashSet<String> = server_matches
+            .as_ref()
+            .map(|matches| matches.opt_strs("priority").into_iter().collect())
+            .unwrap_or_default();
+
+        if let Some(ref catalog) = catalog {
+            let mut restored = catalog.replay(&priority).expect("failed to replay catalog");
+            info!("[WORKER {}] restoring {} requests from catalog", worker.index(), restored.len());
+            builtins.append(&mut restored);
+        }
+
+        // A primary logs all writes it applies, s.t. read replicas
+        // can apply them as well. Replicas follow such a log instead
+        // of accepting writes from their own clients. Only the first
+        // worker writes or follows the log, but all of them have to
+        // know whether they are serving a replica.
+        let mut replication_log = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("replication-log"))
+            .map(|path| ReplicationLog::open(path).expect("failed to open replication log"));
+
+        let mut replica = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("replica-of"))
+            .map(|path| Replica::open(path).expect("failed to open replication log"));
+
+        let is_replica = replica.is_some();
+
+        // Attribute contents are loaded once the schema is in place,
+        // but before any rule or sink from the catalog is implemented.
+        if let Some(name) = server_matches.as_ref().and_then(|matches| matches.opt_str("restore")) {
+            info!("[WORKER {}] restoring attribute contents from snapshot {}", worker.index(), name);
+
+            let position = builtins
+                .iter()
+                .rposition(|request| match request {
+                    Request::CreateAttribute(_) | Request::RegisterSource(_) => true,
+                    _ => false,
+                })
+                .map_or(0, |position| position + 1);
+
+            builtins.insert(position, Request::Restore(name));
+        }
+
+        // Durable consumers are journaled to a single directory, each
+        // of them by the worker it is assigned to (see `journaled_by`).
+        let mut durable = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("durable-dir").map(|dir| (matches, dir)))
+            .map(|(matches, dir)| {
+                let max_batches = matches
+                    .opt_str("durable-max-batches")
+                    .map(|x| x.parse().unwrap_or_else(misconfigured))
+                    .unwrap_or(10_000);
+                let max_age = matches
+                    .opt_str("durable-max-age")
+                    .map(|x| Duration::from_millis(x.parse().unwrap_or_else(misconfigured)));
+
+                Durable::open(dir, max_batches, max_age).expect("failed to open durable directory")
+            });
+
+        // Consumers journaled before a restart subscribe again right
+        // away, s.t. their results keep being journaled.
+        let recovered = match durable {
+            None => Vec::new(),
+            Some(ref mut durable) => durable
+                .recover(worker.index(), worker.peers())
+                .expect("failed to recover durable consumers"),
+        };
+
+        // Durable consumers currently connected, by id.
+        let mut durable_clients: HashMap<String, Token> = HashMap::new();
+
+        // Interests of all durable consumers, by id, and the consumers
+        // whose results are being journaled. Known to all workers.
+        let mut durable_interests: HashMap<String, (usize, usize, RequestId, Interest)> = HashMap::new();
+        let mut durable_running: HashSet<String> = HashSet::new();
+
+        if worker.index() != 0 {
+            catalog = None;
+            replication_log = None;
+            replica = None;
+        }
+
         let preload_command = Command {
             owner: worker.index(),
             client: SYSTEM.0,
+            id: None,
             requests: builtins,
+            instant: wall_clock_millis(),
         };
 
         // setup serialized command queue (shared between all workers)
         let mut sequencer: Sequencer<Command> =
             Sequencer::preloaded(worker, Instant::now(), VecDeque::from(vec![preload_command]));
 
+        if !recovered.is_empty() {
+            info!("[WORKER {}] recovering {} durable consumers", worker.index(), recovered.len());
+
+            sequencer.push(Command {
+                owner: worker.index(),
+                client: SYSTEM.0,
+                id: None,
+                requests: recovery_order(recovered.into_iter().map(Request::Interest).collect(), &priority),
+                instant: wall_clock_millis(),
+            });
+        }
+
         // configure websocket server
         let ws_settings = ws::Settings {
-            max_connections: 1024,
+            max_connections: MAX_CONNECTIONS,
             ..ws::Settings::default()
         };
 
@@ -125,10 +845,14 @@ fn main() {
         let (send_cli, recv_cli) = mio::channel::channel();
 
         // setup results channel
-        let (send_results, recv_results) = mio::channel::channel::<(String, Vec<ResultDiff>)>();
+        let (send_results, recv_results) = mio::channel::channel::<Output>();
 
         // setup errors channel
-        let (send_errors, recv_errors) = mio::channel::channel::<(Vec<Token>, Vec<Error>)>();
+        let (send_errors, recv_errors) = mio::channel::channel::<(Vec<Token>, Vec<Error>, Option<RequestId>)>();
+
+        // setup channel for responses directed at specific clients
+        let (send_responses, recv_responses) =
+            mio::channel::channel::<(Vec<Token>, String, Payload)>();
 
         // setup server socket
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), config.port);
@@ -136,6 +860,30 @@ fn main() {
         let mut connections = Slab::with_capacity(ws_settings.max_connections);
         let mut next_connection_id: u32 = 0;
 
+        // setup plain tcp socket, if asked for
+        let tcp_socket = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("tcp-port"))
+            .and_then(|port| port.parse::<u16>().ok())
+            .map(|port| {
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port + (worker.index() as u16));
+                TcpListener::bind(&addr).unwrap()
+            });
+        let mut tcp_connections: Slab<FramedConnection> = Slab::with_capacity(MAX_CONNECTIONS);
+
+        // setup socket for read-only websocket connections, if asked for
+        let read_only_socket = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("read-only-port"))
+            .and_then(|port| port.parse::<u16>().ok())
+            .map(|port| {
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port + (worker.index() as u16));
+                TcpListener::bind(&addr).unwrap()
+            });
+
+        // settings of connections, as far as they have been touched
+        let mut sessions: HashMap<Token, Session> = HashMap::new();
+
         // setup event loop
         let poll = Poll::new().unwrap();
         let mut events = Events::with_capacity(1024);
@@ -174,9 +922,26 @@ fn main() {
             PollOpt::edge() | PollOpt::oneshot(),
         ).unwrap();
 
+        poll.register(
+            &recv_responses,
+            RESPONSES,
+            Ready::readable(),
+            PollOpt::edge() | PollOpt::oneshot(),
+        ).unwrap();
+
         poll.register(&server_socket, SERVER, Ready::readable(), PollOpt::level())
             .unwrap();
 
+        if let Some(ref tcp_socket) = tcp_socket {
+            poll.register(tcp_socket, TCP_SERVER, Ready::readable(), PollOpt::level())
+                .unwrap();
+        }
+
+        if let Some(ref read_only_socket) = read_only_socket {
+            poll.register(read_only_socket, READ_ONLY_SERVER, Ready::readable(), PollOpt::level())
+                .unwrap();
+        }
+
         info!(
             "[WORKER {}] running with config {:?}",
             worker.index(),
@@ -186,6 +951,34 @@ fn main() {
         // Sequence counter for commands.
         let mut next_tx: u64 = 0;
 
+        // the latest source-driven domain advance issued to the
+        // sequencer, s.t. it is not issued again while in flight
+        let mut requested_advance = Time::default();
+
+        // Once a shutdown has been requested, pending messages are
+        // still delivered to clients until this deadline.
+        let mut shutdown_deadline: Option<Instant> = None;
+        let shutdown_grace_period = server_matches
+            .as_ref()
+            .and_then(|matches| matches.opt_str("shutdown-grace-period"))
+            .map(|x| Duration::from_millis(x.parse().unwrap_or_else(misconfigured)))
+            .unwrap_or_else(|| Duration::from_secs(5));
+
+        // Interests that have been acknowledged, but not yet
+        // implemented, as (owner, client, interest).
+        let mut pending_interests: VecDeque<(usize, usize, RequestId, Interest)> = VecDeque::new();
+
+        // Interests waiting for rules or attributes they depend on,
+        // as (owner, client, interest). They are implemented once the
+        // command creating the last of those has been handled.
+        let mut waiting_interests: Vec<(usize, usize, RequestId, Interest)> = Vec::new();
+
+        // Interests that have been implemented, by the name of the
+        // relation implemented for them, as (owner, client, interest)
+        // of the first one. Relations whose rules are redefined are
+        // implemented anew from these.
+        let mut implementations: HashMap<String, (usize, usize, RequestId, Interest)> = HashMap::new();
+
         loop {
             // each worker has to...
             //
@@ -203,6 +996,10 @@ fn main() {
             // non-blocking (i.e. timeout 0), but higher timeouts can
             // be used for debugging or artificial braking
             //
+            // once all dataflows are done after a shutdown, all their
+            // results have been sent, and are received by this poll
+            let settled = shutdown_deadline.is_some() && server.probe.done();
+
             // @TODO handle errors
             poll.poll(&mut events, Some(Duration::from_millis(0)))
                 .unwrap();
@@ -217,20 +1014,17 @@ fn main() {
                 match event.token() {
                     CLI => {
                         while let Ok(cli_input) = recv_cli.try_recv() {
-                            match serde_json::from_str::<Vec<Request>>(&cli_input) {
-                                Err(serde_error) => {
-                                    let error = Error {
-                                        category: "df.error.category/incorrect",
-                                        message: serde_error.to_string(),
-                                    };
-
-                                    send_errors.send((vec![], vec![error])).unwrap();
+                            match parse_requests(&cli_input) {
+                                Err(error) => {
+                                    send_errors.send((vec![], vec![error], None)).unwrap();
                                 }
                                 Ok(requests) => {
                                     sequencer.push(Command {
                                         owner: worker.index(),
                                         client: SYSTEM.0,
+                                        id: None,
                                         requests,
+                                        instant: wall_clock_millis(),
                                     });
                                 }
                             }
@@ -243,10 +1037,15 @@ fn main() {
                             PollOpt::edge() | PollOpt::oneshot(),
                         ).unwrap();
                     }
-                    SERVER => {
+                    SERVER | READ_ONLY_SERVER => {
                         if event.readiness().is_readable() {
+                            let listener = match read_only_socket {
+                                Some(ref read_only_socket) if event.token() == READ_ONLY_SERVER => read_only_socket,
+                                _ => &server_socket,
+                            };
+
                             // new connection arrived on the server socket
-                            match server_socket.accept() {
+                            match listener.accept() {
                                 Err(err) => error!(
                                     "[WORKER {}] error while accepting connection {:?}",
                                     worker.index(),
@@ -278,6 +1077,10 @@ fn main() {
                                         token
                                     };
 
+                                    if event.token() == READ_ONLY_SERVER {
+                                        sessions.insert(token, Session { read_only: true, ..Default::default() });
+                                    }
+
                                     let conn = &mut connections[token.into()];
 
                                     conn.as_server().unwrap();
@@ -292,9 +1095,182 @@ fn main() {
                             }
                         }
                     }
+                    TCP_SERVER => {
+                        if let Some(ref tcp_socket) = tcp_socket {
+                            match tcp_socket.accept() {
+                                Err(err) => error!(
+                                    "[WORKER {}] error while accepting tcp connection {:?}",
+                                    worker.index(),
+                                    err
+                                ),
+                                Ok((_socket, addr)) if tcp_connections.len() >= MAX_CONNECTIONS => {
+                                    // the socket is closed as it is dropped,
+                                    // just like excess WebSocket connections
+                                    warn!(
+                                        "[WORKER {}] rejecting plain tcp connection from {}, already at {} connections",
+                                        worker.index(),
+                                        addr,
+                                        MAX_CONNECTIONS
+                                    );
+                                }
+                                Ok((socket, addr)) => {
+                                    info!("[WORKER {}] new plain tcp connection from {}", worker.index(), addr);
+
+                                    let entry = tcp_connections.vacant_entry();
+                                    let token = Token(TCP_CLIENTS + entry.key());
+                                    let conn = entry.insert(FramedConnection::new(socket));
+
+                                    poll.register(
+                                        conn.socket(),
+                                        token,
+                                        conn.events(),
+                                        PollOpt::edge() | PollOpt::oneshot(),
+                                    ).unwrap();
+                                }
+                            }
+                        }
+                    }
+                    token if is_tcp(token) => {
+                        let key = token.0 - TCP_CLIENTS;
+
+                        if !tcp_connections.contains(key) {
+                            continue;
+                        }
+
+                        if event.readiness().is_readable() {
+                            // plain tcp clients speak the same protocol,
+                            // one message per frame
+                            for message in tcp_connections[key].read() {
+                                // frames following a hello message are
+                                // in the negotiated encoding
+                                let encoding = sessions
+                                    .get(&token)
+                                    .map(|session| session.encoding)
+                                    .unwrap_or_default();
+
+                                receive(
+                                    worker.index(),
+                                    token,
+                                    encoding.decode(&message),
+                                    &mut sessions,
+                                    &mut sequencer,
+                                    &send_errors,
+                                    &send_responses,
+                                );
+                            }
+                        }
+
+                        if event.readiness().is_writable() {
+                            tcp_connections[key].write();
+                        }
+
+                        if tcp_connections[key].is_closed() {
+                            trace!("Plain tcp connection token={:?} disconnected.", token);
+
+                            // Relations held by this client must be
+                            // released, and its interests ended, on all
+                            // workers.
+                            let requests: Vec<Request> = server
+                                .held_by(worker.index(), &token)
+                                .into_iter()
+                                .map(Request::Release)
+                                .chain(server.subscribed_by(worker.index(), &token).into_iter().map(Request::Uninterest))
+                                .collect();
+                            if !requests.is_empty() {
+                                sequencer.push(Command {
+                                    owner: worker.index(),
+                                    client: token.0,
+                                    id: None,
+                                    requests,
+                                    instant: wall_clock_millis(),
+                                });
+                            }
+
+                            sessions.remove(&token);
+                            server.paused.remove(&token);
+                            server.coalescing.remove(&token);
+                            durable_clients.retain(|_, client| *client != token);
+
+                            let conn = tcp_connections.remove(key);
+                            poll.deregister(conn.socket()).unwrap();
+                        } else {
+                            let conn = &tcp_connections[key];
+                            poll.reregister(
+                                conn.socket(),
+                                token,
+                                conn.events(),
+                                PollOpt::edge() | PollOpt::oneshot(),
+                            ).unwrap();
+                        }
+                    }
                     RESULTS => {
-                        while let Ok((query_name, results)) = recv_results.try_recv() {
-                            info!("[WORKER {}] {:?} {:?}", worker.index(), query_name, results);
+                        // batches journaled for durable consumers, delivered
+                        // once all journals have been synced at once
+                        let mut journaled: Vec<(String, DurableBatch)> = Vec::new();
+
+                        while let Ok(output) = recv_results.try_recv() {
+                            info!("[WORKER {}] {:?}", worker.index(), output);
+
+                            // outputs of discarded implementations are stale
+                            let current = match output {
+                                Output::Results(ref query_name, generation, _)
+                                | Output::Frontier(ref query_name, generation, _)
+                                | Output::Journal(_, ref query_name, generation, _) => {
+                                    generation == server.generation(query_name)
+                                }
+                            };
+
+                            if !current {
+                                continue;
+                            }
+
+                            if let Output::Journal(ref consumer, ref query_name, _, ref results) = output {
+                                if let Some(ref mut durable) = durable {
+                                    match durable.append_to(consumer, query_name, results, SystemTime::now()) {
+                                        Err(error) => error!("[DURABLE] failed to journal results: {:?}", error),
+                                        Ok(None) => {}
+                                        Ok(Some(batch)) => journaled.push((consumer.clone(), batch)),
+                                    }
+                                }
+
+                                continue;
+                            }
+
+                            // Results are sent under the name of the
+                            // query, frontier advancements are sent as
+                            // df.frontier messages naming the query.
+                            let (query_name, message) = match output {
+                                Output::Results(query_name, _, mut results) => {
+                                    // in the order asked for by the interest, if any
+                                    server.sort_results(&query_name, &mut results);
+
+                                    // paused subscriptions catch up on resume
+                                    if !server.paused.is_empty() {
+                                        server.hold_back(&query_name, &results);
+                                    }
+
+                                    // coalescing subscriptions once results are due
+                                    if !server.coalescing.is_empty() {
+                                        server.coalesce_results(&query_name, &results);
+                                    }
+
+                                    (query_name.clone(), (query_name, Payload::Results(results)))
+                                }
+                                Output::Frontier(query_name, _, frontier) => {
+                                    if !server.coalescing.is_empty() {
+                                        server.coalesce_frontier(&query_name, &frontier);
+                                    }
+
+                                    let payload = serde_json::json!({
+                                        "df.frontier/name": query_name,
+                                        "df.frontier/time": frontier,
+                                    });
+
+                                    (query_name, ("df.frontier".to_string(), Payload::Json(payload)))
+                                }
+                                // journaled above
+                                Output::Journal(..) => continue,
+                            };
 
                             match server.interests.get(&query_name) {
                                 None => {
@@ -302,24 +1278,43 @@ fn main() {
                                     info!("NO INTEREST FOR THIS RESULT");
                                 }
                                 Some(tokens) => {
-                                    let serialized = serde_json::to_string::<(String, Vec<ResultDiff>)>(
-                                        &(query_name, results),
-                                    ).expect("failed to serialize outputs");
-                                    let msg = ws::Message::text(serialized);
+                                    let (name, payload) = message;
+                                    let mut outgoing = Outgoing::new(name, payload);
 
                                     for &token in tokens.iter() {
-                                        // @TODO check whether connection still exists
-                                        let conn = &mut connections[token.into()];
-
-                                        conn.send_message(msg.clone())
-                                            .expect("failed to send message");
-
-                                        poll.reregister(
-                                            conn.socket(),
-                                            conn.token(),
-                                            conn.events(),
-                                            PollOpt::edge() | PollOpt::oneshot(),
-                                        ).unwrap();
+                                        if server.is_paused(&query_name, &token) || server.is_coalescing(&query_name, &token) {
+                                            continue;
+                                        }
+
+                                        let bytes = deliver(&poll, &mut connections, &mut tcp_connections, &sessions, token, &mut outgoing);
+
+                                        if let Some(ref mut accounting) = server.accounting {
+                                            accounting.deliver(&query_name, token.0, bytes);
+                                        }
+                                    }
+                                }
+                            }
+                        }
+
+                        // batches are only delivered once they are durable
+                        if let Some(ref mut durable) = durable {
+                            if !journaled.is_empty() {
+                                match durable.sync() {
+                                    Err(error) => error!("[DURABLE] failed to sync journals: {:?}", error),
+                                    Ok(()) => {
+                                        for (consumer, batch) in journaled.drain(..) {
+                                            if let Some(&token) = durable_clients.get(&consumer) {
+                                                let name = batch.name.clone();
+                                                let payload = durable_payload(&consumer, batch, durable.lost(&consumer));
+                                                let mut outgoing = Outgoing::new("df.durable".to_string(), payload);
+
+                                                let bytes = deliver(&poll, &mut connections, &mut tcp_connections, &sessions, token, &mut outgoing);
+
+                                                if let Some(ref mut accounting) = server.accounting {
+                                                    accounting.deliver(&name, token.0, bytes);
+                                                }
+                                            }
+                                        }
                                     }
                                 }
                             }
@@ -333,35 +1328,32 @@ fn main() {
                         ).unwrap();
                     }
                     ERRORS => {
-                        while let Ok((tokens, mut errors)) = recv_errors.try_recv() {
-                            error!("[WORKER {}] {:?}", worker.index(), errors);
+                        while let Ok((tokens, mut errors, request_id)) = recv_errors.try_recv() {
+                            error!("[WORKER {}] {:?} {:?}", worker.index(), request_id, errors);
 
-                            let serializable = errors.drain(..).map(|error| {
+                            let serializable: Vec<serde_json::Value> = errors.drain(..).map(|error| {
                                 let mut serializable = serde_json::Map::new();
                                 serializable.insert("df.error/category".to_string(), serde_json::Value::String(error.category.to_string()));
                                 serializable.insert("df.error/message".to_string(), serde_json::Value::String(error.message.to_string()));
 
-                                serializable
+                                if let Some(request_id) = request_id {
+                                    serializable.insert("df.error/request".to_string(), serde_json::json!({
+                                        "df.request/tx": request_id.tx,
+                                        "df.request/offset": request_id.offset,
+                                        "df.request/kind": request_id.kind,
+                                        "df.request/command": request_id.command,
+                                    }));
+                                }
+
+                                serde_json::Value::Object(serializable)
                             }).collect();
 
-                            let serialized = serde_json::to_string::<(String, Vec<serde_json::Map<_,_>>)>(
-                                &("df.error".to_string(), serializable)
-                            ).expect("failed to serialize errors");
-                            let msg = ws::Message::text(serialized);
+                            let mut outgoing = Outgoing::new("df.error".to_string(), serde_json::Value::Array(serializable));
 
+                            // Errors in system commands (e.g. while
+                            // restoring the catalog) are only logged.
                             for &token in tokens.iter() {
-                                // @TODO check whether connection still exists
-                                let conn = &mut connections[token.into()];
-
-                                conn.send_message(msg.clone())
-                                    .expect("failed to send message");
-
-                                poll.reregister(
-                                    conn.socket(),
-                                    conn.token(),
-                                    conn.events(),
-                                    PollOpt::edge() | PollOpt::oneshot(),
-                                ).unwrap();
+                                deliver(&poll, &mut connections, &mut tcp_connections, &sessions, token, &mut outgoing);
                             }
                         }
 
@@ -372,6 +1364,24 @@ fn main() {
                             PollOpt::edge() | PollOpt::oneshot(),
                         ).unwrap();
                     }
+                    RESPONSES => {
+                        while let Ok((tokens, name, payload)) = recv_responses.try_recv() {
+                            let mut outgoing = Outgoing::new(name, payload);
+
+                            // Responses to system commands have no
+                            // connection to go to.
+                            for &token in tokens.iter() {
+                                deliver(&poll, &mut connections, &mut tcp_connections, &sessions, token, &mut outgoing);
+                            }
+                        }
+
+                        poll.reregister(
+                            &recv_responses,
+                            RESPONSES,
+                            Ready::readable(),
+                            PollOpt::edge() | PollOpt::oneshot(),
+                        ).unwrap();
+                    }
                     _ => {
                         let token = event.token();
                         let active = {
@@ -397,27 +1407,21 @@ fn main() {
                                         for conn_event in conn_events.drain(0..) {
                                             match conn_event {
                                                 ConnEvent::Message(msg) => {
-                                                    match serde_json::from_str::<Vec<Request>>(&msg.into_text().unwrap()) {
-                                                        Err(serde_error) => {
-                                                            let error = Error {
-                                                                category: "df.error.category/incorrect",
-                                                                message: serde_error.to_string(),
-                                                            };
-
-                                                            send_errors.send((vec![token], vec![error])).unwrap();
-                                                        }
-                                                        Ok(requests) => {
-                                                            let command = Command {
-                                                                owner: worker.index(),
-                                                                client: token.into(),
-                                                                requests,
-                                                            };
-
-                                                            trace!("[WORKER {}] {:?}", worker.index(), command);
-
-                                                            sequencer.push(command);
-                                                        }
-                                                    }
+                                                    // binary messages are always CBOR
+                                                    let incoming = match msg {
+                                                        ws::Message::Text(text) => Encoding::Json.decode(text.as_bytes()),
+                                                        ws::Message::Binary(bytes) => Encoding::Cbor.decode(&bytes),
+                                                    };
+
+                                                    receive(
+                                                        worker.index(),
+                                                        token,
+                                                        incoming,
+                                                        &mut sessions,
+                                                        &mut sequencer,
+                                                        &send_errors,
+                                                        &send_responses,
+                                                    );
                                                 }
                                                 _ => {
                                                     println!("other");
@@ -456,6 +1460,30 @@ fn main() {
                             } else {
                                 trace!("WebSocket connection to token={:?} disconnected.", token);
                             }
+
+                            // Relations held by this client must be
+                            // released, and its interests ended, on all
+                            // workers.
+                            let requests: Vec<Request> = server
+                                .held_by(worker.index(), &token)
+                                .into_iter()
+                                .map(Request::Release)
+                                .chain(server.subscribed_by(worker.index(), &token).into_iter().map(Request::Uninterest))
+                                .collect();
+                            if !requests.is_empty() {
+                                sequencer.push(Command {
+                                    owner: worker.index(),
+                                    client: token.into(),
+                                    id: None,
+                                    requests,
+                                    instant: wall_clock_millis(),
+                                });
+                            }
+
+                            sessions.remove(&token);
+                            server.paused.remove(&token);
+                            server.coalescing.remove(&token);
+                            durable_clients.retain(|_, client| *client != token);
                             connections.remove(token.into());
                         } else {
                             let conn = &connections[token.into()];
@@ -470,114 +1498,1220 @@ fn main() {
                 }
             }
 
-            // handle commands
+            if let Some(deadline) = shutdown_deadline {
+                let pending = connections
+                    .iter()
+                    .any(|(_, conn)| conn.events().is_writable())
+                    || tcp_connections
+                    .iter()
+                    .any(|(_, conn)| conn.events().is_writable());
+
+                if (settled && !pending) || Instant::now() >= deadline {
+                    info!("[WORKER {}] shutting down", worker.index());
+                    break;
+                }
+            }
 
-            while let Some(mut command) = sequencer.next() {
+            // relations whose rules have been redefined are implemented
+            // anew for the clients still interested in them, those
+            // whose rules are gone are dropped
 
-                // Count-up sequence numbers.
-                next_tx += 1;
+            for name in server.invalidated.drain(..).collect::<Vec<_>>() {
+                // durable consumers keep their journal, their dataflow
+                // is implemented anew once the rule exists again
+                let consumers: Vec<String> = durable_interests
+                    .iter()
+                    .filter(|(_, (_, _, _, interest))| interest.parameterized_name() == name)
+                    .map(|(consumer, _)| consumer.clone())
+                    .collect();
 
-                info!("[WORKER {}] {:?} {:?}", worker.index(), next_tx, command);
+                for consumer in consumers {
+                    durable_running.remove(&consumer);
 
-                let owner = command.owner;
-                let client = command.client;
+                    let (owner, client, request_id, interest) = durable_interests[&consumer].clone();
 
-                for req in command.requests.drain(..) {
+                    if !server.context.rules.contains_key(&interest.name) {
+                        durable_interests.remove(&consumer);
+                        continue;
+                    }
 
-                    // @TODO only create a single dataflow, but only if req != Transact
+                    match server.parameterize(interest) {
+                        Ok(req) => pending_interests.push_back((owner, client, request_id, req)),
+                        Err(error) => error!("[DURABLE] failed to re-implement {}: {:?}", consumer, error),
+                    }
+                }
 
-                    match req {
-                        Request::Transact(req) => {
-                            if let Err(error) = server.transact(req, owner, worker.index()) {
-                                send_errors.send((vec![Token(client)], vec![error])).unwrap();
-                            }
-                        }
-                        Request::Interest(req) => {
-                            if owner == worker.index() {
-                                // we are the owning worker and thus have to
-                                // keep track of this client's new interest
+                let (owner, client, request_id, interest) = match implementations.remove(&name) {
+                    None => continue,
+                    Some(implementation) => implementation,
+                };
+
+                let outcome = if server.context.rules.contains_key(&interest.name) {
+                    server.parameterize(interest.clone()).map(Some)
+                } else {
+                    Ok(None)
+                };
+
+                let (redefined, status) = match outcome {
+                    Ok(Some(req)) => {
+                        implementations.insert(req.name.clone(), (owner, client, request_id, interest));
+                        pending_interests.push_back((owner, client, request_id, req));
+
+                        (true, serde_json::json!({
+                            "df.interest/name": name,
+                            "df.interest/status": "redefined",
+                        }))
+                    }
+                    Ok(None) => (false, serde_json::json!({
+                        "df.interest/name": name,
+                        "df.interest/status": "unregistered",
+                    })),
+                    Err(error) => (false, serde_json::json!({
+                        "df.interest/name": name,
+                        "df.interest/status": "failed",
+                        "df.interest/error": {
+                            "df.error/category": error.category,
+                            "df.error/message": error.message,
+                        },
+                    })),
+                };
+
+                if !redefined {
+                    server.unsubscribe_all(&name);
+                }
 
-                                let client_token = Token(command.client);
-                                server.interests
-                                    .entry(req.name.clone())
-                                    .or_insert_with(Vec::new)
-                                    .push(client_token);
-                            }
+                // only the owning workers know about the clients
+                let tokens = if redefined {
+                    server.interests.get(&name).cloned().unwrap_or_default()
+                } else {
+                    server.interests.remove(&name).unwrap_or_default()
+                };
+
+                if !tokens.is_empty() {
+                    send_responses
+                        .send((tokens, "df.interest".to_string(), status.into()))
+                        .unwrap();
+                }
+            }
 
-                            if server.context.global_arrangement(&req.name).is_none() {
+            // interests acknowledged in the previous iteration are
+            // implemented before any later command is handled, s.t.
+            // all workers construct their dataflows in the same order
+
+            while let Some((owner, client, request_id, req)) = pending_interests.pop_front() {
+                // durable consumers are served by a dataflow of their own
+                let served = match req.consumer {
+                    None => server.context.global_arrangement(&req.name).is_some(),
+                    Some(ref consumer) => durable_running.contains(consumer),
+                };
+
+                // interests in relations whose rules or attributes
+                // don't exist yet wait for them, rather than failing
+                if !served {
+                    let missing = server.missing_dependencies(&req.name);
+
+                    if !missing.is_empty() {
+                        if owner == worker.index() {
+                            let status = serde_json::json!({
+                                "df.interest/name": req.name,
+                                "df.interest/status": "pending",
+                                "df.interest/missing": missing,
+                            });
 
-                                let send_results_handle = send_results.clone();
+                            send_responses
+                                .send((vec![Token(client)], "df.interest".to_string(), status.into()))
+                                .unwrap();
+                        }
 
-                                worker.dataflow::<u64, _, _>(|scope| {
-                                    let name = req.name.clone();
+                        waiting_interests.push((owner, client, request_id, req));
+                        continue;
+                    }
+                }
 
-                                    match server.interest(&req.name, scope) {
-                                        Err(error) => {
-                                            send_errors.send((vec![Token(client)], vec![error])).unwrap();
-                                        }
-                                        Ok(trace) => {
-                                            trace
-                                                .import_named(scope, &req.name)
-                                            // @TODO clone entire batches instead of flattening
-                                                .as_collection(|tuple,_| tuple.clone())
-                                                .inner
-                                            // .stream
-                                            // .map(|batch| (*batch).clone())
-                                                .unary_notify(
-                                                    Exchange::new(move |_| owner as u64),
-                                                    "ResultsRecv",
-                                                    vec![],
-                                                    move |input, _output: &mut OutputHandle<_, (), _>, _notificator| {
+                let rules = server.implementation_size(&req.name);
+
+                let result = if !served {
+
+                    let send_results_handle = send_results.clone();
+                    let send_responses_handle = send_responses.clone();
+
+                    // the subscription is live once results up to
+                    // the time of implementation have been sent
+                    let live_at = *server.context.internal.time();
+
+                    let replicated = server.context.is_replicated(&req.name);
+                    let (index, peers) = (worker.index(), worker.peers());
+                    let generation = server.generation(&req.name);
+
+                    worker.dataflow::<Time, _, _>(|scope| {
+                        let name = req.name.clone();
+                        let consumer = req.consumer.clone();
+
+                        match server.interest(&req.name, scope) {
+                            Err(error) => Err(error),
+                            Ok(trace) => {
+                                trace
+                                    .import_named(scope, &req.name)
+                                // @TODO clone entire batches instead of flattening
+                                    .as_collection(|tuple,_| tuple.clone())
+                                    .inner
+                                // every worker holds a full copy of replicated
+                                // relations, so each only forwards its share
+                                    .filter(move |(tuple, _time, _diff)| {
+                                        !replicated || (tuple.hashed().as_u64() as usize) % peers == index
+                                    })
+                                // .stream
+                                // .map(|batch| (*batch).clone())
+                                    .unary_frontier(
+                                        Exchange::new(move |_| owner as u64),
+                                        "ResultsRecv",
+                                        move |_capability, _info| {
+
+                                            // results are held back until their time
+                                            // is complete, s.t. clients receive a
+                                            // single batch per time
+                                            let mut pending: HashMap<Time, Vec<ResultDiff>> = HashMap::new();
+                                            let mut frontier: Vec<Time> = Vec::new();
+                                            let mut buffer = Vec::new();
+                                            let mut live = false;
+
+                                            move |input, _output: &mut OutputHandle<_, (), _>| {
+
+                                                // due to the exchange pact, this closure is only
+                                                // executed by the owning worker
+
+                                                input.for_each(|_time, data| {
+                                                    data.swap(&mut buffer);
+
+                                                    for (tuple, t, diff) in buffer.drain(..) {
+                                                        pending.entry(t).or_insert_with(Vec::new).push((tuple, t, diff));
+                                                    }
+                                                });
+
+                                                let next_frontier = input.frontier().frontier().to_vec();
+
+                                                if next_frontier != frontier {
+                                                    let mut complete: Vec<Time> = pending
+                                                        .keys()
+                                                        .filter(|t| !input.frontier().less_equal(t))
+                                                        .cloned()
+                                                        .collect();
+
+                                                    complete.sort();
+
+                                                    for t in complete.drain(..) {
+                                                        let results = pending.remove(&t).unwrap();
+                                                        let output = match consumer {
+                                                            None => Output::Results(name.clone(), generation, results),
+                                                            Some(ref consumer) => {
+                                                                Output::Journal(consumer.clone(), name.clone(), generation, results)
+                                                            }
+                                                        };
+
+                                                        send_results_handle.send(output).unwrap();
+                                                    }
+
+                                                    // durable consumers only receive batches
+                                                    if consumer.is_none() {
+                                                        send_results_handle
+                                                            .send(Output::Frontier(name.clone(), generation, next_frontier.clone()))
+                                                            .unwrap();
+                                                    }
+
+                                                    frontier = next_frontier;
+
+                                                    if !live && !input.frontier().less_equal(&live_at) {
+                                                        live = true;
+
+                                                        let status = serde_json::json!({
+                                                            "df.interest/name": name,
+                                                            "df.interest/status": "live",
+                                                        });
+
+                                                        send_responses_handle
+                                                            .send((vec![Token(client)], "df.interest".to_string(), status.into()))
+                                                            .unwrap();
+                                                    }
+                                                }
+                                            }
+                                        })
+                                    .probe_with(&mut server.probe);
+
+                                Ok(())
+                            }
+                        }
+                    })
+                } else {
+                    Ok(())
+                };
+
+                match result {
+                    Err(error) => {
+                        // the interest ends right away, along with the
+                        // relations derived just for it (e.g. windows)
+                        if req.consumer.is_none() {
+                            if owner == worker.index() {
+                                let remaining = server.interests.get_mut(&req.name).map(|tokens| {
+                                    tokens.retain(|token| *token != Token(client));
+                                    tokens.len()
+                                });
+
+                                if remaining == Some(0) {
+                                    server.interests.remove(&req.name);
+                                }
+                            }
+
+                            for collected in server.unsubscribe(&req.name, owner, &Token(client)) {
+                                implementations.remove(&collected);
+                            }
+                        }
+
+                        // errors are reported by the owner only, as
+                        // tokens are meaningless to other workers
+                        let tokens = if owner == worker.index() { vec![Token(client)] } else { Vec::new() };
+                        send_errors.send((tokens, vec![error], Some(request_id))).unwrap();
+                    }
+                    Ok(()) => {
+                        if let Some(ref consumer) = req.consumer {
+                            if !served {
+                                durable_running.insert(consumer.clone());
+
+                                // the new dataflow derives all results anew,
+                                // only their difference to those already
+                                // journaled is journaled
+                                if let Some(ref mut durable) = durable {
+                                    durable.resume(consumer);
+                                }
+                            }
+                        }
+
+                        if owner == worker.index() {
+                            // relations that are already being served are
+                            // live right away
+                            let mut status = serde_json::json!({
+                                "df.interest/name": req.name,
+                                "df.interest/status": if served { "live" } else { "implemented" },
+                                "df.interest/rules": rules,
+                            });
+
+                            // results carry multiplicities, rather than
+                            // being deduplicated
+                            if server.is_streaming(&req.name) {
+                                status["df.interest/streaming"] = serde_json::json!(true);
+                            }
+
+                            // the relation is served, but not as efficiently
+                            // as it could be
+                            if let Some(warning) = server.optimizer_fallback(&req.name) {
+                                status["df.interest/warning"] = serde_json::json!({
+                                    "df.error/category": warning.category,
+                                    "df.error/message": warning.message,
+                                });
+                            }
+
+                            send_responses
+                                .send((vec![Token(client)], "df.interest".to_string(), status.into()))
+                                .unwrap();
+                        }
+                    }
+                }
+            }
+
+            // replicas apply the primary's writes in logged order
+            if let Some(ref mut replica) = replica {
+                match replica.poll() {
+                    Err(error) => error!("[REPLICA] failed to follow primary: {:?}", error),
+                    Ok(commands) => {
+                        for (instant, requests) in commands {
+                            sequencer.push(Command {
+                                owner: worker.index(),
+                                client: SYSTEM.0,
+                                id: None,
+                                requests,
+                                instant,
+                            });
+                        }
+                    }
+                }
+            }
+
+            // handle commands
+
+            while shutdown_deadline.is_none() {
+                let mut command = match sequencer.next() {
+                    None => break,
+                    Some(command) => command,
+                };
+
+                // Count-up sequence numbers.
+                next_tx += 1;
+
+                info!("[WORKER {}] {:?} {:?}", worker.index(), next_tx, command);
+
+                let started = Instant::now();
+
+                let owner = command.owner;
+                let client = command.client;
+                let command_id = command.id;
+
+                // transactions are described by the instant at which
+                // the command was issued, rather than applied
+                server.context.internal.set_instant(command.instant);
+
+                // errors are reported by the owner only, as tokens
+                // are meaningless to other workers
+                let reply_to = if owner == worker.index() { vec![Token(client)] } else { Vec::new() };
+
+                // Batches are unpacked in place, s.t. their items are
+                // applied in order within this sequencer slot. We
+                // remember which batch each item belongs to, in order
+                // to report per-item results back to the client.
+                let mut batches: Vec<Vec<serde_json::Value>> = Vec::new();
+                let mut requests = Vec::with_capacity(command.requests.len());
+
+                // writes applied as part of this command, to be logged
+                // for replicas
+                let mut writes = Vec::new();
+
+                // failed requests, to be reported in the command's
+                // acknowledgement
+                let mut failures = Vec::new();
+
+                for req in command.requests.drain(..) {
+                    match req {
+                        Request::Batch(mut items) => {
+                            let batch = batches.len();
+                            batches.push(Vec::with_capacity(items.len()));
+                            requests.extend(items.drain(..).map(|item| (Some(batch), item)));
+                        }
+                        req => requests.push((None, req)),
+                    }
+                }
+
+                for (offset, (batch, req)) in requests.drain(..).enumerate() {
+
+                    let request_id = RequestId { tx: next_tx, offset, kind: req.kind(), command: command_id };
+
+                    // @TODO only create a single dataflow, but only if req != Transact
+
+                    // restored requests are already in the catalog
+                    let journaled = match catalog {
+                        Some(_) if client != SYSTEM.0 && Catalog::is_journaled(&req) => Some(req.clone()),
+                        _ => None,
+                    };
+
+                    // replicas receive writes via the replication
+                    // log only, everything else is up to the primary
+                    let rejected = is_replica && client != SYSTEM.0 && is_replicated(&req);
+
+                    // inputs are pinned to the time at which the
+                    // primary applied them, system requests (e.g.
+                    // built-ins) are issued by replicas themselves
+                    let replicated = match (&replication_log, &req) {
+                        // sources advance the domain via system commands
+                        (Some(_), Request::AdvanceDomain(None, next))
+                            if client == SYSTEM.0 && *next > *server.context.internal.time() =>
+                        {
+                            Some(req.clone())
+                        }
+                        _ if client == SYSTEM.0 => None,
+                        (Some(_), Request::Transact(tx_data)) => Some(Request::TransactAt(
+                            *server.context.internal.time(),
+                            tx_data.clone(),
+                        )),
+                        (Some(_), req) if is_replicated(req) => Some(req.clone()),
+                        _ => None,
+                    };
+
+                    let result = match req {
+                        _ if rejected => Err(Error {
+                            category: "df.error.category/unsupported",
+                            message: format!(
+                                "{} requests must be sent to the primary, this server is a read replica.",
+                                request_id.kind
+                            ),
+                        }),
+                        Request::Transact(req) => server.transact(req, owner, worker.index()),
+                        Request::TransactAt(time, req) => server.transact_at(req, time, owner, worker.index()),
+                        Request::Interest(ref interest) if interest.consumer.as_ref().map_or(false, |consumer| {
+                            journaled_by(consumer, worker.peers()) != owner
+                        }) => {
+                            // each durable consumer is journaled by one worker,
+                            // clients have to connect to that one
+                            let consumer = interest.consumer.clone().unwrap_or_default();
+
+                            Err(Error {
+                                category: "df.error.category/incorrect",
+                                message: format!(
+                                    "Durable consumer {} is journaled by worker {}, connect to it instead.",
+                                    consumer,
+                                    journaled_by(&consumer, worker.peers())
+                                ),
+                            })
+                        }
+                        Request::Interest(interest) => match server.parameterize(interest.clone()) {
+                            // interests carrying bindings refer to a specialized rule
+                            Err(error) => Err(error),
+                            Ok(Interest { name, as_of: Some(as_of), .. }) => {
+                                let send_responses_handle = send_responses.clone();
+                                let relation = name.clone();
+
+                                let result = worker.dataflow::<Time, _, _>(|scope| {
+                                    match server.interest_as_of(&name, as_of, scope) {
+                                        Err(error) => Err(error),
+                                        Ok(snapshot) => {
+                                            snapshot
+                                                .inner
+                                                .unary_notify(
+                                                    Exchange::new(move |_| owner as u64),
+                                                    "SnapshotRecv",
+                                                    vec![],
+                                                    move |input, _output: &mut OutputHandle<_, (), _>, _notificator| {
 
                                                         // due to the exchange pact, this closure is only
                                                         // executed by the owning worker
 
                                                         input.for_each(|_time, data| {
-                                                            send_results_handle
-                                                                .send((name.clone(), data.to_vec()))
+                                                            send_responses_handle
+                                                                .send((vec![Token(client)], name.clone(), Payload::Results(data.to_vec())))
                                                                 .unwrap();
                                                         });
                                                     })
                                                 .probe_with(&mut server.probe);
+
+                                            Ok(())
                                         }
                                     }
                                 });
+
+                                // relations derived just for the snapshot
+                                // aren't needed any longer
+                                server.collect(&relation);
+
+                                result
                             }
-                        }
-                        Request::Register(req) => {
-                            if let Err(error) = server.register(req) {
-                                send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                            Ok(req) => {
+                                // the relation is implemented regardless, s.t.
+                                // all workers agree on the dataflows created
+                                let mut failed = None;
+
+                                match req.consumer {
+                                    Some(_) => server.pin(&req.name),
+                                    None => server.subscribe(&req.name, owner, Token(client)),
+                                }
+
+                                if owner == worker.index() {
+                                    // we are the owning worker and thus have to
+                                    // keep track of this client's new interest
+
+                                    let client_token = Token(command.client);
+                                    match req.consumer {
+                                        // durable consumers receive results via their journal
+                                        Some(ref consumer) => match durable {
+                                            None => {
+                                                failed = Some(Error {
+                                                    category: "df.error.category/unsupported",
+                                                    message: "Durable consumers require a --durable-dir.".to_string(),
+                                                });
+                                            }
+                                            Some(ref mut durable) => match durable
+                                                .subscribe(consumer, &req.name, SystemTime::now())
+                                                .and_then(|batches| durable.record_interest(consumer, &interest).map(|_| batches))
+                                            {
+                                                Err(error) => failed = Some(error),
+                                                // consumers recovered at startup are
+                                                // not connected yet
+                                                Ok(_) if client_token == SYSTEM => {}
+                                                Ok(batches) => {
+                                                    durable_clients.insert(consumer.clone(), client_token);
+
+                                                    for batch in batches {
+                                                        let payload = durable_payload(consumer, batch, durable.lost(consumer));
+
+                                                        send_responses
+                                                            .send((vec![client_token], "df.durable".to_string(), payload.into()))
+                                                            .unwrap();
+                                                    }
+                                                }
+                                            },
+                                        },
+                                        None => {
+                                            server.interests
+                                                .entry(req.name.clone())
+                                                .or_insert_with(Vec::new)
+                                                .push(client_token);
+
+                                            server.set_delivery(&req.name, client_token, req.delivery);
+                                        }
+                                    }
+
+                                }
+
+                                // durable consumers are journaled by a dataflow
+                                // of their own, implemented anew on redefinition
+                                match req.consumer {
+                                    None => {
+                                        implementations
+                                            .entry(req.name.clone())
+                                            .or_insert_with(|| (owner, client, request_id, interest.clone()));
+                                    }
+                                    Some(ref consumer) => {
+                                        durable_interests.insert(consumer.clone(), (owner, client, request_id, interest.clone()));
+                                    }
+                                }
+
+                                // implementing the relation might take a while,
+                                // so it is deferred to the next iteration
+                                pending_interests.push_back((owner, client, request_id, req));
+
+                                match failed {
+                                    None => Ok(()),
+                                    Some(error) => Err(error),
+                                }
                             }
+                        },
+                        Request::Register(req) => {
+                            let mut metadata = req.metadata.clone();
+
+                            // relations reading from a rule can only be
+                            // traced back to it before it is replaced
+                            let mut readers: HashMap<String, Vec<String>> = req
+                                .rules
+                                .iter()
+                                .map(|rule| (rule.name.clone(), server.invalidated_by(&rule.name)))
+                                .collect();
+
+                            server.register(req).and_then(|outcomes| {
+                                for (name, outcome) in outcomes.iter() {
+                                    if *outcome == RegisterOutcome::Registered || *outcome == RegisterOutcome::Replaced {
+                                        let metadata = metadata.remove(name).unwrap_or_default();
+                                        server.describe_rule(name, metadata, owner, worker.index())?;
+                                    }
+
+                                    // results are delivered anew from the
+                                    // redefined rule, so clients have to
+                                    // discard what they have seen so far
+                                    if *outcome == RegisterOutcome::Replaced {
+                                        let relations = readers.remove(name).unwrap_or_default();
+                                        notify_invalidated(&server, relations, name, "redefined", "discard", &send_responses);
+                                    }
+                                }
+
+                                if owner == worker.index() {
+                                    let payload = serde_json::to_value(outcomes)
+                                        .expect("failed to serialize outcomes");
+
+                                    send_responses
+                                        .send((vec![Token(client)], "df.register".to_string(), payload.into()))
+                                        .unwrap();
+                                }
+
+                                Ok(())
+                            })
+                        }
+                        Request::Unregister(name) => {
+                            let relations = server.invalidated_by(&name);
+
+                            server.unregister(&name).and_then(|_| {
+                                notify_invalidated(&server, relations, &name, "unregistered", "resubscribe", &send_responses);
+                                server.forget_rule(&name, owner, worker.index())
+                            })
                         }
                         Request::RegisterSource(req) => {
-                            worker.dataflow::<u64, _, _>(|scope| {
-                                if let Err(error) = server.register_source(req, scope) {
-                                    send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                            let RegisterSource { names, source, .. } = req.clone();
+
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                server.register_source(req, scope)
+                            }).and_then(|_| {
+                                for name in names.iter() {
+                                    server.record_attribute(name, None, Some(&source), owner, worker.index())?;
                                 }
-                            });
+
+                                Ok(())
+                            })
                         }
-                        Request::CreateAttribute(CreateAttribute { name, semantics }) => {
-                            worker.dataflow::<u64, _, _>(|scope| {
-                                if let Err(error) = server.context.internal.create_attribute(&name, semantics, scope) {
-                                    send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                        Request::SampleSource(req) => {
+                            // the source is read by the owner only
+                            if owner == worker.index() {
+                                server.sample_source(&req).map(|sample| {
+                                    let payload = serde_json::to_value(sample)
+                                        .expect("failed to serialize sample");
+
+                                    send_responses
+                                        .send((vec![Token(client)], "df.sample".to_string(), payload.into()))
+                                        .unwrap();
+                                })
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Request::RegisterSink(req) => {
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                server.register_sink(req, scope)
+                            })
+                        }
+                        Request::CreateAttribute(CreateAttribute { name, semantics, retention, value_type, append_only, metadata }) => {
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                server.exclude_from_logs(&name, scope);
+                                server.context.internal.create_attribute(&name, semantics.clone(), scope)
+                            }).and_then(|_| match retention {
+                                None => Ok(()),
+                                Some(policy) => server.context.internal.set_retention(&name, policy),
+                            }).and_then(|_| match value_type {
+                                None => Ok(()),
+                                Some(typ) => server.context.internal.set_value_type(&name, typ),
+                            }).and_then(|_| {
+                                if append_only {
+                                    server.context.internal.set_append_only(&name)
+                                } else {
+                                    Ok(())
                                 }
+                            }).and_then(|_| {
+                                server.record_attribute(&name, Some(&semantics), None, owner, worker.index())
+                            }).and_then(|_| {
+                                server.describe_attribute(&name, metadata, owner, worker.index())
+                            })
+                        }
+                        // source-driven advances might have been
+                        // overtaken by the time they are sequenced
+                        Request::AdvanceDomain(None, next)
+                            if client == SYSTEM.0 && next <= *server.context.internal.time() =>
+                        {
+                            Ok(())
+                        }
+                        Request::AdvanceDomain(name, next) => server.advance_domain(name, next),
+                        Request::CloseInput(name) => {
+                            server.context.internal.close_input(name.clone()).map(|_| {
+                                let relations = server.invalidated_by(&name);
+                                notify_invalidated(&server, relations, &name, "closed", "resubscribe", &send_responses);
+                            })
+                        }
+                        Request::Batch(_) => Err(Error {
+                            category: "df.error.category/incorrect",
+                            message: "Batches may not be nested.".to_string(),
+                        }),
+                        Request::Flush => {
+                            if owner == worker.index() {
+                                server.flush(Token(client));
+                            }
+
+                            Ok(())
+                        }
+                        Request::Hold(name, as_of) => server.hold(&name, as_of, owner, Token(client)),
+                        Request::Release(name) => {
+                            server.release(&name, owner, &Token(client));
+                            Ok(())
+                        }
+                        Request::Uninterest(name) => {
+                            waiting_interests.retain(|(o, c, _, req)| !(req.name == name && *o == owner && *c == client));
+
+                            // subscriptions are only tracked by the
+                            // worker owning the respective client
+                            if owner == worker.index() {
+                                let remaining = server.interests.get_mut(&name).map(|tokens| {
+                                    tokens.retain(|token| *token != Token(client));
+                                    tokens.len()
+                                });
+
+                                if remaining == Some(0) {
+                                    server.interests.remove(&name);
+                                }
+
+                                if let Some(paused) = server.paused.get_mut(&Token(client)) {
+                                    paused.remove(&name);
+                                }
+
+                                if let Some(coalescing) = server.coalescing.get_mut(&Token(client)) {
+                                    coalescing.remove(&name);
+                                }
+                            }
+
+                            for collected in server.unsubscribe(&name, owner, &Token(client)) {
+                                implementations.remove(&collected);
+                            }
+
+                            // redefinitions are implemented on behalf of a client
+                            // still interested, if any, rather than one that is gone
+                            let remaining = server.subscriber(&name).map(|(owner, token)| (*owner, token.0));
+                            match remaining {
+                                None => {
+                                    implementations.remove(&name);
+                                }
+                                Some((other_owner, other_client)) => {
+                                    if let Some(implementation) = implementations.get_mut(&name) {
+                                        if implementation.0 == owner && implementation.1 == client {
+                                            implementation.0 = other_owner;
+                                            implementation.1 = other_client;
+                                            implementation.2.command = None;
+                                        }
+                                    }
+                                }
+                            }
+
+                            Ok(())
+                        }
+                        Request::ListAttributes => {
+                            // each worker holds only its part of every attribute
+                            let listed = server.list_attributes();
+                            let sizes = listed.iter().map(|(name, _, size)| (name.clone(), *size)).collect();
+
+                            let attributes: Vec<(String, serde_json::Value)> = listed
+                                .into_iter()
+                                .map(|(name, semantics, _size)| {
+                                    let metadata = server.attribute_metadata(&name).cloned().unwrap_or_default();
+                                    let value_type = server.context.internal.value_type(&name);
+
+                                    let entry = serde_json::json!({
+                                        "df.attribute/name": name,
+                                        "df.attribute/semantics": semantics,
+                                        "df.attribute/type": value_type,
+                                        "df.attribute/append-only": server.context.internal.is_append_only(&name),
+                                        "df.attribute/doc": metadata.doc,
+                                        "df.attribute/owner": metadata.owner,
+                                        "df.attribute/tags": metadata.tags,
+                                    });
+
+                                    (name, entry)
+                                })
+                                .collect();
+
+                            let send_responses_handle = send_responses.clone();
+
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                sum_sizes(scope, sizes, owner, &mut server.probe, move |totals| {
+                                    let attributes = attributes
+                                        .into_iter()
+                                        .map(|(name, mut entry)| {
+                                            entry["df.attribute/size"] = totals.get(&name).cloned().unwrap_or(0).into();
+                                            entry
+                                        })
+                                        .collect();
+
+                                    send_responses_handle
+                                        .send((vec![Token(client)], "df.list-attributes".to_string(), serde_json::Value::Array(attributes).into()))
+                                        .unwrap();
+                                });
                             });
+
+                            Ok(())
+                        }
+                        Request::ListRules => {
+                            // each worker holds only its part of every arrangement
+                            let listed = server.list_rules();
+                            let sizes = listed
+                                .iter()
+                                .filter_map(|(name, size)| size.map(|size| (name.clone(), size)))
+                                .collect();
+
+                            let rules: Vec<(String, bool, serde_json::Value)> = listed
+                                .into_iter()
+                                .map(|(name, size)| {
+                                    let metadata = server.rule_metadata(&name).cloned().unwrap_or_default();
+
+                                    let entry = serde_json::json!({
+                                        "df.rule/name": name,
+                                        "df.rule/implemented": size.is_some(),
+                                        "df.rule/doc": metadata.doc,
+                                        "df.rule/owner": metadata.owner,
+                                        "df.rule/tags": metadata.tags,
+                                    });
+
+                                    (name, size.is_some(), entry)
+                                })
+                                .collect();
+
+                            let send_responses_handle = send_responses.clone();
+
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                sum_sizes(scope, sizes, owner, &mut server.probe, move |totals| {
+                                    let rules = rules
+                                        .into_iter()
+                                        .map(|(name, implemented, mut entry)| {
+                                            entry["df.rule/size"] = if implemented {
+                                                totals.get(&name).cloned().unwrap_or(0).into()
+                                            } else {
+                                                serde_json::Value::Null
+                                            };
+                                            entry
+                                        })
+                                        .collect();
+
+                                    send_responses_handle
+                                        .send((vec![Token(client)], "df.list-rules".to_string(), serde_json::Value::Array(rules).into()))
+                                        .unwrap();
+                                });
+                            });
+
+                            Ok(())
+                        }
+                        Request::Diff(Diff { name, t1, t2 }) => {
+                            let send_responses_handle = send_responses.clone();
+
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                match server.diff(&name, t1, t2, scope) {
+                                    Err(error) => Err(error),
+                                    Ok(diff) => {
+                                        diff
+                                            .inner
+                                            .unary_frontier(
+                                                Exchange::new(move |_| owner as u64),
+                                                "DiffRecv",
+                                                move |_capability, _info| {
+
+                                                    // changes are held back until the diff
+                                                    // is complete, s.t. it is sent in one go
+                                                    let mut changes: Vec<(Vec<Value>, isize)> = Vec::new();
+                                                    let mut buffer = Vec::new();
+                                                    let mut sent = false;
+
+                                                    move |input, _output: &mut OutputHandle<_, (), _>| {
+
+                                                        // due to the exchange pact, this closure is only
+                                                        // executed by the owning worker
+
+                                                        input.for_each(|_time, data| {
+                                                            data.swap(&mut buffer);
+                                                            changes.extend(buffer.drain(..).map(|(tuple, _t, diff)| (tuple, diff)));
+                                                        });
+
+                                                        if !sent && !input.frontier().less_equal(&t2) {
+                                                            sent = true;
+
+                                                            let payload = serde_json::json!({
+                                                                "df.diff/name": name,
+                                                                "df.diff/t1": t1,
+                                                                "df.diff/t2": t2,
+                                                                "df.diff/changes": changes.drain(..).collect::<Vec<_>>(),
+                                                            });
+
+                                                            send_responses_handle
+                                                                .send((vec![Token(client)], "df.diff".to_string(), payload.into()))
+                                                                .unwrap();
+                                                        }
+                                                    }
+                                                })
+                                            .probe_with(&mut server.probe);
+
+                                        Ok(())
+                                    }
+                                }
+                            })
+                        }
+                        Request::Resnapshot(name) => {
+                            let send_responses_handle = send_responses.clone();
+
+                            // the contents are those as of the current time,
+                            // results at later times follow via the client's
+                            // subscription as usual
+                            let as_of = *server.context.internal.time();
+
+                            worker.dataflow::<Time, _, _>(|scope| {
+                                server.interest_as_of(&name, as_of, scope).map(|snapshot| {
+                                    snapshot
+                                        .inner
+                                        .unary_frontier(
+                                            Exchange::new(move |_| owner as u64),
+                                            "ResnapshotRecv",
+                                            move |_capability, _info| {
+
+                                                // results are held back until the snapshot
+                                                // is complete, s.t. it is sent in one go
+                                                let mut results: Vec<ResultDiff> = Vec::new();
+                                                let mut buffer = Vec::new();
+                                                let mut sent = false;
+
+                                                move |input, _output: &mut OutputHandle<_, (), _>| {
+
+                                                    // due to the exchange pact, this closure is only
+                                                    // executed by the owning worker
+
+                                                    input.for_each(|_time, data| {
+                                                        data.swap(&mut buffer);
+                                                        results.extend(buffer.drain(..));
+                                                    });
+
+                                                    if !sent && !input.frontier().less_equal(&as_of) {
+                                                        sent = true;
+
+                                                        let payload = serde_json::json!({
+                                                            "df.resnapshot/name": name,
+                                                            "df.resnapshot/time": as_of,
+                                                            "df.resnapshot/results": results.drain(..).collect::<Vec<_>>(),
+                                                        });
+
+                                                        send_responses_handle
+                                                            .send((vec![Token(client)], "df.resnapshot".to_string(), payload.into()))
+                                                            .unwrap();
+                                                    }
+                                                }
+                                            })
+                                        .probe_with(&mut server.probe);
+                                })
+                            })
+                        }
+                        Request::Snapshot(path) => {
+                            worker.dataflow::<Time, _, _>(|scope| server.snapshot(&path, scope))
                         }
-                        Request::AdvanceDomain(name, next) => {
-                            if let Err(error) = server.advance_domain(name, next) {
-                                send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                        Request::Restore(path) => server.restore(&path, worker.index(), worker.peers()),
+                        Request::Simulate(req) => server.simulate(req),
+                        Request::Shutdown => {
+                            server.shutdown();
+                            shutdown_deadline = Some(Instant::now() + shutdown_grace_period);
+
+                            if owner == worker.index() {
+                                send_responses
+                                    .send((vec![Token(client)], "df.shutdown".to_string(), serde_json::Value::Null.into()))
+                                    .unwrap();
                             }
+
+                            Ok(())
                         }
-                        Request::CloseInput(name) => {
-                            if let Err(error) = server.context.internal.close_input(name) {
-                                send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                        Request::Readers => {
+                            if owner == worker.index() {
+                                let readers: Vec<serde_json::Value> = server.readers
+                                    .iter()
+                                    .flat_map(|(name, readers)| readers.iter().map(move |reader| {
+                                        serde_json::json!({
+                                            "df.reader/relation": name,
+                                            "df.reader/worker": reader.owner,
+                                            "df.reader/client": reader.client.0,
+                                            "df.reader/as-of": reader.as_of,
+                                        })
+                                    }))
+                                    .collect();
+
+                                send_responses
+                                    .send((vec![Token(client)], "df.readers".to_string(), serde_json::Value::Array(readers).into()))
+                                    .unwrap();
+                            }
+
+                            Ok(())
+                        }
+                        Request::Pause(name) => {
+                            // subscriptions are only tracked by the
+                            // worker owning the respective client
+                            if owner == worker.index() {
+                                server.pause(&name, Token(client))
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Request::Resume(name) => {
+                            if owner == worker.index() {
+                                server.resume(&name, &Token(client)).map(|catch_up| match catch_up {
+                                    CatchUp::Results(results) => {
+                                        send_responses
+                                            .send((vec![Token(client)], name, Payload::Results(results)))
+                                            .unwrap();
+                                    }
+                                    CatchUp::Overflowed => {
+                                        let payload = serde_json::json!({
+                                            "df.invalidated/name": name,
+                                            "df.invalidated/cause": name,
+                                            "df.invalidated/change": "overflowed",
+                                            "df.invalidated/action": "snapshot",
+                                        });
+
+                                        send_responses
+                                            .send((vec![Token(client)], "df.invalidated".to_string(), payload.into()))
+                                            .unwrap();
+                                    }
+                                })
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Request::Acknowledge(consumer, seq) => {
+                            // durable consumers are only tracked by the
+                            // worker owning the respective client
+                            if owner == worker.index() {
+                                match durable {
+                                    None => Err(Error {
+                                        category: "df.error.category/unsupported",
+                                        message: "Durable consumers require a --durable-dir.".to_string(),
+                                    }),
+                                    Some(ref mut durable) => durable.acknowledge(&consumer, seq),
+                                }
+                            } else {
+                                Ok(())
+                            }
+                        }
+                        Request::Status => {
+                            if owner == worker.index() {
+                                let status = server.status();
+
+                                let arrangements: Vec<serde_json::Value> = status.arrangements
+                                    .iter()
+                                    .map(|arrangement| {
+                                        serde_json::json!({
+                                            "df.arrangement/name": arrangement.name,
+                                            "df.arrangement/batches": arrangement.batches,
+                                            "df.arrangement/updates": arrangement.updates,
+                                            "df.arrangement/compaction": arrangement.compaction,
+                                        })
+                                    })
+                                    .collect();
+
+                                let sources: Vec<serde_json::Value> = status.sources
+                                    .iter()
+                                    .map(|(name, watermark)| {
+                                        serde_json::json!({
+                                            "df.source/name": name,
+                                            "df.source/watermark": watermark,
+                                            "df.source/complete": watermark.is_none(),
+                                        })
+                                    })
+                                    .collect();
+
+                                let subscriptions: Vec<serde_json::Value> = status.subscriptions
+                                    .iter()
+                                    .map(|(name, clients)| {
+                                        serde_json::json!({
+                                            "df.subscription/name": name,
+                                            "df.subscription/clients": clients,
+                                        })
+                                    })
+                                    .collect();
+
+                                let payload = serde_json::json!({
+                                    "df.status/worker": worker.index(),
+                                    "df.status/time": status.time,
+                                    "df.status/frontier": status.frontier,
+                                    "df.status/arrangements": arrangements,
+                                    "df.status/sources": sources,
+                                    "df.status/subscriptions": subscriptions,
+                                    "df.status/commands": status.latencies.commands,
+                                    "df.status/latency-mean-ms": status.latencies.mean().as_millis() as u64,
+                                    "df.status/latency-max-ms": status.latencies.max.as_millis() as u64,
+                                    "df.status/latency-last-ms": status.latencies.last.as_millis() as u64,
+                                });
+
+                                send_responses
+                                    .send((vec![Token(client)], "df.status".to_string(), payload.into()))
+                                    .unwrap();
+                            }
+
+                            Ok(())
+                        }
+                    };
+
+                    if let Some(batch) = batch {
+                        let status = match result {
+                            Ok(()) => serde_json::json!({ "df.batch/status": "ok" }),
+                            Err(ref error) => serde_json::json!({
+                                "df.batch/status": "error",
+                                "df.error/category": error.category,
+                                "df.error/message": error.message,
+                            }),
+                        };
+
+                        batches[batch].push(status);
+                    }
+
+                    if let (Ok(()), Some(req)) = (&result, journaled) {
+                        if let Err(error) = catalog.as_mut().unwrap().record(&req) {
+                            error!("[CATALOG] failed to record {:?}: {:?}", req, error);
+                        }
+                    }
+
+                    if let (Ok(()), Some(req)) = (&result, replicated) {
+                        writes.push(req);
+                    }
+
+                    if let Err(error) = result {
+                        failures.push(serde_json::json!({
+                            "df.error/category": error.category,
+                            "df.error/message": error.message,
+                            "df.request/offset": offset,
+                            "df.request/kind": request_id.kind,
+                        }));
+
+                        // failed batch items are reported via the
+                        // batch's statuses only
+                        if batch.is_none() {
+                            send_errors.send((reply_to.clone(), vec![error], Some(request_id))).unwrap();
+                        }
+                    }
+                }
+
+                if let Some(ref mut replication_log) = replication_log {
+                    if let Err(error) = replication_log.record(command.instant, &writes) {
+                        error!("[REPLICATION] failed to record {:?}: {:?}", writes, error);
+                    }
+                }
+
+                if owner == worker.index() {
+                    for statuses in batches.drain(..) {
+                        send_responses
+                            .send((vec![Token(client)], "df.batch".to_string(), serde_json::Value::Array(statuses).into()))
+                            .unwrap();
+                    }
+
+                    // commands tagged by the client are acknowledged
+                    // once all of their requests have been applied
+                    // (interests are implemented later on, failures
+                    // to do so are reported as tagged errors)
+                    if let Some(id) = command_id {
+                        let ack = serde_json::json!({
+                            "df.ack/command": id,
+                            "df.ack/tx": next_tx,
+                            "df.ack/status": if failures.is_empty() { "ok" } else { "error" },
+                            "df.ack/errors": failures,
+                        });
+
+                        send_responses
+                            .send((vec![Token(client)], "df.ack".to_string(), ack.into()))
+                            .unwrap();
+                    }
+                }
+
+                server.latencies.record(started.elapsed());
+
+                // all inputs are closed, so there is nothing left to
+                // advance, and no further commands are handled
+                if shutdown_deadline.is_some() {
+                    break;
+                }
+
+                // in simulation mode, only sources advance the domain,
+                // replicas follow the primary's domain
+                if server.simulation.is_none() && !is_replica {
+                    let next = next_time(server.context.internal.time());
+
+                    // epochs are recorded only when actually closed
+                    if next > *server.context.internal.time() {
+                        if let Err(error) = server.record_epoch(owner, worker.index()) {
+                            send_errors.send((reply_to.clone(), vec![error], None)).unwrap();
+                        }
+                    }
+
+                    match server.advance_domain(None, next) {
+                        Err(error) => send_errors.send((reply_to.clone(), vec![error], None)).unwrap(),
+                        Ok(()) => {
+                            if let Some(ref mut replication_log) = replication_log {
+                                let advance = Request::AdvanceDomain(None, next);
+                                if let Err(error) = replication_log.record(wall_clock_millis(), &[advance]) {
+                                    error!("[REPLICATION] failed to record domain advance: {:?}", error);
+                                }
                             }
                         }
                     }
                 }
 
-                if let Err(error) = server.advance_domain(None, next_tx as u64) {
-                    send_errors.send((vec![Token(client)], vec![error])).unwrap();
+                // waiting interests are implemented at the same point
+                // in the command sequence by all workers
+                if !waiting_interests.is_empty() {
+                    let (ready, waiting) = waiting_interests
+                        .drain(..)
+                        .partition::<Vec<_>, _>(|(_, _, _, req)| server.missing_dependencies(&req.name).is_empty());
+
+                    waiting_interests = waiting;
+                    pending_interests.extend(ready);
+                }
+
+                // later commands have to wait for deferred interests
+                if !pending_interests.is_empty() {
+                    break;
                 }
             }
 
@@ -585,7 +2719,121 @@ fn main() {
             // s.t. the sequencer continues issuing commands
             worker.step();
 
-            worker.step_while(|| server.is_any_outdated());
+            // a stalled frontier must not keep us from noticing it
+            match server.watchdog {
+                None => worker.step_while(|| server.is_any_outdated()),
+                Some(ref watchdog) => {
+                    let deadline = Instant::now() + watchdog.timeout();
+                    worker.step_while(|| server.is_any_outdated() && Instant::now() < deadline);
+                }
+            }
+
+            // acknowledge flushes whose inputs have been processed
+            for (time, client) in server.completed_flushes() {
+                let payload = serde_json::to_value(time).expect("failed to serialize time");
+
+                send_responses
+                    .send((vec![client], "df.flush".to_string(), payload.into()))
+                    .unwrap();
+            }
+
+            // deliver results held back by coalescing subscriptions
+            for (client, name, results, frontier) in server.flush_coalesced(Instant::now()) {
+                send_responses
+                    .send((vec![client], name.clone(), Payload::Results(results)))
+                    .unwrap();
+
+                let payload = serde_json::json!({
+                    "df.frontier/name": name,
+                    "df.frontier/time": frontier,
+                });
+
+                send_responses
+                    .send((vec![client], "df.frontier".to_string(), payload.into()))
+                    .unwrap();
+            }
+
+            // sources driving the domain advance it as they make
+            // progress, unless the primary's domain is followed. The
+            // advance is sequenced like any other command, s.t. all
+            // workers apply it at the same point, and issued by the
+            // first worker only.
+            if let Some(target) = server.domain_target() {
+                let frontier = std::cmp::max(*server.context.internal.time(), requested_advance);
+
+                if worker.index() == 0 && !is_replica && frontier < target {
+                    requested_advance = target;
+
+                    sequencer.push(Command {
+                        owner: worker.index(),
+                        client: SYSTEM.0,
+                        id: None,
+                        requests: vec![Request::AdvanceDomain(None, target)],
+                        instant: wall_clock_millis(),
+                    });
+                }
+            }
+
+            match server.check_sources(0, worker.index()) {
+                Err(error) => error!("[WORKER {}] failed to record sources: {:?}", worker.index(), error),
+                Ok(completed) => {
+                    for name in completed {
+                        info!("[WORKER {}] source {} has been read completely", worker.index(), name);
+                    }
+                }
+            }
+
+            if let Err(error) = server.check_logs() {
+                error!("[WORKER {}] failed to record logs: {:?}", worker.index(), error);
+            }
+
+            // usage is accounted for by each worker, but recorded at
+            // a sequenced point, s.t. all workers agree on it
+            let usage = server.check_usage(Instant::now(), worker.index());
+            if !usage.is_empty() {
+                sequencer.push(Command {
+                    owner: worker.index(),
+                    client: SYSTEM.0,
+                    id: None,
+                    requests: vec![Request::Transact(usage)],
+                    instant: wall_clock_millis(),
+                });
+            }
+
+            // report frontiers that have stalled or recovered
+            match server.check_progress(Instant::now(), 0, worker.index()) {
+                Err(error) => error!("[WATCHDOG] failed to record progress: {:?}", error),
+                Ok(alarms) => {
+                    for alarm in alarms {
+                        let (name, time, stuck) = match alarm {
+                            Alarm::Stuck(name, time) => {
+                                warn!("[WATCHDOG] {} is stuck before {:?}", name, time);
+                                (name, time, true)
+                            }
+                            Alarm::Recovered(name, time) => {
+                                info!("[WATCHDOG] {} has reached {:?} again", name, time);
+                                (name, time, false)
+                            }
+                        };
+
+                        if config.enable_watchdog_notifications {
+                            // interests are only tracked by the
+                            // worker owning the respective client
+                            if let Some(tokens) = server.interests.get(&name) {
+                                let payload = serde_json::json!({
+                                    "df.watchdog/name": name,
+                                    "df.watchdog/time": time,
+                                    "df.watchdog/stuck": stuck,
+                                });
+
+                                send_responses
+                                    .send((tokens.clone(), "df.watchdog".to_string(), payload.into()))
+                                    .unwrap();
+                            }
+                        }
+                    }
+                }
+            }
         }
     }).unwrap(); // asserts error-free execution
 }
@@ -1,7 +1,49 @@
 //! Binding language, mainly for use in Hector-powered plans.
 
+#[cfg(feature = "regex-predicates")]
+use std::cell::RefCell;
+#[cfg(feature = "regex-predicates")]
+use std::collections::HashMap;
+
+#[cfg(feature = "regex-predicates")]
+use regex::Regex;
+
 use crate::{Aid, Value, Var};
 
+/// Maximum number of compiled patterns retained per worker.
+#[cfg(feature = "regex-predicates")]
+const MAX_PATTERNS: usize = 1024;
+
+#[cfg(feature = "regex-predicates")]
+thread_local! {
+    /// Patterns compiled by this worker so far, invalid ones as
+    /// `None`, s.t. patterns varying per tuple are compiled only
+    /// once.
+    static PATTERNS: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// Returns true iff the string matches the pattern. Invalid patterns
+/// never match.
+#[cfg(feature = "regex-predicates")]
+fn is_match(s: &str, pattern: &str) -> bool {
+    PATTERNS.with(|patterns| {
+        let mut patterns = patterns.borrow_mut();
+
+        if !patterns.contains_key(pattern) {
+            if patterns.len() >= MAX_PATTERNS {
+                patterns.clear();
+            }
+
+            patterns.insert(pattern.to_string(), Regex::new(pattern).ok());
+        }
+
+        match patterns[pattern] {
+            None => false,
+            Some(ref regex) => regex.is_match(s),
+        }
+    })
+}
+
 /// A thing that can act as a binding of values to symbols.
 pub trait AsBinding {
     /// Iff the binding has opinions about the given symbol, this will
@@ -44,7 +86,8 @@ impl AsBinding for Binding {
 pub struct AttributeBinding {
     /// The symbols this binding talks about.
     pub symbols: (Var, Var),
-    /// The name of a globally known attribute backing this binding.
+    /// The name of a globally known attribute backing this binding,
+    /// or of a published binary relation.
     pub source_attribute: Aid,
 }
 
@@ -108,9 +151,54 @@ pub enum BinaryPredicate {
     EQ,
     /// Not equal
     NEQ,
+    /// The first string starts with the second
+    STARTS_WITH,
+    /// The first string ends with the second
+    ENDS_WITH,
+    /// The first string contains the second
+    CONTAINS,
+    /// The first string matches the regular expression given by the
+    /// second
+    #[cfg(feature = "regex-predicates")]
+    MATCHES,
+}
+
+impl BinaryPredicate {
+    /// Returns true iff the predicate holds for the given arguments.
+    /// String predicates never hold for values other than strings.
+    /// Regular expressions are compiled once per worker and cached,
+    /// invalid ones never match.
+    pub fn apply(&self, a: &Value, b: &Value) -> bool {
+        match *self {
+            BinaryPredicate::LT => a < b,
+            BinaryPredicate::GT => a > b,
+            BinaryPredicate::LTE => a <= b,
+            BinaryPredicate::GTE => a >= b,
+            BinaryPredicate::EQ => a == b,
+            BinaryPredicate::NEQ => a != b,
+            BinaryPredicate::STARTS_WITH => match (a, b) {
+                (Value::String(a), Value::String(b)) => a.starts_with(b.as_str()),
+                _ => false,
+            },
+            BinaryPredicate::ENDS_WITH => match (a, b) {
+                (Value::String(a), Value::String(b)) => a.ends_with(b.as_str()),
+                _ => false,
+            },
+            BinaryPredicate::CONTAINS => match (a, b) {
+                (Value::String(a), Value::String(b)) => a.contains(b.as_str()),
+                _ => false,
+            },
+            #[cfg(feature = "regex-predicates")]
+            BinaryPredicate::MATCHES => match (a, b) {
+                (Value::String(a), Value::String(pattern)) => is_match(a, pattern),
+                _ => false,
+            },
+        }
+    }
 }
 
-/// Describe a binary predicate constraint.
+/// Describe a binary predicate constraint. A binding of symbols
+/// `(x, y)` holds iff `y predicate x` does.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct BinaryPredicateBinding {
     /// The symbols this binding talks about.
@@ -0,0 +1,300 @@
+//! Asynchronous client for 3DF servers, for use from within tokio
+//! applications.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use futures::future;
+use futures::stream;
+use futures::sync::{mpsc, oneshot};
+use futures::{Future, Sink, Stream};
+
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use url::Url;
+
+use crate::server::catalog::encode_request;
+use crate::server::{CreateAttribute, Interest, Register, Request};
+use crate::{Error, ResultDiff, TxData};
+
+/// Keeps track of who is waiting for which incoming messages.
+#[derive(Default)]
+struct Routes {
+    /// Result streams, by relation name.
+    interests: HashMap<String, Vec<mpsc::UnboundedSender<Vec<ResultDiff>>>>,
+    /// Commands waiting for acknowledgement, by command id.
+    acks: HashMap<u64, oneshot::Sender<Result<(), Error>>>,
+    /// The id to tag the next command with.
+    next_id: u64,
+    /// Flushes waiting for acknowledgement, in the order they were
+    /// sent.
+    flushes: VecDeque<oneshot::Sender<()>>,
+}
+
+impl Routes {
+    /// Routes a single message received from the server.
+    fn dispatch(&mut self, text: &str) {
+        let (name, payload) = match serde_json::from_str::<(String, serde_json::Value)>(text) {
+            Err(err) => {
+                error!("[CLIENT] failed to parse message {:?}", err);
+                return;
+            }
+            Ok(message) => message,
+        };
+
+        match name.as_ref() {
+            "df.error" => {
+                // Failed commands are reported via their
+                // acknowledgement, other errors are only logged.
+                error!("[CLIENT] {}", payload);
+            }
+            "df.ack" => {
+                let ack = payload["df.ack/command"]
+                    .as_u64()
+                    .and_then(|id| self.acks.remove(&id));
+
+                if let Some(ack) = ack {
+                    // Commands fail as a whole, reporting their first
+                    // failed request.
+                    let failure = payload["df.ack/errors"]
+                        .as_array()
+                        .and_then(|errors| errors.first())
+                        .map(parse_error);
+
+                    let _ = ack.send(match failure {
+                        None => Ok(()),
+                        Some(error) => Err(error),
+                    });
+                }
+            }
+            "df.flush" => {
+                if let Some(flush) = self.flushes.pop_front() {
+                    let _ = flush.send(());
+                }
+            }
+            _ => {
+                if let Some(senders) = self.interests.get_mut(&name) {
+                    match serde_json::from_value::<Vec<ResultDiff>>(payload) {
+                        Err(err) => error!("[CLIENT] failed to parse results {:?}", err),
+                        Ok(results) => {
+                            // Drop streams that are no longer being consumed.
+                            senders.retain(|sender| sender.unbounded_send(results.clone()).is_ok());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reconstructs an error reported by the server.
+fn parse_error(status: &serde_json::Value) -> Error {
+    let category = match status["df.error/category"].as_str() {
+        Some("df.error.category/conflict") => "df.error.category/conflict",
+        Some("df.error.category/forbidden") => "df.error.category/forbidden",
+        Some("df.error.category/incorrect") => "df.error.category/incorrect",
+        Some("df.error.category/not-found") => "df.error.category/not-found",
+        Some("df.error.category/unsupported") => "df.error.category/unsupported",
+        _ => "df.error.category/fault",
+    };
+
+    Error {
+        category,
+        message: status["df.error/message"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// Errors caused by the connection, rather than by the server.
+fn disconnected() -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: "Connection to server was closed.".to_string(),
+    }
+}
+
+/// Serializes requests for the server, with plans in the versioned
+/// wire format.
+fn encode_requests(requests: &[Request]) -> Result<Vec<serde_json::Value>, Error> {
+    requests.iter().map(encode_request).collect()
+}
+
+/// A connection to a 3DF server.
+pub struct Client {
+    outbox: mpsc::UnboundedSender<Message>,
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl Client {
+    /// Connects to the server at the specified url
+    /// (e.g. `ws://127.0.0.1:6262`). Must be polled from within a
+    /// tokio runtime, as the connection is driven by spawned tasks.
+    pub fn connect(url: &str) -> impl Future<Item = Client, Error = Error> {
+        future::result(Url::parse(url))
+            .map_err(|err| Error {
+                category: "df.error.category/incorrect",
+                message: err.to_string(),
+            })
+            .and_then(|url| {
+                connect_async(url).map_err(|err| Error {
+                    category: "df.error.category/fault",
+                    message: err.to_string(),
+                })
+            })
+            .map(|(socket, _response)| {
+                let (sink, stream) = socket.split();
+                let (outbox, inbox) = mpsc::unbounded();
+                let routes = Arc::new(Mutex::new(Routes::default()));
+
+                tokio::spawn(
+                    inbox
+                        .forward(sink.sink_map_err(|err| error!("[CLIENT] {:?}", err)))
+                        .map(|_| ()),
+                );
+
+                let reader_routes = routes.clone();
+                tokio::spawn(
+                    stream
+                        .for_each(move |message| {
+                            if let Message::Text(text) = message {
+                                reader_routes.lock().unwrap().dispatch(&text);
+                            }
+
+                            Ok(())
+                        })
+                        .map_err(|err| error!("[CLIENT] {:?}", err)),
+                );
+
+                Client { outbox, routes }
+            })
+    }
+
+    /// Creates an attribute. The returned future resolves, once the
+    /// server has acknowledged its creation.
+    pub fn create_attribute(
+        &self,
+        attribute: CreateAttribute,
+    ) -> impl Future<Item = (), Error = Error> {
+        self.command(vec![Request::CreateAttribute(attribute)])
+    }
+
+    /// Registers rules. The returned future resolves, once the server
+    /// has acknowledged their registration.
+    pub fn register(&self, register: Register) -> impl Future<Item = (), Error = Error> {
+        self.command(vec![Request::Register(register)])
+    }
+
+    /// Expresses interest in the named relation. The returned stream
+    /// yields results as they are sent by the server.
+    pub fn interest(&self, name: &str) -> impl Stream<Item = ResultDiff, Error = Error> {
+        self.interest_batches(name).map(stream::iter_ok).flatten()
+    }
+
+    /// Expresses interest in the named relation. The returned stream
+    /// yields batches of results as they are sent by the server.
+    pub fn interest_batches(
+        &self,
+        name: &str,
+    ) -> impl Stream<Item = Vec<ResultDiff>, Error = Error> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        let sent = {
+            let mut routes = self.routes.lock().unwrap();
+            let senders = routes
+                .interests
+                .entry(name.to_string())
+                .or_insert_with(Vec::new);
+
+            senders.push(sender);
+
+            // The server sends results once per interest, so
+            // additional streams share the existing subscription.
+            if senders.len() == 1 {
+                self.send(vec![Request::Interest(Interest {
+                    name: name.to_string(),
+                    as_of: None,
+                    bindings: Vec::new(),
+                    specialize: false,
+                    restrict: None,
+                    project: None,
+                    order_by: Vec::new(),
+                    window: None,
+                    delivery: Default::default(),
+                    consumer: None,
+                })])
+            } else {
+                Ok(())
+            }
+        };
+
+        future::result(sent)
+            .map(move |_| receiver.map_err(|_| disconnected()))
+            .flatten_stream()
+    }
+
+    /// Transacts the given data. The returned future resolves, once
+    /// the server has acknowledged the transaction.
+    pub fn transact(&self, tx_data: Vec<TxData>) -> impl Future<Item = (), Error = Error> {
+        self.command(vec![Request::Transact(tx_data)])
+    }
+
+    /// Resolves once all inputs transacted so far have been fully
+    /// processed by the server, and the resulting outputs have been
+    /// sent out.
+    pub fn flush(&self) -> impl Future<Item = (), Error = Error> {
+        let (sender, receiver) = oneshot::channel();
+
+        let sent = {
+            let mut routes = self.routes.lock().unwrap();
+            routes.flushes.push_back(sender);
+
+            self.send(vec![Request::Flush])
+        };
+
+        future::result(sent).and_then(|_| receiver.map_err(|_canceled| disconnected()))
+    }
+
+    /// Asks the server to shut down, once all outstanding results
+    /// have been delivered.
+    pub fn shutdown(&self) -> Result<(), Error> {
+        self.send(vec![Request::Shutdown])
+    }
+
+    /// Sends a list of requests to the server, tagged with a fresh
+    /// command id. The returned future resolves, once the server has
+    /// acknowledged the command, failing if any of the requests did.
+    fn command(&self, requests: Vec<Request>) -> impl Future<Item = (), Error = Error> {
+        let (sender, receiver) = oneshot::channel();
+
+        let sent = encode_requests(&requests).and_then(|requests| {
+            let mut routes = self.routes.lock().unwrap();
+            let id = routes.next_id;
+            routes.next_id += 1;
+            routes.acks.insert(id, sender);
+
+            let command = serde_json::json!({ "id": id, "requests": requests });
+            let serialized = serde_json::to_string(&command).expect("failed to serialize requests");
+
+            self.outbox
+                .unbounded_send(Message::Text(serialized))
+                .map_err(|_| disconnected())
+        });
+
+        future::result(sent)
+            .and_then(|_| receiver.map_err(|_canceled| disconnected()))
+            .and_then(|result| result)
+    }
+
+    /// Sends a list of requests to the server.
+    fn send(&self, requests: Vec<Request>) -> Result<(), Error> {
+        let requests = encode_requests(&requests)?;
+        let serialized = serde_json::to_string(&requests).expect("failed to serialize requests");
+
+        self.outbox
+            .unbounded_send(Message::Text(serialized))
+            .map_err(|_| disconnected())
+    }
+}
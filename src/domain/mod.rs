@@ -1,22 +1,47 @@
 //! Logic for working with attributes under a shared timestamp
 //! semantics.
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::channels::pact::{Exchange, Pipeline};
 use timely::dataflow::operators::generic::operator::Operator;
-use timely::dataflow::operators::{Filter, FrontierNotificator, Map};
+use timely::dataflow::operators::{
+    Capability, Concatenate, Filter, FrontierNotificator, Map, Probe,
+};
 use timely::dataflow::{ProbeHandle, Scope, Stream};
-use timely::order::TotalOrder;
+use timely::order::{PartialOrder, TotalOrder};
 use timely::progress::Timestamp;
+use timely::scheduling::Activator;
+
+use timely_sort::Unsigned;
 
 use differential_dataflow::input::{Input, InputSession};
 use differential_dataflow::lattice::Lattice;
-use differential_dataflow::operators::Threshold;
-use differential_dataflow::AsCollection;
+use differential_dataflow::operators::arrange::Arrange;
+use differential_dataflow::operators::{Consolidate, Threshold};
+use differential_dataflow::{AsCollection, Collection, Hashable};
+
+use crate::{rewind, time_value, Aid, Eid, Error, Time, TxData, Value};
+use crate::{AttributeSemantics, CollectionIndex, RetentionPolicy, ValueType};
+use crate::{TraceKeyHandle, TraceValHandle};
+
+/// A handle to an arranged attribute, with each (e,v) pair annotated
+/// by the time at which it was asserted.
+pub type TxIndexHandle<T> = TraceKeyHandle<((Value, Value), T), T, isize>;
 
-use crate::{Aid, Error, TxData, Value};
-use crate::{AttributeSemantics, CollectionIndex};
+/// A handle to the inverted index of an attribute with FullText
+/// semantics, arranged from token -> eid.
+pub type FullTextIndexHandle<T> = TraceValHandle<Value, Value, T, isize>;
+
+/// Names of the attributes describing transactions. Once created,
+/// each transaction is described by an entity identifying it via
+/// `df.tx/id` (its time, as bound by tx-time clauses) and recording
+/// the wall-clock instant at which it was transacted in
+/// `df.tx/instant`.
+pub const TX_ATTRIBUTES: &[&str] = &["df.tx/id", "df.tx/instant"];
 
 /// A domain manages attributes (and their inputs) hat share a
 /// timestamp semantics (e.g. come from the same logical source).
@@ -25,12 +50,51 @@ pub struct Domain<T: Timestamp + Lattice + TotalOrder> {
     now_at: T,
     /// Input handles to attributes in this domain.
     input_sessions: HashMap<String, InputSession<T, (Value, Value), isize>>,
+    /// Attributes whose inputs have been closed, along with the time
+    /// at which they were closed.
+    closed: HashMap<Aid, T>,
     /// The probe keeping track of progress in this domain.
     probe: ProbeHandle<T>,
+    /// Probes keeping track of the progress of each source.
+    source_probes: HashMap<Aid, ProbeHandle<T>>,
     /// Forward attribute indices eid -> v.
     pub forward: HashMap<Aid, CollectionIndex<Value, Value, T>>,
     /// Reverse attribute indices v -> eid.
     pub reverse: HashMap<Aid, CollectionIndex<Value, Value, T>>,
+    /// Should new attributes keep track of assertion times?
+    tx_times_enabled: bool,
+    /// Attribute indices ((e, v), tx).
+    pub tx_times: HashMap<Aid, TxIndexHandle<T>>,
+    /// Inverted indices token -> eid, of attributes with FullText
+    /// semantics.
+    pub full_text: HashMap<Aid, FullTextIndexHandle<T>>,
+    /// Attributes whose traces are compacted according to their own
+    /// policy, rather than following the domain.
+    retention: HashMap<Aid, RetentionPolicy>,
+    /// Semantics of attributes created via `create_attribute`.
+    semantics: HashMap<Aid, AttributeSemantics>,
+    /// Types declared for the values of attributes. All values
+    /// transacted into such attributes are coerced to their type.
+    value_types: HashMap<Aid, ValueType>,
+    /// Attributes into which nothing is ever retracted.
+    append_only: HashSet<Aid>,
+    /// Times whose transactions have already been described.
+    described: Vec<T>,
+    /// Wall-clock instant (in milliseconds since the unix epoch) at
+    /// which the command currently being applied was sequenced, if
+    /// known.
+    instant: Option<u64>,
+    /// Gate holding back the data of sources, if enabled (see
+    /// `gate_sources`).
+    gate: Option<Rc<RefCell<Gate<T>>>>,
+}
+
+/// Holds back the data of sources at times at or beyond a horizon.
+struct Gate<T> {
+    /// Data at this time or later is held back, none if None.
+    horizon: Option<T>,
+    /// Activators of the operators holding back data.
+    activators: Vec<Activator>,
 }
 
 impl<T> Domain<T>
@@ -42,12 +106,31 @@ where
         Domain {
             now_at: start_at,
             input_sessions: HashMap::new(),
+            closed: HashMap::new(),
             probe: ProbeHandle::new(),
+            source_probes: HashMap::new(),
             forward: HashMap::new(),
             reverse: HashMap::new(),
+            tx_times_enabled: false,
+            tx_times: HashMap::new(),
+            full_text: HashMap::new(),
+            retention: HashMap::new(),
+            semantics: HashMap::new(),
+            value_types: HashMap::new(),
+            append_only: HashSet::new(),
+            described: Vec::new(),
+            instant: None,
+            gate: None,
         }
     }
 
+    /// Makes all subsequently created attributes keep track of the
+    /// time at which each of their datoms was asserted. This requires
+    /// an additional arrangement per attribute.
+    pub fn enable_tx_times(&mut self) {
+        self.tx_times_enabled = true;
+    }
+
     /// Creates a new collection of (e,v) tuples and indexes it in
     /// various ways. Stores forward, and reverse indices, as well as
     /// the input handle in the server state.
@@ -87,7 +170,7 @@ where
 
                             let mut eids: HashMap<T, HashSet<Value>> = HashMap::new();
                             let mut current: HashMap<Value, Value> = HashMap::new();
-                            let mut next: HashMap<Value, (T, Value)> = HashMap::new();
+                            let mut next: HashMap<Value, (T, Option<Value>)> = HashMap::new();
 
                             let mut tuples = Vec::new();
 
@@ -96,13 +179,26 @@ where
                                     data.swap(&mut tuples);
 
                                     let mut interest = false;
-                                    for ((eid, v), t, _) in tuples.drain(..) {
-                                        let (last_t, _next_v) = next
-                                            .entry(eid.clone())
-                                            .or_insert((cap.time().clone(), v.clone()));
+                                    for ((eid, v), t, diff) in tuples.drain(..) {
+                                        let (last_t, last_v) = next.entry(eid.clone()).or_insert((
+                                            cap.time().clone(),
+                                            current.get(&eid).cloned(),
+                                        ));
 
                                         if last_t.less_equal(&t) {
-                                            next.insert(eid.clone(), (t.clone(), v.clone()));
+                                            if diff > 0 {
+                                                // A new value supersedes whatever
+                                                // value the eid would have otherwise.
+                                                *last_v = Some(v);
+                                            } else if last_v.as_ref() == Some(&v) {
+                                                // Retractions only apply to the
+                                                // value they actually retract.
+                                                *last_v = None;
+                                            } else {
+                                                continue;
+                                            }
+
+                                            *last_t = t.clone();
 
                                             eids.entry(t).or_insert_with(HashSet::new).insert(eid);
 
@@ -120,6 +216,16 @@ where
 
                                     if let Some(mut eids) = eids.remove(cap.time()) {
                                         for eid in eids.drain() {
+                                            // A later change to the same eid
+                                            // is pending, which will be applied
+                                            // at its own time instead.
+                                            match next.get(&eid) {
+                                                Some((t, _)) if !t.less_equal(cap.time()) => {
+                                                    continue
+                                                }
+                                                _ => {}
+                                            }
+
                                             if let Some(current_v) = current.remove(&eid) {
                                                 session.give((
                                                     (eid.clone(), current_v),
@@ -127,7 +233,7 @@ where
                                                     -1,
                                                 ));
                                             }
-                                            if let Some((_t, next_v)) = next.remove(&eid) {
+                                            if let Some((_t, Some(next_v))) = next.remove(&eid) {
                                                 session.give((
                                                     (eid.clone(), next_v.clone()),
                                                     cap.time().clone(),
@@ -142,18 +248,45 @@ where
                         })
                         .as_collection()
                 }
-                AttributeSemantics::CardinalityMany => {
+                AttributeSemantics::CardinalityMany | AttributeSemantics::FullText => {
                     // Ensure that redundant (e,v) pairs don't cause
                     // misleading proposals during joining.
                     tuples.distinct()
                 }
             };
 
+            if typ == AttributeSemantics::FullText {
+                let tokens = tuples
+                    .flat_map(|(e, v)| match v {
+                        Value::String(text) => tokenize(&text)
+                            .into_iter()
+                            .map(|token| (Value::String(token), e.clone()))
+                            .collect(),
+                        _ => Vec::new(),
+                    })
+                    // Several values of an entity might share tokens.
+                    .distinct()
+                    .arrange_named(&format!("FullText({})", name))
+                    .trace;
+
+                self.full_text.insert(name.to_string(), tokens);
+            }
+
             let forward = CollectionIndex::index(name, &tuples);
             let reverse = CollectionIndex::index(name, &tuples.map(|(e, v)| (v, e)));
 
             self.forward.insert(name.to_string(), forward);
             self.reverse.insert(name.to_string(), reverse);
+            self.semantics.insert(name.to_string(), typ);
+
+            if self.tx_times_enabled {
+                let tx_times = annotate_tx_times(&tuples)
+                    .map(|x| (x, ()))
+                    .arrange_named(&format!("TxTimes({})", name))
+                    .trace;
+
+                self.tx_times.insert(name.to_string(), tx_times);
+            }
 
             self.input_sessions.insert(name.to_string(), handle);
 
@@ -161,11 +294,35 @@ where
         }
     }
 
+    /// Holds back the data of all sources created from now on at
+    /// times at or beyond the specified horizon (none, if None),
+    /// until it is moved past them. Allows replaying sources at a
+    /// controlled pace.
+    pub fn gate_sources(&mut self, horizon: Option<T>) {
+        match self.gate {
+            None => {
+                self.gate = Some(Rc::new(RefCell::new(Gate {
+                    horizon,
+                    activators: Vec::new(),
+                })));
+            }
+            Some(ref gate) => {
+                let mut gate = gate.borrow_mut();
+                gate.horizon = horizon;
+
+                for activator in gate.activators.iter() {
+                    activator.activate();
+                }
+            }
+        }
+    }
+
     /// Creates attributes from an external datoms source.
     pub fn create_source<S: Scope<Timestamp = T>>(
         &mut self,
         name: &str,
         name_idx: Option<usize>,
+        append_only: bool,
         datoms: &Stream<S, (usize, ((Value, Value), T, isize))>,
     ) -> Result<(), Error> {
         if self.forward.contains_key(name) {
@@ -174,6 +331,8 @@ where
                 message: format!("An attribute of name {} already exists.", name),
             })
         } else {
+            let mut probe = ProbeHandle::new();
+
             let datoms = match name_idx {
                 None => datoms.map(|(_idx, tuple)| tuple),
                 Some(name_idx) => datoms
@@ -181,6 +340,32 @@ where
                     .map(|(_idx, tuple)| tuple),
             };
 
+            // held back data also holds back the watermark
+            let datoms = match self.gate {
+                None => datoms,
+                Some(ref gate) => hold_back(&datoms, gate.clone()),
+            }
+            .probe_with(&mut probe);
+
+            self.source_probes.insert(name.to_string(), probe);
+
+            // retractions can't be rejected once a source has read
+            // them, so they are dropped instead
+            let datoms = if append_only {
+                self.append_only.insert(name.to_string());
+
+                let name = name.to_string();
+                datoms.filter(move |(_tuple, _time, diff)| {
+                    if *diff < 0 {
+                        warn!("[SOURCE] dropping retraction from append-only {}", name);
+                    }
+
+                    *diff > 0
+                })
+            } else {
+                datoms
+            };
+
             let tuples = datoms
                 .as_collection()
                 // Ensure that redundant (e,v) pairs don't cause
@@ -197,27 +382,123 @@ where
         }
     }
 
+    /// Returns the watermark of each source, i.e. the earliest time
+    /// at which it might still produce data, or None once it has
+    /// been read completely.
+    pub fn source_watermarks(&self) -> Vec<(Aid, Option<T>)> {
+        self.source_probes
+            .iter()
+            .map(|(name, probe)| {
+                let watermark = probe.with_frontier(|frontier| frontier.iter().next().cloned());
+                (name.clone(), watermark)
+            })
+            .collect()
+    }
+
     /// Transact data into one or more inputs.
     pub fn transact(&mut self, tx_data: Vec<TxData>) -> Result<(), Error> {
+        let now_at = self.now_at.clone();
+        self.transact_at(tx_data, now_at)
+    }
+
+    /// Transact data into one or more inputs, at an explicit
+    /// transaction time. This allows bulk loading historical data at
+    /// its original times, as long as those times have not yet been
+    /// closed by advancing the domain beyond them.
+    pub fn transact_at(&mut self, tx_data: Vec<TxData>, time: T) -> Result<(), Error> {
+        let tx_data = self.validate(tx_data, &time)?;
+
         // @TODO do this smarter, e.g. grouped by handle
         for TxData(op, e, a, v) in tx_data {
-            match self.input_sessions.get_mut(&a) {
-                None => {
+            let handle = self
+                .input_sessions
+                .get_mut(&a)
+                .expect("attribute checked during validation");
+
+            handle.update_at((Value::Eid(e), v), time.clone(), op);
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the given data could be transacted at the
+    /// given time, without transacting it, and returns it with all
+    /// values coerced to the declared types of their attributes.
+    /// Transactions are rejected as a whole, s.t. none of their data
+    /// is introduced if any of it is invalid.
+    pub fn validate(&self, tx_data: Vec<TxData>, time: &T) -> Result<Vec<TxData>, Error> {
+        if !self.now_at.less_equal(time) {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
+                    "Cannot transact at {:?}, domain has already advanced to {:?}.",
+                    time, self.now_at
+                ),
+            });
+        }
+
+        let tx_data = self.coerce(tx_data)?;
+
+        for TxData(op, _e, a, _v) in tx_data.iter() {
+            if !self.input_sessions.contains_key(a) {
+                if self.closed.contains_key(a) {
+                    return Err(Error {
+                        category: "df.error.category/conflict",
+                        message: format!("Attribute {} has been closed and is read-only.", a),
+                    });
+                } else {
                     return Err(Error {
                         category: "df.error.category/not-found",
                         message: format!("Attribute {} does not exist.", a),
                     });
                 }
-                Some(handle) => {
-                    handle.update((Value::Eid(e), v), op);
-                }
+            }
+
+            // Retractions would break the promise made to rules
+            // reading from append-only attributes.
+            if *op < 0 && self.append_only.contains(a) {
+                return Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: format!(
+                        "Attribute {} is append-only, retractions are not allowed.",
+                        a
+                    ),
+                });
             }
         }
 
-        Ok(())
+        Ok(tx_data)
+    }
+
+    /// Coerces all values transacted into typed attributes to the
+    /// declared type, failing on the first one that can't be.
+    fn coerce(&self, mut tx_data: Vec<TxData>) -> Result<Vec<TxData>, Error> {
+        if self.value_types.is_empty() {
+            return Ok(tx_data);
+        }
+
+        tx_data
+            .drain(..)
+            .map(|TxData(op, e, a, v)| match self.value_types.get(&a) {
+                None => Ok(TxData(op, e, a, v)),
+                Some(typ) => match typ.coerce(v.clone()) {
+                    None => Err(Error {
+                        category: "df.error.category/incorrect",
+                        message: format!(
+                            "Attribute {} holds values of type {:?}, but got {:?}.",
+                            a, typ, v
+                        ),
+                    }),
+                    Some(v) => Ok(TxData(op, e, a, v)),
+                },
+            })
+            .collect()
     }
 
-    /// Closes and drops an existing input.
+    /// Closes and drops an existing input. The attribute remains
+    /// queryable, but is read-only from here on out. Dropping the
+    /// input session releases its buffers and its capability, s.t.
+    /// the attribute's frontier can advance past the current time.
     pub fn close_input(&mut self, name: String) -> Result<(), Error> {
         match self.input_sessions.remove(&name) {
             None => Err(Error {
@@ -226,11 +507,171 @@ where
             }),
             Some(handle) => {
                 handle.close();
+                self.closed.insert(name, self.now_at.clone());
                 Ok(())
             }
         }
     }
 
+    /// Returns the consolidated contents of all attributes created
+    /// via `create_attribute` as of the specified time, as (a, e, v)
+    /// tuples at that time. Attributes fed by sources are left out,
+    /// as their contents can be recovered from the source itself.
+    pub fn snapshot<S: Scope<Timestamp = T>>(
+        &mut self,
+        scope: &mut S,
+        as_of: T,
+    ) -> Result<Collection<S, (Aid, Value, Value), isize>, Error> {
+        let mut names: Vec<Aid> = self.semantics.keys().cloned().collect();
+        names.sort();
+
+        let mut streams = Vec::with_capacity(names.len());
+
+        for name in names.drain(..) {
+            let trace = &mut self
+                .forward
+                .get_mut(&name)
+                .expect("attribute without index")
+                .propose_trace;
+
+            if !trace
+                .advance_frontier()
+                .iter()
+                .all(|time| time.less_equal(&as_of))
+            {
+                return Err(Error {
+                    category: "df.error.category/unsupported",
+                    message: format!("Attribute {} has been compacted beyond {:?}.", name, as_of),
+                });
+            }
+
+            let (until, at) = (as_of.clone(), as_of.clone());
+            let aid = name.clone();
+
+            streams.push(
+                trace
+                    .import_named(scope, &name)
+                    .as_collection(move |e, v| (aid.clone(), e.clone(), v.clone()))
+                    .inner
+                    .filter(move |(_tuple, time, _diff)| time.less_equal(&until))
+                    .map(move |(tuple, _time, diff)| (tuple, at.clone(), diff)),
+            );
+        }
+
+        Ok(scope.concatenate(streams).as_collection().consolidate())
+    }
+
+    /// Closes and drops all remaining inputs and compacts all traces
+    /// up to the current time, regardless of retention policies. Used
+    /// when shutting down, once nothing is going to be read anymore.
+    pub fn close_all(&mut self) {
+        for (name, handle) in self.input_sessions.drain() {
+            handle.close();
+            self.closed.insert(name, self.now_at.clone());
+        }
+
+        let frontier = &[self.now_at.clone()];
+
+        for index in self.forward.values_mut() {
+            index.advance_by(frontier);
+        }
+
+        for index in self.reverse.values_mut() {
+            index.advance_by(frontier);
+        }
+
+        for trace in self.tx_times.values_mut() {
+            trace.advance_by(frontier);
+        }
+
+        for trace in self.full_text.values_mut() {
+            trace.advance_by(frontier);
+        }
+    }
+
+    /// Sets the retention policy of an existing attribute. Its
+    /// traces will no longer follow the domain when it is advanced.
+    pub fn set_retention(&mut self, name: &str, policy: RetentionPolicy) -> Result<(), Error> {
+        if !self.forward.contains_key(name) {
+            return Err(Error {
+                category: "df.error.category/not-found",
+                message: format!("Attribute {} does not exist.", name),
+            });
+        }
+
+        self.retention.insert(name.to_string(), policy);
+
+        Ok(())
+    }
+
+    /// Declares the type of the values of an existing attribute.
+    /// From here on out, values transacted into it are coerced to
+    /// that type, s.t. its reverse index is ordered according to the
+    /// natural ordering of the type. Values transacted before are
+    /// left as they are. Only attributes created via
+    /// `create_attribute` can be typed.
+    pub fn set_value_type(&mut self, name: &str, typ: ValueType) -> Result<(), Error> {
+        if !self.semantics.contains_key(name) {
+            return Err(Error {
+                category: "df.error.category/not-found",
+                message: format!("Attribute {} does not exist or is fed by a source.", name),
+            });
+        }
+
+        self.value_types.insert(name.to_string(), typ);
+
+        Ok(())
+    }
+
+    /// Declares an existing attribute append-only. From here on out,
+    /// retractions from it are rejected, s.t. rules reading from it
+    /// can skip enforcing set semantics. Attributes whose semantics
+    /// retract values on their own (CardinalityOne) can't be
+    /// append-only. Attributes fed by a source are declared
+    /// append-only when the source is registered instead.
+    pub fn set_append_only(&mut self, name: &str) -> Result<(), Error> {
+        match self.semantics.get(name) {
+            None => Err(Error {
+                category: "df.error.category/not-found",
+                message: format!("Attribute {} does not exist or is fed by a source.", name),
+            }),
+            Some(AttributeSemantics::CardinalityOne) => Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
+                    "Attribute {} retracts replaced values and can't be append-only.",
+                    name
+                ),
+            }),
+            Some(_) => {
+                self.append_only.insert(name.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns true iff the named attribute is append-only.
+    pub fn is_append_only(&self, name: &str) -> bool {
+        self.append_only.contains(name)
+    }
+
+    /// Returns the type declared for the values of the named
+    /// attribute, if any.
+    pub fn value_type(&self, name: &str) -> Option<ValueType> {
+        self.value_types.get(name).cloned()
+    }
+
+    /// Returns the semantics of the named attribute, unless it is
+    /// fed by a source.
+    pub fn semantics(&self, name: &str) -> Option<&AttributeSemantics> {
+        self.semantics.get(name)
+    }
+
+    /// Returns true iff the input for the named attribute has been
+    /// closed.
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.closed.contains_key(name)
+    }
+
     /// Advances the domain to `next`. The `trace_next` parameter can
     /// be used to indicate whether (and if so how closely) traces
     /// should follow the input frontier. Setting this to None
@@ -241,6 +682,7 @@ where
 
         if !self.now_at.eq(&next) {
             self.now_at = next.clone();
+            self.described.retain(|time| next.less_equal(time));
 
             for handle in self.input_sessions.values_mut() {
                 handle.advance_to(next.clone());
@@ -252,13 +694,30 @@ where
                 // the index traces to allow them to compact
 
                 let frontier = &[trace_next];
+                let retention = &self.retention;
 
-                for index in self.forward.values_mut() {
-                    index.advance_by(frontier);
+                for (name, index) in self.forward.iter_mut() {
+                    if !retention.contains_key(name) {
+                        index.advance_by(frontier);
+                    }
                 }
 
-                for index in self.reverse.values_mut() {
-                    index.advance_by(frontier);
+                for (name, index) in self.reverse.iter_mut() {
+                    if !retention.contains_key(name) {
+                        index.advance_by(frontier);
+                    }
+                }
+
+                for (name, trace) in self.tx_times.iter_mut() {
+                    if !retention.contains_key(name) {
+                        trace.advance_by(frontier);
+                    }
+                }
+
+                for (name, trace) in self.full_text.iter_mut() {
+                    if !retention.contains_key(name) {
+                        trace.advance_by(frontier);
+                    }
                 }
             }
         }
@@ -268,4 +727,231 @@ where
     pub fn time(&self) -> &T {
         &self.now_at
     }
+
+    /// Sets the wall-clock instant recorded in `df.tx/instant` for
+    /// subsequent transactions. Servers set this to the instant at
+    /// which each command was sequenced, s.t. all workers, replicas,
+    /// and replays record the same instant. Without it, the clock is
+    /// read when a transaction is described.
+    pub fn set_instant(&mut self, instant: u64) {
+        self.instant = Some(instant);
+    }
+
+    /// Returns the wall-clock instant (in milliseconds since the unix
+    /// epoch) set via `set_instant`, reading the clock if none has
+    /// been set.
+    pub fn instant(&self) -> u64 {
+        self.instant.unwrap_or_else(|| {
+            let instant = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system clock before unix epoch");
+
+            instant.as_secs() * 1000 + u64::from(instant.subsec_millis())
+        })
+    }
+}
+
+impl Domain<Time> {
+    /// Transact data into one or more inputs, at the specified time,
+    /// describing the transaction in the `df.tx/*` attributes (if
+    /// they exist). Each time is described only once, no matter how
+    /// many transactions happen at it. Data fed by sources doesn't
+    /// pass through here and is therefore not described.
+    pub fn transact_with_metadata(
+        &mut self,
+        mut tx_data: Vec<TxData>,
+        time: Time,
+    ) -> Result<(), Error> {
+        let describe = !tx_data.is_empty()
+            && !self.described.contains(&time)
+            && TX_ATTRIBUTES
+                .iter()
+                .all(|name| self.input_sessions.contains_key(*name));
+
+        if describe {
+            let eid = ("df.tx", time).hashed().as_u64() as Eid;
+            let millis = self.instant();
+
+            tx_data.push(TxData(1, eid, "df.tx/id".to_string(), time_value(&time)));
+            tx_data.push(TxData(
+                1,
+                eid,
+                "df.tx/instant".to_string(),
+                Value::Instant(millis),
+            ));
+        }
+
+        self.transact_at(tx_data, time)?;
+
+        if describe {
+            self.described.push(time);
+        }
+
+        Ok(())
+    }
+
+    /// Compacts the traces of all attributes that have a retention
+    /// policy, according to that policy. Should be called whenever
+    /// the domain is advanced.
+    pub fn apply_retention(&mut self) {
+        let now = self.now_at;
+
+        for (name, policy) in self.retention.iter() {
+            let frontier = match policy {
+                RetentionPolicy::KeepAll => continue,
+                RetentionPolicy::KeepLast(units) => rewind(&now, *units),
+                RetentionPolicy::KeepLatest => rewind(&now, 1),
+            };
+
+            if let Some(index) = self.forward.get_mut(name) {
+                index.advance_by(&[frontier]);
+            }
+
+            if let Some(index) = self.reverse.get_mut(name) {
+                index.advance_by(&[frontier]);
+            }
+
+            if let Some(trace) = self.tx_times.get_mut(name) {
+                trace.advance_by(&[frontier]);
+            }
+
+            if let Some(trace) = self.full_text.get_mut(name) {
+                trace.advance_by(&[frontier]);
+            }
+        }
+    }
+}
+
+/// Splits text into the tokens indexed by FullText attributes, i.e.
+/// lowercased runs of alphanumeric characters.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+/// Holds back tuples at times at or beyond the horizon of the gate,
+/// releasing them once it is moved past them.
+fn hold_back<S>(
+    datoms: &Stream<S, ((Value, Value), S::Timestamp, isize)>,
+    gate: Rc<RefCell<Gate<S::Timestamp>>>,
+) -> Stream<S, ((Value, Value), S::Timestamp, isize)>
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+{
+    let scope = datoms.scope();
+
+    datoms.unary_frontier(Pipeline, "Gate", move |_, info| {
+        gate.borrow_mut()
+            .activators
+            .push(scope.activator_for(&info.address[..]));
+
+        let mut held: Vec<(Capability<S::Timestamp>, Vec<_>)> = Vec::new();
+        let mut buffer = Vec::new();
+
+        move |input, output| {
+            input.for_each(|cap, data| {
+                data.swap(&mut buffer);
+
+                for tuple in buffer.drain(..) {
+                    match held
+                        .iter_mut()
+                        .find(|(held_cap, _)| *held_cap.time() == tuple.1)
+                    {
+                        Some((_, tuples)) => tuples.push(tuple),
+                        None => held.push((cap.delayed(&tuple.1), vec![tuple])),
+                    }
+                }
+            });
+
+            let gate = gate.borrow();
+            let mut index = 0;
+
+            while index < held.len() {
+                let released = gate
+                    .horizon
+                    .as_ref()
+                    .map_or(true, |horizon| held[index].0.time().less_than(horizon));
+
+                if released {
+                    let (cap, mut tuples) = held.swap_remove(index);
+                    output.session(&cap).give_vec(&mut tuples);
+                } else {
+                    index += 1;
+                }
+            }
+        }
+    })
+}
+
+/// Annotates each (e,v) pair with the time at which it was
+/// asserted. Retractions are matched up with the assertion they
+/// retract, s.t. the annotated pair disappears again.
+fn annotate_tx_times<S>(
+    tuples: &Collection<S, (Value, Value), isize>,
+) -> Collection<S, ((Value, Value), S::Timestamp), isize>
+where
+    S: Scope,
+    S::Timestamp: Lattice + TotalOrder,
+{
+    let exchange = Exchange::new(|(ev, _t, _diff): &((Value, Value), S::Timestamp, isize)| {
+        ev.hashed().as_u64()
+    });
+
+    tuples
+        .inner
+        .unary_frontier(exchange, "TxTimes", |_, _| {
+            let mut notificator = FrontierNotificator::new();
+
+            let mut pending: HashMap<S::Timestamp, Vec<((Value, Value), isize)>> = HashMap::new();
+            let mut asserted: HashMap<(Value, Value), (S::Timestamp, isize)> = HashMap::new();
+
+            let mut buffer = Vec::new();
+
+            move |input, output| {
+                input.for_each(|cap, data| {
+                    data.swap(&mut buffer);
+
+                    for (ev, t, diff) in buffer.drain(..) {
+                        if !pending.contains_key(&t) {
+                            notificator.notify_at(cap.delayed(&t));
+                        }
+
+                        pending.entry(t).or_insert_with(Vec::new).push((ev, diff));
+                    }
+                });
+
+                notificator.for_each(&[input.frontier()], |cap, _| {
+                    if let Some(mut changes) = pending.remove(cap.time()) {
+                        let mut session = output.session(&cap);
+                        let now = cap.time().clone();
+
+                        // Retractions go first, s.t. a pair that is
+                        // retracted and re-asserted at the same time
+                        // picks up the new assertion time.
+                        changes.sort_by_key(|(_ev, diff)| *diff);
+
+                        for (ev, diff) in changes.drain(..) {
+                            if diff > 0 {
+                                let entry = asserted
+                                    .entry(ev.clone())
+                                    .or_insert_with(|| (now.clone(), 0));
+
+                                entry.1 += diff;
+                                session.give(((ev, entry.0.clone()), now.clone(), diff));
+                            } else if let Some((tx, count)) = asserted.remove(&ev) {
+                                session.give(((ev.clone(), tx.clone()), now.clone(), diff));
+
+                                if count + diff > 0 {
+                                    asserted.insert(ev, (tx, count + diff));
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        })
+        .as_collection()
 }
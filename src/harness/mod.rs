@@ -0,0 +1,250 @@
+//! A harness for testing individual plan stages in isolation.
+//!
+//! Plans are implemented against attributes created on the fly and
+//! fed with the updates given, without going through a server. The
+//! output of the plan is collected per epoch, s.t. tests can assert
+//! exactly what a stage emits (and retracts) at which time.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use timely::dataflow::operators::{Inspect, Probe};
+use timely::dataflow::ProbeHandle;
+use timely::Configuration;
+
+use crate::domain::{Domain, FullTextIndexHandle, TxIndexHandle};
+use crate::plan::{ImplContext, Implementable};
+use crate::udf::ExternalFunction;
+use crate::VariableMap;
+use crate::{Aid, AttributeSemantics, CollectionIndex, Error, IndexDirection, Plan};
+use crate::{Relation, RelationHandle, Rule, ShutdownHandle, Time, TxData, Value, ValueType};
+
+/// The output of a plan, consolidated per epoch. Epochs in which
+/// nothing changed are omitted.
+pub type Epochs = BTreeMap<Time, Vec<(Vec<Value>, isize)>>;
+
+/// A set of attributes and the updates to feed them with, against
+/// which plans can be run.
+#[derive(Clone, Default, Debug)]
+pub struct Harness {
+    /// Attributes to create, in order.
+    attributes: Vec<(Aid, AttributeSemantics)>,
+    /// Updates to feed the attributes with, at the time given.
+    updates: Vec<(Time, TxData)>,
+}
+
+impl Harness {
+    /// Creates a harness without any attributes.
+    pub fn new() -> Self {
+        Harness::default()
+    }
+
+    /// Adds an attribute of the given semantics.
+    pub fn attribute(mut self, name: &str, semantics: AttributeSemantics) -> Self {
+        self.attributes.push((name.to_string(), semantics));
+        self
+    }
+
+    /// Adds an update to one of the attributes, at the given time.
+    pub fn update(mut self, time: Time, tx: TxData) -> Self {
+        self.updates.push((time, tx));
+        self
+    }
+
+    /// Implements the plan in a single-threaded worker, feeds it all
+    /// updates, and returns its output once all of them have been
+    /// processed. Plans referring to rules (rather than just to
+    /// attributes) can't be run this way.
+    pub fn run(&self, plan: &Plan) -> Result<Epochs, Error> {
+        let harness = self.clone();
+        let plan = plan.clone();
+
+        let guards = timely::execute(Configuration::Thread, move |worker| {
+            let mut context = HarnessContext::new(&harness.updates);
+            let mut probe = ProbeHandle::new();
+            let epochs = Rc::new(RefCell::new(Epochs::new()));
+
+            worker.dataflow::<Time, _, _>(|scope| {
+                for (name, semantics) in harness.attributes.iter() {
+                    context
+                        .domain
+                        .create_attribute(name, semantics.clone(), scope)?;
+                }
+
+                let epochs = epochs.clone();
+
+                scope
+                    .iterative::<u64, _, _>(|nested| -> Result<_, Error> {
+                        let relation = plan.implement(nested, &VariableMap::new(), &mut context)?;
+                        Ok(relation.tuples().leave())
+                    })?
+                    .inspect(move |(tuple, time, diff)| {
+                        epochs
+                            .borrow_mut()
+                            .entry(*time)
+                            .or_insert_with(Vec::new)
+                            .push((tuple.clone(), *diff));
+                    })
+                    .probe_with(&mut probe);
+
+                Ok(())
+            })?;
+
+            for (time, tx) in harness.updates.iter() {
+                context.domain.transact_at(vec![tx.clone()], *time)?;
+            }
+
+            context.domain.close_all();
+            worker.step_while(|| !probe.done());
+
+            let mut epochs = epochs.replace(Epochs::new());
+            for changes in epochs.values_mut() {
+                consolidate(changes);
+            }
+            epochs.retain(|_time, changes| !changes.is_empty());
+
+            Ok(epochs)
+        })
+        .map_err(|message| Error {
+            category: "df.error.category/fault",
+            message,
+        })?;
+
+        match guards.join().pop() {
+            Some(Ok(result)) => result,
+            Some(Err(message)) => Err(Error {
+                category: "df.error.category/fault",
+                message,
+            }),
+            None => unreachable!(),
+        }
+    }
+}
+
+/// Sums up the diffs of equal tuples and drops those cancelling out.
+fn consolidate(changes: &mut Vec<(Vec<Value>, isize)>) {
+    changes.sort();
+
+    let mut consolidated: Vec<(Vec<Value>, isize)> = Vec::with_capacity(changes.len());
+    for (tuple, diff) in changes.drain(..) {
+        match consolidated.last_mut() {
+            Some((last, sum)) if *last == tuple => *sum += diff,
+            _ => consolidated.push((tuple, diff)),
+        }
+    }
+
+    consolidated.retain(|(_tuple, diff)| *diff != 0);
+    *changes = consolidated;
+}
+
+/// Implementation context of a harness, providing nothing but the
+/// attributes.
+struct HarnessContext {
+    domain: Domain<Time>,
+    cardinalities: HashMap<Aid, isize>,
+    derived_indices: HashMap<(Aid, IndexDirection), CollectionIndex<Value, Value, Time>>,
+    shutdown_handle: ShutdownHandle,
+}
+
+impl HarnessContext {
+    fn new(updates: &[(Time, TxData)]) -> Self {
+        let mut cardinalities = HashMap::new();
+        for (_time, TxData(diff, _e, aid, _v)) in updates.iter() {
+            *cardinalities.entry(aid.clone()).or_insert(0) += *diff;
+        }
+
+        HarnessContext {
+            domain: Domain::new(Default::default()),
+            cardinalities,
+            derived_indices: HashMap::new(),
+            shutdown_handle: Default::default(),
+        }
+    }
+}
+
+impl ImplContext for HarnessContext {
+    fn rule(&self, _name: &str) -> Option<&Rule> {
+        None
+    }
+
+    fn global_arrangement(&mut self, _name: &str) -> Option<&mut RelationHandle> {
+        None
+    }
+
+    fn forward_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>> {
+        self.domain.forward.get_mut(name)
+    }
+
+    fn reverse_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>> {
+        self.domain.reverse.get_mut(name)
+    }
+
+    fn derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+    ) -> Option<&mut CollectionIndex<Value, Value, Time>> {
+        self.derived_indices.get_mut(&(name.to_string(), direction))
+    }
+
+    fn cache_derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+        index: CollectionIndex<Value, Value, Time>,
+    ) {
+        self.derived_indices
+            .insert((name.to_string(), direction), index);
+    }
+
+    fn attributes(&self) -> Vec<Aid> {
+        self.domain.forward.keys().cloned().collect()
+    }
+
+    fn tx_index(&mut self, name: &str) -> Option<&mut TxIndexHandle<Time>> {
+        self.domain.tx_times.get_mut(name)
+    }
+
+    fn full_text_index(&mut self, name: &str) -> Option<&mut FullTextIndexHandle<Time>> {
+        self.domain.full_text.get_mut(name)
+    }
+
+    fn is_underconstrained(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn is_replicated(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn cardinality(&self, name: &str) -> Option<usize> {
+        self.cardinalities
+            .get(name)
+            .map(|count| std::cmp::max(*count, 0) as usize)
+    }
+
+    fn value_type(&self, name: &str) -> Option<ValueType> {
+        self.domain.value_type(name)
+    }
+
+    fn is_append_only(&self, name: &str) -> bool {
+        self.domain.is_append_only(name)
+    }
+
+    fn is_distinct(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn external_function(&self, _name: &str) -> Option<&ExternalFunction> {
+        None
+    }
+
+    fn hector_batch_limit(&self) -> Option<usize> {
+        None
+    }
+
+    fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+}
@@ -0,0 +1,72 @@
+//! Deterministic derivation of entity ids from external keys, s.t.
+//! independent ingestion paths (sources, transactions, transforms)
+//! agree on entity identity without a central allocator.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::Eid;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Derives an entity id from an external key, via 64-bit FNV-1a.
+/// Unlike the hashes used to distribute data among workers, this is
+/// stable across processes, platforms, and versions, and can be
+/// reproduced by clients.
+pub fn key_eid(key: &str) -> Eid {
+    let mut hash = FNV_OFFSET_BASIS;
+
+    for byte in key.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash as Eid
+}
+
+/// Derives entity ids from external keys, keeping track of all keys
+/// seen in order to report distinct keys mapping onto the same id.
+/// Memory grows with the number of distinct keys.
+#[derive(Default)]
+pub struct KeyEids {
+    keys: HashMap<Eid, String>,
+    collisions: usize,
+}
+
+impl KeyEids {
+    /// Creates a new, empty tracker.
+    pub fn new() -> Self {
+        KeyEids::default()
+    }
+
+    /// Returns the entity id for the specified key, logging a warning
+    /// if another key has been mapped onto it before.
+    pub fn eid(&mut self, key: &str) -> Eid {
+        let eid = key_eid(key);
+
+        match self.keys.entry(eid) {
+            Entry::Vacant(entry) => {
+                entry.insert(key.to_string());
+            }
+            Entry::Occupied(entry) => {
+                if entry.get() != key {
+                    warn!(
+                        "[KEYS] {:?} and {:?} both map onto eid {}",
+                        entry.get(),
+                        key,
+                        eid
+                    );
+                    self.collisions += 1;
+                }
+            }
+        }
+
+        eid
+    }
+
+    /// Returns the number of collisions observed so far.
+    pub fn collisions(&self) -> usize {
+        self.collisions
+    }
+}
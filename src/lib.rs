@@ -6,6 +6,8 @@
 
 #![forbid(missing_docs)]
 
+#[cfg(feature = "arrow-ipc")]
+extern crate arrow;
 extern crate differential_dataflow;
 extern crate timely;
 extern crate timely_sort;
@@ -14,18 +16,44 @@ extern crate log;
 extern crate abomonation;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "async-client")]
+extern crate futures;
 extern crate num_rational;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
+#[cfg(feature = "regex-predicates")]
+extern crate regex;
+#[cfg(feature = "async-client")]
+extern crate tokio;
+#[cfg(feature = "async-client")]
+extern crate tokio_tungstenite;
+#[cfg(feature = "async-client")]
+extern crate url;
 
 pub mod binding;
+#[cfg(feature = "async-client")]
+pub mod client;
 pub mod domain;
+pub mod harness;
+pub mod keys;
 pub mod plan;
 pub mod server;
+pub mod sinks;
 pub mod sources;
+#[cfg(feature = "sql")]
+pub mod sql;
 pub mod timestamp;
+pub mod udf;
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+#[cfg(feature = "real-time")]
+use std::time::Duration;
 
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Broadcast, CapabilitySet};
 use timely::dataflow::scopes::child::{Child, Iterative};
 use timely::dataflow::*;
 use timely::order::Product;
@@ -33,17 +61,18 @@ use timely::progress::timestamp::Refines;
 use timely::progress::Timestamp;
 
 use differential_dataflow::lattice::Lattice;
-use differential_dataflow::operators::arrange::{Arrange, Arranged, TraceAgent};
+use differential_dataflow::operators::arrange::{Arrange, Arranged, ShutdownButton, TraceAgent};
 use differential_dataflow::operators::group::Threshold;
 use differential_dataflow::operators::iterate::Variable;
 use differential_dataflow::trace::implementations::ord::{OrdKeySpine, OrdValSpine};
 use differential_dataflow::trace::wrappers::enter::TraceEnter;
 use differential_dataflow::trace::wrappers::enter_at::TraceEnter as TraceEnterAt;
 use differential_dataflow::trace::TraceReader;
-use differential_dataflow::{Collection, Data};
+use differential_dataflow::{AsCollection, Collection, Data};
 
 pub use num_rational::Rational32;
 
+use binding::Binding;
 pub use plan::{Hector, ImplContext, Implementable, Plan};
 
 /// A unique entity identifier.
@@ -54,6 +83,78 @@ pub type Eid = u64;
 #[cfg(feature = "uuids")]
 pub type Eid = u128;
 
+/// A timestamp in the internal domain, i.e. a command sequence
+/// number.
+#[cfg(not(feature = "real-time"))]
+pub type Time = u64;
+
+/// A timestamp in the internal domain, i.e. a duration since the
+/// unix epoch.
+#[cfg(feature = "real-time")]
+pub type Time = Duration;
+
+/// Returns the time lying the specified number of units (i.e.
+/// commands) before `time`, or the minimum time if there is no such
+/// time.
+#[cfg(not(feature = "real-time"))]
+pub fn rewind(time: &Time, units: u64) -> Time {
+    time.saturating_sub(units)
+}
+
+/// Returns the time lying the specified number of units (i.e.
+/// milliseconds) before `time`, or the minimum time if there is no
+/// such time.
+#[cfg(feature = "real-time")]
+pub fn rewind(time: &Time, units: u64) -> Time {
+    time.checked_sub(Duration::from_millis(units))
+        .unwrap_or_default()
+}
+
+/// Represents a time as a value, s.t. it can be bound in queries.
+#[cfg(not(feature = "real-time"))]
+pub fn time_value(time: &Time) -> Value {
+    Value::Number(*time as i64)
+}
+
+/// Represents a time as a value (in milliseconds), s.t. it can be
+/// bound in queries.
+#[cfg(feature = "real-time")]
+pub fn time_value(time: &Time) -> Value {
+    Value::Instant(time.as_millis() as u64)
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher`, its output does
+/// not change between Rust releases, s.t. it can be used for hashes
+/// that are persisted or become part of names.
+#[derive(Clone, Copy, Debug)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hashes a value using the `StableHasher`.
+pub fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
 /// A unique attribute identifier.
 pub type Aid = String; // u32
 
@@ -81,6 +182,22 @@ pub enum Value {
     Uuid([u8; 16]),
 }
 
+impl Value {
+    /// Returns the name of this type of value.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::Aid(_) => "Aid",
+            Value::String(_) => "String",
+            Value::Bool(_) => "Bool",
+            Value::Number(_) => "Number",
+            Value::Rational32(_) => "Rational32",
+            Value::Eid(_) => "Eid",
+            Value::Instant(_) => "Instant",
+            Value::Uuid(_) => "Uuid",
+        }
+    }
+}
+
 /// A client-facing, non-exceptional error.
 #[derive(Debug)]
 pub struct Error {
@@ -96,7 +213,7 @@ pub struct Error {
 pub struct TxData(pub isize, pub Eid, pub Aid, pub Value);
 
 /// A (tuple, time, diff) triple, as sent back to clients.
-pub type ResultDiff = (Vec<Value>, u64, isize);
+pub type ResultDiff = (Vec<Value>, Time, isize);
 
 /// An entity, attribute, value triple.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
@@ -110,10 +227,37 @@ pub type TraceValHandle<K, V, T, R> = TraceAgent<K, V, T, R, OrdValSpine<K, V, T
 
 // @TODO change this to TraceValHandle<Eid, Value> eventually
 /// A handle to an arranged attribute.
-pub type AttributeHandle = TraceValHandle<Value, Value, u64, isize>;
+pub type AttributeHandle = TraceValHandle<Value, Value, Time, isize>;
 
 /// A handle to an arranged relation.
-pub type RelationHandle = TraceKeyHandle<Vec<Value>, u64, isize>;
+pub type RelationHandle = TraceKeyHandle<Vec<Value>, Time, isize>;
+
+/// Buttons stopping the imports of traces into a dataflow, which
+/// are pressed once the last clone of the handle is dropped. A
+/// dataflow whose imports have been stopped runs out of inputs,
+/// drains, and is torn down.
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    buttons: Rc<RefCell<ShutdownButtons>>,
+}
+
+#[derive(Default)]
+struct ShutdownButtons(Vec<ShutdownButton<CapabilitySet<Time>>>);
+
+impl Drop for ShutdownButtons {
+    fn drop(&mut self) {
+        for button in self.0.iter_mut() {
+            button.press();
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Adds a button to be pressed along with all others.
+    pub fn add_button(&self, button: ShutdownButton<CapabilitySet<Time>>) {
+        self.buttons.borrow_mut().0.push(button);
+    }
+}
 
 // A map for keeping track of collections that are being actively
 // synthesized (i.e. that are not fully defined yet).
@@ -131,6 +275,90 @@ pub enum AttributeSemantics {
     /// Multiple different values for any given eid are allowed, but
     /// (e,v) pairs are enforced to be distinct.
     CardinalityMany,
+    /// Like CardinalityMany, but string values are additionally
+    /// tokenized into an inverted index from tokens to eids, which
+    /// can be searched via FullText plans.
+    FullText,
+}
+
+/// Types an attribute can declare for its values. Values of
+/// different kinds are ordered by kind first, e.g. every number
+/// sorts before every rational. Attributes declaring a type hold
+/// values of that type only, s.t. their reverse index is ordered
+/// according to the natural ordering of the type, and range scans
+/// over it are meaningful.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum ValueType {
+    /// Attribute identifiers, ordered lexicographically.
+    Aid,
+    /// Strings, ordered lexicographically.
+    String,
+    /// Booleans, false before true.
+    Bool,
+    /// 64 bit signed integers.
+    Number,
+    /// 32 bit rationals, ordered numerically.
+    Rational32,
+    /// Entity identifiers.
+    Eid,
+    /// Instants, ordered chronologically.
+    Instant,
+    /// Unique identifiers, ordered bytewise.
+    Uuid,
+}
+
+impl ValueType {
+    /// Converts a value into the equivalent value of this type, if
+    /// there is one. Integral numbers are accepted as rationals,
+    /// entity ids and instants, and integral rationals as numbers.
+    pub fn coerce(self, value: Value) -> Option<Value> {
+        match (self, value) {
+            (ValueType::Aid, Value::Aid(aid)) => Some(Value::Aid(aid)),
+            (ValueType::String, Value::String(s)) => Some(Value::String(s)),
+            (ValueType::Bool, Value::Bool(b)) => Some(Value::Bool(b)),
+            (ValueType::Number, Value::Number(n)) => Some(Value::Number(n)),
+            (ValueType::Number, Value::Rational32(r)) if r.is_integer() => {
+                Some(Value::Number(i64::from(r.to_integer())))
+            }
+            (ValueType::Rational32, Value::Rational32(r)) => Some(Value::Rational32(r)),
+            (ValueType::Rational32, Value::Number(n)) => {
+                if n >= i64::from(i32::min_value()) && n <= i64::from(i32::max_value()) {
+                    Some(Value::Rational32(Rational32::from_integer(n as i32)))
+                } else {
+                    None
+                }
+            }
+            (ValueType::Eid, Value::Eid(e)) => Some(Value::Eid(e)),
+            (ValueType::Eid, Value::Number(n)) if n >= 0 => Some(Value::Eid(n as Eid)),
+            (ValueType::Instant, Value::Instant(t)) => Some(Value::Instant(t)),
+            (ValueType::Instant, Value::Number(n)) if n >= 0 => Some(Value::Instant(n as u64)),
+            (ValueType::Uuid, Value::Uuid(u)) => Some(Value::Uuid(u)),
+            _ => None,
+        }
+    }
+}
+
+/// The directions in which binary relations are indexed.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum IndexDirection {
+    /// Indexed from eid -> value.
+    Forward,
+    /// Indexed from value -> eid.
+    Reverse,
+}
+
+/// Attributes can retain more or less of their history, independent
+/// of whether history is enabled server-wide.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum RetentionPolicy {
+    /// The full history is retained.
+    KeepAll,
+    /// History is retained for the specified number of units of
+    /// domain time (commands, or milliseconds with the `real-time`
+    /// feature), and compacted thereafter.
+    KeepLast(u64),
+    /// Only the latest state is retained.
+    KeepLatest,
 }
 
 /// Various indices over a collection of (K, V) pairs, required to
@@ -196,31 +424,48 @@ where
         }
     }
 
-    /// Returns a LiveIndex that lives in the specified scope.
-    pub fn import<G: Scope<Timestamp = T>>(
+    /// Advances the traces maintained in this index.
+    pub fn advance_by(&mut self, frontier: &[T]) {
+        self.count_trace.advance_by(frontier);
+        self.propose_trace.advance_by(frontier);
+        self.validate_trace.advance_by(frontier);
+    }
+}
+
+impl<K, V> CollectionIndex<K, V, Time>
+where
+    K: Data + Hash,
+    V: Data + Hash,
+{
+    /// Returns a LiveIndex that lives in the specified scope. The
+    /// imports are stopped via the specified handle.
+    pub fn import<G: Scope<Timestamp = Time>>(
         &mut self,
         scope: &G,
+        shutdown_handle: &ShutdownHandle,
     ) -> LiveIndex<
         G,
         K,
         V,
-        TraceKeyHandle<K, T, isize>,
-        TraceValHandle<K, V, T, isize>,
-        TraceKeyHandle<(K, V), T, isize>,
+        TraceKeyHandle<K, Time, isize>,
+        TraceValHandle<K, V, Time, isize>,
+        TraceKeyHandle<(K, V), Time, isize>,
     > {
+        let (count_trace, count_button) = self.count_trace.import_core(scope, "Counts");
+        let (propose_trace, propose_button) = self.propose_trace.import_core(scope, "Proposals");
+        let (validate_trace, validate_button) =
+            self.validate_trace.import_core(scope, "Validations");
+
+        shutdown_handle.add_button(count_button);
+        shutdown_handle.add_button(propose_button);
+        shutdown_handle.add_button(validate_button);
+
         LiveIndex {
-            count_trace: self.count_trace.import(scope),
-            propose_trace: self.propose_trace.import(scope),
-            validate_trace: self.validate_trace.import(scope),
+            count_trace,
+            propose_trace,
+            validate_trace,
         }
     }
-
-    /// Advances the traces maintained in this index.
-    pub fn advance_by(&mut self, frontier: &[T]) {
-        self.count_trace.advance_by(frontier);
-        self.propose_trace.advance_by(frontier);
-        self.validate_trace.advance_by(frontier);
-    }
 }
 
 /// CollectionIndex that was imported into a scope.
@@ -546,41 +791,430 @@ where
 //     }
 // }
 
+/// Arranges a published relation. Replicated relations are broadcast
+/// to every worker and maintained there in full, s.t. joins against
+/// them never require an exchange.
+fn publish_arrangement<S: Scope<Timestamp = Time>>(
+    relation: &Collection<S, Vec<Value>, isize>,
+    name: &str,
+    replicated: bool,
+) -> RelationHandle {
+    if replicated {
+        relation
+            .inner
+            .broadcast()
+            .as_collection()
+            .map(|t| (t, ()))
+            .arrange_core(Pipeline, name)
+            .trace
+    } else {
+        relation.map(|t| (t, ())).arrange_named(name).trace
+    }
+}
+
 /// Returns a deduplicates list of all rules used in the definition of
-/// the specified names. Includes the specified names.
-pub fn collect_dependencies<I: ImplContext>(context: &I, names: &[&str]) -> Vec<Rule> {
+/// the specified names. Includes the specified names. Fails if any of
+/// them is unknown.
+pub fn collect_dependencies<I: ImplContext>(
+    context: &I,
+    names: &[&str],
+) -> Result<Vec<Rule>, Error> {
     let mut seen = HashSet::new();
     let mut rules = Vec::new();
     let mut queue = VecDeque::new();
 
+    // Hector bindings report the attributes they bind as well.
+    let attributes: HashSet<Aid> = context.attributes().into_iter().collect();
+
     for name in names {
         seen.insert(name.to_string());
-        queue.push_back(context.rule(name).expect("unknown rule").clone());
+
+        match context.rule(name) {
+            None => {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!("Unknown rule {}.", name),
+                });
+            }
+            Some(rule) => queue.push_back(rule.clone()),
+        }
     }
 
     while let Some(next) = queue.pop_front() {
         for dep_name in next.plan.dependencies().iter() {
             if !seen.contains(dep_name) {
                 seen.insert(dep_name.to_string());
-                queue.push_back(context.rule(dep_name).expect("unknown dependency").clone());
+
+                match context.rule(dep_name) {
+                    None if attributes.contains(dep_name) => {}
+                    None => {
+                        return Err(Error {
+                            category: "df.error.category/not-found",
+                            message: format!(
+                                "Rule {} depends on unknown rule {}.",
+                                next.name, dep_name
+                            ),
+                        });
+                    }
+                    Some(rule) => queue.push_back(rule.clone()),
+                }
             }
         }
 
         rules.push(next);
     }
 
-    rules
+    Ok(rules)
+}
+
+/// Returns the names of all rules among the given ones that refer to
+/// themselves, directly or via other rules, e.g. transitive closures.
+pub fn recursive_rules(rules: &[Rule]) -> HashSet<String> {
+    let dependencies: HashMap<&str, Vec<String>> = rules
+        .iter()
+        .map(|rule| (rule.name.as_str(), rule.plan.dependencies()))
+        .collect();
+
+    let mut recursive = HashSet::new();
+
+    for rule in rules.iter() {
+        let mut seen = HashSet::new();
+        let mut stack: Vec<&str> = dependencies[rule.name.as_str()]
+            .iter()
+            .map(|name| name.as_str())
+            .collect();
+
+        while let Some(next) = stack.pop() {
+            if next == rule.name {
+                recursive.insert(rule.name.clone());
+                break;
+            }
+
+            if seen.insert(next) {
+                if let Some(deps) = dependencies.get(next) {
+                    stack.extend(deps.iter().map(|name| name.as_str()));
+                }
+            }
+        }
+    }
+
+    recursive
+}
+
+/// Checks that no relation bound by a worst-case optimal join depends
+/// on the rule binding it. Such relations are indexed outside of the
+/// iteration, s.t. they can't be part of a fixed point.
+pub fn check_derived_bindings(rules: &[Rule]) -> Result<(), Error> {
+    let dependencies: HashMap<&str, Vec<String>> = rules
+        .iter()
+        .map(|rule| (rule.name.as_str(), rule.plan.dependencies()))
+        .collect();
+
+    for rule in rules.iter() {
+        for bound in rule.plan.attributes() {
+            if !dependencies.contains_key(bound.as_str()) {
+                continue;
+            }
+
+            let mut seen = HashSet::new();
+            let mut stack: Vec<&str> = vec![bound.as_str()];
+
+            while let Some(next) = stack.pop() {
+                if next == rule.name {
+                    return Err(Error {
+                        category: "df.error.category/unsupported",
+                        message: format!(
+                            "Rule {} binds relation {}, which depends on it in turn.",
+                            rule.name, bound
+                        ),
+                    });
+                }
+
+                if seen.insert(next) {
+                    if let Some(deps) = dependencies.get(next) {
+                        stack.extend(deps.iter().map(|name| name.as_str()));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the given rules are stratifiable, i.e. that no rule
+/// depends negatively on a relation which in turn depends on the rule
+/// itself. Negation within a recursive cycle has no well-defined
+/// fixed point.
+pub fn check_stratification(rules: &[Rule]) -> Result<(), Error> {
+    let dependencies: HashMap<&str, Vec<String>> = rules
+        .iter()
+        .map(|rule| (rule.name.as_str(), rule.plan.dependencies()))
+        .collect();
+
+    for rule in rules.iter() {
+        for negated in rule.plan.negated_dependencies() {
+            // Search for a path leading from the negated relation
+            // back to the rule, remembering how we got anywhere.
+            let mut parents: HashMap<&str, &str> = HashMap::new();
+            let mut stack: Vec<&str> = vec![negated.as_str()];
+            let mut cycle = None;
+
+            while let Some(next) = stack.pop() {
+                if next == rule.name {
+                    let mut path = vec![next];
+                    let mut current = next;
+                    while current != negated.as_str() {
+                        current = parents[current];
+                        path.push(current);
+                    }
+                    path.push(rule.name.as_str());
+                    path.reverse();
+
+                    cycle = Some(path.join(" -> "));
+                    break;
+                }
+
+                if let Some(deps) = dependencies.get(next) {
+                    for dep in deps.iter() {
+                        if dep != &negated && !parents.contains_key(dep.as_str()) {
+                            parents.insert(dep.as_str(), next);
+                            stack.push(dep.as_str());
+                        }
+                    }
+                }
+            }
+
+            if let Some(cycle) = cycle {
+                return Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: format!(
+                        "Rule {} negates {} within a recursive cycle ({}), rules must be stratifiable.",
+                        rule.name, negated, cycle
+                    ),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the kind of the first stage of the plan that might
+/// retract tuples, even if all attributes it reads from are
+/// append-only, if any.
+fn retracting_stage(plan: &Plan) -> Option<&'static str> {
+    match *plan {
+        Plan::Project(ref projection) => retracting_stage(&projection.plan),
+        Plan::Union(ref union) => union.plans.iter().filter_map(retracting_stage).next(),
+        Plan::Join(ref join) => {
+            retracting_stage(&join.left_plan).or_else(|| retracting_stage(&join.right_plan))
+        }
+        Plan::MultiJoin(ref join) => join.plans.iter().filter_map(retracting_stage).next(),
+        Plan::BroadcastJoin(ref join) => {
+            retracting_stage(&join.left_plan).or_else(|| retracting_stage(&join.right_plan))
+        }
+        Plan::Hector(ref hector) => {
+            let negated = hector.bindings.iter().any(|binding| match binding {
+                Binding::Not(_) => true,
+                _ => false,
+            });
+
+            if negated {
+                Some("Antijoin")
+            } else {
+                None
+            }
+        }
+        Plan::Filter(ref filter) => retracting_stage(&filter.plan),
+        Plan::Transform(ref transform) => retracting_stage(&transform.plan),
+        Plan::Pull(ref pull) => pull
+            .paths
+            .iter()
+            .filter_map(|path| retracting_stage(&path.plan))
+            .next(),
+        Plan::PullLevel(ref path) => retracting_stage(&path.plan),
+        Plan::Entities(ref entities) => entities
+            .plan
+            .as_ref()
+            .and_then(|plan| retracting_stage(plan)),
+        Plan::Aggregate(_) => Some("Aggregate"),
+        Plan::Antijoin(_) => Some("Antijoin"),
+        Plan::Negate(_) => Some("Negate"),
+        Plan::Degree(_) => Some("Degree"),
+        Plan::MatchA(_, _, _)
+        | Plan::MatchEA(_, _, _)
+        | Plan::MatchAV(_, _, _)
+        | Plan::MatchATx(_, _, _, _)
+        | Plan::RangeScan(_)
+        | Plan::FullText(_)
+        | Plan::NameExpr(_, _) => None,
+    }
+}
+
+/// Returns the names of all rules among the given ones that can be
+/// streamed, i.e. implemented without enforcing set semantics. Rules
+/// qualify if they read from append-only attributes only, neither
+/// negate nor aggregate, aren't recursive, and depend on qualifying
+/// rules only. Their results then never retract anything, so the
+/// arrangement otherwise maintained to deduplicate them can be
+/// skipped, at the price of results carrying multiplicities.
+/// Qualifying rules are implemented with set semantics regardless,
+/// if asked to via `Register::distinct`.
+pub fn streaming_rules<I: ImplContext>(context: &I, rules: &[Rule]) -> HashSet<String> {
+    let recursive = recursive_rules(rules);
+
+    let mut append_only: HashSet<String> = rules
+        .iter()
+        .filter(|rule| !recursive.contains(&rule.name))
+        .filter(|rule| retracting_stage(&rule.plan).is_none())
+        .filter(|rule| {
+            rule.plan
+                .attributes()
+                .iter()
+                .all(|name| context.is_append_only(name))
+        })
+        .map(|rule| rule.name.clone())
+        .collect();
+
+    // Rules retain their output only if all relations they depend
+    // on do as well.
+    loop {
+        let retracting: Vec<String> = rules
+            .iter()
+            .filter(|rule| append_only.contains(&rule.name))
+            .filter(|rule| {
+                rule.plan
+                    .dependencies()
+                    .iter()
+                    .any(|name| !append_only.contains(name) && !context.is_append_only(name))
+            })
+            .map(|rule| rule.name.clone())
+            .collect();
+
+        if retracting.is_empty() {
+            break;
+        }
+
+        for name in retracting.iter() {
+            append_only.remove(name);
+        }
+    }
+
+    append_only.retain(|name| !context.is_distinct(name));
+    append_only
+}
+
+/// Returns true iff the plan aggregates at any stage.
+fn contains_aggregation(plan: &Plan) -> bool {
+    match *plan {
+        Plan::Aggregate(_) => true,
+        Plan::Project(ref projection) => contains_aggregation(&projection.plan),
+        Plan::Union(ref union) => union.plans.iter().any(contains_aggregation),
+        Plan::Join(ref join) => {
+            contains_aggregation(&join.left_plan) || contains_aggregation(&join.right_plan)
+        }
+        Plan::MultiJoin(ref join) => join.plans.iter().any(contains_aggregation),
+        Plan::BroadcastJoin(ref join) => {
+            contains_aggregation(&join.left_plan) || contains_aggregation(&join.right_plan)
+        }
+        Plan::Antijoin(ref antijoin) => {
+            contains_aggregation(&antijoin.left_plan) || contains_aggregation(&antijoin.right_plan)
+        }
+        Plan::Negate(ref plan) => contains_aggregation(plan),
+        Plan::Filter(ref filter) => contains_aggregation(&filter.plan),
+        Plan::Transform(ref transform) => contains_aggregation(&transform.plan),
+        _ => false,
+    }
+}
+
+/// Returns the kind of the first stage of the plan that can't be
+/// expressed as bindings unified by Hector, if any.
+fn unsupported_by_optimizer(plan: &Plan) -> Option<&'static str> {
+    match *plan {
+        Plan::Project(ref projection) => unsupported_by_optimizer(&projection.plan),
+        Plan::Aggregate(ref aggregate) => unsupported_by_optimizer(&aggregate.plan),
+        Plan::Join(ref join) => unsupported_by_optimizer(&join.left_plan)
+            .or_else(|| unsupported_by_optimizer(&join.right_plan)),
+        Plan::MultiJoin(ref join) => join
+            .plans
+            .iter()
+            .filter_map(unsupported_by_optimizer)
+            .next(),
+        Plan::BroadcastJoin(ref join) => unsupported_by_optimizer(&join.left_plan)
+            .or_else(|| unsupported_by_optimizer(&join.right_plan)),
+        Plan::Hector(_)
+        | Plan::MatchA(_, _, _)
+        | Plan::MatchEA(_, _, _)
+        | Plan::MatchAV(_, _, _) => None,
+        Plan::Union(_) => Some("Union"),
+        Plan::Antijoin(_) => Some("Antijoin"),
+        Plan::Negate(_) => Some("Negate"),
+        Plan::Filter(_) => Some("Filter"),
+        Plan::Transform(_) => Some("Transform"),
+        Plan::MatchATx(_, _, _, _) => Some("MatchATx"),
+        Plan::RangeScan(_) => Some("RangeScan"),
+        Plan::Degree(_) => Some("Degree"),
+        Plan::FullText(_) => Some("FullText"),
+        Plan::Entities(_) => Some("Entities"),
+        Plan::NameExpr(_, _) => Some("NameExpr"),
+        Plan::Pull(_) => Some("Pull"),
+        Plan::PullLevel(_) => Some("PullLevel"),
+    }
+}
+
+/// Prepares a plan containing aggregations for the optimizer.
+/// Aggregations can't be expressed as bindings, so they are kept as
+/// separate stages (pushed beneath joins where possible), with their
+/// inputs unified by Hector in turn.
+fn plan_neu(plan: &Plan) -> Result<Plan, Error> {
+    if !contains_aggregation(plan) {
+        return Ok(Plan::Hector(Hector {
+            variables: plan.bound_symbols(),
+            bindings: plan.into_bindings()?,
+        }));
+    }
+
+    match *plan {
+        Plan::Aggregate(ref aggregate) => match aggregate.push_beneath_join() {
+            Some(pushed) => plan_neu(&pushed),
+            None => Ok(Plan::Aggregate(plan::Aggregate {
+                plan: Box::new(plan_neu(&aggregate.plan)?),
+                ..aggregate.clone()
+            })),
+        },
+        Plan::Project(ref projection) => Ok(Plan::Project(plan::Project {
+            variables: projection.variables.clone(),
+            plan: Box::new(plan_neu(&projection.plan)?),
+        })),
+        Plan::Join(ref join) => Ok(Plan::Join(plan::Join {
+            variables: join.variables.clone(),
+            left_plan: Box::new(plan_neu(&join.left_plan)?),
+            right_plan: Box::new(plan_neu(&join.right_plan)?),
+        })),
+        Plan::MultiJoin(ref join) => Ok(Plan::MultiJoin(plan::MultiJoin {
+            variables: join.variables.clone(),
+            plans: join.plans.iter().map(plan_neu).collect::<Result<_, _>>()?,
+        })),
+        // @TODO other stages above aggregations are implemented
+        // without the optimizer for now
+        _ => Ok(plan.clone()),
+    }
 }
 
 /// Takes a query plan and turns it into a differential dataflow.
-pub fn implement<S: Scope<Timestamp = u64>, I: ImplContext>(
+/// Every rule is bound to a variable of the nested, iterative scope,
+/// s.t. rules may refer to themselves (recursively, or mutually via
+/// other rules) and are iterated until they reach a fixed point.
+/// Negation within such cycles is rejected.
+pub fn implement<S: Scope<Timestamp = Time>, I: ImplContext>(
     name: &str,
     scope: &mut S,
     context: &mut I,
 ) -> Result<HashMap<String, RelationHandle>, Error> {
     scope.iterative::<u64, _, _>(|nested| {
         let publish = vec![name];
-        let mut rules = collect_dependencies(&*context, &publish[..]);
+        let mut rules = collect_dependencies(&*context, &publish[..])?;
 
         let mut local_arrangements = VariableMap::new();
         let mut result_map = HashMap::new();
@@ -603,18 +1237,30 @@ pub fn implement<S: Scope<Timestamp = u64>, I: ImplContext>(
             }
         }
 
+        check_stratification(&rules)?;
+        check_derived_bindings(&rules)?;
+
+        for rule in rules.iter() {
+            rule.plan.validate()?;
+        }
+
+        let streaming = streaming_rules(&*context, &rules);
+
         // Step 1: Create new recursive variables for each rule.
         for rule in rules.iter() {
             if context.is_underconstrained(&rule.name) {
-                local_arrangements
-                    .insert(rule.name.clone(), Variable::new(nested, Product::new(0, 1)));
+                local_arrangements.insert(
+                    rule.name.clone(),
+                    Variable::new(nested, Product::new(Default::default(), 1)),
+                );
             }
         }
 
         // Step 2: Create public arrangements for published relations.
         for name in publish.into_iter() {
             if let Some(relation) = local_arrangements.get(name) {
-                let trace = relation.leave().map(|t| (t, ())).arrange_named(name).trace;
+                let trace =
+                    publish_arrangement(&relation.leave(), name, context.is_replicated(name));
 
                 result_map.insert(name.to_string(), trace);
             } else {
@@ -629,7 +1275,7 @@ pub fn implement<S: Scope<Timestamp = u64>, I: ImplContext>(
         let mut executions = Vec::with_capacity(rules.len());
         for rule in rules.iter() {
             info!("planning {:?}", rule.name);
-            executions.push(rule.plan.implement(nested, &local_arrangements, context));
+            executions.push(rule.plan.implement(nested, &local_arrangements, context)?);
         }
 
         // Step 4: Complete named relations in a specific order (sorted by name).
@@ -645,7 +1291,11 @@ pub fn implement<S: Scope<Timestamp = u64>, I: ImplContext>(
                     });
                 }
                 Some(variable) => {
-                    variable.set(&execution.tuples().distinct());
+                    if streaming.contains(&rule.name) {
+                        variable.set(&execution.tuples());
+                    } else {
+                        variable.set(&execution.tuples().distinct());
+                    }
                 }
             }
         }
@@ -661,12 +1311,12 @@ pub fn implement_neu<S, I>(
     context: &mut I,
 ) -> Result<HashMap<String, RelationHandle>, Error>
 where
-    S: Scope<Timestamp = u64>,
+    S: Scope<Timestamp = Time>,
     I: ImplContext,
 {
     scope.iterative::<u64, _, _>(move |nested| {
         let publish = vec![name];
-        let mut rules = collect_dependencies(&*context, &publish[..]);
+        let mut rules = collect_dependencies(&*context, &publish[..])?;
 
         let mut local_arrangements = VariableMap::new();
         let mut result_map = HashMap::new();
@@ -689,6 +1339,60 @@ where
             }
         }
 
+        // Recursive rules would have to be closed via variables,
+        // which Hector can't refer to (yet).
+        let recursive = recursive_rules(&rules);
+        if !recursive.is_empty() {
+            let mut names: Vec<String> = recursive.into_iter().collect();
+            names.sort();
+
+            return Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!(
+                    "Recursive rules ({}) are not supported by the optimizer.",
+                    names.join(", ")
+                ),
+            });
+        }
+
+        let streaming = streaming_rules(&*context, &rules);
+
+        for rule in rules.iter() {
+            if let Some(stage) = unsupported_by_optimizer(&rule.plan) {
+                return Err(Error {
+                    category: "df.error.category/unsupported",
+                    message: format!(
+                        "{} stages (in rule {}) are not supported by the optimizer.",
+                        stage, rule.name
+                    ),
+                });
+            }
+        }
+
+        // @TODO here we need to split up the plan into multiple
+        // Hector plans (one for each symbol)
+        let plans = rules
+            .iter()
+            .map(|rule| {
+                let plan = if contains_aggregation(&rule.plan) {
+                    plan_neu(&rule.plan)
+                } else {
+                    rule.plan.into_bindings().map(|bindings| {
+                        Plan::Hector(Hector {
+                            variables: rule.plan.variables(),
+                            bindings,
+                        })
+                    })
+                };
+
+                plan.and_then(|plan| plan.validate().map(|()| plan))
+                    .map_err(|error| Error {
+                        category: error.category,
+                        message: format!("In rule {}: {}", rule.name, error.message),
+                    })
+            })
+            .collect::<Result<Vec<Plan>, Error>>()?;
+
         // @TODO at this point we need to know about...
         // @TODO ... which rules require recursion (and thus need wrapping in a Variable)
         // @TODO ... which rules are supposed to be re-used
@@ -700,15 +1404,18 @@ where
         // Step 1: Create new recursive variables for each rule.
         for name in publish.iter() {
             if context.is_underconstrained(name) {
-                local_arrangements
-                    .insert(name.to_string(), Variable::new(nested, Product::new(0, 1)));
+                local_arrangements.insert(
+                    name.to_string(),
+                    Variable::new(nested, Product::new(Default::default(), 1)),
+                );
             }
         }
 
         // Step 2: Create public arrangements for published relations.
         for name in publish.into_iter() {
             if let Some(relation) = local_arrangements.get(name) {
-                let trace = relation.leave().map(|t| (t, ())).arrange_named(name).trace;
+                let trace =
+                    publish_arrangement(&relation.leave(), name, context.is_replicated(name));
 
                 result_map.insert(name.to_string(), trace);
             } else {
@@ -721,18 +1428,10 @@ where
 
         // Step 3: Define the executions for each rule.
         let mut executions = Vec::with_capacity(rules.len());
-        for rule in rules.iter() {
+        for (rule, plan) in rules.iter().zip(plans.iter()) {
             info!("neu_planning {:?}", rule.name);
 
-            // @TODO here we need to split up the plan into multiple
-            // Hector plans (one for each symbol)
-
-            let plan = Plan::Hector(Hector {
-                variables: rule.plan.variables(),
-                bindings: rule.plan.into_bindings(),
-            });
-
-            executions.push(plan.implement(nested, &local_arrangements, context));
+            executions.push(plan.implement(nested, &local_arrangements, context)?);
         }
 
         // Step 4: Complete named relations in a specific order (sorted by name).
@@ -748,7 +1447,11 @@ where
                     });
                 }
                 Some(variable) => {
-                    variable.set(&execution.tuples().distinct());
+                    if streaming.contains(&rule.name) {
+                        variable.set(&execution.tuples());
+                    } else {
+                        variable.set(&execution.tuples().distinct());
+                    }
                 }
             }
         }
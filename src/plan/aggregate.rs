@@ -6,10 +6,11 @@ use timely::dataflow::Scope;
 use differential_dataflow::difference::DiffPair;
 use differential_dataflow::operators::Join as JoinMap;
 use differential_dataflow::operators::{Consolidate, Count, Group, Threshold};
+use differential_dataflow::Collection;
 
 use crate::binding::Binding;
-use crate::plan::{ImplContext, Implementable};
-use crate::{CollectionRelation, Relation, Value, Var, VariableMap};
+use crate::plan::{ImplContext, Implementable, Join, Plan, Project};
+use crate::{CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
 
 use num_rational::{Ratio, Rational32};
 
@@ -28,22 +29,38 @@ pub enum AggregationFn {
     SUM,
     /// Average
     AVG,
-    ///Varianve
+    /// Variance
     VARIANCE,
+    /// The k tuples with the highest values, along with their
+    /// with-symbols. Can't be combined with other aggregations.
+    TOP(usize),
+    /// The k tuples with the lowest values, along with their
+    /// with-symbols. Can't be combined with other aggregations.
+    BOTTOM(usize),
     // /// Standard deviation
     // STDDEV,
 }
 
-/// [WIP] A plan stage applying the specified aggregation functions to
-/// bindings for the specified symbols. Given multiple aggregations
-/// we iterate and n-1 joins are applied to the results.
+/// A plan stage applying the specified aggregation functions to
+/// bindings for the specified symbols. Tuples are grouped by the
+/// values bound to `key_symbols`. Given multiple aggregations we
+/// iterate and n-1 joins are applied to the results.
+///
+/// Aggregates compose with all other plan stages, e.g. aggregating
+/// over the output of a `Join`, `Union`, or `Filter`, or feeding
+/// into a `Join` or a `PullLevel`.
+///
+/// `TOP` and `BOTTOM` retain up to k tuples per key rather than a
+/// single value, their variables may therefore include with-symbols
+/// as well (e.g. the players on a leaderboard).
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Aggregate<P: Implementable> {
-    /// TODO
+    /// Symbols bound by this stage, i.e. the key symbols with each
+    /// aggregation symbol taking the place of its aggregate.
     pub variables: Vec<Var>,
     /// Plan for the data source.
     pub plan: Box<P>,
-    /// Logical predicate to apply.
+    /// Aggregation functions to apply, one per aggregation symbol.
     pub aggregation_fns: Vec<AggregationFn>,
     /// Relation symbols that determine the grouping.
     pub key_symbols: Vec<Var>,
@@ -58,17 +75,17 @@ impl<P: Implementable> Implementable for Aggregate<P> {
         self.plan.dependencies()
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
         self.plan.into_bindings()
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
-        let relation = self.plan.implement(nested, local_arrangements, context);
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let relation = self.plan.implement(nested, local_arrangements, context)?;
 
         // We split the incoming tuples into their (key, value) parts.
         let tuples = relation.tuples_by_symbols(&self.key_symbols);
@@ -97,12 +114,35 @@ impl<P: Implementable> Implementable for Aggregate<P> {
         let mut output_offsets = Vec::new();
 
         for sym in self.aggregation_symbols.iter() {
-            let output_index = variables.iter().position(|&v| *sym == v).unwrap();
+            let output_index = variables
+                .iter()
+                .position(|&v| *sym == v)
+                .ok_or_else(|| Error {
+                    category: "df.error.category/incorrect",
+                    message: format!("Aggregation symbol {} is not among the variables.", sym),
+                })?;
             output_offsets.push(output_index);
 
             variables[output_index] = 0;
         }
 
+        let is_ranking = |aggregation_fn: &AggregationFn| match aggregation_fn {
+            AggregationFn::TOP(_) | AggregationFn::BOTTOM(_) => true,
+            _ => false,
+        };
+
+        if self.aggregation_fns.iter().any(is_ranking) {
+            if self.aggregation_fns.len() > 1 {
+                return Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: "TOP and BOTTOM can't be combined with other aggregations."
+                        .to_string(),
+                });
+            }
+
+            return self.rank(tuples, value_offsets[0]);
+        }
+
         let mut collections = Vec::new();
 
         // We iterate over all aggregations and keep track of the
@@ -171,7 +211,10 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                         .explode(|(key, val)| {
                             let v = match val[0] {
                                 Value::Number(num) => num,
-                                _ => panic!("SUM can only be applied on type Number."),
+                                _ => {
+                                    error!("[AGGREGATE] SUM can only be applied on type Number, skipping {:?}", val[0]);
+                                    return None;
+                                }
                             };
                             Some((key, v as isize))
                         })
@@ -187,7 +230,10 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                         .explode(move |(key, val)| {
                             let v = match val[0] {
                                 Value::Number(num) => num,
-                                _ => panic!("AVG can only be applied on type Number."),
+                                _ => {
+                                    error!("[AGGREGATE] AVG can only be applied on type Number, skipping {:?}", val[0]);
+                                    return None;
+                                }
                             };
                             Some((key, DiffPair::new(v as isize, 1)))
                         })
@@ -211,7 +257,10 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                         .explode(move |(key, val)| {
                             let v = match val[0] {
                                 Value::Number(num) => num,
-                                _ => panic!("VARIANCE can only be applied on type Number."),
+                                _ => {
+                                    error!("[AGGREGATE] VARIANCE can only be applied on type Number, skipping {:?}", val[0]);
+                                    return None;
+                                }
                             };
                             Some((
                                 key,
@@ -235,12 +284,13 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                         });
                     collections.push(tuples);
                 }
+                AggregationFn::TOP(_) | AggregationFn::BOTTOM(_) => unreachable!(),
             };
         }
 
         if collections.len() == 1 {
             let output_index = output_offsets[0];
-            CollectionRelation {
+            Ok(CollectionRelation {
                 symbols: self.variables.to_vec(),
                 tuples: collections[0].map(move |(key, val)| {
                     let mut k = key.clone();
@@ -248,7 +298,7 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                     k.insert(output_index, v);
                     k
                 }),
-            }
+            })
         } else {
             // @TODO replace this with a join application
             let left = collections.remove(0);
@@ -260,7 +310,7 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                 })
             });
 
-            CollectionRelation {
+            Ok(CollectionRelation {
                 symbols: self.variables.to_vec(),
                 tuples: tuples.map(move |(key, vals)| {
                     let mut v = key.clone();
@@ -269,7 +319,201 @@ impl<P: Implementable> Implementable for Aggregate<P> {
                     }
                     v
                 }),
+            })
+        }
+    }
+}
+
+impl Aggregate<Plan> {
+    /// Pushes this aggregation beneath the join it aggregates over,
+    /// provided one side of the join contributes nothing but group
+    /// keys (e.g. the names of the groups). The other side is then
+    /// aggregated before the join, s.t. only a single tuple per group
+    /// has to be joined, rather than every tuple contributing to it.
+    /// As all join symbols are group keys, each group of the
+    /// pre-aggregated side corresponds to exactly one group of the
+    /// original aggregation, which makes this valid for all
+    /// aggregation functions. Returns None, if the rewrite doesn't
+    /// apply.
+    pub fn push_beneath_join(&self) -> Option<Plan> {
+        // Aggregations are usually preceded by a projection onto the
+        // aggregated symbols.
+        let (projection, join) = match *self.plan {
+            Plan::Join(ref join) => (None, join),
+            Plan::Project(ref projection) => match *projection.plan {
+                Plan::Join(ref join) => (Some(&projection.variables), join),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        if !join
+            .variables
+            .iter()
+            .all(|sym| self.key_symbols.contains(sym))
+        {
+            return None;
+        }
+
+        let is_projected = |sym: &Var| projection.map_or(true, |variables| variables.contains(sym));
+
+        let sides = vec![
+            (&join.left_plan, &join.right_plan, true),
+            (&join.right_plan, &join.left_plan, false),
+        ];
+
+        for (inner, outer, inner_is_left) in sides {
+            let outer_symbols = outer.bound_symbols();
+            let inner_symbols = inner.bound_symbols();
+
+            let outer_contributes_keys = outer_symbols
+                .iter()
+                .filter(|sym| is_projected(sym))
+                .all(|sym| self.key_symbols.contains(sym));
+
+            let inner_binds_values = self
+                .aggregation_symbols
+                .iter()
+                .chain(self.with_symbols.iter())
+                .all(|sym| inner_symbols.contains(sym));
+
+            if !outer_contributes_keys || !inner_binds_values {
+                continue;
             }
+
+            let inner = match projection {
+                None => (**inner).clone(),
+                Some(variables) => Plan::Project(Project {
+                    variables: variables
+                        .iter()
+                        .filter(|sym| inner_symbols.contains(sym))
+                        .cloned()
+                        .collect(),
+                    plan: inner.clone(),
+                }),
+            };
+
+            let aggregated = Plan::Aggregate(Aggregate {
+                variables: self
+                    .variables
+                    .iter()
+                    .filter(|sym| {
+                        inner_symbols.contains(sym) || self.aggregation_symbols.contains(sym)
+                    })
+                    .cloned()
+                    .collect(),
+                plan: Box::new(inner),
+                aggregation_fns: self.aggregation_fns.clone(),
+                key_symbols: self
+                    .key_symbols
+                    .iter()
+                    .filter(|sym| inner_symbols.contains(sym))
+                    .cloned()
+                    .collect(),
+                aggregation_symbols: self.aggregation_symbols.clone(),
+                with_symbols: self.with_symbols.clone(),
+            });
+
+            let (left_plan, right_plan) = if inner_is_left {
+                (aggregated, (**outer).clone())
+            } else {
+                ((**outer).clone(), aggregated)
+            };
+
+            return Some(Plan::Project(Project {
+                variables: self.variables.clone(),
+                plan: Box::new(Plan::Join(Join {
+                    variables: join.variables.clone(),
+                    left_plan: Box::new(left_plan),
+                    right_plan: Box::new(right_plan),
+                })),
+            }));
         }
+
+        None
+    }
+}
+
+impl<P: Implementable> Aggregate<P> {
+    /// Retains the k tuples with the highest (`TOP`) or lowest
+    /// (`BOTTOM`) values per key. Ties are broken by the values of
+    /// with-symbols.
+    fn rank<'b, S: Scope<Timestamp = Time>>(
+        &self,
+        tuples: Collection<Iterative<'b, S, u64>, (Vec<Value>, Vec<Value>), isize>,
+        value_offset: usize,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let (k, highest) = match self.aggregation_fns[0] {
+            AggregationFn::TOP(k) => (k, true),
+            AggregationFn::BOTTOM(k) => (k, false),
+            ref other => {
+                return Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: format!("{:?} is not a ranking aggregation.", other),
+                });
+            }
+        };
+
+        let with_length = self.with_symbols.len();
+
+        // Each output variable is either taken from the key, or from
+        // the retained [value, with...] tuples.
+        let sources: Vec<(bool, usize)> = self
+            .variables
+            .iter()
+            .map(|sym| {
+                if let Some(offset) = self.key_symbols.iter().position(|v| v == sym) {
+                    Ok((true, offset))
+                } else if *sym == self.aggregation_symbols[0] {
+                    Ok((false, 0))
+                } else if let Some(offset) = self.with_symbols.iter().position(|v| v == sym) {
+                    Ok((false, 1 + offset))
+                } else {
+                    Err(Error {
+                        category: "df.error.category/incorrect",
+                        message: format!("Symbol {} is neither key, aggregated, nor with.", sym),
+                    })
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        let ranked = tuples
+            .map(move |(key, tuple)| {
+                // With-symbols are always the last elements in the
+                // value part of each tuple.
+                let mut v = vec![tuple[value_offset].clone()];
+                v.extend(tuple[tuple.len() - with_length..].iter().cloned());
+
+                (key, v)
+            })
+            .group(move |_key, vals, output| {
+                // Values are sorted, s.t. ranking amounts to taking
+                // them from either end.
+                if highest {
+                    for (v, _count) in vals.iter().rev().take(k) {
+                        output.push(((*v).clone(), 1));
+                    }
+                } else {
+                    for (v, _count) in vals.iter().take(k) {
+                        output.push(((*v).clone(), 1));
+                    }
+                }
+            });
+
+        Ok(CollectionRelation {
+            symbols: self.variables.to_vec(),
+            tuples: ranked.map(move |(key, v)| {
+                sources
+                    .iter()
+                    .map(|(from_key, offset)| {
+                        if *from_key {
+                            key[*offset].clone()
+                        } else {
+                            v[*offset].clone()
+                        }
+                    })
+                    .collect()
+            }),
+        })
     }
 }
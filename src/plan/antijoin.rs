@@ -7,14 +7,16 @@ use differential_dataflow::operators::Join;
 use differential_dataflow::operators::Threshold;
 
 use crate::plan::{ImplContext, Implementable};
-use crate::{CollectionRelation, Relation, Var, VariableMap};
+use crate::{CollectionRelation, Error, Relation, Time, Var, VariableMap};
 
 /// A plan stage anti-joining both its sources on the specified
-/// symbols. Throws if the sources are not union-compatible, i.e. bind
-/// all of the same symbols in the same order.
+/// symbols, i.e. retaining only those tuples of the left source that
+/// have no match in the right source. This is how Datalog-style
+/// negation (`not`, `not-join`) is expressed. Throws if any of the
+/// specified symbols isn't bound by both sources.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Antijoin<P1: Implementable, P2: Implementable> {
-    /// TODO
+    /// Symbols on which to match left and right tuples.
     pub variables: Vec<Var>,
     /// Plan for the left input.
     pub left_plan: Box<P1>,
@@ -35,18 +37,18 @@ impl<P1: Implementable, P2: Implementable> Implementable for Antijoin<P1, P2> {
         dependencies
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
         let left = self
             .left_plan
-            .implement(nested, local_arrangements, context);
+            .implement(nested, local_arrangements, context)?;
         let right = self
             .right_plan
-            .implement(nested, local_arrangements, context);
+            .implement(nested, local_arrangements, context)?;
 
         let symbols = self
             .variables
@@ -71,6 +73,6 @@ impl<P1: Implementable, P2: Implementable> Implementable for Antijoin<P1, P2> {
             )
             .map(|(key, tuple)| key.iter().cloned().chain(tuple.iter().cloned()).collect());
 
-        CollectionRelation { symbols, tuples }
+        Ok(CollectionRelation { symbols, tuples })
     }
 }
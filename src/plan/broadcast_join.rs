@@ -0,0 +1,121 @@
+//! Broadcast equijoin expression plan.
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Broadcast;
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+use timely::order::Product;
+
+use differential_dataflow::operators::arrange::{Arrange, Arranged, TraceAgent};
+use differential_dataflow::operators::JoinCore;
+use differential_dataflow::trace::implementations::ord::OrdValSpine;
+use differential_dataflow::AsCollection;
+
+use crate::binding::Binding;
+use crate::plan::{ImplContext, Implementable};
+use crate::{CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
+
+type LocalArrangement<'b, S> = Arranged<
+    Iterative<'b, S, u64>,
+    Vec<Value>,
+    Vec<Value>,
+    isize,
+    TraceAgent<
+        Vec<Value>,
+        Vec<Value>,
+        Product<Time, u64>,
+        isize,
+        OrdValSpine<Vec<Value>, Vec<Value>, Product<Time, u64>, isize>,
+    >,
+>;
+
+/// A plan stage joining two source relations on the specified
+/// symbols, by replicating the (presumably small) right input to all
+/// workers. The left input is joined against the replicated copy
+/// in-place, without being exchanged. Useful for joins against small
+/// lookup relations. Throws if any of the join symbols isn't bound by
+/// both sources.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct BroadcastJoin<P1: Implementable, P2: Implementable> {
+    /// TODO
+    pub variables: Vec<Var>,
+    /// Plan for the left input.
+    pub left_plan: Box<P1>,
+    /// Plan for the small, broadcasted right input.
+    pub right_plan: Box<P2>,
+}
+
+impl<P1: Implementable, P2: Implementable> Implementable for BroadcastJoin<P1, P2> {
+    fn dependencies(&self) -> Vec<String> {
+        let mut dependencies = self.left_plan.dependencies();
+        dependencies.append(&mut self.right_plan.dependencies());
+
+        dependencies
+    }
+
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        let mut bindings = self.left_plan.into_bindings()?;
+        bindings.append(&mut self.right_plan.into_bindings()?);
+
+        Ok(bindings)
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let left = self
+            .left_plan
+            .implement(nested, local_arrangements, context)?;
+        let right = self
+            .right_plan
+            .implement(nested, local_arrangements, context)?;
+
+        let symbols = self
+            .variables
+            .iter()
+            .cloned()
+            .chain(
+                left.symbols()
+                    .iter()
+                    .filter(|x| !self.variables.contains(x))
+                    .cloned(),
+            )
+            .chain(
+                right
+                    .symbols()
+                    .iter()
+                    .filter(|x| !self.variables.contains(x))
+                    .cloned(),
+            )
+            .collect();
+
+        // Both sides are arranged worker-locally. Every worker holds
+        // a full copy of the right input and whatever part of the
+        // left input it happens to have.
+        let left_arranged: LocalArrangement<'b, S> = left
+            .tuples_by_symbols(&self.variables)
+            .arrange_core(Pipeline, "BroadcastJoinLeft");
+
+        let right_arranged: LocalArrangement<'b, S> = right
+            .tuples_by_symbols(&self.variables)
+            .inner
+            .broadcast()
+            .as_collection()
+            .arrange_core(Pipeline, "BroadcastJoinRight");
+
+        let tuples = left_arranged.join_core(&right_arranged, |key, v1, v2| {
+            Some(
+                key.iter()
+                    .cloned()
+                    .chain(v1.iter().cloned())
+                    .chain(v2.iter().cloned())
+                    .collect(),
+            )
+        });
+
+        Ok(CollectionRelation { symbols, tuples })
+    }
+}
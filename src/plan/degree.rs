@@ -0,0 +1,76 @@
+//! Degree expression plan.
+
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+
+use differential_dataflow::operators::Count;
+
+use crate::plan::{attribute_not_found, content_id, ImplContext, Implementable};
+use crate::{Aid, CollectionRelation, Eid, Error, Time, Value, Var, VariableMap};
+
+/// A plan stage binding each entity of an attribute to the number of
+/// values it holds (or, in reverse, each value to the number of
+/// entities holding it), e.g. to compute the number of friends of
+/// every person. Counts are read off the count trace the attribute
+/// maintains anyway, instead of proposing all values and counting
+/// them. Entities without any values are not bound. Attributes with
+/// `Raw` semantics count duplicate values repeatedly.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct Degree {
+    /// Symbols to which the entity (or value) and its count are
+    /// bound
+    pub variables: Vec<Var>,
+    /// Attribute to count
+    pub attribute: Aid,
+    /// Count entities per value, rather than values per entity
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+impl Implementable for Degree {
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
+        vec![(
+            content_id(self),
+            "df.pattern/a".to_string(),
+            Value::Aid(self.attribute.to_string()),
+        )]
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        _local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let shutdown_handle = context.shutdown_handle();
+        let index = if self.reverse {
+            context.reverse_index(&self.attribute)
+        } else {
+            context.forward_index(&self.attribute)
+        };
+
+        let tuples = match index {
+            None => return Err(attribute_not_found(&self.attribute)),
+            Some(index) => {
+                let (counts, button) = index
+                    .count_trace
+                    .import_core(&nested.parent, &self.attribute);
+                shutdown_handle.add_button(button);
+
+                counts
+                    .count()
+                    .map(|(key, count)| vec![key, Value::Number(count as i64)])
+                    .enter(nested)
+            }
+        };
+
+        Ok(CollectionRelation {
+            symbols: self.variables.clone(),
+            tuples,
+        })
+    }
+}
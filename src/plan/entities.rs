@@ -0,0 +1,157 @@
+//! Entity change feed expression plan.
+
+use timely::dataflow::operators::{Concatenate, ToStream};
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+
+use differential_dataflow::operators::arrange::ArrangeBySelf;
+use differential_dataflow::operators::{JoinCore, Threshold};
+use differential_dataflow::AsCollection;
+
+use crate::plan::{attribute_not_found, ImplContext, Implementable};
+use crate::{Aid, CollectionRelation, Eid, Error, Relation, Time, Value, Var, VariableMap};
+
+/// A plan stage binding the entity, attribute, and value of every
+/// datom held by a set of entities, across all attributes. An
+/// interest in it receives a single stream of all changes to those
+/// entities, delivered per time like the results of any other
+/// relation, rather than one stream per attribute. Entities are given
+/// by id, by a plan binding the entity symbol (e.g. all entities of
+/// some type), or both, in which case only entities satisfying both
+/// are included. Without either, all entities are. Built-in (`df.`)
+/// attributes and attributes created after the stage has been
+/// implemented are not included.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct Entities<P: Implementable> {
+    /// Symbols to which the entity, attribute, and value of each
+    /// datom are bound
+    pub variables: Vec<Var>,
+    /// Entities to include, by id
+    #[serde(default)]
+    pub eids: Vec<Eid>,
+    /// Plan binding the entity symbol to the entities to include
+    #[serde(default)]
+    pub plan: Option<Box<P>>,
+}
+
+impl<P: Implementable> Implementable for Entities<P> {
+    fn dependencies(&self) -> Vec<String> {
+        self.plan
+            .as_ref()
+            .map_or_else(Vec::new, |plan| plan.dependencies())
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        if self.variables.len() != 3 {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
+                    "Entities binds exactly three symbols, not {}.",
+                    self.variables.len()
+                ),
+            });
+        }
+
+        let planned = match self.plan {
+            None => None,
+            Some(ref plan) => {
+                let relation = plan.implement(nested, local_arrangements, context)?;
+                let e = self.variables[0];
+
+                if !relation.symbols().contains(&e) {
+                    return Err(Error {
+                        category: "df.error.category/incorrect",
+                        message: format!("Entities plan does not bind symbol {}.", e),
+                    });
+                }
+
+                let offset = relation.offset(e);
+
+                Some(
+                    relation
+                        .tuples()
+                        .map(move |tuple| tuple[offset].clone())
+                        .distinct()
+                        .arrange_by_self(),
+                )
+            }
+        };
+
+        // the ids are introduced once, by the first worker
+        let listed = if self.eids.is_empty() {
+            None
+        } else {
+            let mut eids: Vec<Value> = if nested.index() == 0 {
+                self.eids.iter().map(|e| Value::Eid(*e)).collect()
+            } else {
+                Vec::new()
+            };
+            eids.sort();
+            eids.dedup();
+
+            Some(
+                eids.into_iter()
+                    .map(|e| (e, Time::default(), 1))
+                    .to_stream(&mut nested.parent.clone())
+                    .as_collection()
+                    .enter(nested)
+                    .arrange_by_self(),
+            )
+        };
+
+        let entities = match (planned, listed) {
+            (None, None) => None,
+            (Some(entities), None) | (None, Some(entities)) => Some(entities),
+            (Some(planned), Some(listed)) => Some(
+                planned
+                    .join_core(&listed, |e, _, _| Some(e.clone()))
+                    .arrange_by_self(),
+            ),
+        };
+
+        let mut attributes: Vec<Aid> = context
+            .attributes()
+            .into_iter()
+            .filter(|a| !a.starts_with("df."))
+            .collect();
+        attributes.sort();
+
+        let mut streams = Vec::with_capacity(attributes.len());
+        let shutdown_handle = context.shutdown_handle();
+
+        for a in attributes.into_iter() {
+            let (e_v, button) = match context.forward_index(&a) {
+                None => return Err(attribute_not_found(&a)),
+                Some(index) => index.propose_trace.import_core(&nested.parent, &a),
+            };
+            shutdown_handle.add_button(button);
+
+            let e_v = e_v.enter(nested);
+
+            let attribute = Value::Aid(a);
+
+            let datoms = match entities {
+                None => {
+                    e_v.as_collection(move |e, v| vec![e.clone(), attribute.clone(), v.clone()])
+                }
+                Some(ref entities) => entities.join_core(&e_v, move |e, _, v: &Value| {
+                    Some(vec![e.clone(), attribute.clone(), v.clone()])
+                }),
+            };
+
+            streams.push(datoms.inner);
+        }
+
+        let tuples = nested.concatenate(streams).as_collection();
+
+        Ok(CollectionRelation {
+            symbols: self.variables.clone(),
+            tuples,
+        })
+    }
+}
@@ -3,9 +3,13 @@
 use timely::dataflow::scopes::child::Iterative;
 use timely::dataflow::Scope;
 
+#[cfg(feature = "regex-predicates")]
+use regex::Regex;
+
+use crate::binding::ConstantBinding;
 pub use crate::binding::{BinaryPredicate as Predicate, BinaryPredicateBinding, Binding};
 use crate::plan::{ImplContext, Implementable};
-use crate::{CollectionRelation, Relation, Value, Var, VariableMap};
+use crate::{CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
 
 #[inline(always)]
 fn lt(a: &Value, b: &Value) -> bool {
@@ -31,6 +35,23 @@ fn eq(a: &Value, b: &Value) -> bool {
 fn neq(a: &Value, b: &Value) -> bool {
     a != b
 }
+#[inline(always)]
+fn starts_with(a: &Value, b: &Value) -> bool {
+    Predicate::STARTS_WITH.apply(a, b)
+}
+#[inline(always)]
+fn ends_with(a: &Value, b: &Value) -> bool {
+    Predicate::ENDS_WITH.apply(a, b)
+}
+#[inline(always)]
+fn contains(a: &Value, b: &Value) -> bool {
+    Predicate::CONTAINS.apply(a, b)
+}
+#[cfg(feature = "regex-predicates")]
+#[inline(always)]
+fn matches(a: &Value, b: &Value) -> bool {
+    Predicate::MATCHES.apply(a, b)
+}
 
 /// A plan stage filtering source tuples by the specified
 /// predicate. Frontends are responsible for ensuring that the source
@@ -52,26 +73,48 @@ impl<P: Implementable> Implementable for Filter<P> {
         self.plan.dependencies()
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
-        let mut bindings = self.plan.into_bindings();
-        let variables = self.variables.clone();
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        let mut bindings = self.plan.into_bindings()?;
 
-        unimplemented!();
-        // bindings.push(Binding::BinaryPredicate(BinaryPredicateBinding {
-        //     symbols: (variables[0], variables[1]),
-        //     predicate: self.predicate.clone(),
-        // }));
+        // Comparisons with constants can only be expressed as
+        // bindings if they are equalities.
+        let binding = match (&self.variables[..], &self.constants[..]) {
+            // Hector evaluates `(x, y)` as `y predicate x`.
+            ([left, right], _) => Binding::BinaryPredicate(BinaryPredicateBinding {
+                symbols: (*right, *left),
+                predicate: self.predicate.clone(),
+            }),
+            ([variable], [Some(value), None]) | ([variable], [None, Some(value)])
+                if self.predicate == Predicate::EQ =>
+            {
+                Binding::Constant(ConstantBinding {
+                    symbol: *variable,
+                    value: value.clone(),
+                })
+            }
+            _ => {
+                return Err(Error {
+                    category: "df.error.category/unsupported",
+                    message: format!(
+                        "{:?} comparisons with constants can't be implemented via Hector.",
+                        self.predicate
+                    ),
+                });
+            }
+        };
 
-        // bindings
+        bindings.push(binding);
+
+        Ok(bindings)
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
-        let rel = self.plan.implement(nested, local_arrangements, context);
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let rel = self.plan.implement(nested, local_arrangements, context)?;
 
         let key_offsets: Vec<usize> = self
             .variables
@@ -80,9 +123,12 @@ impl<P: Implementable> Implementable for Filter<P> {
                 rel.symbols()
                     .iter()
                     .position(|&v| *sym == v)
-                    .expect("Symbol not found.")
+                    .ok_or_else(|| Error {
+                        category: "df.error.category/incorrect",
+                        message: format!("Filter on unbound symbol {}.", sym),
+                    })
             })
-            .collect();
+            .collect::<Result<_, _>>()?;
 
         let binary_predicate = match self.predicate {
             Predicate::LT => lt,
@@ -91,29 +137,57 @@ impl<P: Implementable> Implementable for Filter<P> {
             Predicate::GTE => gte,
             Predicate::EQ => eq,
             Predicate::NEQ => neq,
+            Predicate::STARTS_WITH => starts_with,
+            Predicate::ENDS_WITH => ends_with,
+            Predicate::CONTAINS => contains,
+            #[cfg(feature = "regex-predicates")]
+            Predicate::MATCHES => matches,
         };
 
+        // Constant patterns are compiled only once.
+        #[cfg(feature = "regex-predicates")]
+        {
+            if let (Predicate::MATCHES, Some(Some(Value::String(pattern)))) =
+                (&self.predicate, self.constants.get(1))
+            {
+                let regex = Regex::new(pattern).map_err(|error| Error {
+                    category: "df.error.category/incorrect",
+                    message: format!("Invalid pattern for MATCHES: {}", error),
+                })?;
+
+                return Ok(CollectionRelation {
+                    symbols: rel.symbols().to_vec(),
+                    tuples: rel
+                        .tuples()
+                        .filter(move |tuple| match tuple[key_offsets[0]] {
+                            Value::String(ref s) => regex.is_match(s),
+                            _ => false,
+                        }),
+                });
+            }
+        }
+
         if let Some(constant) = self.constants[0].clone() {
-            CollectionRelation {
+            Ok(CollectionRelation {
                 symbols: rel.symbols().to_vec(),
                 tuples: rel
                     .tuples()
                     .filter(move |tuple| binary_predicate(&constant, &tuple[key_offsets[0]])),
-            }
+            })
         } else if let Some(constant) = self.constants[1].clone() {
-            CollectionRelation {
+            Ok(CollectionRelation {
                 symbols: rel.symbols().to_vec(),
                 tuples: rel
                     .tuples()
                     .filter(move |tuple| binary_predicate(&tuple[key_offsets[0]], &constant)),
-            }
+            })
         } else {
-            CollectionRelation {
+            Ok(CollectionRelation {
                 symbols: rel.symbols().to_vec(),
                 tuples: rel.tuples().filter(move |tuple| {
                     binary_predicate(&tuple[key_offsets[0]], &tuple[key_offsets[1]])
                 }),
-            }
+            })
         }
     }
 }
@@ -0,0 +1,68 @@
+//! Versioned wire format for plans.
+//!
+//! Plans are exchanged as JSON objects of the form
+//!
+//! ```json
+//! {"df.plan/version": 1, "df.plan/plan": {"MatchA": [0, ":name", 1]}}
+//! ```
+//!
+//! where the plan itself follows the serde layout of `Plan` and
+//! `df.plan/version` names the version of that layout. Only version
+//! `1` exists so far. Clients are expected to send versioned plans,
+//! plans given without the surrounding object are read as the
+//! current version, as sent by clients predating the format.
+
+extern crate serde_json;
+
+use crate::plan::Plan;
+use crate::Error;
+
+/// The version of the plan format produced by `encode_plan`.
+pub const PLAN_FORMAT_VERSION: u64 = 1;
+
+/// Serializes a plan using the current version of the wire format.
+pub fn encode_plan(plan: &Plan) -> Result<serde_json::Value, Error> {
+    let plan = serde_json::to_value(plan).map_err(|err| Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    })?;
+
+    Ok(serde_json::json!({
+        "df.plan/version": PLAN_FORMAT_VERSION,
+        "df.plan/plan": plan,
+    }))
+}
+
+/// Deserializes a plan given in any supported version of the wire
+/// format, or without a version.
+pub fn decode_plan(encoded: serde_json::Value) -> Result<Plan, Error> {
+    let plan = match encoded {
+        serde_json::Value::Object(mut envelope) => match envelope.remove("df.plan/version") {
+            None => serde_json::Value::Object(envelope),
+            Some(version) => {
+                let version = version.as_u64().ok_or_else(|| Error {
+                    category: "df.error.category/incorrect",
+                    message: "Plan format version must be a number.".to_string(),
+                })?;
+
+                if version != PLAN_FORMAT_VERSION {
+                    return Err(Error {
+                        category: "df.error.category/unsupported",
+                        message: format!("Plan format version {} is not supported.", version),
+                    });
+                }
+
+                envelope.remove("df.plan/plan").ok_or_else(|| Error {
+                    category: "df.error.category/incorrect",
+                    message: "Versioned plan is missing df.plan/plan.".to_string(),
+                })?
+            }
+        },
+        bare => bare,
+    };
+
+    serde_json::from_value(plan).map_err(|err| Error {
+        category: "df.error.category/incorrect",
+        message: err.to_string(),
+    })
+}
@@ -0,0 +1,126 @@
+//! Full-text search expression plan.
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+
+use differential_dataflow::operators::Count;
+use differential_dataflow::trace::{BatchReader, Cursor};
+use differential_dataflow::AsCollection;
+
+use crate::domain::tokenize;
+use crate::plan::{content_id, ImplContext, Implementable};
+use crate::{Aid, CollectionRelation, Eid, Error, Time, Value, Var, VariableMap};
+
+/// A plan stage binding all entities of an attribute with FullText
+/// semantics whose values contain every token of a query. The query
+/// is tokenized like the values are, i.e. matching is case
+/// insensitive and ignores punctuation. Instead of scanning every
+/// value, the attribute's inverted index is seeked for each token. An
+/// empty query matches nothing.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct FullText {
+    /// Symbol to which matching eids are bound
+    pub variables: Vec<Var>,
+    /// Attribute to search
+    pub attribute: Aid,
+    /// Text whose tokens must all be present
+    pub query: String,
+}
+
+impl Implementable for FullText {
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
+        let eid = content_id(self);
+
+        vec![
+            (
+                eid,
+                "df.pattern/a".to_string(),
+                Value::Aid(self.attribute.to_string()),
+            ),
+            (
+                eid,
+                "df.full-text/query".to_string(),
+                Value::String(self.query.to_string()),
+            ),
+        ]
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        _local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let mut tokens: Vec<Value> = tokenize(&self.query)
+            .into_iter()
+            .map(Value::String)
+            .collect();
+
+        tokens.sort();
+        tokens.dedup();
+
+        let required = tokens.len() as isize;
+
+        let shutdown_handle = context.shutdown_handle();
+        let (index, button) = match context.full_text_index(&self.attribute) {
+            None => {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!(
+                        "Attribute {} does not exist or has no full-text index.",
+                        self.attribute
+                    ),
+                });
+            }
+            Some(trace) => trace.import_core(&nested.parent, &self.attribute),
+        };
+        shutdown_handle.add_button(button);
+
+        let tuples = index
+            .stream
+            .unary(Pipeline, "FullText", move |_, _| {
+                move |input, output| {
+                    input.for_each(|time, data| {
+                        let mut session = output.session(&time);
+
+                        for batch in data.iter() {
+                            let mut cursor = batch.cursor();
+
+                            // Tokens are sorted, s.t. the cursor
+                            // only ever moves forward.
+                            for token in tokens.iter() {
+                                cursor.seek_key(batch, token);
+
+                                if cursor.get_key(batch) == Some(token) {
+                                    while let Some(e) = cursor.get_val(batch) {
+                                        cursor.map_times(batch, |t, diff| {
+                                            session.give((e.clone(), t.clone(), diff));
+                                        });
+                                        cursor.step_val(batch);
+                                    }
+                                }
+                            }
+                        }
+                    });
+                }
+            })
+            .as_collection()
+            // The inverted index is distinct, so an entity
+            // matches once per token it contains.
+            .count()
+            .filter(move |(_e, count)| *count == required)
+            .map(|(e, _count)| vec![e])
+            .enter(nested);
+
+        Ok(CollectionRelation {
+            symbols: self.variables.clone(),
+            tuples,
+        })
+    }
+}
@@ -1,9 +1,11 @@
 //! WCO expression plan, integrating the following work:
 //! https://github.com/frankmcsherry/differential-dataflow/tree/master/dogsdogsdogs
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
 use std::rc::Rc;
+use std::time::Duration;
 
 use timely::dataflow::channels::pact::{Exchange, Pipeline};
 use timely::dataflow::operators::Concatenate;
@@ -24,9 +26,14 @@ use differential_dataflow::{AsCollection, Collection, Data, Hashable};
 
 use crate::binding::{AsBinding, BinaryPredicate, Binding};
 use crate::binding::{BinaryPredicateBinding, ConstantBinding};
-use crate::plan::{ImplContext, Implementable};
+use crate::plan::{attribute_not_found, ImplContext, Implementable};
 use crate::timestamp::altneu::AltNeu;
-use crate::{CollectionRelation, LiveIndex, Value, Var, VariableMap};
+use crate::{CollectionIndex, CollectionRelation, IndexDirection, LiveIndex};
+use crate::{Error, Time, Value, Var, VariableMap};
+
+/// Delay after which operators that have hit their batch limit
+/// resume working off requests.
+const THROTTLE_DELAY: Duration = Duration::from_millis(1);
 
 /// A type capable of extending a stream of prefixes. Implementors of
 /// `PrefixExtension` provide types and methods for extending a
@@ -94,23 +101,22 @@ where
     }
 }
 
-impl<'a, S, V> IntoExtender<'a, S, V> for BinaryPredicateBinding
+impl<'a, S> IntoExtender<'a, S, Value> for BinaryPredicateBinding
 where
     S: Scope + ScopeParent + 'a,
     S::Timestamp: Lattice + Data + Timestamp,
-    V: Data + Hash,
 {
-    fn into_extender<P: Data + IndexNode<V>, B: AsBinding + std::fmt::Debug>(
+    fn into_extender<P: Data + IndexNode<Value>, B: AsBinding + std::fmt::Debug>(
         &self,
         prefix_symbols: &B,
     ) -> Vec<
         Box<
-            (dyn PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>, Prefix = P, Extension = V>
+            (dyn PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>, Prefix = P, Extension = Value>
                  + 'a),
         >,
     > {
         match direction(prefix_symbols, self.symbols) {
-            Err(_msg) => {
+            Err(_error) => {
                 // We won't panic here, this just means the predicate's symbols
                 // aren't sufficiently bound by the prefixes yet.
                 vec![]
@@ -144,24 +150,30 @@ enum Direction {
     Reverse(usize),
 }
 
-fn direction<P>(prefix_symbols: &P, extender_symbols: (Var, Var)) -> Result<Direction, &'static str>
+fn direction<P>(prefix_symbols: &P, extender_symbols: (Var, Var)) -> Result<Direction, Error>
 where
     P: AsBinding + std::fmt::Debug,
 {
     match AsBinding::binds(prefix_symbols, extender_symbols.0) {
         None => match AsBinding::binds(prefix_symbols, extender_symbols.1) {
-            None => {
-                println!(
+            None => Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
                     "Neither extender symbol {:?} bound by prefix {:?}.",
                     extender_symbols, prefix_symbols
-                );
-                Err("Neither extender symbol bound by prefix.")
-            }
+                ),
+            }),
             Some(offset) => Ok(Direction::Reverse(offset)),
         },
         Some(offset) => {
             match AsBinding::binds(prefix_symbols, extender_symbols.1) {
-                Some(_) => Err("Both extender symbols already bound by prefix."),
+                Some(_) => Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: format!(
+                        "Both extender symbols {:?} already bound by prefix {:?}.",
+                        extender_symbols, prefix_symbols
+                    ),
+                }),
                 None => {
                     // Prefix binds the first extender symbol, but not
                     // the second. Can use forward index.
@@ -183,61 +195,373 @@ impl IndexNode<Value> for Vec<Value> {
     }
 }
 
+/// Arranges a binary relation into forward and reverse indices, s.t.
+/// it can act as a delta source and extender just like an attribute.
+/// Published relations are read from their arrangement, others from
+/// their implementation in the current dataflow.
+fn index_derived<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+    nested: &Iterative<'b, S, u64>,
+    local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+    context: &mut I,
+    name: &str,
+) -> Result<
+    (
+        CollectionIndex<Value, Value, Time>,
+        CollectionIndex<Value, Value, Time>,
+    ),
+    Error,
+> {
+    if let Some(rule) = context.rule(name) {
+        let arity = rule.plan.variables().len();
+
+        if arity != 2 {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
+                    "Only binary relations can be bound, but {} binds {} symbols.",
+                    name, arity
+                ),
+            });
+        }
+    }
+
+    let shutdown_handle = context.shutdown_handle();
+
+    // any tuples that aren't pairs nonetheless are skipped
+    let pairs = match context.global_arrangement(name) {
+        Some(trace) => {
+            let (arranged, button) = trace.import_core(&nested.parent, name);
+            shutdown_handle.add_button(button);
+
+            arranged.flat_map_ref(|tuple, _| match tuple[..] {
+                [ref e, ref v] => Some((e.clone(), v.clone())),
+                _ => None,
+            })
+        }
+        None => match local_arrangements.get(name) {
+            None => {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!("{} is neither an attribute nor a relation.", name),
+                });
+            }
+            Some(relation) => relation.leave().flat_map(|tuple| match tuple[..] {
+                [ref e, ref v] => Some((e.clone(), v.clone())),
+                _ => None,
+            }),
+        },
+    };
+
+    let forward = CollectionIndex::index(name, &pairs);
+    let reverse = CollectionIndex::index(name, &pairs.map(|(e, v)| (v, e)));
+
+    Ok((forward, reverse))
+}
+
+/// Returns the eid -> value index of the named source, which is
+/// either an attribute or a derived relation.
+fn forward_index<'c, I: ImplContext>(
+    context: &'c mut I,
+    name: &str,
+) -> Result<&'c mut CollectionIndex<Value, Value, Time>, Error> {
+    if context.forward_index(name).is_some() {
+        Ok(context.forward_index(name).unwrap())
+    } else {
+        context
+            .derived_index(name, IndexDirection::Forward)
+            .ok_or_else(|| attribute_not_found(name))
+    }
+}
+
+/// Returns the value -> eid index of the named source, which is
+/// either an attribute or a derived relation.
+fn reverse_index<'c, I: ImplContext>(
+    context: &'c mut I,
+    name: &str,
+) -> Result<&'c mut CollectionIndex<Value, Value, Time>, Error> {
+    if context.reverse_index(name).is_some() {
+        Ok(context.reverse_index(name).unwrap())
+    } else {
+        context
+            .derived_index(name, IndexDirection::Reverse)
+            .ok_or_else(|| attribute_not_found(name))
+    }
+}
+
+/// Returns the names of the attributes or relations a binding reads
+/// from.
+fn binding_sources(binding: &Binding) -> Vec<String> {
+    match *binding {
+        Binding::Attribute(ref binding) => vec![binding.source_attribute.to_string()],
+        Binding::Not(ref binding) => binding_sources(&binding.binding),
+        Binding::Constant(_) | Binding::BinaryPredicate(_) => Vec::new(),
+    }
+}
+
+impl Hector {
+    /// Returns all symbols that have to be bound, starting with the
+    /// requested variables. Symbols not requested, such as the ones
+    /// introduced when lowering constant patterns (e.g. the entity of
+    /// a `MatchEA`), must be bound nonetheless, for the constraints
+    /// on them to apply.
+    fn symbols(&self) -> Vec<Var> {
+        let mut symbols = self.variables.clone();
+
+        for binding in self.bindings.iter() {
+            let bound = match binding {
+                Binding::Attribute(binding) => vec![binding.symbols.0, binding.symbols.1],
+                Binding::Constant(binding) => vec![binding.symbol],
+                _ => Vec::new(),
+            };
+
+            for symbol in bound {
+                if !symbols.contains(&symbol) {
+                    symbols.push(symbol);
+                }
+            }
+        }
+
+        symbols
+    }
+
+    /// Checks that every symbol can be bound, no matter which
+    /// attribute acts as the delta source, s.t. implementing the plan
+    /// doesn't fail halfway. Reports the first symbol that can't be
+    /// bound, i.e. that is underconstrained.
+    pub fn validate(&self) -> Result<(), Error> {
+        let incorrect = |message: String| {
+            Err(Error {
+                category: "df.error.category/incorrect",
+                message,
+            })
+        };
+
+        if self.bindings.is_empty() {
+            return incorrect("No bindings passed.".to_string());
+        } else if self.variables.is_empty() {
+            return incorrect("No symbols requested.".to_string());
+        }
+
+        let sources: Vec<(usize, (Var, Var))> = self
+            .bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, binding)| match binding {
+                Binding::Attribute(binding) => Some((idx, binding.symbols)),
+                _ => None,
+            })
+            .collect();
+
+        if sources.is_empty() {
+            return incorrect("At least one attribute must be bound.".to_string());
+        }
+
+        let symbols = self.symbols();
+
+        for (delta_idx, (e, v)) in sources {
+            let mut bound = vec![e, v];
+            let mut remaining: Vec<Var> = symbols
+                .iter()
+                .cloned()
+                .filter(|x| !bound.contains(x))
+                .collect();
+
+            while !remaining.is_empty() {
+                let position = remaining
+                    .iter()
+                    .position(|target| self.proposes(delta_idx, &bound, *target));
+
+                match position {
+                    None => {
+                        return incorrect(format!(
+                            "Variable {} is underconstrained, no binding can bind it given {:?}.",
+                            remaining[0], bound
+                        ));
+                    }
+                    Some(position) => bound.push(remaining.remove(position)),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns true iff any binding other than the delta source can
+    /// propose values for the target, given the bound symbols.
+    fn proposes(&self, delta_idx: usize, bound: &[Var], target: Var) -> bool {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter(|(idx, _binding)| *idx != delta_idx)
+            .any(|(_idx, binding)| match binding {
+                Binding::Constant(binding) => binding.symbol == target,
+                Binding::Attribute(binding) => {
+                    let (e, v) = binding.symbols;
+
+                    (e == target && bound.contains(&v)) || (v == target && bound.contains(&e))
+                }
+                _ => false,
+            })
+    }
+
+    /// Determines the order in which the remaining variables are
+    /// bound, given the symbols already bound by the delta source at
+    /// the specified index. At each step, we pick the variable with
+    /// the cheapest proposal among those that can be proposed at
+    /// all. Constants are free, attributes cost their estimated
+    /// cardinality. Ties (and variables that can't be proposed by
+    /// anything) are left in the requested order.
+    fn plan_order<I: ImplContext>(&self, delta_idx: usize, bound: &[Var], context: &I) -> Vec<Var> {
+        let mut bound = bound.to_vec();
+        let mut remaining: Vec<Var> = self
+            .symbols()
+            .into_iter()
+            .filter(|x| AsBinding::binds(&bound, *x).is_none())
+            .collect();
+
+        let mut order = Vec::with_capacity(remaining.len());
+
+        while !remaining.is_empty() {
+            let mut cheapest: Option<(usize, usize)> = None;
+
+            for (position, target) in remaining.iter().enumerate() {
+                let cost = self
+                    .bindings
+                    .iter()
+                    .enumerate()
+                    .filter(|(idx, _binding)| *idx != delta_idx)
+                    .flat_map(|(_idx, binding)| match binding {
+                        Binding::Constant(binding) if binding.symbol == *target => Some(0),
+                        Binding::Attribute(binding) => {
+                            let (e, v) = binding.symbols;
+                            let proposes = (e == *target && AsBinding::binds(&bound, v).is_some())
+                                || (v == *target && AsBinding::binds(&bound, e).is_some());
+
+                            if proposes {
+                                Some(
+                                    context
+                                        .cardinality(&binding.source_attribute)
+                                        .unwrap_or(std::usize::MAX),
+                                )
+                            } else {
+                                None
+                            }
+                        }
+                        _ => None,
+                    })
+                    .min();
+
+                if let Some(cost) = cost {
+                    match cheapest {
+                        Some((_, min_cost)) if min_cost <= cost => {}
+                        _ => cheapest = Some((position, cost)),
+                    }
+                }
+            }
+
+            // Nothing can be proposed, which will fail loudly later on.
+            let position = cheapest.map(|(position, _cost)| position).unwrap_or(0);
+            let next = remaining.remove(position);
+
+            bound.push(next);
+            order.push(next);
+        }
+
+        order
+    }
+}
+
 impl Implementable for Hector {
     fn dependencies(&self) -> Vec<String> {
-        Vec::new()
+        // Bindings don't tell attributes and relations apart, so all
+        // bound names are reported.
+        let mut names: Vec<String> = self.bindings.iter().flat_map(binding_sources).collect();
+        names.sort();
+        names.dedup();
+        names
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
-        self.bindings.clone()
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        Ok(self.bindings.clone())
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
-        _local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        // Bindings might refer to derived relations rather than
+        // attributes, those we have to index ourselves (unless an
+        // earlier dataflow already did).
+        for binding in self.bindings.iter() {
+            if let Binding::Attribute(binding) = binding {
+                let name = &binding.source_attribute;
+
+                if context.forward_index(name).is_none()
+                    && context
+                        .derived_index(name, IndexDirection::Forward)
+                        .is_none()
+                {
+                    let (forward, reverse) =
+                        index_derived(nested, local_arrangements, context, name)?;
+                    context.cache_derived_index(name, IndexDirection::Forward, forward);
+                    context.cache_derived_index(name, IndexDirection::Reverse, reverse);
+                }
+            }
+        }
+
+        let batch_limit = context.hector_batch_limit().unwrap_or(std::usize::MAX);
+        let shutdown_handle = context.shutdown_handle();
+
         if self.bindings.is_empty() {
-            panic!("No bindings passed.");
+            Err(Error {
+                category: "df.error.category/incorrect",
+                message: "No bindings passed.".to_string(),
+            })
         } else if self.variables.is_empty() {
-            panic!("No symbols requested.");
+            Err(Error {
+                category: "df.error.category/incorrect",
+                message: "No symbols requested.".to_string(),
+            })
         } else if self.bindings.len() == 1 {
             // With only a single binding given, we don't want to do
             // anything fancy (provided the binding is sourceable).
 
             match self.bindings.first().unwrap() {
                 Binding::Attribute(binding) => {
-                    let tuples = context
-                        .forward_index(&binding.source_attribute)
-                        .unwrap()
+                    let (validations, button) = forward_index(context, &binding.source_attribute)?
                         .validate_trace
-                        .import(&nested.parent)
+                        .import_core(&nested.parent, &binding.source_attribute);
+                    shutdown_handle.add_button(button);
+
+                    let tuples = validations
                         .enter(&nested)
                         .as_collection(|(e, v), ()| vec![e.clone(), v.clone()]);
 
-                    CollectionRelation {
-                        symbols: vec![],
+                    Ok(CollectionRelation {
+                        symbols: vec![binding.symbols.0, binding.symbols.1],
                         tuples,
-                    }
-                }
-                _ => {
-                    panic!("Passed a single, non-sourceable binding.");
+                    })
                 }
+                _ => Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: "Passed a single, non-sourceable binding.".to_string(),
+                }),
             }
         } else {
             // In order to avoid delta pipelines looking at each
             // other's data in naughty ways, we need to run them all
             // inside a scope with lexicographic times.
 
-            let joined = nested.scoped::<AltNeu<Product<u64,u64>>, _, _>("AltNeu", |inner| {
+            let joined = nested.scoped::<AltNeu<Product<Time, u64>>, _, _>("AltNeu", |inner| -> Result<_, Error> {
 
                 let scope = inner.clone();
 
-                // @TODO
-                // We need to determine an order on the attributes
-                // that ensures that each is bound by preceeding
-                // attributes. For now, we will take the requested order.
+                // Each delta pipeline binds the remaining variables
+                // in the order determined by `plan_order`, s.t. every
+                // variable is bound by preceding ones and selective
+                // attributes extend first.
 
                 // We cache aggressively, to avoid importing and
                 // wrapping things more than once.
@@ -255,7 +579,7 @@ impl Implementable for Hector {
 
                 // @TODO only do it for distinct attributes
                 let changes = self.bindings.iter().enumerate()
-                    .flat_map(|(idx, delta_binding)| match delta_binding {
+                    .map(|(idx, delta_binding)| -> Result<Option<_>, Error> { match delta_binding {
                         Binding::Attribute(delta_binding) => {
 
                             let mut prefix_symbols = Vec::with_capacity(self.variables.len());
@@ -279,17 +603,19 @@ impl Implementable for Hector {
                                     let match_v = constant_binding.value.clone();
 
                                     // Guaranteed to intersect with offset zero at this point.
-                                    match direction(&prefix_symbols, delta_binding.symbols).unwrap() {
+                                    match direction(&prefix_symbols, delta_binding.symbols)? {
                                         Direction::Forward(_) => {
                                             prefix_symbols.push(delta_binding.symbols.1);
 
                                             // @TODO use wrapper cache here as well
-                                            forward_import.entry(&delta_binding.source_attribute)
-                                                .or_insert_with(|| {
-                                                    context.forward_index(&delta_binding.source_attribute).unwrap()
-                                                        .import(&scope.parent.parent)
+                                            match forward_import.entry(&delta_binding.source_attribute) {
+                                                Entry::Occupied(entry) => entry.into_mut(),
+                                                Entry::Vacant(entry) => entry.insert(
+                                                    forward_index(context, &delta_binding.source_attribute)?
+                                                        .import(&scope.parent.parent, &shutdown_handle)
                                                         .enter(&scope.parent)
-                                                })
+                                                ),
+                                            }
                                                 .propose_trace
                                                 .filter(move |e,_v| *e == match_v)
                                                 .enter(&scope)
@@ -299,12 +625,14 @@ impl Implementable for Hector {
                                             prefix_symbols.push(delta_binding.symbols.0);
 
                                             // @TODO use wrapper cache here as well
-                                            reverse_import.entry(&delta_binding.source_attribute)
-                                                .or_insert_with(|| {
-                                                    context.reverse_index(&delta_binding.source_attribute).unwrap()
-                                                        .import(&scope.parent.parent)
+                                            match reverse_import.entry(&delta_binding.source_attribute) {
+                                                Entry::Occupied(entry) => entry.into_mut(),
+                                                Entry::Vacant(entry) => entry.insert(
+                                                    reverse_index(context, &delta_binding.source_attribute)?
+                                                        .import(&scope.parent.parent, &shutdown_handle)
                                                         .enter(&scope.parent)
-                                                })
+                                                ),
+                                            }
                                                 .propose_trace
                                                 .filter(move |v,_e| *v == match_v)
                                                 .enter(&scope)
@@ -317,22 +645,43 @@ impl Implementable for Hector {
                                 prefix_symbols.push(delta_binding.symbols.1);
 
                                 // @TODO use wrapper cache here as well
-                                forward_import.entry(&delta_binding.source_attribute)
-                                    .or_insert_with(|| {
-                                        context.forward_index(&delta_binding.source_attribute).unwrap()
-                                            .import(&scope.parent.parent)
+                                match forward_import.entry(&delta_binding.source_attribute) {
+                                    Entry::Occupied(entry) => entry.into_mut(),
+                                    Entry::Vacant(entry) => entry.insert(
+                                        forward_index(context, &delta_binding.source_attribute)?
+                                            .import(&scope.parent.parent, &shutdown_handle)
                                             .enter(&scope.parent)
-                                    })
+                                    ),
+                                }
                                     .validate_trace
                                     .enter(&scope)
                                     .as_collection(|(e,v),()| vec![e.clone(), v.clone()])
                             };
 
-                            for target in self.variables.iter() {
+                            // Constants might fix both symbols of the
+                            // delta source (e.g. [42 :name "Dipper"]), in
+                            // which case only one of them was matched above.
+                            for binding in self.bindings.iter() {
+                                if let Binding::Constant(constant) = binding {
+                                    if let Some(offset) = AsBinding::binds(&prefix_symbols, constant.symbol) {
+                                        let value = constant.value.clone();
+                                        source = source.filter(move |tuple| tuple[offset] == value);
+                                    }
+                                }
+                            }
+
+                            let order = self.plan_order(idx, &prefix_symbols, &*context);
+
+                            for target in order.iter() {
                                 match AsBinding::binds(&prefix_symbols, *target) {
                                     Some(_) => { /* already bound */ continue },
                                     None => {
-                                        let mut extenders: Vec<Box<dyn PrefixExtender<Child<'_, Iterative<'b, S, u64>, AltNeu<Product<u64, u64>>>, Prefix=Vec<Value>, Extension=_>>> = vec![];
+                                        let mut extenders: Vec<Box<dyn PrefixExtender<Child<'_, Iterative<'b, S, u64>, AltNeu<Product<Time, u64>>>, Prefix=Vec<Value>, Extension=_>>> = vec![];
+
+                                        // Whether the target is fully determined
+                                        // by a constant, in which case there is
+                                        // no point in counting proposals.
+                                        let mut determined = false;
 
                                         for (other_idx, other) in self.bindings.iter().enumerate() {
 
@@ -347,15 +696,23 @@ impl Implementable for Hector {
                                             if other.binds(*target).is_none() { continue; }
 
                                             match other {
-                                                Binding::Not(other) => {
-                                                    unimplemented!();
-                                                    // extenders.push(Box::new(AntijoinExtender {
+                                                Binding::Not(_other) => {
+                                                    // @TODO extenders.push(Box::new(AntijoinExtender {
                                                     //     phantom: std::marker::PhantomData,
                                                     //     extender: other.binding.into_extender(),
                                                     // }));
+                                                    return Err(Error {
+                                                        category: "df.error.category/unsupported",
+                                                        message: "Negated bindings are not supported by Hector.".to_string(),
+                                                    });
                                                 }
                                                 Binding::Constant(other) => {
-                                                    extenders.append(&mut other.into_extender(&prefix_symbols));
+                                                    // Constants go first, s.t. they can
+                                                    // determine the target on their own.
+                                                    let mut constant = other.into_extender(&prefix_symbols);
+                                                    constant.append(&mut extenders);
+                                                    extenders = constant;
+                                                    determined = true;
                                                 }
                                                 Binding::BinaryPredicate(other) => {
                                                     extenders.append(&mut other.into_extender(&prefix_symbols));
@@ -367,76 +724,79 @@ impl Implementable for Hector {
                                                         (true, &mut forward_neu, &mut reverse_neu)
                                                     };
 
-                                                    match direction(&prefix_symbols, other.symbols) {
-                                                        Err(msg) => panic!(msg),
-                                                        Ok(direction) => match direction {
-                                                            Direction::Forward(offset) => {
-                                                                if !forward_cache.contains_key(&other.source_attribute) {
-                                                                    let imported = forward_import.entry(&other.source_attribute)
-                                                                        .or_insert_with(|| {
-                                                                            context.forward_index(&other.source_attribute).unwrap()
-                                                                                .import(&scope.parent.parent)
-                                                                                .enter(&scope.parent)
-                                                                        });
-
-                                                                    let neu1 = is_neu;
-                                                                    let neu2 = is_neu;
-                                                                    let neu3 = is_neu;
-
-                                                                    forward_cache.insert(
-                                                                        other.source_attribute.clone(),
-                                                                        imported.enter_at(
-                                                                            &scope,
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu1 },
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu2 },
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu3 },
-                                                                        )
-                                                                    );
-                                                                }
-
-                                                                let forward = forward_cache.get(&other.source_attribute)
-                                                                    .expect("Source attribute not found in forward cache.");
-
-                                                                extenders.push(Box::new(CollectionExtender {
-                                                                    phantom: std::marker::PhantomData,
-                                                                    indices: forward.clone(),
-                                                                    key_selector: Rc::new(move |tuple: &Vec<Value>| tuple.index(offset)),
-                                                                }));
-                                                            },
-                                                            Direction::Reverse(offset) => {
-                                                                if !reverse_cache.contains_key(&other.source_attribute) {
-                                                                    let imported = reverse_import.entry(&other.source_attribute)
-                                                                        .or_insert_with(|| {
-                                                                            context.reverse_index(&other.source_attribute).unwrap()
-                                                                                .import(&scope.parent.parent)
-                                                                                .enter(&scope.parent)
-                                                                        });
-
-                                                                    let neu1 = is_neu;
-                                                                    let neu2 = is_neu;
-                                                                    let neu3 = is_neu;
-
-                                                                    reverse_cache.insert(
-                                                                        other.source_attribute.clone(),
-                                                                        imported.enter_at(
-                                                                            &scope,
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu1 },
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu2 },
-                                                                            move |_,_,t| AltNeu { time: *t, neu: neu3 },
-                                                                        )
-                                                                    );
-                                                                }
-
-                                                                let reverse = reverse_cache.get(&other.source_attribute)
-                                                                    .expect("Source attribute not found in reverse cache.");
-
-                                                                extenders.push(Box::new(CollectionExtender {
-                                                                    phantom: std::marker::PhantomData,
-                                                                    indices: reverse.clone(),
-                                                                    key_selector: Rc::new(move |tuple: &Vec<Value>| tuple.index(offset)),
-                                                                }));
-                                                            },
-                                                        }
+                                                    match direction(&prefix_symbols, other.symbols)? {
+                                                        Direction::Forward(offset) => {
+                                                            if !forward_cache.contains_key(&other.source_attribute) {
+                                                                let imported = match forward_import.entry(&other.source_attribute) {
+                                                                    Entry::Occupied(entry) => entry.into_mut(),
+                                                                    Entry::Vacant(entry) => entry.insert(
+                                                                        forward_index(context, &other.source_attribute)?
+                                                                            .import(&scope.parent.parent, &shutdown_handle)
+                                                                            .enter(&scope.parent)
+                                                                    ),
+                                                                };
+
+                                                                let neu1 = is_neu;
+                                                                let neu2 = is_neu;
+                                                                let neu3 = is_neu;
+
+                                                                forward_cache.insert(
+                                                                    other.source_attribute.clone(),
+                                                                    imported.enter_at(
+                                                                        &scope,
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu1 },
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu2 },
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu3 },
+                                                                    )
+                                                                );
+                                                            }
+
+                                                            let forward = forward_cache.get(&other.source_attribute)
+                                                                .expect("Source attribute not found in forward cache.");
+
+                                                            extenders.push(Box::new(CollectionExtender {
+                                                                phantom: std::marker::PhantomData,
+                                                                indices: forward.clone(),
+                                                                key_selector: Rc::new(move |tuple: &Vec<Value>| tuple.index(offset)),
+                                                                batch_limit,
+                                                            }));
+                                                        },
+                                                        Direction::Reverse(offset) => {
+                                                            if !reverse_cache.contains_key(&other.source_attribute) {
+                                                                let imported = match reverse_import.entry(&other.source_attribute) {
+                                                                    Entry::Occupied(entry) => entry.into_mut(),
+                                                                    Entry::Vacant(entry) => entry.insert(
+                                                                        reverse_index(context, &other.source_attribute)?
+                                                                            .import(&scope.parent.parent, &shutdown_handle)
+                                                                            .enter(&scope.parent)
+                                                                    ),
+                                                                };
+
+                                                                let neu1 = is_neu;
+                                                                let neu2 = is_neu;
+                                                                let neu3 = is_neu;
+
+                                                                reverse_cache.insert(
+                                                                    other.source_attribute.clone(),
+                                                                    imported.enter_at(
+                                                                        &scope,
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu1 },
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu2 },
+                                                                        move |_,_,t| AltNeu { time: *t, neu: neu3 },
+                                                                    )
+                                                                );
+                                                            }
+
+                                                            let reverse = reverse_cache.get(&other.source_attribute)
+                                                                .expect("Source attribute not found in reverse cache.");
+
+                                                            extenders.push(Box::new(CollectionExtender {
+                                                                phantom: std::marker::PhantomData,
+                                                                indices: reverse.clone(),
+                                                                key_selector: Rc::new(move |tuple: &Vec<Value>| tuple.index(offset)),
+                                                                batch_limit,
+                                                            }));
+                                                        },
                                                     }
                                                 }
                                             }
@@ -445,8 +805,13 @@ impl Implementable for Hector {
                                         prefix_symbols.push(*target);
 
                                         // @TODO impl ProposeExtensionMethod for Arranged
-                                        source = source
-                                            .extend(&mut extenders[..])
+                                        let extended = if determined {
+                                            source.extend_determined(&mut extenders[..])
+                                        } else {
+                                            source.extend(&mut extenders[..])
+                                        };
+
+                                        source = extended
                                             .map(|(tuple,v)| {
                                                 let mut out = Vec::with_capacity(tuple.len() + 1);
                                                 out.append(&mut tuple.clone());
@@ -459,28 +824,31 @@ impl Implementable for Hector {
                             }
 
                             if self.variables == prefix_symbols {
-                                Some(source.inner)
+                                Ok(Some(source.inner))
                             } else {
                                 let target_variables = self.variables.clone();
-                                Some(source
+                                Ok(Some(source
                                      .map(move |tuple| {
                                          target_variables.iter()
                                              .map(|x| tuple.index(AsBinding::binds(&prefix_symbols, *x).unwrap()))
                                              .collect()
                                      })
-                                     .inner)
+                                     .inner))
                             }
                         }
-                        _ => None
-                    });
+                        _ => Ok(None)
+                    }})
+                    .collect::<Result<Vec<_>, Error>>()?
+                    .into_iter()
+                    .flatten();
 
-                inner.concatenate(changes).as_collection().leave()
-            });
+                Ok(inner.concatenate(changes).as_collection().leave())
+            })?;
 
-            CollectionRelation {
-                symbols: vec![],
+            Ok(CollectionRelation {
+                symbols: self.variables.clone(),
                 tuples: joined.distinct(),
-            }
+            })
         }
     }
 }
@@ -502,6 +870,14 @@ trait ProposeExtensionMethod<'a, S: Scope + ScopeParent, P: Data + Ord> {
                  + 'a),
         >],
     ) -> Collection<Child<'a, S, AltNeu<S::Timestamp>>, (P, E)>;
+
+    fn extend_determined<E: Data + Ord>(
+        &self,
+        extenders: &mut [Box<
+            (dyn PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>, Prefix = P, Extension = E>
+                 + 'a),
+        >],
+    ) -> Collection<Child<'a, S, AltNeu<S::Timestamp>>, (P, E)>;
 }
 
 impl<'a, S: Scope + ScopeParent, P: Data + Ord> ProposeExtensionMethod<'a, S, P>
@@ -548,6 +924,27 @@ impl<'a, S: Scope + ScopeParent, P: Data + Ord> ProposeExtensionMethod<'a, S, P>
             self.scope().concatenate(results).as_collection()
         }
     }
+
+    /// Extends prefixes by the proposals of the first extender,
+    /// which must not depend on the prefix (e.g. a constant), and
+    /// validates them against all others. Counting is skipped
+    /// altogether, as is proposing from (potentially large) traces.
+    fn extend_determined<E: Data + Ord>(
+        &self,
+        extenders: &mut [Box<
+            (dyn PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>, Prefix = P, Extension = E>
+                 + 'a),
+        >],
+    ) -> Collection<Child<'a, S, AltNeu<S::Timestamp>>, (P, E)> {
+        let (first, others) = extenders.split_first_mut().expect("No extenders passed.");
+
+        let mut extensions = first.propose(self);
+        for other in others.iter_mut() {
+            extensions = other.validate(&extensions);
+        }
+
+        extensions
+    }
 }
 
 struct ConstantExtender<P, V>
@@ -608,16 +1005,15 @@ where
     direction: Direction,
 }
 
-impl<'a, S, V, P> PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>>
-    for BinaryPredicateExtender<P, V>
+impl<'a, S, P> PrefixExtender<Child<'a, S, AltNeu<S::Timestamp>>>
+    for BinaryPredicateExtender<P, Value>
 where
     S: Scope + ScopeParent,
     S::Timestamp: Lattice + Data,
-    V: Data + Hash,
-    P: Data + IndexNode<V>,
+    P: Data + IndexNode<Value>,
 {
     type Prefix = P;
-    type Extension = V;
+    type Extension = Value;
 
     fn count(
         &mut self,
@@ -655,6 +1051,12 @@ where
                         .filter(move |(prefix, extension)| *extension == prefix.index(offset)),
                     NEQ => extensions
                         .filter(move |(prefix, extension)| *extension != prefix.index(offset)),
+                    ref predicate => {
+                        let predicate = predicate.clone();
+                        extensions.filter(move |(prefix, extension)| {
+                            predicate.apply(&prefix.index(offset), extension)
+                        })
+                    }
                 }
             }
             Direction::Forward(offset) => {
@@ -671,6 +1073,12 @@ where
                         .filter(move |(prefix, extension)| *extension == prefix.index(offset)),
                     NEQ => extensions
                         .filter(move |(prefix, extension)| *extension != prefix.index(offset)),
+                    ref predicate => {
+                        let predicate = predicate.clone();
+                        extensions.filter(move |(prefix, extension)| {
+                            predicate.apply(extension, &prefix.index(offset))
+                        })
+                    }
                 }
             }
         }
@@ -691,6 +1099,9 @@ where
     phantom: std::marker::PhantomData<P>,
     indices: LiveIndex<Child<'a, S, AltNeu<S::Timestamp>>, K, V, TrCount, TrPropose, TrValidate>,
     key_selector: Rc<F>,
+    /// Maximum number of prefixes worked off by each operator per
+    /// activation.
+    batch_limit: usize,
 }
 
 impl<'a, S, K, V, P, F, TrCount, TrPropose, TrValidate>
@@ -727,6 +1138,9 @@ where
         let counts = &self.indices.count_trace;
         let mut counts_trace = Some(counts.trace.clone());
 
+        let scope = prefixes.scope();
+        let batch_limit = self.batch_limit;
+
         let mut stash = HashMap::new();
         let logic1 = self.key_selector.clone();
         let logic2 = self.key_selector.clone();
@@ -743,95 +1157,122 @@ where
         // TODO: This should be a custom operator with no connection from the second input to the output.
         prefixes
             .inner
-            .binary_frontier(&counts.stream, exchange, Pipeline, "Count", move |_, _| {
-                move |input1, input2, output| {
-                    // drain the first input, stashing requests.
-                    input1.for_each(|capability, data| {
-                        data.swap(&mut buffer1);
-                        stash
-                            .entry(capability.retain())
-                            .or_insert_with(Vec::new)
-                            .extend(buffer1.drain(..))
-                    });
-
-                    // advance the `distinguish_since` frontier to allow all merges.
-                    input2.for_each(|_, batches| {
-                        batches.swap(&mut buffer2);
-                        for batch in buffer2.drain(..) {
-                            if let Some(ref mut trace) = counts_trace {
-                                trace.distinguish_since(batch.upper());
+            .binary_frontier(
+                &counts.stream,
+                exchange,
+                Pipeline,
+                "Count",
+                move |_, info| {
+                    let activator = scope.activator_for(&info.address[..]);
+
+                    move |input1, input2, output| {
+                        // drain the first input, stashing requests.
+                        input1.for_each(|capability, data| {
+                            data.swap(&mut buffer1);
+                            stash
+                                .entry(capability.retain())
+                                .or_insert_with(Vec::new)
+                                .extend(buffer1.drain(..));
+                        });
+
+                        // at most this many requests are worked off per activation.
+                        let mut budget = batch_limit;
+
+                        // advance the `distinguish_since` frontier to allow all merges.
+                        input2.for_each(|_, batches| {
+                            batches.swap(&mut buffer2);
+                            for batch in buffer2.drain(..) {
+                                if let Some(ref mut trace) = counts_trace {
+                                    trace.distinguish_since(batch.upper());
+                                }
                             }
-                        }
-                    });
-
-                    if let Some(ref mut trace) = counts_trace {
-                        for (capability, prefixes) in stash.iter_mut() {
-                            // defer requests at incomplete times.
-                            // NOTE: not all updates may be at complete times, but if this test fails then none of them are.
-                            if !input2.frontier.less_equal(capability.time()) {
-                                let mut session = output.session(capability);
-
-                                // sort requests for in-order cursor traversal. could consolidate?
-                                prefixes.sort_by(|x, y| logic2(&(x.0).0).cmp(&logic2(&(y.0).0)));
-
-                                let (mut cursor, storage) = trace.cursor();
-
-                                for &mut (
-                                    (ref prefix, old_count, old_index),
-                                    ref time,
-                                    ref mut diff,
-                                ) in prefixes.iter_mut()
-                                {
-                                    if !input2.frontier.less_equal(time) {
-                                        let key = logic2(prefix);
-                                        cursor.seek_key(&storage, &key);
-                                        if cursor.get_key(&storage) == Some(&key) {
-                                            let mut count = 0;
-                                            cursor.map_times(&storage, |t, d| {
-                                                if t.less_equal(time) {
-                                                    count += d;
-                                                }
-                                            });
-                                            // assert!(count >= 0);
-                                            let count = count as usize;
-                                            if count > 0 {
-                                                if count < old_count {
-                                                    session.give((
-                                                        (prefix.clone(), count, index),
-                                                        time.clone(),
-                                                        *diff,
-                                                    ));
-                                                } else {
-                                                    session.give((
-                                                        (prefix.clone(), old_count, old_index),
-                                                        time.clone(),
-                                                        *diff,
-                                                    ));
+                        });
+
+                        if let Some(ref mut trace) = counts_trace {
+                            for (capability, prefixes) in stash.iter_mut() {
+                                if budget == 0 {
+                                    break;
+                                }
+
+                                // defer requests at incomplete times.
+                                // NOTE: not all updates may be at complete times, but if this test fails then none of them are.
+                                if !input2.frontier.less_equal(capability.time()) {
+                                    let mut session = output.session(capability);
+
+                                    // sort requests for in-order cursor traversal. could consolidate?
+                                    prefixes
+                                        .sort_by(|x, y| logic2(&(x.0).0).cmp(&logic2(&(y.0).0)));
+
+                                    let (mut cursor, storage) = trace.cursor();
+
+                                    for &mut (
+                                        (ref prefix, old_count, old_index),
+                                        ref time,
+                                        ref mut diff,
+                                    ) in prefixes.iter_mut()
+                                    {
+                                        if budget == 0 {
+                                            break;
+                                        }
+
+                                        if !input2.frontier.less_equal(time) {
+                                            budget -= 1;
+                                            let key = logic2(prefix);
+                                            cursor.seek_key(&storage, &key);
+                                            if cursor.get_key(&storage) == Some(&key) {
+                                                let mut count = 0;
+                                                cursor.map_times(&storage, |t, d| {
+                                                    if t.less_equal(time) {
+                                                        count += d;
+                                                    }
+                                                });
+                                                // assert!(count >= 0);
+                                                let count = count as usize;
+                                                if count > 0 {
+                                                    if count < old_count {
+                                                        session.give((
+                                                            (prefix.clone(), count, index),
+                                                            time.clone(),
+                                                            *diff,
+                                                        ));
+                                                    } else {
+                                                        session.give((
+                                                            (prefix.clone(), old_count, old_index),
+                                                            time.clone(),
+                                                            *diff,
+                                                        ));
+                                                    }
                                                 }
                                             }
+                                            *diff = 0;
                                         }
-                                        *diff = 0;
                                     }
-                                }
 
-                                prefixes.retain(|ptd| ptd.2 != 0);
+                                    prefixes.retain(|ptd| ptd.2 != 0);
+                                }
                             }
                         }
-                    }
 
-                    // drop fully processed capabilities.
-                    stash.retain(|_, prefixes| !prefixes.is_empty());
+                        // drop fully processed capabilities.
+                        stash.retain(|_, prefixes| !prefixes.is_empty());
 
-                    // advance the consolidation frontier (TODO: wierd lexicographic times!)
-                    if let Some(trace) = counts_trace.as_mut() {
-                        trace.advance_by(&input1.frontier().frontier());
-                    }
+                        // advance the consolidation frontier (TODO: wierd lexicographic times!)
+                        if let Some(trace) = counts_trace.as_mut() {
+                            trace.advance_by(&input1.frontier().frontier());
+                        }
+
+                        if input1.frontier().is_empty() && stash.is_empty() {
+                            counts_trace = None;
+                        }
 
-                    if input1.frontier().is_empty() && stash.is_empty() {
-                        counts_trace = None;
+                        // requests left over at complete times are picked up
+                        // later, leaving other operators a chance to run.
+                        if budget == 0 && !stash.is_empty() {
+                            activator.activate_after(THROTTLE_DELAY);
+                        }
                     }
-                }
-            })
+                },
+            )
             .as_collection()
     }
 
@@ -842,6 +1283,9 @@ where
         let propose = &self.indices.propose_trace;
         let mut propose_trace = Some(propose.trace.clone());
 
+        let scope = prefixes.scope();
+        let batch_limit = self.batch_limit;
+
         let mut stash = HashMap::new();
         let logic1 = self.key_selector.clone();
         let logic2 = self.key_selector.clone();
@@ -860,7 +1304,9 @@ where
                 exchange,
                 Pipeline,
                 "Propose",
-                move |_, _| {
+                move |_, info| {
+                    let activator = scope.activator_for(&info.address[..]);
+
                     move |input1, input2, output| {
                         // drain the first input, stashing requests.
                         input1.for_each(|capability, data| {
@@ -868,9 +1314,12 @@ where
                             stash
                                 .entry(capability.retain())
                                 .or_insert_with(Vec::new)
-                                .extend(buffer1.drain(..))
+                                .extend(buffer1.drain(..));
                         });
 
+                        // at most this many requests are worked off per activation.
+                        let mut budget = batch_limit;
+
                         // advance the `distinguish_since` frontier to allow all merges.
                         input2.for_each(|_, batches| {
                             batches.swap(&mut buffer2);
@@ -883,6 +1332,10 @@ where
 
                         if let Some(ref mut trace) = propose_trace {
                             for (capability, prefixes) in stash.iter_mut() {
+                                if budget == 0 {
+                                    break;
+                                }
+
                                 // defer requests at incomplete times.
                                 // NOTE: not all updates may be at complete times, but if this test fails then none of them are.
                                 if !input2.frontier.less_equal(capability.time()) {
@@ -896,7 +1349,12 @@ where
                                     for &mut (ref prefix, ref time, ref mut diff) in
                                         prefixes.iter_mut()
                                     {
+                                        if budget == 0 {
+                                            break;
+                                        }
+
                                         if !input2.frontier.less_equal(time) {
+                                            budget -= 1;
                                             let key = logic2(prefix);
                                             cursor.seek_key(&storage, &key);
                                             if cursor.get_key(&storage) == Some(&key) {
@@ -939,6 +1397,12 @@ where
                         if input1.frontier().is_empty() && stash.is_empty() {
                             propose_trace = None;
                         }
+
+                        // requests left over at complete times are picked up
+                        // later, leaving other operators a chance to run.
+                        if budget == 0 && !stash.is_empty() {
+                            activator.activate_after(THROTTLE_DELAY);
+                        }
                     }
                 },
             )
@@ -957,6 +1421,9 @@ where
         let validate = &self.indices.validate_trace;
         let mut validate_trace = Some(validate.trace.clone());
 
+        let scope = extensions.scope();
+        let batch_limit = self.batch_limit;
+
         let mut stash = HashMap::new();
         let logic1 = self.key_selector.clone();
         let logic2 = self.key_selector.clone();
@@ -977,7 +1444,9 @@ where
                 exchange,
                 Pipeline,
                 "Validate",
-                move |_, _| {
+                move |_, info| {
+                    let activator = scope.activator_for(&info.address[..]);
+
                     move |input1, input2, output| {
                         // drain the first input, stashing requests.
                         input1.for_each(|capability, data| {
@@ -985,9 +1454,12 @@ where
                             stash
                                 .entry(capability.retain())
                                 .or_insert_with(Vec::new)
-                                .extend(buffer1.drain(..))
+                                .extend(buffer1.drain(..));
                         });
 
+                        // at most this many requests are worked off per activation.
+                        let mut budget = batch_limit;
+
                         // advance the `distinguish_since` frontier to allow all merges.
                         input2.for_each(|_, batches| {
                             batches.swap(&mut buffer2);
@@ -1000,6 +1472,10 @@ where
 
                         if let Some(ref mut trace) = validate_trace {
                             for (capability, prefixes) in stash.iter_mut() {
+                                if budget == 0 {
+                                    break;
+                                }
+
                                 // defer requests at incomplete times.
                                 // NOTE: not all updates may be at complete times, but if this test fails then none of them are.
                                 if !input2.frontier.less_equal(capability.time()) {
@@ -1016,7 +1492,12 @@ where
                                     for &mut (ref prefix, ref time, ref mut diff) in
                                         prefixes.iter_mut()
                                     {
+                                        if budget == 0 {
+                                            break;
+                                        }
+
                                         if !input2.frontier.less_equal(time) {
+                                            budget -= 1;
                                             let key = (logic2(&prefix.0), (prefix.1).clone());
                                             cursor.seek_key(&storage, &key);
                                             if cursor.get_key(&storage) == Some(&key) {
@@ -1055,6 +1536,12 @@ where
                         if input1.frontier().is_empty() && stash.is_empty() {
                             validate_trace = None;
                         }
+
+                        // requests left over at complete times are picked up
+                        // later, leaving other operators a chance to run.
+                        if budget == 0 && !stash.is_empty() {
+                            activator.activate_after(THROTTLE_DELAY);
+                        }
                     }
                 },
             )
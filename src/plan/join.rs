@@ -6,9 +6,9 @@ use timely::dataflow::Scope;
 use differential_dataflow::operators::JoinCore;
 
 use crate::binding::Binding;
-use crate::plan::{next_id, ImplContext, Implementable};
+use crate::plan::{content_id, ImplContext, Implementable};
 use crate::{Aid, Eid, Value, Var};
-use crate::{CollectionRelation, Relation, VariableMap};
+use crate::{CollectionRelation, Error, Relation, Time, VariableMap};
 
 /// A plan stage joining two source relations on the specified
 /// symbols. Throws if any of the join symbols isn't bound by both
@@ -36,23 +36,25 @@ impl<P1: Implementable, P2: Implementable> Implementable for Join<P1, P2> {
         dependencies
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
-        let mut left_bindings = self.left_plan.into_bindings();
-        let mut right_bindings = self.right_plan.into_bindings();
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        let mut left_bindings = self.left_plan.into_bindings()?;
+        let mut right_bindings = self.right_plan.into_bindings()?;
 
         let mut bindings = Vec::with_capacity(left_bindings.len() + right_bindings.len());
         bindings.append(&mut left_bindings);
         bindings.append(&mut right_bindings);
 
-        bindings
+        Ok(bindings)
     }
 
     fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
-        let eid = next_id();
-
         let mut left_data = self.left_plan.datafy();
         let mut right_data = self.right_plan.datafy();
 
+        // Plans aren't necessarily hashable, the data describing
+        // them is.
+        let eid = content_id(&("df.join", &self.variables, &left_data, &right_data));
+
         let mut left_eids: Vec<(Eid, Aid, Value)> = left_data
             .iter()
             .map(|(e, _, _)| (eid, "df.join/binding".to_string(), Value::Eid(*e)))
@@ -74,18 +76,18 @@ impl<P1: Implementable, P2: Implementable> Implementable for Join<P1, P2> {
         data
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
         let left = self
             .left_plan
-            .implement(nested, local_arrangements, context);
+            .implement(nested, local_arrangements, context)?;
         let right = self
             .right_plan
-            .implement(nested, local_arrangements, context);
+            .implement(nested, local_arrangements, context)?;
 
         let symbols = self
             .variables
@@ -119,6 +121,6 @@ impl<P1: Implementable, P2: Implementable> Implementable for Join<P1, P2> {
             },
         );
 
-        CollectionRelation { symbols, tuples }
+        Ok(CollectionRelation { symbols, tuples })
     }
 }
@@ -1,5 +1,6 @@
 //! Types and traits for implementing query plans.
 
+use std::hash::Hash;
 use std::ops::Deref;
 use std::sync::atomic::{self, AtomicUsize};
 
@@ -7,36 +8,56 @@ use timely::dataflow::scopes::child::Iterative;
 use timely::dataflow::Scope;
 
 use crate::binding::{AttributeBinding, Binding, ConstantBinding};
+use crate::domain::{FullTextIndexHandle, TxIndexHandle};
+use crate::udf::ExternalFunction;
 use crate::Rule;
-use crate::{Aid, Eid, Value, Var};
-use crate::{CollectionIndex, CollectionRelation, Relation, RelationHandle, VariableMap};
+use crate::ShutdownHandle;
+use crate::VariableMap;
+use crate::{stable_hash, time_value, Aid, Eid, Error, Time, Value, ValueType, Var};
+use crate::{CollectionIndex, CollectionRelation, IndexDirection, Relation, RelationHandle};
 
 pub mod aggregate;
 pub mod antijoin;
+pub mod broadcast_join;
+pub mod degree;
+pub mod entities;
 pub mod filter;
+pub mod format;
+pub mod full_text;
 pub mod hector;
 pub mod join;
+pub mod multi_join;
 pub mod project;
 pub mod pull;
+pub mod range_scan;
 pub mod transform;
 pub mod union;
 
 pub use self::aggregate::{Aggregate, AggregationFn};
 pub use self::antijoin::Antijoin;
+pub use self::broadcast_join::BroadcastJoin;
+pub use self::degree::Degree;
+pub use self::entities::Entities;
 pub use self::filter::{Filter, Predicate};
+pub use self::format::{decode_plan, encode_plan, PLAN_FORMAT_VERSION};
+pub use self::full_text::FullText;
 pub use self::hector::Hector;
 pub use self::join::Join;
+pub use self::multi_join::MultiJoin;
 pub use self::project::Project;
-pub use self::pull::{Pull, PullLevel};
+pub use self::pull::{Pull, PullLevel, PullRecursion};
+pub use self::range_scan::RangeScan;
 pub use self::transform::{Function, Transform};
 pub use self::union::Union;
 
-static ID: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
 static SYM: AtomicUsize = atomic::ATOMIC_USIZE_INIT;
 
-/// @FIXME
-pub fn next_id() -> Eid {
-    ID.fetch_add(1, atomic::Ordering::SeqCst) as Eid
+/// Returns the id of the entity describing a plan stage in meta
+/// relations. Ids are derived from the content of the stage, using a
+/// hash that is fixed across Rust versions, s.t. all workers agree on
+/// them and they remain stable across restarts and upgrades.
+pub fn content_id<T: Hash + ?Sized>(stage: &T) -> Eid {
+    stable_hash(stage) as Eid
 }
 
 /// @FIXME
@@ -44,6 +65,24 @@ pub fn gensym() -> Var {
     SYM.fetch_sub(1, atomic::Ordering::SeqCst) as Var
 }
 
+/// The error returned when implementing a plan that refers to an
+/// attribute that doesn't exist.
+pub(crate) fn attribute_not_found(name: &str) -> Error {
+    Error {
+        category: "df.error.category/not-found",
+        message: format!("Attribute {} does not exist.", name),
+    }
+}
+
+/// The error returned when implementing a plan that refers to a
+/// relation that hasn't been registered.
+pub(crate) fn relation_not_found(name: &str) -> Error {
+    Error {
+        category: "df.error.category/not-found",
+        message: format!("Relation {} does not exist.", name),
+    }
+}
+
 /// A thing that can provide global state required during the
 /// implementation of plans.
 pub trait ImplContext {
@@ -57,18 +96,81 @@ pub trait ImplContext {
     /// Returns a mutable reference to an attribute (a base relation)
     /// arranged from eid -> value, if one is registered under the
     /// given name.
-    fn forward_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, u64>>;
+    fn forward_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>>;
 
     /// Returns a mutable reference to an attribute (a base relation)
     /// arranged from value -> eid, if one is registered under the
     /// given name.
-    fn reverse_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, u64>>;
+    fn reverse_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>>;
+
+    /// Returns a mutable reference to an index over a published
+    /// binary relation, if one has been created by an earlier
+    /// dataflow.
+    fn derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+    ) -> Option<&mut CollectionIndex<Value, Value, Time>>;
+
+    /// Shares an index over a published binary relation with all
+    /// dataflows implemented later on, s.t. they don't have to
+    /// arrange the relation again.
+    fn cache_derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+        index: CollectionIndex<Value, Value, Time>,
+    );
+
+    /// Returns the names of all attributes.
+    fn attributes(&self) -> Vec<Aid>;
+
+    /// Returns a mutable reference to an attribute (a base relation)
+    /// annotated with the time at which each (e,v) pair was
+    /// asserted, if transaction times are tracked for it.
+    fn tx_index(&mut self, name: &str) -> Option<&mut TxIndexHandle<Time>>;
+
+    /// Returns a mutable reference to the inverted index from tokens
+    /// to eids of an attribute, if it has FullText semantics.
+    fn full_text_index(&mut self, name: &str) -> Option<&mut FullTextIndexHandle<Time>>;
 
     /// Returns the current opinion as to whether this rule is
     /// underconstrained. Underconstrained rules cannot be safely
     /// materialized and re-used on their own (i.e. without more
     /// specific constraints).
     fn is_underconstrained(&self, name: &str) -> bool;
+    /// Returns true iff the named relation should be replicated on
+    /// every worker, rather than partitioned across them.
+    fn is_replicated(&self, name: &str) -> bool;
+
+    /// Returns an estimate of the number of tuples in the named
+    /// attribute, if one is available. Estimates must agree across
+    /// workers, as they might influence the shape of dataflows.
+    fn cardinality(&self, name: &str) -> Option<usize>;
+
+    /// Returns the type declared for the values of the named
+    /// attribute, if any.
+    fn value_type(&self, name: &str) -> Option<ValueType>;
+
+    /// Returns true iff nothing is ever retracted from the named
+    /// attribute.
+    fn is_append_only(&self, name: &str) -> bool;
+
+    /// Returns true iff the named relation must be implemented with
+    /// set semantics, even if it could be streamed.
+    fn is_distinct(&self, name: &str) -> bool;
+
+    /// Returns the configuration of the named external function, if
+    /// one has been configured.
+    fn external_function(&self, name: &str) -> Option<&ExternalFunction>;
+
+    /// Returns the maximum number of prefixes each worst-case optimal
+    /// join operator works off at a time, if any.
+    fn hector_batch_limit(&self) -> Option<usize>;
+
+    /// Returns the handle stopping the imports of the dataflow being
+    /// implemented, s.t. it can be torn down once no longer needed.
+    fn shutdown_handle(&self) -> ShutdownHandle;
 }
 
 /// A type that can be implemented as a simple relation.
@@ -79,9 +181,12 @@ pub trait Implementable {
     fn dependencies(&self) -> Vec<String>;
 
     /// Transforms an implementable into an equivalent set of bindings
-    /// that can be unified by Hector.
-    fn into_bindings(&self) -> Vec<Binding> {
-        panic!("This plan can't be implemented via Hector.");
+    /// that can be unified by Hector. Fails if there is no such set.
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        Err(Error {
+            category: "df.error.category/unsupported",
+            message: "This plan can't be implemented via Hector.".to_string(),
+        })
     }
 
     /// @TODO
@@ -89,13 +194,15 @@ pub trait Implementable {
         Vec::new()
     }
 
-    /// Implements the type as a simple relation.
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    /// Implements the type as a simple relation. Fails if the
+    /// implementation refers to attributes or relations that are not
+    /// available in the given context, or if it is malformed.
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S>;
+    ) -> Result<CollectionRelation<'b, S>, Error>;
 }
 
 /// Possible query plan types.
@@ -109,6 +216,10 @@ pub enum Plan {
     Union(Union<Plan>),
     /// Equijoin
     Join(Join<Plan, Plan>),
+    /// Equijoin over any number of inputs
+    MultiJoin(MultiJoin<Plan>),
+    /// Equijoin against a small, replicated relation
+    BroadcastJoin(BroadcastJoin<Plan, Plan>),
     /// WCO
     Hector(Hector),
     /// Antijoin
@@ -125,6 +236,17 @@ pub enum Plan {
     MatchEA(Eid, Aid, Var),
     /// Data pattern of the form [?e a v]
     MatchAV(Var, Aid, Value),
+    /// Data pattern of the form [?e a ?v ?tx]
+    MatchATx(Var, Aid, Var, Var),
+    /// Data pattern of the form [?e a ?v] with lower <= ?v <= upper
+    /// (or lower <= ?v < upper), either bound being optional
+    RangeScan(RangeScan),
+    /// Number of values per entity of an attribute
+    Degree(Degree),
+    /// Entities whose values contain all tokens of a query
+    FullText(FullText),
+    /// All datoms of a set of entities, across attributes
+    Entities(Entities<Plan>),
     /// Sources data from another relation.
     NameExpr(Vec<Var>, String),
     /// Pull expression
@@ -134,6 +256,55 @@ pub enum Plan {
 }
 
 impl Plan {
+    /// Checks the plan for mistakes that would otherwise only
+    /// surface while implementing it. Currently these are Hector
+    /// stages leaving some of their variables unbound, and joins
+    /// without any inputs.
+    pub fn validate(&self) -> Result<(), Error> {
+        match *self {
+            Plan::Project(ref projection) => projection.plan.validate(),
+            Plan::Aggregate(ref aggregate) => aggregate.plan.validate(),
+            Plan::Union(ref union) => union.plans.iter().map(Plan::validate).collect(),
+            Plan::Join(ref join) => {
+                join.left_plan.validate()?;
+                join.right_plan.validate()
+            }
+            Plan::MultiJoin(ref join) => {
+                if join.plans.is_empty() {
+                    return Err(multi_join::empty_join());
+                }
+
+                join.plans.iter().map(Plan::validate).collect()
+            }
+            Plan::BroadcastJoin(ref join) => {
+                join.left_plan.validate()?;
+                join.right_plan.validate()
+            }
+            Plan::Hector(ref hector) => hector.validate(),
+            Plan::Antijoin(ref antijoin) => {
+                antijoin.left_plan.validate()?;
+                antijoin.right_plan.validate()
+            }
+            Plan::Negate(ref plan) => plan.validate(),
+            Plan::Filter(ref filter) => filter.plan.validate(),
+            Plan::Transform(ref transform) => transform.plan.validate(),
+            Plan::Pull(ref pull) => pull.paths.iter().map(|path| path.plan.validate()).collect(),
+            Plan::PullLevel(ref path) => path.plan.validate(),
+            Plan::Entities(ref entities) => entities
+                .plan
+                .as_ref()
+                .map_or(Ok(()), |plan| plan.validate()),
+            Plan::MatchA(_, _, _)
+            | Plan::MatchEA(_, _, _)
+            | Plan::MatchAV(_, _, _)
+            | Plan::MatchATx(_, _, _, _)
+            | Plan::RangeScan(_)
+            | Plan::Degree(_)
+            | Plan::FullText(_)
+            | Plan::NameExpr(_, _) => Ok(()),
+        }
+    }
+
     /// Returns the symbols bound by this plan.
     pub fn variables(&self) -> Vec<Var> {
         match *self {
@@ -141,6 +312,8 @@ impl Plan {
             Plan::Aggregate(ref aggregate) => aggregate.variables.clone(),
             Plan::Union(ref union) => union.variables.clone(),
             Plan::Join(ref join) => join.variables.clone(),
+            Plan::MultiJoin(ref join) => join.variables.clone(),
+            Plan::BroadcastJoin(ref join) => join.variables.clone(),
             Plan::Hector(ref hector) => hector.variables.clone(),
             Plan::Antijoin(ref antijoin) => antijoin.variables.clone(),
             Plan::Negate(ref plan) => plan.variables(),
@@ -149,11 +322,176 @@ impl Plan {
             Plan::MatchA(e, _, v) => vec![e, v],
             Plan::MatchEA(_, _, v) => vec![v],
             Plan::MatchAV(e, _, _) => vec![e],
+            Plan::MatchATx(e, _, v, tx) => vec![e, v, tx],
+            Plan::RangeScan(ref scan) => scan.variables.clone(),
+            Plan::Degree(ref degree) => degree.variables.clone(),
+            Plan::FullText(ref search) => search.variables.clone(),
+            Plan::Entities(ref entities) => entities.variables.clone(),
             Plan::NameExpr(ref variables, ref _name) => variables.clone(),
             Plan::Pull(ref pull) => pull.variables.clone(),
             Plan::PullLevel(ref path) => path.variables.clone(),
         }
     }
+
+    /// Returns all symbols bound by this plan, in order. Unlike
+    /// `variables`, this includes the symbols binary joins don't
+    /// join on.
+    pub fn bound_symbols(&self) -> Vec<Var> {
+        let join_symbols = |variables: &[Var], left: &Plan, right: &Plan| {
+            let mut symbols = variables.to_vec();
+
+            for sym in left
+                .bound_symbols()
+                .into_iter()
+                .chain(right.bound_symbols())
+            {
+                if !symbols.contains(&sym) {
+                    symbols.push(sym);
+                }
+            }
+
+            symbols
+        };
+
+        match *self {
+            Plan::Join(ref join) => {
+                join_symbols(&join.variables, &join.left_plan, &join.right_plan)
+            }
+            Plan::BroadcastJoin(ref join) => {
+                join_symbols(&join.variables, &join.left_plan, &join.right_plan)
+            }
+            _ => self.variables(),
+        }
+    }
+
+    /// Returns the attributes read by this plan. Attributes read by
+    /// the relations it depends on are not included.
+    pub fn attributes(&self) -> Vec<Aid> {
+        match *self {
+            Plan::Project(ref projection) => projection.plan.attributes(),
+            Plan::Aggregate(ref aggregate) => aggregate.plan.attributes(),
+            Plan::Union(ref union) => union.plans.iter().flat_map(Plan::attributes).collect(),
+            Plan::Join(ref join) => {
+                let mut attributes = join.left_plan.attributes();
+                attributes.append(&mut join.right_plan.attributes());
+                attributes
+            }
+            Plan::MultiJoin(ref join) => join.plans.iter().flat_map(Plan::attributes).collect(),
+            Plan::BroadcastJoin(ref join) => {
+                let mut attributes = join.left_plan.attributes();
+                attributes.append(&mut join.right_plan.attributes());
+                attributes
+            }
+            Plan::Hector(ref hector) => hector
+                .bindings
+                .iter()
+                .flat_map(binding_attributes)
+                .collect(),
+            Plan::Antijoin(ref antijoin) => {
+                let mut attributes = antijoin.left_plan.attributes();
+                attributes.append(&mut antijoin.right_plan.attributes());
+                attributes
+            }
+            Plan::Negate(ref plan) => plan.attributes(),
+            Plan::Filter(ref filter) => filter.plan.attributes(),
+            Plan::Transform(ref transform) => transform.plan.attributes(),
+            Plan::MatchA(_, ref a, _) => vec![a.to_string()],
+            Plan::MatchEA(_, ref a, _) => vec![a.to_string()],
+            Plan::MatchAV(_, ref a, _) => vec![a.to_string()],
+            Plan::MatchATx(_, ref a, _, _) => vec![a.to_string()],
+            Plan::RangeScan(ref scan) => vec![scan.attribute.to_string()],
+            Plan::Degree(ref degree) => vec![degree.attribute.to_string()],
+            Plan::FullText(ref search) => vec![search.attribute.to_string()],
+            Plan::Entities(ref entities) => {
+                let mut attributes = entities
+                    .plan
+                    .as_ref()
+                    .map_or_else(Vec::new, |plan| plan.attributes());
+                attributes.push("*".to_string());
+                attributes
+            }
+            Plan::NameExpr(_, _) => Vec::new(),
+            Plan::Pull(ref pull) => pull.paths.iter().flat_map(path_attributes).collect(),
+            Plan::PullLevel(ref path) => path_attributes(path),
+        }
+    }
+
+    /// Returns the relations this plan depends on negatively, i.e. by
+    /// subtracting them from other relations. A rule must not depend
+    /// negatively on itself, neither directly nor via other rules.
+    pub fn negated_dependencies(&self) -> Vec<String> {
+        match *self {
+            Plan::Project(ref projection) => projection.plan.negated_dependencies(),
+            Plan::Aggregate(ref aggregate) => aggregate.plan.negated_dependencies(),
+            Plan::Union(ref union) => union
+                .plans
+                .iter()
+                .flat_map(Plan::negated_dependencies)
+                .collect(),
+            Plan::Join(ref join) => {
+                let mut dependencies = join.left_plan.negated_dependencies();
+                dependencies.append(&mut join.right_plan.negated_dependencies());
+                dependencies
+            }
+            Plan::MultiJoin(ref join) => join
+                .plans
+                .iter()
+                .flat_map(Plan::negated_dependencies)
+                .collect(),
+            Plan::BroadcastJoin(ref join) => {
+                let mut dependencies = join.left_plan.negated_dependencies();
+                dependencies.append(&mut join.right_plan.negated_dependencies());
+                dependencies
+            }
+            Plan::Antijoin(ref antijoin) => {
+                let mut dependencies = antijoin.left_plan.negated_dependencies();
+                dependencies.append(&mut antijoin.right_plan.dependencies());
+                dependencies
+            }
+            Plan::Negate(ref plan) => plan.dependencies(),
+            Plan::Filter(ref filter) => filter.plan.negated_dependencies(),
+            Plan::Transform(ref transform) => transform.plan.negated_dependencies(),
+            Plan::Pull(ref pull) => pull
+                .paths
+                .iter()
+                .flat_map(|path| path.plan.negated_dependencies())
+                .collect(),
+            Plan::PullLevel(ref path) => path.plan.negated_dependencies(),
+            Plan::Entities(ref entities) => entities
+                .plan
+                .as_ref()
+                .map_or_else(Vec::new, |plan| plan.negated_dependencies()),
+            Plan::Hector(_)
+            | Plan::MatchA(_, _, _)
+            | Plan::MatchEA(_, _, _)
+            | Plan::MatchAV(_, _, _)
+            | Plan::MatchATx(_, _, _, _)
+            | Plan::RangeScan(_)
+            | Plan::Degree(_)
+            | Plan::FullText(_)
+            | Plan::NameExpr(_, _) => Vec::new(),
+        }
+    }
+}
+
+fn binding_attributes(binding: &Binding) -> Vec<Aid> {
+    match *binding {
+        Binding::Attribute(ref binding) => vec![binding.source_attribute.to_string()],
+        Binding::Not(ref binding) => binding_attributes(&binding.binding),
+        Binding::Constant(_) | Binding::BinaryPredicate(_) => Vec::new(),
+    }
+}
+
+fn path_attributes(path: &PullLevel<Plan>) -> Vec<Aid> {
+    let mut attributes = path.plan.attributes();
+    attributes.extend(path.pull_attributes.iter().cloned());
+    attributes.extend(path.path_attributes.iter().cloned());
+
+    if let Some(ref recursion) = path.recursion {
+        attributes.push(recursion.attribute.to_string());
+    }
+
+    attributes
 }
 
 impl Implementable for Plan {
@@ -164,6 +502,8 @@ impl Implementable for Plan {
             Plan::Aggregate(ref aggregate) => aggregate.dependencies(),
             Plan::Union(ref union) => union.dependencies(),
             Plan::Join(ref join) => join.dependencies(),
+            Plan::MultiJoin(ref join) => join.dependencies(),
+            Plan::BroadcastJoin(ref join) => join.dependencies(),
             Plan::Hector(ref hector) => hector.dependencies(),
             Plan::Antijoin(ref antijoin) => antijoin.dependencies(),
             Plan::Negate(ref plan) => plan.dependencies(),
@@ -172,31 +512,38 @@ impl Implementable for Plan {
             Plan::MatchA(_, _, _) => Vec::new(),
             Plan::MatchEA(_, _, _) => Vec::new(),
             Plan::MatchAV(_, _, _) => Vec::new(),
+            Plan::MatchATx(_, _, _, _) => Vec::new(),
+            Plan::RangeScan(ref scan) => scan.dependencies(),
+            Plan::Degree(ref degree) => degree.dependencies(),
+            Plan::FullText(ref search) => search.dependencies(),
+            Plan::Entities(ref entities) => entities.dependencies(),
             Plan::NameExpr(_, ref name) => vec![name.to_string()],
             Plan::Pull(ref pull) => pull.dependencies(),
             Plan::PullLevel(ref path) => path.dependencies(),
         }
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
         // @TODO provide a general fold for plans
         match *self {
             Plan::Project(ref projection) => projection.into_bindings(),
             Plan::Aggregate(ref aggregate) => aggregate.into_bindings(),
             Plan::Union(ref union) => union.into_bindings(),
             Plan::Join(ref join) => join.into_bindings(),
+            Plan::MultiJoin(ref join) => join.into_bindings(),
+            Plan::BroadcastJoin(ref join) => join.into_bindings(),
             Plan::Hector(ref hector) => hector.into_bindings(),
             Plan::Antijoin(ref antijoin) => antijoin.into_bindings(),
             Plan::Negate(ref plan) => plan.into_bindings(),
             Plan::Filter(ref filter) => filter.into_bindings(),
             Plan::Transform(ref transform) => transform.into_bindings(),
-            Plan::MatchA(e, ref a, v) => vec![Binding::Attribute(AttributeBinding {
+            Plan::MatchA(e, ref a, v) => Ok(vec![Binding::Attribute(AttributeBinding {
                 symbols: (e, v),
                 source_attribute: a.to_string(),
-            })],
+            })]),
             Plan::MatchEA(match_e, ref a, v) => {
                 let e = gensym();
-                vec![
+                Ok(vec![
                     Binding::Attribute(AttributeBinding {
                         symbols: (e, v),
                         source_attribute: a.to_string(),
@@ -205,11 +552,11 @@ impl Implementable for Plan {
                         symbol: e,
                         value: Value::Eid(match_e),
                     }),
-                ]
+                ])
             }
             Plan::MatchAV(e, ref a, ref match_v) => {
                 let v = gensym();
-                vec![
+                Ok(vec![
                     Binding::Attribute(AttributeBinding {
                         symbols: (e, v),
                         source_attribute: a.to_string(),
@@ -218,9 +565,20 @@ impl Implementable for Plan {
                         symbol: v,
                         value: match_v.clone(),
                     }),
-                ]
+                ])
             }
-            Plan::NameExpr(_, ref _name) => unimplemented!(), // @TODO hmm...
+            Plan::MatchATx(_, _, _, _) => Err(Error {
+                category: "df.error.category/unsupported",
+                message: "MatchATx can't be implemented via Hector.".to_string(),
+            }),
+            Plan::RangeScan(ref scan) => scan.into_bindings(),
+            Plan::Degree(ref degree) => degree.into_bindings(),
+            Plan::FullText(ref search) => search.into_bindings(),
+            Plan::Entities(ref entities) => entities.into_bindings(),
+            Plan::NameExpr(_, ref name) => Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!("References to {} can't be implemented via Hector.", name),
+            }),
             Plan::Pull(ref pull) => pull.into_bindings(),
             Plan::PullLevel(ref path) => path.into_bindings(),
         }
@@ -233,44 +591,55 @@ impl Implementable for Plan {
             Plan::Aggregate(ref aggregate) => aggregate.datafy(),
             Plan::Union(ref union) => union.datafy(),
             Plan::Join(ref join) => join.datafy(),
+            Plan::MultiJoin(ref join) => join.datafy(),
+            Plan::BroadcastJoin(ref join) => join.datafy(),
             Plan::Hector(ref hector) => hector.datafy(),
             Plan::Antijoin(ref antijoin) => antijoin.datafy(),
             Plan::Negate(ref plan) => plan.datafy(),
             Plan::Filter(ref filter) => filter.datafy(),
             Plan::Transform(ref transform) => transform.datafy(),
             Plan::MatchA(_e, ref a, _v) => vec![(
-                next_id(),
+                content_id(self),
                 "df.pattern/a".to_string(),
                 Value::Aid(a.to_string()),
             )],
             Plan::MatchEA(e, ref a, _) => vec![
-                (next_id(), "df.pattern/e".to_string(), Value::Eid(e)),
+                (content_id(self), "df.pattern/e".to_string(), Value::Eid(e)),
                 (
-                    next_id(),
+                    content_id(self),
                     "df.pattern/a".to_string(),
                     Value::Aid(a.to_string()),
                 ),
             ],
             Plan::MatchAV(_, ref a, ref v) => vec![
                 (
-                    next_id(),
+                    content_id(self),
                     "df.pattern/a".to_string(),
                     Value::Aid(a.to_string()),
                 ),
-                (next_id(), "df.pattern/v".to_string(), v.clone()),
+                (content_id(self), "df.pattern/v".to_string(), v.clone()),
             ],
+            Plan::MatchATx(_, ref a, _, _) => vec![(
+                content_id(self),
+                "df.pattern/a".to_string(),
+                Value::Aid(a.to_string()),
+            )],
+            Plan::RangeScan(ref scan) => scan.datafy(),
+            Plan::Degree(ref degree) => degree.datafy(),
+            Plan::FullText(ref search) => search.datafy(),
+            Plan::Entities(ref entities) => entities.datafy(),
             Plan::NameExpr(_, ref _name) => Vec::new(),
             Plan::Pull(ref pull) => pull.datafy(),
             Plan::PullLevel(ref path) => path.datafy(),
         }
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
         match *self {
             Plan::Project(ref projection) => {
                 projection.implement(nested, local_arrangements, context)
@@ -280,77 +649,122 @@ impl Implementable for Plan {
             }
             Plan::Union(ref union) => union.implement(nested, local_arrangements, context),
             Plan::Join(ref join) => join.implement(nested, local_arrangements, context),
+            Plan::MultiJoin(ref join) => join.implement(nested, local_arrangements, context),
+            Plan::BroadcastJoin(ref join) => join.implement(nested, local_arrangements, context),
             Plan::Hector(ref hector) => hector.implement(nested, local_arrangements, context),
             Plan::Antijoin(ref antijoin) => antijoin.implement(nested, local_arrangements, context),
             Plan::Negate(ref plan) => {
-                let rel = plan.implement(nested, local_arrangements, context);
-                CollectionRelation {
+                let rel = plan.implement(nested, local_arrangements, context)?;
+                Ok(CollectionRelation {
                     symbols: rel.symbols().to_vec(),
                     tuples: rel.tuples().negate(),
-                }
+                })
             }
             Plan::Filter(ref filter) => filter.implement(nested, local_arrangements, context),
             Plan::Transform(ref transform) => {
                 transform.implement(nested, local_arrangements, context)
             }
             Plan::MatchA(sym1, ref a, sym2) => {
+                let shutdown_handle = context.shutdown_handle();
                 let tuples = match context.forward_index(a) {
-                    None => panic!("attribute {:?} does not exist", a),
-                    Some(index) => index
-                        .validate_trace
-                        .import_named(&nested.parent, a)
-                        .enter(nested)
-                        .as_collection(|(e, v), _| vec![e.clone(), v.clone()]),
+                    None => return Err(attribute_not_found(a)),
+                    Some(index) => {
+                        let (validate, button) =
+                            index.validate_trace.import_core(&nested.parent, a);
+                        shutdown_handle.add_button(button);
+
+                        validate
+                            .enter(nested)
+                            .as_collection(|(e, v), _| vec![e.clone(), v.clone()])
+                    }
                 };
 
-                CollectionRelation {
+                Ok(CollectionRelation {
                     symbols: vec![sym1, sym2],
                     tuples,
-                }
+                })
             }
             Plan::MatchEA(match_e, ref a, sym1) => {
+                let shutdown_handle = context.shutdown_handle();
                 let tuples = match context.forward_index(a) {
-                    None => panic!("attribute {:?} does not exist", a),
-                    Some(index) => index
-                        .propose_trace
-                        .import_named(&nested.parent, a)
-                        .enter(nested)
-                        .filter(move |e, _v| *e == Value::Eid(match_e))
-                        .as_collection(|_e, v| vec![v.clone()]),
+                    None => return Err(attribute_not_found(a)),
+                    Some(index) => {
+                        let (propose, button) = index.propose_trace.import_core(&nested.parent, a);
+                        shutdown_handle.add_button(button);
+
+                        propose
+                            .enter(nested)
+                            .filter(move |e, _v| *e == Value::Eid(match_e))
+                            .as_collection(|_e, v| vec![v.clone()])
+                    }
                 };
 
-                CollectionRelation {
+                Ok(CollectionRelation {
                     symbols: vec![sym1],
                     tuples,
-                }
+                })
             }
             Plan::MatchAV(sym1, ref a, ref match_v) => {
+                let shutdown_handle = context.shutdown_handle();
                 let tuples = match context.reverse_index(a) {
-                    None => panic!("attribute {:?} does not exist", a),
+                    None => return Err(attribute_not_found(a)),
                     Some(index) => {
                         let match_v = match_v.clone();
-                        index
-                            .propose_trace
-                            .import_named(&nested.parent, a)
+                        let (propose, button) = index.propose_trace.import_core(&nested.parent, a);
+                        shutdown_handle.add_button(button);
+
+                        propose
                             .enter(nested)
                             .filter(move |v, _e| *v == match_v)
                             .as_collection(|_v, e| vec![e.clone()])
                     }
                 };
 
-                CollectionRelation {
+                Ok(CollectionRelation {
                     symbols: vec![sym1],
                     tuples,
+                })
+            }
+            Plan::MatchATx(sym1, ref a, sym2, sym3) => {
+                if context.forward_index(a).is_none() {
+                    return Err(attribute_not_found(a));
                 }
+
+                let shutdown_handle = context.shutdown_handle();
+                let tuples = match context.tx_index(a) {
+                    None => {
+                        return Err(Error {
+                            category: "df.error.category/not-found",
+                            message: format!("Attribute {} does not track transaction times.", a),
+                        });
+                    }
+                    Some(trace) => {
+                        let (tx_index, button) = trace.import_core(&nested.parent, a);
+                        shutdown_handle.add_button(button);
+
+                        tx_index.enter(nested).as_collection(|((e, v), tx), _| {
+                            vec![e.clone(), v.clone(), time_value(tx)]
+                        })
+                    }
+                };
+
+                Ok(CollectionRelation {
+                    symbols: vec![sym1, sym2, sym3],
+                    tuples,
+                })
             }
+            Plan::RangeScan(ref scan) => scan.implement(nested, local_arrangements, context),
+            Plan::Degree(ref degree) => degree.implement(nested, local_arrangements, context),
+            Plan::FullText(ref search) => search.implement(nested, local_arrangements, context),
+            Plan::Entities(ref entities) => entities.implement(nested, local_arrangements, context),
             Plan::NameExpr(ref syms, ref name) => {
                 if context.is_underconstrained(name) {
                     match local_arrangements.get(name) {
-                        None => panic!("{:?} not in relation map", name),
-                        Some(named) => CollectionRelation {
+                        None => Err(relation_not_found(name)),
+                        Some(named) => Ok(CollectionRelation {
                             symbols: syms.clone(),
                             tuples: named.deref().clone(), // @TODO re-use variable directly?
-                        },
+                        }),
                     }
                 } else {
                     // If a rule is not underconstrained, we can
@@ -359,16 +773,22 @@ impl Implementable for Plan {
                     // available as a global arrangement, but we'll do
                     // so for now.
 
+                    let shutdown_handle = context.shutdown_handle();
+
                     match context.global_arrangement(name) {
-                        None => panic!("{:?} not in query map", name),
-                        Some(named) => CollectionRelation {
-                            symbols: syms.clone(),
-                            tuples: named
-                                .import_named(&nested.parent, name)
-                                .enter(nested)
-                                // @TODO this destroys all the arrangement re-use
-                                .as_collection(|tuple, _| tuple.clone()),
-                        },
+                        None => Err(relation_not_found(name)),
+                        Some(named) => {
+                            let (named, button) = named.import_core(&nested.parent, name);
+                            shutdown_handle.add_button(button);
+
+                            Ok(CollectionRelation {
+                                symbols: syms.clone(),
+                                tuples: named
+                                    .enter(nested)
+                                    // @TODO this destroys all the arrangement re-use
+                                    .as_collection(|tuple, _| tuple.clone()),
+                            })
+                        }
                     }
                 }
             }
@@ -0,0 +1,246 @@
+//! Equijoin expression plan over any number of inputs.
+
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+
+use differential_dataflow::operators::arrange::ArrangeByKey;
+use differential_dataflow::operators::JoinCore;
+
+use crate::binding::{AttributeBinding, Binding};
+use crate::plan::{attribute_not_found, content_id, ImplContext, Implementable};
+use crate::{Aid, Eid, Value, Var};
+use crate::{CollectionRelation, Error, Relation, Time, VariableMap};
+
+/// A plan stage joining any number of inputs on all the symbols they
+/// share. Unlike nested binary joins, no join order is prescribed.
+/// When implemented directly, inputs are joined greedily, always
+/// picking the remaining input sharing the most symbols with those
+/// bound so far. Inputs matching an attribute as a whole that are
+/// joined on a single symbol are joined against the attribute's own
+/// indices, rather than being arranged anew. The optimizer may
+/// reorder inputs freely.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct MultiJoin<P: Implementable> {
+    /// Symbols to bind, in order.
+    pub variables: Vec<Var>,
+    /// Plans for the inputs, in no particular order.
+    pub plans: Vec<P>,
+}
+
+/// Returns the number of symbols in `symbols` that are bound in
+/// `bound`.
+fn shared(symbols: &[Var], bound: &[Var]) -> usize {
+    symbols.iter().filter(|sym| bound.contains(sym)).count()
+}
+
+/// Returns the attribute matched by an input, if the input binds
+/// exactly the entity and value symbols of a single attribute.
+fn matched_attribute<P: Implementable>(plan: &P, symbols: &[Var]) -> Option<(Var, Aid, Var)> {
+    match plan.into_bindings() {
+        Ok(ref bindings) if bindings.len() == 1 => match bindings[0] {
+            Binding::Attribute(AttributeBinding {
+                symbols: (e, v),
+                ref source_attribute,
+            }) if e != v && symbols == [e, v] => Some((e, source_attribute.clone(), v)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl<P: Implementable> Implementable for MultiJoin<P> {
+    fn dependencies(&self) -> Vec<String> {
+        let mut dependencies = Vec::new();
+
+        for plan in self.plans.iter() {
+            dependencies.append(&mut plan.dependencies());
+        }
+
+        dependencies
+    }
+
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
+        let mut bindings = Vec::new();
+
+        for plan in self.plans.iter() {
+            bindings.append(&mut plan.into_bindings()?);
+        }
+
+        Ok(bindings)
+    }
+
+    fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
+        let plans_data: Vec<_> = self.plans.iter().map(|plan| plan.datafy()).collect();
+        let eid = content_id(&("df.join", &self.variables, &plans_data));
+        let mut data = Vec::new();
+
+        for mut plan_data in plans_data.into_iter() {
+            let mut plan_eids: Vec<(Eid, Aid, Value)> = plan_data
+                .iter()
+                .map(|(e, _, _)| (eid, "df.join/binding".to_string(), Value::Eid(*e)))
+                .collect();
+
+            data.append(&mut plan_data);
+            data.append(&mut plan_eids);
+        }
+
+        data
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        if self.plans.is_empty() {
+            return Err(empty_join());
+        }
+
+        let mut inputs: Vec<CollectionRelation<'b, S>> = Vec::with_capacity(self.plans.len());
+        let mut attributes: Vec<Option<(Var, Aid, Var)>> = Vec::with_capacity(self.plans.len());
+
+        for plan in self.plans.iter() {
+            let relation = plan.implement(nested, local_arrangements, context)?;
+
+            attributes.push(
+                matched_attribute(plan, relation.symbols())
+                    .filter(|(_, a, _)| context.forward_index(a).is_some()),
+            );
+            inputs.push(relation);
+        }
+
+        // Start with the input that is most connected to the others.
+        let first = (0..inputs.len())
+            .max_by_key(|&idx| {
+                let connections: usize = inputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != idx)
+                    .map(|(_, other)| shared(other.symbols(), inputs[idx].symbols()))
+                    .sum();
+
+                // Prefer earlier inputs on ties.
+                (connections, inputs.len() - idx)
+            })
+            .expect("inputs checked to be non-empty");
+
+        let mut joined = inputs.remove(first);
+        attributes.remove(first);
+
+        while !inputs.is_empty() {
+            let next = (0..inputs.len())
+                .max_by_key(|&idx| {
+                    (
+                        shared(inputs[idx].symbols(), joined.symbols()),
+                        inputs.len() - idx,
+                    )
+                })
+                .expect("inputs checked to be non-empty");
+
+            let right = inputs.remove(next);
+            let attribute = attributes.remove(next);
+
+            let key_symbols: Vec<Var> = joined
+                .symbols()
+                .iter()
+                .filter(|sym| right.symbols().contains(sym))
+                .cloned()
+                .collect();
+
+            if let Some((e, a, v)) = attribute {
+                if key_symbols == [e] || key_symbols == [v] {
+                    // the attribute's forward index is keyed by entity,
+                    // its reverse index by value
+                    let (key, other) = if key_symbols == [e] { (e, v) } else { (v, e) };
+
+                    let shutdown_handle = context.shutdown_handle();
+                    let index = if key == e {
+                        context.forward_index(&a)
+                    } else {
+                        context.reverse_index(&a)
+                    };
+
+                    let propose = match index {
+                        None => return Err(attribute_not_found(&a)),
+                        Some(index) => {
+                            let (propose, button) =
+                                index.propose_trace.import_core(&nested.parent, &a);
+                            shutdown_handle.add_button(button);
+
+                            propose.enter(nested)
+                        }
+                    };
+
+                    let offset = joined.offset(key);
+                    let mut symbols = joined.symbols().to_vec();
+                    symbols.push(other);
+
+                    let tuples = joined
+                        .tuples()
+                        .map(move |tuple| (tuple[offset].clone(), tuple))
+                        .arrange_by_key()
+                        .join_core(&propose, |_key, tuple, value| {
+                            let mut tuple = tuple.clone();
+                            tuple.push(value.clone());
+                            Some(tuple)
+                        });
+
+                    joined = CollectionRelation { symbols, tuples };
+                    continue;
+                }
+            }
+
+            let symbols: Vec<Var> = key_symbols
+                .iter()
+                .cloned()
+                .chain(
+                    joined
+                        .symbols()
+                        .iter()
+                        .filter(|x| !key_symbols.contains(x))
+                        .cloned(),
+                )
+                .chain(
+                    right
+                        .symbols()
+                        .iter()
+                        .filter(|x| !key_symbols.contains(x))
+                        .cloned(),
+                )
+                .collect();
+
+            let tuples = joined.arrange_by_symbols(&key_symbols).join_core(
+                &right.arrange_by_symbols(&key_symbols),
+                |key, v1, v2| {
+                    Some(
+                        key.iter()
+                            .cloned()
+                            .chain(v1.iter().cloned())
+                            .chain(v2.iter().cloned())
+                            .collect(),
+                    )
+                },
+            );
+
+            joined = CollectionRelation { symbols, tuples };
+        }
+
+        let tuples = joined
+            .tuples_by_symbols(&self.variables)
+            .map(|(key, _rest)| key);
+
+        Ok(CollectionRelation {
+            symbols: self.variables.to_vec(),
+            tuples,
+        })
+    }
+}
+
+/// The error reported for joins without any inputs.
+pub(crate) fn empty_join() -> Error {
+    Error {
+        category: "df.error.category/incorrect",
+        message: "MultiJoin requires at least one input.".to_string(),
+    }
+}
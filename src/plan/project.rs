@@ -4,9 +4,9 @@ use timely::dataflow::scopes::child::Iterative;
 use timely::dataflow::Scope;
 
 use crate::binding::Binding;
-use crate::plan::{next_id, ImplContext, Implementable};
+use crate::plan::{content_id, ImplContext, Implementable};
 use crate::{Aid, Eid, Value, Var};
-use crate::{CollectionRelation, Relation, VariableMap};
+use crate::{CollectionRelation, Error, Relation, Time, VariableMap};
 
 /// A plan stage projecting its source to only the specified sequence
 /// of symbols. Throws on unbound symbols. Frontends are responsible
@@ -24,17 +24,17 @@ impl<P: Implementable> Implementable for Project<P> {
         self.plan.dependencies()
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
         self.plan.into_bindings()
     }
 
     fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
-        let eid = next_id();
         let mut data = self.plan.datafy();
 
         if data.is_empty() {
             Vec::new()
         } else {
+            let eid = content_id(&("df.project", &self.variables, &data));
             let child_eid = data[0].0;
 
             data.push((eid, "df.project/binding".to_string(), Value::Eid(child_eid)));
@@ -43,20 +43,20 @@ impl<P: Implementable> Implementable for Project<P> {
         }
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
-        let relation = self.plan.implement(nested, local_arrangements, context);
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let relation = self.plan.implement(nested, local_arrangements, context)?;
         let tuples = relation
             .tuples_by_symbols(&self.variables)
             .map(|(key, _tuple)| key);
 
-        CollectionRelation {
+        Ok(CollectionRelation {
             symbols: self.variables.to_vec(),
             tuples,
-        }
+        })
     }
 }
@@ -3,11 +3,15 @@
 use timely::dataflow::operators::Concatenate;
 use timely::dataflow::scopes::child::Iterative;
 use timely::dataflow::Scope;
+use timely::order::Product;
 
-use differential_dataflow::AsCollection;
+use differential_dataflow::operators::arrange::{Arrange, ArrangeBySelf, Arranged, TraceAgent};
+use differential_dataflow::operators::{JoinCore, Threshold};
+use differential_dataflow::trace::implementations::ord::OrdValSpine;
+use differential_dataflow::{AsCollection, Collection};
 
-use crate::plan::{ImplContext, Implementable};
-use crate::{Aid, CollectionRelation, Relation, Value, Var, VariableMap};
+use crate::plan::{attribute_not_found, ImplContext, Implementable};
+use crate::{Aid, CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
 
 /// A plan stage for extracting all matching [e a v] tuples for a
 /// given set of attributes and an input relation specifying entities.
@@ -17,11 +21,38 @@ pub struct PullLevel<P: Implementable> {
     pub variables: Vec<Var>,
     /// Plan for the input relation.
     pub plan: Box<P>,
-    /// Attributes to pull for the input entities.
+    /// Attributes to pull for the input entities. The wildcard `*`
+    /// stands for all attributes.
     pub pull_attributes: Vec<Aid>,
     /// Attribute names to distinguish plans of the same
     /// length. Useful to feed into a nested hash-map directly.
     pub path_attributes: Vec<Aid>,
+    /// Should input entities without any datoms on the pulled
+    /// attributes be marked via a `df.pull/unresolved` placeholder,
+    /// rather than being omitted?
+    #[serde(default)]
+    pub mark_unresolved: bool,
+    /// Reference attribute to follow recursively from the pulled
+    /// entities, pulling the same attributes at each level.
+    #[serde(default)]
+    pub recursion: Option<PullRecursion>,
+    /// Should each result be prefixed by its path schema? Paths of
+    /// different depths interleave results of different arities, so
+    /// the prefix lets clients dispatch without inferring structure
+    /// from arity. The prefix holds the number of path attributes as
+    /// a `Number`, followed by the path attributes themselves.
+    #[serde(default)]
+    pub envelope: bool,
+}
+
+/// A bounded recursive pull spec, following a reference attribute
+/// (e.g. `parent/child`) up to a maximum depth.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct PullRecursion {
+    /// Reference attribute to follow.
+    pub attribute: Aid,
+    /// Maximum number of times to follow it.
+    pub depth: usize,
 }
 
 /// A plan stage for pull queries split into individual paths. So
@@ -66,90 +97,290 @@ fn interleave(values: &[Value], constants: &[Aid]) -> Vec<Value> {
     }
 }
 
+/// Prefixes each result with the schema of the path it was pulled
+/// along, i.e. the number of path attributes followed by the path
+/// attributes themselves.
+fn envelop<'b, S: Scope<Timestamp = Time>>(
+    results: Collection<Iterative<'b, S, u64>, Vec<Value>, isize>,
+    path_attributes: &[Aid],
+) -> Collection<Iterative<'b, S, u64>, Vec<Value>, isize> {
+    let mut schema = Vec::with_capacity(path_attributes.len() + 1);
+    schema.push(Value::Number(path_attributes.len() as i64));
+    schema.extend(path_attributes.iter().cloned().map(Value::Aid));
+
+    results.map(move |tuple| schema.iter().cloned().chain(tuple).collect())
+}
+
+/// Pulls the specified attributes for the entity at the end of each
+/// path. Result tuples hold the interleaved path, the attribute, and
+/// the value, optionally prefixed by the path schema.
+fn pull_paths<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+    paths: Collection<Iterative<'b, S, u64>, Vec<Value>, isize>,
+    e_path: Option<&PathsByEntity<'b, S>>,
+    path_attributes: &[Aid],
+    pull_attributes: &[Aid],
+    mark_unresolved: bool,
+    envelope: bool,
+    nested: &mut Iterative<'b, S, u64>,
+    context: &mut I,
+) -> Result<Collection<Iterative<'b, S, u64>, Vec<Value>, isize>, Error> {
+    let results = pull_without_envelope(
+        paths,
+        e_path,
+        path_attributes,
+        pull_attributes,
+        mark_unresolved,
+        nested,
+        context,
+    )?;
+
+    if envelope {
+        Ok(envelop(results, path_attributes))
+    } else {
+        Ok(results)
+    }
+}
+
+/// Pulls the specified attributes for the entity at the end of each
+/// path, without prefixing results by their path schema. Paths have
+/// to be arranged by entity, unless there is nothing to pull.
+fn pull_without_envelope<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+    paths: Collection<Iterative<'b, S, u64>, Vec<Value>, isize>,
+    e_path: Option<&PathsByEntity<'b, S>>,
+    path_attributes: &[Aid],
+    pull_attributes: &[Aid],
+    mark_unresolved: bool,
+    nested: &mut Iterative<'b, S, u64>,
+    context: &mut I,
+) -> Result<Collection<Iterative<'b, S, u64>, Vec<Value>, isize>, Error> {
+    if pull_attributes.is_empty() {
+        let path_attributes = path_attributes.to_vec();
+        return Ok(paths.map(move |tuple| interleave(&tuple, &path_attributes)));
+    }
+
+    let e_path = e_path.expect("Paths must be arranged by entity.");
+
+    let mut resolved = Vec::new();
+    let shutdown_handle = context.shutdown_handle();
+
+    let mut streams: Vec<_> = pull_attributes
+        .iter()
+        .map(|a| {
+            let (e_v, button) = match context.forward_index(a) {
+                None => return Err(attribute_not_found(a)),
+                Some(index) => index.propose_trace.import_core(&nested.parent, a),
+            };
+            shutdown_handle.add_button(button);
+
+            let e_v = e_v.enter(nested);
+
+            if mark_unresolved {
+                resolved.push(e_v.as_collection(|e, _v| e.clone()).inner);
+            }
+
+            let attribute = Value::Aid(a.clone());
+            let path_attributes = path_attributes.to_vec();
+
+            Ok(e_path
+                .join_core(&e_v, move |_e, path: &Vec<Value>, v: &Value| {
+                    // Each result tuple must hold the interleaved
+                    // path, the attribute, and the value,
+                    // i.e. [?p "parent/child" ?c ?a ?v]
+                    let mut result = interleave(path, &path_attributes);
+                    result.push(attribute.clone());
+                    result.push(v.clone());
+
+                    Some(result)
+                })
+                .inner)
+        })
+        .collect::<Result<_, _>>()?;
+
+    if mark_unresolved {
+        // Entities which haven't arrived yet (or have been
+        // deleted) are reported as such, s.t. clients can
+        // tell them apart from entities without children.
+        //
+        // The antijoin is spelled out in terms of the existing
+        // arrangement, s.t. paths aren't exchanged once more.
+        let resolved = nested
+            .concatenate(resolved)
+            .as_collection()
+            .distinct()
+            .arrange_by_self();
+        let path_attributes = path_attributes.to_vec();
+
+        let resolved_paths =
+            e_path.join_core(&resolved, |_e, path: &Vec<Value>, _| Some(path.clone()));
+
+        let unresolved = paths.concat(&resolved_paths.negate()).map(move |path| {
+            let mut result = interleave(&path, &path_attributes);
+            result.push(Value::Aid("df.pull/unresolved".to_string()));
+            result.push(Value::Bool(true));
+
+            result
+        });
+
+        streams.push(unresolved.inner);
+    }
+
+    Ok(nested.concatenate(streams).as_collection())
+}
+
+/// Paths arranged by the entity they end in.
+type PathsByEntity<'b, S> = Arranged<
+    Iterative<'b, S, u64>,
+    Value,
+    Vec<Value>,
+    isize,
+    TraceAgent<
+        Value,
+        Vec<Value>,
+        Product<Time, u64>,
+        isize,
+        OrdValSpine<Value, Vec<Value>, Product<Time, u64>, isize>,
+    >,
+>;
+
+/// Arranges paths by the entity they end in.
+fn arrange_by_entity<'b, S: Scope<Timestamp = Time>>(
+    paths: &Collection<Iterative<'b, S, u64>, Vec<Value>, isize>,
+) -> PathsByEntity<'b, S> {
+    paths.map(|t| (t.last().unwrap().clone(), t)).arrange()
+}
+
 impl<P: Implementable> Implementable for PullLevel<P> {
     fn dependencies(&self) -> Vec<String> {
         Vec::new()
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
-        use timely::order::Product;
-
-        use differential_dataflow::operators::arrange::{Arrange, Arranged, TraceAgent};
-        use differential_dataflow::operators::JoinCore;
-        use differential_dataflow::trace::implementations::ord::OrdValSpine;
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let input = self.plan.implement(nested, local_arrangements, context)?;
 
-        let input = self.plan.implement(nested, local_arrangements, context);
+        if self.pull_attributes.is_empty()
+            && self.path_attributes.is_empty()
+            && self.recursion.is_none()
+        {
+            // nothing to pull
+            return Ok(input);
+        }
 
-        if self.pull_attributes.is_empty() {
-            if self.path_attributes.is_empty() {
-                // nothing to pull
-                input
+        // The wildcard stands for all attributes known at the time
+        // this level is implemented, except for built-in (`df.`) ones.
+        let mut pull_attributes: Vec<Aid> = Vec::new();
+        for a in self.pull_attributes.iter() {
+            let mut expanded = if a == "*" {
+                context
+                    .attributes()
+                    .into_iter()
+                    .filter(|a| !a.starts_with("df."))
+                    .collect()
             } else {
-                let path_attributes = self.path_attributes.clone();
-                let tuples = input
-                    .tuples()
-                    .map(move |tuple| interleave(&tuple, &path_attributes));
+                vec![a.clone()]
+            };
+            expanded.sort();
 
-                CollectionRelation {
-                    symbols: vec![],
-                    tuples,
+            for a in expanded.drain(..) {
+                if !pull_attributes.contains(&a) {
+                    pull_attributes.push(a);
                 }
             }
+        }
+
+        let mut paths = input.tuples();
+        let mut path_attributes = self.path_attributes.clone();
+
+        let depth = self
+            .recursion
+            .as_ref()
+            .map_or(0, |recursion| recursion.depth);
+
+        // Paths are arranged by the entity they end in once per
+        // level of recursion, and that arrangement is shared between
+        // pulling attributes and following the reference attribute.
+        // Only paths extended by referenced entities are exchanged
+        // again.
+        let needs_arrangement = |level: usize| !pull_attributes.is_empty() || level < depth;
+
+        let mut e_path = if needs_arrangement(0) {
+            Some(arrange_by_entity(&paths))
         } else {
-            // Arrange input entities by eid.
-            let paths = input.tuples();
-            let e_path: Arranged<
-                Iterative<S, u64>,
-                Value,
-                Vec<Value>,
-                isize,
-                TraceAgent<
-                    Value,
-                    Vec<Value>,
-                    Product<u64, u64>,
-                    isize,
-                    OrdValSpine<Value, Vec<Value>, Product<u64, u64>, isize>,
-                >,
-            > = paths.map(|t| (t.last().unwrap().clone(), t)).arrange();
-
-            let streams = self.pull_attributes.iter().map(|a| {
-                let e_v = match context.forward_index(a) {
-                    None => panic!("attribute {:?} does not exist", a),
+            None
+        };
+
+        let mut streams = vec![
+            pull_paths(
+                paths.clone(),
+                e_path.as_ref(),
+                &path_attributes,
+                &pull_attributes,
+                self.mark_unresolved,
+                self.envelope,
+                nested,
+                context,
+            )?
+            .inner,
+        ];
+
+        if let Some(ref recursion) = self.recursion {
+            // Each level of recursion follows the reference
+            // attribute once more, extending the paths by the
+            // referenced entities.
+            for level in 1..=recursion.depth {
+                let shutdown_handle = context.shutdown_handle();
+                let (e_child, button) = match context.forward_index(&recursion.attribute) {
+                    None => return Err(attribute_not_found(&recursion.attribute)),
                     Some(index) => index
                         .propose_trace
-                        .import_named(&nested.parent, a)
-                        .enter(nested),
+                        .import_core(&nested.parent, &recursion.attribute),
                 };
+                shutdown_handle.add_button(button);
 
-                let attribute = Value::Aid(a.clone());
-                let path_attributes: Vec<Aid> = self.path_attributes.clone();
+                let e_child = e_child.enter(nested);
 
-                e_path
-                    .join_core(&e_v, move |_e, path: &Vec<Value>, v: &Value| {
-                        // Each result tuple must hold the interleaved
-                        // path, the attribute, and the value,
-                        // i.e. [?p "parent/child" ?c ?a ?v]
-                        let mut result = interleave(path, &path_attributes);
-                        result.push(attribute.clone());
-                        result.push(v.clone());
+                paths = e_path
+                    .expect("Paths must be arranged by entity.")
+                    .join_core(&e_child, |_e, path: &Vec<Value>, child: &Value| {
+                        let mut result = path.clone();
+                        result.push(child.clone());
 
                         Some(result)
-                    })
-                    .inner
-            });
+                    });
+                path_attributes.push(recursion.attribute.clone());
 
-            let tuples = nested.concatenate(streams).as_collection();
+                e_path = if needs_arrangement(level) {
+                    Some(arrange_by_entity(&paths))
+                } else {
+                    None
+                };
 
-            CollectionRelation {
-                symbols: vec![], // @TODO
-                tuples,
+                streams.push(
+                    pull_paths(
+                        paths.clone(),
+                        e_path.as_ref(),
+                        &path_attributes,
+                        &pull_attributes,
+                        self.mark_unresolved,
+                        self.envelope,
+                        nested,
+                        context,
+                    )?
+                    .inner,
+                );
             }
         }
+
+        let tuples = nested.concatenate(streams).as_collection();
+
+        Ok(CollectionRelation {
+            symbols: vec![], // @TODO
+            tuples,
+        })
     }
 }
 
@@ -158,24 +389,28 @@ impl<P: Implementable> Implementable for Pull<P> {
         Vec::new()
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
         let mut scope = nested.clone();
-        let streams = self.paths.iter().map(|path| {
-            path.implement(&mut scope, local_arrangements, context)
-                .tuples()
-                .inner
-        });
+        let mut streams = Vec::with_capacity(self.paths.len());
+
+        for path in self.paths.iter() {
+            streams.push(
+                path.implement(&mut scope, local_arrangements, context)?
+                    .tuples()
+                    .inner,
+            );
+        }
 
         let tuples = nested.concatenate(streams).as_collection();
 
-        CollectionRelation {
+        Ok(CollectionRelation {
             symbols: vec![], // @TODO
             tuples,
-        }
+        })
     }
 }
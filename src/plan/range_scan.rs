@@ -0,0 +1,136 @@
+//! Range scan expression plan.
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
+use timely::dataflow::scopes::child::Iterative;
+use timely::dataflow::Scope;
+
+use differential_dataflow::trace::{BatchReader, Cursor};
+use differential_dataflow::{AsCollection, Collection};
+
+use crate::plan::{attribute_not_found, content_id, ImplContext, Implementable};
+use crate::{Aid, CollectionRelation, Eid, Error, Time, Value, Var, VariableMap};
+
+/// A plan stage binding all (e,v) pairs of an attribute whose values
+/// lie within a range, which includes its upper bound unless
+/// `upper_exclusive` is set. Instead of filtering every value, each
+/// batch of the attribute's reverse index is scanned starting at the
+/// lower bound, up until the upper bound.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct RangeScan {
+    /// Symbols to which the eid and the value are bound
+    pub variables: Vec<Var>,
+    /// Attribute to scan
+    pub attribute: Aid,
+    /// Smallest value to include, unbounded if not specified
+    pub lower: Option<Value>,
+    /// Largest value to include, unbounded if not specified
+    pub upper: Option<Value>,
+    /// Whether values equal to the upper bound are excluded, i.e.
+    /// whether the range is half-open
+    #[serde(default)]
+    pub upper_exclusive: bool,
+}
+
+impl Implementable for RangeScan {
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn datafy(&self) -> Vec<(Eid, Aid, Value)> {
+        vec![(
+            content_id(self),
+            "df.pattern/a".to_string(),
+            Value::Aid(self.attribute.to_string()),
+        )]
+    }
+
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+        &self,
+        nested: &mut Iterative<'b, S, u64>,
+        _local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
+        context: &mut I,
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let tuples = scan(
+            nested,
+            context,
+            &self.attribute,
+            self.lower.clone(),
+            self.upper.clone(),
+            !self.upper_exclusive,
+        )?;
+
+        Ok(CollectionRelation {
+            symbols: self.variables.clone(),
+            tuples,
+        })
+    }
+}
+
+/// Binds all (e,v) pairs of an attribute whose values lie within the
+/// specified bounds, by seeking each batch of the attribute's reverse
+/// index. The lower bound is always inclusive. Bounds are coerced to
+/// the type declared for the attribute, if any.
+fn scan<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
+    nested: &mut Iterative<'b, S, u64>,
+    context: &mut I,
+    attribute: &str,
+    lower: Option<Value>,
+    upper: Option<Value>,
+    upper_inclusive: bool,
+) -> Result<Collection<Iterative<'b, S, u64>, Vec<Value>, isize>, Error> {
+    // Values of typed attributes are all of the same kind, bounds of
+    // a different kind would seek past all of them.
+    let (lower, upper) = match context.value_type(attribute) {
+        None => (lower, upper),
+        Some(typ) => {
+            let coerce = |bound: Value| typ.coerce(bound.clone()).unwrap_or(bound);
+            (lower.map(coerce), upper.map(coerce))
+        }
+    };
+
+    let shutdown_handle = context.shutdown_handle();
+    let (proposals, button) = match context.reverse_index(attribute) {
+        None => return Err(attribute_not_found(attribute)),
+        Some(index) => index.propose_trace.import_core(&nested.parent, attribute),
+    };
+    shutdown_handle.add_button(button);
+
+    Ok(proposals
+        .stream
+        .unary(Pipeline, "RangeScan", move |_, _| {
+            move |input, output| {
+                input.for_each(|time, data| {
+                    let mut session = output.session(&time);
+
+                    for batch in data.iter() {
+                        let mut cursor = batch.cursor();
+
+                        if let Some(ref lower) = lower {
+                            cursor.seek_key(batch, lower);
+                        }
+
+                        while let Some(v) = cursor.get_key(batch) {
+                            if let Some(ref upper) = upper {
+                                if v > upper || (v == upper && !upper_inclusive) {
+                                    break;
+                                }
+                            }
+
+                            while let Some(e) = cursor.get_val(batch) {
+                                cursor.map_times(batch, |t, diff| {
+                                    session.give(((e.clone(), v.clone()), t.clone(), diff));
+                                });
+                                cursor.step_val(batch);
+                            }
+
+                            cursor.step_key(batch);
+                        }
+                    }
+                });
+            }
+        })
+        .as_collection()
+        .enter(nested)
+        .map(|(e, v)| vec![e, v]))
+}
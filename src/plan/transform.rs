@@ -1,11 +1,20 @@
 //! Function expression plan.
 
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::{Map, Operator};
 use timely::dataflow::scopes::child::Iterative;
 use timely::dataflow::Scope;
+use timely::scheduling::Scheduler;
+
+use differential_dataflow::AsCollection;
 
 use crate::binding::Binding;
+use crate::keys::KeyEids;
 use crate::plan::{ImplContext, Implementable};
-use crate::{CollectionRelation, Relation, Value, Var, VariableMap};
+use crate::udf::Background;
+use crate::{CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
 
 /// Permitted functions.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
@@ -16,6 +25,13 @@ pub enum Function {
     ADD,
     /// Subtracts one or more numbers from the first provided
     SUBTRACT,
+    /// Concatenates two or more strings, in argument order
+    CONCAT,
+    /// Derives an entity id from a string key, see `keys::key_eid`
+    EID,
+    /// Calls the named external function (see `udf`), which might
+    /// produce any number of results per tuple
+    EXTERNAL(String),
 }
 
 /// A plan stage applying a built-in function to source tuples.
@@ -24,7 +40,7 @@ pub enum Function {
 /// the right symbol.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Transform<P: Implementable> {
-    /// TODO
+    /// Symbols whose values are passed as arguments, in order
     pub variables: Vec<Var>,
     /// Symbol to which the result of the transformation is bound
     pub result_sym: Var,
@@ -32,7 +48,8 @@ pub struct Transform<P: Implementable> {
     pub plan: Box<P>,
     /// Function to apply
     pub function: Function,
-    /// Constant inputs
+    /// Constant inputs, by argument position. Positions holding
+    /// `None` are filled from `variables`, in order.
     pub constants: Vec<Option<Value>>,
 }
 
@@ -41,17 +58,17 @@ impl<P: Implementable> Implementable for Transform<P> {
         self.plan.dependencies()
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
         self.plan.into_bindings()
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
-        let rel = self.plan.implement(nested, local_arrangements, context);
+    ) -> Result<CollectionRelation<'b, S>, Error> {
+        let rel = self.plan.implement(nested, local_arrangements, context)?;
 
         let key_offsets: Vec<usize> = self
             .variables
@@ -60,54 +77,104 @@ impl<P: Implementable> Implementable for Transform<P> {
                 rel.symbols()
                     .iter()
                     .position(|&v| *sym == v)
-                    .expect("Symbol not found.")
+                    .ok_or_else(|| Error {
+                        category: "df.error.category/incorrect",
+                        message: format!("Transform of unbound symbol {}.", sym),
+                    })
             })
-            .collect();
+            .collect::<Result<_, _>>()?;
 
         let mut symbols = rel.symbols().to_vec().clone();
         symbols.push(self.result_sym);
 
         let constants_local = self.constants.clone();
 
+        // positions without a constant are filled from the variables
+        let arguments = self.constants.iter().filter(|arg| arg.is_none()).count();
+        let expected = match self.function {
+            Function::SUBTRACT if self.constants.get(0).map_or(false, Option::is_some) => 0,
+            Function::TRUNCATE | Function::SUBTRACT => 1,
+            Function::EID if self.constants.get(0).map_or(true, Option::is_none) => 1,
+            _ => arguments,
+        };
+
+        if key_offsets.len() < expected {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Missing argument for {:?}.", self.function),
+            });
+        }
+
         match self.function {
-            Function::TRUNCATE => CollectionRelation {
-                symbols,
-                tuples: rel.tuples().map(move |tuple| {
-                    let mut t = match tuple[key_offsets[0]] {
-                        Value::Instant(inst) => inst as u64,
-                        _ => panic!("TRUNCATE can only be applied to timestamps"),
-                    };
-                    let default_interval = String::from(":hour");
-                    let interval_param = match constants_local[1].clone() {
-                        Some(Value::String(interval)) => interval,
-                        None => default_interval,
-                        _ => panic!("Parameter for TRUNCATE must be a string"),
-                    };
+            Function::ADD | Function::SUBTRACT => {
+                check_constants(&self.function, &self.constants, "numbers", |c| match c {
+                    Value::Number(_) => true,
+                    _ => false,
+                })?
+            }
+            Function::CONCAT | Function::EID => {
+                check_constants(&self.function, &self.constants, "strings", |c| match c {
+                    Value::String(_) => true,
+                    _ => false,
+                })?
+            }
+            _ => {}
+        }
 
-                    let mod_val = match interval_param.as_ref() {
+        let relation = match self.function {
+            Function::TRUNCATE => {
+                let mod_val = match self.constants.get(1) {
+                    None | Some(None) => 3_600_000,
+                    Some(Some(Value::String(interval))) => match interval.as_ref() {
                         ":minute" => 60000,
                         ":hour" => 3_600_000,
                         ":day" => 86_400_000,
                         ":week" => 604_800_000,
-                        _ => panic!("Unknown interval for TRUNCATE"),
-                    };
+                        _ => {
+                            return Err(Error {
+                                category: "df.error.category/incorrect",
+                                message: format!("Unknown interval {} for TRUNCATE.", interval),
+                            });
+                        }
+                    },
+                    Some(Some(_)) => {
+                        return Err(Error {
+                            category: "df.error.category/incorrect",
+                            message: "Parameter for TRUNCATE must be a string.".to_string(),
+                        });
+                    }
+                };
 
-                    t = t - (t % mod_val);
-                    let mut v = tuple.clone();
-                    v.push(Value::Instant(t));
-                    v
-                }),
-            },
+                CollectionRelation {
+                    symbols,
+                    tuples: rel.tuples().flat_map(move |tuple| {
+                        let t = match tuple[key_offsets[0]] {
+                            Value::Instant(inst) => inst as u64,
+                            _ => {
+                                error!("[TRANSFORM] TRUNCATE can only be applied to timestamps, dropping {:?}", tuple);
+                                return None;
+                            }
+                        };
+
+                        let mut v = tuple.clone();
+                        v.push(Value::Instant(t - (t % mod_val)));
+                        Some(v)
+                    }),
+                }
+            }
             Function::ADD => CollectionRelation {
                 symbols,
-                tuples: rel.tuples().map(move |tuple| {
+                tuples: rel.tuples().flat_map(move |tuple| {
                     let mut result = 0;
 
                     // summands (vars)
                     for offset in &key_offsets {
                         let summand = match tuple[*offset] {
                             Value::Number(s) => s as i64,
-                            _ => panic!("ADD can only be applied to numbers"),
+                            _ => {
+                                error!("[TRANSFORM] ADD can only be applied to numbers, dropping {:?}", tuple);
+                                return None;
+                            }
                         };
 
                         result += summand;
@@ -115,36 +182,33 @@ impl<P: Implementable> Implementable for Transform<P> {
 
                     // summands (constants)
                     for arg in &constants_local {
-                        if let Some(constant) = arg {
-                            let summand = match constant {
-                                Value::Number(s) => *s as i64,
-                                _ => panic!("ADD can only be applied to numbers"),
-                            };
-
-                            result += summand;
+                        if let Some(Value::Number(s)) = arg {
+                            result += *s as i64;
                         }
                     }
 
                     let mut v = tuple.clone();
                     v.push(Value::Number(result));
-                    v
+                    Some(v)
                 }),
             },
             Function::SUBTRACT => CollectionRelation {
                 symbols,
-                tuples: rel.tuples().map(move |tuple| {
+                tuples: rel.tuples().flat_map(move |tuple| {
                     // minuend is either symbol or variable, depending on
                     // position in transform
 
-                    let mut result = match constants_local[0].clone() {
-                        Some(constant) => match constant {
-                            Value::Number(minuend) => minuend as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
-                        },
-                        None => match tuple[key_offsets[0]] {
-                            Value::Number(minuend) => minuend as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
-                        },
+                    let minuend = match constants_local.get(0) {
+                        Some(Some(constant)) => constant,
+                        _ => &tuple[key_offsets[0]],
+                    };
+
+                    let mut result = match minuend {
+                        Value::Number(minuend) => *minuend as i64,
+                        _ => {
+                            error!("[TRANSFORM] SUBTRACT can only be applied to numbers, dropping {:?}", tuple);
+                            return None;
+                        }
                     };
 
                     // avoid filtering out the minuend by doubling it
@@ -154,7 +218,10 @@ impl<P: Implementable> Implementable for Transform<P> {
                     for offset in &key_offsets {
                         let subtrahend = match tuple[*offset] {
                             Value::Number(s) => s as i64,
-                            _ => panic!("SUBTRACT can only be applied to numbers"),
+                            _ => {
+                                error!("[TRANSFORM] SUBTRACT can only be applied to numbers, dropping {:?}", tuple);
+                                return None;
+                            }
                         };
 
                         result -= subtrahend;
@@ -162,21 +229,248 @@ impl<P: Implementable> Implementable for Transform<P> {
 
                     // subtrahends (constants)
                     for arg in &constants_local {
-                        if let Some(constant) = arg {
-                            let subtrahend = match constant {
-                                Value::Number(s) => *s as i64,
-                                _ => panic!("SUBTRACT can only be applied to numbers"),
-                            };
-
-                            result -= subtrahend;
+                        if let Some(Value::Number(s)) = arg {
+                            result -= *s as i64;
                         }
                     }
 
                     let mut v = tuple.clone();
                     v.push(Value::Number(result));
-                    v
+                    Some(v)
                 }),
             },
-        }
+            Function::CONCAT => CollectionRelation {
+                symbols,
+                tuples: rel.tuples().flat_map(move |tuple| {
+                    let mut result = String::new();
+                    let mut offsets = key_offsets.iter();
+
+                    // arguments are either constants or the next
+                    // variable, depending on position in transform
+                    let mut parts = Vec::with_capacity(constants_local.len());
+
+                    for arg in constants_local.iter() {
+                        match arg {
+                            Some(constant) => parts.push(constant),
+                            None => match offsets.next() {
+                                Some(offset) => parts.push(&tuple[*offset]),
+                                None => {
+                                    error!("[TRANSFORM] CONCAT is missing an argument, dropping {:?}", tuple);
+                                    return None;
+                                }
+                            },
+                        }
+                    }
+
+                    // remaining variables
+                    for part in parts.into_iter().chain(offsets.map(|offset| &tuple[*offset])) {
+                        match part {
+                            Value::String(s) => result.push_str(s),
+                            _ => {
+                                error!("[TRANSFORM] CONCAT can only be applied to strings, dropping {:?}", tuple);
+                                return None;
+                            }
+                        }
+                    }
+
+                    let mut v = tuple.clone();
+                    v.push(Value::String(result));
+                    Some(v)
+                }),
+            },
+            Function::EID => {
+                let mut eids = KeyEids::new();
+
+                CollectionRelation {
+                    symbols,
+                    tuples: rel
+                        .tuples()
+                        .inner
+                        .flat_map(move |(tuple, time, diff)| {
+                            let key = match constants_local.get(0) {
+                                Some(Some(constant)) => constant,
+                                _ => &tuple[key_offsets[0]],
+                            };
+
+                            let eid = match key {
+                                Value::String(ref key) => eids.eid(key),
+                                _ => {
+                                    error!("[TRANSFORM] EID can only be applied to strings, dropping {:?}", tuple);
+                                    return None;
+                                }
+                            };
+
+                            let mut v = tuple.clone();
+                            v.push(Value::Eid(eid));
+                            Some((v, time, diff))
+                        })
+                        .as_collection(),
+                }
+            }
+            Function::EXTERNAL(ref name) => {
+                let function = context
+                    .external_function(name)
+                    .ok_or_else(|| Error {
+                        category: "df.error.category/not-found",
+                        message: format!("Unknown external function {}.", name),
+                    })?
+                    .clone();
+
+                let name = name.clone();
+                let scope = rel.tuples().inner.scope();
+                let mut buffer = Vec::new();
+
+                CollectionRelation {
+                    symbols,
+                    tuples: rel
+                        .tuples()
+                        .inner
+                        .unary(Pipeline, "External", move |_, info| {
+                            let process =
+                                Background::new(function, scope.sync_activator_for(&info.address[..]));
+
+                            // results per argument list, along with the
+                            // number of tuples they have been produced for
+                            let mut cache: HashMap<Vec<Value>, (Vec<Value>, isize)> = HashMap::new();
+                            // additions waiting to be processed, by
+                            // argument list, which keep cached results
+                            // from being released in the meantime
+                            let mut pinned: HashMap<Vec<Value>, usize> = HashMap::new();
+                            let mut pending = VecDeque::new();
+
+                            move |input, output| {
+                                input.for_each(|time, data| {
+                                    data.swap(&mut buffer);
+
+                                    let mut batch = Vec::with_capacity(buffer.len());
+                                    let mut missing = Vec::new();
+                                    let mut requested = HashSet::new();
+
+                                    for (tuple, t, diff) in buffer.drain(..) {
+                                        // arguments are either constants or
+                                        // the next variable, by position
+                                        let mut offsets = key_offsets.iter();
+                                        let mut arguments: Vec<Value> = constants_local
+                                            .iter()
+                                            .map(|arg| match arg {
+                                                Some(constant) => constant.clone(),
+                                                None => tuple[*offsets.next().unwrap()].clone(),
+                                            })
+                                            .collect();
+
+                                        arguments.extend(offsets.map(|offset| tuple[*offset].clone()));
+
+                                        // only additions with unknown
+                                        // arguments are sent to the process
+                                        if diff > 0 {
+                                            *pinned.entry(arguments.clone()).or_insert(0) += 1;
+
+                                            if !cache.contains_key(&arguments)
+                                                && requested.insert(arguments.clone())
+                                            {
+                                                missing.push(arguments.clone());
+                                            }
+                                        }
+
+                                        batch.push((tuple, arguments, t, diff));
+                                    }
+
+                                    process.call(missing.clone());
+                                    pending.push_back((time.retain(), batch, missing));
+                                });
+
+                                // responses arrive in request order, s.t.
+                                // additions are resolved before their
+                                // retractions
+                                while !pending.is_empty() {
+                                    let response = match process.try_recv() {
+                                        None => break,
+                                        Some(response) => response,
+                                    };
+
+                                    let (capability, batch, missing) = pending.pop_front().unwrap();
+
+                                    let fresh: HashMap<Vec<Value>, Vec<Value>> = match response {
+                                        Ok(results) => missing.into_iter().zip(results).collect(),
+                                        Err(err) => {
+                                            error!(
+                                                "[UDF] {} dropped {} tuples: {}",
+                                                name,
+                                                missing.len(),
+                                                err
+                                            );
+                                            HashMap::new()
+                                        }
+                                    };
+
+                                    let mut session = output.session(&capability);
+
+                                    for (tuple, arguments, t, diff) in batch {
+                                        if diff > 0 {
+                                            let pins = pinned.get_mut(&arguments).unwrap();
+                                            *pins -= 1;
+
+                                            if *pins == 0 {
+                                                pinned.remove(&arguments);
+                                            }
+                                        }
+
+                                        if !cache.contains_key(&arguments) {
+                                            match fresh.get(&arguments) {
+                                                // retractions of dropped
+                                                // additions are dropped too
+                                                None => continue,
+                                                Some(results) => {
+                                                    cache.insert(arguments.clone(), (results.clone(), 0));
+                                                }
+                                            }
+                                        }
+
+                                        let released = {
+                                            let (results, count) = cache.get_mut(&arguments).unwrap();
+
+                                            for result in results.iter() {
+                                                let mut v = tuple.clone();
+                                                v.push(result.clone());
+                                                session.give((v, t.clone(), diff));
+                                            }
+
+                                            *count += diff;
+                                            *count <= 0 && !pinned.contains_key(&arguments)
+                                        };
+
+                                        if released {
+                                            cache.remove(&arguments);
+                                        }
+                                    }
+                                }
+                            }
+                        })
+                        .as_collection(),
+                }
+            }
+        };
+
+        Ok(relation)
+    }
+}
+
+/// Ensures that all constant arguments are of the kind a function
+/// can be applied to.
+fn check_constants(
+    function: &Function,
+    constants: &[Option<Value>],
+    kind: &str,
+    valid: fn(&Value) -> bool,
+) -> Result<(), Error> {
+    match constants.iter().flatten().find(|c| !valid(c)) {
+        None => Ok(()),
+        Some(constant) => Err(Error {
+            category: "df.error.category/incorrect",
+            message: format!(
+                "{:?} can only be applied to {}, not {:?}.",
+                function, kind, constant
+            ),
+        }),
     }
 }
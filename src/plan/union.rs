@@ -7,17 +7,22 @@ use differential_dataflow::operators::Threshold;
 
 use crate::binding::Binding;
 use crate::plan::{ImplContext, Implementable};
-use crate::{CollectionRelation, Relation, Var, VariableMap};
+use crate::{CollectionRelation, Error, Relation, Time, Value, Var, VariableMap};
 
-/// A plan stage taking the union over its sources. Frontends are
-/// responsible to ensure that the sources are union-compatible
-/// (i.e. bind all of the same symbols in the same order).
+/// A plan stage taking the union over its sources. Each source is
+/// projected onto `variables`, so sources may bind additional
+/// symbols, in any order. Symbols a source doesn't bind at all are
+/// filled in with the `fill` value, if one is specified.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Union<P: Implementable> {
     /// TODO
     pub variables: Vec<Var>,
     /// Plan for the data source.
     pub plans: Vec<P>,
+    /// Value to bind to symbols not bound by a source. Sources
+    /// must bind all symbols, if this isn't specified.
+    #[serde(default)]
+    pub fill: Option<Value>,
 }
 
 impl<P: Implementable> Implementable for Union<P> {
@@ -31,38 +36,90 @@ impl<P: Implementable> Implementable for Union<P> {
         dependencies
     }
 
-    fn into_bindings(&self) -> Vec<Binding> {
+    fn into_bindings(&self) -> Result<Vec<Binding>, Error> {
         let mut bindings = Vec::new();
 
         for plan in self.plans.iter() {
-            bindings.append(&mut plan.into_bindings());
+            bindings.append(&mut plan.into_bindings()?);
         }
 
-        bindings
+        Ok(bindings)
     }
 
-    fn implement<'b, S: Scope<Timestamp = u64>, I: ImplContext>(
+    fn implement<'b, S: Scope<Timestamp = Time>, I: ImplContext>(
         &self,
         nested: &mut Iterative<'b, S, u64>,
         local_arrangements: &VariableMap<Iterative<'b, S, u64>>,
         context: &mut I,
-    ) -> CollectionRelation<'b, S> {
+    ) -> Result<CollectionRelation<'b, S>, Error> {
         use differential_dataflow::AsCollection;
         use timely::dataflow::operators::Concatenate;
 
         let mut scope = nested.clone();
-        let streams = self.plans.iter().map(|plan| {
-            plan.implement(&mut scope, local_arrangements, context)
-                .tuples_by_symbols(&self.variables)
-                .map(|(key, _vals)| key)
-                .inner
-        });
+        let mut streams = Vec::with_capacity(self.plans.len());
+
+        for plan in self.plans.iter() {
+            let relation = plan.implement(&mut scope, local_arrangements, context)?;
+
+            // Align the source onto the requested symbols, taking
+            // note of those it doesn't bind.
+            let (bound, missing): (Vec<Var>, Vec<Var>) = self
+                .variables
+                .iter()
+                .cloned()
+                .partition(|sym| relation.symbols().contains(sym));
+
+            if missing.is_empty() {
+                streams.push(
+                    relation
+                        .tuples_by_symbols(&self.variables)
+                        .map(|(key, _vals)| key)
+                        .inner,
+                );
+            } else {
+                let fill = match self.fill {
+                    None => {
+                        return Err(Error {
+                            category: "df.error.category/incorrect",
+                            message: format!(
+                                "Union source doesn't bind {:?} and no fill value was specified.",
+                                missing
+                            ),
+                        });
+                    }
+                    Some(ref fill) => fill.clone(),
+                };
+
+                // Offset of each requested symbol within the
+                // projected tuple, or None if it must be filled.
+                let offsets: Vec<Option<usize>> = self
+                    .variables
+                    .iter()
+                    .map(|sym| bound.iter().position(|other| other == sym))
+                    .collect();
+
+                streams.push(
+                    relation
+                        .tuples_by_symbols(&bound)
+                        .map(move |(key, _vals)| {
+                            offsets
+                                .iter()
+                                .map(|offset| match offset {
+                                    None => fill.clone(),
+                                    Some(offset) => key[*offset].clone(),
+                                })
+                                .collect()
+                        })
+                        .inner,
+                );
+            }
+        }
 
         let concat = nested.concatenate(streams).as_collection();
 
-        CollectionRelation {
+        Ok(CollectionRelation {
             symbols: self.variables.to_vec(),
             tuples: concat.distinct(),
-        }
+        })
     }
 }
@@ -0,0 +1,306 @@
+//! Resource accounting, for chargeback in deployments shared between
+//! several tenants.
+//!
+//! The work done by a dataflow (time spent running its operators and
+//! updates held by its arrangements, as observed via logging) is
+//! attributed to the relation it was implemented for. Relations it
+//! depends on and implements along the way are part of its cost.
+//! Results delivered are attributed to both the relation they belong
+//! to and the client receiving them. Each worker accounts for its
+//! own share, periodically recording it in the `df.usage.*`
+//! attributes (via the sequencer) and reporting it to all hooks
+//! installed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_dataflow::Hashable;
+
+use timely_sort::Unsigned;
+
+use crate::sinks::webhook::{parse_url, post};
+use crate::{Aid, Eid, Error, TxData, Value};
+
+/// Names of the attributes usage is recorded in. Each relation and
+/// client is identified by an entity per worker.
+pub const ATTRIBUTES: &[&str] = &[
+    "df.usage.rule/name",
+    "df.usage.rule/worker",
+    "df.usage.rule/elapsed",
+    "df.usage.rule/arranged",
+    "df.usage.rule/delivered",
+    "df.usage.client/id",
+    "df.usage.client/worker",
+    "df.usage.client/delivered",
+];
+
+/// Resources consumed on behalf of a single relation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct RuleUsage {
+    /// Nanoseconds spent running the operators of the dataflows
+    /// implemented for the relation.
+    pub elapsed: u64,
+    /// Number of updates held by the arrangements of those
+    /// dataflows.
+    pub arranged: i64,
+    /// Number of bytes of results (and frontier advancements)
+    /// delivered to clients.
+    pub delivered: u64,
+}
+
+/// Resources consumed on a single worker, since it started.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Usage {
+    /// The worker reporting.
+    pub worker: usize,
+    /// Resources consumed on behalf of each relation, by name.
+    pub rules: BTreeMap<String, RuleUsage>,
+    /// Number of bytes delivered to each client, by id.
+    pub clients: BTreeMap<usize, u64>,
+}
+
+/// Receives usage reports, e.g. to feed them into a billing system.
+/// Hooks are called from the worker, so they should hand off any
+/// slow work.
+pub trait UsageHook {
+    /// Called whenever the usage of a worker has changed, at most
+    /// once per accounting interval.
+    fn report(&mut self, usage: &Usage);
+}
+
+/// A hook posting each report as JSON to an HTTP endpoint, from a
+/// separate thread. Only plain http is supported.
+pub struct UsageWebhook {
+    outbox: Sender<String>,
+}
+
+impl UsageWebhook {
+    /// Creates a hook posting to the specified url.
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let (address, host, path) = parse_url(url)?;
+        let (send, recv) = channel::<String>();
+
+        thread::spawn(move || {
+            for payload in recv.iter() {
+                if let Err(err) = post(&address, &host, &path, &payload) {
+                    error!("[ACCOUNTING] failed to reach {}: {}", host, err);
+                }
+            }
+        });
+
+        Ok(UsageWebhook { outbox: send })
+    }
+}
+
+impl UsageHook for UsageWebhook {
+    fn report(&mut self, usage: &Usage) {
+        let payload = serde_json::to_string(usage).expect("failed to serialize usage");
+
+        if self.outbox.send(payload).is_err() {
+            error!("[ACCOUNTING] webhook thread terminated");
+        }
+    }
+}
+
+/// Keeps track of the resources consumed by a single worker.
+pub struct Accounting {
+    /// Minimum time between two reports.
+    interval: Duration,
+    /// The instant of the last report.
+    reported_at: Option<Instant>,
+    /// Addresses of dataflows, along with the relation each was
+    /// implemented for.
+    dataflows: Vec<(Vec<usize>, String)>,
+    /// Bytes delivered per relation.
+    delivered: HashMap<String, u64>,
+    /// Bytes delivered per client.
+    clients: HashMap<usize, u64>,
+    /// The usage reported last.
+    reported: Usage,
+    /// Hooks receiving every report.
+    pub hooks: Vec<Box<dyn UsageHook>>,
+}
+
+impl Accounting {
+    /// Creates an accounting reporting at most once per interval.
+    pub fn new(interval: Duration) -> Self {
+        Accounting {
+            interval,
+            reported_at: None,
+            dataflows: Vec::new(),
+            delivered: HashMap::new(),
+            clients: HashMap::new(),
+            reported: Usage::default(),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Attributes the dataflow at the specified address to the
+    /// specified relation, unless it has been attributed already.
+    pub fn attribute(&mut self, address: Vec<usize>, name: &str) {
+        if !self.dataflows.iter().any(|(known, _)| *known == address) {
+            self.dataflows.push((address, name.to_string()));
+        }
+    }
+
+    /// Accounts for results of the specified relation delivered to
+    /// the specified client.
+    pub fn deliver(&mut self, name: &str, client: usize, bytes: usize) {
+        *self.delivered.entry(name.to_string()).or_insert(0) += bytes as u64;
+        *self.clients.entry(client).or_insert(0) += bytes as u64;
+    }
+
+    /// Returns the current usage, given the address, total running
+    /// time, and arrangement size of each operator of this worker.
+    /// Operators of dataflows not attributed to any relation are not
+    /// accounted for.
+    pub fn usage(&self, worker: usize, operators: &[(Vec<usize>, Duration, isize)]) -> Usage {
+        let mut rules: BTreeMap<String, RuleUsage> = BTreeMap::new();
+
+        for (address, elapsed, size) in operators.iter() {
+            let dataflow = self
+                .dataflows
+                .iter()
+                .find(|(prefix, _)| address.len() > prefix.len() && address.starts_with(prefix));
+
+            if let Some((_, name)) = dataflow {
+                let usage = rules.entry(name.clone()).or_insert_with(RuleUsage::default);
+                usage.elapsed +=
+                    elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+                usage.arranged += *size as i64;
+            }
+        }
+
+        for (name, bytes) in self.delivered.iter() {
+            rules
+                .entry(name.clone())
+                .or_insert_with(RuleUsage::default)
+                .delivered += *bytes;
+        }
+
+        Usage {
+            worker,
+            rules,
+            clients: self
+                .clients
+                .iter()
+                .map(|(id, bytes)| (*id, *bytes))
+                .collect(),
+        }
+    }
+
+    /// Reports the current usage, if it has changed and the interval
+    /// has passed since the last report. Returns the changes to the
+    /// usage attributes it implies, as the retraction of the previous
+    /// totals and the assertion of the new ones.
+    pub fn check(
+        &mut self,
+        worker: usize,
+        operators: &[(Vec<usize>, Duration, isize)],
+        now: Instant,
+    ) -> Vec<TxData> {
+        let due = self.reported_at.map_or(true, |reported_at| {
+            now.duration_since(reported_at) >= self.interval
+        });
+
+        if !due {
+            return Vec::new();
+        }
+
+        let usage = self.usage(worker, operators);
+
+        if usage.rules == self.reported.rules && usage.clients == self.reported.clients {
+            return Vec::new();
+        }
+
+        let mut tx_data = Vec::new();
+
+        for (name, current) in usage.rules.iter() {
+            let eid = ("df.usage.rule", worker, name).hashed().as_u64() as Eid;
+
+            match self.reported.rules.get(name) {
+                Some(previous) if previous == current => continue,
+                Some(previous) => {
+                    tx_data.extend(rule_facts(previous).map(|(aid, v)| TxData(-1, eid, aid, v)))
+                }
+                None => {
+                    tx_data.push(TxData(
+                        1,
+                        eid,
+                        Aid::from("df.usage.rule/name"),
+                        Value::Aid(name.clone()),
+                    ));
+                    tx_data.push(TxData(
+                        1,
+                        eid,
+                        Aid::from("df.usage.rule/worker"),
+                        Value::Number(worker as i64),
+                    ));
+                }
+            }
+
+            tx_data.extend(rule_facts(current).map(|(aid, v)| TxData(1, eid, aid, v)));
+        }
+
+        for (id, current) in usage.clients.iter() {
+            let eid = ("df.usage.client", worker, id).hashed().as_u64() as Eid;
+            let aid = Aid::from("df.usage.client/delivered");
+
+            match self.reported.clients.get(id) {
+                Some(previous) if previous == current => continue,
+                Some(previous) => tx_data.push(TxData(
+                    -1,
+                    eid,
+                    aid.clone(),
+                    Value::Number(*previous as i64),
+                )),
+                None => {
+                    tx_data.push(TxData(
+                        1,
+                        eid,
+                        Aid::from("df.usage.client/id"),
+                        Value::Number(*id as i64),
+                    ));
+                    tx_data.push(TxData(
+                        1,
+                        eid,
+                        Aid::from("df.usage.client/worker"),
+                        Value::Number(worker as i64),
+                    ));
+                }
+            }
+
+            tx_data.push(TxData(1, eid, aid, Value::Number(*current as i64)));
+        }
+
+        for hook in self.hooks.iter_mut() {
+            hook.report(&usage);
+        }
+
+        self.reported = usage;
+        self.reported_at = Some(now);
+
+        tx_data
+    }
+}
+
+/// Returns the usage attributes describing a relation.
+fn rule_facts(usage: &RuleUsage) -> impl Iterator<Item = (Aid, Value)> {
+    vec![
+        (
+            Aid::from("df.usage.rule/elapsed"),
+            Value::Number(usage.elapsed as i64),
+        ),
+        (
+            Aid::from("df.usage.rule/arranged"),
+            Value::Number(usage.arranged),
+        ),
+        (
+            Aid::from("df.usage.rule/delivered"),
+            Value::Number(usage.delivered as i64),
+        ),
+    ]
+    .into_iter()
+}
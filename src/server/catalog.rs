@@ -0,0 +1,127 @@
+//! Persistence of the server's schema across restarts.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::plan::encode_plan;
+use crate::server::{parse_requests, recovery_order, Request};
+use crate::Error;
+
+/// Wraps an io error.
+fn fault(err: std::io::Error) -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    }
+}
+
+/// Serializes a request s.t. it can be read back via
+/// `parse_requests`, with plans in the versioned wire format.
+pub(crate) fn encode_request(request: &Request) -> Result<serde_json::Value, Error> {
+    match *request {
+        Request::Register(ref register) => {
+            let mut serialized = serialize(request)?;
+
+            if let Some(rules) = serialized
+                .pointer_mut("/Register/rules")
+                .and_then(|rules| rules.as_array_mut())
+            {
+                for (rule, original) in rules.iter_mut().zip(register.rules.iter()) {
+                    rule["plan"] = encode_plan(&original.plan)?;
+                }
+            }
+
+            Ok(serialized)
+        }
+        Request::Batch(ref requests) => {
+            let items = requests
+                .iter()
+                .map(encode_request)
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            Ok(serde_json::json!({ "Batch": items }))
+        }
+        _ => serialize(request),
+    }
+}
+
+/// Serializes a request as is.
+fn serialize(request: &Request) -> Result<serde_json::Value, Error> {
+    serde_json::to_value(request).map_err(|err| Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    })
+}
+
+/// An append-only journal of the requests defining a server's schema
+/// (attributes, sources, and rules), s.t. a restarted server can
+/// restore them before accepting new requests. Each journaled request
+/// is stored as a json line, with plans in the versioned wire format
+/// (see `plan::format`).
+pub struct Catalog {
+    /// Location of the journal.
+    path: PathBuf,
+    /// The journal, opened for appending.
+    journal: File,
+}
+
+impl Catalog {
+    /// Opens the journal at the specified path, creating it if it
+    /// doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(fault)?;
+
+        Ok(Catalog { path, journal })
+    }
+
+    /// Returns true iff the request is part of the schema and should
+    /// therefore be journaled.
+    pub fn is_journaled(request: &Request) -> bool {
+        match *request {
+            Request::Register(_)
+            | Request::Unregister(_)
+            | Request::CreateAttribute(_)
+            | Request::RegisterSource(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Appends a successfully applied request to the journal. Requests
+    /// not part of the schema are ignored.
+    pub fn record(&mut self, request: &Request) -> Result<(), Error> {
+        if !Catalog::is_journaled(request) {
+            return Ok(());
+        }
+
+        let line = serde_json::to_string(&vec![encode_request(request)?])
+            .expect("failed to serialize request");
+
+        writeln!(self.journal, "{}", line).map_err(fault)?;
+        self.journal.sync_data().map_err(fault)
+    }
+
+    /// Reads back all journaled requests, in the order in which they
+    /// should be replayed, given the relations to prioritize (see
+    /// `recovery_order`).
+    pub fn replay(&self, priority: &HashSet<String>) -> Result<Vec<Request>, Error> {
+        let reader = BufReader::new(File::open(&self.path).map_err(fault)?);
+        let mut requests = Vec::new();
+
+        for line in reader.lines() {
+            let line = line.map_err(fault)?;
+
+            if !line.trim().is_empty() {
+                requests.append(&mut parse_requests(&line)?);
+            }
+        }
+
+        Ok(recovery_order(requests, priority))
+    }
+}
@@ -0,0 +1,638 @@
+//! Durable subscriptions, for consumers that must not miss any
+//! results (e.g. billing pipelines), even while disconnected.
+//!
+//! Each durable consumer is identified by an id of its choosing and
+//! subscribes to a single relation. Every batch of results of that
+//! relation is journaled for the consumer under a sequence number,
+//! and retained until the consumer acknowledges it. Consumers
+//! resubscribing under the same id (e.g. after reconnecting, or
+//! after a restart) receive all batches they haven't acknowledged
+//! yet, in order. Retention is bounded in the number and age of the
+//! batches retained per consumer, batches exceeding either bound are
+//! dropped and counted as lost.
+//!
+//! Journals are kept in a single directory, one per consumer, and
+//! each consumer is journaled by the worker `journaled_by` assigns it
+//! to. Consumers journaled before a restart are recovered, s.t. their
+//! results keep being journaled while they are disconnected. Besides
+//! the batches retained, each journal keeps the accumulated results
+//! journaled so far. Once a consumer's results are derived anew (e.g.
+//! after a restart), only the difference to those is journaled,
+//! rather than another full snapshot of the relation.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::server::Interest;
+use crate::{Error, ResultDiff, Value};
+
+/// Number of journal entries no longer needed that are tolerated
+/// beyond the number of batches retained, before a journal is
+/// compacted.
+const COMPACTION_SLACK: usize = 64;
+
+/// Wraps an io error.
+fn fault(err: std::io::Error) -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    }
+}
+
+/// Milliseconds within a duration.
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+/// Milliseconds since the epoch.
+fn millis(time: SystemTime) -> u64 {
+    as_millis(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// Returns the index of the worker journaling the specified consumer,
+/// which is the only one accepting its subscriptions. Consumers are
+/// assigned by a stable (FNV-1a) hash of their id, s.t. assignments
+/// hold across restarts with the same number of workers.
+pub fn journaled_by(consumer: &str, peers: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in consumer.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    (hash % peers as u64) as usize
+}
+
+/// Returns true iff the consumer id can safely be used as a file
+/// name.
+fn is_valid(consumer: &str) -> bool {
+    !consumer.is_empty()
+        && consumer
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A batch of results retained for a durable consumer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DurableBatch {
+    /// Sequence number, by which the batch is acknowledged.
+    pub seq: u64,
+    /// The relation the results belong to.
+    pub name: String,
+    /// The results themselves.
+    pub results: Vec<ResultDiff>,
+    /// Wall-clock milliseconds since the epoch, at which the batch
+    /// was journaled.
+    pub journaled_at: u64,
+}
+
+/// An entry of a consumer's journal.
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    /// The relation the consumer is subscribed to.
+    Subscribed(String),
+    /// The interest the consumer subscribed with, s.t. it can be
+    /// expressed again after a restart.
+    Interest(Interest),
+    /// The accumulated results journaled so far, written when the
+    /// journal is compacted.
+    State(Vec<(Vec<Value>, isize)>),
+    /// The next sequence number and the number of batches lost so
+    /// far, written when the journal is compacted.
+    Cursor(u64, u64),
+    /// A batch of results.
+    Batch(DurableBatch),
+    /// All batches up to and including this sequence number have
+    /// been acknowledged.
+    Acknowledged(u64),
+    /// All batches up to and including this sequence number have
+    /// been dropped without being acknowledged.
+    Dropped(u64),
+}
+
+/// A durable consumer and the batches retained for it.
+struct Consumer {
+    name: String,
+    interest: Option<Interest>,
+    path: PathBuf,
+    journal: File,
+    batches: VecDeque<DurableBatch>,
+    next_seq: u64,
+    lost: u64,
+    /// Accumulated results journaled so far.
+    state: HashMap<Vec<Value>, isize>,
+    /// Are the consumer's results being derived anew? If so, the
+    /// next batch is journaled as the difference to `state`.
+    resuming: bool,
+    /// Have entries been written since the journal was last synced?
+    dirty: bool,
+    /// Number of journal entries no longer needed.
+    stale: usize,
+}
+
+impl Consumer {
+    /// Applies a journal entry to the consumer's state.
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Subscribed(name) => self.name = name,
+            Entry::Interest(interest) => self.interest = Some(interest),
+            Entry::State(state) => self.state = state.into_iter().collect(),
+            Entry::Cursor(next_seq, lost) => {
+                self.next_seq = next_seq;
+                self.lost = lost;
+            }
+            Entry::Batch(batch) => {
+                self.next_seq = std::cmp::max(self.next_seq, batch.seq + 1);
+
+                for (tuple, _time, diff) in batch.results.iter() {
+                    let count = self.state.entry(tuple.clone()).or_insert(0);
+                    *count += diff;
+
+                    if *count == 0 {
+                        self.state.remove(tuple);
+                    }
+                }
+
+                self.batches.push_back(batch);
+            }
+            Entry::Acknowledged(seq) => {
+                let before = self.batches.len();
+                self.batches.retain(|batch| batch.seq > seq);
+                self.stale += before - self.batches.len() + 1;
+            }
+            Entry::Dropped(seq) => {
+                let before = self.batches.len();
+                self.batches.retain(|batch| batch.seq > seq);
+                self.lost += (before - self.batches.len()) as u64;
+                self.stale += before - self.batches.len() + 1;
+            }
+        }
+    }
+
+    /// Applies an entry and appends it to the journal. The journal
+    /// is only synced by `sync`.
+    fn record(&mut self, entry: Entry) -> Result<(), Error> {
+        let line = serde_json::to_string(&entry).expect("failed to serialize journal entry");
+
+        writeln!(self.journal, "{}", line).map_err(fault)?;
+        self.dirty = true;
+
+        self.apply(entry);
+
+        Ok(())
+    }
+
+    /// Syncs the journal, if anything has been written to it since
+    /// it was last synced.
+    fn sync(&mut self) -> Result<(), Error> {
+        if self.dirty {
+            self.journal.sync_data().map_err(fault)?;
+            self.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Compacts the journal, once it consists mostly of entries that
+    /// are no longer needed.
+    fn maybe_compact(&mut self) -> Result<(), Error> {
+        if self.stale > self.batches.len() + COMPACTION_SLACK {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the results to journal for a batch, i.e. the results
+    /// themselves, or their difference to the results journaled so
+    /// far if the consumer is resuming.
+    fn resume(&mut self, results: &[ResultDiff]) -> Vec<ResultDiff> {
+        if !self.resuming || results.is_empty() {
+            return results.to_vec();
+        }
+
+        self.resuming = false;
+
+        let time = results[0].1;
+        let mut difference: HashMap<Vec<Value>, isize> = HashMap::new();
+
+        for (tuple, _time, diff) in results.iter() {
+            *difference.entry(tuple.clone()).or_insert(0) += diff;
+        }
+
+        for (tuple, count) in self.state.iter() {
+            *difference.entry(tuple.clone()).or_insert(0) -= count;
+        }
+
+        let mut difference: Vec<ResultDiff> = difference
+            .into_iter()
+            .filter(|(_tuple, diff)| *diff != 0)
+            .map(|(tuple, diff)| (tuple, time, diff))
+            .collect();
+
+        difference.sort();
+        difference
+    }
+
+    /// Drops the oldest batches exceeding the retention bounds.
+    fn trim(
+        &mut self,
+        max_batches: usize,
+        max_age: Option<Duration>,
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        let excess = self.batches.len().saturating_sub(max_batches);
+        let mut dropped = self
+            .batches
+            .iter()
+            .take(excess)
+            .map(|batch| batch.seq)
+            .last();
+
+        if let Some(max_age) = max_age {
+            let oldest = millis(now).saturating_sub(as_millis(max_age));
+
+            if let Some(expired) = self
+                .batches
+                .iter()
+                .take_while(|batch| batch.journaled_at < oldest)
+                .map(|batch| batch.seq)
+                .last()
+            {
+                dropped = std::cmp::max(dropped, Some(expired));
+            }
+        }
+
+        match dropped {
+            None => Ok(()),
+            Some(seq) => self.record(Entry::Dropped(seq)),
+        }
+    }
+
+    /// Rewrites the journal s.t. it contains only the batches still
+    /// retained.
+    fn compact(&mut self) -> Result<(), Error> {
+        let compacted = self.path.with_extension("compacted");
+
+        {
+            let mut writer = BufWriter::new(File::create(&compacted).map_err(fault)?);
+
+            // batches still retained are accounted for in the state
+            // once they are read back
+            let mut state: HashMap<Vec<Value>, isize> = self.state.clone();
+            for batch in self.batches.iter() {
+                for (tuple, _time, diff) in batch.results.iter() {
+                    *state.entry(tuple.clone()).or_insert(0) -= diff;
+                }
+            }
+
+            let mut entries = vec![Entry::Subscribed(self.name.clone())];
+            entries.extend(self.interest.clone().map(Entry::Interest));
+            entries.push(Entry::State(
+                state
+                    .into_iter()
+                    .filter(|(_tuple, count)| *count != 0)
+                    .collect(),
+            ));
+            entries.push(Entry::Cursor(self.next_seq, self.lost));
+            entries.extend(self.batches.iter().cloned().map(Entry::Batch));
+
+            for entry in entries.iter() {
+                let line = serde_json::to_string(entry).expect("failed to serialize journal entry");
+                writeln!(writer, "{}", line).map_err(fault)?;
+            }
+
+            writer.flush().map_err(fault)?;
+            writer.get_ref().sync_data().map_err(fault)?;
+        }
+
+        fs::rename(&compacted, &self.path).map_err(fault)?;
+
+        self.journal = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(fault)?;
+        self.dirty = false;
+        self.stale = 0;
+
+        Ok(())
+    }
+}
+
+/// The durable consumers journaled by a worker, one file per consumer
+/// within a directory shared by all workers.
+pub struct Durable {
+    dir: PathBuf,
+    max_batches: usize,
+    max_age: Option<Duration>,
+    consumers: HashMap<String, Consumer>,
+}
+
+impl Durable {
+    /// Opens the specified directory, creating it if it doesn't exist
+    /// yet. At most `max_batches` batches, none older than `max_age`
+    /// (if set), are retained per consumer.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        max_batches: usize,
+        max_age: Option<Duration>,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(fault)?;
+
+        Ok(Durable {
+            dir,
+            max_batches,
+            max_age,
+            consumers: HashMap::new(),
+        })
+    }
+
+    /// Subscribes the consumer to the named relation, returning all
+    /// batches it hasn't acknowledged yet, in order. Consumers known
+    /// from an earlier subscription (possibly before a restart) must
+    /// subscribe to the same relation again.
+    pub fn subscribe(
+        &mut self,
+        consumer: &str,
+        name: &str,
+        now: SystemTime,
+    ) -> Result<Vec<DurableBatch>, Error> {
+        if !is_valid(consumer) {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!(
+                    "Consumer ids may only contain alphanumerics, dashes, and underscores, got {}.",
+                    consumer
+                ),
+            });
+        }
+
+        if !self.consumers.contains_key(consumer) {
+            let loaded = self.load(consumer, name)?;
+            self.consumers.insert(consumer.to_string(), loaded);
+        }
+
+        let (max_batches, max_age) = (self.max_batches, self.max_age);
+        let state = self.consumers.get_mut(consumer).unwrap();
+
+        if state.name != name {
+            return Err(Error {
+                category: "df.error.category/conflict",
+                message: format!(
+                    "Consumer {} is subscribed to {}, not {}.",
+                    consumer, state.name, name
+                ),
+            });
+        }
+
+        state.trim(max_batches, max_age, now)?;
+        state.maybe_compact()?;
+        state.sync()?;
+
+        Ok(state.batches.iter().cloned().collect())
+    }
+
+    /// Journals the interest the consumer subscribed with, s.t. it
+    /// can be expressed again by `recover` after a restart.
+    pub fn record_interest(&mut self, consumer: &str, interest: &Interest) -> Result<(), Error> {
+        match self.consumers.get_mut(consumer) {
+            None => Err(unknown(consumer)),
+            Some(state) => {
+                if state.interest.as_ref() != Some(interest) {
+                    state.record(Entry::Interest(interest.clone()))?;
+                    state.sync()?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads back the journals of all consumers assigned to the
+    /// specified worker (see `journaled_by`), returning the interests
+    /// they subscribed with. Their results are derived anew, so each
+    /// of them is resuming.
+    pub fn recover(&mut self, worker_index: usize, peers: usize) -> Result<Vec<Interest>, Error> {
+        let mut consumers: Vec<String> = fs::read_dir(&self.dir)
+            .map_err(fault)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+
+                if path.extension().map_or(false, |ext| ext == "jsonl") {
+                    path.file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .map(|stem| stem.to_string())
+                } else {
+                    None
+                }
+            })
+            .filter(|consumer| is_valid(consumer) && journaled_by(consumer, peers) == worker_index)
+            .collect();
+
+        consumers.sort();
+
+        let mut interests = Vec::with_capacity(consumers.len());
+
+        for consumer in consumers.into_iter() {
+            if !self.consumers.contains_key(&consumer) {
+                let mut loaded = self.load(&consumer, "")?;
+                loaded.resuming = true;
+                self.consumers.insert(consumer.clone(), loaded);
+            }
+
+            let state = &self.consumers[&consumer];
+
+            let interest = state.interest.clone().unwrap_or_else(|| Interest {
+                name: state.name.clone(),
+                as_of: None,
+                bindings: Vec::new(),
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: Some(consumer.clone()),
+            });
+
+            interests.push(interest);
+        }
+
+        Ok(interests)
+    }
+
+    /// Marks the consumer as resuming, because its results are about
+    /// to be derived anew. The next batch journaled for it is the
+    /// difference to the results journaled so far.
+    pub fn resume(&mut self, consumer: &str) {
+        if let Some(state) = self.consumers.get_mut(consumer) {
+            state.resuming = true;
+        }
+    }
+
+    /// Reads a consumer's journal back, or starts a new one.
+    fn load(&self, consumer: &str, name: &str) -> Result<Consumer, Error> {
+        let path = self.dir.join(format!("{}.jsonl", consumer));
+        let exists = path.exists();
+
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(fault)?;
+
+        let mut state = Consumer {
+            name: name.to_string(),
+            interest: None,
+            path: path.clone(),
+            journal,
+            batches: VecDeque::new(),
+            next_seq: 0,
+            lost: 0,
+            state: HashMap::new(),
+            resuming: false,
+            dirty: false,
+            stale: 0,
+        };
+
+        if exists {
+            let reader = BufReader::new(File::open(&path).map_err(fault)?);
+
+            for line in reader.lines() {
+                let line = line.map_err(fault)?;
+
+                if !line.trim().is_empty() {
+                    let entry = serde_json::from_str(&line).map_err(|err| Error {
+                        category: "df.error.category/fault",
+                        message: format!("Corrupt journal for consumer {}: {}", consumer, err),
+                    })?;
+
+                    state.apply(entry);
+                }
+            }
+        } else {
+            state.record(Entry::Subscribed(name.to_string()))?;
+            state.sync()?;
+        }
+
+        Ok(state)
+    }
+
+    /// Journals a batch of results of the named relation for all
+    /// consumers subscribed to it, returning the batches to deliver to
+    /// each of them. Journals are synced before returning.
+    pub fn append(
+        &mut self,
+        name: &str,
+        results: &[ResultDiff],
+        now: SystemTime,
+    ) -> Result<Vec<(String, DurableBatch)>, Error> {
+        let mut consumers: Vec<String> = self
+            .consumers
+            .iter()
+            .filter(|(_consumer, state)| state.name == name)
+            .map(|(consumer, _state)| consumer.clone())
+            .collect();
+
+        consumers.sort();
+
+        let mut journaled = Vec::new();
+
+        for consumer in consumers.into_iter() {
+            if let Some(batch) = self.append_to(&consumer, name, results, now)? {
+                journaled.push((consumer, batch));
+            }
+        }
+
+        self.sync()?;
+
+        Ok(journaled)
+    }
+
+    /// Journals a batch of results of the named relation for the
+    /// specified consumer, returning the batch to deliver to it, if
+    /// any. Journals are not synced (see `sync`), which has to happen
+    /// before the batch is delivered.
+    pub fn append_to(
+        &mut self,
+        consumer: &str,
+        name: &str,
+        results: &[ResultDiff],
+        now: SystemTime,
+    ) -> Result<Option<DurableBatch>, Error> {
+        let (max_batches, max_age) = (self.max_batches, self.max_age);
+
+        let state = match self.consumers.get_mut(consumer) {
+            None => return Err(unknown(consumer)),
+            Some(state) => state,
+        };
+
+        if state.name != name {
+            return Ok(None);
+        }
+
+        let results = state.resume(results);
+
+        if results.is_empty() {
+            return Ok(None);
+        }
+
+        let batch = DurableBatch {
+            seq: state.next_seq,
+            name: name.to_string(),
+            results,
+            journaled_at: millis(now),
+        };
+
+        state.record(Entry::Batch(batch.clone()))?;
+        state.trim(max_batches, max_age, now)?;
+        state.maybe_compact()?;
+
+        Ok(Some(batch))
+    }
+
+    /// Syncs all journals written to since they were last synced.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        for state in self.consumers.values_mut() {
+            state.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Acknowledges all batches up to and including the specified
+    /// sequence number, s.t. they are no longer retained.
+    pub fn acknowledge(&mut self, consumer: &str, seq: u64) -> Result<(), Error> {
+        match self.consumers.get_mut(consumer) {
+            None => Err(unknown(consumer)),
+            Some(state) => {
+                state.record(Entry::Acknowledged(seq))?;
+                state.maybe_compact()?;
+                state.sync()
+            }
+        }
+    }
+
+    /// Returns the number of batches dropped for the consumer without
+    /// having been acknowledged.
+    pub fn lost(&self, consumer: &str) -> u64 {
+        self.consumers
+            .get(consumer)
+            .map(|state| state.lost)
+            .unwrap_or(0)
+    }
+}
+
+/// Error for consumers not journaled by this worker.
+fn unknown(consumer: &str) -> Error {
+    Error {
+        category: "df.error.category/not-found",
+        message: format!("Unknown durable consumer {}.", consumer),
+    }
+}
@@ -0,0 +1,203 @@
+//! Wire encodings negotiated per connection.
+//!
+//! Clients start out speaking JSON. Sending a hello message
+//! (`{"hello": {"encoding": "cbor"}}`) switches all further messages
+//! sent to the client to the requested encoding. Hello messages may
+//! also make a connection read-only (`"read_only": true`). WebSocket clients
+//! may send binary (CBOR) or text (JSON) messages regardless, while
+//! plain TCP clients must send all frames after the hello in the
+//! negotiated encoding as well.
+
+use crate::server::{decode_command, Request};
+use crate::{Error, ResultDiff};
+
+/// Serialization formats available on the wire.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Encoding {
+    /// JSON, the default.
+    Json,
+    /// CBOR (RFC 7049), considerably more compact for large result
+    /// batches.
+    Cbor,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Json
+    }
+}
+
+impl Encoding {
+    /// Looks up an encoding by the name clients refer to it by.
+    pub fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "json" => Ok(Encoding::Json),
+            "cbor" => Ok(Encoding::Cbor),
+            _ => Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!("Unknown encoding {}.", name),
+            }),
+        }
+    }
+
+    /// Returns the name clients refer to this encoding by.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    /// Decodes a message sent by a client in this encoding.
+    pub fn decode(&self, bytes: &[u8]) -> Incoming {
+        let message: Result<serde_json::Value, String> = match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|err| err.to_string()),
+            Encoding::Cbor => serde_cbor::from_slice(bytes).map_err(|err| err.to_string()),
+        };
+
+        match message {
+            Err(message) => Incoming::Command(
+                None,
+                Err(Error {
+                    category: "df.error.category/incorrect",
+                    message,
+                }),
+            ),
+            Ok(message) => match message.get("hello") {
+                None => {
+                    let (id, requests) = decode_command(message);
+                    Incoming::Command(id, requests)
+                }
+                Some(hello) => Incoming::Hello(Hello::decode(hello)),
+            },
+        }
+    }
+}
+
+/// Settings requested by a client via a hello message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hello {
+    /// The encoding to switch to, if any.
+    pub encoding: Option<Encoding>,
+    /// Whether the client gives up write access for the rest of the
+    /// connection. This can't be undone.
+    pub read_only: bool,
+}
+
+impl Hello {
+    fn decode(hello: &serde_json::Value) -> Result<Self, Error> {
+        let encoding = match hello.get("encoding") {
+            None => None,
+            Some(name) => match name.as_str() {
+                None => {
+                    return Err(Error {
+                        category: "df.error.category/incorrect",
+                        message: "Encodings must be given by name.".to_string(),
+                    });
+                }
+                Some(name) => Some(Encoding::from_name(name)?),
+            },
+        };
+
+        let read_only = hello
+            .get("read_only")
+            .and_then(|read_only| read_only.as_bool())
+            .unwrap_or(false);
+
+        if encoding.is_none() && !read_only {
+            Err(Error {
+                category: "df.error.category/incorrect",
+                message: "Hello messages must request an encoding or read-only access.".to_string(),
+            })
+        } else {
+            Ok(Hello {
+                encoding,
+                read_only,
+            })
+        }
+    }
+}
+
+/// Messages clients may send.
+pub enum Incoming {
+    /// A request to change the connection's settings.
+    Hello(Result<Hello, Error>),
+    /// A command, along with its id (see `parse_command`).
+    Command(Option<u64>, Result<Vec<Request>, Error>),
+}
+
+/// The payload of a message sent to clients. Results make up the
+/// bulk of all messages and are serialized directly into each
+/// encoding, without passing through a `serde_json::Value`.
+#[derive(Clone, Debug)]
+pub enum Payload {
+    /// Any other payload.
+    Json(serde_json::Value),
+    /// Results of a relation.
+    Results(Vec<ResultDiff>),
+}
+
+impl From<serde_json::Value> for Payload {
+    fn from(value: serde_json::Value) -> Self {
+        Payload::Json(value)
+    }
+}
+
+impl From<Vec<ResultDiff>> for Payload {
+    fn from(results: Vec<ResultDiff>) -> Self {
+        Payload::Results(results)
+    }
+}
+
+/// A message to be sent to clients, which might have negotiated
+/// different encodings. Each encoding is serialized at most once.
+pub struct Outgoing {
+    name: String,
+    payload: Payload,
+    json: Option<String>,
+    cbor: Option<Vec<u8>>,
+}
+
+impl Outgoing {
+    /// Wraps a message consisting of a name and a payload.
+    pub fn new<P: Into<Payload>>(name: String, payload: P) -> Self {
+        Outgoing {
+            name,
+            payload: payload.into(),
+            json: None,
+            cbor: None,
+        }
+    }
+
+    /// Returns the JSON serialization of the message.
+    pub fn text(&mut self) -> &str {
+        let (name, payload) = (&self.name, &self.payload);
+
+        self.json.get_or_insert_with(|| {
+            match payload {
+                Payload::Json(value) => serde_json::to_string(&(name, value)),
+                Payload::Results(results) => serde_json::to_string(&(name, results)),
+            }
+            .expect("failed to serialize message")
+        })
+    }
+
+    /// Returns the serialization of the message in the specified
+    /// encoding.
+    pub fn encoded(&mut self, encoding: Encoding) -> &[u8] {
+        match encoding {
+            Encoding::Json => self.text().as_bytes(),
+            Encoding::Cbor => {
+                let (name, payload) = (&self.name, &self.payload);
+
+                self.cbor.get_or_insert_with(|| {
+                    match payload {
+                        Payload::Json(value) => serde_cbor::to_vec(&(name, value)),
+                        Payload::Results(results) => serde_cbor::to_vec(&(name, results)),
+                    }
+                    .expect("failed to serialize message")
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Timely and differential logging, recorded in internal attributes
+//! s.t. dataflows can be monitored via ordinary rules.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use timely::logging::{StartStop, TimelyEvent, WorkerIdentifier};
+use timely::logging_core::Registry;
+
+use differential_dataflow::logging::DifferentialEvent;
+
+use crate::{Aid, Eid, TxData, Value};
+
+/// Names of the attributes logging events are recorded in. Operators
+/// are identified by the same entity across all of them.
+pub const ATTRIBUTES: &[&str] = &[
+    "df.timely.operates/name",
+    "df.timely.operates/address",
+    "df.timely.operates/worker",
+    "df.timely.channels/scope",
+    "df.timely.channels/source",
+    "df.timely.channels/target",
+    "df.timely.schedule/elapsed",
+    "df.differential.arrangement/size",
+    "df.differential.merge/operator",
+    "df.differential.merge/scale",
+    "df.differential.merge/merged",
+    "df.differential.merge/result",
+    "df.differential.merge/duration",
+    "df.differential.merge/batches",
+];
+
+/// Number of completed merges each worker keeps recorded. Older ones
+/// are retracted again, s.t. the merge attributes don't grow without
+/// bound.
+pub const MAX_MERGES: usize = 1024;
+
+/// Collects the logging events of a single worker, until they are
+/// recorded.
+pub struct Logging {
+    /// The worker whose events are collected.
+    worker_index: usize,
+    /// Timely events, along with the time at which they occurred.
+    timely: Rc<RefCell<Vec<(Duration, TimelyEvent)>>>,
+    /// Differential events, along with the time at which they
+    /// occurred.
+    differential: Rc<RefCell<Vec<(Duration, DifferentialEvent)>>>,
+    /// Addresses of all operators, by id.
+    addresses: HashMap<usize, Vec<usize>>,
+    /// Operators currently scheduled, along with the time at which
+    /// they started running.
+    scheduled: HashMap<usize, Duration>,
+    /// Total time spent running each operator, along with the total
+    /// recorded last.
+    elapsed: HashMap<usize, (Duration, Option<Duration>)>,
+    /// Number of updates held by each arrangement, along with the
+    /// number recorded last.
+    sizes: HashMap<usize, (isize, Option<isize>)>,
+    /// Number of batches each arrangement consists of.
+    batches: HashMap<usize, isize>,
+    /// Merges in progress, by arrangement and scale, along with the
+    /// time at which they started.
+    merging: HashMap<(usize, usize), Duration>,
+    /// Number of merges completed so far.
+    merges: usize,
+    /// Datoms describing the merges still recorded, oldest first.
+    recorded_merges: VecDeque<Vec<TxData>>,
+    /// Dataflows whose events are not recorded, by index, s.t.
+    /// recording events doesn't in turn cause further events.
+    ignored_dataflows: HashSet<usize>,
+    /// Operators of ignored dataflows, by id.
+    ignored: HashSet<usize>,
+}
+
+impl Logging {
+    /// Starts collecting the timely and differential events of the
+    /// worker owning the specified log registry. Only events logged
+    /// from here on out are collected.
+    pub fn install(worker_index: usize, registry: &mut Registry<WorkerIdentifier>) -> Self {
+        let timely = Rc::new(RefCell::new(Vec::new()));
+        let differential = Rc::new(RefCell::new(Vec::new()));
+
+        let timely_events = timely.clone();
+        registry.insert::<TimelyEvent, _>("timely", move |_time, data| {
+            let mut events = timely_events.borrow_mut();
+            events.extend(data.drain(..).map(|(time, _worker, event)| (time, event)));
+        });
+
+        let differential_events = differential.clone();
+        registry.insert::<DifferentialEvent, _>("differential/arrange", move |_time, data| {
+            let mut events = differential_events.borrow_mut();
+            events.extend(data.drain(..).map(|(time, _worker, event)| (time, event)));
+        });
+
+        Logging {
+            worker_index,
+            timely,
+            differential,
+            addresses: HashMap::new(),
+            scheduled: HashMap::new(),
+            elapsed: HashMap::new(),
+            sizes: HashMap::new(),
+            batches: HashMap::new(),
+            merging: HashMap::new(),
+            merges: 0,
+            recorded_merges: VecDeque::new(),
+            ignored_dataflows: HashSet::new(),
+            ignored: HashSet::new(),
+        }
+    }
+
+    /// Stops recording the events of the dataflow with the specified
+    /// index, e.g. because it maintains or reads from the logging
+    /// attributes themselves.
+    pub fn ignore_dataflow(&mut self, index: usize) {
+        self.ignored_dataflows.insert(index);
+    }
+
+    /// Returns true iff the scope at the specified address belongs to
+    /// an ignored dataflow.
+    fn is_ignored(&self, address: &[usize]) -> bool {
+        address
+            .first()
+            .map_or(false, |index| self.ignored_dataflows.contains(index))
+    }
+
+    /// Returns the entity identifying an operator of this worker.
+    /// Operator ids are only unique within a worker.
+    fn operator_eid(&self, id: usize) -> Eid {
+        log_eid(OPERATOR, self.worker_index, id)
+    }
+
+    /// Returns the entity identifying a channel of this worker.
+    fn channel_eid(&self, id: usize) -> Eid {
+        log_eid(CHANNEL, self.worker_index, id)
+    }
+
+    /// Returns the entity identifying the n-th merge completed by
+    /// this worker.
+    fn merge_eid(&self, merge: usize) -> Eid {
+        log_eid(MERGE, self.worker_index, merge)
+    }
+
+    /// Drains all events collected since the last call, returning
+    /// the changes to the logging attributes they imply. Scheduling
+    /// times and arrangement sizes are reported once per call, as
+    /// the retraction of the previous total and the assertion of the
+    /// new one. Every completed merge is reported as an entity of
+    /// its own, of which only the most recent `MAX_MERGES` are kept.
+    pub fn drain(&mut self) -> Vec<TxData> {
+        let mut tx_data = Vec::new();
+
+        let timely_events: Vec<_> = self.timely.borrow_mut().drain(..).collect();
+
+        for (time, event) in timely_events {
+            match event {
+                TimelyEvent::Operates(operates) => {
+                    if self.is_ignored(&operates.addr) {
+                        self.ignored.insert(operates.id);
+                        continue;
+                    }
+
+                    let eid = self.operator_eid(operates.id);
+                    let address = format!("{:?}", operates.addr);
+
+                    self.addresses.insert(operates.id, operates.addr);
+
+                    tx_data.push(datom(
+                        eid,
+                        "df.timely.operates/name",
+                        Value::String(operates.name),
+                    ));
+                    tx_data.push(datom(
+                        eid,
+                        "df.timely.operates/address",
+                        Value::String(address),
+                    ));
+                    tx_data.push(datom(
+                        eid,
+                        "df.timely.operates/worker",
+                        Value::Number(self.worker_index as i64),
+                    ));
+                }
+                TimelyEvent::Channels(channels) => {
+                    if self.is_ignored(&channels.scope_addr) {
+                        continue;
+                    }
+
+                    let eid = self.channel_eid(channels.id);
+                    let scope = format!("{:?}", channels.scope_addr);
+                    let source = format!("{}:{}", channels.source.0, channels.source.1);
+                    let target = format!("{}:{}", channels.target.0, channels.target.1);
+
+                    tx_data.push(datom(eid, "df.timely.channels/scope", Value::String(scope)));
+                    tx_data.push(datom(
+                        eid,
+                        "df.timely.channels/source",
+                        Value::String(source),
+                    ));
+                    tx_data.push(datom(
+                        eid,
+                        "df.timely.channels/target",
+                        Value::String(target),
+                    ));
+                }
+                TimelyEvent::Schedule(schedule) => {
+                    if self.ignored.contains(&schedule.id) {
+                        continue;
+                    }
+
+                    if let StartStop::Start = schedule.start_stop {
+                        self.scheduled.insert(schedule.id, time);
+                    } else if let Some(started) = self.scheduled.remove(&schedule.id) {
+                        let elapsed = self
+                            .elapsed
+                            .entry(schedule.id)
+                            .or_insert((Duration::default(), None));
+
+                        elapsed.0 += time - started;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let differential_events: Vec<_> = self.differential.borrow_mut().drain(..).collect();
+
+        for (time, event) in differential_events {
+            match event {
+                DifferentialEvent::Batch(batch) => {
+                    if self.ignored.contains(&batch.operator) {
+                        continue;
+                    }
+
+                    let size = self.sizes.entry(batch.operator).or_insert((0, None));
+                    size.0 += batch.length as isize;
+
+                    *self.batches.entry(batch.operator).or_insert(0) += 1;
+                }
+                DifferentialEvent::Merge(ref merge) if self.ignored.contains(&merge.operator) => {}
+                DifferentialEvent::Merge(merge) => match merge.complete {
+                    None => {
+                        self.merging.insert((merge.operator, merge.scale), time);
+                    }
+                    Some(complete) => {
+                        let merged = merge.length1 + merge.length2;
+
+                        let size = self.sizes.entry(merge.operator).or_insert((0, None));
+                        size.0 += complete as isize;
+                        size.0 -= merged as isize;
+
+                        // two batches are replaced by their merge
+                        let batches = self.batches.entry(merge.operator).or_insert(0);
+                        *batches -= 1;
+                        let batches = *batches;
+
+                        let duration = match self.merging.remove(&(merge.operator, merge.scale)) {
+                            None => Duration::default(),
+                            Some(started) => time - started,
+                        };
+
+                        let eid = self.merge_eid(self.merges);
+                        self.merges += 1;
+
+                        let operator = Value::Eid(self.operator_eid(merge.operator));
+                        let mut merge_data = Vec::with_capacity(6);
+
+                        merge_data.push(datom(eid, "df.differential.merge/operator", operator));
+                        merge_data.push(datom(
+                            eid,
+                            "df.differential.merge/scale",
+                            Value::Number(merge.scale as i64),
+                        ));
+                        merge_data.push(datom(
+                            eid,
+                            "df.differential.merge/merged",
+                            Value::Number(merged as i64),
+                        ));
+                        merge_data.push(datom(
+                            eid,
+                            "df.differential.merge/result",
+                            Value::Number(complete as i64),
+                        ));
+                        merge_data.push(datom(
+                            eid,
+                            "df.differential.merge/duration",
+                            nanos(duration),
+                        ));
+                        merge_data.push(datom(
+                            eid,
+                            "df.differential.merge/batches",
+                            Value::Number(batches as i64),
+                        ));
+
+                        tx_data.extend(merge_data.iter().cloned());
+                        self.recorded_merges.push_back(merge_data);
+
+                        // only the most recent merges are kept around
+                        while self.recorded_merges.len() > MAX_MERGES {
+                            if let Some(expired) = self.recorded_merges.pop_front() {
+                                tx_data.extend(
+                                    expired
+                                        .into_iter()
+                                        .map(|TxData(diff, e, a, v)| TxData(-diff, e, a, v)),
+                                );
+                            }
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let elapsed: Vec<_> = self
+            .elapsed
+            .iter_mut()
+            .filter(|(_id, (total, recorded))| *recorded != Some(*total))
+            .map(|(id, (total, recorded))| (*id, *total, recorded.replace(*total)))
+            .collect();
+
+        for (id, total, previous) in elapsed {
+            let eid = self.operator_eid(id);
+
+            if let Some(previous) = previous {
+                tx_data.push(retraction(
+                    eid,
+                    "df.timely.schedule/elapsed",
+                    nanos(previous),
+                ));
+            }
+
+            tx_data.push(datom(eid, "df.timely.schedule/elapsed", nanos(total)));
+        }
+
+        let sizes: Vec<_> = self
+            .sizes
+            .iter_mut()
+            .filter(|(_id, (size, recorded))| *recorded != Some(*size))
+            .map(|(id, (size, recorded))| (*id, *size, recorded.replace(*size)))
+            .collect();
+
+        for (id, size, previous) in sizes {
+            let eid = self.operator_eid(id);
+
+            if let Some(previous) = previous {
+                tx_data.push(retraction(
+                    eid,
+                    "df.differential.arrangement/size",
+                    Value::Number(previous as i64),
+                ));
+            }
+
+            tx_data.push(datom(
+                eid,
+                "df.differential.arrangement/size",
+                Value::Number(size as i64),
+            ));
+        }
+
+        tx_data
+    }
+
+    /// Returns the address of every operator observed so far, along
+    /// with the total time spent running it and the number of updates
+    /// it holds, if it maintains an arrangement. Reflects the events
+    /// drained so far.
+    pub fn operators(&self) -> Vec<(Vec<usize>, Duration, isize)> {
+        self.addresses
+            .iter()
+            .map(|(id, address)| {
+                let elapsed = self
+                    .elapsed
+                    .get(id)
+                    .map(|(total, _recorded)| *total)
+                    .unwrap_or_default();
+                let size = self
+                    .sizes
+                    .get(id)
+                    .map(|(size, _recorded)| *size)
+                    .unwrap_or(0);
+
+                (address.clone(), elapsed, size)
+            })
+            .collect()
+    }
+}
+
+/// Kinds of entities logging events are recorded for.
+const OPERATOR: u64 = 1;
+const CHANNEL: u64 = 2;
+const MERGE: u64 = 3;
+
+/// Returns the entity identifying a logged operator, channel, or
+/// merge. Entities are laid out explicitly rather than hashed, s.t.
+/// they don't depend on the hasher and never collide: the top bit
+/// marks logging entities, followed by the kind of entity (7 bits),
+/// the worker (16 bits), and its worker-local id (40 bits).
+fn log_eid(kind: u64, worker_index: usize, id: usize) -> Eid {
+    let eid = (1 << 63)
+        | (kind << 56)
+        | ((worker_index as u64 & 0xFFFF) << 40)
+        | (id as u64 & 0xFF_FFFF_FFFF);
+
+    eid as Eid
+}
+
+fn datom(eid: Eid, aid: &str, value: Value) -> TxData {
+    TxData(1, eid, Aid::from(aid), value)
+}
+
+fn retraction(eid: Eid, aid: &str, value: Value) -> TxData {
+    TxData(-1, eid, Aid::from(aid), value)
+}
+
+fn nanos(duration: Duration) -> Value {
+    Value::Number(duration.as_secs() as i64 * 1_000_000_000 + i64::from(duration.subsec_nanos()))
+}
@@ -1,21 +1,55 @@
 //! Server logic for driving the library via commands.
 
-use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
+pub mod accounting;
+pub mod catalog;
+pub mod durable;
+pub mod encoding;
+pub mod logging;
+pub mod replication;
+pub mod simulation;
+pub mod tcp;
+pub mod watchdog;
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::generic::{Operator, OutputHandle};
+use timely::dataflow::operators::{Filter, Map, Probe, ToStream};
 use timely::dataflow::{ProbeHandle, Scope};
+use timely::order::PartialOrder;
 
 use differential_dataflow::collection::Collection;
-use differential_dataflow::trace::TraceReader;
+use differential_dataflow::operators::arrange::Arrange;
+use differential_dataflow::operators::{Consolidate, Group, Join, Threshold};
+use differential_dataflow::trace::{BatchReader, Cursor, TraceReader};
+use differential_dataflow::{AsCollection, Hashable};
+
+use timely_sort::Unsigned;
 
-use crate::domain::Domain;
-use crate::plan::{ImplContext, Implementable};
-use crate::sources::{Source, Sourceable};
+use crate::binding::{AsBinding, Binding, ConstantBinding};
+use crate::domain::{Domain, FullTextIndexHandle, TxIndexHandle, TX_ATTRIBUTES};
+use crate::plan::{decode_plan, filter, Hector, ImplContext, Implementable, Plan, Predicate};
+use crate::sinks::{Sink, Sinkable};
+use crate::sources::{Sample, Source, Sourceable};
+use crate::udf::ExternalFunction;
 use crate::Rule;
 use crate::{
-    implement, implement_neu, AttributeSemantics, CollectionIndex, RelationHandle, TraceKeyHandle,
+    collect_dependencies, implement, implement_neu, stable_hash, streaming_rules,
+    AttributeSemantics, CollectionIndex, IndexDirection, RelationHandle, RetentionPolicy,
+    ShutdownHandle, TraceKeyHandle, ValueType,
 };
-use crate::{Aid, Error, TxData, Value};
+use crate::{rewind, time_value, Aid, Eid, Error, ResultDiff, Time, TxData, Value, Var};
+
+use self::accounting::Accounting;
+use self::logging::Logging;
+use self::simulation::{Simulate, Simulation};
+use self::watchdog::{Alarm, Watchdog};
 
 /// Server configuration.
 #[derive(Clone, Debug)]
@@ -30,6 +64,38 @@ pub struct Config {
     pub enable_optimizer: bool,
     /// Should queries on the query graph be available?
     pub enable_meta: bool,
+    /// Should attributes keep track of the time at which each datom
+    /// was asserted, s.t. it can be bound in queries?
+    pub enable_tx_times: bool,
+    /// If set, relations and sources whose frontier stalls for
+    /// longer than this are reported as stuck.
+    pub watchdog_timeout: Option<Duration>,
+    /// Should clients be notified when relations they are
+    /// interested in get stuck?
+    pub enable_watchdog_notifications: bool,
+    /// Should the domain be driven by its sources only, under the
+    /// control of Simulate requests?
+    pub enable_simulation: bool,
+    /// Should timely and differential logging events be recorded in
+    /// the `df.timely.*` and `df.differential.*` attributes?
+    pub enable_logging: bool,
+    /// If set, the resources consumed on behalf of each relation and
+    /// client are recorded in the `df.usage.*` attributes (and
+    /// reported to all usage hooks) at most this often.
+    pub accounting_interval: Option<Duration>,
+    /// Maximum number of distinct tuples whose changes are held back
+    /// for a paused subscription.
+    pub paused_results_limit: usize,
+    /// Functions implemented by subprocesses, which can be called
+    /// from Transform stages by name.
+    pub external_functions: HashMap<String, ExternalFunction>,
+    /// If set, worst-case optimal join operators work off at most
+    /// this many prefixes at a time, before yielding to other
+    /// operators.
+    pub hector_batch_limit: Option<usize>,
+    /// Directory snapshots are written to and restored from. Snapshot
+    /// and Restore requests are rejected unless set.
+    pub snapshot_dir: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -40,16 +106,221 @@ impl Default for Config {
             enable_history: false,
             enable_optimizer: false,
             enable_meta: false,
+            enable_tx_times: false,
+            watchdog_timeout: None,
+            enable_watchdog_notifications: false,
+            enable_simulation: false,
+            enable_logging: false,
+            accounting_interval: None,
+            paused_results_limit: 100_000,
+            external_functions: HashMap::new(),
+            hector_batch_limit: None,
+            snapshot_dir: None,
         }
     }
 }
 
 /// A request expressing interest in receiving results published under
-/// the specified name.
+/// the specified name. Interests with bindings, restrictions,
+/// projections, orderings, or windows derive a relation of their own,
+/// whose results are published under the name returned by
+/// `parameterized_name`.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct Interest {
     /// The name of a previously registered dataflow.
     pub name: String,
+    /// If set, only a snapshot as of this time is sent, not a stream.
+    #[serde(default)]
+    pub as_of: Option<Time>,
+    /// Constants to bind variables of the rule's plan to.
+    #[serde(default)]
+    pub bindings: Vec<(Var, Value)>,
+    /// Fold the bindings into the plan, rather than filter results?
+    #[serde(default)]
+    pub specialize: bool,
+    /// A static collection to semijoin the relation with.
+    #[serde(default)]
+    pub restrict: Option<Restriction>,
+    /// Variables to deliver, in order, instead of entire tuples.
+    #[serde(default)]
+    pub project: Option<Vec<Var>>,
+    /// Variables by which to sort the results delivered for each time.
+    #[serde(default)]
+    pub order_by: Vec<SortKey>,
+    /// A window of the sorted results to deliver, instead of all.
+    #[serde(default)]
+    pub window: Option<Window>,
+    /// How often results are delivered to the client.
+    #[serde(default)]
+    pub delivery: Delivery,
+    /// Retain results for this durable consumer until acknowledged.
+    #[serde(default)]
+    pub consumer: Option<String>,
+}
+
+/// Policies for delivering the results of an interest.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Delivery {
+    /// Results are delivered once per time, as soon as the time is
+    /// complete.
+    Immediate,
+    /// Results are held back and consolidated, and delivered at most
+    /// once every so many milliseconds.
+    Every(u64),
+    /// Results are held back and consolidated, and delivered once
+    /// the relation has advanced by this many times.
+    Ticks(u64),
+}
+
+impl Default for Delivery {
+    fn default() -> Self {
+        Delivery::Immediate
+    }
+}
+
+/// A variable to sort results by.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct SortKey {
+    /// The variable whose values to compare.
+    pub variable: Var,
+    /// Sort from largest to smallest value?
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// A window into the results of a relation, sorted according to the
+/// interest's sort keys (and by entire tuples, beyond those). Only
+/// tuples entering or leaving the window are delivered, e.g. to page
+/// through large result sets in a UI.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Window {
+    /// Number of sorted tuples to skip.
+    pub offset: usize,
+    /// Maximum number of tuples to deliver.
+    pub limit: usize,
+}
+
+/// A small static collection provided by a client, restricting the
+/// tuples it receives to those whose value at the specified offset
+/// is contained in the collection (e.g. a list of eids the client
+/// cares about).
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct Restriction {
+    /// Offset of the restricted value within each tuple.
+    pub offset: usize,
+    /// Values to retain tuples for.
+    pub values: Vec<Value>,
+}
+
+impl Interest {
+    /// Returns the name under which results for this interest are
+    /// published. Interests carrying bindings refer to a specialized
+    /// rule, named after the original one and the bound values (and
+    /// whether they are specialized). Restricted interests are named
+    /// after their offset and a hash of their values as well,
+    /// projected and ordered ones after their projection and sort
+    /// keys, and windowed ones after their window.
+    pub fn parameterized_name(&self) -> String {
+        let mut name = self.name.clone();
+
+        if !self.bindings.is_empty() {
+            let bindings = serde_json::to_string(&self.bindings)
+                .expect("failed to serialize interest bindings");
+
+            name.push_str(&bindings);
+
+            if self.specialize {
+                name.push_str("{\"specialize\":true}");
+            }
+        }
+
+        // restrictions might hold many values, so they are named
+        // after a hash of the values instead
+        if let Some(ref restriction) = self.restrict {
+            let mut values = restriction.values.clone();
+            values.sort();
+            values.dedup();
+
+            name.push_str(&format!(
+                "{{\"restrict\":[{},\"{:016x}\"]}}",
+                restriction.offset,
+                stable_hash(&values)
+            ));
+        }
+
+        if let Some(ref project) = self.project {
+            let project =
+                serde_json::to_string(project).expect("failed to serialize interest projection");
+
+            name.push_str(&project);
+        }
+
+        if !self.order_by.is_empty() {
+            let order_by =
+                serde_json::to_string(&self.order_by).expect("failed to serialize interest order");
+
+            name.push_str(&order_by);
+        }
+
+        if let Some(ref window) = self.window {
+            let window =
+                serde_json::to_string(window).expect("failed to serialize interest window");
+
+            name.push_str(&window);
+        }
+
+        name
+    }
+}
+
+/// A request for the consolidated changes to a relation between two
+/// times.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct Diff {
+    /// The name of a previously registered dataflow.
+    pub name: String,
+    /// Changes at times up to and including this one are excluded.
+    pub t1: Time,
+    /// Changes at times up to and including this one are included.
+    pub t2: Time,
+}
+
+/// Policies for handling registrations of rules under names that
+/// are already taken.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Reject the entire registration.
+    Error,
+    /// Keep the existing definition and skip the new one.
+    Ignore,
+    /// Accept the registration iff the new plan hashes to the same
+    /// value as the existing one, reject it otherwise.
+    ReplaceIfEqualHash,
+    /// Redefine the existing rule. Implementations of it, and of all
+    /// rules depending on it, are discarded, s.t. interested clients
+    /// can be switched over to the new definition.
+    Replace,
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        ConflictPolicy::Ignore
+    }
+}
+
+/// Outcome of registering a single rule, as reported back to the
+/// client.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum RegisterOutcome {
+    /// The rule was not known before and has been registered.
+    Registered,
+    /// A rule of the same name existed and the new one was skipped.
+    Ignored,
+    /// An identical rule of the same name existed already.
+    Unchanged,
+    /// A different rule of the same name existed and has been
+    /// redefined.
+    Replaced,
 }
 
 /// A request with the intent of synthesising one or more new rules
@@ -60,6 +331,22 @@ pub struct Register {
     pub rules: Vec<Rule>,
     /// The names of rules that should be published.
     pub publish: Vec<String>,
+    /// How to handle rules whose names are already taken.
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// The names of published rules that should be replicated on
+    /// every worker. Useful for small, read-mostly relations that
+    /// are joined against by many queries.
+    #[serde(default)]
+    pub replicate: Vec<String>,
+    /// The names of rules that should be implemented with set
+    /// semantics, even if they could be streamed (see
+    /// `streaming_rules`).
+    #[serde(default)]
+    pub distinct: Vec<String>,
+    /// Metadata to attach to rules, by rule name.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Metadata>,
 }
 
 /// A request with the intent of attaching to an external data source
@@ -70,6 +357,39 @@ pub struct RegisterSource {
     pub names: Vec<String>,
     /// A source configuration.
     pub source: Source,
+    /// Should the domain be advanced automatically, as this source
+    /// makes progress? Otherwise it is only advanced by commands.
+    #[serde(default)]
+    pub drive_domain: bool,
+    /// Should the source's attributes be append-only? Retractions
+    /// read from the source are then dropped, and rules reading from
+    /// them can be streamed (see `CreateAttribute`).
+    #[serde(default)]
+    pub append_only: bool,
+}
+
+/// A request to try out a source configuration before registering
+/// it (a dry run of RegisterSource). A bounded sample is read from
+/// the source and summarized, but nothing is written into the domain.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct SampleSource {
+    /// The names the source would be registered under.
+    pub names: Vec<String>,
+    /// A source configuration.
+    pub source: Source,
+    /// The maximum number of records to read.
+    pub limit: usize,
+}
+
+/// A request with the intent of feeding all changes to a named
+/// relation into an external system, e.g. to raise alerts whenever
+/// a rule starts matching.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct RegisterSink {
+    /// The name of the relation to feed into the sink.
+    pub name: String,
+    /// A sink configuration.
+    pub sink: Sink,
 }
 
 /// A request with the intent of creating a new named, globally
@@ -82,6 +402,66 @@ pub struct CreateAttribute {
     /// Semantics enforced on this attribute by 3DF (vs those enforced
     /// by the external source).
     pub semantics: AttributeSemantics,
+    /// How much history to retain for this attribute. Follows the
+    /// server-wide `enable_history` setting if not specified.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    /// The type of this attribute's values, if they should be
+    /// ordered according to its natural ordering (see `ValueType`).
+    #[serde(default)]
+    pub value_type: Option<ValueType>,
+    /// Should retractions from this attribute be rejected? Rules
+    /// reading from append-only attributes only can be streamed,
+    /// rather than implemented with set semantics.
+    #[serde(default)]
+    pub append_only: bool,
+    /// Metadata to attach to this attribute.
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+/// Metadata describing an attribute or a rule. It has no effect on
+/// how either is implemented, but is recorded in the built-in
+/// catalog, s.t. large deployments can keep track of who is
+/// responsible for what, and why it exists.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    /// A human-readable description.
+    #[serde(default)]
+    pub doc: Option<String>,
+    /// Whoever is responsible, e.g. a team or a person.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Arbitrary labels, e.g. to group related rules.
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Metadata {
+    /// Returns true iff nothing has been specified.
+    pub fn is_empty(&self) -> bool {
+        self.doc.is_none() && self.owner.is_none() && self.tags.is_empty()
+    }
+
+    /// Returns the facts recording this metadata in the catalog
+    /// attributes of the specified namespace.
+    fn facts(&self, namespace: &str) -> Vec<(String, Value)> {
+        let mut facts = Vec::new();
+
+        if let Some(ref doc) = self.doc {
+            facts.push((format!("{}/doc", namespace), Value::String(doc.clone())));
+        }
+
+        if let Some(ref owner) = self.owner {
+            facts.push((format!("{}/owner", namespace), Value::String(owner.clone())));
+        }
+
+        for tag in self.tags.iter() {
+            facts.push((format!("{}/tag", namespace), Value::String(tag.clone())));
+        }
+
+        facts
+    }
 }
 
 /// Possible request types.
@@ -89,18 +469,532 @@ pub struct CreateAttribute {
 pub enum Request {
     /// Sends inputs via one or more registered handles.
     Transact(Vec<TxData>),
+    /// Sends inputs via one or more registered handles, at an
+    /// explicit transaction time.
+    TransactAt(Time, Vec<TxData>),
     /// Expresses interest in a named relation.
     Interest(Interest),
     /// Registers one or more named relations.
     Register(Register),
     /// Registers an external data source.
     RegisterSource(RegisterSource),
+    /// Reads a sample from an external data source, without
+    /// registering it.
+    SampleSource(SampleSource),
+    /// Registers an external data sink.
+    RegisterSink(RegisterSink),
     /// Creates a named input handle that can be `Transact`ed upon.
     CreateAttribute(CreateAttribute),
     /// Advances the specified domain to the specified time.
-    AdvanceDomain(Option<String>, u64),
+    AdvanceDomain(Option<String>, Time),
     /// Closes a named input handle.
     CloseInput(String),
+    /// Applies a list of requests in order, within a single
+    /// sequencer slot. Per-item results, including errors, are
+    /// reported back to the client in a single `df.batch` message.
+    Batch(Vec<Request>),
+    /// Requests an acknowledgement, once all inputs transacted up
+    /// until now have been fully processed.
+    Flush,
+    /// Holds back compaction of a relation, s.t. the client can keep
+    /// reading it as of the specified time. Holding again moves the
+    /// client's existing reader.
+    Hold(String, Time),
+    /// Releases the client's hold on a relation.
+    Release(String),
+    /// Ends the client's interest in a relation. Relations derived
+    /// for the interest (specialized, restricted, projected, or
+    /// windowed) are discarded once no client is interested in them.
+    Uninterest(String),
+    /// Lists all readers holding back compaction.
+    Readers,
+    /// Lists all attributes, along with their semantics and sizes.
+    ListAttributes,
+    /// Lists all registered rules, along with the sizes of those
+    /// that have been implemented.
+    ListRules,
+    /// Computes the changes to a relation between two times, as a
+    /// one-shot result.
+    Diff(Diff),
+    /// Writes the contents of all attributes to the named snapshot,
+    /// within the configured snapshot directory.
+    Snapshot(String),
+    /// Bulk-loads attribute contents from the named snapshot.
+    Restore(String),
+    /// Closes all inputs, waits for all results to be delivered,
+    /// and stops the server.
+    Shutdown,
+    /// Controls the pace of a simulation.
+    Simulate(Simulate),
+    /// Reports the state of the worker owning the client's
+    /// connection (arrangement sizes, frontiers, and command
+    /// processing latencies).
+    Status,
+    /// Pauses the client's subscription to a relation. Results are
+    /// held back (consolidated) until the subscription is resumed.
+    Pause(String),
+    /// Resumes the client's subscription to a relation, delivering
+    /// the results held back in the meantime.
+    Resume(String),
+    /// Sends the client the current contents of a relation, s.t. it
+    /// can start over from there once its subscription has been
+    /// invalidated (e.g. after overflowing while paused).
+    Resnapshot(String),
+    /// Acknowledges all batches up to and including the specified
+    /// sequence number on behalf of a durable consumer, s.t. they
+    /// are no longer retained.
+    Acknowledge(String, u64),
+    /// Removes a registered rule, provided no other rule depends on
+    /// it. Clients interested in it are notified.
+    Unregister(String),
+}
+
+impl Request {
+    /// Returns the name of this kind of request.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Request::Transact(_) => "Transact",
+            Request::TransactAt(_, _) => "TransactAt",
+            Request::Interest(_) => "Interest",
+            Request::Register(_) => "Register",
+            Request::RegisterSource(_) => "RegisterSource",
+            Request::SampleSource(_) => "SampleSource",
+            Request::RegisterSink(_) => "RegisterSink",
+            Request::CreateAttribute(_) => "CreateAttribute",
+            Request::AdvanceDomain(_, _) => "AdvanceDomain",
+            Request::CloseInput(_) => "CloseInput",
+            Request::Batch(_) => "Batch",
+            Request::Flush => "Flush",
+            Request::Hold(_, _) => "Hold",
+            Request::Release(_) => "Release",
+            Request::Uninterest(_) => "Uninterest",
+            Request::Readers => "Readers",
+            Request::ListAttributes => "ListAttributes",
+            Request::ListRules => "ListRules",
+            Request::Diff(_) => "Diff",
+            Request::Snapshot(_) => "Snapshot",
+            Request::Restore(_) => "Restore",
+            Request::Shutdown => "Shutdown",
+            Request::Simulate(_) => "Simulate",
+            Request::Status => "Status",
+            Request::Pause(_) => "Pause",
+            Request::Resume(_) => "Resume",
+            Request::Resnapshot(_) => "Resnapshot",
+            Request::Acknowledge(_, _) => "Acknowledge",
+            Request::Unregister(_) => "Unregister",
+        }
+    }
+
+    /// Returns true iff the request neither writes to the domain nor
+    /// changes the server's configuration, i.e. iff it may be issued
+    /// by read-only clients. Holds block compaction for everyone, and
+    /// therefore count as writes.
+    pub fn is_read_only(&self) -> bool {
+        match *self {
+            Request::Interest(_)
+            | Request::Flush
+            | Request::Release(_)
+            | Request::Uninterest(_)
+            | Request::Readers
+            | Request::ListAttributes
+            | Request::ListRules
+            | Request::Diff(_)
+            | Request::Status
+            | Request::Pause(_)
+            | Request::Resume(_)
+            | Request::Resnapshot(_)
+            | Request::Acknowledge(_, _) => true,
+            Request::Batch(ref requests) => requests.iter().all(Request::is_read_only),
+            _ => false,
+        }
+    }
+}
+
+/// Parses a list of requests sent by a client. Plans within rule
+/// registrations may be given in any supported version of the plan
+/// format (see `plan::format`).
+pub fn parse_requests(text: &str) -> Result<Vec<Request>, Error> {
+    let requests: serde_json::Value = serde_json::from_str(text).map_err(|err| Error {
+        category: "df.error.category/incorrect",
+        message: err.to_string(),
+    })?;
+
+    decode_requests(requests)
+}
+
+/// Parses a message sent by a client, which is either a bare list of
+/// requests, or an object tagging the list with a command id chosen
+/// by the client (`{"id": 1, "requests": [...]}`). Responses to the
+/// command carry its id, s.t. clients can pipeline commands. The id
+/// is returned even if the requests themselves are malformed.
+pub fn parse_command(text: &str) -> (Option<u64>, Result<Vec<Request>, Error>) {
+    let message: serde_json::Value = match serde_json::from_str(text) {
+        Err(err) => {
+            return (
+                None,
+                Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: err.to_string(),
+                }),
+            );
+        }
+        Ok(message) => message,
+    };
+
+    decode_command(message)
+}
+
+/// Interprets an already deserialized client message as a command,
+/// see `parse_command`.
+pub fn decode_command(message: serde_json::Value) -> (Option<u64>, Result<Vec<Request>, Error>) {
+    match message {
+        serde_json::Value::Object(mut envelope) => {
+            let id = envelope.get("id").and_then(|id| id.as_u64());
+
+            match envelope.remove("requests") {
+                None => (
+                    id,
+                    Err(Error {
+                        category: "df.error.category/incorrect",
+                        message: "Commands must hold a list of requests.".to_string(),
+                    }),
+                ),
+                Some(requests) => (id, decode_requests(requests)),
+            }
+        }
+        requests => (None, decode_requests(requests)),
+    }
+}
+
+/// Deserializes a list of requests, upgrading their plans and
+/// expanding SQL statements (with the `sql` feature).
+fn decode_requests(mut requests: serde_json::Value) -> Result<Vec<Request>, Error> {
+    upgrade_plans(&mut requests)?;
+
+    #[cfg(feature = "sql")]
+    crate::sql::expand_requests(&mut requests)?;
+
+    serde_json::from_value(requests).map_err(|err| Error {
+        category: "df.error.category/incorrect",
+        message: err.to_string(),
+    })
+}
+
+/// Replaces all plans within serialized Register requests by their
+/// current serialization.
+fn upgrade_plans(requests: &mut serde_json::Value) -> Result<(), Error> {
+    if let Some(requests) = requests.as_array_mut() {
+        for request in requests.iter_mut() {
+            if let Some(batch) = request.get_mut("Batch") {
+                upgrade_plans(batch)?;
+            } else if let Some(rules) = request
+                .get_mut("Register")
+                .and_then(|register| register.get_mut("rules"))
+                .and_then(|rules| rules.as_array_mut())
+            {
+                for rule in rules.iter_mut() {
+                    if let Some(plan) = rule.get_mut("plan") {
+                        let upgraded = decode_plan(plan.take())?;
+                        *plan = serde_json::to_value(upgraded).map_err(|err| Error {
+                            category: "df.error.category/fault",
+                            message: err.to_string(),
+                        })?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Orders a log of requests for replay after a restart. Attributes
+/// and sources are restored first, followed by rule and sink
+/// (un)registrations and then by interests in any of the `priority`
+/// relations (given via `--priority`), s.t. those subscriptions are
+/// live before the bulk of the data is replayed. All other requests
+/// (transactions, domain advances, and interests in non-priority
+/// relations) follow in their original relative order.
+pub fn recovery_order(requests: Vec<Request>, priority: &HashSet<String>) -> Vec<Request> {
+    let rank = |request: &Request| match request {
+        Request::CreateAttribute(_) | Request::RegisterSource(_) => 0,
+        Request::Register(_) | Request::Unregister(_) | Request::RegisterSink(_) => 1,
+        Request::Interest(ref interest) if priority.contains(&interest.name) => 2,
+        _ => 3,
+    };
+
+    let mut ranked: Vec<(usize, Request)> = requests
+        .into_iter()
+        .map(|request| (rank(&request), request))
+        .collect();
+
+    // A stable sort keeps transactions in order.
+    ranked.sort_by_key(|(rank, _)| *rank);
+
+    ranked.into_iter().map(|(_, request)| request).collect()
+}
+
+/// Constrains each of the bound variables to the given constant,
+/// by filtering the plan. Variables projected away at the top of the
+/// plan are constrained beneath the projection.
+fn bind_variables(plan: Plan, bindings: &[(Var, Value)]) -> Result<Plan, Error> {
+    let mut plan = plan;
+
+    if let Plan::Project(mut projection) = plan {
+        projection.plan = Box::new(bind_variables(*projection.plan, bindings)?);
+        return Ok(Plan::Project(projection));
+    }
+
+    let variables = plan.variables();
+
+    for (var, value) in bindings.iter() {
+        if !variables.contains(var) {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Variable {} is not bound by the rule.", var),
+            });
+        }
+
+        plan = Plan::Filter(filter::Filter {
+            variables: vec![*var],
+            predicate: Predicate::EQ,
+            plan: Box::new(plan),
+            constants: vec![Some(value.clone()), None],
+        });
+    }
+
+    Ok(plan)
+}
+
+/// Constant-folds the bound variables into the plan, by lowering it
+/// to Hector bindings and binding each variable to its constant, s.t.
+/// only matching tuples are ever looked up, instead of all of them
+/// being filtered. Plans that can't be lowered are constrained by
+/// filtering instead (see `bind_variables`).
+fn specialize_plan(plan: Plan, bindings: &[(Var, Value)]) -> Result<Plan, Error> {
+    let mut lowered = match plan.into_bindings() {
+        Err(_) => return bind_variables(plan, bindings),
+        Ok(lowered) => lowered,
+    };
+
+    for (var, _value) in bindings.iter() {
+        if !lowered.iter().any(|binding| binding.binds(*var).is_some()) {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Variable {} is not bound by the rule.", var),
+            });
+        }
+    }
+
+    lowered.extend(bindings.iter().map(|(var, value)| {
+        Binding::Constant(ConstantBinding {
+            symbol: *var,
+            value: value.clone(),
+        })
+    }));
+
+    Ok(Plan::Hector(Hector {
+        variables: plan.variables(),
+        bindings: lowered,
+    }))
+}
+
+/// Compares two tuples by the values at the specified offsets, each
+/// either ascending or descending.
+fn compare_by(ordering: &[(usize, bool)], x: &[Value], y: &[Value]) -> std::cmp::Ordering {
+    ordering
+        .iter()
+        .map(|&(offset, descending)| {
+            if descending {
+                y[offset].cmp(&x[offset])
+            } else {
+                x[offset].cmp(&y[offset])
+            }
+        })
+        .find(|ordering| *ordering != std::cmp::Ordering::Equal)
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// Wraps an io error.
+fn io_fault(err: std::io::Error) -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    }
+}
+
+/// Writes one part of a snapshot, one json array [a e v diff] per
+/// line.
+fn write_snapshot(path: &Path, contents: &[(Aid, Value, Value, isize)]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for update in contents.iter() {
+        serde_json::to_writer(&mut writer, update)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()
+}
+
+/// Counts the updates held by this worker's part of a trace.
+fn count_updates<K, V, Tr: TraceReader<K, V, Time, isize>>(trace: &mut Tr) -> usize {
+    let (mut cursor, storage) = trace.cursor();
+    let mut updates = 0;
+
+    while cursor.key_valid(&storage) {
+        while cursor.val_valid(&storage) {
+            cursor.map_times(&storage, |_time, _diff| updates += 1);
+            cursor.step_val(&storage);
+        }
+        cursor.step_key(&storage);
+    }
+
+    updates
+}
+
+fn hash_plan(rule: &Rule) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    rule.plan.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A long-lived as-of reader of a relation.
+#[derive(Clone, Debug)]
+pub struct Reader<Token> {
+    /// The worker owning the client's connection.
+    pub owner: usize,
+    /// The client reading.
+    pub client: Token,
+    /// The earliest time the client may still read at.
+    pub as_of: Time,
+}
+
+/// Statistics on the commands processed by a worker.
+#[derive(Clone, Debug, Default)]
+pub struct Latencies {
+    /// Number of commands processed.
+    pub commands: u64,
+    /// Time spent processing all commands.
+    pub total: Duration,
+    /// Time spent processing the slowest command.
+    pub max: Duration,
+    /// Time spent processing the latest command.
+    pub last: Duration,
+}
+
+impl Latencies {
+    /// Records the time spent processing another command.
+    pub fn record(&mut self, latency: Duration) {
+        self.commands += 1;
+        self.total += latency;
+        self.max = std::cmp::max(self.max, latency);
+        self.last = latency;
+    }
+
+    /// Returns the mean time spent processing a command.
+    pub fn mean(&self) -> Duration {
+        if self.commands == 0 {
+            Duration::default()
+        } else {
+            self.total / (self.commands as u32)
+        }
+    }
+}
+
+/// The state of an arrangement, as maintained by a single worker.
+#[derive(Clone, Debug)]
+pub struct ArrangementStatus {
+    /// Name of the arranged relation or attribute.
+    pub name: String,
+    /// Number of batches the trace currently consists of.
+    pub batches: usize,
+    /// Approximate number of updates held by the trace (before
+    /// batches are merged, updates might be counted repeatedly).
+    pub updates: usize,
+    /// The frontier up to which the trace may be compacted.
+    pub compaction: Vec<Time>,
+}
+
+/// The state of a single worker, as reported upon a Status request.
+#[derive(Clone, Debug)]
+pub struct Status {
+    /// The time of the internal domain.
+    pub time: Time,
+    /// The frontier of all dataflows implemented so far.
+    pub frontier: Vec<Time>,
+    /// Attribute indices (eid -> value) and published relations.
+    pub arrangements: Vec<ArrangementStatus>,
+    /// Attributes fed by sources, along with the earliest time at
+    /// which each source might still produce data, or None once it
+    /// has been read completely.
+    pub sources: Vec<(Aid, Option<Time>)>,
+    /// Relations clients connected to this worker are interested
+    /// in, along with the number of clients.
+    pub subscriptions: Vec<(String, usize)>,
+    /// Command processing latencies.
+    pub latencies: Latencies,
+}
+
+/// Describes this worker's part of a trace.
+fn arrangement_status<K, V, Tr>(name: &str, trace: &mut Tr) -> ArrangementStatus
+where
+    Tr: TraceReader<K, V, Time, isize>,
+    Tr::Batch: BatchReader<K, V, Time, isize>,
+{
+    let mut batches = 0;
+    let mut updates = 0;
+
+    trace.map_batches(|batch| {
+        batches += 1;
+        updates += batch.len();
+    });
+
+    ArrangementStatus {
+        name: name.to_string(),
+        batches,
+        updates,
+        compaction: trace.advance_frontier().to_vec(),
+    }
+}
+
+/// Results held back from a paused subscription.
+#[derive(Default, Debug)]
+pub struct Paused {
+    /// Net change of each tuple, along with the latest time at which
+    /// it changed.
+    changes: HashMap<Vec<Value>, (Time, isize)>,
+    /// Whether more tuples changed than could be held back.
+    overflowed: bool,
+}
+
+/// Results held back from a subscription with a coalescing delivery
+/// policy, until they are due.
+#[derive(Debug)]
+pub struct Coalescing {
+    /// When results are due.
+    delivery: Delivery,
+    /// Net change of each tuple, along with the latest time at which
+    /// it changed.
+    changes: HashMap<Vec<Value>, (Time, isize)>,
+    /// The latest frontier of the relation, if it advanced since
+    /// results were last delivered.
+    frontier: Option<Vec<Time>>,
+    /// Number of times the relation advanced since results were last
+    /// delivered.
+    ticks: u64,
+    /// When results were last delivered.
+    delivered_at: Instant,
+}
+
+/// What a client receives when resuming a subscription.
+#[derive(Debug, PartialEq)]
+pub enum CatchUp {
+    /// The net changes since the subscription was paused, each at
+    /// the latest time at which the tuple changed.
+    Results(Vec<ResultDiff>),
+    /// Too many tuples changed while the subscription was paused,
+    /// so the client has to start over from a snapshot.
+    Overflowed,
 }
 
 /// Server context maintaining globally registered arrangements and
@@ -112,8 +1006,74 @@ pub struct Server<Token: Hash> {
     pub context: Context,
     /// Mapping from query names to interested client tokens.
     pub interests: HashMap<String, Vec<Token>>,
+    /// Results held back from paused subscriptions, by client and
+    /// relation name.
+    pub paused: HashMap<Token, HashMap<String, Paused>>,
+    /// Results held back from subscriptions with a coalescing
+    /// delivery policy, by client and relation name.
+    pub coalescing: HashMap<Token, HashMap<String, Coalescing>>,
     /// Probe keeping track of overall dataflow progress.
-    pub probe: ProbeHandle<u64>,
+    pub probe: ProbeHandle<Time>,
+    /// Clients waiting for all inputs up to some time to be fully
+    /// processed, in the order in which they asked.
+    pub flushes: VecDeque<(Time, Token)>,
+    /// Long-lived as-of readers, by relation. Relations are not
+    /// compacted beyond the earliest time any of their readers still
+    /// reads at.
+    pub readers: HashMap<String, Vec<Reader<Token>>>,
+    /// Watchdog reporting stalled frontiers, if enabled.
+    pub watchdog: Option<Watchdog>,
+    /// Simulation controlling the domain, if enabled.
+    pub simulation: Option<Simulation>,
+    /// Latencies of the commands processed by this worker.
+    pub latencies: Latencies,
+    /// Logging events collected from this worker, if enabled.
+    pub logging: Option<Logging>,
+    /// Accounting of the resources consumed, if enabled.
+    pub accounting: Option<Accounting>,
+    /// Sources whose progress drives the domain.
+    drivers: HashSet<Aid>,
+    /// Sources known to have been read completely.
+    completed_sources: HashSet<Aid>,
+    /// Restricted relations, by name, along with the relation they
+    /// restrict.
+    restrictions: HashMap<String, (String, Restriction)>,
+    /// Projected (or merely ordered) relations, by name, along with
+    /// the relation they project and the offsets they retain, if
+    /// any. Merely ordered ones retain entire tuples.
+    projections: HashMap<String, (String, Option<Vec<usize>>)>,
+    /// Orders in which results of relations are delivered, by name,
+    /// as offsets to compare by and whether to do so descending.
+    orderings: HashMap<String, Vec<(usize, bool)>>,
+    /// Windowed relations, by name, along with the relation they
+    /// page through, the order in which they do so, and the window.
+    windows: HashMap<String, (String, Vec<(usize, bool)>, Window)>,
+    /// Metadata attached to attributes, by name.
+    attribute_metadata: HashMap<Aid, Metadata>,
+    /// Metadata attached to rules, by name.
+    rule_metadata: HashMap<Aid, Metadata>,
+    /// Relations the optimizer couldn't implement, by name, along
+    /// with the reason.
+    optimizer_fallbacks: HashMap<String, Error>,
+    /// Rules specialized to the bindings of an interest, by name,
+    /// along with the rule they specialize.
+    specializations: HashMap<String, String>,
+    /// Number of times the implementation of a relation has been
+    /// discarded, by name. Outputs of earlier implementations are
+    /// stale.
+    generations: HashMap<String, usize>,
+    /// Clients interested in each relation, across all workers, by
+    /// owning worker and token. Relations derived for an interest are
+    /// garbage-collected once no client is interested in them.
+    subscribers: HashMap<String, Vec<(usize, Token)>>,
+    /// Relations journaled for durable consumers, which outlive
+    /// their clients' connections and are never garbage-collected.
+    pinned: HashSet<String>,
+    /// Relations whose implementations have been discarded, since
+    /// the rules they derive from were redefined or unregistered.
+    /// Drained by the caller, which is responsible for implementing
+    /// them anew for clients still interested in them.
+    pub invalidated: Vec<String>,
 }
 
 /// Implementation context.
@@ -123,9 +1083,32 @@ pub struct Context {
     /// Set of rules known to be underconstrained.
     pub underconstrained: HashSet<Aid>,
     /// Internal domain of command sequence numbers.
-    pub internal: Domain<u64>,
+    pub internal: Domain<Time>,
     /// Named relations.
     pub arrangements: HashMap<Aid, RelationHandle>,
+    /// Named relations that are replicated on every worker.
+    pub replicated: HashSet<Aid>,
+    /// Named relations implemented with set semantics, even if they
+    /// could be streamed.
+    pub distinct: HashSet<Aid>,
+    /// Indices over published binary relations, shared between all
+    /// dataflows binding them.
+    pub derived_indices: HashMap<(Aid, IndexDirection), CollectionIndex<Value, Value, Time>>,
+    /// Net number of tuples transacted into each attribute. Every
+    /// worker sees all transactions, so these agree across workers.
+    pub cardinalities: HashMap<Aid, isize>,
+    /// Functions implemented by subprocesses, by name.
+    pub external_functions: HashMap<String, ExternalFunction>,
+    /// Number of prefixes worst-case optimal join operators work off
+    /// at a time, if bounded.
+    pub hector_batch_limit: Option<usize>,
+    /// Handle stopping the imports of the dataflow currently being
+    /// implemented.
+    pub shutdown_handle: ShutdownHandle,
+    /// Handles of the dataflows maintaining each named relation or
+    /// the indices derived from it. Dataflows are torn down once
+    /// none of the relations they maintain are held anymore.
+    pub shutdown_handles: HashMap<Aid, Vec<ShutdownHandle>>,
 }
 
 impl Context {
@@ -135,8 +1118,22 @@ impl Context {
         // view of the data
         trace.distinguish_since(&[]);
 
+        self.shutdown_handles
+            .entry(name.clone())
+            .or_insert_with(Vec::new)
+            .push(self.shutdown_handle.clone());
+
         self.arrangements.insert(name, trace);
     }
+
+    /// Updates cardinality estimates with the given transaction data.
+    pub fn count_tx_data(&mut self, tx_data: &[TxData]) {
+        for TxData(diff, _e, aid, _v) in tx_data.iter() {
+            if self.internal.forward.contains_key(aid) {
+                *self.cardinalities.entry(aid.clone()).or_insert(0) += *diff;
+            }
+        }
+    }
 }
 
 impl ImplContext for Context {
@@ -144,82 +1141,356 @@ impl ImplContext for Context {
         self.rules.get(name)
     }
 
+    fn cardinality(&self, name: &str) -> Option<usize> {
+        self.cardinalities
+            .get(name)
+            .map(|count| std::cmp::max(*count, 0) as usize)
+    }
+
     fn global_arrangement(&mut self, name: &str) -> Option<&mut RelationHandle> {
         self.arrangements.get_mut(name)
     }
 
-    fn forward_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, u64>> {
+    fn forward_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>> {
         self.internal.forward.get_mut(name)
     }
 
-    fn reverse_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, u64>> {
+    fn reverse_index(&mut self, name: &str) -> Option<&mut CollectionIndex<Value, Value, Time>> {
         self.internal.reverse.get_mut(name)
     }
 
+    fn derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+    ) -> Option<&mut CollectionIndex<Value, Value, Time>> {
+        self.derived_indices.get_mut(&(name.to_string(), direction))
+    }
+
+    fn cache_derived_index(
+        &mut self,
+        name: &str,
+        direction: IndexDirection,
+        index: CollectionIndex<Value, Value, Time>,
+    ) {
+        self.shutdown_handles
+            .entry(name.to_string())
+            .or_insert_with(Vec::new)
+            .push(self.shutdown_handle.clone());
+
+        self.derived_indices
+            .insert((name.to_string(), direction), index);
+    }
+
+    fn attributes(&self) -> Vec<Aid> {
+        self.internal.forward.keys().cloned().collect()
+    }
+
+    fn tx_index(&mut self, name: &str) -> Option<&mut TxIndexHandle<Time>> {
+        self.internal.tx_times.get_mut(name)
+    }
+
+    fn full_text_index(&mut self, name: &str) -> Option<&mut FullTextIndexHandle<Time>> {
+        self.internal.full_text.get_mut(name)
+    }
+
     fn is_underconstrained(&self, _name: &str) -> bool {
         // self.underconstrained.contains(name)
         true
     }
+
+    fn is_replicated(&self, name: &str) -> bool {
+        self.replicated.contains(name)
+    }
+
+    fn value_type(&self, name: &str) -> Option<ValueType> {
+        self.internal.value_type(name)
+    }
+
+    fn is_append_only(&self, name: &str) -> bool {
+        self.internal.is_append_only(name)
+    }
+
+    fn is_distinct(&self, name: &str) -> bool {
+        self.distinct.contains(name)
+    }
+
+    fn external_function(&self, name: &str) -> Option<&ExternalFunction> {
+        self.external_functions.get(name)
+    }
+
+    fn hector_batch_limit(&self) -> Option<usize> {
+        self.hector_batch_limit
+    }
+
+    fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
 }
 
 impl<Token: Hash> Server<Token> {
     /// Creates a new server state from a configuration.
     pub fn new(config: Config) -> Self {
+        let mut internal = Domain::new(Default::default());
+        if config.enable_tx_times {
+            internal.enable_tx_times();
+        }
+
+        let watchdog = config.watchdog_timeout.map(Watchdog::new);
+
+        // simulated sources only deliver data up to the horizon
+        let simulation = if config.enable_simulation {
+            let simulation = Simulation::new();
+            internal.gate_sources(simulation.horizon());
+
+            Some(simulation)
+        } else {
+            None
+        };
+
+        let external_functions = config.external_functions.clone();
+        let hector_batch_limit = config.hector_batch_limit;
+
         Server {
             config,
             context: Context {
                 rules: HashMap::new(),
-                internal: Domain::new(0),
+                internal,
                 underconstrained: HashSet::new(),
                 arrangements: HashMap::new(),
+                replicated: HashSet::new(),
+                distinct: HashSet::new(),
+                derived_indices: HashMap::new(),
+                cardinalities: HashMap::new(),
+                external_functions,
+                hector_batch_limit,
+                shutdown_handle: Default::default(),
+                shutdown_handles: HashMap::new(),
             },
             interests: HashMap::new(),
+            paused: HashMap::new(),
+            coalescing: HashMap::new(),
             probe: ProbeHandle::new(),
+            flushes: VecDeque::new(),
+            readers: HashMap::new(),
+            watchdog,
+            simulation,
+            drivers: HashSet::new(),
+            latencies: Latencies::default(),
+            logging: None,
+            accounting: config.accounting_interval.map(Accounting::new),
+            completed_sources: HashSet::new(),
+            restrictions: HashMap::new(),
+            projections: HashMap::new(),
+            orderings: HashMap::new(),
+            windows: HashMap::new(),
+            attribute_metadata: HashMap::new(),
+            rule_metadata: HashMap::new(),
+            optimizer_fallbacks: HashMap::new(),
+            specializations: HashMap::new(),
+            generations: HashMap::new(),
+            subscribers: HashMap::new(),
+            pinned: HashSet::new(),
+            invalidated: Vec::new(),
         }
     }
 
     /// Returns commands to install built-in plans.
     pub fn builtins() -> Vec<Request> {
-        vec![
+        let mut builtins = vec![
+            // The attribute catalog comes first, s.t. all other
+            // built-in attributes are recorded in it.
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/semantics".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/epoch".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/source".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/name".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/doc".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/owner".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.attribute/tag".to_string(),
+                semantics: AttributeSemantics::CardinalityMany,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.rule/name".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.rule/doc".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.rule/owner".to_string(),
+                semantics: AttributeSemantics::CardinalityOne,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.rule/tag".to_string(),
+                semantics: AttributeSemantics::CardinalityMany,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.pattern/e".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.pattern/a".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.pattern/v".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.join/binding".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.union/binding".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.project/binding".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.project/symbols".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df/name".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.name/symbols".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             Request::CreateAttribute(CreateAttribute {
                 name: "df.name/plan".to_string(),
                 semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.epoch/instant".to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.watchdog/stuck".to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            }),
+            Request::CreateAttribute(CreateAttribute {
+                name: "df.source/complete".to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
             }),
             // Request::Register(Register {
             //     publish: vec!["df.rules".to_string()],
@@ -248,7 +1519,46 @@ impl<Token: Hash> Server<Token> {
             //         }
             //     ],
             // }),
-        ]
+        ];
+
+        // Logging attributes are written to all the time, so only
+        // their latest state is retained.
+        builtins.extend(logging::ATTRIBUTES.iter().map(|name| {
+            Request::CreateAttribute(CreateAttribute {
+                name: name.to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: Some(RetentionPolicy::KeepLatest),
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            })
+        }));
+
+        builtins.extend(accounting::ATTRIBUTES.iter().map(|name| {
+            Request::CreateAttribute(CreateAttribute {
+                name: name.to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: false,
+                metadata: Default::default(),
+            })
+        }));
+
+        // Transactions are described as they happen and never
+        // retracted.
+        builtins.extend(TX_ATTRIBUTES.iter().map(|name| {
+            Request::CreateAttribute(CreateAttribute {
+                name: name.to_string(),
+                semantics: AttributeSemantics::Raw,
+                retention: None,
+                value_type: None,
+                append_only: true,
+                metadata: Default::default(),
+            })
+        }));
+
+        builtins
     }
 
     /// Handle a Transact request.
@@ -258,170 +1568,2457 @@ impl<Token: Hash> Server<Token> {
         owner: usize,
         worker_index: usize,
     ) -> Result<(), Error> {
+        let time = *self.context.internal.time();
+        self.transact_at(tx_data, time, owner, worker_index)
+    }
+
+    /// Handle a TransactAt request. Data is validated on all
+    /// workers, s.t. all of them agree on whether it counts towards
+    /// cardinality estimates, and thereby on the plans derived from
+    /// them.
+    pub fn transact_at(
+        &mut self,
+        tx_data: Vec<TxData>,
+        time: Time,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        let tx_data = self.context.internal.validate(tx_data, &time)?;
+        self.context.count_tx_data(&tx_data);
+
+        // @TODO Route datoms to domains by attribute name patterns
+        // (e.g. staging vs. production), s.t. a single connection
+        // can write to several of them. This requires named domains
+        // first, but there is only the internal one so far (see
+        // advance_domain).
+
         // only the owner should actually introduce new inputs
         if owner == worker_index {
-            self.context.internal.transact(tx_data)
+            self.context.internal.transact_with_metadata(tx_data, time)
         } else {
             Ok(())
         }
     }
 
+    /// Returns the number of rules that have to be implemented in
+    /// order to serve an interest in the named relation, as an
+    /// indication of how long this is going to take.
+    pub fn implementation_size(&self, name: &str) -> usize {
+        if self.context.arrangements.contains_key(name) || self.context.rule(name).is_none() {
+            0
+        } else {
+            collect_dependencies(&self.context, &[name])
+                .map(|rules| {
+                    rules
+                        .iter()
+                        .filter(|rule| !self.context.arrangements.contains_key(&rule.name))
+                        .count()
+                })
+                .unwrap_or(0)
+        }
+    }
+
+    /// Returns the rules and attributes that have to be registered
+    /// or created before the named relation can be implemented, e.g.
+    /// because the client registers them after expressing interest,
+    /// or because the source feeding them hasn't been registered yet.
+    /// Relations derived for an interest are checked via the rule
+    /// they were derived from.
+    pub fn missing_dependencies(&self, name: &str) -> Vec<String> {
+        let mut name = name.to_string();
+
+        loop {
+            let source = if let Some((source, _)) = self.restrictions.get(&name) {
+                source
+            } else if let Some((source, _)) = self.projections.get(&name) {
+                source
+            } else if let Some((source, _, _)) = self.windows.get(&name) {
+                source
+            } else {
+                break;
+            };
+
+            name = source.clone();
+        }
+
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue = vec![name];
+
+        while let Some(next) = queue.pop() {
+            if !seen.insert(next.clone()) || self.context.arrangements.contains_key(&next) {
+                continue;
+            }
+
+            match self.context.rules.get(&next) {
+                Some(rule) => {
+                    queue.extend(rule.plan.dependencies());
+                    queue.extend(rule.plan.attributes().into_iter().filter(|a| a != "*"));
+                }
+                None => {
+                    if !self.context.internal.forward.contains_key(&next) {
+                        missing.push(next);
+                    }
+                }
+            }
+        }
+
+        missing.sort();
+        missing
+    }
+
+    /// Returns the relations of interest reading from the named
+    /// attribute or rule, directly or via other rules. Clients
+    /// interested in them have to be notified when it stops being
+    /// maintained, as their subscriptions would otherwise silently
+    /// stop producing data.
+    pub fn invalidated_by(&self, name: &str) -> Vec<String> {
+        let mut invalidated: Vec<String> = self
+            .interests
+            .keys()
+            .filter(|relation| self.depends_on(relation, name))
+            .cloned()
+            .collect();
+
+        invalidated.sort();
+        invalidated
+    }
+
+    /// Returns true iff the relation is, or transitively reads from,
+    /// the named attribute or rule. Plans reading all attributes (the
+    /// `*` wildcard) read from every attribute but the built-in
+    /// (`df.`) ones.
+    fn depends_on(&self, relation: &str, name: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut queue = vec![relation.to_string()];
+
+        let matches_wildcard = !name.starts_with("df.") && !self.context.rules.contains_key(name);
+
+        while let Some(next) = queue.pop() {
+            if next == name {
+                return true;
+            }
+
+            if let Some(rule) = self.context.rules.get(&next) {
+                if rule
+                    .plan
+                    .attributes()
+                    .iter()
+                    .any(|a| a == name || (a == "*" && matches_wildcard))
+                {
+                    return true;
+                }
+
+                for dependency in rule.plan.dependencies() {
+                    if seen.insert(dependency.clone()) {
+                        queue.push(dependency);
+                    }
+                }
+            }
+
+            // restricted, projected, and windowed relations read
+            // from the relation they were derived from
+            let source = if let Some((source, _)) = self.restrictions.get(&next) {
+                Some(source)
+            } else if let Some((source, _)) = self.projections.get(&next) {
+                Some(source)
+            } else {
+                self.windows.get(&next).map(|(source, _, _)| source)
+            };
+
+            if let Some(source) = source {
+                if seen.insert(source.clone()) {
+                    queue.push(source.clone());
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Stops recording the logging events (if enabled) of the
+    /// dataflow the scope belongs to, if it maintains or reads from
+    /// one of the logging attributes. Recording events of such
+    /// dataflows would cause yet more events, without end.
+    pub fn exclude_from_logs<S: Scope>(&mut self, name: &str, scope: &S) {
+        if self.logging.is_none() {
+            return;
+        }
+
+        let reads_logs = logging::ATTRIBUTES
+            .iter()
+            .any(|attribute| self.depends_on(name, attribute));
+
+        if let Some(ref mut logging) = self.logging {
+            if let Some(index) = scope.addr().first() {
+                if reads_logs {
+                    logging.ignore_dataflow(*index);
+                }
+            }
+        }
+    }
+
     /// Handles an Interest request.
-    pub fn interest<S: Scope<Timestamp = u64>>(
+    pub fn interest<S: Scope<Timestamp = Time>>(
         &mut self,
         name: &str,
         scope: &mut S,
-    ) -> Result<&mut TraceKeyHandle<Vec<Value>, u64, isize>, Error> {
-        match name {
-            "df.timely/operates" => {
-                // use timely::logging::{BatchLogger, TimelyEvent};
-                // use timely::dataflow::operators::capture::EventWriter;
-
-                // let writer = EventWriter::new(stream);
-                // let mut logger = BatchLogger::new(writer);
-                // scope.log_register()
-                //     .insert::<TimelyEvent,_>("timely", move |time, data| logger.publish_batch(time, data));
+    ) -> Result<&mut TraceKeyHandle<Vec<Value>, Time, isize>, Error> {
+        // Everything implemented on behalf of this relation is torn
+        // down along with it, independently of the relation (if any)
+        // it is implemented on behalf of in turn.
+        let outer = std::mem::replace(&mut self.context.shutdown_handle, Default::default());
+        let implemented = self.implement_interest(name, scope).map(|_| ());
+        self.context.shutdown_handle = outer;
+        implemented?;
 
-                // logging_stream
-                //     .flat_map(|(t,_,x)| {
-                //         if let Operates(event) = x {
-                //             Some((event, t, 1 as isize))
-                //         } else { None }
-                //     })
-                //     .as_collection()
+        match self.context.global_arrangement(name) {
+            None => Err(Error {
+                category: "df.error.category/fault",
+                message: format!(
+                    "Relation of interest ({}) wasn't actually implemented.",
+                    name
+                ),
+            }),
+            Some(trace) => Ok(trace),
+        }
+    }
 
-                unimplemented!();
+    fn implement_interest<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        scope: &mut S,
+    ) -> Result<&mut TraceKeyHandle<Vec<Value>, Time, isize>, Error> {
+        if let Some(ref mut accounting) = self.accounting {
+            if !self.context.arrangements.contains_key(name) {
+                accounting.attribute(scope.addr(), name);
             }
-            _ => {
-                // We need to do a `contains_key` here to avoid taking
-                // a mut ref on context.
-                if self.context.arrangements.contains_key(name) {
-                    // Rule is already implemented.
-                    Ok(self.context.global_arrangement(name).unwrap())
-                } else if self.config.enable_optimizer {
-                    let rel_map = implement_neu(name, scope, &mut self.context)?;
-
-                    for (name, trace) in rel_map.into_iter() {
-                        self.context.register_arrangement(name, trace);
-                    }
+        }
 
-                    match self.context.global_arrangement(name) {
-                        None => Err(Error {
-                            category: "df.error.category/fault",
-                            message: format!(
-                                "Relation of interest ({}) wasn't actually implemented.",
-                                name
-                            ),
-                        }),
-                        Some(trace) => Ok(trace),
-                    }
-                } else {
-                    let rel_map = implement(name, scope, &mut self.context)?;
+        if !self.context.arrangements.contains_key(name) {
+            self.exclude_from_logs(name, scope);
+        }
 
-                    for (name, trace) in rel_map.into_iter() {
-                        self.context.register_arrangement(name, trace);
-                    }
+        // We need to do a `contains_key` here to avoid taking
+        // a mut ref on context.
+        if self.context.arrangements.contains_key(name) {
+            // Rule is already implemented.
+            Ok(self.context.global_arrangement(name).unwrap())
+        } else if let Some((relation, restriction)) = self.restrictions.get(name).cloned() {
+            self.restrict(name, &relation, restriction, scope)
+        } else if let Some((relation, offsets)) = self.projections.get(name).cloned() {
+            self.project(name, &relation, offsets, scope)
+        } else if let Some((relation, ordering, window)) = self.windows.get(name).cloned() {
+            self.paginate(name, &relation, ordering, window, scope)
+        } else {
+            let rel_map = if self.config.enable_optimizer {
+                match implement_neu(name, scope, &mut self.context) {
+                    Ok(rel_map) => rel_map,
+                    Err(error) => {
+                        if error.category != "df.error.category/unsupported" {
+                            return Err(error);
+                        }
 
-                    match self.context.global_arrangement(name) {
-                        None => Err(Error {
-                            category: "df.error.category/fault",
-                            message: format!(
-                                "Relation of interest ({}) wasn't actually implemented.",
-                                name
-                            ),
-                        }),
-                        Some(trace) => Ok(trace),
+                        // Optimizer gaps should cost performance,
+                        // rather than availability.
+                        warn!(
+                            "[OPTIMIZER] falling back to the naive planner for {}: {}",
+                            name, error.message
+                        );
+
+                        self.optimizer_fallbacks.insert(name.to_string(), error);
+
+                        implement(name, scope, &mut self.context)?
                     }
                 }
+            } else {
+                implement(name, scope, &mut self.context)?
+            };
+
+            for (name, trace) in rel_map.into_iter() {
+                self.context.register_arrangement(name, trace);
+            }
+
+            self.watch_relation(name, scope);
+
+            match self.context.global_arrangement(name) {
+                None => Err(Error {
+                    category: "df.error.category/fault",
+                    message: format!(
+                        "Relation of interest ({}) wasn't actually implemented.",
+                        name
+                    ),
+                }),
+                Some(trace) => Ok(trace),
             }
         }
     }
 
-    /// Handle a Register request.
-    pub fn register(&mut self, req: Register) -> Result<(), Error> {
-        let Register { rules, .. } = req;
+    /// Returns the reason the optimizer couldn't implement the named
+    /// relation, if it had to be implemented by the naive planner
+    /// instead, despite the optimizer being enabled.
+    pub fn optimizer_fallback(&self, name: &str) -> Option<&Error> {
+        self.optimizer_fallbacks.get(name)
+    }
 
-        for rule in rules.into_iter() {
-            if self.context.rules.contains_key(&rule.name) {
-                // @TODO panic if hashes don't match
-                // panic!("Attempted to re-register a named relation");
-                continue;
-            } else {
-                if self.config.enable_meta {
-                    let mut data = rule.plan.datafy();
-                    let tx_data: Vec<TxData> =
-                        data.drain(..).map(|(e, a, v)| TxData(1, e, a, v)).collect();
+    /// Returns true iff the named rule is streamed, i.e. implemented
+    /// without enforcing set semantics, as all its inputs are
+    /// append-only (see `streaming_rules`).
+    pub fn is_streaming(&self, name: &str) -> bool {
+        if !self.context.rules.contains_key(name) {
+            return false;
+        }
 
-                    self.transact(tx_data, 0, 0)?;
-                }
+        match collect_dependencies(&self.context, &[name]) {
+            Err(_) => false,
+            Ok(rules) => streaming_rules(&self.context, &rules).contains(name),
+        }
+    }
 
-                self.context.rules.insert(rule.name.to_string(), rule);
+    /// Implements a restricted interest, by semijoining the relation
+    /// it restricts with the static collection provided by the
+    /// client, and publishes the result under the specified name.
+    fn restrict<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        relation: &str,
+        restriction: Restriction,
+        scope: &mut S,
+    ) -> Result<&mut TraceKeyHandle<Vec<Value>, Time, isize>, Error> {
+        // every worker holds a full copy of replicated relations, so
+        // each only contributes its share
+        let replicated = self.context.is_replicated(relation);
+        let (index, peers) = (scope.index(), scope.peers());
+
+        let (arranged, button) = self.interest(relation, scope)?.import_core(scope, relation);
+        self.context.shutdown_handle.add_button(button);
+
+        let tuples = arranged
+            .as_collection(|tuple, _| tuple.clone())
+            .filter(move |tuple| {
+                !replicated || (tuple.hashed().as_u64() as usize) % peers == index
+            });
+
+        // the collection is introduced once, by the first worker
+        let Restriction { offset, mut values } = restriction;
+        values.sort();
+        values.dedup();
+
+        if index != 0 {
+            values.clear();
+        }
+
+        let keys = values
+            .into_iter()
+            .map(|value| (value, Time::default(), 1))
+            .to_stream(scope)
+            .as_collection();
+
+        let trace = tuples
+            .map(move |tuple| (tuple[offset].clone(), tuple))
+            .semijoin(&keys)
+            .map(|(_value, tuple)| (tuple, ()))
+            .arrange_named(name)
+            .trace;
+
+        self.context.register_arrangement(name.to_string(), trace);
+        self.watch_relation(name, scope);
+
+        Ok(self.context.global_arrangement(name).unwrap())
+    }
+
+    /// Implements a projected interest, by retaining only the values
+    /// at the specified offsets of each tuple of the relation it
+    /// projects, and publishes the result under the specified name.
+    /// Without offsets, the arrangement of the relation is published
+    /// under the specified name as is.
+    fn project<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        relation: &str,
+        offsets: Option<Vec<usize>>,
+        scope: &mut S,
+    ) -> Result<&mut TraceKeyHandle<Vec<Value>, Time, isize>, Error> {
+        let offsets = match offsets {
+            Some(offsets) => offsets,
+            None => {
+                // results are only sorted upon delivery, so the
+                // relation itself can be shared
+                let trace = self.interest(relation, scope)?.clone();
+                self.context.register_arrangement(name.to_string(), trace);
+
+                return Ok(self.context.global_arrangement(name).unwrap());
+            }
+        };
+
+        // every worker holds a full copy of replicated relations, so
+        // each only contributes its share
+        let replicated = self.context.is_replicated(relation);
+        let (index, peers) = (scope.index(), scope.peers());
+
+        let (arranged, button) = self.interest(relation, scope)?.import_core(scope, relation);
+        self.context.shutdown_handle.add_button(button);
+
+        let trace = arranged
+            .as_collection(|tuple, _| tuple.clone())
+            .filter(move |tuple| !replicated || (tuple.hashed().as_u64() as usize) % peers == index)
+            .map(move |tuple| {
+                offsets
+                    .iter()
+                    .map(|offset| tuple[*offset].clone())
+                    .collect::<Vec<Value>>()
+            })
+            // projected tuples are a set, just like relations are
+            .distinct()
+            .map(|tuple| (tuple, ()))
+            .arrange_named(name)
+            .trace;
+
+        self.context.register_arrangement(name.to_string(), trace);
+        self.watch_relation(name, scope);
+
+        Ok(self.context.global_arrangement(name).unwrap())
+    }
+
+    /// Ensures that all attributes and relations the given rules
+    /// refer to exist, either already or as part of the same
+    /// registration. Derived relations may stand in for attributes.
+    fn check_dependencies(&self, rules: &[Rule]) -> Result<(), Error> {
+        let is_relation = |name: &str| {
+            self.context.rules.contains_key(name)
+                || self.context.arrangements.contains_key(name)
+                || rules.iter().any(|rule| rule.name == name)
+        };
+
+        for rule in rules.iter() {
+            let bound = rule.plan.attributes();
+
+            let mut attributes: Vec<Aid> = bound
+                .iter()
+                .filter(|a| a.as_str() != "*")
+                .filter(|a| !self.context.internal.forward.contains_key(*a) && !is_relation(a))
+                .cloned()
+                .collect();
+            attributes.sort();
+            attributes.dedup();
+
+            // names bound by Hector are checked as attributes above
+            let mut relations: Vec<String> = rule
+                .plan
+                .dependencies()
+                .into_iter()
+                .filter(|name| !bound.contains(name) && !is_relation(name))
+                .collect();
+            relations.sort();
+            relations.dedup();
+
+            let mut missing = Vec::new();
+            if !attributes.is_empty() {
+                missing.push(format!("unknown attributes {}", attributes.join(", ")));
+            }
+            if !relations.is_empty() {
+                missing.push(format!("unknown relations {}", relations.join(", ")));
+            }
+
+            if !missing.is_empty() {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!("Rule {} refers to {}.", rule.name, missing.join(" and ")),
+                });
             }
         }
 
         Ok(())
     }
 
-    /// Handle a RegisterSource request.
-    pub fn register_source<S: Scope<Timestamp = u64>>(
+    /// Implements a windowed interest, and publishes the result under
+    /// the specified name. Tuples are spread across buckets by hash,
+    /// and only the first tuples within each bucket (as many as the
+    /// window extends to) are passed on to ever coarser buckets, s.t.
+    /// a change only re-sorts the buckets it falls into, rather than
+    /// the whole relation.
+    fn paginate<S: Scope<Timestamp = Time>>(
         &mut self,
-        req: RegisterSource,
+        name: &str,
+        relation: &str,
+        ordering: Vec<(usize, bool)>,
+        window: Window,
         scope: &mut S,
-    ) -> Result<(), Error> {
-        let RegisterSource { mut names, source } = req;
+    ) -> Result<&mut TraceKeyHandle<Vec<Value>, Time, isize>, Error> {
+        // every worker holds a full copy of replicated relations, so
+        // each only contributes its share
+        let replicated = self.context.is_replicated(relation);
+        let (index, peers) = (scope.index(), scope.peers());
+        let extent = window.offset.saturating_add(window.limit);
 
-        if names.len() == 1 {
-            let name = names.pop().unwrap();
-            let datoms = source.source(scope, names.clone());
+        let (arranged, button) = self.interest(relation, scope)?.import_core(scope, relation);
+        self.context.shutdown_handle.add_button(button);
 
-            self.context.internal.create_source(&name, None, &datoms)
-        } else if names.len() > 1 {
-            let datoms = source.source(scope, names.clone());
+        let mut buckets = arranged
+            .as_collection(|tuple, _| tuple.clone())
+            .filter(move |tuple| !replicated || (tuple.hashed().as_u64() as usize) % peers == index)
+            .map(|tuple| (tuple.hashed().as_u64(), tuple));
 
-            for (name_idx, name) in names.iter().enumerate() {
-                self.context
-                    .internal
-                    .create_source(name, Some(name_idx), &datoms)?;
-            }
+        for bits in [16u64, 12, 8, 4].iter().cloned() {
+            let ordering = ordering.clone();
 
-            Ok(())
-        } else {
-            Ok(())
+            buckets = buckets
+                .map(move |(hash, tuple)| (hash & ((1 << bits) - 1), tuple))
+                .group(move |_bucket, input, output| {
+                    let mut tuples: Vec<&Vec<Value>> =
+                        input.iter().map(|(tuple, _count)| *tuple).collect();
+
+                    // tuples arrive sorted, so ties are broken by them
+                    tuples.sort_by(|x, y| compare_by(&ordering, x, y));
+
+                    for tuple in tuples.into_iter().take(extent) {
+                        output.push((tuple.clone(), 1));
+                    }
+                });
+        }
+
+        let trace = buckets
+            // the remaining candidates are gathered under a single
+            // key, s.t. the window only changes where they do
+            .map(|(_bucket, tuple)| ((), tuple))
+            .group(move |_key, input, output| {
+                let mut tuples: Vec<&Vec<Value>> =
+                    input.iter().map(|(tuple, _count)| *tuple).collect();
+
+                tuples.sort_by(|x, y| compare_by(&ordering, x, y));
+
+                for tuple in tuples.into_iter().skip(window.offset).take(window.limit) {
+                    output.push((tuple.clone(), 1));
+                }
+            })
+            .map(|((), tuple)| (tuple, ()))
+            .arrange_named(name)
+            .trace;
+
+        self.context.register_arrangement(name.to_string(), trace);
+        self.watch_relation(name, scope);
+
+        Ok(self.context.global_arrangement(name).unwrap())
+    }
+
+    /// Sorts a batch of results of the named relation according to
+    /// the order requested by the interest it was published for, if
+    /// any. Results are sorted within each time, times remain in
+    /// order. Ties retain their relative order.
+    pub fn sort_results(&self, name: &str, results: &mut [ResultDiff]) {
+        if let Some(ordering) = self.orderings.get(name) {
+            results.sort_by(|(x, t1, _), (y, t2, _)| {
+                t1.cmp(t2).then_with(|| compare_by(ordering, x, y))
+            });
         }
     }
 
-    /// Handle an AdvanceDomain request.
-    pub fn advance_domain(&mut self, name: Option<String>, next: u64) -> Result<(), Error> {
-        match name {
-            None => {
-                // If history is not enabled, we want to keep traces advanced
-                // up to the previous time.
-                let trace_next = if self.config.enable_history {
-                    None
-                } else {
-                    Some(next - 1)
-                };
+    /// Handles an Interest request specifying an `as_of` time. The
+    /// resulting collection contains the consolidated state of the
+    /// named relation as of that time, and nothing thereafter.
+    /// Unless history is enabled, only times that have not yet been
+    /// compacted can be queried.
+    pub fn interest_as_of<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        as_of: Time,
+        scope: &mut S,
+    ) -> Result<Collection<S, Vec<Value>, isize>, Error> {
+        // every worker holds a full copy of replicated relations, so
+        // each only contributes its share
+        let replicated = self.context.is_replicated(name);
+        let (index, peers) = (scope.index(), scope.peers());
 
-                self.context.internal.advance_to(next, trace_next);
+        let trace = self.interest(name, scope)?;
 
-                if let Some(trace_next) = trace_next {
-                    // if historical queries don't matter, we should advance
-                    // the index traces to allow them to compact
+        if !trace
+            .advance_frontier()
+            .iter()
+            .all(|time| time.less_equal(&as_of))
+        {
+            return Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!(
+                    "Time {:?} has been compacted, as-of queries require history to be enabled.",
+                    as_of
+                ),
+            });
+        }
+
+        let snapshot = trace
+            .import_named(scope, name)
+            .as_collection(|tuple, _| tuple.clone())
+            .inner
+            .filter(move |(tuple, time, _diff)| {
+                *time <= as_of
+                    && (!replicated || (tuple.hashed().as_u64() as usize) % peers == index)
+            })
+            .map(move |(tuple, _time, diff)| (tuple, as_of, diff))
+            .as_collection()
+            .consolidate();
+
+        Ok(snapshot)
+    }
+
+    /// Handles a Diff request. The resulting collection contains the
+    /// consolidated changes to the named relation after `t1` and up
+    /// to `t2`, all at `t2`. Unless history is enabled, only times
+    /// that have not yet been compacted can be diffed.
+    pub fn diff<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        t1: Time,
+        t2: Time,
+        scope: &mut S,
+    ) -> Result<Collection<S, Vec<Value>, isize>, Error> {
+        if !t1.less_equal(&t2) {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Cannot diff from {:?} back to {:?}.", t1, t2),
+            });
+        }
+
+        // every worker holds a full copy of replicated relations, so
+        // each only contributes its share
+        let replicated = self.context.is_replicated(name);
+        let (index, peers) = (scope.index(), scope.peers());
 
-                    let frontier = &[trace_next];
+        let trace = self.interest(name, scope)?;
 
-                    for trace in self.context.arrangements.values_mut() {
-                        trace.advance_by(frontier);
+        if !trace
+            .advance_frontier()
+            .iter()
+            .all(|time| time.less_equal(&t1))
+        {
+            return Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!(
+                    "Time {:?} has been compacted, diffs require history to be enabled.",
+                    t1
+                ),
+            });
+        }
+
+        let diff = trace
+            .import_named(scope, name)
+            .as_collection(|tuple, _| tuple.clone())
+            .inner
+            .filter(move |(tuple, time, _diff)| {
+                !time.less_equal(&t1)
+                    && time.less_equal(&t2)
+                    && (!replicated || (tuple.hashed().as_u64() as usize) % peers == index)
+            })
+            .map(move |(tuple, _time, diff)| (tuple, t2, diff))
+            .as_collection()
+            .consolidate();
+
+        Ok(diff)
+    }
+
+    /// Handle a Register request. Returns the outcome for each
+    /// registered rule. Plans are validated and conflicts checked
+    /// before any rule is registered, s.t. a rejected request has no
+    /// effect.
+    pub fn register(&mut self, req: Register) -> Result<Vec<(String, RegisterOutcome)>, Error> {
+        let Register {
+            rules,
+            conflict_policy,
+            replicate,
+            distinct,
+            ..
+        } = req;
+
+        // Plans that can't be implemented are rejected up front,
+        // rather than failing once a client is interested in them.
+        for rule in rules.iter() {
+            rule.plan.validate().map_err(|error| Error {
+                category: error.category,
+                message: format!("In rule {}: {}", rule.name, error.message),
+            })?;
+        }
+
+        self.check_dependencies(&rules)?;
+
+        let mut outcomes = Vec::with_capacity(rules.len());
+
+        for rule in rules.iter() {
+            let outcome = match self.context.rules.get(&rule.name) {
+                None => RegisterOutcome::Registered,
+                Some(existing) => match conflict_policy {
+                    ConflictPolicy::Ignore => RegisterOutcome::Ignored,
+                    ConflictPolicy::Error => {
+                        return Err(Error {
+                            category: "df.error.category/conflict",
+                            message: format!("A rule of name {} already exists.", rule.name),
+                        });
+                    }
+                    ConflictPolicy::ReplaceIfEqualHash => {
+                        if hash_plan(existing) == hash_plan(rule) {
+                            RegisterOutcome::Unchanged
+                        } else {
+                            return Err(Error {
+                                category: "df.error.category/conflict",
+                                message: format!(
+                                    "A different rule of name {} already exists.",
+                                    rule.name
+                                ),
+                            });
+                        }
                     }
+                    ConflictPolicy::Replace => {
+                        if hash_plan(existing) == hash_plan(rule) {
+                            RegisterOutcome::Unchanged
+                        } else {
+                            RegisterOutcome::Replaced
+                        }
+                    }
+                },
+            };
+
+            outcomes.push((rule.name.clone(), outcome));
+        }
+
+        for (rule, (_name, outcome)) in rules.into_iter().zip(outcomes.iter()) {
+            if *outcome == RegisterOutcome::Registered {
+                self.datafy_rule(&rule, 1)?;
+                self.context.rules.insert(rule.name.to_string(), rule);
+            } else if *outcome == RegisterOutcome::Replaced {
+                if let Some(existing) = self.context.rules.get(&rule.name).cloned() {
+                    self.datafy_rule(&existing, -1)?;
                 }
+                self.datafy_rule(&rule, 1)?;
 
-                Ok(())
+                self.invalidate(&rule.name);
+                self.context.rules.insert(rule.name.to_string(), rule);
             }
-            Some(_) => Err(Error {
-                category: "df.error.category/unsupported",
-                message: "Named domains are not yet supported.".to_string(),
-            }),
         }
+
+        // @TODO Rule outputs can't be published back into attributes
+        // (derived attributes) yet. Once they can, such registrations
+        // must be checked for cycles (a rule deriving an attribute it
+        // reads from, possibly via other rules), rejecting them with
+        // the offending cycle unless fixed-point semantics have been
+        // asked for explicitly. `recursive_rules` covers the
+        // rule-to-rule part of that.
+
+        self.context.replicated.extend(replicate);
+        self.context.distinct.extend(distinct);
+
+        Ok(outcomes)
+    }
+
+    /// Asserts (or retracts, given a negative diff) the datoms
+    /// describing the plan of a rule, if meta data is enabled.
+    fn datafy_rule(&mut self, rule: &Rule, diff: isize) -> Result<(), Error> {
+        if !self.config.enable_meta {
+            return Ok(());
+        }
+
+        // Identical stages share an entity, within a rule they are
+        // described only once.
+        let mut data = rule.plan.datafy();
+        data.sort();
+        data.dedup();
+
+        let tx_data: Vec<TxData> = data
+            .drain(..)
+            .map(|(e, a, v)| TxData(diff, e, a, v))
+            .collect();
+
+        self.transact(tx_data, 0, 0)
+    }
+
+    /// Handles an Unregister request. Rules other rules still depend
+    /// on can't be unregistered, rules specialized to the bindings of
+    /// an interest in it are removed along with it.
+    pub fn unregister(&mut self, name: &str) -> Result<(), Error> {
+        if !self.context.rules.contains_key(name) {
+            return Err(Error {
+                category: "df.error.category/not-found",
+                message: format!("Unknown rule {}.", name),
+            });
+        }
+
+        let mut dependents: Vec<&String> = self
+            .context
+            .rules
+            .values()
+            .filter(|rule| rule.name != name && !self.specializations.contains_key(&rule.name))
+            .filter(|rule| {
+                rule.plan.dependencies().iter().any(|x| x == name)
+                    || rule.plan.attributes().iter().any(|x| x == name)
+            })
+            .map(|rule| &rule.name)
+            .collect();
+
+        if !dependents.is_empty() {
+            dependents.sort();
+            return Err(Error {
+                category: "df.error.category/conflict",
+                message: format!(
+                    "Rule {} is still depended on by {}.",
+                    name,
+                    dependents
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+
+        if let Some(rule) = self.context.rules.get(name).cloned() {
+            self.datafy_rule(&rule, -1)?;
+        }
+
+        self.invalidate(name);
+
+        self.context.rules.remove(name);
+        self.context.underconstrained.remove(name);
+        self.context.replicated.remove(name);
+        self.context.distinct.remove(name);
+
+        Ok(())
+    }
+
+    /// Returns the generation of the implementation of the specified
+    /// relation. Outputs of dataflows implemented for an earlier
+    /// generation are stale and must be discarded.
+    pub fn generation(&self, name: &str) -> usize {
+        self.generations.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Discards the implementations of the specified relation and of
+    /// everything derived from it: rules depending on it, rules
+    /// specialized from those, and restricted, projected, or windowed
+    /// relations on top of any of them. All handles to them are
+    /// released, which tears down their dataflows once nothing else
+    /// maintained by them is held, and the next interest implements
+    /// them anew. Discarded names are recorded in `invalidated`.
+    fn invalidate(&mut self, name: &str) {
+        let mut discarded: HashSet<String> = HashSet::new();
+        discarded.insert(name.to_string());
+
+        loop {
+            let mut derived: Vec<String> = Vec::new();
+
+            for rule in self.context.rules.values() {
+                if !discarded.contains(&rule.name)
+                    && (rule
+                        .plan
+                        .dependencies()
+                        .iter()
+                        .any(|x| discarded.contains(x))
+                        || rule.plan.attributes().iter().any(|x| discarded.contains(x)))
+                {
+                    derived.push(rule.name.clone());
+                }
+            }
+
+            for (specialized, source) in self.specializations.iter() {
+                if discarded.contains(source) && !discarded.contains(specialized) {
+                    derived.push(specialized.clone());
+                }
+            }
+
+            let sources = self
+                .restrictions
+                .iter()
+                .map(|(derived, (source, _))| (derived, source))
+                .chain(
+                    self.projections
+                        .iter()
+                        .map(|(derived, (source, _))| (derived, source)),
+                )
+                .chain(
+                    self.windows
+                        .iter()
+                        .map(|(derived, (source, _, _))| (derived, source)),
+                );
+
+            for (name, source) in sources {
+                if discarded.contains(source) && !discarded.contains(name) {
+                    derived.push(name.clone());
+                }
+            }
+
+            if derived.is_empty() {
+                break;
+            }
+
+            discarded.extend(derived);
+        }
+
+        let mut discarded: Vec<String> = discarded.into_iter().collect();
+        discarded.sort();
+
+        for name in discarded.into_iter() {
+            self.context.arrangements.remove(&name);
+            self.context
+                .derived_indices
+                .remove(&(name.clone(), IndexDirection::Forward));
+            self.context
+                .derived_indices
+                .remove(&(name.clone(), IndexDirection::Reverse));
+            self.context.shutdown_handles.remove(&name);
+            self.readers.remove(&name);
+            self.optimizer_fallbacks.remove(&name);
+
+            // Specializations and shaped relations are re-derived
+            // from the current definitions once interest is
+            // expressed again.
+            if self.specializations.remove(&name).is_some() {
+                self.context.rules.remove(&name);
+                self.context.replicated.remove(&name);
+                self.context.distinct.remove(&name);
+            }
+            self.restrictions.remove(&name);
+            if self.projections.remove(&name).is_some() {
+                self.context.replicated.remove(&name);
+            }
+            self.orderings.remove(&name);
+            self.windows.remove(&name);
+
+            *self.generations.entry(name.clone()).or_insert(0) += 1;
+            self.invalidated.push(name);
+        }
+    }
+
+    /// Discards a relation derived for an interest, along with the
+    /// relations it was in turn derived from, as long as neither
+    /// clients nor other derived relations or readers still use them.
+    /// Registered rules are never discarded. Returns the names of all
+    /// discarded relations.
+    pub fn collect(&mut self, name: &str) -> Vec<String> {
+        let mut collected = Vec::new();
+        let mut candidates = vec![name.to_string()];
+
+        while let Some(name) = candidates.pop() {
+            let source = if let Some((source, _)) = self.restrictions.get(&name) {
+                Some(source.clone())
+            } else if let Some((source, _)) = self.projections.get(&name) {
+                Some(source.clone())
+            } else if let Some((source, _, _)) = self.windows.get(&name) {
+                Some(source.clone())
+            } else if self.specializations.contains_key(&name) {
+                None
+            } else {
+                continue;
+            };
+
+            let in_use = self.subscribers.contains_key(&name)
+                || self.pinned.contains(&name)
+                || self.readers.contains_key(&name)
+                || self
+                    .restrictions
+                    .values()
+                    .any(|(source, _)| *source == name)
+                || self.projections.values().any(|(source, _)| *source == name)
+                || self.windows.values().any(|(source, _, _)| *source == name);
+
+            if in_use {
+                continue;
+            }
+
+            self.context.arrangements.remove(&name);
+            self.context
+                .derived_indices
+                .remove(&(name.clone(), IndexDirection::Forward));
+            self.context
+                .derived_indices
+                .remove(&(name.clone(), IndexDirection::Reverse));
+            self.context.shutdown_handles.remove(&name);
+            self.optimizer_fallbacks.remove(&name);
+
+            if self.specializations.remove(&name).is_some() {
+                self.context.rules.remove(&name);
+                self.context.replicated.remove(&name);
+                self.context.distinct.remove(&name);
+            }
+            self.restrictions.remove(&name);
+            if self.projections.remove(&name).is_some() {
+                self.context.replicated.remove(&name);
+            }
+            self.orderings.remove(&name);
+            self.windows.remove(&name);
+
+            // outputs of the dataflows left behind are stale, should
+            // the same relation be derived again
+            *self.generations.entry(name.clone()).or_insert(0) += 1;
+
+            candidates.extend(source);
+            collected.push(name);
+        }
+
+        collected
+    }
+
+    /// Specializes the rule an interest refers to, by binding
+    /// variables of its plan to the constants carried by the
+    /// interest. The specialized rule is registered once per distinct
+    /// set of bindings and shared by all interests in it. Restricted
+    /// interests are recorded s.t. the restriction is applied when
+    /// the interest is implemented, projected and ordered ones
+    /// likewise. Returns an equivalent interest without bindings,
+    /// restriction, projection, order, or window.
+    pub fn parameterize(&mut self, interest: Interest) -> Result<Interest, Error> {
+        if interest.bindings.is_empty()
+            && interest.restrict.is_none()
+            && interest.project.is_none()
+            && interest.order_by.is_empty()
+            && interest.window.is_none()
+        {
+            return Ok(interest);
+        }
+
+        let variables = match self.context.rules.get(&interest.name) {
+            None => {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!("Unknown rule {}.", interest.name),
+                });
+            }
+            Some(rule) => rule.plan.variables(),
+        };
+        let arity = variables.len();
+
+        if let Some(ref restriction) = interest.restrict {
+            if restriction.offset >= arity {
+                return Err(Error {
+                    category: "df.error.category/incorrect",
+                    message: format!(
+                        "Offset {} is out of bounds for rule {}.",
+                        restriction.offset, interest.name
+                    ),
+                });
+            }
+        }
+
+        // offsets of the projected variables within each tuple, and
+        // of the sort keys within each projected tuple
+        let offset_of = |variables: &[Var], variable: Var| {
+            variables
+                .iter()
+                .position(|x| *x == variable)
+                .ok_or_else(|| Error {
+                    category: "df.error.category/incorrect",
+                    message: format!(
+                        "Variable {} is not bound by interest {}.",
+                        variable, interest.name
+                    ),
+                })
+        };
+
+        let projected = interest
+            .project
+            .clone()
+            .unwrap_or_else(|| variables.clone());
+
+        let offsets = projected
+            .iter()
+            .map(|variable| offset_of(&variables, *variable))
+            .collect::<Result<Vec<usize>, Error>>()?;
+
+        let ordering = interest
+            .order_by
+            .iter()
+            .map(|key| offset_of(&projected, key.variable).map(|offset| (offset, key.descending)))
+            .collect::<Result<Vec<(usize, bool)>, Error>>()?;
+
+        // the rule specialized to the bindings (if any), which might
+        // in turn be restricted
+        let bound = Interest {
+            restrict: None,
+            project: None,
+            order_by: Vec::new(),
+            window: None,
+            ..interest.clone()
+        };
+        let name = bound.parameterized_name();
+
+        if !self.context.rules.contains_key(&name) {
+            let plan = self.context.rules[&interest.name].plan.clone();
+            let plan = if interest.specialize {
+                specialize_plan(plan, &interest.bindings)?
+            } else {
+                bind_variables(plan, &interest.bindings)?
+            };
+
+            if self.context.replicated.contains(&interest.name) {
+                self.context.replicated.insert(name.clone());
+            }
+
+            if self.context.distinct.contains(&interest.name) {
+                self.context.distinct.insert(name.clone());
+            }
+
+            self.context.rules.insert(
+                name.clone(),
+                Rule {
+                    name: name.clone(),
+                    plan,
+                },
+            );
+            self.specializations
+                .insert(name.clone(), interest.name.clone());
+        }
+
+        let name = match interest.restrict {
+            None => name,
+            Some(ref restriction) => {
+                let restricted = Interest {
+                    project: None,
+                    order_by: Vec::new(),
+                    window: None,
+                    ..interest.clone()
+                }
+                .parameterized_name();
+
+                let mut restriction = restriction.clone();
+                restriction.values.sort();
+                restriction.values.dedup();
+
+                match self.restrictions.get(&restricted) {
+                    Some((_, existing)) if *existing != restriction => {
+                        return Err(Error {
+                            category: "df.error.category/conflict",
+                            message: format!(
+                                "Restriction of interest {} collides with a different one of the same hash.",
+                                interest.name
+                            ),
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.restrictions
+                            .insert(restricted.clone(), (name, restriction));
+                    }
+                }
+
+                restricted
+            }
+        };
+
+        let name = if interest.project.is_none() && interest.order_by.is_empty() {
+            name
+        } else {
+            let shaped = Interest {
+                window: None,
+                ..interest.clone()
+            }
+            .parameterized_name();
+
+            // ordered relations are shared with all workers holding
+            // a copy, just like the relation they order
+            let offsets = if interest.project.is_none() {
+                if self.context.replicated.contains(&name) {
+                    self.context.replicated.insert(shaped.clone());
+                }
+
+                None
+            } else {
+                Some(offsets)
+            };
+
+            self.projections
+                .entry(shaped.clone())
+                .or_insert_with(|| (name, offsets));
+
+            if !ordering.is_empty() {
+                self.orderings
+                    .entry(shaped.clone())
+                    .or_insert_with(|| ordering.clone());
+            }
+
+            shaped
+        };
+
+        let name = match interest.window {
+            None => name,
+            Some(window) => {
+                let windowed = interest.parameterized_name();
+
+                self.windows
+                    .entry(windowed.clone())
+                    .or_insert_with(|| (name, ordering.clone(), window));
+
+                if !ordering.is_empty() {
+                    self.orderings.entry(windowed.clone()).or_insert(ordering);
+                }
+
+                windowed
+            }
+        };
+
+        Ok(Interest {
+            name,
+            as_of: interest.as_of,
+            bindings: Vec::new(),
+            specialize: false,
+            restrict: None,
+            project: None,
+            order_by: Vec::new(),
+            window: None,
+            delivery: interest.delivery,
+            consumer: interest.consumer,
+        })
+    }
+
+    /// Handles a Status request. Reports on the arrangements
+    /// maintained by this worker, its frontiers, and the latencies of
+    /// the commands it has processed.
+    pub fn status(&mut self) -> Status {
+        let mut arrangements = Vec::new();
+
+        let mut attributes: Vec<Aid> = self.context.internal.forward.keys().cloned().collect();
+        attributes.sort();
+
+        for name in attributes.iter() {
+            let index = self.context.internal.forward.get_mut(name).unwrap();
+            arrangements.push(arrangement_status(name, &mut index.propose_trace));
+        }
+
+        let mut relations: Vec<Aid> = self.context.arrangements.keys().cloned().collect();
+        relations.sort();
+
+        for name in relations.iter() {
+            let trace = self.context.arrangements.get_mut(name).unwrap();
+            arrangements.push(arrangement_status(name, trace));
+        }
+
+        let mut sources = self.context.internal.source_watermarks();
+        sources.sort();
+
+        let mut subscriptions: Vec<(String, usize)> = self
+            .interests
+            .iter()
+            .map(|(name, tokens)| (name.clone(), tokens.len()))
+            .collect();
+        subscriptions.sort();
+
+        Status {
+            time: *self.context.internal.time(),
+            frontier: self.probe.with_frontier(|frontier| frontier.to_vec()),
+            arrangements,
+            sources,
+            subscriptions,
+            latencies: self.latencies.clone(),
+        }
+    }
+
+    /// Handle a ListAttributes request. Returns the name of each
+    /// attribute, along with its semantics (unless it is fed by a
+    /// source) and the number of updates held by this worker's
+    /// part of its forward index.
+    pub fn list_attributes(&mut self) -> Vec<(String, Option<AttributeSemantics>, usize)> {
+        let internal = &mut self.context.internal;
+        let mut names: Vec<Aid> = internal.forward.keys().cloned().collect();
+        names.sort();
+
+        names
+            .drain(..)
+            .map(|name| {
+                let semantics = internal.semantics(&name).cloned();
+                let size =
+                    count_updates(&mut internal.forward.get_mut(&name).unwrap().propose_trace);
+
+                (name, semantics, size)
+            })
+            .collect()
+    }
+
+    /// Handle a ListRules request. Returns the name of each rule,
+    /// along with the number of updates held by this worker's part
+    /// of its arrangement, if the rule has been implemented.
+    pub fn list_rules(&mut self) -> Vec<(String, Option<usize>)> {
+        let mut names: Vec<Aid> = self.context.rules.keys().cloned().collect();
+        names.sort();
+
+        let arrangements = &mut self.context.arrangements;
+
+        names
+            .drain(..)
+            .map(|name| {
+                let size = arrangements.get_mut(&name).map(count_updates);
+                (name, size)
+            })
+            .collect()
+    }
+
+    /// Handle a RegisterSource request.
+    pub fn register_source<S: Scope<Timestamp = Time>>(
+        &mut self,
+        req: RegisterSource,
+        scope: &mut S,
+    ) -> Result<(), Error> {
+        let RegisterSource {
+            mut names,
+            source,
+            drive_domain,
+            append_only,
+        } = req;
+
+        // sources that fail to open don't drive anything
+        if names.len() == 1 {
+            let name = names.pop().unwrap();
+            let datoms = source.source(scope, names.clone())?;
+
+            if drive_domain {
+                self.drivers.insert(name.clone());
+            }
+
+            if let Some(ref mut watchdog) = self.watchdog {
+                datoms.probe_with(watchdog.watch(&name));
+            }
+
+            self.context
+                .internal
+                .create_source(&name, None, append_only, &datoms)
+        } else if names.len() > 1 {
+            let datoms = source.source(scope, names.clone())?;
+
+            if drive_domain {
+                self.drivers.extend(names.iter().cloned());
+            }
+
+            for (name_idx, name) in names.iter().enumerate() {
+                if let Some(ref mut watchdog) = self.watchdog {
+                    datoms.probe_with(watchdog.watch(name));
+                }
+
+                self.context
+                    .internal
+                    .create_source(name, Some(name_idx), append_only, &datoms)?;
+            }
+
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Handle a SampleSource request. Reports the value types and
+    /// number of values read for each attribute, as well as any
+    /// records that failed to parse.
+    pub fn sample_source(&self, req: &SampleSource) -> Result<Sample, Error> {
+        req.source.sample(&req.names, req.limit)
+    }
+
+    /// Handle a RegisterSink request.
+    pub fn register_sink<S: Scope<Timestamp = Time>>(
+        &mut self,
+        req: RegisterSink,
+        scope: &mut S,
+    ) -> Result<(), Error> {
+        let RegisterSink { name, sink } = req;
+
+        let replicated = self.context.is_replicated(&name);
+        let (index, peers) = (scope.index(), scope.peers());
+
+        let mut trace = self.interest(&name, scope)?.clone();
+
+        let variables = self
+            .context
+            .rule(&name)
+            .map(|rule| rule.plan.variables())
+            .unwrap_or_default();
+
+        let tuples = trace
+            .import_named(scope, &name)
+            .as_collection(|tuple, _| tuple.clone())
+            .inner
+            // every worker holds a full copy of replicated
+            // relations, so each only forwards its share
+            .filter(move |(tuple, _time, _diff)| {
+                !replicated || (tuple.hashed().as_u64() as usize) % peers == index
+            });
+
+        sink.sink(&name, &variables, &tuples)
+    }
+
+    /// Handle an AdvanceDomain request. Fails if the domain has
+    /// already been advanced beyond the requested time.
+    pub fn advance_domain(&mut self, name: Option<String>, next: Time) -> Result<(), Error> {
+        match name {
+            None if next < *self.context.internal.time() => Err(Error {
+                category: "df.error.category/conflict",
+                message: format!(
+                    "Domain has already been advanced to {:?}.",
+                    self.context.internal.time()
+                ),
+            }),
+            None => {
+                // If history is not enabled, we want to keep traces advanced
+                // up to the previous time.
+                let trace_next = if self.config.enable_history {
+                    None
+                } else {
+                    Some(rewind(&next, 1))
+                };
+
+                self.context.internal.advance_to(next, trace_next);
+                self.context.internal.apply_retention();
+
+                if let Some(trace_next) = trace_next {
+                    // if historical queries don't matter, we should advance
+                    // the index traces to allow them to compact, but
+                    // not beyond what any of their readers needs
+
+                    for (name, trace) in self.context.arrangements.iter_mut() {
+                        let frontier = self
+                            .readers
+                            .get(name)
+                            .into_iter()
+                            .flat_map(|readers| readers.iter())
+                            .map(|reader| reader.as_of)
+                            .fold(trace_next, std::cmp::min);
+
+                        trace.advance_by(&[frontier]);
+                    }
+
+                    // indices over published relations are held back
+                    // by the same readers
+                    for ((name, _direction), index) in self.context.derived_indices.iter_mut() {
+                        let frontier = self
+                            .readers
+                            .get(name)
+                            .into_iter()
+                            .flat_map(|readers| readers.iter())
+                            .map(|reader| reader.as_of)
+                            .fold(trace_next, std::cmp::min);
+
+                        index.advance_by(&[frontier]);
+                    }
+                }
+
+                Ok(())
+            }
+            Some(_) => Err(Error {
+                category: "df.error.category/unsupported",
+                message: "Named domains are not yet supported.".to_string(),
+            }),
+        }
+    }
+
+    /// Handle a Hold request. Fails if the relation has already been
+    /// compacted beyond the requested time.
+    pub fn hold(
+        &mut self,
+        name: &str,
+        as_of: Time,
+        owner: usize,
+        client: Token,
+    ) -> Result<(), Error>
+    where
+        Token: Eq,
+    {
+        match self.context.arrangements.get_mut(name) {
+            None => {
+                return Err(Error {
+                    category: "df.error.category/not-found",
+                    message: format!("Relation {} has not been published.", name),
+                });
+            }
+            Some(trace) => {
+                if !trace
+                    .advance_frontier()
+                    .iter()
+                    .all(|time| time.less_equal(&as_of))
+                {
+                    return Err(Error {
+                        category: "df.error.category/unsupported",
+                        message: format!(
+                            "Relation {} has been compacted beyond {:?}.",
+                            name, as_of
+                        ),
+                    });
+                }
+            }
+        }
+
+        let readers = self
+            .readers
+            .entry(name.to_string())
+            .or_insert_with(Vec::new);
+
+        match readers
+            .iter_mut()
+            .find(|reader| reader.owner == owner && reader.client == client)
+        {
+            Some(reader) => reader.as_of = as_of,
+            None => readers.push(Reader {
+                owner,
+                client,
+                as_of,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Handle a Release request. Releasing a relation without
+    /// holding it has no effect.
+    pub fn release(&mut self, name: &str, owner: usize, client: &Token)
+    where
+        Token: Eq,
+    {
+        let released = match self.readers.get_mut(name) {
+            None => false,
+            Some(readers) => {
+                readers.retain(|reader| !(reader.owner == owner && reader.client == *client));
+                readers.is_empty()
+            }
+        };
+
+        if released {
+            self.readers.remove(name);
+        }
+    }
+
+    /// Returns the names of all relations held by the specified
+    /// client, e.g. in order to release them once it disconnects.
+    pub fn held_by(&self, owner: usize, client: &Token) -> Vec<String>
+    where
+        Token: Eq,
+    {
+        self.readers
+            .iter()
+            .filter(|(_name, readers)| {
+                readers
+                    .iter()
+                    .any(|reader| reader.owner == owner && reader.client == *client)
+            })
+            .map(|(name, _readers)| name.clone())
+            .collect()
+    }
+
+    /// Records the client's interest in a relation, as returned by
+    /// `parameterize`. Expressing the same interest twice has no
+    /// effect.
+    pub fn subscribe(&mut self, name: &str, owner: usize, client: Token)
+    where
+        Token: Eq,
+    {
+        let subscribers = self
+            .subscribers
+            .entry(name.to_string())
+            .or_insert_with(Vec::new);
+
+        if !subscribers.iter().any(|(o, c)| *o == owner && *c == client) {
+            subscribers.push((owner, client));
+        }
+    }
+
+    /// Handle an Uninterest request. Ending an interest that was
+    /// never expressed has no effect. Returns the names of all
+    /// relations discarded, since no client is interested in them
+    /// any longer.
+    pub fn unsubscribe(&mut self, name: &str, owner: usize, client: &Token) -> Vec<String>
+    where
+        Token: Eq,
+    {
+        let unsubscribed = match self.subscribers.get_mut(name) {
+            None => false,
+            Some(subscribers) => {
+                subscribers.retain(|(o, c)| !(*o == owner && c == client));
+                subscribers.is_empty()
+            }
+        };
+
+        if unsubscribed {
+            self.subscribers.remove(name);
+            self.collect(name)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns one of the clients still interested in a relation, if
+    /// any.
+    pub fn subscriber(&self, name: &str) -> Option<&(usize, Token)> {
+        self.subscribers
+            .get(name)
+            .and_then(|subscribers| subscribers.first())
+    }
+
+    /// Keeps a relation journaled for a durable consumer from being
+    /// garbage-collected.
+    pub fn pin(&mut self, name: &str) {
+        self.pinned.insert(name.to_string());
+    }
+
+    /// Forgets all clients interested in a relation, e.g. because its
+    /// rule has been unregistered.
+    pub fn unsubscribe_all(&mut self, name: &str) {
+        self.subscribers.remove(name);
+        self.pinned.remove(name);
+    }
+
+    /// Returns the names of all relations the specified client is
+    /// interested in, e.g. in order to end those interests once it
+    /// disconnects.
+    pub fn subscribed_by(&self, owner: usize, client: &Token) -> Vec<String>
+    where
+        Token: Eq,
+    {
+        self.subscribers
+            .iter()
+            .filter(|(_name, subscribers)| {
+                subscribers.iter().any(|(o, c)| *o == owner && c == client)
+            })
+            .map(|(name, _subscribers)| name.clone())
+            .collect()
+    }
+
+    /// Handle a Pause request. Pausing a paused subscription has no
+    /// effect.
+    pub fn pause(&mut self, name: &str, client: Token) -> Result<(), Error>
+    where
+        Token: Eq,
+    {
+        let interested = self
+            .interests
+            .get(name)
+            .map(|tokens| tokens.contains(&client))
+            .unwrap_or(false);
+
+        if !interested {
+            return Err(Error {
+                category: "df.error.category/not-found",
+                message: format!("Not subscribed to {}.", name),
+            });
+        }
+
+        self.paused
+            .entry(client)
+            .or_insert_with(HashMap::new)
+            .entry(name.to_string())
+            .or_insert_with(Paused::default);
+
+        Ok(())
+    }
+
+    /// Returns true iff the client's subscription to the specified
+    /// relation is paused.
+    pub fn is_paused(&self, name: &str, client: &Token) -> bool
+    where
+        Token: Eq,
+    {
+        self.paused
+            .get(client)
+            .map(|paused| paused.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Holds back results of the specified relation for all paused
+    /// subscriptions to it, consolidating them as they come in.
+    pub fn hold_back(&mut self, name: &str, results: &[ResultDiff])
+    where
+        Token: Eq,
+    {
+        let limit = self.config.paused_results_limit;
+
+        for paused in self.paused.values_mut() {
+            if let Some(paused) = paused.get_mut(name) {
+                if paused.overflowed {
+                    continue;
+                }
+
+                for (tuple, time, diff) in results.iter() {
+                    let change = paused.changes.entry(tuple.clone()).or_insert((*time, 0));
+
+                    if change.0 < *time {
+                        change.0 = *time;
+                    }
+
+                    change.1 += diff;
+                }
+
+                paused.changes.retain(|_tuple, (_time, diff)| *diff != 0);
+
+                if paused.changes.len() > limit {
+                    paused.changes = HashMap::new();
+                    paused.overflowed = true;
+                }
+            }
+        }
+    }
+
+    /// Sets the policy by which results of the specified relation are
+    /// delivered to the client. Results of coalescing subscriptions
+    /// are held back until they are due, see `flush_coalesced`.
+    pub fn set_delivery(&mut self, name: &str, client: Token, delivery: Delivery)
+    where
+        Token: Eq,
+    {
+        match delivery {
+            Delivery::Immediate => {
+                if let Some(coalescing) = self.coalescing.get_mut(&client) {
+                    coalescing.remove(name);
+
+                    if coalescing.is_empty() {
+                        self.coalescing.remove(&client);
+                    }
+                }
+            }
+            _ => {
+                self.coalescing
+                    .entry(client)
+                    .or_insert_with(HashMap::new)
+                    .insert(
+                        name.to_string(),
+                        Coalescing {
+                            delivery,
+                            changes: HashMap::new(),
+                            frontier: None,
+                            ticks: 0,
+                            delivered_at: Instant::now(),
+                        },
+                    );
+            }
+        }
+    }
+
+    /// Returns true iff results of the specified relation are held
+    /// back for the client, until they are due.
+    pub fn is_coalescing(&self, name: &str, client: &Token) -> bool
+    where
+        Token: Eq,
+    {
+        self.coalescing
+            .get(client)
+            .map(|coalescing| coalescing.contains_key(name))
+            .unwrap_or(false)
+    }
+
+    /// Holds back results of the specified relation for all
+    /// coalescing subscriptions to it, consolidating them as they
+    /// come in, s.t. tuples added and retracted again within the
+    /// same window are never delivered.
+    pub fn coalesce_results(&mut self, name: &str, results: &[ResultDiff])
+    where
+        Token: Eq,
+    {
+        for coalescing in self.coalescing.values_mut() {
+            if let Some(coalescing) = coalescing.get_mut(name) {
+                for (tuple, time, diff) in results.iter() {
+                    let change = coalescing
+                        .changes
+                        .entry(tuple.clone())
+                        .or_insert((*time, 0));
+
+                    if change.0 < *time {
+                        change.0 = *time;
+                    }
+
+                    change.1 += diff;
+                }
+
+                coalescing
+                    .changes
+                    .retain(|_tuple, (_time, diff)| *diff != 0);
+            }
+        }
+    }
+
+    /// Records an advance of the frontier of the specified relation
+    /// for all coalescing subscriptions to it.
+    pub fn coalesce_frontier(&mut self, name: &str, frontier: &[Time])
+    where
+        Token: Eq,
+    {
+        for coalescing in self.coalescing.values_mut() {
+            if let Some(coalescing) = coalescing.get_mut(name) {
+                coalescing.frontier = Some(frontier.to_vec());
+                coalescing.ticks += 1;
+            }
+        }
+    }
+
+    /// Returns the results held back from coalescing subscriptions
+    /// that are due at the specified instant, along with the frontier
+    /// of the relation they have been consolidated up to. Only
+    /// subscriptions whose relation advanced since their results
+    /// were last delivered are ever due.
+    pub fn flush_coalesced(
+        &mut self,
+        now: Instant,
+    ) -> Vec<(Token, String, Vec<ResultDiff>, Vec<Time>)>
+    where
+        Token: Eq + Clone,
+    {
+        let mut due = Vec::new();
+
+        for (client, coalescing) in self.coalescing.iter_mut() {
+            for (name, coalescing) in coalescing.iter_mut() {
+                let is_due = match coalescing.delivery {
+                    Delivery::Immediate => true,
+                    Delivery::Every(millis) => {
+                        now >= coalescing.delivered_at + Duration::from_millis(millis)
+                    }
+                    Delivery::Ticks(ticks) => coalescing.ticks >= ticks,
+                };
+
+                if !is_due {
+                    continue;
+                }
+
+                if let Some(frontier) = coalescing.frontier.take() {
+                    let mut results: Vec<ResultDiff> = coalescing
+                        .changes
+                        .drain()
+                        .map(|(tuple, (time, diff))| (tuple, time, diff))
+                        .collect();
+
+                    results.sort();
+
+                    coalescing.ticks = 0;
+                    coalescing.delivered_at = now;
+
+                    due.push((client.clone(), name.clone(), results, frontier));
+                }
+            }
+        }
+
+        // paused subscriptions catch up on resume instead
+        due.retain(|(client, name, _results, _frontier)| !self.is_paused(name, client));
+
+        for (_client, name, results, _frontier) in due.iter_mut() {
+            self.sort_results(name, results);
+        }
+
+        due
+    }
+
+    /// Handle a Resume request, returning the results held back in
+    /// the meantime.
+    pub fn resume(&mut self, name: &str, client: &Token) -> Result<CatchUp, Error>
+    where
+        Token: Eq,
+    {
+        let paused = self
+            .paused
+            .get_mut(client)
+            .and_then(|paused| paused.remove(name));
+
+        if self
+            .paused
+            .get(client)
+            .map(|paused| paused.is_empty())
+            .unwrap_or(false)
+        {
+            self.paused.remove(client);
+        }
+
+        match paused {
+            None => Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Subscription to {} is not paused.", name),
+            }),
+            Some(ref paused) if paused.overflowed => Ok(CatchUp::Overflowed),
+            Some(paused) => {
+                let mut results: Vec<ResultDiff> = paused
+                    .changes
+                    .into_iter()
+                    .map(|(tuple, (time, diff))| (tuple, time, diff))
+                    .collect();
+
+                results.sort();
+                self.sort_results(name, &mut results);
+
+                Ok(CatchUp::Results(results))
+            }
+        }
+    }
+
+    /// Records the instant at which the current command was
+    /// sequenced as the one at which the current epoch of the
+    /// internal domain is being closed, via an
+    /// `[epoch :df.epoch/instant ms]` datom. Must be called only
+    /// right before actually advancing the domain. The datom is not
+    /// described in the `df.tx/*` attributes. Does nothing if the
+    /// `df.epoch/instant` attribute hasn't been created.
+    pub fn record_epoch(&mut self, owner: usize, worker_index: usize) -> Result<(), Error> {
+        if !self
+            .context
+            .internal
+            .forward
+            .contains_key("df.epoch/instant")
+        {
+            return Ok(());
+        }
+
+        // only the owner should actually introduce new inputs
+        if owner != worker_index {
+            return Ok(());
+        }
+
+        #[cfg(not(feature = "real-time"))]
+        let epoch = *self.context.internal.time() as Eid;
+        #[cfg(feature = "real-time")]
+        let epoch = self.context.internal.time().as_millis() as Eid;
+
+        let millis = self.context.internal.instant();
+
+        self.context.internal.transact(vec![TxData(
+            1,
+            epoch,
+            "df.epoch/instant".to_string(),
+            Value::Instant(millis),
+        )])
+    }
+
+    /// Handles a Flush request. The client will be acknowledged once
+    /// the probe has passed the current time.
+    pub fn flush(&mut self, client: Token) {
+        let time = *self.context.internal.time();
+        self.flushes.push_back((time, client));
+    }
+
+    /// Returns all clients whose flushes have completed, together
+    /// with the time up to which their inputs have been processed.
+    pub fn completed_flushes(&mut self) -> Vec<(Time, Token)> {
+        let mut completed = Vec::new();
+
+        // Flush times are non-decreasing, so we can stop at the
+        // first one still pending.
+        while let Some((time, _)) = self.flushes.front() {
+            if self.probe.less_equal(time) {
+                break;
+            }
+
+            completed.push(self.flushes.pop_front().unwrap());
+        }
+
+        completed
+    }
+
+    /// Records an attribute in the built-in `df.attribute/*` catalog,
+    /// s.t. clients can discover the schema at runtime. Attributes
+    /// fed by a source have no semantics of their own, but record
+    /// the kind of source instead. Catalog attributes that haven't
+    /// been created are skipped.
+    pub fn record_attribute(
+        &mut self,
+        name: &str,
+        semantics: Option<&AttributeSemantics>,
+        source: Option<&Source>,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        // Entity ids must agree across workers and restarts.
+        let eid = name.hashed().as_u64() as Eid;
+        let epoch = time_value(self.context.internal.time());
+
+        let mut facts = vec![
+            ("df.attribute/name", Value::Aid(name.to_string())),
+            ("df.attribute/epoch", epoch),
+        ];
+
+        if let Some(semantics) = semantics {
+            let semantics = match semantics {
+                AttributeSemantics::Raw => "Raw",
+                AttributeSemantics::CardinalityOne => "CardinalityOne",
+                AttributeSemantics::CardinalityMany => "CardinalityMany",
+                AttributeSemantics::FullText => "FullText",
+            };
+
+            facts.push((
+                "df.attribute/semantics",
+                Value::String(semantics.to_string()),
+            ));
+        }
+
+        if let Some(source) = source {
+            facts.push((
+                "df.attribute/source",
+                Value::String(source.kind().to_string()),
+            ));
+        }
+
+        let tx_data = facts
+            .drain(..)
+            .filter(|(aid, _)| self.context.internal.forward.contains_key(*aid))
+            .map(|(aid, v)| TxData(1, eid, aid.to_string(), v))
+            .collect();
+
+        self.transact(tx_data, owner, worker_index)
+    }
+
+    /// Attaches metadata to an attribute, recording it in the
+    /// built-in `df.attribute/*` catalog alongside the attribute
+    /// itself. Metadata attached previously is replaced.
+    pub fn describe_attribute(
+        &mut self,
+        name: &str,
+        metadata: Metadata,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        let eid = name.hashed().as_u64() as Eid;
+        let previous = self.attribute_metadata.remove(name).unwrap_or_default();
+
+        let retractions = previous.facts("df.attribute");
+        let assertions = metadata.facts("df.attribute");
+
+        if !metadata.is_empty() {
+            self.attribute_metadata.insert(name.to_string(), metadata);
+        }
+
+        self.record_metadata(eid, retractions, assertions, owner, worker_index)
+    }
+
+    /// Records a rule in the built-in `df.rule/*` catalog, along
+    /// with the metadata attached to it. Metadata attached
+    /// previously is replaced.
+    pub fn describe_rule(
+        &mut self,
+        name: &str,
+        metadata: Metadata,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        // Rules may share their name with an attribute, but must not
+        // share its entity.
+        let eid = ("df.rule", name).hashed().as_u64() as Eid;
+        let previous = self.rule_metadata.remove(name);
+
+        let retractions = previous
+            .as_ref()
+            .map(|previous| previous.facts("df.rule"))
+            .unwrap_or_default();
+
+        let mut assertions = metadata.facts("df.rule");
+
+        if previous.is_none() {
+            assertions.push(("df.rule/name".to_string(), Value::Aid(name.to_string())));
+        }
+
+        self.rule_metadata.insert(name.to_string(), metadata);
+
+        self.record_metadata(eid, retractions, assertions, owner, worker_index)
+    }
+
+    /// Retracts a rule from the built-in `df.rule/*` catalog, along
+    /// with the metadata attached to it.
+    pub fn forget_rule(
+        &mut self,
+        name: &str,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        let eid = ("df.rule", name).hashed().as_u64() as Eid;
+
+        match self.rule_metadata.remove(name) {
+            None => Ok(()),
+            Some(previous) => {
+                let mut retractions = previous.facts("df.rule");
+                retractions.push(("df.rule/name".to_string(), Value::Aid(name.to_string())));
+
+                self.record_metadata(eid, retractions, Vec::new(), owner, worker_index)
+            }
+        }
+    }
+
+    /// Returns the metadata attached to the specified attribute.
+    pub fn attribute_metadata(&self, name: &str) -> Option<&Metadata> {
+        self.attribute_metadata.get(name)
+    }
+
+    /// Returns the metadata attached to the specified rule.
+    pub fn rule_metadata(&self, name: &str) -> Option<&Metadata> {
+        self.rule_metadata
+            .get(name)
+            .filter(|metadata| !metadata.is_empty())
+    }
+
+    /// Transacts changes to catalog facts about a single entity.
+    /// Catalog attributes that haven't been created are skipped.
+    fn record_metadata(
+        &mut self,
+        eid: Eid,
+        retractions: Vec<(String, Value)>,
+        assertions: Vec<(String, Value)>,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<(), Error> {
+        let forward = &self.context.internal.forward;
+
+        let tx_data: Vec<TxData> = retractions
+            .into_iter()
+            .map(|(aid, v)| TxData(-1, eid, aid, v))
+            .chain(
+                assertions
+                    .into_iter()
+                    .map(|(aid, v)| TxData(1, eid, aid, v)),
+            )
+            .filter(|TxData(_, _, aid, _)| forward.contains_key(aid))
+            .collect();
+
+        if tx_data.is_empty() {
+            Ok(())
+        } else {
+            self.transact(tx_data, owner, worker_index)
+        }
+    }
+
+    /// Resolves the name of a snapshot to a directory within the
+    /// configured snapshot directory. Clients can't reach anywhere
+    /// else, names must be relative and can't refer to parents.
+    fn snapshot_path(&self, name: &str) -> Result<PathBuf, Error> {
+        let dir = self.config.snapshot_dir.as_ref().ok_or_else(|| Error {
+            category: "df.error.category/unsupported",
+            message: "No snapshot directory has been configured.".to_string(),
+        })?;
+
+        let confined = Path::new(name)
+            .components()
+            .all(|component| match component {
+                Component::Normal(_) => true,
+                _ => false,
+            });
+
+        if name.is_empty() || !confined {
+            return Err(Error {
+                category: "df.error.category/incorrect",
+                message: format!("Invalid snapshot name {:?}.", name),
+            });
+        }
+
+        Ok(dir.join(name))
+    }
+
+    /// Handles a Snapshot request. Each worker writes its share of
+    /// the consolidated contents of all attributes as of the current
+    /// time to a part file of its own, within the named snapshot's
+    /// directory. Parts are complete once the domain has advanced
+    /// beyond that time.
+    pub fn snapshot<S: Scope<Timestamp = Time>>(
+        &mut self,
+        name: &str,
+        scope: &mut S,
+    ) -> Result<(), Error> {
+        let path = self.snapshot_path(name)?;
+        let as_of = *self.context.internal.time();
+        let contents = self.context.internal.snapshot(scope, as_of)?;
+
+        std::fs::create_dir_all(&path).map_err(io_fault)?;
+        let part = path.join(format!("part-{}.json", scope.index()));
+
+        contents
+            .inner
+            .unary_frontier(Pipeline, "Snapshot", move |_capability, _info| {
+                let mut buffer = Vec::new();
+                let mut contents = Vec::new();
+                let mut written = false;
+
+                move |input, _output: &mut OutputHandle<_, (), _>| {
+                    input.for_each(|_time, data| {
+                        data.swap(&mut buffer);
+                        contents.extend(
+                            buffer
+                                .drain(..)
+                                .map(|((a, e, v), _t, diff)| (a, e, v, diff)),
+                        );
+                    });
+
+                    if !written && !input.frontier().less_equal(&as_of) {
+                        written = true;
+
+                        if let Err(err) = write_snapshot(&part, &contents) {
+                            error!("[SNAPSHOT] failed to write {:?}: {}", part, err);
+                        }
+
+                        contents.clear();
+                    }
+                }
+            })
+            .probe_with(&mut self.probe);
+
+        Ok(())
+    }
+
+    /// Handles a Restore request. Bulk-loads the attribute contents
+    /// of the named snapshot into the domain at the current time.
+    /// Attributes must have been created beforehand, but no relation
+    /// may have been implemented yet, s.t. the contents arrive as
+    /// the initial batch of each attribute, rather than as changes
+    /// every rule has to work through. Part files are spread across
+    /// workers, s.t. a snapshot can be restored by a different
+    /// number of workers than wrote it. All workers read all parts
+    /// nonetheless, s.t. they agree on cardinality estimates.
+    pub fn restore(&mut self, name: &str, worker_index: usize, peers: usize) -> Result<(), Error> {
+        let path = self.snapshot_path(name)?;
+
+        if !self.context.arrangements.is_empty() {
+            return Err(Error {
+                category: "df.error.category/conflict",
+                message: "Snapshots can only be restored before any relation is implemented."
+                    .to_string(),
+            });
+        }
+
+        let mut tx_data = Vec::new();
+        let mut others = Vec::new();
+
+        for entry in std::fs::read_dir(&path).map_err(io_fault)? {
+            let entry = entry.map_err(io_fault)?;
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            let part: usize = match file_name
+                .trim_start_matches("part-")
+                .trim_end_matches(".json")
+                .parse()
+            {
+                Err(_) => continue,
+                Ok(part) => part,
+            };
+
+            let part_data = if part % peers == worker_index {
+                &mut tx_data
+            } else {
+                &mut others
+            };
+
+            let reader = BufReader::new(File::open(entry.path()).map_err(io_fault)?);
+
+            for line in reader.lines() {
+                let line = line.map_err(io_fault)?;
+                let (a, e, v, diff): (Aid, Value, Value, isize) = serde_json::from_str(&line)
+                    .map_err(|err| Error {
+                        category: "df.error.category/incorrect",
+                        message: format!("Malformed snapshot {}: {}", file_name, err),
+                    })?;
+
+                match e {
+                    Value::Eid(e) => part_data.push(TxData(diff, e, a, v)),
+                    _ => {
+                        return Err(Error {
+                            category: "df.error.category/unsupported",
+                            message: format!("Only entity ids can be restored, not {:?}.", e),
+                        });
+                    }
+                }
+            }
+        }
+
+        let time = *self.context.internal.time();
+        let tx_data = self.context.internal.validate(tx_data, &time)?;
+        let others = self.context.internal.validate(others, &time)?;
+
+        self.context.count_tx_data(&tx_data);
+        self.context.count_tx_data(&others);
+
+        self.context.internal.transact_at(tx_data, time)
+    }
+
+    /// Handles a Shutdown request. Closes all inputs, s.t. dataflows
+    /// fed by them can complete, and lets all traces compact up to
+    /// the current time, releasing any readers holding them back.
+    /// Pending flushes are acknowledged as usual, once the probe has
+    /// caught up.
+    pub fn shutdown(&mut self) {
+        self.readers.clear();
+        self.context.internal.close_all();
+
+        let frontier = &[*self.context.internal.time()];
+
+        for trace in self.context.arrangements.values_mut() {
+            trace.advance_by(frontier);
+        }
+
+        for index in self.context.derived_indices.values_mut() {
+            index.advance_by(frontier);
+        }
+    }
+
+    /// Attaches the watchdog (if enabled) to a freshly implemented
+    /// relation.
+    fn watch_relation<S: Scope<Timestamp = Time>>(&mut self, name: &str, scope: &mut S) {
+        if let Some(ref mut watchdog) = self.watchdog {
+            if let Some(trace) = self.context.arrangements.get_mut(name) {
+                trace
+                    .import_named(scope, name)
+                    .stream
+                    .probe_with(watchdog.watch(name));
+            }
+        }
+    }
+
+    /// Checks whether any watched frontier has stalled, or recovered,
+    /// since the last check. Stuck names are recorded in the
+    /// `df.watchdog/stuck` relation, along with the time they failed
+    /// to reach, until they recover.
+    pub fn check_progress(
+        &mut self,
+        now: Instant,
+        owner: usize,
+        worker_index: usize,
+    ) -> Result<Vec<Alarm>, Error> {
+        let alarms = match self.watchdog {
+            None => Vec::new(),
+            Some(ref mut watchdog) => watchdog.check(self.context.internal.time(), now),
+        };
+
+        let tx_data: Vec<TxData> = alarms
+            .iter()
+            .map(|alarm| {
+                let (op, name, time) = match alarm {
+                    Alarm::Stuck(name, time) => (1, name, time),
+                    Alarm::Recovered(name, time) => (-1, name, time),
+                };
+
+                let eid = name.hashed().as_u64() as Eid;
+
+                TxData(op, eid, "df.watchdog/stuck".to_string(), time_value(time))
+            })
+            .collect();
+
+        if !tx_data.is_empty()
+            && self
+                .context
+                .internal
+                .forward
+                .contains_key("df.watchdog/stuck")
+            && owner == worker_index
+        {
+            // Alarms are raised by each worker on its own, so they
+            // mustn't count towards cardinality estimates.
+            self.context.internal.transact(tx_data)?;
+        }
+
+        Ok(alarms)
+    }
+
+    /// Returns the time up to which sources driving the domain have
+    /// made progress, i.e. the time the domain can be advanced to.
+    /// None if there are no such sources, or all of them have been
+    /// read completely.
+    pub fn source_watermark(&self) -> Option<Time> {
+        self.context
+            .internal
+            .source_watermarks()
+            .drain(..)
+            .filter(|(name, _watermark)| self.drivers.contains(name))
+            .filter_map(|(_name, watermark)| watermark)
+            .min()
+    }
+
+    /// Returns the time the domain should be advanced to, following
+    /// the sources driving it, within the limits of the simulation
+    /// (if any).
+    pub fn domain_target(&self) -> Option<Time> {
+        match self.simulation {
+            None => self.source_watermark(),
+            Some(ref simulation) => simulation.target(self.source_watermark()),
+        }
+    }
+
+    /// Handles a Simulate request.
+    pub fn simulate(&mut self, request: Simulate) -> Result<(), Error> {
+        match self.simulation {
+            None => Err(Error {
+                category: "df.error.category/unsupported",
+                message: "Simulation mode is not enabled.".to_string(),
+            }),
+            Some(ref mut simulation) => {
+                simulation.control(request, self.context.internal.time());
+                self.context.internal.gate_sources(simulation.horizon());
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the names of sources that have been read completely
+    /// since the last check. These are recorded in the
+    /// `df.source/complete` relation.
+    pub fn check_sources(&mut self, owner: usize, worker_index: usize) -> Result<Vec<Aid>, Error> {
+        let mut completed: Vec<Aid> = self
+            .context
+            .internal
+            .source_watermarks()
+            .drain(..)
+            .filter(|(name, watermark)| {
+                watermark.is_none() && !self.completed_sources.contains(name)
+            })
+            .map(|(name, _watermark)| name)
+            .collect();
+
+        completed.sort();
+        self.completed_sources.extend(completed.iter().cloned());
+
+        let tx_data: Vec<TxData> = completed
+            .iter()
+            .map(|name| {
+                let eid = name.hashed().as_u64() as Eid;
+                TxData(
+                    1,
+                    eid,
+                    "df.source/complete".to_string(),
+                    Value::Aid(name.clone()),
+                )
+            })
+            .collect();
+
+        if !tx_data.is_empty()
+            && self
+                .context
+                .internal
+                .forward
+                .contains_key("df.source/complete")
+            && owner == worker_index
+        {
+            // Completion is observed by each worker on its own, so
+            // it mustn't count towards cardinality estimates.
+            self.context.internal.transact(tx_data)?;
+        }
+
+        Ok(completed)
+    }
+
+    /// Records the logging events collected (if enabled) since the
+    /// last check in the `df.timely.*` and `df.differential.*`
+    /// attributes. Events are specific to each worker, so every
+    /// worker records its own.
+    pub fn check_logs(&mut self) -> Result<(), Error> {
+        let tx_data = match self.logging {
+            None => return Ok(()),
+            Some(ref mut logging) => logging.drain(),
+        };
+
+        if tx_data.is_empty() {
+            return Ok(());
+        }
+
+        // Events are observed by each worker on its own, so they
+        // mustn't count towards cardinality estimates.
+        self.context.internal.transact(tx_data)
+    }
+
+    /// Reports the resources consumed (if accounting is enabled) to
+    /// all usage hooks, provided they changed and the accounting
+    /// interval has passed, and returns the `df.usage.*` datoms
+    /// recording them. Operator times and arrangement sizes are only
+    /// available with logging enabled. Every worker accounts for its
+    /// own share, but the datoms must be transacted via the
+    /// sequencer, s.t. all workers agree on them.
+    pub fn check_usage(&mut self, now: Instant, worker_index: usize) -> Vec<TxData> {
+        let operators = match self.logging {
+            None => Vec::new(),
+            Some(ref logging) => logging.operators(),
+        };
+
+        let tx_data = match self.accounting {
+            None => return Vec::new(),
+            Some(ref mut accounting) => accounting.check(worker_index, &operators, now),
+        };
+
+        let forward = &self.context.internal.forward;
+        tx_data
+            .into_iter()
+            .filter(|TxData(_, _, aid, _)| forward.contains_key(aid))
+            .collect()
     }
 
     /// Returns true iff the probe is behind any input handle. Mostly
@@ -436,7 +4033,7 @@ impl<Token: Hash> Server<Token> {
 
     /// Helper for registering, publishing, and indicating interest in
     /// a single, named query. Used for testing.
-    pub fn test_single<S: Scope<Timestamp = u64>>(
+    pub fn test_single<S: Scope<Timestamp = Time>>(
         &mut self,
         scope: &mut S,
         rule: Rule,
@@ -447,6 +4044,10 @@ impl<Token: Hash> Server<Token> {
         self.register(Register {
             rules: vec![rule],
             publish: vec![publish_name],
+            conflict_policy: Default::default(),
+            replicate: Vec::new(),
+            distinct: Vec::new(),
+            metadata: Default::default(),
         })
         .unwrap();
 
@@ -0,0 +1,130 @@
+//! Replication of a primary server's writes to read replicas.
+//!
+//! A primary appends every write it has applied to a replication
+//! log, one line per sequenced command, along with the instant at
+//! which the command was sequenced. Replicas follow that log and
+//! apply the same writes in the same order, at the same times and
+//! instants, while
+//! serving interests of their own clients locally. Writes must
+//! always go to the primary.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::server::catalog::encode_request;
+use crate::server::{parse_requests, Request};
+use crate::sources::tail::Tail;
+use crate::Error;
+
+/// Wraps an io error.
+fn fault(err: std::io::Error) -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: err.to_string(),
+    }
+}
+
+/// Returns true iff the request modifies the state shared between a
+/// primary and its replicas, i.e. it is replicated and must not be
+/// issued to replicas directly. Source registrations are not
+/// replicated, s.t. replicas don't compete with the primary for the
+/// data sources hand out (e.g. partitions of a Kafka topic).
+pub fn is_replicated(request: &Request) -> bool {
+    match *request {
+        Request::Transact(_)
+        | Request::TransactAt(_, _)
+        | Request::Register(_)
+        | Request::Unregister(_)
+        | Request::CreateAttribute(_)
+        | Request::AdvanceDomain(_, _)
+        | Request::CloseInput(_)
+        | Request::Restore(_) => true,
+        Request::Batch(ref requests) => requests.iter().any(is_replicated),
+        _ => false,
+    }
+}
+
+/// The primary's end of the replication log, opened for appending.
+pub struct ReplicationLog {
+    journal: File,
+}
+
+impl ReplicationLog {
+    /// Opens the log at the specified path, creating it if it doesn't
+    /// exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(fault)?;
+
+        Ok(ReplicationLog { journal })
+    }
+
+    /// Appends the writes applied as part of a single command,
+    /// sequenced at the specified instant (in milliseconds since the
+    /// unix epoch). Any `Transact` must have been pinned to the time
+    /// at which it was applied (as a `TransactAt`), s.t. replicas
+    /// agree on the times of all inputs.
+    pub fn record(&mut self, instant: u64, requests: &[Request]) -> Result<(), Error> {
+        if requests.is_empty() {
+            return Ok(());
+        }
+
+        let serialized = requests
+            .iter()
+            .map(encode_request)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let line = serde_json::to_string(&serde_json::json!({
+            "instant": instant,
+            "requests": serialized,
+        }))
+        .expect("failed to serialize requests");
+
+        writeln!(self.journal, "{}", line).map_err(fault)?;
+        self.journal.flush().map_err(fault)
+    }
+}
+
+/// A replica's end of the replication log, following it from the
+/// beginning.
+pub struct Replica {
+    tail: Tail,
+}
+
+impl Replica {
+    /// Starts following the log at the specified path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Replica {
+            tail: Tail::open(path, true).map_err(fault)?,
+        })
+    }
+
+    /// Returns the commands appended to the log since the last call,
+    /// in order, along with the instants at which they were
+    /// sequenced.
+    pub fn poll(&mut self) -> Result<Vec<(u64, Vec<Request>)>, Error> {
+        let mut commands = Vec::new();
+
+        while let Some(line) = self.tail.next_line() {
+            if !line.trim().is_empty() {
+                let command: serde_json::Value =
+                    serde_json::from_str(&line).map_err(|err| Error {
+                        category: "df.error.category/incorrect",
+                        message: err.to_string(),
+                    })?;
+
+                let instant = command["instant"].as_u64().ok_or_else(|| Error {
+                    category: "df.error.category/incorrect",
+                    message: "Replicated commands must carry an instant.".to_string(),
+                })?;
+
+                commands.push((instant, parse_requests(&command["requests"].to_string())?));
+            }
+        }
+
+        Ok(commands)
+    }
+}
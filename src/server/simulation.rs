@@ -0,0 +1,95 @@
+//! Replaying historical sources at a controlled pace, e.g. in order
+//! to backtest rules.
+
+use crate::Time;
+
+/// A request controlling how far a simulation may advance.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum Simulate {
+    /// Holds the domain at its current time.
+    Pause,
+    /// Lets the domain follow its sources freely.
+    Resume,
+    /// Lets the domain advance by the specified number of epochs.
+    Step(u64),
+    /// Lets the domain advance up to the specified time.
+    Jump(Time),
+}
+
+/// Keeps track of how far the domain may follow its sources. In
+/// simulation mode the domain is advanced by nothing else, in
+/// particular not by the wall-clock or by commands, and sources
+/// don't deliver any data beyond it.
+pub struct Simulation {
+    /// Time up to which the domain may advance, or None if it may
+    /// follow its sources freely.
+    horizon: Option<Time>,
+}
+
+/// Returns the time the specified number of epochs after `time`.
+#[cfg(not(feature = "real-time"))]
+fn after(time: &Time, epochs: u64) -> Time {
+    time + epochs
+}
+
+/// Returns the time the specified number of epochs (milliseconds)
+/// after `time`.
+#[cfg(feature = "real-time")]
+fn after(time: &Time, epochs: u64) -> Time {
+    *time + std::time::Duration::from_millis(epochs)
+}
+
+impl Simulation {
+    /// Creates a new simulation, paused at the very beginning.
+    pub fn new() -> Self {
+        Simulation {
+            horizon: Some(Default::default()),
+        }
+    }
+
+    /// Applies a control request, given the current time of the
+    /// domain.
+    pub fn control(&mut self, request: Simulate, now: &Time) {
+        self.horizon = match request {
+            Simulate::Pause => Some(*now),
+            Simulate::Resume => None,
+            Simulate::Step(epochs) => {
+                let from = match self.horizon {
+                    Some(horizon) if horizon > *now => horizon,
+                    _ => *now,
+                };
+
+                Some(after(&from, epochs))
+            }
+            Simulate::Jump(time) => Some(time),
+        };
+    }
+
+    /// Returns the time up to which the domain may advance, or None
+    /// if it may follow its sources freely. Sources are held back
+    /// at this time.
+    pub fn horizon(&self) -> Option<Time> {
+        self.horizon
+    }
+
+    /// Returns the time the domain should be advanced to, given the
+    /// watermark of its sources (None once they have been read
+    /// completely). Sources that have been read completely no longer
+    /// hold back a paused simulation, s.t. a final Jump can release
+    /// their last epochs.
+    pub fn target(&self, watermark: Option<Time>) -> Option<Time> {
+        match self.horizon {
+            None => watermark,
+            Some(horizon) => match watermark {
+                None => Some(horizon),
+                Some(watermark) => Some(std::cmp::min(watermark, horizon)),
+            },
+        }
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation::new()
+    }
+}
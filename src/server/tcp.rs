@@ -0,0 +1,191 @@
+//! Plain TCP connections, speaking length-prefixed frames instead of
+//! WebSocket messages.
+
+use std::io::{ErrorKind, Read, Write};
+
+use mio::net::TcpStream;
+use mio::Ready;
+
+/// Frames larger than this are considered corrupt. Frames are
+/// buffered in full before they are decoded, so this bounds the
+/// memory a single client can tie up.
+pub const MAX_FRAME_LENGTH: usize = 1 << 24;
+
+/// Clients falling behind by more than this many queued bytes are
+/// disconnected, rather than buffered for indefinitely.
+pub const MAX_OUTGOING_LENGTH: usize = 4 * MAX_FRAME_LENGTH;
+
+/// Encodes a message as a frame, i.e. its length as a 32 bit
+/// big-endian integer, followed by the message itself.
+pub fn encode_frame(message: &[u8]) -> Vec<u8> {
+    let length = message.len() as u32;
+    let mut frame = Vec::with_capacity(4 + message.len());
+
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(message);
+
+    frame
+}
+
+/// Reassembles frames from a stream of bytes, which might split them
+/// arbitrarily.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Creates a new decoder.
+    pub fn new() -> Self {
+        FrameDecoder { buffer: Vec::new() }
+    }
+
+    /// Appends bytes read from the stream.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete message, if any. Fails on frames
+    /// exceeding `MAX_FRAME_LENGTH`.
+    pub fn next_message(&mut self) -> Result<Option<Vec<u8>>, String> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let mut prefix = [0; 4];
+        prefix.copy_from_slice(&self.buffer[..4]);
+        let length = u32::from_be_bytes(prefix) as usize;
+
+        if length > MAX_FRAME_LENGTH {
+            return Err(format!("Frame of length {} exceeds the limit.", length));
+        }
+
+        if self.buffer.len() < 4 + length {
+            return Ok(None);
+        }
+
+        Ok(Some(self.buffer.drain(..4 + length).skip(4).collect()))
+    }
+}
+
+/// A client connected via plain TCP.
+pub struct FramedConnection {
+    socket: TcpStream,
+    decoder: FrameDecoder,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl FramedConnection {
+    /// Wraps a freshly accepted socket.
+    pub fn new(socket: TcpStream) -> Self {
+        FramedConnection {
+            socket,
+            decoder: FrameDecoder::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Returns the underlying socket, e.g. to (re)register it.
+    pub fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    /// Returns the events this connection is waiting on. Closed
+    /// connections wait to be writable, s.t. they are noticed soon.
+    pub fn events(&self) -> Ready {
+        if self.outgoing.is_empty() && !self.closed {
+            Ready::readable()
+        } else {
+            Ready::readable() | Ready::writable()
+        }
+    }
+
+    /// Returns true iff the peer has closed the connection, or sent
+    /// a corrupt frame.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Reads all available bytes from the socket, returning the
+    /// messages completed by them. Frames are decoded as their bytes
+    /// arrive, s.t. oversized ones are rejected by their length
+    /// prefix, before any more of them is buffered.
+    pub fn read(&mut self) -> Vec<Vec<u8>> {
+        let mut buffer = [0; 4096];
+        let mut messages = Vec::new();
+
+        while !self.closed {
+            match self.socket.read(&mut buffer) {
+                Ok(0) => {
+                    self.closed = true;
+                }
+                Ok(read) => {
+                    self.decoder.extend(&buffer[..read]);
+
+                    loop {
+                        match self.decoder.next_message() {
+                            Ok(None) => break,
+                            Ok(Some(message)) => messages.push(message),
+                            Err(err) => {
+                                error!("[TCP] dropping connection: {}", err);
+                                self.closed = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    trace!("[TCP] error while reading: {}", err);
+                    self.closed = true;
+                }
+            }
+        }
+
+        messages
+    }
+
+    /// Queues a message for sending. Drops the connection instead,
+    /// if that would exceed `MAX_OUTGOING_LENGTH`.
+    pub fn send(&mut self, message: &[u8]) {
+        if self.closed {
+            return;
+        }
+
+        if self.outgoing.len() + 4 + message.len() > MAX_OUTGOING_LENGTH {
+            error!(
+                "[TCP] dropping connection: {} bytes queued for a slow client.",
+                self.outgoing.len()
+            );
+            self.outgoing.clear();
+            self.closed = true;
+        } else {
+            self.outgoing.extend(encode_frame(message));
+        }
+    }
+
+    /// Writes as many queued bytes as the socket accepts.
+    pub fn write(&mut self) {
+        while !self.outgoing.is_empty() {
+            match self.socket.write(&self.outgoing) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    trace!("[TCP] error while writing: {}", err);
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+    }
+}
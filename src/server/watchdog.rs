@@ -0,0 +1,101 @@
+//! Detection of stalled progress.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use timely::dataflow::ProbeHandle;
+
+use crate::Time;
+
+/// A change in the progress status of a watched name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    /// The frontier failed to reach the specified time within the
+    /// timeout.
+    Stuck(String, Time),
+    /// A previously stuck frontier has reached the time it was
+    /// waiting on.
+    Recovered(String, Time),
+}
+
+/// Watches the frontiers of named relations and sources, reporting
+/// those that fail to keep up with the domain. A frontier is only
+/// considered stuck if it doesn't reach a time it was expected to
+/// reach for longer than the timeout, s.t. relations that merely lag
+/// behind a steady stream of inputs are not reported.
+pub struct Watchdog {
+    /// How long a frontier may stall before it is reported.
+    timeout: Duration,
+    /// Probes observing each watched frontier.
+    probes: HashMap<String, ProbeHandle<Time>>,
+    /// Watched names whose frontier is behind, along with the time
+    /// it was expected to reach and the instant it fell behind.
+    behind: HashMap<String, (Time, Instant)>,
+    /// Watched names that have been reported as stuck.
+    stuck: HashSet<String>,
+}
+
+impl Watchdog {
+    /// Creates a new watchdog reporting frontiers stalled for longer
+    /// than the specified timeout.
+    pub fn new(timeout: Duration) -> Self {
+        Watchdog {
+            timeout,
+            probes: HashMap::new(),
+            behind: HashMap::new(),
+            stuck: HashSet::new(),
+        }
+    }
+
+    /// Returns the timeout after which stalled frontiers are
+    /// reported.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns a probe to attach to the stream whose progress should
+    /// be watched under the specified name.
+    pub fn watch(&mut self, name: &str) -> &mut ProbeHandle<Time> {
+        self.probes
+            .entry(name.to_string())
+            .or_insert_with(ProbeHandle::new)
+    }
+
+    /// Compares all watched frontiers against the time they are
+    /// expected to reach and returns any changes in their status.
+    pub fn check(&mut self, expected: &Time, now: Instant) -> Vec<Alarm> {
+        let mut alarms = Vec::new();
+
+        for (name, probe) in self.probes.iter() {
+            if let Some((target, since)) = self.behind.get(name).cloned() {
+                if probe.less_than(&target) {
+                    if now.duration_since(since) >= self.timeout && self.stuck.insert(name.clone())
+                    {
+                        alarms.push(Alarm::Stuck(name.clone(), target));
+                    }
+
+                    continue;
+                }
+
+                self.behind.remove(name);
+
+                if self.stuck.remove(name) {
+                    alarms.push(Alarm::Recovered(name.clone(), target));
+                }
+            }
+
+            if probe.less_than(expected) {
+                self.behind.insert(name.clone(), (expected.clone(), now));
+            }
+        }
+
+        alarms
+    }
+
+    /// Returns the names currently reported as stuck.
+    pub fn stuck(&self) -> Vec<String> {
+        let mut stuck: Vec<String> = self.stuck.iter().cloned().collect();
+        stuck.sort();
+        stuck
+    }
+}
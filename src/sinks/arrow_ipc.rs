@@ -0,0 +1,281 @@
+//! Operator and utilities to write the changes to a relation as
+//! Apache Arrow record batches (IPC stream format), for consumption
+//! by analytical tools, e.g. via `pyarrow.ipc.open_stream`.
+
+extern crate arrow;
+extern crate timely;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, BooleanBuilder, Int64Builder, StringBuilder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+
+use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::generic::{Operator, OutputHandle};
+use timely::dataflow::{Scope, Stream};
+
+use crate::sinks::Sinkable;
+use crate::{time_value, Error, ResultDiff, Time, Value, Var};
+
+/// A directory receiving one Arrow IPC stream per epoch in which the
+/// relation it is attached to changes, named `<relation>-<time>.arrows`.
+/// Each stream holds a single record batch with one column per
+/// variable of the relation (named `?<var>`), followed by `df.time`
+/// and `df.diff` columns.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct ArrowFile {
+    /// Directory to write to. Must exist.
+    pub directory: String,
+}
+
+fn fault(err: ArrowError) -> Error {
+    Error {
+        category: "df.error.category/fault",
+        message: format!("{:?}", err),
+    }
+}
+
+/// Arrow type used to represent a column of values. Columns mixing
+/// different kinds of values, as well as kinds without a natural
+/// Arrow counterpart, are represented by their json encoding.
+fn column_type(values: &[&Value]) -> DataType {
+    let kind = |value: &Value| match value {
+        Value::Number(_) => DataType::Int64,
+        Value::Eid(_) | Value::Instant(_) => DataType::UInt64,
+        Value::Bool(_) => DataType::Boolean,
+        _ => DataType::Utf8,
+    };
+
+    match values.first() {
+        None => DataType::Utf8,
+        Some(first) => {
+            let data_type = kind(first);
+
+            if values.iter().all(|value| kind(value) == data_type) {
+                data_type
+            } else {
+                DataType::Utf8
+            }
+        }
+    }
+}
+
+fn column(data_type: &DataType, values: &[&Value]) -> Result<ArrayRef, Error> {
+    match data_type {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::new(values.len());
+            for value in values.iter() {
+                match value {
+                    Value::Number(x) => builder.append_value(*x).map_err(fault)?,
+                    _ => builder.append_null().map_err(fault)?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        DataType::UInt64 => {
+            let mut builder = UInt64Builder::new(values.len());
+            for value in values.iter() {
+                match value {
+                    Value::Eid(x) => builder.append_value(*x as u64).map_err(fault)?,
+                    Value::Instant(x) => builder.append_value(*x).map_err(fault)?,
+                    _ => builder.append_null().map_err(fault)?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        DataType::Boolean => {
+            let mut builder = BooleanBuilder::new(values.len());
+            for value in values.iter() {
+                match value {
+                    Value::Bool(x) => builder.append_value(*x).map_err(fault)?,
+                    _ => builder.append_null().map_err(fault)?,
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        _ => {
+            let mut builder = StringBuilder::new(values.len());
+            for value in values.iter() {
+                match value {
+                    Value::String(x) | Value::Aid(x) => builder.append_value(x).map_err(fault)?,
+                    other => {
+                        let json = serde_json::to_string(other).map_err(|err| Error {
+                            category: "df.error.category/fault",
+                            message: err.to_string(),
+                        })?;
+
+                        builder.append_value(&json).map_err(fault)?
+                    }
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+    }
+}
+
+/// Encodes a set of results as a single record batch in Arrow IPC
+/// stream format. All tuples must be of the same arity.
+pub fn encode(variables: &[Var], results: &[ResultDiff]) -> Result<Vec<u8>, Error> {
+    let arity = results
+        .first()
+        .map_or(variables.len(), |(tuple, _time, _diff)| tuple.len());
+
+    if let Some((tuple, _time, _diff)) = results.iter().find(|(tuple, _, _)| tuple.len() != arity) {
+        return Err(Error {
+            category: "df.error.category/incorrect",
+            message: format!(
+                "Can't encode tuples of arity {} and {} into the same batch.",
+                arity,
+                tuple.len()
+            ),
+        });
+    }
+
+    let mut fields = Vec::with_capacity(arity + 2);
+    let mut columns = Vec::with_capacity(arity + 2);
+
+    for offset in 0..arity {
+        let values: Vec<&Value> = results
+            .iter()
+            .map(|(tuple, _time, _diff)| &tuple[offset])
+            .collect();
+
+        let name = match variables.get(offset) {
+            None => format!("?{}", offset),
+            Some(var) => format!("?{}", var),
+        };
+
+        let data_type = column_type(&values);
+        columns.push(column(&data_type, &values)?);
+        fields.push(Field::new(&name, data_type, true));
+    }
+
+    let times: Vec<Value> = results
+        .iter()
+        .map(|(_tuple, time, _diff)| time_value(time))
+        .collect();
+    let times: Vec<&Value> = times.iter().collect();
+    let time_type = column_type(&times);
+    columns.push(column(&time_type, &times)?);
+    fields.push(Field::new("df.time", time_type, false));
+
+    let mut diffs = Int64Builder::new(results.len());
+    for (_tuple, _time, diff) in results.iter() {
+        diffs.append_value(*diff as i64).map_err(fault)?;
+    }
+    columns.push(Arc::new(diffs.finish()));
+    fields.push(Field::new("df.diff", DataType::Int64, false));
+
+    let schema = Schema::new(fields);
+    let batch = RecordBatch::try_new(Arc::new(schema.clone()), columns).map_err(fault)?;
+
+    let mut buffer = Vec::new();
+    {
+        let mut writer = StreamWriter::try_new(&mut buffer, &schema).map_err(fault)?;
+        writer.write(&batch).map_err(fault)?;
+        writer.finish().map_err(fault)?;
+    }
+
+    Ok(buffer)
+}
+
+/// Formats a time for use in file names.
+fn epoch(time: &Time) -> Result<String, Error> {
+    match time_value(time) {
+        Value::Number(epoch) => Ok(epoch.to_string()),
+        Value::Instant(millis) => Ok(millis.to_string()),
+        other => Err(Error {
+            category: "df.error.category/unsupported",
+            message: format!("Unexpected time value {:?}.", other),
+        }),
+    }
+}
+
+impl Sinkable for ArrowFile {
+    fn sink<G: Scope<Timestamp = Time>>(
+        &self,
+        name: &str,
+        variables: &[Var],
+        stream: &Stream<G, ResultDiff>,
+    ) -> Result<(), Error> {
+        let directory = PathBuf::from(&self.directory);
+        let variables = variables.to_vec();
+        let name = name.to_string();
+
+        // All changes are routed to a single worker, s.t. each epoch
+        // ends up in a single batch.
+        stream.unary_frontier(
+            Exchange::new(|_| 0),
+            &format!("ArrowFile({})", self.directory),
+            move |_capability, _info| {
+                let mut pending: HashMap<Time, HashMap<Vec<Value>, isize>> = HashMap::new();
+                let mut buffer = Vec::new();
+
+                move |input, _output: &mut OutputHandle<_, (), _>| {
+                    input.for_each(|_time, data| {
+                        data.swap(&mut buffer);
+
+                        for (tuple, time, diff) in buffer.drain(..) {
+                            *pending
+                                .entry(time)
+                                .or_insert_with(HashMap::new)
+                                .entry(tuple)
+                                .or_insert(0) += diff;
+                        }
+                    });
+
+                    let frontier = input.frontier();
+                    let mut complete: Vec<Time> = pending
+                        .keys()
+                        .filter(|time| !frontier.less_equal(time))
+                        .cloned()
+                        .collect();
+
+                    complete.sort();
+
+                    for time in complete.drain(..) {
+                        let results: Vec<ResultDiff> = pending
+                            .remove(&time)
+                            .unwrap()
+                            .into_iter()
+                            .filter(|(_tuple, diff)| *diff != 0)
+                            .map(|(tuple, diff)| (tuple, time, diff))
+                            .collect();
+
+                        if results.is_empty() {
+                            continue;
+                        }
+
+                        let written = epoch(&time).and_then(|epoch| {
+                            let path = directory.join(format!("{}-{}.arrows", name, epoch));
+                            let bytes = encode(&variables, &results)?;
+
+                            File::create(&path)
+                                .and_then(|mut file| file.write_all(&bytes))
+                                .map_err(|err| Error {
+                                    category: "df.error.category/fault",
+                                    message: format!("{:?}: {}", path, err),
+                                })
+                        });
+
+                        if let Err(error) = written {
+                            error!(
+                                "[ARROW] failed to write results of {} at {:?}: {:?}",
+                                name, time, error
+                            );
+                        }
+                    }
+                }
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+//! Types and operators to feed outputs of dataflows into external
+//! systems.
+
+extern crate timely;
+
+use timely::dataflow::{Scope, Stream};
+
+use crate::{Error, ResultDiff, Time, Var};
+
+#[cfg(feature = "arrow-ipc")]
+pub mod arrow_ipc;
+#[cfg(feature = "arrow-ipc")]
+pub use self::arrow_ipc::ArrowFile;
+pub mod webhook;
+pub use self::webhook::Webhook;
+
+/// An external system that can receive the outputs of a relation.
+pub trait Sinkable {
+    /// Creates a timely operator feeding the changes to the named
+    /// relation into the sink. Tuples bind the given variables, in
+    /// order.
+    fn sink<G: Scope<Timestamp = Time>>(
+        &self,
+        name: &str,
+        variables: &[Var],
+        stream: &Stream<G, ResultDiff>,
+    ) -> Result<(), Error>;
+}
+
+/// Supported external sinks.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum Sink {
+    /// HTTP endpoints receiving a POST whenever a relation changes
+    Webhook(Webhook),
+    /// Directories receiving Arrow record batches for each epoch
+    #[cfg(feature = "arrow-ipc")]
+    ArrowFile(ArrowFile),
+}
+
+impl Sinkable for Sink {
+    fn sink<G: Scope<Timestamp = Time>>(
+        &self,
+        name: &str,
+        variables: &[Var],
+        stream: &Stream<G, ResultDiff>,
+    ) -> Result<(), Error> {
+        match *self {
+            Sink::Webhook(ref sink) => sink.sink(name, variables, stream),
+            #[cfg(feature = "arrow-ipc")]
+            Sink::ArrowFile(ref sink) => sink.sink(name, variables, stream),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Operator and utilities to POST changes to a relation to an HTTP
+//! endpoint, e.g. to turn rules into alerts.
+
+extern crate serde_json;
+extern crate timely;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use timely::dataflow::channels::pact::Exchange;
+use timely::dataflow::operators::generic::{Operator, OutputHandle};
+use timely::dataflow::{Scope, Stream};
+
+use crate::sinks::Sinkable;
+use crate::{Error, ResultDiff, Time, Value, Var};
+
+/// An HTTP endpoint receiving a JSON payload of the form
+/// `{"df.alert/name": .., "df.alert/added": [..], "df.alert/retracted": [..]}`
+/// whenever the relation it is attached to changes.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Endpoint to POST to, e.g. `http://localhost:8080/alerts`. Only
+    /// plain http is supported.
+    pub url: String,
+    /// Minimum number of milliseconds between two requests. Changes
+    /// are accumulated in the meantime, s.t. tuples that are added
+    /// and retracted again in between don't cause any requests.
+    #[serde(default)]
+    pub debounce_ms: u64,
+    /// Should retracted tuples be reported, too?
+    #[serde(default)]
+    pub notify_retractions: bool,
+}
+
+/// Splits a url into the address to connect to, the host, and the
+/// path to request.
+pub(crate) fn parse_url(url: &str) -> Result<(String, String, String), Error> {
+    let rest = if url.starts_with("http://") {
+        &url["http://".len()..]
+    } else {
+        return Err(Error {
+            category: "df.error.category/unsupported",
+            message: format!("Webhook {} must use plain http.", url),
+        });
+    };
+
+    let (host, path) = match rest.find('/') {
+        None => (rest, "/"),
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+    };
+
+    if host.is_empty() {
+        return Err(Error {
+            category: "df.error.category/incorrect",
+            message: format!("Webhook {} is missing a host.", url),
+        });
+    }
+
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    Ok((address, host.to_string(), path.to_string()))
+}
+
+/// How long to wait for an endpoint to accept a connection, a
+/// request, or to respond, before giving up on the request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of requests waiting to be sent by the HTTP worker.
+/// Further requests are dropped, s.t. an endpoint that is slow to
+/// respond can't make them pile up.
+const QUEUE_LIMIT: usize = 1024;
+
+/// A request to be sent by the HTTP worker: the address to connect
+/// to, the host, the path, and the payload.
+type Outgoing = (String, String, String, String);
+
+thread_local! {
+    /// The HTTP worker shared by all webhooks of this worker, s.t.
+    /// requests are sent in order and without blocking the worker.
+    /// Started once the first request is sent.
+    static OUTBOX: RefCell<Option<SyncSender<Outgoing>>> = RefCell::new(None);
+}
+
+/// Hands a request to the shared HTTP worker, starting it if
+/// necessary. Fails if it can't be started, has terminated, or too
+/// many requests are waiting already.
+pub(crate) fn send(address: &str, host: &str, path: &str, payload: String) -> Result<(), Error> {
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+
+        if outbox.is_none() {
+            let (send, recv) = sync_channel::<Outgoing>(QUEUE_LIMIT);
+
+            thread::Builder::new()
+                .name("webhooks".to_string())
+                .spawn(move || {
+                    for (address, host, path, payload) in recv.iter() {
+                        if let Err(err) = post(&address, &host, &path, &payload) {
+                            error!("[WEBHOOK] failed to reach {}: {}", host, err);
+                        }
+                    }
+                })
+                .map_err(|err| Error {
+                    category: "df.error.category/fault",
+                    message: format!("Failed to start the webhook worker: {}", err),
+                })?;
+
+            *outbox = Some(send);
+        }
+
+        let request = (
+            address.to_string(),
+            host.to_string(),
+            path.to_string(),
+            payload,
+        );
+
+        match outbox.as_ref().map(|outbox| outbox.try_send(request)) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(TrySendError::Full(_))) => Err(Error {
+                category: "df.error.category/fault",
+                message: format!(
+                    "{} requests are waiting to be sent already, dropping this one.",
+                    QUEUE_LIMIT
+                ),
+            }),
+            _ => {
+                // started anew with the next request
+                *outbox = None;
+
+                Err(Error {
+                    category: "df.error.category/fault",
+                    message: "The webhook worker has terminated.".to_string(),
+                })
+            }
+        }
+    })
+}
+
+/// Connects to the first of the addresses a host resolves to that
+/// accepts a connection in time.
+fn connect(address: &str) -> std::io::Result<TcpStream> {
+    let mut last_error = None;
+
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} doesn't resolve to any address", address),
+        )
+    }))
+}
+
+/// Sends a single request and checks the response status.
+pub(crate) fn post(address: &str, host: &str, path: &str, payload: &str) -> std::io::Result<()> {
+    let mut stream = connect(address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        payload.len(),
+        payload
+    )?;
+
+    let mut status_line = String::new();
+    BufReader::new(&stream).read_line(&mut status_line)?;
+
+    let success = status_line
+        .split_whitespace()
+        .nth(1)
+        .map_or(false, |status| status.starts_with('2'));
+
+    if !success {
+        warn!("[WEBHOOK] {} responded with {:?}", host, status_line.trim());
+    }
+
+    Ok(())
+}
+
+impl Sinkable for Webhook {
+    fn sink<G: Scope<Timestamp = Time>>(
+        &self,
+        name: &str,
+        _variables: &[Var],
+        stream: &Stream<G, ResultDiff>,
+    ) -> Result<(), Error> {
+        let (address, host, path) = parse_url(&self.url)?;
+        let debounce = Duration::from_millis(self.debounce_ms);
+        let notify_retractions = self.notify_retractions;
+        let name = name.to_string();
+
+        let scope = stream.scope();
+
+        // All changes are routed to a single worker, s.t. each of
+        // them is only reported once.
+        stream.unary_frontier(
+            Exchange::new(|_| 0),
+            &format!("Webhook({})", self.url),
+            move |_capability, info| {
+                let activator = scope.activator_for(&info.address[..]);
+
+                let mut pending: Vec<ResultDiff> = Vec::new();
+                let mut changes: HashMap<Vec<Value>, isize> = HashMap::new();
+                let mut last_sent: Option<Instant> = None;
+                let mut buffer = Vec::new();
+
+                move |input, _output: &mut OutputHandle<_, (), _>| {
+                    input.for_each(|_time, data| {
+                        data.swap(&mut buffer);
+                        pending.extend(buffer.drain(..));
+                    });
+
+                    // Only changes at complete times are reported,
+                    // otherwise we might report tuples that are
+                    // retracted again at the same time.
+                    let frontier = input.frontier();
+                    let mut incomplete = Vec::new();
+                    for (tuple, time, diff) in pending.drain(..) {
+                        if frontier.less_equal(&time) {
+                            incomplete.push((tuple, time, diff));
+                        } else {
+                            *changes.entry(tuple).or_insert(0) += diff;
+                        }
+                    }
+                    pending = incomplete;
+
+                    changes.retain(|_tuple, diff| *diff != 0);
+
+                    if changes.is_empty() {
+                        return;
+                    }
+
+                    if let Some(last) = last_sent {
+                        let elapsed = last.elapsed();
+                        if elapsed < debounce {
+                            // Make sure we get around to sending these.
+                            activator.activate_after(debounce - elapsed);
+                            return;
+                        }
+                    }
+
+                    let mut added = Vec::new();
+                    let mut retracted = Vec::new();
+                    for (tuple, diff) in changes.drain() {
+                        if diff > 0 {
+                            added.push(tuple);
+                        } else if notify_retractions {
+                            retracted.push(tuple);
+                        }
+                    }
+
+                    if added.is_empty() && retracted.is_empty() {
+                        return;
+                    }
+
+                    let payload = serde_json::json!({
+                        "df.alert/name": name,
+                        "df.alert/added": added,
+                        "df.alert/retracted": retracted,
+                    });
+
+                    // Requests are sent by the shared HTTP worker, in
+                    // order not to block the worker.
+                    if let Err(error) = send(&address, &host, &path, payload.to_string()) {
+                        error!("[WEBHOOK] failed to notify {}: {}", host, error.message);
+                    }
+
+                    last_sent = Some(Instant::now());
+                }
+            },
+        );
+
+        Ok(())
+    }
+}
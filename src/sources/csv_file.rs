@@ -2,116 +2,184 @@
 
 extern crate timely;
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
-
-use timely::dataflow::operators::generic;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
 use timely::dataflow::{Scope, Stream};
 
-use crate::sources::Sourceable;
-use crate::{Eid, Value};
+use crate::keys::KeyEids;
+use crate::sources::tail::{read_lines, Tail};
+use crate::sources::{Sample, Sourceable};
+use crate::{Eid, Error, Time, Value};
 
 /// A local filesystem data source.
+///
+/// When following the file, lines appended after its initial contents
+/// have been read are introduced at subsequent epochs.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct CsvFile {
-    /// Path to a file on each workers local filesystem.
+    /// Path to a file on the first worker's local filesystem.
     pub path: String,
     /// Separator to use.
     pub separator: char,
     /// Specifies the column offsets and their value types, that
     /// should be introduced.
     pub schema: Vec<(usize, Value)>,
+    /// Keep watching the file for appended lines, instead of
+    /// finishing once its initial contents have been read.
+    #[serde(default)]
+    pub follow: bool,
+    /// Treat the first column as an external key, from which entity
+    /// ids are derived (see `keys::key_eid`), rather than as an
+    /// entity id.
+    #[serde(default)]
+    pub hash_eids: bool,
 }
 
-impl Sourceable for CsvFile {
-    fn source<G: Scope<Timestamp = u64>>(
-        &self,
-        scope: &G,
-        _names: Vec<String>,
-    ) -> Stream<G, (usize, ((Value, Value), u64, isize))> {
-        let filename = self.path.clone();
+/// Parses a single column according to the type of value it is
+/// expected to hold.
+fn parse_column(column: &str, type_hint: &Value) -> Result<Value, String> {
+    let column = column.trim().trim_matches('"');
+
+    match type_hint {
+        Value::String(_) => Ok(Value::String(column.to_string())),
+        Value::Number(_) => column
+            .parse::<i64>()
+            .map(Value::Number)
+            .map_err(|_| format!("{} is not a number", column)),
+        Value::Eid(_) => column
+            .parse::<Eid>()
+            .map(Value::Eid)
+            .map_err(|_| format!("{} is not a eid", column)),
+        _ => Err("Only String, Number, and Eid are supported at the moment.".to_string()),
+    }
+}
 
-        generic::operator::source(scope, &format!("File({})", filename), |capability, info| {
-            let activator = scope.activator_for(&info.address[..]);
+impl CsvFile {
+    /// Reads up to `limit` lines from the start of the file (see
+    /// `Source::sample`). Names correspond to schema entries by
+    /// position.
+    pub fn sample(&self, names: &[String], limit: usize) -> Result<Sample, Error> {
+        let mut tail = Tail::open(&self.path, false).map_err(|err| Error {
+            category: "df.error.category/fault",
+            message: err.to_string(),
+        })?;
+
+        let mut sample = Sample::new(names);
+
+        while sample.records < limit {
+            let line = match tail.next_line() {
+                None => break,
+                Some(line) => line,
+            };
+
+            if line.is_empty() {
+                continue;
+            }
 
-            let mut cap = Some(capability);
+            let columns: Vec<&str> = line.split(self.separator).collect();
 
-            let worker_index = scope.index();
-            let num_workers = scope.peers();
+            if !self.hash_eids {
+                if let Err(err) = parse_column(columns[0], &Value::Eid(0)) {
+                    sample.error(err);
+                }
+            }
 
-            let path = Path::new(&filename);
-            let file = File::open(&path).unwrap();
-            let reader = BufReader::new(file);
-            let mut iterator = reader.lines().peekable();
+            for (name, (offset, type_hint)) in names.iter().zip(self.schema.iter()) {
+                match columns.get(*offset) {
+                    None => sample.error(format!("Column {} is missing.", offset)),
+                    Some(column) => match parse_column(column, type_hint) {
+                        Err(err) => sample.error(err),
+                        Ok(v) => sample.value(name, &v),
+                    },
+                }
+            }
 
-            let mut num_datums_read = 0;
-            let mut datum_index = 0;
+            sample.records += 1;
+        }
 
-            // @TODO this is annoying
-            let separator = self.separator;
-            let schema = self.schema.clone();
+        Ok(sample)
+    }
+}
 
-            move |output| {
-                if iterator.peek().is_some() {
-                    let mut session = output.session(cap.as_ref().unwrap());
+impl Sourceable for CsvFile {
+    fn source<G: Scope<Timestamp = Time>>(
+        &self,
+        scope: &G,
+        _names: Vec<String>,
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error> {
+        let filename = self.path.clone();
 
-                    for readline in iterator.by_ref().take(255) {
-                        let line: String = readline.expect("read error");
+        let tail = Tail::open(&filename, self.follow).map_err(|err| Error {
+            category: "df.error.category/not-found",
+            message: format!("Failed to open {}: {}", filename, err),
+        })?;
+
+        // @TODO this is annoying
+        let separator = self.separator;
+        let schema = self.schema.clone();
+        let hash_eids = self.hash_eids;
+        let mut eids = KeyEids::new();
+
+        let datoms = read_lines(scope, &format!("File({})", filename), tail).unary(
+            Pipeline,
+            &format!("ParseCsv({})", filename),
+            move |_capability, _info| {
+                let mut buffer = Vec::new();
+
+                move |input, output| {
+                    input.for_each(|cap, data| {
+                        data.swap(&mut buffer);
+
+                        let time = *cap.time();
+                        let mut session = output.session(&cap);
+
+                        for (index, line) in buffer.drain(..) {
+                            if line.is_empty() {
+                                continue;
+                            }
 
-                        if (datum_index % num_workers == worker_index) && !line.is_empty() {
                             let columns: Vec<&str> = line.split(separator).collect();
 
-                            let eid = Value::Eid(
-                                columns[0]
-                                    .trim()
-                                    .trim_matches('"')
-                                    .parse::<Eid>()
-                                    .expect("not a eid"),
-                            );
-
-                            for (name_idx, (offset, type_hint)) in schema.iter().enumerate() {
-                                let v = match type_hint {
-                                    Value::String(_) => Value::String(
-                                        columns[*offset].trim().trim_matches('"').to_string(),
-                                    ),
-                                    Value::Number(_) => Value::Number(
-                                        columns[*offset]
-                                            .trim()
-                                            .trim_matches('"')
-                                            .parse::<i64>()
-                                            .expect("not a number"),
-                                    ),
-                                    Value::Eid(_) => Value::Eid(
-                                        columns[*offset]
-                                            .trim()
-                                            .trim_matches('"')
-                                            .parse::<Eid>()
-                                            .expect("not a eid"),
-                                    ),
-                                    _ => panic!(
-                                        "Only String, Number, and Eid are supported at the moment."
-                                    ),
-                                };
-
-                                session.give((name_idx, ((eid.clone(), v), 0, 1)));
+                            let key = columns[0].trim().trim_matches('"');
+                            let eid = if hash_eids {
+                                Ok(Value::Eid(eids.eid(key)))
+                            } else {
+                                parse_column(key, &Value::Eid(0))
+                            };
+
+                            // Malformed lines are skipped as a whole.
+                            let values = eid.and_then(|eid| {
+                                schema
+                                    .iter()
+                                    .map(|(offset, type_hint)| match columns.get(*offset) {
+                                        None => Err(format!("column {} is missing", offset)),
+                                        Some(column) => parse_column(column, type_hint),
+                                    })
+                                    .collect::<Result<Vec<Value>, String>>()
+                                    .map(|values| (eid, values))
+                            });
+
+                            match values {
+                                Err(err) => {
+                                    warn!(
+                                        "[CSV] skipping line {} of {}: {}",
+                                        index + 1,
+                                        filename,
+                                        err
+                                    )
+                                }
+                                Ok((eid, values)) => {
+                                    for (name_idx, v) in values.into_iter().enumerate() {
+                                        session.give((name_idx, ((eid.clone(), v), time, 1)));
+                                    }
+                                }
                             }
-
-                            num_datums_read += 1;
                         }
-
-                        datum_index += 1;
-                    }
-
-                    activator.activate();
-                } else {
-                    println!(
-                        "[WORKER {}] read {} out of {} datums",
-                        worker_index, num_datums_read, datum_index
-                    );
-                    cap = None;
+                    });
                 }
-            }
-        })
+            },
+        );
+
+        Ok(datoms)
     }
 }
@@ -0,0 +1,170 @@
+//! Operator and utilities to replay Datomic or Datascript
+//! transaction logs.
+
+extern crate serde_json;
+extern crate timely;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use timely::dataflow::operators::generic;
+use timely::dataflow::{Scope, Stream};
+
+use crate::sources::{epoch_time, Sourceable};
+use crate::{Eid, Error, Time, Value};
+
+/// A transaction log exported from Datomic (e.g. via `tx-range`) or
+/// Datascript, containing one transaction per line. Each transaction
+/// is either a json array of datoms, or an object holding them under
+/// `data`. Datoms are arrays of the form `[e a v tx added]`, where
+/// `tx` is ignored and `added` defaults to true. Attributes are given
+/// as keywords, with or without the leading colon (or in their
+/// transit form, `~:name`), or by their numeric id, which is
+/// resolved via `idents`.
+///
+/// Transaction boundaries are preserved, the n-th transaction of the
+/// log being introduced at the n-th epoch.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct DatomicLog {
+    /// Path to a file on each workers local filesystem.
+    pub path: String,
+    /// Reference attributes, whose numeric values are entity ids.
+    #[serde(default)]
+    pub refs: Vec<String>,
+    /// Attribute names by the numeric ids used for them in the log.
+    #[serde(default)]
+    pub idents: BTreeMap<u64, String>,
+}
+
+/// Strips the prefixes an attribute keyword might carry, s.t. all of
+/// its forms can be compared.
+fn keyword(name: &str) -> &str {
+    name.trim_start_matches("~:").trim_start_matches(':')
+}
+
+/// Converts a single json datom into an attribute offset, an (e, v)
+/// pair, and a diff. Datoms of attributes not asked for are skipped.
+fn parse_datom(
+    datom: &serde_json::Value,
+    names: &[String],
+    refs: &[String],
+    idents: &BTreeMap<u64, String>,
+) -> Option<(usize, (Value, Value), isize)> {
+    let fields = datom.as_array()?;
+
+    let e = fields.get(0)?.as_u64()?;
+    let a = match *fields.get(1)? {
+        serde_json::Value::String(ref a) => keyword(a),
+        serde_json::Value::Number(ref id) => keyword(idents.get(&id.as_u64()?)?),
+        _ => return None,
+    };
+    let name_idx = names.iter().position(|name| keyword(name) == a)?;
+
+    let v = match *fields.get(2)? {
+        serde_json::Value::String(ref s) => Value::String(s.to_string()),
+        serde_json::Value::Bool(b) => Value::Bool(b),
+        serde_json::Value::Number(ref num) => {
+            if refs.iter().any(|r| keyword(r) == a) {
+                Value::Eid(num.as_u64()? as Eid)
+            } else {
+                Value::Number(num.as_i64()?)
+            }
+        }
+        _ => return None,
+    };
+
+    let diff = match fields.get(4).and_then(|added| added.as_bool()) {
+        Some(false) => -1,
+        _ => 1,
+    };
+
+    Some((name_idx, (Value::Eid(e as Eid), v), diff))
+}
+
+impl Sourceable for DatomicLog {
+    fn source<G: Scope<Timestamp = Time>>(
+        &self,
+        scope: &G,
+        names: Vec<String>,
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error> {
+        let filename = self.path.clone();
+        let refs = self.refs.clone();
+        let idents = self.idents.clone();
+
+        let file = File::open(Path::new(&filename)).map_err(|err| Error {
+            category: "df.error.category/not-found",
+            message: format!("Failed to open {}: {}", filename, err),
+        })?;
+
+        Ok(generic::operator::source(
+            scope,
+            &format!("DatomicLog({})", filename),
+            move |capability, info| {
+                let activator = scope.activator_for(&info.address[..]);
+
+                let mut cap = Some(capability);
+
+                let worker_index = scope.index();
+                let num_workers = scope.peers();
+
+                let reader = BufReader::new(file);
+                let mut iterator = reader.lines().peekable();
+
+                // Every worker reads the entire log, in order to
+                // advance its capability at each transaction
+                // boundary, but only introduces its share of
+                // transactions.
+                let mut epoch: u64 = 0;
+
+                move |output| {
+                    if let Some(ref mut cap) = cap {
+                        for readline in iterator.by_ref().take(256) {
+                            let line = readline.expect("read error");
+
+                            if line.trim().is_empty() {
+                                continue;
+                            }
+
+                            if epoch as usize % num_workers == worker_index {
+                                match serde_json::from_str::<serde_json::Value>(&line) {
+                                    Err(err) => {
+                                        warn!("[DATOMIC] skipping malformed transaction: {}", err)
+                                    }
+                                    Ok(tx) => {
+                                        let datoms = match tx.get("data") {
+                                            Some(data) => data.as_array(),
+                                            None => tx.as_array(),
+                                        };
+
+                                        let mut session = output.session(cap);
+                                        let time = epoch_time(epoch);
+
+                                        for datom in datoms.into_iter().flatten() {
+                                            match parse_datom(datom, &names, &refs, &idents) {
+                                                None => {}
+                                                Some((name_idx, tuple, diff)) => {
+                                                    session.give((name_idx, (tuple, time, diff)));
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+
+                            epoch += 1;
+                            cap.downgrade(&epoch_time(epoch));
+                        }
+                    }
+
+                    if iterator.peek().is_some() {
+                        activator.activate();
+                    } else {
+                        cap = None;
+                    }
+                }
+            },
+        ))
+    }
+}
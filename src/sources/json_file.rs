@@ -4,112 +4,225 @@
 extern crate serde_json;
 extern crate timely;
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::collections::HashMap;
 
-use timely::dataflow::operators::generic;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::Operator;
 use timely::dataflow::{Scope, Stream};
 
 // use sources::json_file::flate2::read::GzDecoder;
 
-use crate::sources::Sourceable;
-use crate::{Eid, Value};
+use num_rational::Rational32;
 
-/// A local filesystem data source containing JSON objects.
+use crate::keys::KeyEids;
+use crate::sources::tail::{read_lines, Tail};
+use crate::sources::{Sample, Sourceable};
+use crate::{Eid, Error, Time, Value};
+
+/// A local filesystem data source containing JSON objects, one per
+/// line. Each object becomes an entity, see `flatten` for how its
+/// fields map to attributes.
+///
+/// When following the file, lines appended after its initial contents
+/// have been read are introduced at subsequent epochs.
 #[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub struct JsonFile {
-    /// Path to a file on each workers local filesystem.
+    /// Path to a file on the first worker's local filesystem.
     pub path: String,
+    /// Keep watching the file for appended lines, instead of
+    /// finishing once its initial contents have been read.
+    #[serde(default)]
+    pub follow: bool,
+    /// Top-level field holding an external key for each object, from
+    /// which its entity id is derived (see `keys::key_eid`). Objects
+    /// are numbered by their position in the file otherwise.
+    #[serde(default)]
+    pub eid_key: Option<String>,
+}
+
+/// Flattens a json value into (attribute, value) pairs. Nested
+/// objects are namespaced by their key (e.g. `address/street`),
+/// arrays produce one pair per element, and nulls are skipped.
+/// Numbers are read as integers where possible, and approximated by
+/// rationals otherwise.
+fn flatten(name: &str, json: &serde_json::Value, pairs: &mut Vec<(String, Value)>) {
+    match *json {
+        serde_json::Value::Null => {}
+        serde_json::Value::Bool(b) => pairs.push((name.to_string(), Value::Bool(b))),
+        serde_json::Value::String(ref s) => {
+            pairs.push((name.to_string(), Value::String(s.to_string())))
+        }
+        serde_json::Value::Number(ref num) => {
+            let v = match num.as_i64() {
+                Some(num) => Some(Value::Number(num)),
+                None => num
+                    .as_f64()
+                    .and_then(Rational32::approximate_float)
+                    .map(Value::Rational32),
+            };
+
+            match v {
+                None => warn!("[JSON] can't represent {} of {}", num, name),
+                Some(v) => pairs.push((name.to_string(), v)),
+            }
+        }
+        serde_json::Value::Array(ref elements) => {
+            for element in elements.iter() {
+                flatten(name, element, pairs);
+            }
+        }
+        serde_json::Value::Object(ref obj) => {
+            for (k, v) in obj.iter() {
+                if name.is_empty() {
+                    flatten(k, v, pairs);
+                } else {
+                    flatten(&format!("{}/{}", name, k), v, pairs);
+                }
+            }
+        }
+    }
+}
+
+impl JsonFile {
+    /// Reads up to `limit` objects from the start of the file (see
+    /// `Source::sample`). Every field is reported, s.t. misspelled
+    /// attribute names stand out.
+    pub fn sample(&self, names: &[String], limit: usize) -> Result<Sample, Error> {
+        let mut tail = Tail::open(&self.path, false).map_err(|err| Error {
+            category: "df.error.category/fault",
+            message: err.to_string(),
+        })?;
+
+        let mut sample = Sample::new(names);
+
+        while sample.records < limit {
+            let line = match tail.next_line() {
+                None => break,
+                Some(line) => line,
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<serde_json::Value>(&line) {
+                Err(err) => sample.error(err.to_string()),
+                Ok(obj) => {
+                    let has_key = match self.eid_key {
+                        None => true,
+                        Some(ref eid_key) => match obj.get(eid_key) {
+                            None | Some(serde_json::Value::Null) => false,
+                            Some(_) => true,
+                        },
+                    };
+
+                    if has_key {
+                        let mut pairs = Vec::new();
+                        flatten("", &obj, &mut pairs);
+
+                        for (k, v) in pairs.iter() {
+                            sample.value(k, v);
+                        }
+                    } else {
+                        sample.error(format!(
+                            "Object without {}.",
+                            self.eid_key.as_ref().unwrap()
+                        ));
+                    }
+                }
+            }
+
+            sample.records += 1;
+        }
+
+        Ok(sample)
+    }
 }
 
 impl Sourceable for JsonFile {
-    fn source<G: Scope>(
+    fn source<G: Scope<Timestamp = Time>>(
         &self,
         scope: &G,
         names: Vec<String>,
-    ) -> Stream<G, (usize, ((Value, Value), u64, isize))> {
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error> {
         let filename = self.path.clone();
+        let eid_key = self.eid_key.clone();
 
-        generic::operator::source(
-            scope,
-            &format!("File({})", filename),
-            move |capability, info| {
-                let activator = scope.activator_for(&info.address[..]);
-
-                let mut cap = Some(capability);
-
-                let worker_index = scope.index();
-                let num_workers = scope.peers();
-
-                let path = Path::new(&filename);
-                let file = File::open(&path).unwrap();
-                // let reader = BufReader::new(GzDecoder::new(file));
-                let reader = BufReader::new(file);
-                let mut iterator = reader.lines().peekable();
-
-                let mut num_objects_read = 0;
-                let mut object_index = 0;
-
-                move |output| {
-                    if iterator.peek().is_some() {
-                        let mut session = output.session(cap.as_ref().unwrap());
-
-                        for readline in iterator.by_ref().take(256 - 1) {
-                            let line = readline.expect("read error");
-
-                            if (object_index % num_workers == worker_index) && !line.is_empty() {
-                                // @TODO parse only the names we are interested in
-                                // @TODO run with Value = serde_json::Value
-
-                                let obj: serde_json::Value = serde_json::from_str(&line).unwrap();
-                                let obj_map = obj.as_object().unwrap();
-
-                                // In the common case we assume that all objects share
-                                // roughly the same number of attributes, a (potentially small)
-                                // subset of which is actually requested downstream.
-                                //
-                                // otherwise:
-                                // for (k, v) in obj.as_object().unwrap() {
-
-                                for (name_idx, k) in names.iter().enumerate() {
-                                    match obj_map.get(k) {
-                                        None => {}
-                                        Some(json_value) => {
-                                            let v = match *json_value {
-                                            serde_json::Value::String(ref s) => Value::String(s.to_string()),
-                                            serde_json::Value::Number(ref num) => {
-                                                match num.as_i64() {
-                                                    None => panic!("only i64 supported at the moment"),
-                                                    Some(num) => Value::Number(num),
-                                                }
-                                            },
-                                            serde_json::Value::Bool(ref b) => Value::Bool(*b),
-                                            _ => panic!("only strings, booleans, and i64 types supported at the moment"),
-                                        };
-
-                                            session.give((
-                                                name_idx,
-                                                ((Value::Eid(object_index as Eid), v), 0, 1),
-                                            ));
-                                        }
-                                    }
-                                }
+        let tail = Tail::open(&filename, self.follow).map_err(|err| Error {
+            category: "df.error.category/not-found",
+            message: format!("Failed to open {}: {}", filename, err),
+        })?;
 
-                                num_objects_read += 1;
-                            }
+        let name_indices: HashMap<String, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(name_idx, name)| (name.clone(), name_idx))
+            .collect();
 
-                            object_index += 1;
-                        }
+        let mut eids = KeyEids::new();
 
-                        // println!("[WORKER {}] read {} out of {} objects", worker_index, num_objects_read, object_index);
+        let datoms = read_lines(scope, &format!("File({})", filename), tail).unary(
+            Pipeline,
+            &format!("ParseJson({})", filename),
+            move |_capability, _info| {
+                let mut buffer = Vec::new();
 
-                        activator.activate();
-                    } else {
-                        cap = None;
-                    }
+                move |input, output| {
+                    input.for_each(|cap, data| {
+                        data.swap(&mut buffer);
+
+                        let time = *cap.time();
+                        let mut session = output.session(&cap);
+
+                        for (object_index, line) in buffer.drain(..) {
+                            if line.is_empty() {
+                                continue;
+                            }
+
+                            // @TODO parse only the names we are interested in
+                            // @TODO run with Value = serde_json::Value
+
+                            let obj: serde_json::Value = match serde_json::from_str(&line) {
+                                Ok(obj) => obj,
+                                Err(err) => {
+                                    warn!(
+                                        "[JSON] skipping line {} of {}: {}",
+                                        object_index + 1,
+                                        filename,
+                                        err
+                                    );
+                                    continue;
+                                }
+                            };
+
+                            let eid = match eid_key {
+                                None => Some(object_index as Eid),
+                                Some(ref eid_key) => match obj.get(eid_key) {
+                                    None | Some(serde_json::Value::Null) => {
+                                        warn!("[JSON] skipping object without {}", eid_key);
+                                        None
+                                    }
+                                    Some(serde_json::Value::String(key)) => Some(eids.eid(key)),
+                                    Some(key) => Some(eids.eid(&key.to_string())),
+                                },
+                            };
+
+                            let mut pairs = Vec::new();
+                            flatten("", &obj, &mut pairs);
+
+                            if let Some(eid) = eid {
+                                for (k, v) in pairs.drain(..) {
+                                    if let Some(name_idx) = name_indices.get(&k) {
+                                        session.give((*name_idx, ((Value::Eid(eid), v), time, 1)));
+                                    }
+                                }
+                            }
+                        }
+                    });
                 }
             },
-        )
+        );
+
+        Ok(datoms)
     }
 }
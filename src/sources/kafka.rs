@@ -0,0 +1,275 @@
+//! Operator and utilities to source data from Kafka topics containing
+//! json objects.
+
+extern crate serde_json;
+extern crate timely;
+
+use std::collections::HashMap;
+use std::time::Duration;
+#[cfg(feature = "real-time")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use timely::dataflow::operators::generic;
+use timely::dataflow::{Scope, Stream};
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::{Message, Offset};
+
+use crate::sources::Sourceable;
+use crate::{Eid, Error, Time, Value};
+
+/// Delay before polling again, once no messages are available.
+const POLL_DELAY: Duration = Duration::from_millis(10);
+
+/// Policy for where to start consuming a partition, if the consumer
+/// group has no committed offset for it yet.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub enum OffsetReset {
+    /// Start at the oldest available offset.
+    Earliest,
+    /// Start at the next offset written after subscribing.
+    Latest,
+}
+
+/// Determines the time at which a message is introduced, as well as
+/// the earliest time at which the next message on the same partition
+/// may be introduced. Logical timestamps follow partition offsets.
+#[cfg(not(feature = "real-time"))]
+fn message_times<M: Message>(message: &M) -> (Time, Time) {
+    let offset = message.offset() as Time;
+    (offset, offset + 1)
+}
+
+/// Determines the time at which a message is introduced, as well as
+/// the earliest time at which the next message on the same partition
+/// may be introduced. Real-time timestamps follow the event time
+/// recorded by the producer.
+#[cfg(feature = "real-time")]
+fn message_times<M: Message>(message: &M) -> (Time, Time) {
+    let millis = message.timestamp().to_millis().unwrap_or(0);
+    let time = Duration::from_millis(millis as u64);
+    (time, time)
+}
+
+/// Determines the earliest time at which the next message may be
+/// introduced on a partition that has been read up to the specified
+/// offset, without any messages waiting.
+#[cfg(not(feature = "real-time"))]
+fn idle_time(offset: i64) -> Time {
+    offset as Time
+}
+
+/// Determines the earliest time at which the next message may be
+/// introduced on a partition that has been read up to the specified
+/// offset, without any messages waiting. Idle partitions follow the
+/// wall clock.
+#[cfg(feature = "real-time")]
+fn idle_time(_offset: i64) -> Time {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// A Kafka topic containing one json object per message.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct KafkaSource {
+    /// Comma-separated list of bootstrap brokers.
+    pub brokers: String,
+    /// Topic to consume.
+    pub topic: String,
+    /// Consumer group to commit offsets for.
+    pub group_id: String,
+    /// Where to start reading partitions without committed offsets.
+    pub offset_reset: OffsetReset,
+}
+
+/// Entity id of the object held by a message. Offsets are only
+/// unique within a partition, so the partition occupies the bits
+/// above the 48 lowest ones.
+fn message_eid<M: Message>(message: &M) -> Eid {
+    ((message.partition() as Eid) << 48) | (message.offset() as Eid & ((1 << 48) - 1))
+}
+
+fn parse_value(json_value: &serde_json::Value) -> Option<Value> {
+    match *json_value {
+        serde_json::Value::String(ref s) => Some(Value::String(s.to_string())),
+        serde_json::Value::Number(ref num) => num.as_i64().map(Value::Number),
+        serde_json::Value::Bool(ref b) => Some(Value::Bool(*b)),
+        _ => None,
+    }
+}
+
+impl Sourceable for KafkaSource {
+    fn source<G: Scope<Timestamp = Time>>(
+        &self,
+        scope: &G,
+        names: Vec<String>,
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error> {
+        let offset_reset = match self.offset_reset {
+            OffsetReset::Earliest => "earliest",
+            OffsetReset::Latest => "latest",
+        };
+
+        // Only the first worker consumes the topic, s.t. the
+        // frontier of the source never waits on workers without any
+        // partitions assigned. Data is spread across workers when it
+        // is arranged.
+        let consumer: Option<BaseConsumer> = if scope.index() == 0 {
+            // Offsets are committed once messages have been
+            // introduced, rather than once they have been polled.
+            let consumer: BaseConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &self.brokers)
+                .set("group.id", &self.group_id)
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", offset_reset)
+                .create()
+                .map_err(|err| Error {
+                    category: "df.error.category/fault",
+                    message: format!("Failed to create kafka consumer: {}", err),
+                })?;
+
+            consumer.subscribe(&[&self.topic]).map_err(|err| Error {
+                category: "df.error.category/fault",
+                message: format!("Failed to subscribe to topic {}: {}", self.topic, err),
+            })?;
+
+            Some(consumer)
+        } else {
+            None
+        };
+
+        Ok(generic::operator::source(
+            scope,
+            &format!("Kafka({})", self.topic),
+            move |capability, info| {
+                let activator = scope.activator_for(&info.address[..]);
+
+                let (mut cap, consumer) = match consumer {
+                    None => (None, None),
+                    Some(consumer) => (Some(capability), Some(consumer)),
+                };
+
+                // The earliest time expected next on each partition
+                // assigned to the consumer.
+                let mut next_times: HashMap<i32, Time> = HashMap::new();
+
+                move |output| {
+                    let consumer = match consumer {
+                        None => return,
+                        Some(ref consumer) => consumer,
+                    };
+
+                    let mut drained = false;
+                    let mut introduced = false;
+
+                    if let Some(ref mut cap) = cap {
+                        let cap_time = *cap.time();
+                        let mut session = output.session(cap);
+
+                        for _ in 0..256 {
+                            match consumer.poll(Duration::from_millis(0)) {
+                                None => {
+                                    drained = true;
+                                    break;
+                                }
+                                Some(Err(err)) => {
+                                    error!("[KAFKA] error while polling: {}", err);
+                                    drained = true;
+                                    break;
+                                }
+                                Some(Ok(message)) => {
+                                    let (message_time, next_time) = message_times(&message);
+                                    next_times.insert(message.partition(), next_time);
+                                    introduced = true;
+
+                                    let payload = match message.payload_view::<str>() {
+                                        Some(Ok(payload)) => payload,
+                                        _ => {
+                                            warn!("[KAFKA] skipping non-utf8 message");
+                                            continue;
+                                        }
+                                    };
+
+                                    let obj: serde_json::Value = match serde_json::from_str(payload)
+                                    {
+                                        Ok(obj) => obj,
+                                        Err(err) => {
+                                            warn!("[KAFKA] skipping malformed message: {}", err);
+                                            continue;
+                                        }
+                                    };
+
+                                    if let Some(obj_map) = obj.as_object() {
+                                        // Partitions progress independently, so we
+                                        // can't emit at times the capability has
+                                        // already moved beyond.
+                                        let time = std::cmp::max(message_time, cap_time);
+                                        let eid = Value::Eid(message_eid(&message));
+
+                                        for (name_idx, k) in names.iter().enumerate() {
+                                            if let Some(v) = obj_map.get(k).and_then(parse_value) {
+                                                session
+                                                    .give((name_idx, ((eid.clone(), v), time, 1)));
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    if introduced {
+                        if let Err(err) = consumer.commit_consumer_state(CommitMode::Async) {
+                            error!("[KAFKA] failed to commit offsets: {}", err);
+                        }
+                    }
+
+                    // Partitions without messages waiting won't see
+                    // any before their current position, and
+                    // partitions no longer assigned none at all.
+                    if drained {
+                        if let Ok(positions) = consumer.position() {
+                            let assigned: HashMap<i32, Offset> = positions
+                                .elements()
+                                .iter()
+                                .map(|elem| (elem.partition(), elem.offset()))
+                                .collect();
+
+                            next_times.retain(|partition, _| assigned.contains_key(partition));
+
+                            for (partition, offset) in assigned {
+                                if let Offset::Offset(offset) = offset {
+                                    let time = idle_time(offset);
+                                    let next_time = next_times.entry(partition).or_insert(time);
+
+                                    if *next_time < time {
+                                        *next_time = time;
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    // We can only promise no more data at times before
+                    // the slowest partition's next time.
+                    if let Some(ref mut cap) = cap {
+                        if let Some(min_time) = next_times.values().min() {
+                            if *cap.time() < *min_time {
+                                cap.downgrade(min_time);
+                            }
+                        }
+                    }
+
+                    // Polling again right away would spin while the
+                    // topic is idle.
+                    if drained {
+                        activator.activate_after(POLL_DELAY);
+                    } else {
+                        activator.activate();
+                    }
+                }
+            },
+        ))
+    }
+}
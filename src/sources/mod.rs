@@ -3,24 +3,49 @@
 extern crate differential_dataflow;
 extern crate timely;
 
+use std::collections::BTreeMap;
+
 use timely::dataflow::{Scope, Stream};
 
-use crate::Value;
+use crate::{Error, Time, Value};
 
 pub mod csv_file;
 pub use self::csv_file::CsvFile;
+pub mod datomic_log;
+pub use self::datomic_log::DatomicLog;
 pub mod json_file;
 pub use self::json_file::JsonFile;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "kafka")]
+pub use self::kafka::{KafkaSource, OffsetReset};
+pub(crate) mod tail;
+
+/// Maps the n-th epoch of a source onto the time at which its data
+/// is introduced. Logical timestamps simply count epochs.
+#[cfg(not(feature = "real-time"))]
+pub(crate) fn epoch_time(epoch: u64) -> Time {
+    epoch
+}
+
+/// Maps the n-th epoch of a source onto the time at which its data
+/// is introduced. Real-time timestamps are spaced a millisecond
+/// apart, as only the order of epochs is known to sources such as
+/// logs or files.
+#[cfg(feature = "real-time")]
+pub(crate) fn epoch_time(epoch: u64) -> Time {
+    std::time::Duration::from_millis(epoch)
+}
 
 /// An external data source that can provide Datoms.
 pub trait Sourceable {
     /// Creates a timely operator reading from the source and
-    /// producing inputs.
-    fn source<G: Scope<Timestamp = u64>>(
+    /// producing inputs. Fails if the source can't be opened.
+    fn source<G: Scope<Timestamp = Time>>(
         &self,
         scope: &G,
         names: Vec<String>,
-    ) -> Stream<G, (usize, ((Value, Value), u64, isize))>;
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error>;
 }
 
 /// Supported external data sources.
@@ -28,19 +53,100 @@ pub trait Sourceable {
 pub enum Source {
     /// CSV files
     CsvFile(CsvFile),
+    /// Datomic or Datascript transaction logs
+    DatomicLog(DatomicLog),
     /// Files containing json objects
     JsonFile(JsonFile),
+    /// Kafka topics containing json objects
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaSource),
+}
+
+impl Source {
+    /// Returns the name of this kind of source.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Source::CsvFile(_) => "CsvFile",
+            Source::DatomicLog(_) => "DatomicLog",
+            Source::JsonFile(_) => "JsonFile",
+            #[cfg(feature = "kafka")]
+            Source::Kafka(_) => "Kafka",
+        }
+    }
+}
+
+impl Source {
+    /// Reads up to `limit` records from the source, without
+    /// introducing them anywhere, and summarizes what would have been
+    /// read for the specified attribute names. Only supported by file
+    /// sources for now.
+    pub fn sample(&self, names: &[String], limit: usize) -> Result<Sample, Error> {
+        match *self {
+            Source::CsvFile(ref source) => source.sample(names, limit),
+            Source::JsonFile(ref source) => source.sample(names, limit),
+            _ => Err(Error {
+                category: "df.error.category/unsupported",
+                message: format!("{} sources can't be sampled.", self.kind()),
+            }),
+        }
+    }
+}
+
+/// Summary of a bounded sample read from a source.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Sample {
+    /// Number of records (e.g. lines) read.
+    pub records: usize,
+    /// Number of values read for each attribute, by the type of value
+    /// they were parsed as. Attributes asked for but not found in the
+    /// sample are reported without any values.
+    pub attributes: BTreeMap<String, BTreeMap<&'static str, usize>>,
+    /// Records that could not be parsed, by their offset within the
+    /// sample, along with the reason.
+    pub errors: Vec<(usize, String)>,
+}
+
+impl Sample {
+    /// Creates an empty sample for the specified attribute names.
+    pub fn new(names: &[String]) -> Self {
+        Sample {
+            records: 0,
+            attributes: names
+                .iter()
+                .map(|name| (name.clone(), BTreeMap::new()))
+                .collect(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records a value read for the named attribute.
+    pub fn value(&mut self, name: &str, value: &Value) {
+        *self
+            .attributes
+            .entry(name.to_string())
+            .or_insert_with(BTreeMap::new)
+            .entry(value.kind())
+            .or_insert(0) += 1;
+    }
+
+    /// Records a record that could not be parsed.
+    pub fn error(&mut self, message: String) {
+        self.errors.push((self.records, message));
+    }
 }
 
 impl Sourceable for Source {
-    fn source<G: Scope<Timestamp = u64>>(
+    fn source<G: Scope<Timestamp = Time>>(
         &self,
         scope: &G,
         names: Vec<String>,
-    ) -> Stream<G, (usize, ((Value, Value), u64, isize))> {
+    ) -> Result<Stream<G, (usize, ((Value, Value), Time, isize))>, Error> {
         match *self {
             Source::CsvFile(ref source) => source.source(scope, names),
+            Source::DatomicLog(ref source) => source.source(scope, names),
             Source::JsonFile(ref source) => source.source(scope, names),
+            #[cfg(feature = "kafka")]
+            Source::Kafka(ref source) => source.source(scope, names),
         }
     }
 }
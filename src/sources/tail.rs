@@ -0,0 +1,147 @@
+//! Utilities to read lines from local files that might still be
+//! growing.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+use timely::dataflow::operators::{generic, Exchange};
+use timely::dataflow::{Scope, Stream};
+
+use crate::sources::epoch_time;
+use crate::Time;
+
+/// Reads a file line by line. When following, lines appended after
+/// the end of the file has been reached are picked up as well (tail
+/// -f semantics), and a trailing line is only returned once it has
+/// been terminated. Lines are decoded only once complete, s.t. a
+/// multi-byte character that has only partly been appended yet does
+/// no harm.
+pub struct Tail {
+    reader: BufReader<File>,
+    partial: Vec<u8>,
+    follow: bool,
+}
+
+impl Tail {
+    /// Opens the file at the specified path.
+    pub fn open<P: AsRef<Path>>(path: P, follow: bool) -> std::io::Result<Self> {
+        Ok(Tail {
+            reader: BufReader::new(File::open(path)?),
+            partial: Vec::new(),
+            follow,
+        })
+    }
+
+    /// Returns the next complete line, or None if no further line
+    /// is available at the moment.
+    pub fn next_line(&mut self) -> Option<String> {
+        if let Err(err) = self.reader.read_until(b'\n', &mut self.partial) {
+            error!("[TAIL] failed to read: {}", err);
+            return None;
+        }
+
+        let terminated = self.partial.ends_with(b"\n");
+
+        if self.partial.is_empty() {
+            None
+        } else if terminated || !self.follow {
+            let mut line = std::mem::replace(&mut self.partial, Vec::new());
+
+            if terminated {
+                line.pop();
+                if line.ends_with(b"\r") {
+                    line.pop();
+                }
+            }
+
+            match String::from_utf8(line) {
+                Ok(line) => Some(line),
+                Err(err) => {
+                    warn!("[TAIL] replacing invalid utf-8 in line");
+                    Some(String::from_utf8_lossy(err.as_bytes()).into_owned())
+                }
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// Delay before looking for appended lines again, once a followed
+/// file has been read to its end.
+const FOLLOW_DELAY: Duration = Duration::from_millis(100);
+
+/// Creates an operator reading a file line by line, producing each
+/// line along with its index. The initial contents of the file are
+/// introduced at the first epoch, every subsequent batch of appended
+/// lines (when following) at a new one. Only the first worker reads
+/// the file, s.t. all workers agree on the epoch of each line, and
+/// lines are spread across workers by their index.
+pub(crate) fn read_lines<G: Scope<Timestamp = Time>>(
+    scope: &G,
+    name: &str,
+    mut tail: Tail,
+) -> Stream<G, (usize, String)> {
+    let name = name.to_string();
+
+    generic::operator::source(scope, &name.clone(), move |capability, info| {
+        let activator = scope.activator_for(&info.address[..]);
+
+        let mut cap = if scope.index() == 0 {
+            Some(capability)
+        } else {
+            None
+        };
+
+        let mut index = 0;
+        let mut epoch: u64 = 0;
+        let mut lines_at_epoch = 0;
+
+        move |output| {
+            let mut exhausted = false;
+
+            if let Some(ref mut cap) = cap {
+                let mut session = output.session(cap);
+
+                for _ in 0..256 {
+                    match tail.next_line() {
+                        None => {
+                            exhausted = true;
+                            break;
+                        }
+                        Some(line) => {
+                            session.give((index, line));
+                            index += 1;
+                            lines_at_epoch += 1;
+                        }
+                    }
+                }
+            } else {
+                return;
+            }
+
+            if !exhausted {
+                activator.activate();
+            } else if !tail.follow {
+                info!("[{}] read {} lines", name, index);
+                cap = None;
+            } else {
+                // Once all lines available so far have been read,
+                // their epoch is complete.
+                if epoch == 0 || lines_at_epoch > 0 {
+                    epoch += 1;
+                    lines_at_epoch = 0;
+
+                    if let Some(ref mut cap) = cap {
+                        cap.downgrade(&epoch_time(epoch));
+                    }
+                }
+
+                activator.activate_after(FOLLOW_DELAY);
+            }
+        }
+    })
+    .exchange(|(index, _line)| *index as u64)
+}
@@ -0,0 +1,944 @@
+//! A SQL frontend, for clients thinking in tables rather than in
+//! Datalog.
+//!
+//! Tables are attribute namespaces: every entity holding any of the
+//! attributes `t/...` is a row of table `t`, and its column `c` is
+//! the value of attribute `t/c`. The `id` column is the entity id
+//! itself. Names that aren't plain identifiers (e.g. `df.tx`) can be
+//! quoted with double quotes.
+//!
+//! Only a restricted subset of SQL is supported:
+//!
+//! - `SELECT cols FROM t [AS] a [, ...] [JOIN u [AS] b ON cond] ...
+//! [WHERE cond] [GROUP BY cols]`, where conditions are conjunctions
+//! of comparisons (`=`, `!=`, `<>`, `<`, `<=`, `>`, `>=`) between
+//! columns and literals (numbers, single-quoted strings, `TRUE`,
+//! `FALSE`), and selected columns may be aggregated with `COUNT`,
+//! `SUM`, `MIN`, `MAX`, `AVG`, `MEDIAN`, or `VARIANCE`. Rows lacking
+//! any of the columns referred to are skipped, as with an inner join
+//! per column.
+//! - `CREATE MATERIALIZED VIEW name AS SELECT ...`, registering and
+//! publishing a rule, unless the name is already taken.
+//! - `TAIL name`, expressing interest in a relation.
+//! - `DROP VIEW name`, unregistering a rule.
+//!
+//! A bare `SELECT` both registers its rule (named after its plan) and
+//! expresses interest in it. Results are delivered as for any other
+//! relation, i.e. as a stream of changes.
+
+use std::collections::HashMap;
+
+use crate::plan::{
+    content_id, Aggregate, AggregationFn, Filter, Function, MultiJoin, Predicate, Project,
+    Transform,
+};
+use crate::server::{ConflictPolicy, Interest, Register, Request};
+use crate::{Aid, Eid, Error, Plan, Rule, Value, Var};
+
+/// Compiles a single SELECT statement into a plan.
+pub fn parse_select(sql: &str) -> Result<Plan, Error> {
+    let mut parser = Parser::new(sql)?;
+    let select = parser.select()?;
+    parser.end()?;
+
+    select.lower()
+}
+
+/// Compiles a list of statements, separated by semicolons, into the
+/// requests implementing them.
+pub fn parse(sql: &str) -> Result<Vec<Request>, Error> {
+    let mut parser = Parser::new(sql)?;
+    let mut requests = Vec::new();
+
+    loop {
+        while parser.symbol(";") {}
+
+        if parser.peek().is_none() {
+            return Ok(requests);
+        }
+
+        requests.extend(parser.statement()?);
+
+        if parser.peek().is_some() {
+            parser.expect_symbol(";")?;
+        }
+    }
+}
+
+/// Replaces all `{"Sql": "..."}` requests (within batches as well) by
+/// the requests implementing the statements given.
+pub(crate) fn expand_requests(requests: &mut serde_json::Value) -> Result<(), Error> {
+    if let Some(requests) = requests.as_array_mut() {
+        let mut expanded = Vec::with_capacity(requests.len());
+
+        for mut request in requests.drain(..) {
+            if let Some(batch) = request.get_mut("Batch") {
+                expand_requests(batch)?;
+            }
+
+            let sql = request
+                .get("Sql")
+                .and_then(|sql| sql.as_str())
+                .map(str::to_string);
+
+            match sql {
+                None => expanded.push(request),
+                Some(sql) => {
+                    for request in parse(&sql)? {
+                        expanded.push(
+                            serde_json::to_value(request).expect("failed to serialize request"),
+                        );
+                    }
+                }
+            }
+        }
+
+        *requests = expanded;
+    }
+
+    Ok(())
+}
+
+/// Words that can't be used as unquoted names.
+const KEYWORDS: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "GROUP",
+    "BY",
+    "JOIN",
+    "INNER",
+    "ON",
+    "AND",
+    "AS",
+    "CREATE",
+    "MATERIALIZED",
+    "VIEW",
+    "TAIL",
+    "DROP",
+    "TRUE",
+    "FALSE",
+];
+
+/// Symbols, longest first s.t. they are matched greedily.
+const SYMBOLS: &[&str] = &[
+    "<=", ">=", "<>", "!=", "<", ">", "=", ",", ".", "(", ")", "*", ";",
+];
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    /// An unquoted identifier or keyword.
+    Word(String),
+    /// A double-quoted identifier.
+    Quoted(String),
+    /// A single-quoted string literal.
+    Text(String),
+    /// An integer literal.
+    Number(i64),
+    /// Punctuation and comparison operators.
+    Symbol(&'static str),
+}
+
+fn incorrect(message: String) -> Error {
+    Error {
+        category: "df.error.category/incorrect",
+        message,
+    }
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, Error> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    // Reads everything up to the closing quote, doubled quotes
+    // standing for the quote itself.
+    let quoted = |position: &mut usize, quote: char| -> Result<String, Error> {
+        let mut text = String::new();
+        *position += 1;
+
+        loop {
+            match chars.get(*position) {
+                None => return Err(incorrect(format!("Unterminated {} in SQL.", quote))),
+                Some(&c) if c == quote => {
+                    if chars.get(*position + 1) == Some(&quote) {
+                        text.push(quote);
+                        *position += 2;
+                    } else {
+                        *position += 1;
+                        return Ok(text);
+                    }
+                }
+                Some(&c) => {
+                    text.push(c);
+                    *position += 1;
+                }
+            }
+        }
+    };
+
+    while position < chars.len() {
+        let c = chars[position];
+        let rest: String = chars[position..].iter().take(2).collect();
+        let negative = c == '-' && chars.get(position + 1).map_or(false, char::is_ascii_digit);
+
+        if c.is_whitespace() {
+            position += 1;
+        } else if c.is_ascii_digit() || negative {
+            let start = position;
+            position += 1;
+            while chars.get(position).map_or(false, char::is_ascii_digit) {
+                position += 1;
+            }
+
+            let digits: String = chars[start..position].iter().collect();
+            let number = digits
+                .parse()
+                .map_err(|_| incorrect(format!("Number {} out of range.", digits)))?;
+
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = position;
+            while chars
+                .get(position)
+                .map_or(false, |c| c.is_alphanumeric() || *c == '_' || *c == '-')
+            {
+                position += 1;
+            }
+
+            tokens.push(Token::Word(chars[start..position].iter().collect()));
+        } else if c == '"' {
+            tokens.push(Token::Quoted(quoted(&mut position, '"')?));
+        } else if c == '\'' {
+            tokens.push(Token::Text(quoted(&mut position, '\'')?));
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            position += symbol.len();
+            tokens.push(Token::Symbol(*symbol));
+        } else {
+            return Err(incorrect(format!("Unexpected character {} in SQL.", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A column, optionally qualified by a table name or alias.
+#[derive(Clone, PartialEq, Debug)]
+struct Column {
+    table: Option<String>,
+    name: String,
+}
+
+impl Column {
+    /// Returns true iff the column stands for the entity id.
+    fn is_id(&self) -> bool {
+        self.name == "id"
+    }
+
+    /// Interprets a literal compared with the column. Entity ids are
+    /// given as plain numbers.
+    fn literal(&self, value: &Value) -> Value {
+        match value {
+            Value::Number(number) if self.is_id() => Value::Eid(*number as Eid),
+            _ => value.clone(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Item {
+    Column(Column),
+    /// An aggregated column, `COUNT(*)` having none.
+    Aggregate(AggregationFn, Option<Column>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Operand {
+    Column(Column),
+    Literal(Value),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Comparison {
+    left: Operand,
+    predicate: Predicate,
+    right: Operand,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Select {
+    items: Vec<Item>,
+    /// Tables along with their aliases.
+    tables: Vec<(String, String)>,
+    conditions: Vec<Comparison>,
+    group_by: Vec<Column>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(sql: &str) -> Result<Self, Error> {
+        Ok(Parser {
+            tokens: tokenize(sql)?,
+            position: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, Error> {
+        match self.peek() {
+            None => Err(incorrect(format!(
+                "Unexpected end of SQL, expected {}.",
+                expected
+            ))),
+            Some(token) => Err(incorrect(format!(
+                "Unexpected {:?} in SQL, expected {}.",
+                token, expected
+            ))),
+        }
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        match self.peek() {
+            None | Some(Token::Symbol(";")) => Ok(()),
+            Some(_) => self.unexpected("end of statement"),
+        }
+    }
+
+    /// Consumes the keyword, if it comes next.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            self.unexpected(keyword)
+        }
+    }
+
+    /// Consumes the symbol, if it comes next.
+    fn symbol(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(Token::Symbol(next)) if *next == symbol => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            self.unexpected(symbol)
+        }
+    }
+
+    /// Consumes a name, if one comes next.
+    fn name(&mut self) -> Option<String> {
+        let name = match self.peek() {
+            Some(Token::Word(word)) => {
+                if KEYWORDS.iter().any(|kw| word.eq_ignore_ascii_case(kw)) {
+                    return None;
+                }
+                word.clone()
+            }
+            Some(Token::Quoted(name)) => name.clone(),
+            _ => return None,
+        };
+
+        self.position += 1;
+        Some(name)
+    }
+
+    fn expect_name(&mut self) -> Result<String, Error> {
+        match self.name() {
+            Some(name) => Ok(name),
+            None => self.unexpected("a name"),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Vec<Request>, Error> {
+        if self.keyword("CREATE") {
+            self.expect_keyword("MATERIALIZED")?;
+            self.expect_keyword("VIEW")?;
+            let name = self.expect_name()?;
+            self.expect_keyword("AS")?;
+            let plan = self.select()?.lower()?;
+
+            // views are named by clients, which must not silently
+            // end up with someone else's definition
+            Ok(vec![register(name, plan, ConflictPolicy::Error)])
+        } else if self.keyword("TAIL") {
+            Ok(vec![Request::Interest(interest(self.expect_name()?))])
+        } else if self.keyword("DROP") {
+            self.expect_keyword("VIEW")?;
+            Ok(vec![Request::Unregister(self.expect_name()?)])
+        } else {
+            let plan = self.select()?.lower()?;
+            let name = format!("sql/{}", content_id(&plan));
+
+            Ok(vec![
+                register(name.clone(), plan, Default::default()),
+                Request::Interest(interest(name)),
+            ])
+        }
+    }
+
+    fn select(&mut self) -> Result<Select, Error> {
+        self.expect_keyword("SELECT")?;
+
+        let mut items = vec![self.item()?];
+        while self.symbol(",") {
+            items.push(self.item()?);
+        }
+
+        self.expect_keyword("FROM")?;
+
+        let mut tables = vec![self.table()?];
+        let mut conditions = Vec::new();
+
+        loop {
+            if self.symbol(",") {
+                tables.push(self.table()?);
+                continue;
+            }
+
+            if self.keyword("INNER") {
+                self.expect_keyword("JOIN")?;
+            } else if !self.keyword("JOIN") {
+                break;
+            }
+
+            tables.push(self.table()?);
+            self.expect_keyword("ON")?;
+            conditions.extend(self.conjunction()?);
+        }
+
+        if self.keyword("WHERE") {
+            conditions.extend(self.conjunction()?);
+        }
+
+        let mut group_by = Vec::new();
+        if self.keyword("GROUP") {
+            self.expect_keyword("BY")?;
+
+            group_by.push(self.column()?);
+            while self.symbol(",") {
+                group_by.push(self.column()?);
+            }
+        }
+
+        Ok(Select {
+            items,
+            tables,
+            conditions,
+            group_by,
+        })
+    }
+
+    fn table(&mut self) -> Result<(String, String), Error> {
+        let table = self.expect_name()?;
+
+        let alias = if self.keyword("AS") {
+            self.expect_name()?
+        } else {
+            self.name().unwrap_or_else(|| table.clone())
+        };
+
+        Ok((table, alias))
+    }
+
+    fn column(&mut self) -> Result<Column, Error> {
+        let name = self.expect_name()?;
+
+        if self.symbol(".") {
+            Ok(Column {
+                table: Some(name),
+                name: self.expect_name()?,
+            })
+        } else {
+            Ok(Column { table: None, name })
+        }
+    }
+
+    fn item(&mut self) -> Result<Item, Error> {
+        let aggregation_fn = match self.peek() {
+            Some(Token::Word(word)) => match word.to_uppercase().as_str() {
+                "COUNT" => Some(AggregationFn::COUNT),
+                "SUM" => Some(AggregationFn::SUM),
+                "MIN" => Some(AggregationFn::MIN),
+                "MAX" => Some(AggregationFn::MAX),
+                "AVG" => Some(AggregationFn::AVG),
+                "MEDIAN" => Some(AggregationFn::MEDIAN),
+                "VARIANCE" => Some(AggregationFn::VARIANCE),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let is_call = self.tokens.get(self.position + 1) == Some(&Token::Symbol("("));
+
+        match aggregation_fn {
+            Some(aggregation_fn) if is_call => {
+                self.position += 2;
+
+                let column = if aggregation_fn == AggregationFn::COUNT && self.symbol("*") {
+                    None
+                } else {
+                    Some(self.column()?)
+                };
+
+                self.expect_symbol(")")?;
+                Ok(Item::Aggregate(aggregation_fn, column))
+            }
+            _ => Ok(Item::Column(self.column()?)),
+        }
+    }
+
+    fn conjunction(&mut self) -> Result<Vec<Comparison>, Error> {
+        let mut comparisons = vec![self.comparison()?];
+        while self.keyword("AND") {
+            comparisons.push(self.comparison()?);
+        }
+
+        Ok(comparisons)
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, Error> {
+        let left = self.operand()?;
+
+        let predicate = match self.peek() {
+            Some(Token::Symbol("=")) => Predicate::EQ,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Predicate::NEQ,
+            Some(Token::Symbol("<")) => Predicate::LT,
+            Some(Token::Symbol("<=")) => Predicate::LTE,
+            Some(Token::Symbol(">")) => Predicate::GT,
+            Some(Token::Symbol(">=")) => Predicate::GTE,
+            _ => return self.unexpected("a comparison"),
+        };
+        self.position += 1;
+
+        let right = self.operand()?;
+
+        Ok(Comparison {
+            left,
+            predicate,
+            right,
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, Error> {
+        if self.keyword("TRUE") {
+            return Ok(Operand::Literal(Value::Bool(true)));
+        } else if self.keyword("FALSE") {
+            return Ok(Operand::Literal(Value::Bool(false)));
+        }
+
+        let literal = match self.peek().cloned() {
+            Some(Token::Number(number)) => Value::Number(number),
+            Some(Token::Text(text)) => Value::String(text),
+            _ => return Ok(Operand::Column(self.column()?)),
+        };
+
+        self.position += 1;
+        Ok(Operand::Literal(literal))
+    }
+}
+
+fn register(name: String, plan: Plan, conflict_policy: ConflictPolicy) -> Request {
+    Request::Register(Register {
+        rules: vec![Rule {
+            name: name.clone(),
+            plan,
+        }],
+        publish: vec![name],
+        conflict_policy,
+        replicate: Vec::new(),
+        distinct: Vec::new(),
+        metadata: Default::default(),
+    })
+}
+
+fn interest(name: String) -> Interest {
+    Interest {
+        name,
+        as_of: None,
+        bindings: Vec::new(),
+        specialize: false,
+        restrict: None,
+        project: None,
+        order_by: Vec::new(),
+        window: None,
+        delivery: Default::default(),
+        consumer: None,
+    }
+}
+
+/// Variables and clauses introduced while lowering a query.
+struct Lowering<'a> {
+    tables: &'a [(String, String)],
+    /// The entity variable of each table.
+    entities: Vec<Var>,
+    /// The variable bound to each (table, column).
+    columns: HashMap<(usize, String), Var>,
+    /// Clauses [?e t/c ?v], in order.
+    clauses: Vec<(Var, Aid, Var)>,
+    /// Variables known to be equal, as (variable, representative).
+    unified: HashMap<Var, Var>,
+    next: Var,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(tables: &'a [(String, String)]) -> Self {
+        // Aggregations mark the variables they replace with 0, which
+        // is therefore never handed out.
+        let entities: Vec<Var> = (1..=tables.len() as Var).collect();
+
+        Lowering {
+            tables,
+            next: entities.len() as Var + 1,
+            entities,
+            columns: HashMap::new(),
+            clauses: Vec::new(),
+            unified: HashMap::new(),
+        }
+    }
+
+    fn table(&self, column: &Column) -> Result<usize, Error> {
+        match column.table {
+            None if self.tables.len() == 1 => Ok(0),
+            None => Err(incorrect(format!(
+                "Column {} is ambiguous, qualify it with a table.",
+                column.name
+            ))),
+            Some(ref qualifier) => self
+                .tables
+                .iter()
+                .position(|(_table, alias)| alias == qualifier)
+                .ok_or_else(|| Error {
+                    category: "df.error.category/not-found",
+                    message: format!("Table {} is not part of the query.", qualifier),
+                }),
+        }
+    }
+
+    fn var(&mut self, column: &Column) -> Result<Var, Error> {
+        let table = self.table(column)?;
+
+        if column.is_id() {
+            return Ok(self.entities[table]);
+        }
+
+        let key = (table, column.name.clone());
+        if let Some(var) = self.columns.get(&key) {
+            return Ok(*var);
+        }
+
+        let var = self.next;
+        self.next += 1;
+
+        let aid = format!("{}/{}", self.tables[table].0, column.name);
+        self.clauses.push((self.entities[table], aid, var));
+        self.columns.insert(key, var);
+
+        Ok(var)
+    }
+
+    fn find(&self, mut var: Var) -> Var {
+        while let Some(next) = self.unified.get(&var) {
+            var = *next;
+        }
+        var
+    }
+
+    fn unify(&mut self, a: Var, b: Var) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.unified
+                .insert(std::cmp::max(a, b), std::cmp::min(a, b));
+        }
+    }
+}
+
+/// A comparison after resolving its columns.
+enum Constraint {
+    Vars(Var, Predicate, Var),
+    Left(Var, Predicate, Value),
+    Right(Value, Predicate, Var),
+}
+
+impl Select {
+    fn lower(&self) -> Result<Plan, Error> {
+        let mut lowering = Lowering::new(&self.tables);
+
+        let mut outputs = Vec::new();
+        let mut aggregations = Vec::new();
+
+        // COUNT(*) counts a variable bound to the same constant in
+        // every row, which therefore never coincides with a grouped
+        // column. Rows are told apart by the entities of all tables.
+        let mut rows = None;
+
+        for item in self.items.iter() {
+            match item {
+                Item::Column(column) => outputs.push((lowering.var(column)?, None)),
+                Item::Aggregate(aggregation_fn, column) => {
+                    let var = match column {
+                        None => *rows.get_or_insert_with(|| {
+                            let var = lowering.next;
+                            lowering.next += 1;
+                            var
+                        }),
+                        Some(column) => lowering.var(column)?,
+                    };
+
+                    outputs.push((var, Some(aggregation_fn.clone())));
+                    aggregations.push(aggregation_fn.clone());
+                }
+            }
+        }
+
+        let group_by = self
+            .group_by
+            .iter()
+            .map(|column| lowering.var(column))
+            .collect::<Result<Vec<Var>, Error>>()?;
+
+        let mut constraints = Vec::new();
+
+        for comparison in self.conditions.iter() {
+            let constraint = match (&comparison.left, &comparison.right) {
+                (Operand::Column(left), Operand::Column(right)) => {
+                    let (left, right) = (lowering.var(left)?, lowering.var(right)?);
+
+                    if comparison.predicate == Predicate::EQ {
+                        lowering.unify(left, right);
+                        continue;
+                    }
+
+                    Constraint::Vars(left, comparison.predicate.clone(), right)
+                }
+                (Operand::Column(column), Operand::Literal(value)) => Constraint::Left(
+                    lowering.var(column)?,
+                    comparison.predicate.clone(),
+                    column.literal(value),
+                ),
+                (Operand::Literal(value), Operand::Column(column)) => Constraint::Right(
+                    column.literal(value),
+                    comparison.predicate.clone(),
+                    lowering.var(column)?,
+                ),
+                (Operand::Literal(_), Operand::Literal(_)) => {
+                    return Err(incorrect(
+                        "Comparisons must involve at least one column.".to_string(),
+                    ));
+                }
+            };
+
+            constraints.push(constraint);
+        }
+
+        // Equal columns share a single variable from here on.
+        let mut clauses: Vec<(Var, Aid, Var)> = Vec::new();
+        for (e, aid, v) in lowering.clauses.iter() {
+            let clause = (lowering.find(*e), aid.clone(), lowering.find(*v));
+            if !clauses.contains(&clause) {
+                clauses.push(clause);
+            }
+        }
+
+        for (index, (table, _alias)) in self.tables.iter().enumerate() {
+            let e = lowering.find(lowering.entities[index]);
+            if !clauses.iter().any(|(ce, _, cv)| *ce == e || *cv == e) {
+                return Err(incorrect(format!(
+                    "Table {} must be constrained by at least one of its columns.",
+                    table
+                )));
+            }
+        }
+
+        let mut variables: Vec<Var> = Vec::new();
+        for (e, _aid, v) in clauses.iter() {
+            for var in &[*e, *v] {
+                if !variables.contains(var) {
+                    variables.push(*var);
+                }
+            }
+        }
+
+        let mut plan = if clauses.len() == 1 {
+            let (e, aid, v) = clauses.pop().unwrap();
+            Plan::MatchA(e, aid, v)
+        } else {
+            Plan::MultiJoin(MultiJoin {
+                variables,
+                plans: clauses
+                    .into_iter()
+                    .map(|(e, aid, v)| Plan::MatchA(e, aid, v))
+                    .collect(),
+            })
+        };
+
+        for constraint in constraints.into_iter() {
+            let (variables, predicate, constants) = match constraint {
+                Constraint::Vars(left, predicate, right) => (
+                    vec![lowering.find(left), lowering.find(right)],
+                    predicate,
+                    vec![None, None],
+                ),
+                Constraint::Left(left, predicate, value) => (
+                    vec![lowering.find(left)],
+                    predicate,
+                    vec![None, Some(value)],
+                ),
+                Constraint::Right(value, predicate, right) => (
+                    vec![lowering.find(right)],
+                    predicate,
+                    vec![Some(value), None],
+                ),
+            };
+
+            plan = Plan::Filter(Filter {
+                variables,
+                predicate,
+                plan: Box::new(plan),
+                constants,
+            });
+        }
+
+        if let Some(var) = rows {
+            plan = Plan::Transform(Transform {
+                variables: Vec::new(),
+                result_sym: var,
+                plan: Box::new(plan),
+                function: Function::ADD,
+                constants: vec![Some(Value::Number(1))],
+            });
+        }
+
+        let outputs: Vec<(Var, Option<AggregationFn>)> = outputs
+            .into_iter()
+            .map(|(var, aggregation_fn)| (lowering.find(var), aggregation_fn))
+            .collect();
+        let group_by: Vec<Var> = group_by.iter().map(|var| lowering.find(*var)).collect();
+        let selected: Vec<Var> = outputs.iter().map(|(var, _)| *var).collect();
+
+        if aggregations.is_empty() {
+            if let Some((var, _)) = outputs
+                .iter()
+                .find(|(var, _)| !group_by.is_empty() && !group_by.contains(var))
+            {
+                return Err(ungrouped(&self.items, &selected, *var));
+            }
+
+            return Ok(Plan::Project(Project {
+                variables: selected,
+                plan: Box::new(plan),
+            }));
+        }
+
+        // Keys are the selected columns, in order, followed by those
+        // only grouped by.
+        let mut key_symbols: Vec<Var> = Vec::new();
+        let mut aggregation_symbols: Vec<Var> = Vec::new();
+
+        for (var, aggregation_fn) in outputs.iter() {
+            match aggregation_fn {
+                None if group_by.contains(var) => key_symbols.push(*var),
+                None => return Err(ungrouped(&self.items, &selected, *var)),
+                Some(_) => aggregation_symbols.push(*var),
+            }
+        }
+
+        let unselected: Vec<Var> = group_by
+            .iter()
+            .filter(|var| !key_symbols.contains(var))
+            .cloned()
+            .collect();
+        key_symbols.extend(unselected.iter().cloned());
+
+        if let Some(var) = aggregation_symbols
+            .iter()
+            .find(|var| key_symbols.contains(var))
+        {
+            return Err(incorrect(format!(
+                "Column {} can't be both grouped by and aggregated.",
+                column_name(&self.items, &selected, *var)
+            )));
+        }
+
+        // Every row counts, even if it agrees with another one on all
+        // aggregated columns.
+        let mut with_symbols: Vec<Var> = Vec::new();
+        for entity in lowering.entities.iter().map(|e| lowering.find(*e)) {
+            let is_taken = key_symbols.contains(&entity)
+                || aggregation_symbols.contains(&entity)
+                || with_symbols.contains(&entity);
+
+            if !is_taken {
+                with_symbols.push(entity);
+            }
+        }
+
+        let mut projected = key_symbols.clone();
+        for var in aggregation_symbols.iter().chain(with_symbols.iter()) {
+            if !projected.contains(var) {
+                projected.push(*var);
+            }
+        }
+
+        let aggregate = Plan::Aggregate(Aggregate {
+            variables: selected.iter().chain(unselected.iter()).cloned().collect(),
+            plan: Box::new(Plan::Project(Project {
+                variables: projected,
+                plan: Box::new(plan),
+            })),
+            aggregation_fns: aggregations,
+            key_symbols,
+            aggregation_symbols,
+            with_symbols,
+        });
+
+        if unselected.is_empty() {
+            Ok(aggregate)
+        } else {
+            Ok(Plan::Project(Project {
+                variables: selected,
+                plan: Box::new(aggregate),
+            }))
+        }
+    }
+}
+
+/// Returns the name of the selected column bound to the variable.
+fn column_name(items: &[Item], selected: &[Var], var: Var) -> String {
+    let position = selected.iter().position(|x| *x == var);
+
+    match position.map(|position| &items[position]) {
+        Some(Item::Column(column)) | Some(Item::Aggregate(_, Some(column))) => column.name.clone(),
+        _ => "*".to_string(),
+    }
+}
+
+fn ungrouped(items: &[Item], selected: &[Var], var: Var) -> Error {
+    incorrect(format!(
+        "Column {} must appear in GROUP BY or be aggregated.",
+        column_name(items, selected, var)
+    ))
+}
@@ -0,0 +1,353 @@
+//! External functions, implemented by subprocesses speaking a
+//! line-delimited JSON protocol. This allows Transform stages to
+//! call into code written in any language.
+//!
+//! The process is sent one line per input tuple, holding a JSON array
+//! of argument values (e.g. `[{"Number":1},{"String":"a"}]`). It must
+//! respond with exactly one line per input line, in order, holding a
+//! JSON array of result values. Each result produces an output tuple
+//! of its own, an empty array drops the input tuple.
+//!
+//! Processes are driven by threads of their own, s.t. workers never
+//! block waiting for them. Results are cached per argument list for
+//! as long as any tuple with those arguments is present, and
+//! retractions replay the cached results rather than calling the
+//! process again. Every worker runs processes of its own, and tuples
+//! the process fails on (after exhausting its restart policy) are
+//! dropped, along with their later retractions.
+
+extern crate serde_json;
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use timely::scheduling::SyncActivator;
+
+use crate::Value;
+
+/// What to do about a process that exited, timed out, or responded
+/// with garbage.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum RestartPolicy {
+    /// Give up on the batch at hand, and on all later ones.
+    Never,
+    /// Restart the process and retry the batch, at most this many
+    /// times over the lifetime of the operator.
+    Limit(usize),
+    /// Restart the process and retry the batch, once per batch.
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "always" => Ok(RestartPolicy::Always),
+            _ => s
+                .parse()
+                .map(RestartPolicy::Limit)
+                .map_err(|_| format!("Unknown restart policy {}.", s)),
+        }
+    }
+}
+
+/// Configuration of an external function.
+#[derive(Clone, Debug)]
+pub struct ExternalFunction {
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+    /// Maximum number of tuples sent to the process before waiting
+    /// for its responses.
+    pub batch_size: usize,
+    /// How long to wait for the responses to a batch.
+    pub timeout: Duration,
+    /// What to do about failing processes.
+    pub restart: RestartPolicy,
+}
+
+impl ExternalFunction {
+    /// Creates a function implemented by the specified command, with
+    /// default settings.
+    pub fn new(command: Vec<String>) -> Self {
+        ExternalFunction {
+            command,
+            batch_size: 1024,
+            timeout: Duration::from_secs(5),
+            restart: RestartPolicy::Limit(3),
+        }
+    }
+
+    /// Parses a specification of the form `NAME=PROGRAM ARGS..`, as
+    /// given on the command line.
+    pub fn parse(spec: &str) -> Result<(String, Self), String> {
+        let idx = spec
+            .find('=')
+            .ok_or_else(|| format!("Expected NAME=COMMAND, got {}.", spec))?;
+
+        let name = spec[..idx].trim().to_string();
+        let command: Vec<String> = spec[idx + 1..]
+            .split_whitespace()
+            .map(|part| part.to_string())
+            .collect();
+
+        if name.is_empty() || command.is_empty() {
+            Err(format!("Expected NAME=COMMAND, got {}.", spec))
+        } else {
+            Ok((name, ExternalFunction::new(command)))
+        }
+    }
+}
+
+/// A running process.
+struct Running {
+    child: Child,
+    requests: Sender<Vec<u8>>,
+    lines: Receiver<String>,
+}
+
+/// A process implementing an external function, started on first
+/// use and restarted according to the function's restart policy.
+pub struct Subprocess {
+    function: ExternalFunction,
+    running: Option<Running>,
+    restarts: usize,
+    failed: bool,
+}
+
+impl Subprocess {
+    /// Creates a handle to a process implementing the specified
+    /// function. The process is not started yet.
+    pub fn new(function: ExternalFunction) -> Self {
+        Subprocess {
+            function,
+            running: None,
+            restarts: 0,
+            failed: false,
+        }
+    }
+
+    /// Returns the number of times the process has been restarted.
+    pub fn restarts(&self) -> usize {
+        self.restarts
+    }
+
+    /// Applies the function to each of the specified argument lists,
+    /// returning the results for each of them, in order.
+    pub fn call(&mut self, arguments: &[Vec<Value>]) -> Result<Vec<Vec<Value>>, String> {
+        let mut results = Vec::with_capacity(arguments.len());
+
+        for batch in arguments.chunks(std::cmp::max(self.function.batch_size, 1)) {
+            results.extend(self.call_batch(batch)?);
+        }
+
+        Ok(results)
+    }
+
+    fn call_batch(&mut self, batch: &[Vec<Value>]) -> Result<Vec<Vec<Value>>, String> {
+        if self.failed {
+            return Err("The process has failed for good.".to_string());
+        }
+
+        let mut retried = false;
+
+        loop {
+            match self.try_batch(batch) {
+                Ok(results) => return Ok(results),
+                Err(err) => {
+                    self.stop();
+
+                    let restart = match self.function.restart {
+                        RestartPolicy::Never => false,
+                        RestartPolicy::Limit(limit) => !retried && self.restarts < limit,
+                        RestartPolicy::Always => !retried,
+                    };
+
+                    if restart {
+                        warn!("[UDF] restarting {:?}: {}", self.function.command, err);
+                        self.restarts += 1;
+                        retried = true;
+                    } else {
+                        if self.function.restart != RestartPolicy::Always {
+                            self.failed = true;
+                        }
+
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_batch(&mut self, batch: &[Vec<Value>]) -> Result<Vec<Vec<Value>>, String> {
+        if self.running.is_none() {
+            self.running = Some(self.spawn()?);
+        }
+
+        let running = self.running.as_mut().unwrap();
+
+        let mut request = Vec::new();
+        for arguments in batch.iter() {
+            serde_json::to_writer(&mut request, arguments).map_err(|err| err.to_string())?;
+            request.push(b'\n');
+        }
+
+        running
+            .requests
+            .send(request)
+            .map_err(|_| "Failed to write to the process.".to_string())?;
+
+        let deadline = Instant::now() + self.function.timeout;
+        let mut results = Vec::with_capacity(batch.len());
+
+        while results.len() < batch.len() {
+            let now = Instant::now();
+            let remaining = if now < deadline {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            };
+
+            let line = match running.lines.recv_timeout(remaining) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(format!("Timed out after {:?}.", self.function.timeout));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err("The process exited.".to_string());
+                }
+            };
+
+            let values: Vec<Value> = serde_json::from_str(&line)
+                .map_err(|err| format!("Malformed response {:?}: {}", line, err))?;
+
+            results.push(values);
+        }
+
+        Ok(results)
+    }
+
+    fn spawn(&self) -> Result<Running, String> {
+        let mut child = Command::new(&self.function.command[0])
+            .args(&self.function.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Failed to start {:?}: {}", self.function.command, err))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (requests, pending) = channel::<Vec<u8>>();
+        let (send_lines, lines) = channel();
+
+        // Requests are written on a thread of their own as well, s.t.
+        // a process that stops reading can't block the caller past
+        // the deadline. Killing the process unblocks the thread.
+        thread::spawn(move || {
+            for request in pending.iter() {
+                if stdin
+                    .write_all(&request)
+                    .and_then(|_| stdin.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        // Responses are read on a thread of their own, s.t. waiting
+        // for them can time out.
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Err(_) => break,
+                    Ok(line) => {
+                        if send_lines.send(line).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Running {
+            child,
+            requests,
+            lines,
+        })
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut running) = self.running.take() {
+            // Killing fails iff the process has exited already.
+            let _ = running.child.kill();
+            let _ = running.child.wait();
+        }
+    }
+}
+
+impl Drop for Subprocess {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A process implementing an external function, called from a
+/// thread of its own. Responses are returned in request order, and
+/// the operator making the requests is activated whenever one is
+/// ready.
+pub struct Background {
+    requests: Sender<Vec<Vec<Value>>>,
+    responses: Receiver<Result<Vec<Vec<Value>>, String>>,
+}
+
+impl Background {
+    /// Starts a thread calling the specified function on behalf of
+    /// the operator behind `activator`.
+    pub fn new(function: ExternalFunction, activator: SyncActivator) -> Self {
+        let (requests, pending) = channel::<Vec<Vec<Value>>>();
+        let (send_responses, responses) = channel();
+
+        // The thread (and with it, the process) goes away along with
+        // the handle.
+        thread::spawn(move || {
+            let mut process = Subprocess::new(function);
+
+            for arguments in pending.iter() {
+                if send_responses.send(process.call(&arguments)).is_err() {
+                    break;
+                }
+
+                let _ = activator.activate();
+            }
+        });
+
+        Background {
+            requests,
+            responses,
+        }
+    }
+
+    /// Requests the function to be applied to each of the specified
+    /// argument lists, without waiting for the results.
+    pub fn call(&self, arguments: Vec<Vec<Value>>) {
+        // A failure is reported by `try_recv`.
+        let _ = self.requests.send(arguments);
+    }
+
+    /// Returns the response to the oldest outstanding request, if
+    /// it is available yet.
+    pub fn try_recv(&self) -> Option<Result<Vec<Vec<Value>>, String>> {
+        match self.responses.try_recv() {
+            Ok(response) => Some(response),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("The process thread is gone.".to_string())),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use declarative_dataflow::server::accounting::{Accounting, RuleUsage, Usage, UsageHook};
+use declarative_dataflow::{TxData, Value};
+
+struct Recorder(Rc<RefCell<Vec<Usage>>>);
+
+impl UsageHook for Recorder {
+    fn report(&mut self, usage: &Usage) {
+        self.0.borrow_mut().push(usage.clone());
+    }
+}
+
+#[test]
+fn attribute_to_relations() {
+    let mut accounting = Accounting::new(Duration::from_secs(1));
+
+    accounting.attribute(vec![0], "names");
+    accounting.attribute(vec![1], "ages");
+    // the first relation implemented by a dataflow pays for it
+    accounting.attribute(vec![0], "aliases");
+
+    accounting.deliver("names", 7, 100);
+    accounting.deliver("names", 8, 50);
+    accounting.deliver("ages", 7, 10);
+
+    let operators = vec![
+        (vec![0, 1], Duration::from_millis(2), 0),
+        (vec![0, 2], Duration::from_millis(3), 40),
+        (vec![0, 2, 1], Duration::from_millis(1), 2),
+        (vec![1, 1], Duration::from_nanos(500), 5),
+        // the dataflow scopes themselves, and unattributed dataflows
+        (vec![0], Duration::from_millis(100), 0),
+        (vec![2, 1], Duration::from_millis(100), 0),
+    ];
+
+    let usage = accounting.usage(3, &operators);
+
+    assert_eq!(usage.worker, 3);
+    assert_eq!(
+        usage.rules.into_iter().collect::<Vec<_>>(),
+        vec![
+            (
+                "ages".to_string(),
+                RuleUsage {
+                    elapsed: 500,
+                    arranged: 5,
+                    delivered: 10,
+                }
+            ),
+            (
+                "names".to_string(),
+                RuleUsage {
+                    elapsed: 6_000_000,
+                    arranged: 42,
+                    delivered: 150,
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        usage.clients.into_iter().collect::<Vec<_>>(),
+        vec![(7, 110), (8, 50)]
+    );
+}
+
+#[test]
+fn report_changes() {
+    let reports = Rc::new(RefCell::new(Vec::new()));
+    let interval = Duration::from_secs(1);
+    let now = Instant::now();
+
+    let mut accounting = Accounting::new(interval);
+    accounting.hooks.push(Box::new(Recorder(reports.clone())));
+
+    // nothing to report yet
+    assert!(accounting.check(0, &[], now).is_empty());
+    assert!(reports.borrow().is_empty());
+
+    accounting.deliver("names", 7, 100);
+
+    let tx_data = accounting.check(0, &[], now);
+    let delivered = |tx_data: &[TxData]| -> Vec<(isize, Value)> {
+        tx_data
+            .iter()
+            .filter(|TxData(_, _, aid, _)| aid == "df.usage.client/delivered")
+            .map(|TxData(diff, _, _, v)| (*diff, v.clone()))
+            .collect()
+    };
+
+    assert_eq!(delivered(&tx_data), vec![(1, Value::Number(100))]);
+    assert_eq!(reports.borrow().len(), 1);
+
+    // reports are rate-limited
+    accounting.deliver("names", 7, 20);
+    assert!(accounting.check(0, &[], now).is_empty());
+
+    let tx_data = accounting.check(0, &[], now + interval);
+    assert_eq!(
+        delivered(&tx_data),
+        vec![(-1, Value::Number(100)), (1, Value::Number(120))]
+    );
+    assert_eq!(reports.borrow().len(), 2);
+    assert_eq!(reports.borrow()[1].rules["names"].delivered, 120);
+}
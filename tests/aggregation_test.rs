@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter::FromIterator;
 use std::sync::mpsc::channel;
 use std::time::Duration;
@@ -8,8 +8,10 @@ use timely::dataflow::operators::Operator;
 use timely::Configuration;
 
 use declarative_dataflow::binding::Binding;
-use declarative_dataflow::plan::{Aggregate, AggregationFn, Implementable, Join, Project};
-use declarative_dataflow::server::Server;
+use declarative_dataflow::plan::{
+    Aggregate, AggregationFn, Filter, Implementable, Join, Predicate, Project, PullLevel, Union,
+};
+use declarative_dataflow::server::{Config, Register, Server};
 use declarative_dataflow::{Aid, AttributeSemantics, Plan, Rule, TxData, Value};
 use Value::{Eid, Number, Rational32, String};
 
@@ -25,7 +27,7 @@ struct Case {
 fn dependencies(case: &Case) -> HashSet<Aid> {
     let mut deps = HashSet::new();
 
-    for binding in case.plan.into_bindings().iter() {
+    for binding in case.plan.into_bindings().unwrap().iter() {
         if let Binding::Attribute(binding) = binding {
             deps.insert(binding.source_attribute.clone());
         }
@@ -602,6 +604,198 @@ fn run_aggregation_cases() {
             expectations: vec![
                 vec![(vec![Number(6)], 0, 1)],
             ],
+        },
+        Case {
+            description: "[:find ?monster (top 2 ?heads) :with ?monster :where [?e :monster ?monster] [?e :heads ?heads]]",
+            plan: {
+                let (e, monster, heads) = (1, 2, 3);
+                Plan::Aggregate(Aggregate {
+                    variables: vec![monster, heads],
+                    plan: Box::new(Plan::Project(Project {
+                        variables: vec![heads, monster],
+                        plan: Box::new(Plan::Join(Join {
+                            variables: vec![e],
+                            left_plan: Box::new(Plan::MatchA(e, ":monster".to_string(), monster)),
+                            right_plan: Box::new(Plan::MatchA(e, ":heads".to_string(), heads)),
+                        })),
+                    })),
+                    aggregation_fns: vec![AggregationFn::TOP(2)],
+                    key_symbols: vec![],
+                    aggregation_symbols: vec![heads],
+                    with_symbols: vec![monster],
+                })
+            },
+            transactions: vec![
+                vec![
+                    TxData(1, 1, ":monster".to_string(), String("Cerberus".to_string())),
+                    TxData(1, 1, ":heads".to_string(), Number(3)),
+                    TxData(1, 2, ":monster".to_string(), String("Medusa".to_string())),
+                    TxData(1, 2, ":heads".to_string(), Number(1)),
+                    TxData(1, 3, ":monster".to_string(), String("Cyclops".to_string())),
+                    TxData(1, 3, ":heads".to_string(), Number(1)),
+                    TxData(1, 4, ":monster".to_string(), String("Hydra".to_string())),
+                    TxData(1, 4, ":heads".to_string(), Number(9)),
+                ],
+            ],
+            expectations: vec![
+                vec![
+                    (vec![String("Hydra".to_string()), Number(9)], 0, 1),
+                    (vec![String("Cerberus".to_string()), Number(3)], 0, 1),
+                ],
+            ],
+        },
+        Case {
+            description: "[:find ?monster (bottom 2 ?heads) :with ?monster :where [?e :monster ?monster] [?e :heads ?heads]]",
+            plan: {
+                let (e, monster, heads) = (1, 2, 3);
+                Plan::Aggregate(Aggregate {
+                    variables: vec![monster, heads],
+                    plan: Box::new(Plan::Project(Project {
+                        variables: vec![heads, monster],
+                        plan: Box::new(Plan::Join(Join {
+                            variables: vec![e],
+                            left_plan: Box::new(Plan::MatchA(e, ":monster".to_string(), monster)),
+                            right_plan: Box::new(Plan::MatchA(e, ":heads".to_string(), heads)),
+                        })),
+                    })),
+                    aggregation_fns: vec![AggregationFn::BOTTOM(2)],
+                    key_symbols: vec![],
+                    aggregation_symbols: vec![heads],
+                    with_symbols: vec![monster],
+                })
+            },
+            transactions: vec![
+                vec![
+                    TxData(1, 1, ":monster".to_string(), String("Cerberus".to_string())),
+                    TxData(1, 1, ":heads".to_string(), Number(3)),
+                    TxData(1, 2, ":monster".to_string(), String("Medusa".to_string())),
+                    TxData(1, 2, ":heads".to_string(), Number(1)),
+                    TxData(1, 3, ":monster".to_string(), String("Cyclops".to_string())),
+                    TxData(1, 3, ":heads".to_string(), Number(1)),
+                    TxData(1, 4, ":monster".to_string(), String("Hydra".to_string())),
+                    TxData(1, 4, ":heads".to_string(), Number(9)),
+                ],
+            ],
+            expectations: vec![
+                vec![
+                    (vec![String("Cyclops".to_string()), Number(1)], 0, 1),
+                    (vec![String("Medusa".to_string()), Number(1)], 0, 1),
+                ],
+            ],
+        },
+        Case {
+            description: "[:find (count ?e) :where (or [?e :amount] [?e :price])]",
+            plan: {
+                let (e, amount, price) = (1, 2, 3);
+                Plan::Aggregate(Aggregate {
+                    variables: vec![e],
+                    plan: Box::new(Plan::Union(Union {
+                        variables: vec![e],
+                        plans: vec![
+                            Plan::MatchA(e, ":amount".to_string(), amount),
+                            Plan::MatchA(e, ":price".to_string(), price),
+                        ],
+                        fill: None,
+                    })),
+                    aggregation_fns: vec![AggregationFn::COUNT],
+                    key_symbols: vec![],
+                    aggregation_symbols: vec![e],
+                    with_symbols: vec![],
+                })
+            },
+            transactions: vec![
+                vec![
+                    TxData(1, 1, ":amount".to_string(), Number(5)),
+                    TxData(1, 2, ":amount".to_string(), Number(10)),
+                    TxData(1, 2, ":price".to_string(), Number(10)),
+                    TxData(1, 3, ":price".to_string(), Number(2)),
+                ],
+            ],
+            expectations: vec![
+                vec![(vec![Number(3)], 0, 1)],
+            ],
+        },
+        Case {
+            description: "[:find (count ?e) :where [?e :amount ?amount] [?e :limit ?limit] [(< ?amount ?limit)]]",
+            plan: {
+                let (e, amount, limit) = (1, 2, 3);
+                Plan::Aggregate(Aggregate {
+                    variables: vec![e],
+                    plan: Box::new(Plan::Project(Project {
+                        variables: vec![e],
+                        plan: Box::new(Plan::Filter(Filter {
+                            variables: vec![amount, limit],
+                            predicate: Predicate::LT,
+                            plan: Box::new(Plan::Join(Join {
+                                variables: vec![e],
+                                left_plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                                right_plan: Box::new(Plan::MatchA(e, ":limit".to_string(), limit)),
+                            })),
+                            constants: vec![None, None],
+                        })),
+                    })),
+                    aggregation_fns: vec![AggregationFn::COUNT],
+                    key_symbols: vec![],
+                    aggregation_symbols: vec![e],
+                    with_symbols: vec![],
+                })
+            },
+            transactions: vec![
+                vec![
+                    TxData(1, 1, ":amount".to_string(), Number(5)),
+                    TxData(1, 1, ":limit".to_string(), Number(10)),
+                    TxData(1, 2, ":amount".to_string(), Number(12)),
+                    TxData(1, 2, ":limit".to_string(), Number(10)),
+                    TxData(1, 3, ":amount".to_string(), Number(3)),
+                    TxData(1, 3, ":limit".to_string(), Number(4)),
+                ],
+            ],
+            expectations: vec![
+                vec![(vec![Number(2)], 0, 1)],
+            ],
+        },
+        Case {
+            description: "[:find ?name ?total :where [(sum ?amount) ?total :by ?e] [?e :name ?name]]",
+            plan: {
+                let (e, amount, name) = (1, 2, 3);
+                Plan::Project(Project {
+                    variables: vec![name, amount],
+                    plan: Box::new(Plan::Join(Join {
+                        variables: vec![e],
+                        left_plan: Box::new(Plan::Aggregate(Aggregate {
+                            variables: vec![e, amount],
+                            plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                            aggregation_fns: vec![AggregationFn::SUM],
+                            key_symbols: vec![e],
+                            aggregation_symbols: vec![amount],
+                            with_symbols: vec![],
+                        })),
+                        right_plan: Box::new(Plan::MatchA(e, ":name".to_string(), name)),
+                    })),
+                })
+            },
+            transactions: vec![
+                vec![
+                    TxData(1, 1, ":amount".to_string(), Number(5)),
+                    TxData(1, 1, ":amount".to_string(), Number(2)),
+                    TxData(1, 2, ":amount".to_string(), Number(10)),
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                vec![
+                    TxData(-1, 1, ":amount".to_string(), Number(2)),
+                ],
+            ],
+            expectations: vec![
+                vec![
+                    (vec![String("Dipper".to_string()), Number(7)], 0, 1),
+                    (vec![String("Mabel".to_string()), Number(10)], 0, 1),
+                ],
+                vec![
+                    (vec![String("Dipper".to_string()), Number(7)], 1, -1),
+                    (vec![String("Dipper".to_string()), Number(5)], 1, 1),
+                ],
+            ],
         }
     ];
 
@@ -680,3 +874,293 @@ fn run_aggregation_cases() {
         .unwrap();
     }
 }
+
+#[test]
+fn aggregation_pushed_beneath_join() {
+    let (e, d, name) = (1, 2, 3);
+
+    // [:find ?d ?name (count ?e) :where [?e :employee/dept ?d] [?d :dept/name ?name]]
+    let plan = Plan::Aggregate(Aggregate {
+        variables: vec![d, name, e],
+        plan: Box::new(Plan::Project(Project {
+            variables: vec![d, name, e],
+            plan: Box::new(Plan::Join(Join {
+                variables: vec![d],
+                left_plan: Box::new(Plan::MatchA(e, ":employee/dept".to_string(), d)),
+                right_plan: Box::new(Plan::MatchA(d, ":dept/name".to_string(), name)),
+            })),
+        })),
+        aggregation_fns: vec![AggregationFn::COUNT],
+        key_symbols: vec![d, name],
+        aggregation_symbols: vec![e],
+        with_symbols: vec![],
+    });
+
+    // Department names only contribute group keys, so employees are
+    // counted before joining.
+    if let Plan::Aggregate(ref aggregate) = plan {
+        assert_eq!(
+            aggregate.push_beneath_join(),
+            Some(Plan::Project(Project {
+                variables: vec![d, name, e],
+                plan: Box::new(Plan::Join(Join {
+                    variables: vec![d],
+                    left_plan: Box::new(Plan::Aggregate(Aggregate {
+                        variables: vec![d, e],
+                        plan: Box::new(Plan::Project(Project {
+                            variables: vec![d, e],
+                            plan: Box::new(Plan::MatchA(e, ":employee/dept".to_string(), d)),
+                        })),
+                        aggregation_fns: vec![AggregationFn::COUNT],
+                        key_symbols: vec![d],
+                        aggregation_symbols: vec![e],
+                        with_symbols: vec![],
+                    })),
+                    right_plan: Box::new(Plan::MatchA(d, ":dept/name".to_string(), name)),
+                })),
+            }))
+        );
+    }
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut outputs = Vec::new();
+        let mut expected = HashMap::new();
+
+        for enable_optimizer in vec![false, true] {
+            let mut server = Server::<u64>::new(Config {
+                enable_optimizer,
+                ..Default::default()
+            });
+            let (send_results, results) = channel();
+
+            worker.dataflow::<u64, _, _>(|scope| {
+                for attribute in &[":employee/dept", ":dept/name"] {
+                    server
+                        .context
+                        .internal
+                        .create_attribute(attribute, AttributeSemantics::Raw, scope)
+                        .unwrap();
+                }
+
+                server
+                    .register(Register {
+                        rules: vec![Rule {
+                            name: "headcount".to_string(),
+                            plan: plan.clone(),
+                        }],
+                        publish: vec!["headcount".to_string()],
+                        conflict_policy: Default::default(),
+                        replicate: Vec::new(),
+                        distinct: Vec::new(),
+                        metadata: Default::default(),
+                    })
+                    .unwrap();
+
+                server
+                    .interest("headcount", scope)
+                    .unwrap()
+                    .import_named(scope, "headcount")
+                    .as_collection(|tuple, _| tuple.clone())
+                    .inspect(move |x| {
+                        send_results.send(x.clone()).unwrap();
+                    });
+            });
+
+            let mut depts: HashMap<u64, u64> = HashMap::new();
+            let mut names: HashMap<u64, std::string::String> = HashMap::new();
+
+            for round in 0..6 {
+                let mut tx_data = Vec::new();
+
+                if round == 0 {
+                    for (dept, dept_name) in vec![(100, "a"), (101, "b"), (102, "c")] {
+                        names.insert(dept, dept_name.to_string());
+                        tx_data.push(TxData(
+                            1,
+                            dept,
+                            ":dept/name".to_string(),
+                            String(dept_name.to_string()),
+                        ));
+                    }
+                }
+
+                // Employees keep switching departments, retracting
+                // their previous assignment each time.
+                for employee in 1..=30 {
+                    if round > 0 && (employee + round) % 2 != 0 {
+                        continue;
+                    }
+
+                    if let Some(dept) = depts.remove(&employee) {
+                        tx_data.push(TxData(
+                            -1,
+                            employee,
+                            ":employee/dept".to_string(),
+                            Eid(dept),
+                        ));
+                    }
+
+                    if round < 5 || employee % 3 != 0 {
+                        let dept = 100 + (employee + round) % 3;
+                        depts.insert(employee, dept);
+                        tx_data.push(TxData(1, employee, ":employee/dept".to_string(), Eid(dept)));
+                    }
+                }
+
+                // Departments are renamed.
+                if round == 3 {
+                    let old = names.remove(&101).unwrap();
+                    tx_data.push(TxData(-1, 101, ":dept/name".to_string(), String(old)));
+                }
+                if round == 4 {
+                    names.insert(101, "b2".to_string());
+                    tx_data.push(TxData(
+                        1,
+                        101,
+                        ":dept/name".to_string(),
+                        String("b2".to_string()),
+                    ));
+                }
+
+                server.transact(tx_data, 0, 0).unwrap();
+                server.advance_domain(None, round + 1).unwrap();
+
+                worker.step_while(|| server.is_any_outdated());
+            }
+
+            let mut output: HashMap<(Vec<Value>, u64), isize> = HashMap::new();
+            for (tuple, time, diff) in results.try_iter() {
+                *output.entry((tuple, time)).or_insert(0) += diff;
+            }
+            output.retain(|_, diff| *diff != 0);
+            outputs.push(output);
+
+            expected.clear();
+            for dept in depts.values() {
+                if let Some(dept_name) = names.get(dept) {
+                    *expected.entry((*dept, dept_name.clone())).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Both implementations agree on every change.
+        assert_eq!(outputs[0], outputs[1]);
+
+        let mut state: HashMap<Vec<Value>, isize> = HashMap::new();
+        for ((tuple, _time), diff) in outputs[1].iter() {
+            *state.entry(tuple.clone()).or_insert(0) += diff;
+        }
+        state.retain(|_, diff| *diff != 0);
+
+        let expected: HashMap<Vec<Value>, isize> = expected
+            .into_iter()
+            .map(|((dept, dept_name), count)| {
+                (vec![Eid(dept), String(dept_name), Number(count)], 1)
+            })
+            .collect();
+
+        assert_eq!(state, expected);
+    })
+    .unwrap();
+}
+
+#[test]
+fn aggregation_pulled() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (e, amount) = (1, 2);
+
+        // The name of the most recent entity with an amount.
+        let plan = Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: Box::new(Plan::Aggregate(Aggregate {
+                variables: vec![e],
+                plan: Box::new(Plan::Project(Project {
+                    variables: vec![e],
+                    plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+                })),
+                aggregation_fns: vec![AggregationFn::MAX],
+                key_symbols: vec![],
+                aggregation_symbols: vec![e],
+                with_symbols: vec![],
+            })),
+            pull_attributes: vec![":name".to_string()],
+            path_attributes: vec![],
+            mark_unresolved: false,
+            recursion: None,
+            envelope: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for attribute in &[":amount", ":name"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(attribute, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "latest".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        let name = |e, name: &str| {
+            vec![
+                Eid(e),
+                Value::Aid(":name".to_string()),
+                String(name.to_string()),
+            ]
+        };
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":amount".to_string(), Number(5)),
+                    TxData(1, 2, ":amount".to_string(), Number(10)),
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(results.recv().unwrap(), (name(2, "Mabel"), 1));
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+
+        server
+            .transact(vec![TxData(-1, 2, ":amount".to_string(), Number(10))], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut expected = HashSet::new();
+        expected.insert((name(2, "Mabel"), -1));
+        expected.insert((name(1, "Dipper"), 1));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("Unknown result {:?}.", result);
+            }
+        }
+
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
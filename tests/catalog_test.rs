@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+
+use declarative_dataflow::plan::Project;
+use declarative_dataflow::server::catalog::Catalog;
+use declarative_dataflow::server::{
+    recovery_order, CreateAttribute, Interest, Register, RegisterSource, Request,
+};
+use declarative_dataflow::sources::{JsonFile, Source};
+use declarative_dataflow::{AttributeSemantics, Plan, Rule, TxData, Value};
+
+#[test]
+fn journal_and_replay() {
+    let path = std::env::temp_dir().join("declarative-dataflow-catalog.jsonl");
+    let _ = std::fs::remove_file(&path);
+
+    let create = Request::CreateAttribute(CreateAttribute {
+        name: ":name".to_string(),
+        semantics: AttributeSemantics::Raw,
+        retention: None,
+        value_type: None,
+        append_only: false,
+        metadata: Default::default(),
+    });
+    let register = Request::Register(Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::Project(Project {
+                variables: vec![1],
+                plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+            }),
+        }],
+        publish: vec!["names".to_string()],
+        conflict_policy: Default::default(),
+        replicate: vec!["names".to_string()],
+        distinct: vec![],
+        metadata: Default::default(),
+    });
+    let source = Request::RegisterSource(RegisterSource {
+        names: vec![":age".to_string()],
+        source: Source::JsonFile(JsonFile {
+            path: "people.json".to_string(),
+            follow: false,
+            eid_key: None,
+        }),
+        drive_domain: false,
+        append_only: false,
+    });
+    let transact = Request::Transact(vec![TxData(
+        1,
+        1,
+        ":name".to_string(),
+        Value::String("Dipper".to_string()),
+    )]);
+
+    {
+        let mut catalog = Catalog::open(&path).unwrap();
+
+        catalog.record(&register).unwrap();
+        catalog.record(&transact).unwrap();
+        catalog.record(&create).unwrap();
+    }
+
+    // Journals survive re-opening.
+    let mut catalog = Catalog::open(&path).unwrap();
+    catalog.record(&source).unwrap();
+
+    assert!(!Catalog::is_journaled(&transact));
+    assert_eq!(
+        catalog.replay(&HashSet::new()).unwrap(),
+        vec![create, source, register]
+    );
+}
+
+#[test]
+fn recovery_order_prioritizes() {
+    let interest = |name: &str| {
+        Request::Interest(Interest {
+            name: name.to_string(),
+            as_of: None,
+            bindings: Vec::new(),
+            specialize: false,
+            restrict: None,
+            project: None,
+            order_by: Vec::new(),
+            window: None,
+            delivery: Default::default(),
+            consumer: None,
+        })
+    };
+
+    let create = Request::CreateAttribute(CreateAttribute {
+        name: ":name".to_string(),
+        semantics: AttributeSemantics::Raw,
+        retention: None,
+        value_type: None,
+        append_only: false,
+        metadata: Default::default(),
+    });
+    let register = Request::Register(Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, ":name".to_string(), 1),
+        }],
+        publish: vec!["names".to_string()],
+        conflict_policy: Default::default(),
+        replicate: vec![],
+        distinct: vec![],
+        metadata: Default::default(),
+    });
+    let transact = Request::Transact(vec![TxData(
+        1,
+        1,
+        ":name".to_string(),
+        Value::String("Dipper".to_string()),
+    )]);
+
+    let requests = vec![
+        interest("ages"),
+        transact.clone(),
+        register.clone(),
+        interest("names"),
+        create.clone(),
+    ];
+
+    // Without priorities, interests keep their place among the data.
+    assert_eq!(
+        recovery_order(requests.clone(), &HashSet::new()),
+        vec![
+            create.clone(),
+            register.clone(),
+            interest("ages"),
+            transact.clone(),
+            interest("names"),
+        ]
+    );
+
+    let priority: HashSet<String> = vec!["names".to_string()].into_iter().collect();
+
+    assert_eq!(
+        recovery_order(requests, &priority),
+        vec![
+            create,
+            register,
+            interest("names"),
+            interest("ages"),
+            transact
+        ]
+    );
+}
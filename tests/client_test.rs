@@ -0,0 +1,174 @@
+#![cfg(feature = "async-client")]
+
+use std::sync::{Arc, Mutex};
+
+use futures::{stream, Future, Sink, Stream};
+
+use tokio::net::TcpListener;
+use tokio::runtime::current_thread::Runtime;
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use declarative_dataflow::client::Client;
+use declarative_dataflow::server::encoding::Outgoing;
+use declarative_dataflow::server::{parse_command, CreateAttribute, Register, Request};
+use declarative_dataflow::{AttributeSemantics, Plan, ResultDiff, Rule, Time, TxData, Value};
+use Value::{Eid, String};
+
+/// Answers a single message the way the server would, decoding it
+/// via the server's own request parsing. Transactions against an
+/// attribute named "missing" fail.
+fn respond(text: &str, received: &Mutex<Vec<Request>>) -> Vec<Message> {
+    let (id, requests) = parse_command(text);
+    let requests = requests.expect("failed to decode requests");
+
+    let mut responses = Vec::new();
+    let mut errors = Vec::new();
+
+    for request in requests.iter() {
+        match request {
+            Request::Transact(tx_data) if tx_data.iter().any(|datom| datom.2 == "missing") => {
+                errors.push(serde_json::json!({
+                    "df.error/category": "df.error.category/not-found",
+                    "df.error/message": "Attribute missing does not exist.",
+                }));
+            }
+            Request::Interest(interest) => {
+                let results: Vec<ResultDiff> = vec![(
+                    vec![Eid(1), String("Dipper".to_string())],
+                    Time::default(),
+                    1,
+                )];
+                responses.push(Outgoing::new(interest.name.clone(), results));
+            }
+            Request::Flush => {
+                let payload = serde_json::to_value(Time::default()).unwrap();
+                responses.push(Outgoing::new("df.flush".to_string(), payload));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(id) = id {
+        let ack = serde_json::json!({
+            "df.ack/command": id,
+            "df.ack/tx": 0,
+            "df.ack/status": if errors.is_empty() { "ok" } else { "error" },
+            "df.ack/errors": errors,
+        });
+        responses.push(Outgoing::new("df.ack".to_string(), ack));
+    }
+
+    received.lock().unwrap().extend(requests);
+
+    responses
+        .iter_mut()
+        .map(|outgoing| Message::Text(outgoing.text().to_string()))
+        .collect()
+}
+
+#[test]
+fn round_trip() {
+    let mut runtime = Runtime::new().unwrap();
+    let received = Arc::new(Mutex::new(Vec::new()));
+
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server_received = received.clone();
+    let server = listener
+        .incoming()
+        .into_future()
+        .map_err(|(err, _incoming)| panic!("{:?}", err))
+        .and_then(|(socket, _incoming)| {
+            accept_async(socket.expect("no connection")).map_err(|err| panic!("{:?}", err))
+        })
+        .and_then(move |socket| {
+            let (sink, stream) = socket.split();
+
+            stream
+                .filter_map(|message| match message {
+                    Message::Text(text) => Some(text),
+                    _ => None,
+                })
+                .map(move |text| stream::iter_ok(respond(&text, &server_received)))
+                .flatten()
+                .forward(sink)
+                .map(|_| ())
+                .map_err(|err| panic!("{:?}", err))
+        });
+
+    runtime.spawn(server);
+
+    let client = runtime
+        .block_on(Client::connect(&format!("ws://{}", address)))
+        .unwrap();
+
+    let attribute = CreateAttribute {
+        name: "person/name".to_string(),
+        semantics: AttributeSemantics::Raw,
+        retention: None,
+        value_type: None,
+        append_only: false,
+        metadata: Default::default(),
+    };
+
+    let register = Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, "person/name".to_string(), 1),
+        }],
+        publish: vec!["names".to_string()],
+        conflict_policy: Default::default(),
+        replicate: Vec::new(),
+        distinct: Vec::new(),
+        metadata: Default::default(),
+    };
+
+    let tx_data = vec![TxData(
+        1,
+        1,
+        "person/name".to_string(),
+        String("Dipper".to_string()),
+    )];
+
+    runtime
+        .block_on(client.create_attribute(attribute.clone()))
+        .unwrap();
+    runtime.block_on(client.register(register.clone())).unwrap();
+    runtime.block_on(client.transact(tx_data.clone())).unwrap();
+
+    let error = runtime
+        .block_on(client.transact(vec![TxData(1, 1, "missing".to_string(), Eid(2))]))
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/not-found");
+    assert_eq!(error.message, "Attribute missing does not exist.");
+
+    let (results, _stream) = runtime
+        .block_on(client.interest("names").into_future())
+        .map_err(|(err, _stream)| err)
+        .unwrap();
+    assert_eq!(
+        results,
+        Some((
+            vec![Eid(1), String("Dipper".to_string())],
+            Time::default(),
+            1
+        ))
+    );
+
+    runtime.block_on(client.flush()).unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 6);
+    assert_eq!(received[0], Request::CreateAttribute(attribute));
+    assert_eq!(received[1], Request::Register(register));
+    assert_eq!(received[2], Request::Transact(tx_data));
+
+    match received[4] {
+        Request::Interest(ref interest) => assert_eq!(interest.name, "names"),
+        ref other => panic!("expected an interest, got {:?}", other),
+    }
+
+    assert_eq!(received[5], Request::Flush);
+}
@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use declarative_dataflow::server::durable::{journaled_by, Durable};
+use declarative_dataflow::server::Interest;
+use declarative_dataflow::Value::{Eid, Number};
+
+fn fresh_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn redeliver_until_acknowledged() {
+    let dir = fresh_dir("declarative-dataflow-durable-redeliver");
+    let now = SystemTime::now();
+
+    {
+        let mut durable = Durable::open(&dir, 100, None).unwrap();
+
+        assert_eq!(durable.subscribe("billing", "ages", now).unwrap(), vec![]);
+
+        let journaled = durable
+            .append("ages", &[(vec![Eid(100), Number(1)], 1, 1)], now)
+            .unwrap();
+        assert_eq!(journaled.len(), 1);
+        assert_eq!(journaled[0].0, "billing");
+        assert_eq!(journaled[0].1.seq, 0);
+
+        durable
+            .append("ages", &[(vec![Eid(100), Number(2)], 2, 1)], now)
+            .unwrap();
+
+        // other relations are not journaled for the consumer
+        assert!(durable
+            .append("names", &[(vec![Eid(100), Number(3)], 3, 1)], now)
+            .unwrap()
+            .is_empty());
+    }
+
+    // unacknowledged batches survive a restart
+    let mut durable = Durable::open(&dir, 100, None).unwrap();
+    let batches = durable.subscribe("billing", "ages", now).unwrap();
+
+    assert_eq!(
+        batches.iter().map(|batch| batch.seq).collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert_eq!(batches[1].results, vec![(vec![Eid(100), Number(2)], 2, 1)]);
+
+    durable.acknowledge("billing", 0).unwrap();
+
+    // sequence numbers continue where they left off
+    let journaled = durable
+        .append("ages", &[(vec![Eid(200), Number(5)], 3, 1)], now)
+        .unwrap();
+    assert_eq!(journaled[0].1.seq, 2);
+
+    drop(durable);
+
+    let mut durable = Durable::open(&dir, 100, None).unwrap();
+    let batches = durable.subscribe("billing", "ages", now).unwrap();
+
+    assert_eq!(
+        batches.iter().map(|batch| batch.seq).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(durable.lost("billing"), 0);
+
+    assert!(durable.acknowledge("unknown", 0).is_err());
+}
+
+#[test]
+fn bounded_retention() {
+    let dir = fresh_dir("declarative-dataflow-durable-bounded");
+    let now = SystemTime::now();
+
+    let mut durable = Durable::open(&dir, 2, Some(Duration::from_secs(60))).unwrap();
+    durable.subscribe("billing", "ages", now).unwrap();
+
+    for time in 0..3 {
+        durable
+            .append(
+                "ages",
+                &[(vec![Eid(100), Number(time)], time as u64, 1)],
+                now,
+            )
+            .unwrap();
+    }
+
+    // the oldest batch exceeded the limit
+    assert_eq!(durable.lost("billing"), 1);
+
+    let batches = durable.subscribe("billing", "ages", now).unwrap();
+    assert_eq!(
+        batches.iter().map(|batch| batch.seq).collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    // the remaining ones expire eventually
+    let later = now + Duration::from_secs(120);
+    assert!(durable
+        .subscribe("billing", "ages", later)
+        .unwrap()
+        .is_empty());
+    assert_eq!(durable.lost("billing"), 3);
+}
+
+#[test]
+fn invalid_subscriptions() {
+    let dir = fresh_dir("declarative-dataflow-durable-invalid");
+    let now = SystemTime::now();
+
+    let mut durable = Durable::open(&dir, 100, None).unwrap();
+
+    // ids end up in file names
+    assert!(durable.subscribe("../billing", "ages", now).is_err());
+    assert!(durable.subscribe("", "ages", now).is_err());
+
+    durable.subscribe("billing", "ages", now).unwrap();
+
+    // each consumer is subscribed to a single relation
+    let error = durable.subscribe("billing", "names", now).unwrap_err();
+    assert_eq!(error.category, "df.error.category/conflict");
+}
+
+#[test]
+fn assigned_to_a_stable_worker() {
+    for peers in 1..8 {
+        let worker = journaled_by("billing", peers);
+
+        assert!(worker < peers);
+        assert_eq!(worker, journaled_by("billing", peers));
+    }
+
+    assert_eq!(journaled_by("billing", 1), 0);
+}
+
+#[test]
+fn recover_and_resume() {
+    let dir = fresh_dir("declarative-dataflow-durable-recover");
+    let now = SystemTime::now();
+
+    let interest = Interest {
+        name: "ages".to_string(),
+        as_of: None,
+        bindings: Vec::new(),
+        specialize: false,
+        restrict: None,
+        project: None,
+        order_by: Vec::new(),
+        window: None,
+        delivery: Default::default(),
+        consumer: Some("billing".to_string()),
+    };
+
+    {
+        let mut durable = Durable::open(&dir, 100, None).unwrap();
+
+        durable.subscribe("billing", "ages", now).unwrap();
+        durable.record_interest("billing", &interest).unwrap();
+
+        durable
+            .append(
+                "ages",
+                &[
+                    (vec![Eid(100), Number(1)], 1, 1),
+                    (vec![Eid(200), Number(2)], 1, 1),
+                ],
+                now,
+            )
+            .unwrap();
+        durable.acknowledge("billing", 0).unwrap();
+    }
+
+    // other workers don't recover the consumer
+    let mut other = Durable::open(&dir, 100, None).unwrap();
+    let worker = journaled_by("billing", 2);
+    assert!(other.recover(1 - worker, 2).unwrap().is_empty());
+
+    let mut durable = Durable::open(&dir, 100, None).unwrap();
+    assert_eq!(durable.recover(worker, 2).unwrap(), vec![interest]);
+
+    // only the difference to what was journaled before is recorded
+    let batch = durable
+        .append_to(
+            "billing",
+            "ages",
+            &[
+                (vec![Eid(100), Number(1)], 5, 1),
+                (vec![Eid(300), Number(3)], 5, 1),
+            ],
+            now,
+        )
+        .unwrap()
+        .unwrap();
+    durable.sync().unwrap();
+
+    assert_eq!(batch.seq, 1);
+    assert_eq!(
+        batch.results,
+        vec![
+            (vec![Eid(200), Number(2)], 5, -1),
+            (vec![Eid(300), Number(3)], 5, 1),
+        ]
+    );
+
+    // later batches are journaled as they are
+    let batch = durable
+        .append_to(
+            "billing",
+            "ages",
+            &[(vec![Eid(300), Number(3)], 6, -1)],
+            now,
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(batch.results, vec![(vec![Eid(300), Number(3)], 6, -1)]);
+}
+
+#[test]
+fn compaction_keeps_state() {
+    let dir = fresh_dir("declarative-dataflow-durable-compaction");
+    let now = SystemTime::now();
+
+    {
+        let mut durable = Durable::open(&dir, 1000, None).unwrap();
+        durable.subscribe("billing", "ages", now).unwrap();
+
+        for time in 0..200 {
+            let journaled = durable
+                .append(
+                    "ages",
+                    &[(vec![Eid(time as u64), Number(time)], time as u64, 1)],
+                    now,
+                )
+                .unwrap();
+
+            durable.acknowledge("billing", journaled[0].1.seq).unwrap();
+        }
+    }
+
+    // the journal has been compacted
+    let journal = std::fs::read_to_string(dir.join("billing.jsonl")).unwrap();
+    assert!(journal.lines().count() < 2 * 200);
+
+    let mut durable = Durable::open(&dir, 1000, None).unwrap();
+    durable.recover(journaled_by("billing", 1), 1).unwrap();
+
+    // acknowledged results are still known after compaction
+    let batch = durable
+        .append_to(
+            "billing",
+            "ages",
+            &(0..200)
+                .map(|time| (vec![Eid(time as u64), Number(time)], 300, 1))
+                .collect::<Vec<_>>(),
+            now,
+        )
+        .unwrap();
+    assert_eq!(batch, None);
+}
@@ -0,0 +1,98 @@
+use declarative_dataflow::server::encoding::{Encoding, Hello, Incoming, Outgoing, Payload};
+use declarative_dataflow::server::Request;
+use declarative_dataflow::Value;
+
+#[test]
+fn hello() {
+    match Encoding::Json.decode(br#"{"hello": {"encoding": "cbor"}}"#) {
+        Incoming::Hello(Ok(hello)) => assert_eq!(
+            hello,
+            Hello {
+                encoding: Some(Encoding::Cbor),
+                read_only: false,
+            }
+        ),
+        _ => panic!("expected a hello"),
+    }
+
+    match Encoding::Json.decode(br#"{"hello": {"encoding": "xml"}}"#) {
+        Incoming::Hello(Err(error)) => {
+            assert_eq!(error.category, "df.error.category/unsupported")
+        }
+        _ => panic!("expected a failed hello"),
+    }
+
+    match Encoding::Json.decode(br#"{"hello": {"read_only": true}}"#) {
+        Incoming::Hello(Ok(hello)) => assert_eq!(
+            hello,
+            Hello {
+                encoding: None,
+                read_only: true,
+            }
+        ),
+        _ => panic!("expected a hello"),
+    }
+
+    match Encoding::Json.decode(br#"{"hello": {}}"#) {
+        Incoming::Hello(Err(error)) => assert_eq!(error.category, "df.error.category/incorrect"),
+        _ => panic!("expected a failed hello"),
+    }
+}
+
+#[test]
+fn cbor_commands() {
+    let command = serde_json::json!({ "id": 3, "requests": ["Flush", "Status"] });
+    let bytes = serde_cbor::to_vec(&command).unwrap();
+
+    match Encoding::Cbor.decode(&bytes) {
+        Incoming::Command(id, Ok(requests)) => {
+            assert_eq!(id, Some(3));
+            assert_eq!(requests, vec![Request::Flush, Request::Status]);
+        }
+        _ => panic!("expected a command"),
+    }
+
+    // JSON is not valid CBOR
+    match Encoding::Cbor.decode(br#"["Flush"]"#) {
+        Incoming::Command(None, Err(error)) => {
+            assert_eq!(error.category, "df.error.category/incorrect")
+        }
+        _ => panic!("expected a malformed command"),
+    }
+}
+
+#[test]
+fn outgoing() {
+    let payload = serde_json::json!([[[1, 2], 0, 1]]);
+    let mut outgoing = Outgoing::new("q".to_string(), payload.clone());
+
+    assert_eq!(outgoing.text(), r#"["q",[[[1,2],0,1]]]"#);
+
+    let decoded: (String, serde_json::Value) =
+        serde_cbor::from_slice(outgoing.encoded(Encoding::Cbor)).unwrap();
+
+    assert_eq!(decoded, ("q".to_string(), payload));
+}
+
+#[test]
+fn outgoing_results() {
+    let results = vec![(
+        vec![Value::Eid(1), Value::String("Dipper".to_string())],
+        0,
+        1,
+    )];
+    let transcoded = serde_json::to_value(&results).unwrap();
+
+    // results are serialized directly, but just like any other payload
+    let mut outgoing = Outgoing::new("q".to_string(), Payload::Results(results));
+    let mut reference = Outgoing::new("q".to_string(), transcoded);
+
+    assert_eq!(outgoing.text(), reference.text());
+
+    let decoded: (String, serde_json::Value) =
+        serde_cbor::from_slice(outgoing.encoded(Encoding::Cbor)).unwrap();
+    let expected: (String, serde_json::Value) =
+        serde_cbor::from_slice(reference.encoded(Encoding::Cbor)).unwrap();
+
+    assert_eq!(decoded, expected);
+}
@@ -0,0 +1,263 @@
+use declarative_dataflow::harness::Harness;
+use declarative_dataflow::plan::{Aggregate, AggregationFn, Antijoin, Entities, RangeScan, Union};
+use declarative_dataflow::{AttributeSemantics, Plan, TxData, Value};
+use Value::{Aid, Bool, Eid, Number, String};
+
+#[test]
+fn antijoin_per_epoch() {
+    // [:find ?e ?n :where [?e :name ?n] (not [?e :banned true])]
+    let (e, n) = (1, 2);
+    let plan = Plan::Antijoin(Antijoin {
+        variables: vec![e],
+        left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+        right_plan: Box::new(Plan::MatchAV(e, ":banned".to_string(), Bool(true))),
+    });
+
+    let name = |e, n: &str| TxData(1, e, ":name".to_string(), String(n.to_string()));
+
+    let epochs = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .attribute(":banned", AttributeSemantics::Raw)
+        .update(0, name(1, "Dipper"))
+        .update(0, name(2, "Mabel"))
+        .update(1, TxData(1, 2, ":banned".to_string(), Bool(true)))
+        .update(2, TxData(-1, 2, ":banned".to_string(), Bool(true)))
+        // a ban and its lifting within the same epoch cancel out
+        .update(3, TxData(1, 1, ":banned".to_string(), Bool(true)))
+        .update(3, TxData(-1, 1, ":banned".to_string(), Bool(true)))
+        .run(&plan)
+        .unwrap();
+
+    let mabel = vec![Eid(2), String("Mabel".to_string())];
+
+    assert_eq!(epochs.keys().cloned().collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(
+        epochs[&0],
+        vec![
+            (vec![Eid(1), String("Dipper".to_string())], 1),
+            (mabel.clone(), 1)
+        ]
+    );
+    assert_eq!(epochs[&1], vec![(mabel.clone(), -1)]);
+    assert_eq!(epochs[&2], vec![(mabel, 1)]);
+}
+
+#[test]
+fn aggregate_per_epoch() {
+    // [:find ?e (count ?amount) :where [?e :amount ?amount]]
+    let (e, amount) = (1, 2);
+    let plan = Plan::Aggregate(Aggregate {
+        variables: vec![e, amount],
+        plan: Box::new(Plan::MatchA(e, ":amount".to_string(), amount)),
+        aggregation_fns: vec![AggregationFn::COUNT],
+        key_symbols: vec![e],
+        aggregation_symbols: vec![amount],
+        with_symbols: vec![],
+    });
+
+    let amount = |diff, e, x| TxData(diff, e, ":amount".to_string(), Number(x));
+
+    let epochs = Harness::new()
+        .attribute(":amount", AttributeSemantics::CardinalityMany)
+        .update(1, amount(1, 100, 5))
+        .update(1, amount(1, 100, 10))
+        .update(2, amount(1, 100, 15))
+        .update(2, amount(1, 200, 5))
+        .update(3, amount(-1, 100, 5))
+        .run(&plan)
+        .unwrap();
+
+    assert_eq!(epochs[&1], vec![(vec![Eid(100), Number(2)], 1)]);
+    assert_eq!(
+        epochs[&2],
+        vec![
+            (vec![Eid(100), Number(2)], -1),
+            (vec![Eid(100), Number(3)], 1),
+            (vec![Eid(200), Number(1)], 1),
+        ]
+    );
+    assert_eq!(
+        epochs[&3],
+        vec![
+            (vec![Eid(100), Number(2)], 1),
+            (vec![Eid(100), Number(3)], -1),
+        ]
+    );
+}
+
+#[test]
+fn entity_changes_per_epoch() {
+    let datom = |diff, e, a: &str, v| TxData(diff, e, a.to_string(), v);
+    let harness = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .attribute(":age", AttributeSemantics::Raw)
+        .attribute(":species", AttributeSemantics::Raw)
+        .update(0, datom(1, 1, ":name", String("Dipper".to_string())))
+        .update(0, datom(1, 1, ":age", Number(12)))
+        .update(0, datom(1, 2, ":name", String("Waddles".to_string())))
+        .update(0, datom(1, 2, ":species", String("pig".to_string())))
+        .update(1, datom(-1, 1, ":age", Number(12)))
+        .update(1, datom(1, 1, ":age", Number(13)))
+        .update(2, datom(1, 2, ":age", Number(3)));
+
+    let (e, a, v) = (0, 1, 2);
+    let change = |e, a: &str, v, diff| (vec![Eid(e), Aid(a.to_string()), v], diff);
+
+    // by id
+    let plan = Plan::Entities(Entities {
+        variables: vec![e, a, v],
+        eids: vec![1],
+        plan: None,
+    });
+
+    let epochs = harness.run(&plan).unwrap();
+
+    assert_eq!(epochs.keys().cloned().collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(
+        epochs[&0],
+        vec![
+            change(1, ":age", Number(12), 1),
+            change(1, ":name", String("Dipper".to_string()), 1),
+        ]
+    );
+    assert_eq!(
+        epochs[&1],
+        vec![
+            change(1, ":age", Number(12), -1),
+            change(1, ":age", Number(13), 1),
+        ]
+    );
+
+    // by predicate, e.g. all pigs
+    let plan = Plan::Entities(Entities {
+        variables: vec![e, a, v],
+        eids: vec![],
+        plan: Some(Box::new(Plan::MatchAV(
+            e,
+            ":species".to_string(),
+            String("pig".to_string()),
+        ))),
+    });
+
+    let epochs = harness.run(&plan).unwrap();
+
+    assert_eq!(epochs.keys().cloned().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(
+        epochs[&0],
+        vec![
+            change(2, ":name", String("Waddles".to_string()), 1),
+            change(2, ":species", String("pig".to_string()), 1),
+        ]
+    );
+    assert_eq!(epochs[&2], vec![change(2, ":age", Number(3), 1)]);
+
+    // both, i.e. only pigs among the listed ids
+    let plan = Plan::Entities(Entities {
+        variables: vec![e, a, v],
+        eids: vec![1, 2],
+        plan: Some(Box::new(Plan::MatchAV(
+            e,
+            ":species".to_string(),
+            String("pig".to_string()),
+        ))),
+    });
+
+    let epochs = harness.run(&plan).unwrap();
+
+    assert_eq!(epochs.keys().cloned().collect::<Vec<_>>(), vec![0, 2]);
+    assert_eq!(epochs[&2], vec![change(2, ":age", Number(3), 1)]);
+
+    let plan = Plan::Entities(Entities {
+        variables: vec![e, a, v],
+        eids: vec![1],
+        plan: Some(Box::new(Plan::MatchAV(
+            e,
+            ":species".to_string(),
+            String("pig".to_string()),
+        ))),
+    });
+
+    assert!(harness.run(&plan).unwrap().is_empty());
+}
+
+#[test]
+fn missing_attributes() {
+    let plan = Plan::MatchA(1, ":name".to_string(), 2);
+
+    // attributes can't be created twice
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&plan)
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/conflict");
+
+    // updates can only go to attributes that exist
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .update(0, TxData(1, 1, ":age".to_string(), Number(12)))
+        .run(&plan)
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/not-found");
+}
+
+#[test]
+fn implementation_errors() {
+    // plans referring to attributes that don't exist are rejected
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&Plan::MatchA(1, ":age".to_string(), 2))
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/not-found");
+
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&Plan::RangeScan(RangeScan {
+            variables: vec![1, 2],
+            attribute: ":age".to_string(),
+            lower: Some(Number(18)),
+            upper: None,
+            upper_exclusive: false,
+        }))
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/not-found");
+
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&Plan::MatchATx(1, ":age".to_string(), 2, 3))
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/not-found");
+    assert_eq!(error.message, "Attribute :age does not exist.");
+
+    // as are malformed ones, e.g. unions of sources binding
+    // different symbols without a fill value
+    let plan = Plan::Union(Union {
+        variables: vec![1, 2],
+        plans: vec![
+            Plan::MatchA(1, ":name".to_string(), 2),
+            Plan::MatchAV(1, ":name".to_string(), String("Mabel".to_string())),
+        ],
+        fill: None,
+    });
+
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&plan)
+        .unwrap_err();
+    assert_eq!(error.category, "df.error.category/incorrect");
+    assert!(error.message.contains("[2]"));
+
+    // the same union implements fine once a fill value is given
+    let plan = match plan {
+        Plan::Union(union) => Plan::Union(Union {
+            fill: Some(Bool(false)),
+            ..union
+        }),
+        _ => unreachable!(),
+    };
+
+    assert!(Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .run(&plan)
+        .is_ok());
+}
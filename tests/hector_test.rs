@@ -11,9 +11,10 @@ use declarative_dataflow::binding::BinaryPredicate::LT;
 use declarative_dataflow::binding::{
     AttributeBinding, BinaryPredicateBinding, Binding, ConstantBinding,
 };
-use declarative_dataflow::plan::Hector;
-use declarative_dataflow::server::Server;
-use declarative_dataflow::{Aid, AttributeSemantics, Plan, Rule, TxData, Value};
+use declarative_dataflow::harness::Harness;
+use declarative_dataflow::plan::{Filter, Hector, Join, Predicate, Project, Union};
+use declarative_dataflow::server::{Config, Register, Server};
+use declarative_dataflow::{Aid, AttributeSemantics, Implementable, Plan, Rule, TxData, Value};
 use Binding::{Attribute, BinaryPredicate, Constant};
 use Value::{Eid, Number, String};
 
@@ -111,6 +112,41 @@ fn run_hector_cases() {
                 ]],
             }
         },
+        {
+            let (e, a, n) = (1, 2, 3);
+            Case {
+                description: "[?e :age ?a] [?e :name ?n] (constant ?a 12)",
+                plan: Hector {
+                    variables: vec![e, a, n],
+                    bindings: vec![
+                        Attribute(AttributeBinding {
+                            symbols: (e, n),
+                            source_attribute: ":name".to_string(),
+                        }),
+                        Attribute(AttributeBinding {
+                            symbols: (e, a),
+                            source_attribute: ":age".to_string(),
+                        }),
+                        Constant(ConstantBinding {
+                            symbol: a,
+                            value: Number(12),
+                        }),
+                    ],
+                },
+                transactions: vec![vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 1, ":age".to_string(), Number(12)),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 2, ":age".to_string(), Number(13)),
+                    TxData(1, 3, ":name".to_string(), String("Soos".to_string())),
+                ]],
+                expectations: vec![vec![(
+                    vec![Eid(1), Number(12), String("Dipper".to_string())],
+                    0,
+                    1,
+                )]],
+            }
+        },
         {
             let (a, b, c) = (1, 2, 3);
             Case {
@@ -259,6 +295,36 @@ fn run_hector_cases() {
                 1,
             )]],
         },
+        {
+            let (a, b, c, d) = (1, 2, 3, 4);
+            Case {
+                description: "[?a :x ?b] [?b :y ?c] [?c :z ?d] (in an order that doesn't bind)",
+                plan: Hector {
+                    variables: vec![a, d, b, c],
+                    bindings: vec![
+                        Attribute(AttributeBinding {
+                            symbols: (a, b),
+                            source_attribute: ":x".to_string(),
+                        }),
+                        Attribute(AttributeBinding {
+                            symbols: (b, c),
+                            source_attribute: ":y".to_string(),
+                        }),
+                        Attribute(AttributeBinding {
+                            symbols: (c, d),
+                            source_attribute: ":z".to_string(),
+                        }),
+                    ],
+                },
+                transactions: vec![vec![
+                    TxData(1, 100, ":x".to_string(), Eid(200)),
+                    TxData(1, 200, ":y".to_string(), Eid(300)),
+                    TxData(1, 300, ":z".to_string(), Number(1)),
+                    TxData(1, 400, ":x".to_string(), Eid(500)),
+                ]],
+                expectations: vec![vec![(vec![Eid(100), Number(1), Eid(200), Eid(300)], 0, 1)]],
+            }
+        },
     ];
 
     for case in cases.drain(..) {
@@ -339,3 +405,845 @@ fn run_hector_cases() {
         .unwrap();
     }
 }
+
+#[test]
+fn derived_relation_bindings() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [?c parent ?p] [?p :name ?n]
+        let plan = Plan::Hector(Hector {
+            variables: vec![0, 1, 2],
+            bindings: vec![
+                Attribute(AttributeBinding {
+                    symbols: (0, 1),
+                    source_attribute: "parent".to_string(),
+                }),
+                Attribute(AttributeBinding {
+                    symbols: (1, 2),
+                    source_attribute: ":name".to_string(),
+                }),
+            ],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":parent", ":name"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .register(Register {
+                    rules: vec![Rule {
+                        name: "parent".to_string(),
+                        plan: Plan::MatchA(0, ":parent".to_string(), 1),
+                    }],
+                    publish: vec!["parent".to_string()],
+                    conflict_policy: Default::default(),
+                    replicate: Vec::new(),
+                    distinct: Vec::new(),
+                    metadata: Default::default(),
+                })
+                .unwrap();
+
+            server.interest("parent", scope).unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "hector".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":parent".to_string(), Eid(10)),
+                    TxData(1, 2, ":parent".to_string(), Eid(20)),
+                    TxData(1, 10, ":name".to_string(), String("Stan".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), Eid(10), String("Stan".to_string())], 0, 1)]
+        );
+        assert_eq!(server.context.derived_indices.len(), 2);
+
+        // Later dataflows re-use the indices arranged by the first.
+        let (send_results, results) = channel();
+
+        // [?c parent ?p] [?c :name ?n]
+        let plan = Plan::Hector(Hector {
+            variables: vec![0, 1, 2],
+            bindings: vec![
+                Attribute(AttributeBinding {
+                    symbols: (0, 1),
+                    source_attribute: "parent".to_string(),
+                }),
+                Attribute(AttributeBinding {
+                    symbols: (0, 2),
+                    source_attribute: ":name".to_string(),
+                }),
+            ],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "hector_again".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    2,
+                    ":name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 2).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(server.context.derived_indices.len(), 2);
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(2), Eid(20), String("Dipper".to_string())], 1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn unpublished_relation_bindings() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [?c parent ?p] [?p :name ?n]
+        let hector = Hector {
+            variables: vec![0, 1, 2],
+            bindings: vec![
+                Attribute(AttributeBinding {
+                    symbols: (0, 1),
+                    source_attribute: "parent".to_string(),
+                }),
+                Attribute(AttributeBinding {
+                    symbols: (1, 2),
+                    source_attribute: ":name".to_string(),
+                }),
+            ],
+        };
+
+        assert_eq!(
+            hector.dependencies(),
+            vec![":name".to_string(), "parent".to_string()]
+        );
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":parent", ":name"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .register(Register {
+                    rules: vec![Rule {
+                        name: "parent".to_string(),
+                        plan: Plan::MatchA(0, ":parent".to_string(), 1),
+                    }],
+                    publish: vec!["parent".to_string()],
+                    conflict_policy: Default::default(),
+                    replicate: Vec::new(),
+                    distinct: Vec::new(),
+                    metadata: Default::default(),
+                })
+                .unwrap();
+
+            // nobody is interested in parent, it is implemented
+            // alongside the rule binding it
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "hector".to_string(),
+                        plan: Plan::Hector(hector),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        assert!(!server.context.arrangements.contains_key("parent"));
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":parent".to_string(), Eid(10)),
+                    TxData(1, 10, ":name".to_string(), String("Stan".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), Eid(10), String("Stan".to_string())], 0, 1)]
+        );
+
+        // [?c ancestor ?a] [?a :parent ?p]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "ancestor".to_string(),
+                    plan: Plan::Hector(Hector {
+                        variables: vec![0, 2],
+                        bindings: vec![
+                            Attribute(AttributeBinding {
+                                symbols: (0, 1),
+                                source_attribute: "ancestor".to_string(),
+                            }),
+                            Attribute(AttributeBinding {
+                                symbols: (1, 2),
+                                source_attribute: ":parent".to_string(),
+                            }),
+                        ],
+                    }),
+                }],
+                publish: vec!["ancestor".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            let error = server.interest("ancestor", scope).unwrap_err();
+            assert_eq!(error.category, "df.error.category/unsupported");
+        });
+    })
+    .unwrap();
+}
+
+#[test]
+fn constant_patterns() {
+    let (e, n, f) = (1, 2, 3);
+
+    let project = |variables: Vec<u32>, plan: Plan| {
+        Plan::Project(Project {
+            variables,
+            plan: Box::new(plan),
+        })
+    };
+
+    let cases: Vec<(&str, Plan, Vec<Vec<Value>>)> = vec![
+        (
+            "[1 :name ?n]",
+            project(vec![n], Plan::MatchEA(1, ":name".to_string(), n)),
+            vec![vec![String("Dipper".to_string())]],
+        ),
+        (
+            "[?e :age 12]",
+            project(vec![e], Plan::MatchAV(e, ":age".to_string(), Number(12))),
+            vec![vec![Eid(1)], vec![Eid(2)]],
+        ),
+        (
+            "[3 :friend ?f] [?f :name ?n]",
+            project(
+                vec![f, n],
+                Plan::Join(Join {
+                    variables: vec![f],
+                    left_plan: Box::new(Plan::MatchEA(3, ":friend".to_string(), f)),
+                    right_plan: Box::new(Plan::MatchA(f, ":name".to_string(), n)),
+                }),
+            ),
+            vec![vec![Eid(1), String("Dipper".to_string())]],
+        ),
+        (
+            "[?e :age 12] [?e :name ?n]",
+            project(
+                vec![e, n],
+                Plan::Join(Join {
+                    variables: vec![e],
+                    left_plan: Box::new(Plan::MatchAV(e, ":age".to_string(), Number(12))),
+                    right_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                }),
+            ),
+            vec![
+                vec![Eid(1), String("Dipper".to_string())],
+                vec![Eid(2), String("Mabel".to_string())],
+            ],
+        ),
+        (
+            "[1 :friend ?f] [?f :age 12]",
+            project(
+                vec![f],
+                Plan::Join(Join {
+                    variables: vec![f],
+                    left_plan: Box::new(Plan::MatchEA(1, ":friend".to_string(), f)),
+                    right_plan: Box::new(Plan::MatchAV(f, ":age".to_string(), Number(12))),
+                }),
+            ),
+            vec![vec![Eid(2)]],
+        ),
+        (
+            "[?e :name ?n] (constant ?e 3) (constant ?n 'Soos')",
+            Plan::Hector(Hector {
+                variables: vec![e, n],
+                bindings: vec![
+                    Attribute(AttributeBinding {
+                        symbols: (e, n),
+                        source_attribute: ":name".to_string(),
+                    }),
+                    Constant(ConstantBinding {
+                        symbol: e,
+                        value: Eid(3),
+                    }),
+                    Constant(ConstantBinding {
+                        symbol: n,
+                        value: String("Soos".to_string()),
+                    }),
+                ],
+            }),
+            vec![vec![Eid(3), String("Soos".to_string())]],
+        ),
+        (
+            "[?e :name ?n] (constant ?e 3) (constant ?n 'Dipper')",
+            Plan::Hector(Hector {
+                variables: vec![e, n],
+                bindings: vec![
+                    Attribute(AttributeBinding {
+                        symbols: (e, n),
+                        source_attribute: ":name".to_string(),
+                    }),
+                    Constant(ConstantBinding {
+                        symbol: e,
+                        value: Eid(3),
+                    }),
+                    Constant(ConstantBinding {
+                        symbol: n,
+                        value: String("Dipper".to_string()),
+                    }),
+                ],
+            }),
+            vec![],
+        ),
+    ];
+
+    for (description, plan, expected) in cases.into_iter() {
+        timely::execute(Configuration::Thread, move |worker| {
+            dbg!(description);
+
+            // Lowering constant patterns into bindings must yield the
+            // same results as implementing them directly.
+            for enable_optimizer in vec![false, true] {
+                let mut server = Server::<u64>::new(Config {
+                    enable_optimizer,
+                    ..Default::default()
+                });
+                let (send_results, results) = channel();
+
+                worker.dataflow::<u64, _, _>(|scope| {
+                    for name in &[":name", ":age", ":friend"] {
+                        server
+                            .context
+                            .internal
+                            .create_attribute(name, AttributeSemantics::Raw, scope)
+                            .unwrap();
+                    }
+
+                    server
+                        .test_single(
+                            scope,
+                            Rule {
+                                name: "constants".to_string(),
+                                plan: plan.clone(),
+                            },
+                        )
+                        .inspect(move |x| {
+                            send_results.send(x.clone()).unwrap();
+                        });
+                });
+
+                server
+                    .transact(
+                        vec![
+                            TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                            TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                            TxData(1, 3, ":name".to_string(), String("Soos".to_string())),
+                            TxData(1, 1, ":age".to_string(), Number(12)),
+                            TxData(1, 2, ":age".to_string(), Number(12)),
+                            TxData(1, 3, ":age".to_string(), Number(22)),
+                            TxData(1, 1, ":friend".to_string(), Eid(2)),
+                            TxData(1, 2, ":friend".to_string(), Eid(1)),
+                            TxData(1, 3, ":friend".to_string(), Eid(1)),
+                        ],
+                        0,
+                        0,
+                    )
+                    .unwrap();
+
+                server.advance_domain(None, 1).unwrap();
+                worker.step_while(|| server.is_any_outdated());
+
+                let mut tuples: Vec<Vec<Value>> = results
+                    .try_iter()
+                    .map(|(tuple, time, diff)| {
+                        assert_eq!((time, diff), (0, 1));
+                        tuple
+                    })
+                    .collect();
+
+                tuples.sort();
+
+                assert_eq!(tuples, expected, "optimizer: {}", enable_optimizer);
+            }
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn predicate_bindings() {
+    let (e, x, y) = (1, 2, 3);
+
+    let filter = |predicate: Predicate, a: &str| {
+        Plan::Project(Project {
+            variables: vec![e, x, y],
+            plan: Box::new(Plan::Filter(Filter {
+                variables: vec![x, y],
+                predicate,
+                plan: Box::new(Plan::Join(Join {
+                    variables: vec![e],
+                    left_plan: Box::new(Plan::MatchA(e, a.to_string(), x)),
+                    right_plan: Box::new(Plan::MatchA(e, a.to_string(), y)),
+                })),
+                constants: vec![None, None],
+            })),
+        })
+    };
+
+    let cases: Vec<(&str, Plan, Vec<Vec<Value>>)> = vec![
+        (
+            "[?e :num ?x] [?e :num ?y] (< ?x ?y)",
+            filter(Predicate::LT, ":num"),
+            vec![
+                vec![Eid(100), Number(1), Number(2)],
+                vec![Eid(100), Number(1), Number(3)],
+                vec![Eid(100), Number(2), Number(3)],
+            ],
+        ),
+        (
+            "[?e :num ?x] [?e :num ?y] (>= ?x ?y)",
+            filter(Predicate::GTE, ":num"),
+            vec![
+                vec![Eid(100), Number(1), Number(1)],
+                vec![Eid(100), Number(2), Number(1)],
+                vec![Eid(100), Number(2), Number(2)],
+                vec![Eid(100), Number(3), Number(1)],
+                vec![Eid(100), Number(3), Number(2)],
+                vec![Eid(100), Number(3), Number(3)],
+            ],
+        ),
+        (
+            "[?e :name ?x] [?e :name ?y] (starts-with? ?x ?y)",
+            filter(Predicate::STARTS_WITH, ":name"),
+            vec![
+                vec![
+                    Eid(200),
+                    String("Dipper".to_string()),
+                    String("Dip".to_string()),
+                ],
+                vec![
+                    Eid(200),
+                    String("Dipper".to_string()),
+                    String("Dipper".to_string()),
+                ],
+                vec![
+                    Eid(200),
+                    String("Dip".to_string()),
+                    String("Dip".to_string()),
+                ],
+            ],
+        ),
+    ];
+
+    for (description, plan, mut expected) in cases.into_iter() {
+        expected.sort();
+
+        timely::execute(Configuration::Thread, move |worker| {
+            dbg!(description);
+
+            // Lowering predicates on two symbols into bindings must
+            // yield the same results as implementing them directly.
+            for enable_optimizer in vec![false, true] {
+                let mut server = Server::<u64>::new(Config {
+                    enable_optimizer,
+                    ..Default::default()
+                });
+                let (send_results, results) = channel();
+
+                worker.dataflow::<u64, _, _>(|scope| {
+                    for name in &[":num", ":name"] {
+                        server
+                            .context
+                            .internal
+                            .create_attribute(name, AttributeSemantics::Raw, scope)
+                            .unwrap();
+                    }
+
+                    server
+                        .test_single(
+                            scope,
+                            Rule {
+                                name: "predicates".to_string(),
+                                plan: plan.clone(),
+                            },
+                        )
+                        .inspect(move |x| {
+                            send_results.send(x.clone()).unwrap();
+                        });
+                });
+
+                server
+                    .transact(
+                        vec![
+                            TxData(1, 100, ":num".to_string(), Number(1)),
+                            TxData(1, 100, ":num".to_string(), Number(2)),
+                            TxData(1, 100, ":num".to_string(), Number(3)),
+                            TxData(1, 200, ":name".to_string(), String("Dipper".to_string())),
+                            TxData(1, 200, ":name".to_string(), String("Dip".to_string())),
+                        ],
+                        0,
+                        0,
+                    )
+                    .unwrap();
+
+                server.advance_domain(None, 1).unwrap();
+                worker.step_while(|| server.is_any_outdated());
+
+                let mut tuples: Vec<Vec<Value>> = results
+                    .try_iter()
+                    .map(|(tuple, time, diff)| {
+                        assert_eq!((time, diff), (0, 1));
+                        tuple
+                    })
+                    .collect();
+
+                tuples.sort();
+
+                assert_eq!(tuples, expected, "optimizer: {}", enable_optimizer);
+                assert!(server.optimizer_fallback("predicates").is_none());
+            }
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn optimizer_fallback() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_optimizer: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":name", ":nickname"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .register(Register {
+                    rules: vec![
+                        Rule {
+                            name: "names".to_string(),
+                            plan: Plan::MatchA(0, ":name".to_string(), 1),
+                        },
+                        Rule {
+                            name: "names_or_nicknames".to_string(),
+                            plan: Plan::Union(Union {
+                                variables: vec![0, 1],
+                                plans: vec![
+                                    Plan::MatchA(0, ":name".to_string(), 1),
+                                    Plan::MatchA(0, ":nickname".to_string(), 1),
+                                ],
+                                fill: None,
+                            }),
+                        },
+                    ],
+                    publish: vec!["names".to_string(), "names_or_nicknames".to_string()],
+                    conflict_policy: Default::default(),
+                    replicate: Vec::new(),
+                    distinct: Vec::new(),
+                    metadata: Default::default(),
+                })
+                .unwrap();
+
+            server.interest("names", scope).unwrap();
+
+            server
+                .interest("names_or_nicknames", scope)
+                .unwrap()
+                .import_named(scope, "names_or_nicknames")
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        // Unions can't be unified by Hector, so the relation is
+        // implemented by the naive planner instead.
+        assert!(server.optimizer_fallback("names").is_none());
+        assert_eq!(
+            server
+                .optimizer_fallback("names_or_nicknames")
+                .unwrap()
+                .category,
+            "df.error.category/unsupported"
+        );
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 1, ":nickname".to_string(), String("Dip".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("Dip".to_string())], 1),
+                (vec![Eid(1), String("Dipper".to_string())], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn bounded_batches() {
+    timely::execute(Configuration::Thread, move |worker| {
+        // every operator works off a single prefix at a time
+        let mut server = Server::<u64>::new(Config {
+            hector_batch_limit: Some(1),
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        // [?a :edge ?b] [?b :edge ?c] [?a :edge ?c]
+        let (a, b, c) = (1, 2, 3);
+        let plan = Plan::Hector(Hector {
+            variables: vec![a, b, c],
+            bindings: vec![
+                Attribute(AttributeBinding {
+                    symbols: (a, b),
+                    source_attribute: ":edge".to_string(),
+                }),
+                Attribute(AttributeBinding {
+                    symbols: (b, c),
+                    source_attribute: ":edge".to_string(),
+                }),
+                Attribute(AttributeBinding {
+                    symbols: (a, c),
+                    source_attribute: ":edge".to_string(),
+                }),
+            ],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":edge", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "triangles".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        // a complete graph on six nodes, directed from lower to
+        // higher ids
+        let mut tx_data = Vec::new();
+        for from in 1..=6 {
+            for to in (from + 1)..=6 {
+                tx_data.push(TxData(1, from, ":edge".to_string(), Eid(to)));
+            }
+        }
+
+        server.transact(tx_data, 0, 0).unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        let mut expected = Vec::new();
+        for x in 1..=6 {
+            for y in (x + 1)..=6 {
+                for z in (y + 1)..=6 {
+                    expected.push((vec![Eid(x), Eid(y), Eid(z)], 1));
+                }
+            }
+        }
+
+        // throttling the operators doesn't change the results
+        assert_eq!(received, expected);
+    })
+    .unwrap();
+}
+
+#[test]
+fn underconstrained_variables() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        // [:find ?e ?n ?x :where [?e :name ?n] [(< ?n ?x)]]
+        let (e, n, x) = (1, 2, 3);
+        let plan = Plan::Hector(Hector {
+            variables: vec![e, n, x],
+            bindings: vec![
+                Attribute(AttributeBinding {
+                    symbols: (e, n),
+                    source_attribute: ":name".to_string(),
+                }),
+                BinaryPredicate(BinaryPredicateBinding {
+                    symbols: (n, x),
+                    predicate: LT,
+                }),
+            ],
+        });
+
+        let error = server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "unbound".to_string(),
+                    plan: plan.clone(),
+                }],
+                publish: vec!["unbound".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap_err();
+
+        assert_eq!(error.category, "df.error.category/incorrect");
+        assert!(error.message.contains("Variable 3 is underconstrained"));
+
+        // nothing is registered
+        assert!(server.context.rules.is_empty());
+
+        // rules bypassing registration fail to be implemented,
+        // rather than taking down the worker
+        server.context.rules.insert(
+            "unbound".to_string(),
+            Rule {
+                name: "unbound".to_string(),
+                plan,
+            },
+        );
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            let error = server.interest("unbound", scope).unwrap_err();
+            assert_eq!(error.category, "df.error.category/incorrect");
+        });
+    })
+    .unwrap();
+}
+
+#[test]
+fn disconnected_bindings() {
+    // [:find ?a ?b ?c ?d :where [?a :name ?b] [?c :age ?d]]
+    let plan = Plan::Hector(Hector {
+        variables: vec![0, 1, 2, 3],
+        bindings: vec![
+            Attribute(AttributeBinding {
+                symbols: (0, 1),
+                source_attribute: ":name".to_string(),
+            }),
+            Attribute(AttributeBinding {
+                symbols: (2, 3),
+                source_attribute: ":age".to_string(),
+            }),
+        ],
+    });
+
+    // fails to be implemented, rather than taking down the worker
+    let error = Harness::new()
+        .attribute(":name", AttributeSemantics::Raw)
+        .attribute(":age", AttributeSemantics::Raw)
+        .run(&plan)
+        .unwrap_err();
+
+    assert_eq!(error.category, "df.error.category/incorrect");
+}
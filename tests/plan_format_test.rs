@@ -0,0 +1,243 @@
+use declarative_dataflow::binding::{AttributeBinding, Binding};
+use declarative_dataflow::plan::{
+    content_id, decode_plan, encode_plan, Aggregate, AggregationFn, Antijoin, BroadcastJoin,
+    Filter, Function, Hector, Implementable, Join, Predicate, Project, Pull, PullLevel, RangeScan,
+    Transform, Union, PLAN_FORMAT_VERSION,
+};
+use declarative_dataflow::server::{parse_command, parse_requests, Request};
+use declarative_dataflow::{Plan, Value};
+use Value::{Number, String};
+
+fn all_plans() -> Vec<Plan> {
+    let (e, a, v) = (1, 2, 3);
+    let pattern = || Box::new(Plan::MatchA(e, ":name".to_string(), v));
+
+    vec![
+        Plan::Project(Project {
+            variables: vec![e],
+            plan: pattern(),
+        }),
+        Plan::Aggregate(Aggregate {
+            variables: vec![v],
+            plan: pattern(),
+            aggregation_fns: vec![AggregationFn::COUNT],
+            key_symbols: vec![],
+            aggregation_symbols: vec![v],
+            with_symbols: vec![e],
+        }),
+        Plan::Union(Union {
+            variables: vec![e, v],
+            plans: vec![*pattern(), Plan::MatchA(e, ":age".to_string(), a)],
+            fill: Some(Value::Bool(false)),
+        }),
+        Plan::Join(Join {
+            variables: vec![e],
+            left_plan: pattern(),
+            right_plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+        }),
+        Plan::BroadcastJoin(BroadcastJoin {
+            variables: vec![e],
+            left_plan: pattern(),
+            right_plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+        }),
+        Plan::Hector(Hector {
+            variables: vec![e, v],
+            bindings: vec![Binding::Attribute(AttributeBinding {
+                symbols: (e, v),
+                source_attribute: ":name".to_string(),
+            })],
+        }),
+        Plan::Antijoin(Antijoin {
+            variables: vec![e],
+            left_plan: pattern(),
+            right_plan: Box::new(Plan::MatchAV(e, ":banned".to_string(), Value::Bool(true))),
+        }),
+        Plan::Negate(pattern()),
+        Plan::Filter(Filter {
+            variables: vec![a],
+            predicate: Predicate::LTE,
+            plan: Box::new(Plan::MatchA(e, ":age".to_string(), a)),
+            constants: vec![Some(Number(18)), None],
+        }),
+        Plan::Transform(Transform {
+            variables: vec![v],
+            result_sym: a,
+            plan: pattern(),
+            function: Function::CONCAT,
+            constants: vec![Some(String("Hello ".to_string())), None],
+        }),
+        Plan::MatchA(e, ":name".to_string(), v),
+        Plan::MatchEA(100, ":name".to_string(), v),
+        Plan::MatchAV(e, ":name".to_string(), String("Dipper".to_string())),
+        Plan::MatchATx(e, ":name".to_string(), v, a),
+        Plan::RangeScan(RangeScan {
+            variables: vec![e, v],
+            attribute: ":age".to_string(),
+            lower: Some(Number(10)),
+            upper: None,
+            upper_exclusive: false,
+        }),
+        Plan::NameExpr(vec![e, v], "names".to_string()),
+        Plan::Pull(Pull {
+            variables: vec![],
+            paths: vec![PullLevel {
+                variables: vec![],
+                plan: pattern(),
+                pull_attributes: vec![":age".to_string()],
+                path_attributes: vec![],
+                mark_unresolved: true,
+                recursion: None,
+                envelope: false,
+            }],
+        }),
+        Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: pattern(),
+            pull_attributes: vec![":age".to_string()],
+            path_attributes: vec![":parent/child".to_string()],
+            mark_unresolved: false,
+            recursion: None,
+            envelope: false,
+        }),
+    ]
+}
+
+#[test]
+fn round_trip() {
+    for plan in all_plans() {
+        let encoded = encode_plan(&plan).unwrap();
+
+        assert_eq!(encoded["df.plan/version"], PLAN_FORMAT_VERSION);
+        assert_eq!(decode_plan(encoded).unwrap(), plan);
+    }
+}
+
+#[test]
+fn datafy_deterministic() {
+    let (e, n, a) = (1, 2, 3);
+    let join = |attribute: &str| {
+        Plan::Join(Join {
+            variables: vec![e],
+            left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            right_plan: Box::new(Plan::MatchA(e, attribute.to_string(), a)),
+        })
+    };
+
+    let data = join(":age").datafy();
+
+    // ids don't depend on how many plans were described before
+    assert_eq!(join(":age").datafy(), data);
+
+    // patterns and the join binding them are distinct entities
+    let mut eids: Vec<_> = data.iter().map(|(eid, _, _)| *eid).collect();
+    eids.sort();
+    eids.dedup();
+    assert_eq!(eids.len(), 3);
+
+    // identical stages share an entity, others don't
+    let other = join(":height").datafy();
+    assert_eq!(data[0], other[0]);
+    assert_ne!(data[1].0, other[1].0);
+    assert_ne!(data[2].0, other[2].0);
+
+    // ids are persisted, so they must not change between releases
+    assert_eq!(content_id("a"), 620_310_408_636_712_809);
+}
+
+#[test]
+fn round_trip_unversioned() {
+    for plan in all_plans() {
+        let bare = serde_json::to_value(&plan).unwrap();
+        assert_eq!(decode_plan(bare).unwrap(), plan);
+    }
+}
+
+#[test]
+fn unsupported_version() {
+    let encoded = serde_json::json!({
+        "df.plan/version": PLAN_FORMAT_VERSION + 1,
+        "df.plan/plan": {"MatchA": [1, ":age", 2]},
+    });
+
+    let error = decode_plan(encoded).unwrap_err();
+    assert_eq!(error.category, "df.error.category/unsupported");
+}
+
+#[test]
+fn parse_versioned_requests() {
+    let plan = Plan::MatchA(1, ":name".to_string(), 2);
+    let text = serde_json::json!([{
+        "Batch": [{
+            "Register": {
+                "rules": [{"name": "names", "plan": encode_plan(&plan).unwrap()}],
+                "publish": ["names"],
+            }
+        }]
+    }])
+    .to_string();
+
+    let mut requests = parse_requests(&text).unwrap();
+
+    match requests.pop() {
+        Some(Request::Batch(mut items)) => match items.pop() {
+            Some(Request::Register(register)) => assert_eq!(register.rules[0].plan, plan),
+            other => panic!("unexpected request {:?}", other),
+        },
+        other => panic!("unexpected request {:?}", other),
+    }
+}
+
+#[test]
+fn parse_tagged_commands() {
+    let plan = Plan::MatchA(1, ":name".to_string(), 2);
+    let text = serde_json::json!({
+        "id": 7,
+        "requests": [{
+            "Register": {
+                "rules": [{"name": "names", "plan": encode_plan(&plan).unwrap()}],
+                "publish": ["names"],
+            }
+        }, "Flush"]
+    })
+    .to_string();
+
+    let (id, requests) = parse_command(&text);
+    let mut requests = requests.unwrap();
+
+    assert_eq!(id, Some(7));
+    assert_eq!(requests.pop(), Some(Request::Flush));
+
+    match requests.pop() {
+        Some(Request::Register(register)) => assert_eq!(register.rules[0].plan, plan),
+        other => panic!("unexpected request {:?}", other),
+    }
+
+    // bare lists of requests carry no id
+    let (id, requests) = parse_command(r#"["Flush"]"#);
+    assert_eq!(id, None);
+    assert_eq!(requests.unwrap(), vec![Request::Flush]);
+
+    // ids are recovered from malformed commands
+    let (id, requests) = parse_command(r#"{"id": 8, "requests": ["Unknown"]}"#);
+    assert_eq!(id, Some(8));
+    assert!(requests.is_err());
+}
+
+#[test]
+fn read_only_requests() {
+    let requests = parse_requests(
+        r#"["Status", "ListRules", {"Release": "names"}, {"Batch": ["Flush", "Readers"]}]"#,
+    )
+    .unwrap();
+
+    assert!(requests.iter().all(Request::is_read_only));
+
+    let requests = parse_requests(
+        r#"[{"Transact": []}, "Shutdown", {"CloseInput": ":name"}, {"Batch": ["Flush", {"Transact": []}]}]"#,
+    )
+    .unwrap();
+
+    assert!(!requests.iter().any(Request::is_read_only));
+
+    assert!(!Request::Hold("names".to_string(), Default::default()).is_read_only());
+}
@@ -4,7 +4,7 @@ use std::time::Duration;
 
 use timely::Configuration;
 
-use declarative_dataflow::plan::{Pull, PullLevel};
+use declarative_dataflow::plan::{Pull, PullLevel, PullRecursion};
 use declarative_dataflow::server::Server;
 use declarative_dataflow::{AttributeSemantics, Plan, Rule, TxData, Value};
 use AttributeSemantics::Raw;
@@ -22,6 +22,9 @@ fn pull_level() {
             plan: Box::new(Plan::MatchAV(e, "admin?".to_string(), Bool(false))),
             pull_attributes: vec!["name".to_string(), "age".to_string()],
             path_attributes: vec![],
+            mark_unresolved: false,
+            recursion: None,
+            envelope: false,
         });
 
         worker.dataflow::<u64, _, _>(|scope| {
@@ -118,6 +121,9 @@ fn pull_children() {
             plan: Box::new(Plan::MatchA(parent, "parent/child".to_string(), child)),
             pull_attributes: vec!["name".to_string(), "age".to_string()],
             path_attributes: vec!["parent/child".to_string()],
+            mark_unresolved: false,
+            recursion: None,
+            envelope: false,
         });
 
         worker.dataflow::<u64, _, _>(|scope| {
@@ -225,6 +231,135 @@ fn pull_children() {
     .unwrap();
 }
 
+#[test]
+fn pull_unresolved_children() {
+    timely::execute(Configuration::Thread, |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let (parent, child) = (1, 2);
+        let plan = Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: Box::new(Plan::MatchA(parent, "parent/child".to_string(), child)),
+            pull_attributes: vec!["name".to_string()],
+            path_attributes: vec!["parent/child".to_string()],
+            mark_unresolved: true,
+            recursion: None,
+            envelope: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute("parent/child", Raw, scope)
+                .unwrap();
+            server
+                .context
+                .internal
+                .create_attribute("name", Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "pull_unresolved_children".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, "parent/child".to_string(), Eid(300)),
+                    TxData(1, 200, "parent/child".to_string(), Eid(400)),
+                    TxData(1, 300, "name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let unresolved = vec![
+            Eid(200),
+            Aid("parent/child".to_string()),
+            Eid(400),
+            Aid("df.pull/unresolved".to_string()),
+            Bool(true),
+        ];
+
+        let mut expected = HashSet::new();
+        expected.insert((
+            vec![
+                Eid(100),
+                Aid("parent/child".to_string()),
+                Eid(300),
+                Aid("name".to_string()),
+                String("Mabel".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((unresolved.clone(), 1));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("unknown result {:?}", result);
+            }
+        }
+
+        // Once the child arrives, the placeholder is retracted.
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    400,
+                    "name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut expected = HashSet::new();
+        expected.insert((
+            vec![
+                Eid(200),
+                Aid("parent/child".to_string()),
+                Eid(400),
+                Aid("name".to_string()),
+                String("Dipper".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((unresolved, -1));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("unknown result {:?}", result);
+            }
+        }
+
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
+
 #[test]
 fn pull() {
     timely::execute(Configuration::Thread, |worker| {
@@ -244,12 +379,18 @@ fn pull() {
                         "pattern/v".to_string(),
                     ],
                     path_attributes: vec!["join/binding".to_string()],
+                    mark_unresolved: false,
+                    recursion: None,
+                    envelope: false,
                 },
                 PullLevel {
                     variables: vec![],
                     plan: Box::new(Plan::MatchA(a, "name".to_string(), c)),
                     pull_attributes: vec![],
                     path_attributes: vec!["name".to_string()],
+                    mark_unresolved: false,
+                    recursion: None,
+                    envelope: false,
                 },
             ],
         });
@@ -364,3 +505,291 @@ fn pull() {
     })
     .unwrap();
 }
+
+#[test]
+fn pull_wildcard() {
+    timely::execute(Configuration::Thread, |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let e = 1;
+        let plan = Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: Box::new(Plan::MatchAV(e, "admin?".to_string(), Bool(true))),
+            pull_attributes: vec!["*".to_string()],
+            path_attributes: vec![],
+            mark_unresolved: false,
+            recursion: None,
+            envelope: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            // built-in attributes are not matched by the wildcard
+            for name in &["admin?", "name", "age", "df.tx/note"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "pull_wildcard".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, "admin?".to_string(), Bool(true)),
+                    TxData(1, 100, "name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 100, "age".to_string(), Number(12)),
+                    TxData(1, 200, "name".to_string(), String("Dipper".to_string())),
+                    TxData(
+                        1,
+                        100,
+                        "df.tx/note".to_string(),
+                        String("hidden".to_string()),
+                    ),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut expected = HashSet::new();
+        expected.insert((vec![Eid(100), Aid("admin?".to_string()), Bool(true)], 1));
+        expected.insert((
+            vec![
+                Eid(100),
+                Aid("name".to_string()),
+                String("Mabel".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((vec![Eid(100), Aid("age".to_string()), Number(12)], 1));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("unknown result {:?}", result);
+            }
+        }
+
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn pull_recursive() {
+    timely::execute(Configuration::Thread, |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let e = 1;
+        let plan = Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: Box::new(Plan::MatchAV(e, "root?".to_string(), Bool(true))),
+            pull_attributes: vec!["name".to_string()],
+            path_attributes: vec![],
+            mark_unresolved: false,
+            recursion: Some(PullRecursion {
+                attribute: "parent/child".to_string(),
+                depth: 2,
+            }),
+            envelope: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &["root?", "parent/child", "name"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "pull_recursive".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, "root?".to_string(), Bool(true)),
+                    TxData(1, 100, "name".to_string(), String("Stan".to_string())),
+                    TxData(1, 100, "parent/child".to_string(), Eid(200)),
+                    TxData(1, 200, "name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 200, "parent/child".to_string(), Eid(300)),
+                    TxData(1, 300, "name".to_string(), String("Waddles".to_string())),
+                    TxData(1, 300, "parent/child".to_string(), Eid(400)),
+                    TxData(1, 400, "name".to_string(), String("Too deep".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut expected = HashSet::new();
+        expected.insert((
+            vec![
+                Eid(100),
+                Aid("name".to_string()),
+                String("Stan".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((
+            vec![
+                Eid(100),
+                Aid("parent/child".to_string()),
+                Eid(200),
+                Aid("name".to_string()),
+                String("Dipper".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((
+            vec![
+                Eid(100),
+                Aid("parent/child".to_string()),
+                Eid(200),
+                Aid("parent/child".to_string()),
+                Eid(300),
+                Aid("name".to_string()),
+                String("Waddles".to_string()),
+            ],
+            1,
+        ));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("unknown result {:?}", result);
+            }
+        }
+
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn pull_envelope() {
+    timely::execute(Configuration::Thread, |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let e = 1;
+        let plan = Plan::PullLevel(PullLevel {
+            variables: vec![],
+            plan: Box::new(Plan::MatchAV(e, "root?".to_string(), Bool(true))),
+            pull_attributes: vec!["name".to_string()],
+            path_attributes: vec![],
+            mark_unresolved: false,
+            recursion: Some(PullRecursion {
+                attribute: "parent/child".to_string(),
+                depth: 1,
+            }),
+            envelope: true,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &["root?", "parent/child", "name"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "pull_envelope".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, "root?".to_string(), Bool(true)),
+                    TxData(1, 100, "name".to_string(), String("Stan".to_string())),
+                    TxData(1, 100, "parent/child".to_string(), Eid(200)),
+                    TxData(1, 200, "name".to_string(), String("Dipper".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut expected = HashSet::new();
+        expected.insert((
+            vec![
+                Number(0),
+                Eid(100),
+                Aid("name".to_string()),
+                String("Stan".to_string()),
+            ],
+            1,
+        ));
+        expected.insert((
+            vec![
+                Number(1),
+                Aid("parent/child".to_string()),
+                Eid(100),
+                Aid("parent/child".to_string()),
+                Eid(200),
+                Aid("name".to_string()),
+                String("Dipper".to_string()),
+            ],
+            1,
+        ));
+
+        for _i in 0..expected.len() {
+            let result = results.recv().unwrap();
+            if !expected.remove(&result) {
+                panic!("unknown result {:?}", result);
+            }
+        }
+
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
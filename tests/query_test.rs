@@ -8,9 +8,15 @@ use timely::dataflow::operators::Operator;
 use timely::Configuration;
 
 use declarative_dataflow::binding::Binding;
-use declarative_dataflow::plan::{Implementable, Join, Project};
-use declarative_dataflow::server::Server;
-use declarative_dataflow::{Aid, AttributeSemantics, Plan, Rule, TxData, Value};
+use declarative_dataflow::plan::{
+    Aggregate, AggregationFn, Antijoin, BroadcastJoin, Degree, Filter, FullText, Implementable,
+    Join, MultiJoin, Predicate, Project, RangeScan, Union,
+};
+use declarative_dataflow::server::{Config, Register, Server};
+use declarative_dataflow::{
+    check_stratification, recursive_rules, Aid, AttributeSemantics, Plan, Rational32, Rule, TxData,
+    Value, ValueType,
+};
 use Value::{Eid, Number, String};
 
 struct Case {
@@ -23,7 +29,7 @@ struct Case {
 fn dependencies(case: &Case) -> HashSet<Aid> {
     let mut deps = HashSet::new();
 
-    for binding in case.plan.into_bindings().iter() {
+    for binding in case.plan.into_bindings().unwrap().iter() {
         if let Binding::Attribute(binding) = binding {
             deps.insert(binding.source_attribute.clone());
         }
@@ -95,6 +101,108 @@ fn run_query_cases() {
                 )]],
             }
         },
+        {
+            let (e, c, n) = (1, 2, 3);
+            Case {
+                description:
+                    "[:find ?e ?n :where [?e :country ?c] [?c :country/name ?n]] (broadcast)",
+                plan: Plan::Project(Project {
+                    variables: vec![e, n],
+                    plan: Box::new(Plan::BroadcastJoin(BroadcastJoin {
+                        variables: vec![c],
+                        left_plan: Box::new(Plan::MatchA(e, ":country".to_string(), c)),
+                        right_plan: Box::new(Plan::MatchA(c, ":country/name".to_string(), n)),
+                    })),
+                }),
+                transactions: vec![vec![
+                    TxData(1, 1, ":country".to_string(), Eid(10)),
+                    TxData(1, 2, ":country".to_string(), Eid(20)),
+                    TxData(
+                        1,
+                        10,
+                        ":country/name".to_string(),
+                        String("Oregon".to_string()),
+                    ),
+                ]],
+                expectations: vec![vec![(vec![Eid(1), String("Oregon".to_string())], 0, 1)]],
+            }
+        },
+        {
+            let (e, c, n, a) = (1, 2, 3, 4);
+            Case {
+                description:
+                    "[:find ?e ?n ?a :where [?e :age ?a] [?c :country/name ?n] [?e :country ?c]]",
+                plan: Plan::MultiJoin(MultiJoin {
+                    variables: vec![e, n, a],
+                    plans: vec![
+                        Plan::MatchA(e, ":age".to_string(), a),
+                        Plan::MatchA(c, ":country/name".to_string(), n),
+                        Plan::MatchA(e, ":country".to_string(), c),
+                    ],
+                }),
+                transactions: vec![vec![
+                    TxData(1, 1, ":age".to_string(), Number(12)),
+                    TxData(1, 1, ":country".to_string(), Eid(10)),
+                    TxData(1, 2, ":country".to_string(), Eid(10)),
+                    TxData(
+                        1,
+                        10,
+                        ":country/name".to_string(),
+                        String("Oregon".to_string()),
+                    ),
+                ]],
+                expectations: vec![vec![(
+                    vec![Eid(1), String("Oregon".to_string()), Number(12)],
+                    0,
+                    1,
+                )]],
+            }
+        },
+        {
+            let (e, c, p) = (1, 2, 3);
+            Case {
+                description: "[:find ?e ?p :where [?e :country ?c] [?p :president ?c]]",
+                plan: Plan::MultiJoin(MultiJoin {
+                    variables: vec![e, p],
+                    plans: vec![
+                        Plan::MatchA(e, ":country".to_string(), c),
+                        Plan::MatchA(p, ":president".to_string(), c),
+                    ],
+                }),
+                transactions: vec![vec![
+                    TxData(1, 1, ":country".to_string(), Eid(10)),
+                    TxData(1, 2, ":country".to_string(), Eid(20)),
+                    TxData(1, 3, ":president".to_string(), Eid(10)),
+                ]],
+                expectations: vec![vec![(vec![Eid(1), Eid(3)], 0, 1)]],
+            }
+        },
+        {
+            let (e, a, n) = (1, 2, 3);
+            Case {
+                description: "[:find ?e ?n ?a :where (or [?e :name ?n] [?e :age ?a])]",
+                plan: Plan::Union(Union {
+                    variables: vec![e, n, a],
+                    plans: vec![
+                        Plan::MatchA(e, ":name".to_string(), n),
+                        Plan::MatchA(e, ":age".to_string(), a),
+                    ],
+                    fill: Some(Value::Bool(false)),
+                }),
+                transactions: vec![vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":age".to_string(), Number(12)),
+                ]],
+                expectations: vec![vec![
+                    (
+                        vec![Eid(1), String("Dipper".to_string()), Value::Bool(false)],
+                        0,
+                        1,
+                    ),
+                    (vec![Eid(2), Value::Bool(false), Number(12)], 0, 1),
+                ]],
+            }
+        },
         // {
         //     let (e, a, n) = (1, 2, 3);
 
@@ -206,3 +314,860 @@ fn run_query_cases() {
         .unwrap();
     }
 }
+
+#[test]
+fn antijoin() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?n :where [?e :name ?n] (not [?e :banned true])]
+        let (e, n) = (1, 2);
+        let plan = Plan::Antijoin(Antijoin {
+            variables: vec![e],
+            left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            right_plan: Box::new(Plan::MatchAV(e, ":banned".to_string(), Value::Bool(true))),
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in [":name", ":banned"].iter() {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "antijoin".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 200, ":name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 200, ":banned".to_string(), Value::Bool(true)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.recv_timeout(Duration::from_millis(400)).unwrap(),
+            (vec![Eid(100), String("Dipper".to_string())], 1)
+        );
+        assert!(results.recv_timeout(Duration::from_millis(400)).is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn transitive_closure() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [(reach ?x ?y) [?x :edge ?y]]
+        // [(reach ?x ?y) (reach ?x ?z) [?z :edge ?y]]
+        let (x, y, z) = (1, 2, 3);
+        let reach = Rule {
+            name: "reach".to_string(),
+            plan: Plan::Union(Union {
+                variables: vec![x, y],
+                plans: vec![
+                    Plan::MatchA(x, ":edge".to_string(), y),
+                    Plan::Project(Project {
+                        variables: vec![x, y],
+                        plan: Box::new(Plan::Join(Join {
+                            variables: vec![z],
+                            left_plan: Box::new(Plan::NameExpr(vec![x, z], "reach".to_string())),
+                            right_plan: Box::new(Plan::MatchA(z, ":edge".to_string(), y)),
+                        })),
+                    }),
+                ],
+                fill: None,
+            }),
+        };
+
+        let edges = Rule {
+            name: "edges".to_string(),
+            plan: Plan::MatchA(x, ":edge".to_string(), y),
+        };
+
+        assert_eq!(
+            recursive_rules(&[reach.clone(), edges]),
+            HashSet::from_iter(vec!["reach".to_string()])
+        );
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":edge", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server.test_single(scope, reach).inspect(move |x| {
+                send_results.send((x.0.clone(), x.2)).unwrap();
+            });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":edge".to_string(), Eid(2)),
+                    TxData(1, 2, ":edge".to_string(), Eid(3)),
+                    TxData(1, 3, ":edge".to_string(), Eid(4)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received = Vec::new();
+        while let Ok(result) = results.recv_timeout(Duration::from_millis(400)) {
+            received.push(result);
+        }
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), Eid(2)], 1),
+                (vec![Eid(1), Eid(3)], 1),
+                (vec![Eid(1), Eid(4)], 1),
+                (vec![Eid(2), Eid(3)], 1),
+                (vec![Eid(2), Eid(4)], 1),
+                (vec![Eid(3), Eid(4)], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn range_scan() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [:find ?e ?age :where [?e :age ?age] [(<= 18 ?age 65)]]
+        let (e, age) = (1, 2);
+        let plan = Plan::RangeScan(RangeScan {
+            variables: vec![e, age],
+            attribute: ":age".to_string(),
+            lower: Some(Number(18)),
+            upper: Some(Number(65)),
+            upper_exclusive: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":age", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "range_scan".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":age".to_string(), Number(12)),
+                    TxData(1, 200, ":age".to_string(), Number(18)),
+                    TxData(1, 300, ":age".to_string(), Number(42)),
+                    TxData(1, 400, ":age".to_string(), Number(65)),
+                    TxData(1, 500, ":age".to_string(), Number(70)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(200), Number(18)], 1),
+                (vec![Eid(300), Number(42)], 1),
+                (vec![Eid(400), Number(65)], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn half_open_range_scan() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [?e :age ?age] with 18 <= ?age < 65, and ?age >= 42
+        let (e, age) = (1, 2);
+        let rules = vec![
+            Rule {
+                name: "working_age".to_string(),
+                plan: Plan::RangeScan(RangeScan {
+                    variables: vec![e, age],
+                    attribute: ":age".to_string(),
+                    lower: Some(Number(18)),
+                    upper: Some(Number(65)),
+                    upper_exclusive: true,
+                }),
+            },
+            Rule {
+                name: "senior".to_string(),
+                plan: Plan::RangeScan(RangeScan {
+                    variables: vec![e, age],
+                    attribute: ":age".to_string(),
+                    lower: Some(Number(42)),
+                    upper: None,
+                    upper_exclusive: true,
+                }),
+            },
+        ];
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":age", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            for rule in rules.iter().cloned() {
+                let name = rule.name.clone();
+                let send_results = send_results.clone();
+
+                server.test_single(scope, rule).inspect(move |x| {
+                    send_results.send((name.clone(), x.0.clone(), x.2)).unwrap();
+                });
+            }
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":age".to_string(), Number(12)),
+                    TxData(1, 200, ":age".to_string(), Number(18)),
+                    TxData(1, 300, ":age".to_string(), Number(42)),
+                    TxData(1, 400, ":age".to_string(), Number(65)),
+                    TxData(1, 500, ":age".to_string(), Number(70)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        let senior = "senior".to_string();
+        let working_age = "working_age".to_string();
+
+        // upper bounds are exclusive, lower bounds inclusive
+        assert_eq!(
+            received,
+            vec![
+                (senior.clone(), vec![Eid(300), Number(42)], 1),
+                (senior.clone(), vec![Eid(400), Number(65)], 1),
+                (senior, vec![Eid(500), Number(70)], 1),
+                (working_age.clone(), vec![Eid(200), Number(18)], 1),
+                (working_age, vec![Eid(300), Number(42)], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn typed_range_scan() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [?e :price ?price] with 2 <= ?price <= 7/2
+        let plan = Plan::RangeScan(RangeScan {
+            variables: vec![0, 1],
+            attribute: ":price".to_string(),
+            lower: Some(Number(2)),
+            upper: Some(Value::Rational32(Rational32::new(7, 2))),
+            upper_exclusive: false,
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .context
+                .internal
+                .set_value_type(":price", ValueType::Rational32)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "cheap".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        // Mismatching values are rejected along with the whole
+        // transaction.
+        assert!(server
+            .transact(
+                vec![
+                    TxData(1, 100, ":price".to_string(), Number(1)),
+                    TxData(1, 200, ":price".to_string(), String("free".to_string())),
+                ],
+                0,
+                0,
+            )
+            .is_err());
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":price".to_string(), Number(1)),
+                    TxData(
+                        1,
+                        200,
+                        ":price".to_string(),
+                        Value::Rational32(Rational32::new(5, 2)),
+                    ),
+                    TxData(1, 300, ":price".to_string(), Number(3)),
+                    TxData(1, 400, ":price".to_string(), Number(10)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        // numbers are stored as rationals, and ordered numerically
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(200), Value::Rational32(Rational32::new(5, 2))], 1),
+                (
+                    vec![Eid(300), Value::Rational32(Rational32::from_integer(3))],
+                    1
+                ),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn degree() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // number of friends per person, and of people befriending each friend
+        let (e, n) = (1, 2);
+        let rules = vec![
+            Rule {
+                name: "friends".to_string(),
+                plan: Plan::Degree(Degree {
+                    variables: vec![e, n],
+                    attribute: ":friend".to_string(),
+                    reverse: false,
+                }),
+            },
+            Rule {
+                name: "befriended".to_string(),
+                plan: Plan::Degree(Degree {
+                    variables: vec![e, n],
+                    attribute: ":friend".to_string(),
+                    reverse: true,
+                }),
+            },
+        ];
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":friend", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            for rule in rules.iter().cloned() {
+                let name = rule.name.clone();
+                let send_results = send_results.clone();
+
+                server.test_single(scope, rule).inspect(move |x| {
+                    send_results.send((name.clone(), x.0.clone(), x.2)).unwrap();
+                });
+            }
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":friend".to_string(), Eid(200)),
+                    TxData(1, 100, ":friend".to_string(), Eid(300)),
+                    TxData(1, 200, ":friend".to_string(), Eid(300)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        let befriended = "befriended".to_string();
+        let friends = "friends".to_string();
+
+        assert_eq!(
+            received,
+            vec![
+                (befriended.clone(), vec![Eid(200), Number(1)], 1),
+                (befriended.clone(), vec![Eid(300), Number(2)], 1),
+                (friends.clone(), vec![Eid(100), Number(2)], 1),
+                (friends.clone(), vec![Eid(200), Number(1)], 1),
+            ]
+        );
+
+        server
+            .transact(vec![TxData(-1, 100, ":friend".to_string(), Eid(300))], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        // counts are updated, rather than recomputed
+        assert_eq!(
+            received,
+            vec![
+                (befriended.clone(), vec![Eid(300), Number(1)], 1),
+                (befriended, vec![Eid(300), Number(2)], -1),
+                (friends.clone(), vec![Eid(100), Number(1)], 1),
+                (friends, vec![Eid(100), Number(2)], -1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn full_text() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        let e = 1;
+        let rules = vec![
+            Rule {
+                name: "rust".to_string(),
+                plan: Plan::FullText(FullText {
+                    variables: vec![e],
+                    attribute: ":bio".to_string(),
+                    query: "Rust".to_string(),
+                }),
+            },
+            Rule {
+                name: "dataflow".to_string(),
+                plan: Plan::FullText(FullText {
+                    variables: vec![e],
+                    attribute: ":bio".to_string(),
+                    query: "differential, dataflow!".to_string(),
+                }),
+            },
+        ];
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":bio", AttributeSemantics::FullText, scope)
+                .unwrap();
+
+            for rule in rules.iter().cloned() {
+                let name = rule.name.clone();
+                let send_results = send_results.clone();
+
+                server.test_single(scope, rule).inspect(move |x| {
+                    send_results.send((name.clone(), x.0.clone(), x.2)).unwrap();
+                });
+            }
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(
+                        1,
+                        100,
+                        ":bio".to_string(),
+                        String("Writes Rust, and differential dataflow.".to_string()),
+                    ),
+                    TxData(
+                        1,
+                        200,
+                        ":bio".to_string(),
+                        String("Timely dataflow in rust".to_string()),
+                    ),
+                    TxData(
+                        1,
+                        300,
+                        ":bio".to_string(),
+                        String("Differential".to_string()),
+                    ),
+                    TxData(1, 300, ":bio".to_string(), String("Dataflow".to_string())),
+                    TxData(1, 400, ":bio".to_string(), Number(42)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        let dataflow = "dataflow".to_string();
+        let rust = "rust".to_string();
+
+        // tokens may be spread across several values of an entity
+        assert_eq!(
+            received,
+            vec![
+                (dataflow.clone(), vec![Eid(100)], 1),
+                (dataflow.clone(), vec![Eid(300)], 1),
+                (rust.clone(), vec![Eid(100)], 1),
+                (rust, vec![Eid(200)], 1),
+            ]
+        );
+
+        server
+            .transact(
+                vec![TxData(
+                    -1,
+                    300,
+                    ":bio".to_string(),
+                    String("Dataflow".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let received: Vec<_> = results.try_iter().collect();
+
+        assert_eq!(received, vec![(dataflow, vec![Eid(300)], -1)]);
+    })
+    .unwrap();
+}
+
+#[test]
+fn string_predicates() {
+    let name = |n: &str| String(n.to_string());
+
+    assert!(Predicate::STARTS_WITH.apply(&name("Dipper"), &name("Dip")));
+    assert!(!Predicate::STARTS_WITH.apply(&name("Dip"), &name("Dipper")));
+    assert!(Predicate::ENDS_WITH.apply(&name("Dipper"), &name("per")));
+    assert!(Predicate::CONTAINS.apply(&name("Dipper"), &name("ipp")));
+    assert!(!Predicate::CONTAINS.apply(&Number(1), &name("1")));
+
+    #[cfg(feature = "regex-predicates")]
+    {
+        assert!(Predicate::MATCHES.apply(&name("Dipper"), &name("^D.p+er$")));
+        assert!(!Predicate::MATCHES.apply(&name("Mabel"), &name("^D")));
+        assert!(!Predicate::MATCHES.apply(&name("Dipper"), &name("(")));
+
+        // compiled patterns are reused, invalid ones included
+        assert!(Predicate::MATCHES.apply(&name("Dippper"), &name("^D.p+er$")));
+        assert!(!Predicate::MATCHES.apply(&name("Mabel"), &name("(")));
+    }
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        // [?e :name ?n] [(starts-with? ?n "M")]
+        // [?e :name ?n] [?e :alias ?a] [(contains? ?n ?a)]
+        let (e, n, a) = (1, 2, 3);
+        let rules = vec![
+            Rule {
+                name: "m".to_string(),
+                plan: Plan::Filter(Filter {
+                    variables: vec![n],
+                    predicate: Predicate::STARTS_WITH,
+                    plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                    constants: vec![None, Some(name("M"))],
+                }),
+            },
+            Rule {
+                name: "aliased".to_string(),
+                plan: Plan::Filter(Filter {
+                    variables: vec![n, a],
+                    predicate: Predicate::CONTAINS,
+                    plan: Box::new(Plan::Join(Join {
+                        variables: vec![e],
+                        left_plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                        right_plan: Box::new(Plan::MatchA(e, ":alias".to_string(), a)),
+                    })),
+                    constants: vec![None, None],
+                }),
+            },
+        ];
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for attribute in &[":name", ":alias"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(attribute, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            for rule in rules.iter().cloned() {
+                let rule_name = rule.name.clone();
+                let send_results = send_results.clone();
+
+                server.test_single(scope, rule).inspect(move |x| {
+                    send_results
+                        .send((rule_name.clone(), x.0.clone(), x.2))
+                        .unwrap();
+                });
+            }
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 100, ":name".to_string(), name("Dipper")),
+                    TxData(1, 100, ":alias".to_string(), name("Dip")),
+                    TxData(1, 200, ":name".to_string(), name("Mabel")),
+                    TxData(1, 200, ":alias".to_string(), name("Mabes")),
+                    TxData(1, 300, ":name".to_string(), Number(3)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (
+                    "aliased".to_string(),
+                    vec![Eid(100), name("Dipper"), name("Dip")],
+                    1
+                ),
+                ("m".to_string(), vec![Eid(200), name("Mabel")], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn multi_join_optimizer() {
+    let (e, c, n) = (1, 2, 3);
+
+    // [:find ?n (count ?e) :where [?e :country ?c] [?c :country/name ?n]]
+    let plan = Plan::Aggregate(Aggregate {
+        variables: vec![n, e],
+        plan: Box::new(Plan::MultiJoin(MultiJoin {
+            variables: vec![n, e],
+            plans: vec![
+                Plan::MatchA(e, ":country".to_string(), c),
+                Plan::MatchA(c, ":country/name".to_string(), n),
+            ],
+        })),
+        aggregation_fns: vec![AggregationFn::COUNT],
+        key_symbols: vec![n],
+        aggregation_symbols: vec![e],
+        with_symbols: vec![],
+    });
+
+    // joins without inputs are rejected up front
+    assert!(Plan::MultiJoin(MultiJoin {
+        variables: vec![e],
+        plans: vec![],
+    })
+    .validate()
+    .is_err());
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut outputs = Vec::new();
+
+        for enable_optimizer in vec![false, true] {
+            let mut server = Server::<u64>::new(Config {
+                enable_optimizer,
+                ..Default::default()
+            });
+            let (send_results, results) = channel();
+
+            worker.dataflow::<u64, _, _>(|scope| {
+                for attribute in &[":country", ":country/name"] {
+                    server
+                        .context
+                        .internal
+                        .create_attribute(attribute, AttributeSemantics::Raw, scope)
+                        .unwrap();
+                }
+
+                server
+                    .register(Register {
+                        rules: vec![Rule {
+                            name: "residents".to_string(),
+                            plan: plan.clone(),
+                        }],
+                        publish: vec!["residents".to_string()],
+                        conflict_policy: Default::default(),
+                        replicate: Vec::new(),
+                        distinct: Vec::new(),
+                        metadata: Default::default(),
+                    })
+                    .unwrap();
+
+                server
+                    .interest("residents", scope)
+                    .unwrap()
+                    .import_named(scope, "residents")
+                    .as_collection(|tuple, _| tuple.clone())
+                    .inspect(move |x| {
+                        send_results.send(x.clone()).unwrap();
+                    });
+            });
+
+            server
+                .transact(
+                    vec![
+                        TxData(1, 1, ":country".to_string(), Eid(10)),
+                        TxData(1, 2, ":country".to_string(), Eid(10)),
+                        TxData(1, 3, ":country".to_string(), Eid(20)),
+                        TxData(1, 10, ":country/name".to_string(), String("a".to_string())),
+                        TxData(1, 20, ":country/name".to_string(), String("b".to_string())),
+                    ],
+                    0,
+                    0,
+                )
+                .unwrap();
+            server.advance_domain(None, 1).unwrap();
+
+            worker.step_while(|| server.is_any_outdated());
+
+            let mut output: Vec<(Vec<Value>, u64, isize)> = results.try_iter().collect();
+            output.sort();
+            outputs.push(output);
+        }
+
+        assert_eq!(
+            outputs[0],
+            vec![
+                (vec![String("a".to_string()), Number(2)], 0, 1),
+                (vec![String("b".to_string()), Number(1)], 0, 1),
+            ]
+        );
+        assert_eq!(outputs[0], outputs[1]);
+    })
+    .unwrap();
+}
+
+#[test]
+fn unstratifiable_negation() {
+    // [(p ?x) [?x :node _] (not (q ?x))]
+    // [(q ?x) (p ?x)]
+    let (x, y) = (1, 2);
+    let p = Rule {
+        name: "p".to_string(),
+        plan: Plan::Antijoin(Antijoin {
+            variables: vec![x],
+            left_plan: Box::new(Plan::Project(Project {
+                variables: vec![x],
+                plan: Box::new(Plan::MatchA(x, ":node".to_string(), y)),
+            })),
+            right_plan: Box::new(Plan::NameExpr(vec![x], "q".to_string())),
+        }),
+    };
+    let q = Rule {
+        name: "q".to_string(),
+        plan: Plan::NameExpr(vec![x], "p".to_string()),
+    };
+    let nodes = Rule {
+        name: "q".to_string(),
+        plan: Plan::Project(Project {
+            variables: vec![x],
+            plan: Box::new(Plan::MatchA(x, ":node".to_string(), y)),
+        }),
+    };
+
+    let error = check_stratification(&[p.clone(), q]).unwrap_err();
+    assert_eq!(error.category, "df.error.category/incorrect");
+    assert!(error.message.contains("p -> q -> p"));
+
+    // Negating a relation outside of the cycle is fine.
+    assert!(check_stratification(&[p, nodes]).is_ok());
+}
@@ -1,11 +1,23 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
 use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use timely::dataflow::operators::Probe;
+use timely::dataflow::ProbeHandle;
 use timely::Configuration;
 
-use declarative_dataflow::plan::{Join, Project};
-use declarative_dataflow::server::Server;
-use declarative_dataflow::{AttributeSemantics, Plan, Rule, TxData, Value};
-use Value::{Eid, String};
+use declarative_dataflow::binding::{AttributeBinding, Binding};
+use declarative_dataflow::plan::{Hector, Join, Project};
+use declarative_dataflow::server::logging::{Logging, ATTRIBUTES};
+use declarative_dataflow::server::{
+    parse_requests, Config, ConflictPolicy, Interest, Metadata, Register, RegisterOutcome,
+    RegisterSink, Request, Restriction, Server, SortKey, Window,
+};
+use declarative_dataflow::sinks::{Sink, Webhook};
+use declarative_dataflow::{AttributeSemantics, Plan, RetentionPolicy, Rule, TxData, Value};
+use Value::{Eid, Number, String};
 
 #[test]
 fn match_ea_after_input() {
@@ -151,3 +163,3146 @@ fn join_after_input() {
     })
     .unwrap();
 }
+
+#[test]
+fn register_conflict_policies() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":name", ":alias"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        let rule = Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, ":name".to_string(), 1),
+        };
+        let other_rule = Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, ":alias".to_string(), 1),
+        };
+
+        let register = |rule: &Rule, conflict_policy: ConflictPolicy| Register {
+            rules: vec![rule.clone()],
+            publish: vec![],
+            conflict_policy,
+            replicate: vec![],
+            distinct: vec![],
+            metadata: Default::default(),
+        };
+
+        assert_eq!(
+            server
+                .register(register(&rule, ConflictPolicy::Error))
+                .unwrap(),
+            vec![("names".to_string(), RegisterOutcome::Registered)]
+        );
+        assert_eq!(
+            server
+                .register(register(&other_rule, ConflictPolicy::Ignore))
+                .unwrap(),
+            vec![("names".to_string(), RegisterOutcome::Ignored)]
+        );
+        assert_eq!(
+            server
+                .register(register(&rule, ConflictPolicy::ReplaceIfEqualHash))
+                .unwrap(),
+            vec![("names".to_string(), RegisterOutcome::Unchanged)]
+        );
+        assert!(server
+            .register(register(&other_rule, ConflictPolicy::ReplaceIfEqualHash))
+            .is_err());
+        assert!(server
+            .register(register(&rule, ConflictPolicy::Error))
+            .is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn redefine_and_unregister() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":name", ":alias"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        let register = |rules: Vec<Rule>| Register {
+            rules,
+            publish: vec![],
+            conflict_policy: ConflictPolicy::Replace,
+            replicate: vec![],
+            distinct: vec![],
+            metadata: Default::default(),
+        };
+
+        let names = Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, ":name".to_string(), 1),
+        };
+        let labels = Rule {
+            name: "labels".to_string(),
+            plan: Plan::Project(Project {
+                variables: vec![1],
+                plan: Box::new(Plan::NameExpr(vec![0, 1], "names".to_string())),
+            }),
+        };
+
+        server
+            .register(register(vec![names.clone(), labels]))
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("labels", scope).unwrap();
+        });
+
+        assert!(server.context.arrangements.contains_key("names"));
+        assert!(server.context.arrangements.contains_key("labels"));
+        assert!(server.context.shutdown_handles.contains_key("names"));
+        assert!(server.context.shutdown_handles.contains_key("labels"));
+
+        // identical definitions leave the implementation alone
+        assert_eq!(
+            server.register(register(vec![names])).unwrap(),
+            vec![("names".to_string(), RegisterOutcome::Unchanged)]
+        );
+        assert!(server.invalidated.is_empty());
+
+        // redefining a rule discards everything derived from it
+        let aliases = Rule {
+            name: "names".to_string(),
+            plan: Plan::MatchA(0, ":alias".to_string(), 1),
+        };
+
+        assert_eq!(
+            server.register(register(vec![aliases])).unwrap(),
+            vec![("names".to_string(), RegisterOutcome::Replaced)]
+        );
+        assert_eq!(
+            server.invalidated.drain(..).collect::<Vec<_>>(),
+            vec!["labels".to_string(), "names".to_string()]
+        );
+        assert!(!server.context.arrangements.contains_key("names"));
+        assert!(!server.context.arrangements.contains_key("labels"));
+        assert!(server.context.shutdown_handles.is_empty());
+        assert_eq!(server.generation("labels"), 1);
+        assert_eq!(server.generation(":name"), 0);
+
+        // rules still depended on can't be unregistered
+        let error = server.unregister("names").unwrap_err();
+        assert_eq!(error.category, "df.error.category/conflict");
+
+        server.unregister("labels").unwrap();
+        server.unregister("names").unwrap();
+        assert!(server.context.rules.is_empty());
+
+        let error = server.unregister("names").unwrap_err();
+        assert_eq!(error.category, "df.error.category/not-found");
+    })
+    .unwrap();
+}
+
+#[test]
+fn rule_datoms_follow_redefinition() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_meta: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute("df.pattern/a", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "patterns".to_string(),
+                        plan: Plan::MatchA(0, "df.pattern/a".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        let register = |plan: Plan| Register {
+            rules: vec![Rule {
+                name: "names".to_string(),
+                plan,
+            }],
+            publish: vec![],
+            conflict_policy: ConflictPolicy::Replace,
+            replicate: vec![],
+            distinct: vec![],
+            metadata: Default::default(),
+        };
+
+        let mut step = |server: &mut Server<u64>, t: u64| {
+            server.advance_domain(None, t).unwrap();
+            worker.step_while(|| server.is_any_outdated());
+
+            let mut received: Vec<_> = results
+                .try_iter()
+                .map(|(tuple, time, diff)| (tuple[1].clone(), time, diff))
+                .collect();
+            received.sort();
+            received
+        };
+
+        server
+            .register(register(Plan::MatchA(0, ":name".to_string(), 1)))
+            .unwrap();
+
+        assert_eq!(
+            step(&mut server, 1),
+            vec![(Value::Aid(":name".to_string()), 0, 1)]
+        );
+
+        // redefining a rule replaces the datoms describing it
+        server
+            .register(register(Plan::MatchA(0, ":alias".to_string(), 1)))
+            .unwrap();
+
+        assert_eq!(
+            step(&mut server, 2),
+            vec![
+                (Value::Aid(":alias".to_string()), 1, 1),
+                (Value::Aid(":name".to_string()), 1, -1),
+            ]
+        );
+
+        // unregistering a rule retracts them
+        server.unregister("names").unwrap();
+
+        assert_eq!(
+            step(&mut server, 3),
+            vec![(Value::Aid(":alias".to_string()), 2, -1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn register_unknown_dependencies() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        let register = |rules: Vec<Rule>| Register {
+            rules,
+            publish: vec![],
+            conflict_policy: Default::default(),
+            replicate: vec![],
+            distinct: vec![],
+            metadata: Default::default(),
+        };
+
+        // [:find ?e ?n ?a :where [?e :name ?n] [?e :age ?a] [?e :email ?m] (friends ?e ?f)]
+        let rule = Rule {
+            name: "profiles".to_string(),
+            plan: Plan::Join(Join {
+                variables: vec![0],
+                left_plan: Box::new(Plan::Join(Join {
+                    variables: vec![0],
+                    left_plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+                    right_plan: Box::new(Plan::MatchA(0, ":email".to_string(), 3)),
+                })),
+                right_plan: Box::new(Plan::Join(Join {
+                    variables: vec![0],
+                    left_plan: Box::new(Plan::MatchA(0, ":age".to_string(), 2)),
+                    right_plan: Box::new(Plan::NameExpr(vec![0, 4], "friends".to_string())),
+                })),
+            }),
+        };
+
+        let error = server.register(register(vec![rule.clone()])).unwrap_err();
+        assert_eq!(error.category, "df.error.category/not-found");
+        assert_eq!(
+            error.message,
+            "Rule profiles refers to unknown attributes :age, :email and unknown relations friends."
+        );
+        assert!(server.context.rules.is_empty());
+
+        // relations may be registered alongside the rules using them
+        let friends = Rule {
+            name: "friends".to_string(),
+            plan: Plan::MatchA(0, ":name".to_string(), 1),
+        };
+        let names = Rule {
+            name: "names".to_string(),
+            plan: Plan::Project(Project {
+                variables: vec![0, 1],
+                plan: Box::new(Plan::NameExpr(vec![0, 1], "friends".to_string())),
+            }),
+        };
+
+        server.register(register(vec![names, friends])).unwrap();
+        assert_eq!(server.context.rules.len(), 2);
+    })
+    .unwrap();
+}
+
+#[test]
+fn streaming_append_only() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":kind", ":label"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        server.context.internal.set_append_only(":kind").unwrap();
+
+        let click = || String("click".to_string());
+        let tx_data = vec![
+            TxData(1, 1, ":kind".to_string(), click()),
+            TxData(1, 2, ":kind".to_string(), click()),
+            TxData(1, 1, ":label".to_string(), click()),
+            TxData(1, 2, ":label".to_string(), click()),
+        ];
+
+        server.transact(tx_data, 0, 0).unwrap();
+
+        // [:find ?k :where [?e :kind ?k]]
+        let project = |name: &str, attribute: &str| Rule {
+            name: name.to_string(),
+            plan: Plan::Project(Project {
+                variables: vec![1],
+                plan: Box::new(Plan::MatchA(0, attribute.to_string(), 1)),
+            }),
+        };
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for (name, attribute) in &[("kinds", ":kind"), ("labels", ":label")] {
+                let send_results = send_results.clone();
+                let name = name.to_string();
+
+                server
+                    .test_single(scope, project(&name, attribute))
+                    .inspect(move |x| {
+                        send_results.send((name.clone(), x.0.clone(), x.2)).unwrap();
+                    });
+            }
+        });
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        // results of streamed rules carry multiplicities
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                ("kinds".to_string(), vec![click()], 2),
+                ("labels".to_string(), vec![click()], 1),
+            ]
+        );
+
+        assert!(server.is_streaming("kinds"));
+        assert!(!server.is_streaming("labels"));
+
+        // unless asked for set semantics explicitly
+        server
+            .register(Register {
+                rules: vec![project("distinct_kinds", ":kind")],
+                publish: vec![],
+                conflict_policy: Default::default(),
+                replicate: vec![],
+                distinct: vec!["distinct_kinds".to_string()],
+                metadata: Default::default(),
+            })
+            .unwrap();
+        assert!(!server.is_streaming("distinct_kinds"));
+
+        // append-only attributes reject retractions
+        let error = server
+            .transact(vec![TxData(-1, 1, ":kind".to_string(), click())], 0, 0)
+            .unwrap_err();
+        assert_eq!(error.category, "df.error.category/incorrect");
+    })
+    .unwrap();
+}
+
+#[test]
+fn match_a_tx() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_tx_times: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":status", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    1,
+                    ":status".to_string(),
+                    String("open".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        server
+            .transact(
+                vec![
+                    TxData(-1, 1, ":status".to_string(), String("open".to_string())),
+                    TxData(1, 1, ":status".to_string(), String("closed".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "status_tx".to_string(),
+                        plan: Plan::MatchATx(0, ":status".to_string(), 1, 2),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.advance_domain(None, 3).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.recv().unwrap(),
+            (vec![Eid(1), String("closed".to_string()), Number(1)], 1)
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn join_tx_metadata() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_tx_times: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":status", "df.tx/id", "df.tx/instant"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        let status = |e, s: &str| vec![TxData(1, e, ":status".to_string(), String(s.to_string()))];
+
+        // two transactions at the same time are described only once
+        server.transact(status(1, "open"), 0, 0).unwrap();
+        server.transact(status(2, "open"), 0, 0).unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        server.transact(status(3, "closed"), 0, 0).unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            // [:find ?e ?s ?t :where [?e :status ?s ?t] [?tx :df.tx/id ?t]]
+            let (e, s, t, tx) = (0, 1, 2, 3);
+            let plan = Plan::Project(Project {
+                variables: vec![e, s, t],
+                plan: Box::new(Plan::Join(Join {
+                    variables: vec![t],
+                    left_plan: Box::new(Plan::MatchATx(e, ":status".to_string(), s, t)),
+                    right_plan: Box::new(Plan::MatchA(tx, "df.tx/id".to_string(), t)),
+                })),
+            });
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "status_tx".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server.advance_domain(None, 3).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("open".to_string()), Number(0)], 1),
+                (vec![Eid(2), String("open".to_string()), Number(0)], 1),
+                (vec![Eid(3), String("closed".to_string()), Number(1)], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn tx_instant_of_command() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &[":status", "df.tx/id", "df.tx/instant"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        // the instant at which the command was issued is recorded,
+        // rather than the one at which it is applied
+        server.context.internal.set_instant(1_540_048_515_500);
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    1,
+                    ":status".to_string(),
+                    String("open".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "instants".to_string(),
+                        plan: Plan::MatchA(0, "df.tx/instant".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.0[1].clone()).unwrap();
+                });
+        });
+
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![Value::Instant(1_540_048_515_500)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn epoch_instants() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &["df.epoch/instant", "df.tx/id", "df.tx/instant"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            for name in &["df.epoch/instant", "df.tx/id"] {
+                let send_results = send_results.clone();
+
+                server
+                    .test_single(
+                        scope,
+                        Rule {
+                            name: format!("{}-values", name),
+                            plan: Plan::MatchA(0, name.to_string(), 1),
+                        },
+                    )
+                    .inspect(move |x| send_results.send(x.clone()).unwrap());
+            }
+        });
+
+        // the instant at which the command was sequenced is recorded,
+        // once, by its owner
+        server.context.internal.set_instant(1_540_048_515_500);
+        server.record_epoch(1, 0).unwrap();
+        server.record_epoch(0, 0).unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // epochs are not described as transactions
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(0), Value::Instant(1_540_048_515_500)], 0, 1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn transact_at_historical_times() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_history: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "prices".to_string(),
+                        plan: Plan::MatchA(0, ":price".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .transact_at(
+                vec![TxData(1, 1, ":price".to_string(), Number(10))],
+                5,
+                0,
+                0,
+            )
+            .unwrap();
+        server
+            .transact_at(
+                vec![
+                    TxData(-1, 1, ":price".to_string(), Number(10)),
+                    TxData(1, 1, ":price".to_string(), Number(12)),
+                ],
+                10,
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 11).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort_by_key(|x| (x.1, x.2));
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), Number(10)], 5, 1),
+                (vec![Eid(1), Number(10)], 10, -1),
+                (vec![Eid(1), Number(12)], 10, 1),
+            ]
+        );
+
+        assert!(server
+            .transact_at(vec![TxData(1, 2, ":price".to_string(), Number(7))], 3, 0, 0,)
+            .is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn interest_as_of() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_history: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "prices".to_string(),
+                    plan: Plan::MatchA(0, ":price".to_string(), 1),
+                }],
+                publish: vec!["prices".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        server
+            .transact(vec![TxData(1, 1, ":price".to_string(), Number(10))], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        server
+            .transact(
+                vec![
+                    TxData(-1, 1, ":price".to_string(), Number(10)),
+                    TxData(1, 1, ":price".to_string(), Number(12)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest_as_of("prices", 0, scope)
+                .unwrap()
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut server.probe);
+        });
+
+        server.advance_domain(None, 3).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), Number(10)], 0, 1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn replicated_relation() {
+    timely::execute(Configuration::Process(2), move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .register(Register {
+                    rules: vec![Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    }],
+                    publish: vec!["names".to_string()],
+                    conflict_policy: Default::default(),
+                    replicate: vec!["names".to_string()],
+                    distinct: vec![],
+                    metadata: Default::default(),
+                })
+                .unwrap();
+
+            server
+                .interest("names", scope)
+                .unwrap()
+                .import_named(scope, "names")
+                .stream
+                .probe_with(&mut probe);
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                worker.index(),
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| probe.less_than(server.context.internal.time()));
+
+        // every worker maintains a full copy
+        let names = server
+            .status()
+            .arrangements
+            .into_iter()
+            .find(|arrangement| arrangement.name == "names")
+            .unwrap();
+        assert_eq!(names.updates, 2);
+    })
+    .unwrap();
+}
+
+#[test]
+fn retention_keep_latest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_history: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .context
+            .internal
+            .set_retention(":price", RetentionPolicy::KeepLatest)
+            .unwrap();
+
+        server
+            .transact(vec![TxData(1, 1, ":price".to_string(), Number(10))], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        server
+            .transact(
+                vec![
+                    TxData(-1, 1, ":price".to_string(), Number(10)),
+                    TxData(1, 1, ":price".to_string(), Number(12)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "prices".to_string(),
+                        plan: Plan::MatchA(0, ":price".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server.advance_domain(None, 3).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // despite history being enabled, the superseded price has
+        // been compacted away
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), Number(12)], 1, 1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn retention_keep_last_units() {
+    // retention is given in units of domain time, whatever the
+    // representation of times
+    let policy: RetentionPolicy = serde_json::from_str(r#"{"KeepLast": 10}"#).unwrap();
+    assert_eq!(policy, RetentionPolicy::KeepLast(10));
+}
+
+#[test]
+fn cardinality_one() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::CardinalityOne, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        let mut expect =
+            |tx_data: Vec<TxData>, next: u64, expected: Vec<(Vec<Value>, u64, isize)>| {
+                server.transact(tx_data, 0, 0).unwrap();
+                server.advance_domain(None, next).unwrap();
+
+                worker.step_while(|| server.is_any_outdated());
+
+                let mut received: Vec<_> = results.try_iter().collect();
+                received.sort_by_key(|x| x.2);
+
+                assert_eq!(received, expected);
+            };
+
+        expect(
+            vec![TxData(
+                1,
+                1,
+                ":name".to_string(),
+                String("Mabel".to_string()),
+            )],
+            1,
+            vec![(vec![Eid(1), String("Mabel".to_string())], 0, 1)],
+        );
+
+        // a new value implicitly retracts the previous one
+        expect(
+            vec![TxData(
+                1,
+                1,
+                ":name".to_string(),
+                String("Dipper".to_string()),
+            )],
+            2,
+            vec![
+                (vec![Eid(1), String("Mabel".to_string())], 1, -1),
+                (vec![Eid(1), String("Dipper".to_string())], 1, 1),
+            ],
+        );
+
+        // retracting a value other than the current one has no effect
+        expect(
+            vec![TxData(
+                -1,
+                1,
+                ":name".to_string(),
+                String("Mabel".to_string()),
+            )],
+            3,
+            vec![],
+        );
+
+        // retracting the current value leaves the eid without a value
+        expect(
+            vec![TxData(
+                -1,
+                1,
+                ":name".to_string(),
+                String("Dipper".to_string()),
+            )],
+            4,
+            vec![(vec![Eid(1), String("Dipper".to_string())], 3, -1)],
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn flush_acknowledgement() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    1,
+                    ":name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.flush(7);
+
+        worker.step();
+        assert!(server.completed_flushes().is_empty());
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(server.completed_flushes(), vec![(0, 7)]);
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), String("Dipper".to_string())], 0, 1)]
+        );
+        assert!(server.flushes.is_empty());
+    })
+    .unwrap();
+}
+
+#[test]
+fn attribute_catalog() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in &["df.attribute/name", "df.attribute/semantics"] {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::CardinalityOne, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "catalog".to_string(),
+                        plan: Plan::Join(Join {
+                            variables: vec![0],
+                            left_plan: Box::new(Plan::MatchA(
+                                0,
+                                "df.attribute/name".to_string(),
+                                1,
+                            )),
+                            right_plan: Box::new(Plan::MatchA(
+                                0,
+                                "df.attribute/semantics".to_string(),
+                                2,
+                            )),
+                        }),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .record_attribute(
+                ":name",
+                Some(&AttributeSemantics::CardinalityOne),
+                None,
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        let received: Vec<_> = results
+            .try_iter()
+            .map(|(tuple, time, diff)| (tuple[1..].to_vec(), time, diff))
+            .collect();
+
+        assert_eq!(
+            received,
+            vec![(
+                vec![
+                    Value::Aid(":name".to_string()),
+                    String("CardinalityOne".to_string())
+                ],
+                0,
+                1
+            )]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn rule_metadata() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute("df.rule/name", AttributeSemantics::CardinalityOne, scope)
+                .unwrap();
+
+            server
+                .context
+                .internal
+                .create_attribute("df.rule/owner", AttributeSemantics::CardinalityOne, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "owners".to_string(),
+                        plan: Plan::Join(Join {
+                            variables: vec![0],
+                            left_plan: Box::new(Plan::MatchA(0, "df.rule/name".to_string(), 1)),
+                            right_plan: Box::new(Plan::MatchA(0, "df.rule/owner".to_string(), 2)),
+                        }),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        let metadata = Metadata {
+            doc: Some("All known names.".to_string()),
+            owner: Some("search".to_string()),
+            tags: vec!["people".to_string()],
+        };
+
+        server
+            .describe_rule("names", metadata.clone(), 0, 0)
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        let received: Vec<_> = results
+            .try_iter()
+            .map(|(tuple, time, diff)| (tuple[1..].to_vec(), time, diff))
+            .collect();
+
+        assert_eq!(
+            received,
+            vec![(
+                vec![
+                    Value::Aid("names".to_string()),
+                    String("search".to_string())
+                ],
+                0,
+                1
+            )]
+        );
+        assert_eq!(server.rule_metadata("names"), Some(&metadata));
+
+        // Describing a rule again replaces its metadata.
+        let metadata = Metadata {
+            owner: Some("billing".to_string()),
+            ..Default::default()
+        };
+
+        server
+            .describe_rule("names", metadata.clone(), 0, 0)
+            .unwrap();
+
+        server.advance_domain(None, 2).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results
+            .try_iter()
+            .map(|(tuple, time, diff)| (tuple[1..].to_vec(), time, diff))
+            .collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (
+                    vec![
+                        Value::Aid("names".to_string()),
+                        String("billing".to_string())
+                    ],
+                    1,
+                    1
+                ),
+                (
+                    vec![
+                        Value::Aid("names".to_string()),
+                        String("search".to_string())
+                    ],
+                    1,
+                    -1
+                ),
+            ]
+        );
+        assert_eq!(server.rule_metadata("names"), Some(&metadata));
+        assert_eq!(server.rule_metadata("ages"), None);
+    })
+    .unwrap();
+}
+
+#[test]
+fn metadata_defaults() {
+    let requests = parse_requests(
+        r#"[{"CreateAttribute": {"name": ":name", "semantics": "Raw"}},
+            {"CreateAttribute": {"name": ":age", "semantics": "Raw",
+                                 "metadata": {"doc": "In years.", "tags": ["people"]}}}]"#,
+    )
+    .unwrap();
+
+    let metadata: Vec<Metadata> = requests
+        .into_iter()
+        .map(|request| match request {
+            Request::CreateAttribute(req) => req.metadata,
+            _ => panic!("Expected a CreateAttribute request."),
+        })
+        .collect();
+
+    assert_eq!(
+        metadata,
+        vec![
+            Metadata::default(),
+            Metadata {
+                doc: Some("In years.".to_string()),
+                owner: None,
+                tags: vec!["people".to_string()],
+            },
+        ]
+    );
+    assert!(metadata[0].is_empty());
+}
+
+#[test]
+fn webhook_sink() {
+    timely::execute(Configuration::Thread, |worker| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (send_requests, requests) = channel();
+
+        thread::spawn(move || {
+            let (stream, _addr) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+
+                if header.trim().is_empty() {
+                    break;
+                } else if header.starts_with("Content-Length:") {
+                    content_length = header["Content-Length:".len()..].trim().parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+
+            send_requests
+                .send((request_line, std::string::String::from_utf8(body).unwrap()))
+                .unwrap();
+        });
+
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "prices".to_string(),
+                    plan: Plan::MatchA(0, ":price".to_string(), 1),
+                }],
+                publish: vec!["prices".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        worker
+            .dataflow::<u64, _, _>(|scope| {
+                server.register_sink(
+                    RegisterSink {
+                        name: "prices".to_string(),
+                        sink: Sink::Webhook(Webhook {
+                            url: url.clone(),
+                            debounce_ms: 0,
+                            notify_retractions: false,
+                        }),
+                    },
+                    scope,
+                )
+            })
+            .unwrap();
+
+        // Tuples added and retracted at the same time are not
+        // reported.
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":price".to_string(), Number(10)),
+                    TxData(1, 2, ":price".to_string(), Number(20)),
+                    TxData(-1, 2, ":price".to_string(), Number(20)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (request_line, body) = loop {
+            worker.step();
+
+            if let Ok(request) = requests.try_recv() {
+                break request;
+            }
+
+            assert!(Instant::now() < deadline, "no request received");
+        };
+
+        assert_eq!(request_line.trim(), "POST /alerts HTTP/1.1");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "df.alert/name": "prices",
+                "df.alert/added": [[Eid(1), Number(10)]],
+                "df.alert/retracted": [],
+            })
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn readers_hold_back_compaction() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":price", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![
+                    Rule {
+                        name: "prices".to_string(),
+                        plan: Plan::MatchA(0, ":price".to_string(), 1),
+                    },
+                    Rule {
+                        name: "unheld".to_string(),
+                        plan: Plan::MatchA(0, ":price".to_string(), 1),
+                    },
+                ],
+                publish: vec!["prices".to_string(), "unheld".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        // both relations must be implemented before they can be held
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("prices", scope).unwrap();
+            server.interest("unheld", scope).unwrap();
+        });
+
+        server
+            .transact(vec![TxData(1, 1, ":price".to_string(), Number(10))], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        server.hold("prices", 0, 0, 7).unwrap();
+        assert_eq!(server.held_by(0, &7), vec!["prices".to_string()]);
+
+        server
+            .transact(
+                vec![
+                    TxData(-1, 1, ":price".to_string(), Number(10)),
+                    TxData(1, 1, ":price".to_string(), Number(12)),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+        server.advance_domain(None, 3).unwrap();
+
+        // only the held relation can still be read as of time 0
+        assert!(server.hold("unheld", 0, 0, 8).is_err());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            assert!(server.interest_as_of("unheld", 0, scope).is_err());
+
+            server
+                .interest_as_of("prices", 0, scope)
+                .unwrap()
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut server.probe);
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Value::Eid(1), Number(10)], 0, 1)]
+        );
+
+        server.release("prices", 0, &7);
+        assert!(server.readers.is_empty());
+
+        server.advance_domain(None, 4).unwrap();
+
+        assert!(server.hold("prices", 0, 0, 7).is_err());
+        worker.dataflow::<u64, _, _>(|scope| {
+            assert!(server.interest_as_of("prices", 0, scope).is_err());
+        });
+    })
+    .unwrap();
+}
+
+#[test]
+fn list_attributes_and_rules() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::CardinalityOne, scope)
+                .unwrap();
+            server
+                .context
+                .internal
+                .create_attribute(":age", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                    Rule {
+                        name: "ages".to_string(),
+                        plan: Plan::MatchA(0, ":age".to_string(), 1),
+                    },
+                ],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("names", scope).unwrap();
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            server.list_attributes(),
+            vec![
+                (":age".to_string(), Some(AttributeSemantics::Raw), 0),
+                (
+                    ":name".to_string(),
+                    Some(AttributeSemantics::CardinalityOne),
+                    2
+                ),
+            ]
+        );
+        assert_eq!(
+            server.list_rules(),
+            vec![("ages".to_string(), None), ("names".to_string(), Some(2))]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn parameterized_interest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?n :in ?e :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "name-of".to_string(),
+                    plan: Plan::Project(Project {
+                        variables: vec![1],
+                        plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+                    }),
+                }],
+                publish: vec!["name-of".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let interest = server
+            .parameterize(Interest {
+                name: "name-of".to_string(),
+                as_of: None,
+                bindings: vec![(0, Eid(2))],
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        assert_eq!(interest.name, "name-of[[0,{\"Eid\":2}]]");
+        assert!(interest.bindings.is_empty());
+
+        assert!(server
+            .parameterize(Interest {
+                name: "name-of".to_string(),
+                as_of: None,
+                bindings: vec![(5, Eid(2))],
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .is_err());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.recv().unwrap(),
+            (vec![String("Mabel".to_string())], 1)
+        );
+        assert!(results.try_recv().is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn specialized_interest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?n :in ?e :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "name-of".to_string(),
+                    plan: Plan::Project(Project {
+                        variables: vec![1],
+                        plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+                    }),
+                }],
+                publish: vec!["name-of".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let interest = server
+            .parameterize(Interest {
+                name: "name-of".to_string(),
+                as_of: None,
+                bindings: vec![(0, Eid(2))],
+                specialize: true,
+                restrict: None,
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        // the binding is folded into the plan, rather than filtering
+        assert_eq!(
+            interest.name,
+            "name-of[[0,{\"Eid\":2}]]{\"specialize\":true}"
+        );
+        match server.context.rules[&interest.name].plan {
+            Plan::Hector(ref hector) => assert_eq!(hector.variables, vec![1]),
+            ref plan => panic!("expected a specialized plan, got {:?}", plan),
+        }
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.recv().unwrap(),
+            (vec![String("Mabel".to_string())], 1)
+        );
+        assert!(results.try_recv().is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn parameterized_interest_collected() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?n :in ?e :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "name-of".to_string(),
+                    plan: Plan::Project(Project {
+                        variables: vec![1],
+                        plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+                    }),
+                }],
+                publish: vec!["name-of".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let interest = server
+            .parameterize(Interest {
+                name: "name-of".to_string(),
+                as_of: None,
+                bindings: vec![(0, Eid(2))],
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest(&interest.name, scope).unwrap();
+        });
+
+        server.subscribe(&interest.name, 0, 1);
+        server.subscribe(&interest.name, 0, 2);
+
+        assert_eq!(server.subscribed_by(0, &1), vec![interest.name.clone()]);
+
+        // the specialized rule outlives all but the last interest in it
+        assert!(server.unsubscribe(&interest.name, 0, &1).is_empty());
+        assert_eq!(server.list_rules().len(), 2);
+
+        assert_eq!(
+            server.unsubscribe(&interest.name, 0, &2),
+            vec![interest.name.clone()]
+        );
+        assert_eq!(
+            server
+                .list_rules()
+                .into_iter()
+                .map(|(name, _size)| name)
+                .collect::<Vec<_>>(),
+            vec!["name-of".to_string()]
+        );
+        assert!(server.subscribed_by(0, &2).is_empty());
+    })
+    .unwrap();
+}
+
+#[test]
+fn restricted_interest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?e ?n :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                }],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let restrict = |offset| {
+            Some(Restriction {
+                offset,
+                values: vec![Eid(3), Eid(1), Eid(1)],
+            })
+        };
+
+        let interest = server
+            .parameterize(Interest {
+                name: "names".to_string(),
+                as_of: None,
+                bindings: vec![],
+                specialize: false,
+                restrict: restrict(0),
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        // restrictions are named after a hash of their values,
+        // regardless of their order
+        assert!(interest.name.starts_with("names{\"restrict\":[0,"));
+        assert!(interest.name.len() < 48);
+        assert!(interest.restrict.is_none());
+        assert_eq!(
+            server
+                .parameterize(Interest {
+                    name: "names".to_string(),
+                    as_of: None,
+                    bindings: vec![],
+                    specialize: false,
+                    restrict: Some(Restriction {
+                        offset: 0,
+                        values: vec![Eid(1), Eid(3)],
+                    }),
+                    project: None,
+                    order_by: Vec::new(),
+                    window: None,
+                    delivery: Default::default(),
+                    consumer: None,
+                })
+                .unwrap()
+                .name,
+            interest.name
+        );
+
+        assert!(server
+            .parameterize(Interest {
+                name: "names".to_string(),
+                as_of: None,
+                bindings: vec![],
+                specialize: false,
+                restrict: restrict(2),
+                project: None,
+                order_by: Vec::new(),
+                window: None,
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .is_err());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 3, ":name".to_string(), String("Soos".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 1),
+                (vec![Eid(3), String("Soos".to_string())], 1),
+            ]
+        );
+
+        // the restriction is released along with the interest
+        assert_eq!(server.collect(&interest.name), vec![interest.name.clone()]);
+        assert!(!server.context.arrangements.contains_key(&interest.name));
+        assert!(!server.context.shutdown_handles.contains_key(&interest.name));
+    })
+    .unwrap();
+}
+
+#[test]
+fn projected_interest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?e ?n :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                }],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let shape = |project, variable| Interest {
+            name: "names".to_string(),
+            as_of: None,
+            bindings: vec![],
+            specialize: false,
+            restrict: None,
+            project,
+            order_by: vec![SortKey {
+                variable,
+                descending: true,
+            }],
+            window: None,
+            delivery: Default::default(),
+            consumer: None,
+        };
+
+        let interest = server.parameterize(shape(Some(vec![1]), 1)).unwrap();
+
+        assert_eq!(
+            interest.name,
+            "names[1][{\"variable\":1,\"descending\":true}]"
+        );
+        assert!(interest.project.is_none());
+        assert!(interest.order_by.is_empty());
+
+        // projected variables must be bound by the rule, sort keys
+        // must be projected
+        assert!(server.parameterize(shape(Some(vec![5]), 1)).is_err());
+        assert!(server.parameterize(shape(Some(vec![1]), 0)).is_err());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        // merely ordered interests share the relation's arrangement
+        let ordered = server.parameterize(shape(None, 1)).unwrap();
+        let (send_ordered, ordered_results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&ordered.name, scope)
+                .unwrap()
+                .import_named(scope, &ordered.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_ordered.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 3, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // projected tuples are distinct
+        let mut received: Vec<_> = results.try_iter().collect();
+        server.sort_results(&interest.name, &mut received);
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![String("Mabel".to_string())], 0, 1),
+                (vec![String("Dipper".to_string())], 0, 1),
+            ]
+        );
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    4,
+                    ":name".to_string(),
+                    String("Soos".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        // results are sorted within each time only
+        let mut received: Vec<_> = ordered_results.try_iter().collect();
+        server.sort_results(&ordered.name, &mut received);
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+                (vec![Eid(3), String("Mabel".to_string())], 0, 1),
+                (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(4), String("Soos".to_string())], 1, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn windowed_interest() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?e ?n :where [?e :name ?n]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                }],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let interest = server
+            .parameterize(Interest {
+                name: "names".to_string(),
+                as_of: None,
+                bindings: vec![],
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: vec![SortKey {
+                    variable: 1,
+                    descending: false,
+                }],
+                window: Some(Window {
+                    offset: 1,
+                    limit: 2,
+                }),
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            interest.name,
+            "names[{\"variable\":1,\"descending\":false}]{\"offset\":1,\"limit\":2}"
+        );
+        assert!(interest.window.is_none());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        let name = |e, n: &str| TxData(1, e, ":name".to_string(), String(n.to_string()));
+
+        server
+            .transact(
+                vec![
+                    name(1, "Dipper"),
+                    name(2, "Mabel"),
+                    name(3, "Soos"),
+                    name(4, "Wendy"),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        server.sort_results(&interest.name, &mut received);
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+                (vec![Eid(3), String("Soos".to_string())], 0, 1),
+            ]
+        );
+
+        // only tuples entering or leaving the window are delivered
+        server.transact(vec![name(5, "Candy")], 0, 0).unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 1, 1),
+                (vec![Eid(3), String("Soos".to_string())], 1, -1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn windowed_interest_on_large_relations() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":age", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // [:find ?e ?a :where [?e :age ?a]]
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "ages".to_string(),
+                    plan: Plan::MatchA(0, ":age".to_string(), 1),
+                }],
+                publish: vec!["ages".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        let interest = server
+            .parameterize(Interest {
+                name: "ages".to_string(),
+                as_of: None,
+                bindings: vec![],
+                specialize: false,
+                restrict: None,
+                project: None,
+                order_by: vec![SortKey {
+                    variable: 1,
+                    descending: true,
+                }],
+                window: Some(Window {
+                    offset: 10,
+                    limit: 3,
+                }),
+                delivery: Default::default(),
+                consumer: None,
+            })
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .interest(&interest.name, scope)
+                .unwrap()
+                .import_named(scope, &interest.name)
+                .as_collection(|tuple, _| tuple.clone())
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        // many more tuples than buckets retain
+        let tx_data = (1..=1000)
+            .map(|e| TxData(1, e, ":age".to_string(), Number(e as i64)))
+            .collect();
+
+        server.transact(tx_data, 0, 0).unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        server.sort_results(&interest.name, &mut received);
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(990), Number(990)], 0, 1),
+                (vec![Eid(989), Number(989)], 0, 1),
+                (vec![Eid(988), Number(988)], 0, 1),
+            ]
+        );
+
+        // retracting a tuple before the window shifts it
+        server
+            .transact(
+                vec![TxData(-1, 1000, ":age".to_string(), Number(1000))],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(987), Number(987)], 1, 1),
+                (vec![Eid(990), Number(990)], 1, -1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn shutdown_completes_dataflows() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send((x.0.clone(), x.2)).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    1,
+                    ":name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.shutdown();
+
+        assert!(server
+            .transact(
+                vec![TxData(
+                    1,
+                    2,
+                    ":name".to_string(),
+                    String("Mabel".to_string())
+                )],
+                0,
+                0,
+            )
+            .is_err());
+
+        // With all inputs closed, the dataflow runs to completion.
+        while !server.probe.done() {
+            worker.step();
+        }
+
+        assert_eq!(
+            results.recv().unwrap(),
+            (vec![Eid(1), String("Dipper".to_string())], 1)
+        );
+        assert!(results.try_recv().is_err());
+    })
+    .unwrap();
+}
+
+#[test]
+fn diff_between_times() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_history: true,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server.test_single(
+                scope,
+                Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                },
+            );
+        });
+
+        let epochs = vec![
+            vec![TxData(
+                1,
+                1,
+                ":name".to_string(),
+                String("Dipper".to_string()),
+            )],
+            vec![TxData(
+                1,
+                2,
+                ":name".to_string(),
+                String("Mabel".to_string()),
+            )],
+            vec![
+                TxData(-1, 1, ":name".to_string(), String("Dipper".to_string())),
+                TxData(1, 3, ":name".to_string(), String("Soos".to_string())),
+            ],
+            vec![TxData(
+                1,
+                4,
+                ":name".to_string(),
+                String("Wendy".to_string()),
+            )],
+        ];
+
+        for (time, tx_data) in epochs.into_iter().enumerate() {
+            server.transact(tx_data, 0, 0).unwrap();
+            server.advance_domain(None, time as u64 + 1).unwrap();
+        }
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert!(worker
+            .dataflow::<u64, _, _>(|scope| server.diff("names", 2, 1, scope).map(|_| ()))
+            .is_err());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .diff("names", 0, 2, scope)
+                .unwrap()
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut server.probe);
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut changes: Vec<_> = results.try_iter().collect();
+        changes.sort();
+
+        assert_eq!(
+            changes,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 2, -1),
+                (vec![Eid(2), String("Mabel".to_string())], 2, 1),
+                (vec![Eid(3), String("Soos".to_string())], 2, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let config = Config {
+        snapshot_dir: Some(std::env::temp_dir()),
+        ..Default::default()
+    };
+    let path = format!("df-snapshot-{}", std::process::id());
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(config.clone());
+
+        // snapshots can't leave the snapshot directory
+        for name in &["../escape", "/tmp/escape", ""] {
+            let error = worker
+                .dataflow::<u64, _, _>(|scope| server.snapshot(name, scope))
+                .unwrap_err();
+            assert_eq!(error.category, "df.error.category/incorrect");
+        }
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                    TxData(1, 3, ":name".to_string(), String("Soos".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 1).unwrap();
+
+        server
+            .transact(
+                vec![TxData(
+                    -1,
+                    3,
+                    ":name".to_string(),
+                    String("Soos".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+
+        server.advance_domain(None, 2).unwrap();
+
+        worker
+            .dataflow::<u64, _, _>(|scope| server.snapshot(&path, scope))
+            .unwrap();
+
+        server.advance_domain(None, 3).unwrap();
+        worker.step_while(|| server.is_any_outdated());
+
+        // A fresh server with the same schema.
+        let mut restored = Server::<u64>::new(config.clone());
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            restored
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        restored
+            .restore(&path, worker.index(), worker.peers())
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            restored
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        // relations are implemented by now
+        let error = restored
+            .restore(&path, worker.index(), worker.peers())
+            .unwrap_err();
+        assert_eq!(error.category, "df.error.category/conflict");
+
+        restored.advance_domain(None, 1).unwrap();
+        worker.step_while(|| restored.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn implementation_size() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                    Rule {
+                        name: "named".to_string(),
+                        plan: Plan::Project(Project {
+                            variables: vec![0],
+                            plan: Box::new(Plan::NameExpr(vec![0, 1], "names".to_string())),
+                        }),
+                    },
+                ],
+                publish: vec!["named".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        assert_eq!(server.implementation_size("named"), 2);
+        assert_eq!(server.implementation_size("names"), 1);
+        assert_eq!(server.implementation_size("unknown"), 0);
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("named", scope).unwrap();
+        });
+
+        assert_eq!(server.implementation_size("named"), 0);
+    })
+    .unwrap();
+}
+
+#[test]
+fn cardinalities_after_validation() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        let invalid = vec![
+            TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+            TxData(1, 2, ":unknown".to_string(), String("Mabel".to_string())),
+        ];
+
+        // neither the owner nor any other worker counts rejected data
+        assert!(server.transact(invalid.clone(), 0, 0).is_err());
+        assert!(server.transact(invalid, 1, 0).is_err());
+        assert_eq!(server.context.cardinalities.get(":name"), None);
+
+        let valid = vec![TxData(
+            1,
+            1,
+            ":name".to_string(),
+            String("Dipper".to_string()),
+        )];
+
+        server.transact(valid.clone(), 0, 0).unwrap();
+        server.transact_at(valid, 0, 1, 0).unwrap();
+        assert_eq!(server.context.cardinalities.get(":name"), Some(&2));
+    })
+    .unwrap();
+}
+
+#[test]
+fn missing_dependencies() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        assert_eq!(
+            server.missing_dependencies("names"),
+            vec!["names".to_string()]
+        );
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                }],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        assert!(server.missing_dependencies("names").is_empty());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("names", scope).unwrap();
+        });
+
+        assert!(server.missing_dependencies("names").is_empty());
+    })
+    .unwrap();
+}
+
+#[test]
+fn invalidated_by() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in [":name", ":age"].iter() {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        server
+            .register(Register {
+                rules: vec![
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                    Rule {
+                        name: "named".to_string(),
+                        plan: Plan::Project(Project {
+                            variables: vec![0],
+                            plan: Box::new(Plan::NameExpr(vec![0, 1], "names".to_string())),
+                        }),
+                    },
+                    Rule {
+                        name: "ages".to_string(),
+                        plan: Plan::MatchA(0, ":age".to_string(), 1),
+                    },
+                    Rule {
+                        name: "aged".to_string(),
+                        plan: Plan::Hector(Hector {
+                            variables: vec![0, 1, 2],
+                            bindings: vec![
+                                Binding::Attribute(AttributeBinding {
+                                    symbols: (0, 1),
+                                    source_attribute: "names".to_string(),
+                                }),
+                                Binding::Attribute(AttributeBinding {
+                                    symbols: (0, 2),
+                                    source_attribute: "ages".to_string(),
+                                }),
+                            ],
+                        }),
+                    },
+                ],
+                publish: vec!["named".to_string(), "ages".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        for name in ["named", "ages", "aged", ":name"].iter() {
+            server.interests.insert(name.to_string(), vec![0]);
+        }
+
+        assert_eq!(
+            server.invalidated_by(":name"),
+            vec![":name".to_string(), "aged".to_string(), "named".to_string()]
+        );
+        assert_eq!(
+            server.invalidated_by("names"),
+            vec!["aged".to_string(), "named".to_string()]
+        );
+        assert_eq!(
+            server.invalidated_by(":age"),
+            vec!["aged".to_string(), "ages".to_string()]
+        );
+        assert!(server.invalidated_by(":unknown").is_empty());
+    })
+    .unwrap();
+}
+
+#[cfg(feature = "arrow-ipc")]
+#[test]
+fn arrow_encoding() {
+    use arrow::array::{Int64Array, StringArray, UInt64Array};
+    use arrow::ipc::reader::StreamReader;
+
+    use declarative_dataflow::sinks::arrow_ipc;
+
+    let results = vec![
+        (vec![Eid(1), String("Dipper".to_string())], 1, 1),
+        (vec![Eid(2), String("Mabel".to_string())], 1, -1),
+    ];
+
+    let bytes = arrow_ipc::encode(&[0, 1], &results).unwrap();
+    let mut reader = StreamReader::try_new(std::io::Cursor::new(bytes)).unwrap();
+
+    let field_names: Vec<_> = reader
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect();
+
+    assert_eq!(field_names, vec!["?0", "?1", "df.time", "df.diff"]);
+
+    let batch = reader.next().unwrap().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+
+    let eids = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<UInt64Array>()
+        .unwrap();
+    let names = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    let diffs = batch
+        .column(3)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+
+    assert_eq!(
+        (eids.value(0), names.value(0), diffs.value(0)),
+        (1, "Dipper", 1)
+    );
+    assert_eq!(
+        (eids.value(1), names.value(1), diffs.value(1)),
+        (2, "Mabel", -1)
+    );
+
+    let mixed = vec![
+        (vec![Eid(1), String("Dipper".to_string())], 1, 1),
+        (vec![Eid(2)], 1, 1),
+    ];
+
+    let error = arrow_ipc::encode(&[0, 1], &mixed).unwrap_err();
+    assert_eq!(error.category, "df.error.category/incorrect");
+}
+
+#[test]
+fn status() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server
+            .register(Register {
+                rules: vec![Rule {
+                    name: "names".to_string(),
+                    plan: Plan::MatchA(0, ":name".to_string(), 1),
+                }],
+                publish: vec!["names".to_string()],
+                conflict_policy: Default::default(),
+                replicate: Vec::new(),
+                distinct: Vec::new(),
+                metadata: Default::default(),
+            })
+            .unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.interest("names", scope).unwrap();
+        });
+
+        server
+            .transact(
+                vec![
+                    TxData(1, 1, ":name".to_string(), String("Dipper".to_string())),
+                    TxData(1, 2, ":name".to_string(), String("Mabel".to_string())),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        server.latencies.record(Duration::from_millis(4));
+        server.latencies.record(Duration::from_millis(2));
+
+        server.interests.insert("names".to_string(), vec![1, 2]);
+
+        let status = server.status();
+
+        assert_eq!(status.time, 1);
+        assert_eq!(
+            status
+                .arrangements
+                .iter()
+                .map(|arrangement| (arrangement.name.as_str(), arrangement.updates))
+                .collect::<Vec<_>>(),
+            vec![(":name", 2), ("names", 2)]
+        );
+        assert!(status.sources.is_empty());
+        assert_eq!(status.subscriptions, vec![("names".to_string(), 2)]);
+        assert_eq!(status.latencies.commands, 2);
+        assert_eq!(status.latencies.mean(), Duration::from_millis(3));
+        assert_eq!(status.latencies.max, Duration::from_millis(4));
+        assert_eq!(status.latencies.last, Duration::from_millis(2));
+    })
+    .unwrap();
+}
+
+#[test]
+fn logging() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        server.logging = Some(Logging::install(worker.index(), &mut worker.log_register()));
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in ATTRIBUTES.iter() {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server.check_logs().unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "operators".to_string(),
+                        plan: Plan::MatchA(0, "df.timely.operates/name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.0[1].clone()).unwrap();
+                });
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let names: Vec<Value> = results.try_iter().collect();
+
+        assert!(names.contains(&String("Counts(:name)".to_string())));
+        assert!(names.contains(&String("Proposals(:name)".to_string())));
+    })
+    .unwrap();
+}
+
+#[test]
+fn logging_skips_own_dataflows() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        server.logging = Some(Logging::install(worker.index(), &mut worker.log_register()));
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in ATTRIBUTES.iter() {
+                server.exclude_from_logs(name, scope);
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server.exclude_from_logs(":name", scope);
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        server.check_logs().unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "operators".to_string(),
+                        plan: Plan::MatchA(0, "df.timely.operates/name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.0[1].clone()).unwrap();
+                });
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let names: Vec<Value> = results.try_iter().collect();
+
+        assert!(names.contains(&String("Counts(:name)".to_string())));
+        assert!(!names.contains(&String("Counts(df.timely.operates/name)".to_string())));
+    })
+    .unwrap();
+}
+
+#[test]
+fn logging_merges() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let (send_results, results) = channel();
+
+        server.logging = Some(Logging::install(worker.index(), &mut worker.log_register()));
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            for name in ATTRIBUTES.iter() {
+                server
+                    .context
+                    .internal
+                    .create_attribute(name, AttributeSemantics::Raw, scope)
+                    .unwrap();
+            }
+
+            server
+                .context
+                .internal
+                .create_attribute(":age", AttributeSemantics::Raw, scope)
+                .unwrap();
+        });
+
+        // every epoch adds another batch to each arrangement of :age
+        for epoch in 1..9 {
+            server
+                .transact(
+                    vec![TxData(1, epoch, ":age".to_string(), Number(epoch as i64))],
+                    0,
+                    0,
+                )
+                .unwrap();
+            server.advance_domain(None, epoch).unwrap();
+            worker.step_while(|| server.is_any_outdated());
+            server.check_logs().unwrap();
+        }
+
+        server.advance_domain(None, 9).unwrap();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "merges".to_string(),
+                        plan: Plan::MatchA(0, "df.differential.merge/merged".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.0[1].clone()).unwrap();
+                });
+        });
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let merged: Vec<Value> = results.try_iter().collect();
+
+        assert!(!merged.is_empty());
+    })
+    .unwrap();
+}
@@ -0,0 +1,100 @@
+use declarative_dataflow::plan::Project;
+use declarative_dataflow::server::replication::{is_replicated, Replica, ReplicationLog};
+use declarative_dataflow::server::{CreateAttribute, Interest, Register, RegisterSource, Request};
+use declarative_dataflow::sources::{JsonFile, Source};
+use declarative_dataflow::{AttributeSemantics, Plan, Rule, TxData, Value};
+
+#[test]
+fn follow_primary() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-replication-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let create = Request::CreateAttribute(CreateAttribute {
+        name: ":name".to_string(),
+        semantics: AttributeSemantics::Raw,
+        retention: None,
+        value_type: None,
+        append_only: false,
+        metadata: Default::default(),
+    });
+    let register = Request::Register(Register {
+        rules: vec![Rule {
+            name: "names".to_string(),
+            plan: Plan::Project(Project {
+                variables: vec![1],
+                plan: Box::new(Plan::MatchA(0, ":name".to_string(), 1)),
+            }),
+        }],
+        publish: vec!["names".to_string()],
+        conflict_policy: Default::default(),
+        replicate: vec![],
+        distinct: vec![],
+        metadata: Default::default(),
+    });
+    let transact = Request::TransactAt(
+        1,
+        vec![TxData(
+            1,
+            1,
+            ":name".to_string(),
+            Value::String("Dipper".to_string()),
+        )],
+    );
+    let source = Request::RegisterSource(RegisterSource {
+        names: vec![":age".to_string()],
+        source: Source::JsonFile(JsonFile {
+            path: "people.json".to_string(),
+            follow: false,
+            eid_key: None,
+        }),
+        drive_domain: false,
+        append_only: false,
+    });
+    let interest = Request::Interest(Interest {
+        name: "names".to_string(),
+        as_of: None,
+        bindings: vec![],
+        specialize: false,
+        restrict: None,
+        project: None,
+        order_by: Vec::new(),
+        window: None,
+        delivery: Default::default(),
+        consumer: None,
+    });
+
+    assert!(is_replicated(&transact));
+    assert!(is_replicated(&Request::Batch(vec![
+        interest.clone(),
+        transact.clone()
+    ])));
+    assert!(!is_replicated(&interest));
+    assert!(!is_replicated(&source));
+
+    let mut log = ReplicationLog::open(&path).unwrap();
+    log.record(10, &[create.clone(), register.clone()]).unwrap();
+
+    let mut replica = Replica::open(&path).unwrap();
+    assert_eq!(
+        replica.poll().unwrap(),
+        vec![(10, vec![create.clone(), register.clone()])]
+    );
+    assert!(replica.poll().unwrap().is_empty());
+
+    // Writes appended later are picked up, one command at a time.
+    log.record(11, &[transact.clone()]).unwrap();
+    log.record(12, &[Request::AdvanceDomain(None, 2)]).unwrap();
+    log.record(13, &[]).unwrap();
+
+    assert_eq!(
+        replica.poll().unwrap(),
+        vec![
+            (11, vec![transact]),
+            (12, vec![Request::AdvanceDomain(None, 2)])
+        ]
+    );
+    let _ = std::fs::remove_file(&path);
+}
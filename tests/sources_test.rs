@@ -0,0 +1,1045 @@
+use std::fs;
+use std::sync::mpsc::channel;
+
+use num_rational::Rational32;
+
+use timely::dataflow::ProbeHandle;
+use timely::Configuration;
+
+use declarative_dataflow::keys::key_eid;
+use declarative_dataflow::server::simulation::Simulate;
+use declarative_dataflow::server::{Config, RegisterSource, Server};
+use declarative_dataflow::sources::{CsvFile, DatomicLog, JsonFile, Source};
+use declarative_dataflow::{Plan, Rule, Value};
+use Value::{Eid, String};
+
+#[test]
+fn datomic_log_replay() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-datomic-log-{}.jsonl",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"{"t": 1000, "data": [[1, ":name", "Dipper", 1000, true], [2, ":name", "Mabel", 1000, true], [1, ":friend", 2, 1000, true]]}"#,
+            "\n",
+            r#"[[2, ":name", "Mabel", 1001, false], [2, ":name", "Mabel Pines", 1001, true], [3, ":ignored", 1, 1001]]"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+        let (send_friends, friends) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec![":name".to_string(), ":friend".to_string()],
+                        source: Source::DatomicLog(DatomicLog {
+                            path: path.clone(),
+                            refs: vec![":friend".to_string()],
+                            idents: Default::default(),
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "friends".to_string(),
+                        plan: Plan::MatchA(0, ":friend".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_friends.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut probe);
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut probe);
+        });
+
+        while probe.less_than(&2) {
+            worker.step();
+        }
+
+        assert_eq!(
+            friends.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), Eid(2)], 0, 1)]
+        );
+
+        let mut names: Vec<_> = results.try_iter().collect();
+        names.sort();
+
+        assert_eq!(
+            names,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+                (vec![Eid(2), String("Mabel".to_string())], 1, -1),
+                (vec![Eid(2), String("Mabel Pines".to_string())], 1, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn datomic_log_attribute_forms() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-datomic-forms-{}.jsonl",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"[[1, ":name", "Dipper"], [2, "name", "Mabel"], [3, "~:name", "Soos"]]"#,
+            "\n",
+            r#"[[4, 72, "Wendy"], [1, 73, 2], [5, 74, "Stan"]]"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let source = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec![":name".to_string(), ":friend".to_string()],
+                        source: Source::DatomicLog(DatomicLog {
+                            path: source.clone(),
+                            refs: vec!["friend".to_string()],
+                            idents: vec![(72, ":name".to_string()), (73, "friend".to_string())]
+                                .into_iter()
+                                .collect(),
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            for (name, a) in [("names", ":name"), ("friends", ":friend")].iter() {
+                let send_results = send_results.clone();
+
+                server
+                    .test_single(
+                        scope,
+                        Rule {
+                            name: name.to_string(),
+                            plan: Plan::MatchA(0, a.to_string(), 1),
+                        },
+                    )
+                    .inspect(move |x| send_results.send(x.clone()).unwrap())
+                    .probe_with(&mut probe);
+            }
+        });
+
+        while probe.less_than(&2) {
+            worker.step();
+        }
+
+        let mut datoms: Vec<_> = results.try_iter().collect();
+        datoms.sort();
+
+        let mut expected = vec![
+            (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+            (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+            (vec![Eid(3), String("Soos".to_string())], 0, 1),
+            (vec![Eid(4), String("Wendy".to_string())], 1, 1),
+            (vec![Eid(1), Eid(2)], 1, 1),
+        ];
+        expected.sort();
+
+        assert_eq!(datoms, expected);
+
+        // logs that can't be opened are reported, rather than
+        // bringing down the worker
+        let missing = worker.dataflow::<u64, _, _>(|scope| {
+            server.register_source(
+                RegisterSource {
+                    names: vec![":missing".to_string()],
+                    source: Source::DatomicLog(DatomicLog {
+                        path: format!("{}.missing", source),
+                        refs: vec![],
+                        idents: Default::default(),
+                    }),
+                    drive_domain: false,
+                    append_only: false,
+                },
+                scope,
+            )
+        });
+
+        assert_eq!(missing.unwrap_err().category, "df.error.category/not-found");
+    })
+    .unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn append_only_source() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-append-only-{}.jsonl",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"[[1, ":name", "Dipper", 1000, true], [2, ":name", "Mabel", 1000, true]]"#,
+            "\n",
+            r#"[[2, ":name", "Mabel", 1001, false], [2, ":name", "Mabel Pines", 1001, true]]"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec![":name".to_string()],
+                        source: Source::DatomicLog(DatomicLog {
+                            path: path.clone(),
+                            refs: vec![],
+                            idents: Default::default(),
+                        }),
+                        drive_domain: false,
+                        append_only: true,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                })
+                .probe_with(&mut probe);
+        });
+
+        assert!(server.context.internal.is_append_only(":name"));
+
+        while probe.less_than(&2) {
+            worker.step();
+        }
+
+        // retractions read from the source are dropped
+        let mut names: Vec<_> = results.try_iter().collect();
+        names.sort();
+
+        assert_eq!(
+            names,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(2), String("Mabel".to_string())], 0, 1),
+                (vec![Eid(2), String("Mabel Pines".to_string())], 1, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn source_watermarks() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-watermarks-{}.jsonl",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"[[1, ":name", "Dipper", 1000, true]]"#,
+            "\n",
+            r#"[[2, ":name", "Mabel", 1001, true]]"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let source = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec![":name".to_string()],
+                        source: Source::DatomicLog(DatomicLog {
+                            path: source.clone(),
+                            refs: vec![],
+                            idents: Default::default(),
+                        }),
+                        drive_domain: true,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+        });
+
+        assert_eq!(server.source_watermark(), Some(0));
+        assert!(server.check_sources(0, 0).unwrap().is_empty());
+
+        let mut completed = Vec::new();
+        while completed.is_empty() {
+            worker.step();
+            completed = server.check_sources(0, 0).unwrap();
+        }
+
+        assert_eq!(completed, vec![":name".to_string()]);
+        assert_eq!(server.source_watermark(), None);
+        assert!(server.check_sources(0, 0).unwrap().is_empty());
+    })
+    .unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn simulation_controls_domain() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-simulation-{}.jsonl",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"[[1, ":name", "Dipper", 1000, true]]"#,
+            "\n",
+            r#"[[2, ":name", "Mabel", 1001, true]]"#,
+            "\n",
+            r#"[[3, ":name", "Soos", 1002, true]]"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let source = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Config {
+            enable_simulation: true,
+            ..Default::default()
+        });
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec![":name".to_string()],
+                        source: Source::DatomicLog(DatomicLog {
+                            path: source.clone(),
+                            refs: vec![],
+                            idents: Default::default(),
+                        }),
+                        drive_domain: true,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, ":name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| send_results.send(x.clone()).unwrap())
+                .probe_with(&mut probe);
+        });
+
+        let name = |e, name: &str| vec![Eid(e), String(name.to_string())];
+
+        // Follows the sources, as the server does between commands.
+        let mut follow = |server: &mut Server<u64>, steps: usize| {
+            for _ in 0..steps {
+                worker.step();
+
+                if let Some(target) = server.domain_target() {
+                    if *server.context.internal.time() < target {
+                        server.advance_domain(None, target).unwrap();
+                    }
+                }
+            }
+        };
+
+        // Simulations start out paused.
+        follow(&mut server, 100);
+        assert_eq!(*server.context.internal.time(), 0);
+        assert!(results.try_recv().is_err());
+
+        // Sources deliver nothing beyond the simulated time.
+        server.simulate(Simulate::Step(1)).unwrap();
+        follow(&mut server, 100);
+        assert_eq!(*server.context.internal.time(), 1);
+        assert!(!probe.less_than(&1));
+        assert!(probe.less_than(&2));
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(name(1, "Dipper"), 0, 1)]
+        );
+
+        server.simulate(Simulate::Jump(10)).unwrap();
+        follow(&mut server, 100);
+        assert_eq!(*server.context.internal.time(), 10);
+        assert!(!probe.less_than(&10));
+
+        // The domain is never rewound.
+        assert_eq!(
+            server.advance_domain(None, 5).unwrap_err().category,
+            "df.error.category/conflict"
+        );
+
+        let mut released = results.try_iter().collect::<Vec<_>>();
+        released.sort();
+        assert_eq!(
+            released,
+            vec![(name(2, "Mabel"), 1, 1), (name(3, "Soos"), 2, 1)]
+        );
+
+        assert!(Server::<u64>::new(Default::default())
+            .simulate(Simulate::Pause)
+            .is_err());
+    })
+    .unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn json_file_flattening() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-nested-{}.json",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"{"name": "Dipper", "age": 12, "active": true, "address": {"town": "Gravity Falls", "zip": null}, "tags": ["twin", "detective"]}"#,
+            "\n",
+            r#"{"name": "Mabel", "height": 1.5}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        let names = vec![
+            "age",
+            "active",
+            "address/town",
+            "address/zip",
+            "tags",
+            "height",
+        ];
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: names.iter().map(|name| name.to_string()).collect(),
+                        source: Source::JsonFile(JsonFile {
+                            path: path.clone(),
+                            follow: false,
+                            eid_key: None,
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            for name in names.iter() {
+                let send_results = send_results.clone();
+                let attribute = name.to_string();
+
+                server
+                    .test_single(
+                        scope,
+                        Rule {
+                            name: format!("{}-values", name),
+                            plan: Plan::MatchA(0, name.to_string(), 1),
+                        },
+                    )
+                    .inspect(move |(tuple, _time, diff)| {
+                        send_results
+                            .send((attribute.clone(), tuple.clone(), *diff))
+                            .unwrap();
+                    })
+                    .probe_with(&mut probe);
+            }
+        });
+
+        server.advance_domain(None, 1).unwrap();
+
+        while probe.less_than(&1) {
+            worker.step();
+        }
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                ("active".to_string(), vec![Eid(0), Value::Bool(true)], 1),
+                (
+                    "address/town".to_string(),
+                    vec![Eid(0), String("Gravity Falls".to_string())],
+                    1
+                ),
+                ("age".to_string(), vec![Eid(0), Value::Number(12)], 1),
+                (
+                    "height".to_string(),
+                    vec![Eid(1), Value::Rational32(Rational32::new(3, 2))],
+                    1
+                ),
+                (
+                    "tags".to_string(),
+                    vec![Eid(0), String("detective".to_string())],
+                    1
+                ),
+                (
+                    "tags".to_string(),
+                    vec![Eid(0), String("twin".to_string())],
+                    1
+                ),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn json_file_following() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-followed-{}.json",
+        std::process::id()
+    ));
+
+    fs::write(&path, concat!(r#"{"name": "Dipper"}"#, "\n")).unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec!["name".to_string()],
+                        source: Source::JsonFile(JsonFile {
+                            path: path.clone(),
+                            follow: true,
+                            eid_key: None,
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, "name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| send_results.send(x.clone()).unwrap())
+                .probe_with(&mut probe);
+        });
+
+        server.advance_domain(None, 10).unwrap();
+
+        while probe.less_than(&1) {
+            worker.step();
+        }
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(0), String("Dipper".to_string())], 0, 1)]
+        );
+
+        // The source stays alive, holding back the next epoch until
+        // further lines are appended.
+        for _ in 0..100 {
+            worker.step();
+        }
+        assert!(probe.less_than(&2));
+
+        // A line appended without its terminator is only read once it
+        // has been completed.
+        {
+            use std::io::Write;
+
+            let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(br#"{"name": "Ma"#).unwrap();
+            file.flush().unwrap();
+
+            for _ in 0..100 {
+                worker.step();
+            }
+            assert!(probe.less_than(&2));
+
+            file.write_all(concat!(r#"bel"}"#, "\n").as_bytes())
+                .unwrap();
+            file.flush().unwrap();
+        }
+
+        while probe.less_than(&2) {
+            worker.step();
+        }
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), String("Mabel".to_string())], 1, 1)]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn json_file_malformed_lines() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-malformed-{}.json",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"{"name": "Dipper"}"#,
+            "\n",
+            r#"{"name": "Mab"#,
+            "\n",
+            r#"{"name": "Soos"}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let source = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec!["name".to_string()],
+                        source: Source::JsonFile(JsonFile {
+                            path: source.clone(),
+                            follow: false,
+                            eid_key: None,
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, "name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| send_results.send(x.clone()).unwrap())
+                .probe_with(&mut probe);
+        });
+
+        server.advance_domain(None, 1).unwrap();
+
+        while probe.less_than(&1) {
+            worker.step();
+        }
+
+        // the malformed line is skipped, later ones are still read
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(0), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(2), String("Soos".to_string())], 0, 1),
+            ]
+        );
+    })
+    .unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn json_file_eid_keys() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-keyed-{}.json",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"{"id": "user/dipper", "name": "Dipper"}"#,
+            "\n",
+            r#"{"name": "Nobody"}"#,
+            "\n",
+            r#"{"id": "user/dipper", "age": 12}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec!["name".to_string(), "age".to_string()],
+                        source: Source::JsonFile(JsonFile {
+                            path: path.clone(),
+                            follow: false,
+                            eid_key: Some("id".to_string()),
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            for name in ["name", "age"].iter() {
+                let send_results = send_results.clone();
+
+                server
+                    .test_single(
+                        scope,
+                        Rule {
+                            name: format!("{}-values", name),
+                            plan: Plan::MatchA(0, name.to_string(), 1),
+                        },
+                    )
+                    .inspect(move |(tuple, _time, diff)| {
+                        send_results.send((tuple.clone(), *diff)).unwrap();
+                    })
+                    .probe_with(&mut probe);
+            }
+        });
+
+        server.advance_domain(None, 1).unwrap();
+
+        while probe.less_than(&1) {
+            worker.step();
+        }
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        let dipper = key_eid("user/dipper");
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(dipper), String("Dipper".to_string())], 1),
+                (vec![Eid(dipper), Value::Number(12)], 1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn csv_file_sampling() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-sample-{}.csv",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            "1,Dipper,12\n",
+            "2,Mabel,twelve\n",
+            "x,Soos,22\n",
+            "4,Wendy,15\n"
+        ),
+    )
+    .unwrap();
+
+    let source = Source::CsvFile(CsvFile {
+        path: path.to_str().unwrap().to_string(),
+        separator: ',',
+        schema: vec![(1, String(Default::default())), (2, Value::Number(0))],
+        follow: false,
+        hash_eids: false,
+    });
+
+    let names = vec!["name".to_string(), "age".to_string()];
+    let sample = source.sample(&names, 3).unwrap();
+
+    assert_eq!(sample.records, 3);
+    assert_eq!(sample.attributes["name"]["String"], 3);
+    assert_eq!(sample.attributes["age"]["Number"], 2);
+    assert_eq!(
+        sample.errors,
+        vec![
+            (1, "twelve is not a number".to_string()),
+            (2, "x is not a eid".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn csv_file_malformed_lines() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-malformed-{}.csv",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!("1,Dipper,12\n", "2,Mabel,twelve\n", "3\n", "4,Soos,22\n"),
+    )
+    .unwrap();
+
+    let path = path.to_str().unwrap().to_string();
+
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+        let mut probe = ProbeHandle::new();
+        let (send_results, results) = channel();
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .register_source(
+                    RegisterSource {
+                        names: vec!["name".to_string(), "age".to_string()],
+                        source: Source::CsvFile(CsvFile {
+                            path: path.clone(),
+                            separator: ',',
+                            schema: vec![(1, String(Default::default())), (2, Value::Number(0))],
+                            follow: true,
+                            hash_eids: false,
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "names".to_string(),
+                        plan: Plan::MatchA(0, "name".to_string(), 1),
+                    },
+                )
+                .inspect(move |x| send_results.send(x.clone()).unwrap())
+                .probe_with(&mut probe);
+        });
+
+        server.advance_domain(None, 10).unwrap();
+
+        while probe.less_than(&1) {
+            worker.step();
+        }
+
+        // lines that fail to parse are skipped as a whole
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), String("Dipper".to_string())], 0, 1),
+                (vec![Eid(4), String("Soos".to_string())], 0, 1),
+            ]
+        );
+
+        // A multi-byte character appended in two steps is only
+        // decoded once the line is complete.
+        {
+            use std::io::Write;
+
+            let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(b"5,Andr\xc3").unwrap();
+            file.flush().unwrap();
+
+            for _ in 0..100 {
+                worker.step();
+            }
+            assert!(probe.less_than(&2));
+
+            file.write_all(b"\xa9,30\n").unwrap();
+            file.flush().unwrap();
+        }
+
+        while probe.less_than(&2) {
+            worker.step();
+        }
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(5), String("Andr\u{e9}".to_string())], 1, 1)]
+        );
+
+        fs::remove_file(&path).unwrap();
+    })
+    .unwrap();
+}
+
+#[test]
+fn json_file_sampling() {
+    let path = std::env::temp_dir().join(format!(
+        "declarative-dataflow-sample-{}.json",
+        std::process::id()
+    ));
+
+    fs::write(
+        &path,
+        concat!(
+            r#"{"name": "Dipper", "age": 12}"#,
+            "\n",
+            r#"{"name": "Mabel", "age": "twelve"}"#,
+            "\n",
+            r#"{"name": "#,
+            "\n",
+        ),
+    )
+    .unwrap();
+
+    let source = Source::JsonFile(JsonFile {
+        path: path.to_str().unwrap().to_string(),
+        follow: false,
+        eid_key: None,
+    });
+
+    let names = vec!["name".to_string(), "nmae".to_string()];
+    let sample = source.sample(&names, 10).unwrap();
+
+    assert_eq!(sample.records, 3);
+    assert_eq!(sample.attributes["name"]["String"], 2);
+    assert_eq!(sample.attributes["age"]["Number"], 1);
+    assert_eq!(sample.attributes["age"]["String"], 1);
+    assert!(sample.attributes["nmae"].is_empty());
+    assert_eq!(sample.errors.len(), 1);
+    assert_eq!(sample.errors[0].0, 2);
+}
+
+#[test]
+fn missing_source_files() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut server = Server::<u64>::new(Default::default());
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            let error = server
+                .register_source(
+                    RegisterSource {
+                        names: vec!["name".to_string()],
+                        source: Source::CsvFile(CsvFile {
+                            path: "/nonexistent/declarative-dataflow.csv".to_string(),
+                            separator: ',',
+                            schema: vec![(1, String(Default::default()))],
+                            follow: false,
+                            hash_eids: false,
+                        }),
+                        drive_domain: false,
+                        append_only: false,
+                    },
+                    scope,
+                )
+                .unwrap_err();
+
+            assert_eq!(error.category, "df.error.category/not-found");
+        });
+    })
+    .unwrap();
+}
@@ -0,0 +1,146 @@
+#![cfg(feature = "sql")]
+
+use declarative_dataflow::harness::Harness;
+use declarative_dataflow::server::{parse_requests, ConflictPolicy, Request};
+use declarative_dataflow::sql::{parse, parse_select};
+use declarative_dataflow::{AttributeSemantics, TxData, Value};
+use Value::{Eid, Number, String};
+
+fn zoo() -> Harness {
+    let datom = |e, a: &str, v| TxData(1, e, a.to_string(), v);
+    let name = |n: &str| String(n.to_string());
+
+    Harness::new()
+        .attribute("person/name", AttributeSemantics::Raw)
+        .attribute("person/age", AttributeSemantics::Raw)
+        .attribute("pet/owner", AttributeSemantics::Raw)
+        .attribute("pet/name", AttributeSemantics::Raw)
+        .update(0, datom(1, "person/name", name("Dipper")))
+        .update(0, datom(1, "person/age", Number(12)))
+        .update(0, datom(2, "person/name", name("Mabel")))
+        .update(0, datom(2, "person/age", Number(12)))
+        .update(0, datom(3, "person/name", name("Stan")))
+        .update(0, datom(3, "person/age", Number(60)))
+        .update(0, datom(10, "pet/owner", Eid(2)))
+        .update(0, datom(10, "pet/name", name("Waddles")))
+        .update(0, datom(11, "pet/owner", Eid(3)))
+        .update(0, datom(11, "pet/name", name("Gompers")))
+        .update(0, datom(12, "pet/owner", Eid(3)))
+        .update(0, datom(12, "pet/name", name("Fluffy")))
+}
+
+#[test]
+fn select_join_where() {
+    let plan = parse_select(
+        "SELECT p.name, t.name FROM person p JOIN pet AS t ON t.owner = p.id WHERE p.age >= 18",
+    )
+    .unwrap();
+
+    let epochs = zoo().run(&plan).unwrap();
+
+    assert_eq!(
+        epochs[&0],
+        vec![
+            (
+                vec![String("Stan".to_string()), String("Fluffy".to_string())],
+                1
+            ),
+            (
+                vec![String("Stan".to_string()), String("Gompers".to_string())],
+                1
+            ),
+        ]
+    );
+
+    // entity ids are compared as such
+    let plan = parse_select("select name from person where id = 1").unwrap();
+    let epochs = zoo().run(&plan).unwrap();
+
+    assert_eq!(epochs[&0], vec![(vec![String("Dipper".to_string())], 1)]);
+}
+
+#[test]
+fn select_group_by() {
+    let plan = parse_select("SELECT t.owner, COUNT(*) FROM pet t GROUP BY t.owner").unwrap();
+    let epochs = zoo().run(&plan).unwrap();
+
+    assert_eq!(
+        epochs[&0],
+        vec![(vec![Eid(2), Number(1)], 1), (vec![Eid(3), Number(2)], 1)]
+    );
+
+    // rows are counted per group, whichever table it is keyed by
+    for query in &[
+        "SELECT t.owner, COUNT(*) FROM person p JOIN pet t ON t.owner = p.id GROUP BY t.owner",
+        "SELECT p.id, COUNT(*) FROM person p JOIN pet t ON t.owner = p.id GROUP BY p.id",
+    ] {
+        let plan = parse_select(query).unwrap();
+        let epochs = zoo().run(&plan).unwrap();
+
+        assert_eq!(
+            epochs[&0],
+            vec![(vec![Eid(2), Number(1)], 1), (vec![Eid(3), Number(2)], 1)],
+            "{}",
+            query
+        );
+    }
+
+    // rows agreeing on the aggregated column are counted separately
+    let plan = parse_select("SELECT COUNT(age) FROM person").unwrap();
+    let epochs = zoo().run(&plan).unwrap();
+
+    assert_eq!(epochs[&0], vec![(vec![Number(3)], 1)]);
+}
+
+#[test]
+fn statements() {
+    let requests = parse(
+        "CREATE MATERIALIZED VIEW adults AS SELECT name FROM person WHERE age >= 18; TAIL adults;",
+    )
+    .unwrap();
+
+    match &requests[..] {
+        [Request::Register(register), Request::Interest(interest)] => {
+            assert_eq!(register.rules[0].name, "adults");
+            assert_eq!(register.publish, vec!["adults".to_string()]);
+            assert_eq!(register.conflict_policy, ConflictPolicy::Error);
+            assert_eq!(interest.name, "adults");
+        }
+        _ => panic!("unexpected requests {:?}", requests),
+    }
+
+    // bare queries are registered and subscribed to at once
+    let requests = parse_requests(r#"[{"Sql": "SELECT name FROM person"}, "Flush"]"#).unwrap();
+
+    match &requests[..] {
+        [Request::Register(register), Request::Interest(interest), Request::Flush] => {
+            assert_eq!(register.publish, vec![interest.name.clone()]);
+        }
+        _ => panic!("unexpected requests {:?}", requests),
+    }
+
+    match &parse("DROP VIEW adults").unwrap()[..] {
+        [Request::Unregister(name)] => assert_eq!(name, "adults"),
+        requests => panic!("unexpected requests {:?}", requests),
+    }
+}
+
+#[test]
+fn rejected_queries() {
+    let queries = vec![
+        // ambiguous columns
+        "SELECT name FROM person p JOIN pet t ON t.owner = p.id",
+        // columns neither grouped by nor aggregated
+        "SELECT name, COUNT(*) FROM person",
+        // tables not constrained by any column
+        "SELECT p.name FROM person p, pet t",
+        // syntax errors
+        "SELECT FROM person",
+        "SELECT name FROM person WHERE name = 'Dipper",
+    ];
+
+    for query in queries {
+        let error = parse_select(query).unwrap_err();
+        assert_eq!(error.category, "df.error.category/incorrect", "{}", query);
+    }
+}
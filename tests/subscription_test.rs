@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use declarative_dataflow::server::{parse_requests, CatchUp, Config, Delivery, Request, Server};
+use declarative_dataflow::Value::{Eid, Number};
+
+#[test]
+fn pause_and_resume() {
+    let mut server = Server::<u64>::new(Default::default());
+
+    // only existing subscriptions can be paused
+    assert!(server.pause("ages", 1).is_err());
+
+    server.interests.insert("ages".to_string(), vec![1, 2]);
+    server.pause("ages", 1).unwrap();
+
+    assert!(server.is_paused("ages", &1));
+    assert!(!server.is_paused("ages", &2));
+
+    server.hold_back(
+        "ages",
+        &[
+            (vec![Eid(100), Number(1)], 1, 1),
+            (vec![Eid(200), Number(2)], 1, 1),
+        ],
+    );
+    server.hold_back(
+        "ages",
+        &[
+            (vec![Eid(100), Number(1)], 2, -1),
+            (vec![Eid(100), Number(3)], 2, 1),
+        ],
+    );
+
+    // changes are consolidated, retractions cancel out
+    assert_eq!(
+        server.resume("ages", &1).unwrap(),
+        CatchUp::Results(vec![
+            (vec![Eid(100), Number(3)], 2, 1),
+            (vec![Eid(200), Number(2)], 1, 1),
+        ])
+    );
+
+    assert!(!server.is_paused("ages", &1));
+    assert!(server.resume("ages", &1).is_err());
+}
+
+#[test]
+fn pause_overflow() {
+    let mut server = Server::<u64>::new(Config {
+        paused_results_limit: 2,
+        ..Default::default()
+    });
+
+    server.interests.insert("ages".to_string(), vec![1]);
+    server.pause("ages", 1).unwrap();
+
+    server.hold_back(
+        "ages",
+        &[
+            (vec![Eid(100), Number(1)], 1, 1),
+            (vec![Eid(200), Number(2)], 1, 1),
+            (vec![Eid(300), Number(3)], 1, 1),
+        ],
+    );
+
+    assert_eq!(server.resume("ages", &1).unwrap(), CatchUp::Overflowed);
+
+    // clients start over from a snapshot, even on read-only connections
+    let requests = parse_requests(r#"[{"Resnapshot": "ages"}]"#).unwrap();
+    assert_eq!(requests, vec![Request::Resnapshot("ages".to_string())]);
+    assert!(requests[0].is_read_only());
+}
+
+#[test]
+fn coalesced_delivery() {
+    let mut server = Server::<u64>::new(Default::default());
+    let start = Instant::now();
+
+    server.set_delivery("ages", 1, Delivery::Ticks(2));
+    server.set_delivery("ages", 2, Delivery::Every(1000));
+    server.set_delivery("ages", 3, Delivery::Immediate);
+
+    assert!(server.is_coalescing("ages", &1));
+    assert!(server.is_coalescing("ages", &2));
+    assert!(!server.is_coalescing("ages", &3));
+
+    server.coalesce_results(
+        "ages",
+        &[
+            (vec![Eid(100), Number(1)], 1, 1),
+            (vec![Eid(200), Number(2)], 1, 1),
+        ],
+    );
+    server.coalesce_frontier("ages", &[2]);
+
+    // nothing is due yet
+    assert!(server.flush_coalesced(start).is_empty());
+
+    server.coalesce_results(
+        "ages",
+        &[
+            (vec![Eid(100), Number(1)], 2, -1),
+            (vec![Eid(100), Number(3)], 2, 1),
+            (vec![Eid(200), Number(2)], 2, -1),
+        ],
+    );
+    server.coalesce_frontier("ages", &[3]);
+
+    // changes within the window are consolidated
+    let expected = vec![(vec![Eid(100), Number(3)], 2, 1)];
+
+    assert_eq!(
+        server.flush_coalesced(start),
+        vec![(1, "ages".to_string(), expected.clone(), vec![3])]
+    );
+    assert_eq!(
+        server.flush_coalesced(Instant::now() + Duration::from_millis(1000)),
+        vec![(2, "ages".to_string(), expected, vec![3])]
+    );
+
+    // results are only due once the relation advances again
+    assert!(server
+        .flush_coalesced(Instant::now() + Duration::from_millis(5000))
+        .is_empty());
+
+    server.set_delivery("ages", 1, Delivery::Immediate);
+    assert!(!server.is_coalescing("ages", &1));
+}
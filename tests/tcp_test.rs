@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use declarative_dataflow::server::tcp::{
+    encode_frame, FrameDecoder, FramedConnection, MAX_FRAME_LENGTH,
+};
+
+/// Returns a client socket, and the server's end of its connection.
+fn connect() -> (TcpStream, FramedConnection) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (socket, _addr) = listener.accept().unwrap();
+    let socket = mio::net::TcpStream::from_stream(socket).unwrap();
+
+    (client, FramedConnection::new(socket))
+}
+
+#[test]
+fn split_frames() {
+    let mut bytes = encode_frame(b"[{\"Transact\": []}]");
+    bytes.extend(encode_frame(b""));
+    bytes.extend(encode_frame(b"[]"));
+
+    let mut decoder = FrameDecoder::new();
+    let mut messages = Vec::new();
+
+    // Frames may be split arbitrarily across reads.
+    for chunk in bytes.chunks(3) {
+        decoder.extend(chunk);
+
+        while let Some(message) = decoder.next_message().unwrap() {
+            messages.push(message);
+        }
+    }
+
+    assert_eq!(
+        messages,
+        vec![
+            b"[{\"Transact\": []}]".to_vec(),
+            b"".to_vec(),
+            b"[]".to_vec()
+        ]
+    );
+}
+
+#[test]
+fn oversized_frames() {
+    let mut decoder = FrameDecoder::new();
+    decoder.extend(&((MAX_FRAME_LENGTH + 1) as u32).to_be_bytes());
+    assert!(decoder.next_message().is_err());
+}
+
+#[test]
+fn oversized_frames_close_connections() {
+    let (mut client, mut conn) = connect();
+
+    // The frame is rejected by its prefix alone.
+    client
+        .write_all(&((MAX_FRAME_LENGTH + 1) as u32).to_be_bytes())
+        .unwrap();
+    client.write_all(&[0; 64]).unwrap();
+
+    for _ in 0..100 {
+        assert!(conn.read().is_empty());
+
+        if conn.is_closed() {
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert!(conn.is_closed());
+}
+
+#[test]
+fn slow_clients_are_dropped() {
+    let (_client, mut conn) = connect();
+    let message = vec![0; MAX_FRAME_LENGTH];
+
+    // Nothing is written, as if the client never read its results.
+    for _ in 0..3 {
+        conn.send(&message);
+    }
+    assert!(!conn.is_closed());
+
+    conn.send(&message);
+    assert!(conn.is_closed());
+    assert!(conn.events().is_writable());
+}
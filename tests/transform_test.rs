@@ -8,10 +8,12 @@ use timely::dataflow::operators::Operator;
 use timely::Configuration;
 
 use declarative_dataflow::binding::Binding;
+use declarative_dataflow::harness::Harness;
+use declarative_dataflow::keys::{key_eid, KeyEids};
 use declarative_dataflow::plan::{Function, Implementable, Transform};
 use declarative_dataflow::server::Server;
-use declarative_dataflow::{Aid, AttributeSemantics, Plan, Rule, TxData, Value};
-use Value::{Eid, Instant};
+use declarative_dataflow::{Aid, AttributeSemantics, Plan, Rule, TxData, Value, Var};
+use Value::{Eid, Instant, Number};
 
 struct Case {
     description: &'static str,
@@ -23,7 +25,7 @@ struct Case {
 fn dependencies(case: &Case) -> HashSet<Aid> {
     let mut deps = HashSet::new();
 
-    for binding in case.plan.into_bindings().iter() {
+    for binding in case.plan.into_bindings().unwrap().iter() {
         if let Binding::Attribute(binding) = binding {
             deps.insert(binding.source_attribute.clone());
         }
@@ -74,6 +76,118 @@ fn run_transform_cases() {
         ]],
     }];
 
+    cases.push(Case {
+        description: "[:find ?e ?greeting :where [?e :name ?n] [(str \"Hello, \" ?n) ?greeting]]",
+        plan: {
+            let (e, n, greeting) = (1, 2, 3);
+            let constants = vec![Some(Value::String("Hello, ".to_string())), None];
+            Plan::Transform(Transform {
+                variables: vec![n],
+                result_sym: greeting,
+                plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+                function: Function::CONCAT,
+                constants,
+            })
+        },
+        transactions: vec![vec![
+            TxData(
+                1,
+                1,
+                ":name".to_string(),
+                Value::String("Dipper".to_string()),
+            ),
+            TxData(
+                1,
+                2,
+                ":name".to_string(),
+                Value::String("Mabel".to_string()),
+            ),
+        ]],
+        expectations: vec![vec![
+            (
+                vec![
+                    Eid(1),
+                    Value::String("Dipper".to_string()),
+                    Value::String("Hello, Dipper".to_string()),
+                ],
+                0,
+                1,
+            ),
+            (
+                vec![
+                    Eid(2),
+                    Value::String("Mabel".to_string()),
+                    Value::String("Hello, Mabel".to_string()),
+                ],
+                0,
+                1,
+            ),
+        ]],
+    });
+
+    cases.push(Case {
+        description: "[:find ?e ?id :where [?e :key ?k] [(eid ?k) ?id]]",
+        plan: {
+            let (e, k, id) = (1, 2, 3);
+            Plan::Transform(Transform {
+                variables: vec![k],
+                result_sym: id,
+                plan: Box::new(Plan::MatchA(e, ":key".to_string(), k)),
+                function: Function::EID,
+                constants: vec![None],
+            })
+        },
+        transactions: vec![vec![TxData(
+            1,
+            1,
+            ":key".to_string(),
+            Value::String("user/dipper".to_string()),
+        )]],
+        expectations: vec![vec![(
+            vec![
+                Eid(1),
+                Value::String("user/dipper".to_string()),
+                Eid(key_eid("user/dipper")),
+            ],
+            0,
+            1,
+        )]],
+    });
+
+    // keys that aren't strings are dropped, rather than taking down
+    // the worker
+    cases.push(Case {
+        description: "[:find ?e ?id :where [?e :key ?k] [(eid ?k) ?id]] (mixed keys)",
+        plan: {
+            let (e, k, id) = (1, 2, 3);
+            Plan::Transform(Transform {
+                variables: vec![k],
+                result_sym: id,
+                plan: Box::new(Plan::MatchA(e, ":key".to_string(), k)),
+                function: Function::EID,
+                constants: vec![None],
+            })
+        },
+        transactions: vec![vec![
+            TxData(1, 1, ":key".to_string(), Number(7)),
+            TxData(
+                1,
+                2,
+                ":key".to_string(),
+                Value::String("user/mabel".to_string()),
+            ),
+        ]],
+        expectations: vec![vec![(
+            vec![
+                Eid(2),
+                Value::String("user/mabel".to_string()),
+                Eid(key_eid("user/mabel")),
+            ],
+            0,
+            1,
+        )]],
+    });
+
     for case in cases.drain(..) {
         timely::execute(Configuration::Thread, move |worker| {
             let mut server = Server::<u64>::new(Default::default());
@@ -149,3 +263,71 @@ fn run_transform_cases() {
         .unwrap();
     }
 }
+
+#[test]
+fn key_eids() {
+    // 64-bit FNV-1a, as reproducible by clients
+    assert_eq!(key_eid(""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(key_eid("a"), 0xaf63_dc4c_8601_ec8c);
+
+    let mut eids = KeyEids::new();
+    assert_eq!(eids.eid("user/dipper"), key_eid("user/dipper"));
+    assert_eq!(eids.eid("user/dipper"), key_eid("user/dipper"));
+    assert_ne!(eids.eid("user/mabel"), key_eid("user/dipper"));
+    assert_eq!(eids.collisions(), 0);
+}
+
+#[test]
+fn invalid_constants() {
+    // [:find ?h :where [?e :timestamp ?t] [(interval ?t :fortnight) ?h]]
+    let (e, t, h) = (1, 2, 3);
+    let plan = Plan::Transform(Transform {
+        variables: vec![t],
+        result_sym: h,
+        plan: Box::new(Plan::MatchA(e, ":timestamp".to_string(), t)),
+        function: Function::TRUNCATE,
+        constants: vec![None, Some(Value::String(":fortnight".to_string()))],
+    });
+
+    // fails to be implemented, rather than taking down the worker
+    let error = Harness::new()
+        .attribute(":timestamp", AttributeSemantics::Raw)
+        .run(&plan)
+        .unwrap_err();
+
+    assert_eq!(error.category, "df.error.category/incorrect");
+}
+
+#[test]
+fn invalid_arguments() {
+    let (e, n, result) = (1, 2, 3);
+    let transform = |function: Function, variables: Vec<Var>, constants: Vec<Option<Value>>| {
+        Plan::Transform(Transform {
+            variables,
+            result_sym: result,
+            plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            function,
+            constants,
+        })
+    };
+
+    let plans = vec![
+        // only strings can be concatenated
+        transform(Function::CONCAT, vec![n], vec![Some(Number(1)), None]),
+        // one argument short
+        transform(Function::CONCAT, vec![n], vec![None, None]),
+        // keys must be strings
+        transform(Function::EID, vec![], vec![Some(Number(1))]),
+        // no key at all
+        transform(Function::EID, vec![], vec![None]),
+    ];
+
+    for plan in plans.iter() {
+        let error = Harness::new()
+            .attribute(":name", AttributeSemantics::Raw)
+            .run(plan)
+            .unwrap_err();
+
+        assert_eq!(error.category, "df.error.category/incorrect");
+    }
+}
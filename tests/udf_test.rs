@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use timely::Configuration;
+
+use declarative_dataflow::plan::{Function, Transform};
+use declarative_dataflow::server::{Config, Server};
+use declarative_dataflow::udf::{ExternalFunction, RestartPolicy, Subprocess};
+use declarative_dataflow::{AttributeSemantics, Plan, Rule, TxData, Value};
+use Value::{Eid, Number, String};
+
+#[test]
+fn parse_specs() {
+    let (name, function) = ExternalFunction::parse("score=python3 score.py --fast").unwrap();
+
+    assert_eq!(name, "score");
+    assert_eq!(function.command, vec!["python3", "score.py", "--fast"]);
+    assert_eq!(function.restart, RestartPolicy::Limit(3));
+
+    assert!(ExternalFunction::parse("score").is_err());
+    assert!(ExternalFunction::parse("score=").is_err());
+
+    assert_eq!("never".parse(), Ok(RestartPolicy::Never));
+    assert_eq!("always".parse(), Ok(RestartPolicy::Always));
+    assert_eq!("5".parse(), Ok(RestartPolicy::Limit(5)));
+    assert!("sometimes".parse::<RestartPolicy>().is_err());
+}
+
+#[test]
+fn batches() {
+    // cat responds with the arguments it was sent
+    let mut function = ExternalFunction::new(vec!["cat".to_string()]);
+    function.batch_size = 2;
+
+    let mut process = Subprocess::new(function);
+    let arguments = vec![
+        vec![Number(1)],
+        vec![Number(2), String("two".to_string())],
+        vec![],
+    ];
+
+    assert_eq!(process.call(&arguments), Ok(arguments.clone()));
+    assert_eq!(process.call(&arguments[..1]), Ok(arguments[..1].to_vec()));
+    assert_eq!(process.restarts(), 0);
+}
+
+#[test]
+fn restarts() {
+    // head exits after responding once
+    let mut function =
+        ExternalFunction::new(vec!["head".to_string(), "-n".to_string(), "1".to_string()]);
+    function.batch_size = 1;
+
+    let mut process = Subprocess::new(function);
+    let arguments = vec![vec![Number(1)], vec![Number(2)]];
+
+    assert_eq!(process.call(&arguments), Ok(arguments.clone()));
+    assert_eq!(process.restarts(), 1);
+}
+
+#[test]
+fn timeouts() {
+    let mut function = ExternalFunction::new(vec!["sleep".to_string(), "5".to_string()]);
+    function.timeout = Duration::from_millis(100);
+    function.restart = RestartPolicy::Never;
+
+    let mut process = Subprocess::new(function);
+
+    assert!(process.call(&[vec![Number(1)]]).is_err());
+    assert_eq!(process.restarts(), 0);
+
+    // the process is given up on for good
+    assert!(process.call(&[vec![Number(1)]]).is_err());
+}
+
+#[test]
+fn unread_requests() {
+    // sleep never reads its input, so requests larger than the pipe
+    // buffer can't be written
+    let mut function = ExternalFunction::new(vec!["sleep".to_string(), "5".to_string()]);
+    function.timeout = Duration::from_millis(100);
+    function.restart = RestartPolicy::Never;
+
+    let mut process = Subprocess::new(function);
+    let started = Instant::now();
+
+    assert!(process.call(&[vec![String("x".repeat(1 << 20))]]).is_err());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn external_transform() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut external_functions = HashMap::new();
+        external_functions.insert(
+            "echo".to_string(),
+            ExternalFunction::new(vec!["cat".to_string()]),
+        );
+
+        let mut server = Server::<u64>::new(Config {
+            external_functions,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        // [:find ?e ?n ?echo :where [?e :name ?n] [(echo ?n) ?echo]]
+        let (e, n, echo) = (1, 2, 3);
+        let plan = Plan::Transform(Transform {
+            variables: vec![n],
+            result_sym: echo,
+            plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            function: Function::EXTERNAL("echo".to_string()),
+            constants: vec![Some(Number(0)), None],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "echo".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        server
+            .transact(
+                vec![TxData(
+                    1,
+                    1,
+                    ":name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        // every value responded with is bound in a tuple of its own
+        assert_eq!(
+            received,
+            vec![
+                (
+                    vec![
+                        Eid(1),
+                        String("Dipper".to_string()),
+                        String("Dipper".to_string())
+                    ],
+                    0,
+                    1
+                ),
+                (vec![Eid(1), String("Dipper".to_string()), Number(0)], 0, 1),
+            ]
+        );
+
+        server
+            .transact(
+                vec![TxData(
+                    -1,
+                    1,
+                    ":name".to_string(),
+                    String("Dipper".to_string()),
+                )],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        // retractions replay the results of the additions
+        assert_eq!(
+            received,
+            vec![
+                (
+                    vec![
+                        Eid(1),
+                        String("Dipper".to_string()),
+                        String("Dipper".to_string())
+                    ],
+                    1,
+                    -1
+                ),
+                (vec![Eid(1), String("Dipper".to_string()), Number(0)], 1, -1),
+            ]
+        );
+    })
+    .unwrap();
+}
+
+#[test]
+fn cached_arguments() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut external_functions = HashMap::new();
+        external_functions.insert(
+            "echo".to_string(),
+            ExternalFunction::new(vec!["cat".to_string()]),
+        );
+
+        let mut server = Server::<u64>::new(Config {
+            external_functions,
+            ..Default::default()
+        });
+        let (send_results, results) = channel();
+
+        // [:find ?e ?n ?echo :where [?e :name ?n] [(echo ?n) ?echo]]
+        let (e, n, echo) = (1, 2, 3);
+        let plan = Plan::Transform(Transform {
+            variables: vec![n],
+            result_sym: echo,
+            plan: Box::new(Plan::MatchA(e, ":name".to_string(), n)),
+            function: Function::EXTERNAL("echo".to_string()),
+            constants: vec![None],
+        });
+
+        worker.dataflow::<u64, _, _>(|scope| {
+            server
+                .context
+                .internal
+                .create_attribute(":name", AttributeSemantics::Raw, scope)
+                .unwrap();
+
+            server
+                .test_single(
+                    scope,
+                    Rule {
+                        name: "echo".to_string(),
+                        plan,
+                    },
+                )
+                .inspect(move |x| {
+                    send_results.send(x.clone()).unwrap();
+                });
+        });
+
+        let dipper = || String("Dipper".to_string());
+
+        server
+            .transact(vec![TxData(1, 1, ":name".to_string(), dipper())], 0, 0)
+            .unwrap();
+        server.advance_domain(None, 1).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        assert_eq!(
+            results.try_iter().collect::<Vec<_>>(),
+            vec![(vec![Eid(1), dipper(), dipper()], 0, 1)]
+        );
+
+        // The cached results for these arguments are released by the
+        // retraction, but still needed for the addition.
+        server
+            .transact(
+                vec![
+                    TxData(-1, 1, ":name".to_string(), dipper()),
+                    TxData(1, 2, ":name".to_string(), dipper()),
+                ],
+                0,
+                0,
+            )
+            .unwrap();
+        server.advance_domain(None, 2).unwrap();
+
+        worker.step_while(|| server.is_any_outdated());
+
+        let mut received: Vec<_> = results.try_iter().collect();
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                (vec![Eid(1), dipper(), dipper()], 1, -1),
+                (vec![Eid(2), dipper(), dipper()], 1, 1),
+            ]
+        );
+    })
+    .unwrap();
+}
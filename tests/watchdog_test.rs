@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use timely::dataflow::operators::{Input, Probe};
+use timely::Configuration;
+
+use declarative_dataflow::server::watchdog::{Alarm, Watchdog};
+
+#[test]
+fn stalled_frontier() {
+    timely::execute(Configuration::Thread, move |worker| {
+        let mut watchdog = Watchdog::new(Duration::from_millis(0));
+
+        let mut input = worker.dataflow::<u64, _, _>(|scope| {
+            let (input, stream) = scope.new_input::<()>();
+            stream.probe_with(watchdog.watch("stalled"));
+            input
+        });
+
+        input.advance_to(1);
+        worker.step();
+
+        let now = Instant::now();
+
+        // Frontiers that keep up are not reported.
+        assert!(watchdog.check(&1, now).is_empty());
+
+        // Falling behind is only reported after the timeout.
+        assert!(watchdog.check(&2, now).is_empty());
+        assert_eq!(
+            watchdog.check(&2, now),
+            vec![Alarm::Stuck("stalled".to_string(), 2)]
+        );
+        assert_eq!(watchdog.stuck(), vec!["stalled".to_string()]);
+
+        // Alarms are only raised once.
+        assert!(watchdog.check(&3, now).is_empty());
+
+        input.advance_to(2);
+        worker.step();
+
+        assert_eq!(
+            watchdog.check(&3, now),
+            vec![Alarm::Recovered("stalled".to_string(), 2)]
+        );
+        assert!(watchdog.stuck().is_empty());
+    })
+    .unwrap();
+}